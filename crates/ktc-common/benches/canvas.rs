@@ -0,0 +1,78 @@
+// `cargo bench -p ktc-common --bench canvas` times the CPU renderer's full
+// 1080p redraw: the background pattern, four decorated windows, an upscaled
+// one and a dimmed overlay. It needs no display, so it runs anywhere.
+use ktc_common::{simd, Canvas, Rectangle};
+
+fn main() {
+    const FRAMES: u32 = 120;
+    let (width, height) = (1920, 1080);
+    let (tile_w, tile_h, title) = (960, 540, 24);
+
+    let mut canvas = Canvas::new(width, height, 0xFF1A1A2E);
+    let content: Vec<u32> = (0..tile_w * tile_h)
+        .map(|i| 0x80000000 | (i as u32 & 0xFFFFFF))
+        .collect();
+    let tiles = [(0, 0), (960, 0), (0, 540), (960, 540)];
+
+    let draw = |canvas: &mut Canvas| {
+        canvas.clear_with_pattern(0xFF1A1A2E, 0xFF16213E);
+        for (x, y) in tiles {
+            let focused = (x, y) == (0, 0);
+            canvas.draw_decorations(
+                x,
+                y,
+                tile_w as i32,
+                tile_h as i32 - title,
+                title,
+                focused,
+                0xFF2D2D44,
+                0xFF1F1F2E,
+                0xFF4A9EFF,
+                0xFF505050,
+            );
+            canvas.blit_fast(
+                &content,
+                tile_w,
+                tile_h - title as usize,
+                tile_w,
+                x,
+                y + title,
+            );
+        }
+        canvas.blit_scaled(
+            &content,
+            tile_w,
+            [0.0, 0.0, 640.0, 360.0],
+            Rectangle {
+                x: 480,
+                y: 270,
+                width: 960,
+                height: 540,
+            },
+        );
+        canvas.blend_rect(
+            Rectangle {
+                x: 0,
+                y: 0,
+                width: 960,
+                height: 540,
+            },
+            0xFF000000,
+        );
+    };
+
+    draw(&mut canvas);
+    let start = std::time::Instant::now();
+    for _ in 0..FRAMES {
+        draw(&mut canvas);
+    }
+    let per_frame = start.elapsed() / FRAMES;
+    println!(
+        "{}x{} CPU full redraw ({}): {:.2} ms/frame over {} frames",
+        width,
+        height,
+        simd::BACKEND,
+        per_frame.as_secs_f64() * 1000.0,
+        FRAMES
+    );
+}
@@ -0,0 +1,566 @@
+// the CPU renderer's framebuffer and the drawing it does
+use crate::simd;
+use crate::{Font, Rectangle};
+
+pub struct Canvas {
+    pub pixels: Vec<u32>,
+    pub cursor_save: Vec<u32>,
+    pub cursor_save_x: i32,
+    pub cursor_save_y: i32,
+    pub cursor_save_w: usize,
+    pub cursor_save_h: usize,
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize, bg_color: u32) -> Self {
+        let stride = width;
+        let pixels = vec![bg_color; width * height];
+        Self {
+            pixels,
+            cursor_save: Vec::new(),
+            cursor_save_x: -100,
+            cursor_save_y: -100,
+            cursor_save_w: 0,
+            cursor_save_h: 0,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize, bg_color: u32) {
+        if self.width != width || self.height != height {
+            self.width = width;
+            self.height = height;
+            self.stride = width;
+            self.pixels = vec![bg_color; width * height];
+            self.cursor_save_x = -100;
+            self.cursor_save_y = -100;
+        }
+    }
+
+    pub fn clear(&mut self, color: u32) {
+        self.pixels.fill(color);
+    }
+
+    // pixels is a tightly packed image the size of the canvas
+    pub fn copy_from(&mut self, pixels: &[u32]) {
+        let (width, stride) = (self.width, self.stride);
+        for (y, row) in pixels.chunks_exact(width).take(self.height).enumerate() {
+            self.pixels[y * stride..y * stride + width].copy_from_slice(row);
+        }
+    }
+
+    pub fn clear_with_pattern(&mut self, bg_dark: u32, bg_light: u32) {
+        let tile_size = 32;
+        let width = self.width;
+        let stride = self.stride;
+
+        for band_y in (0..self.height).step_by(tile_size) {
+            let ty = band_y / tile_size;
+            let (base_color, alt_color) = if ty % 2 == 0 {
+                (bg_dark, bg_light)
+            } else {
+                (bg_light, bg_dark)
+            };
+
+            let row_start = band_y * stride;
+            for x in (0..width).step_by(tile_size) {
+                let color = if (x / tile_size) % 2 == 0 {
+                    base_color
+                } else {
+                    alt_color
+                };
+                let tile_end = (x + tile_size).min(width);
+                simd::fill(&mut self.pixels[row_start + x..row_start + tile_end], color);
+            }
+
+            // the other rows of the band are the same, so they're plain copies
+            for y in band_y + 1..(band_y + tile_size).min(self.height) {
+                self.pixels
+                    .copy_within(row_start..row_start + width, y * stride);
+            }
+        }
+    }
+
+    pub fn draw_banner(&mut self, lines: &[String]) {
+        self.draw_text_box(lines, 0xFFB02020, false);
+    }
+
+    pub fn draw_help(&mut self, lines: &[String]) {
+        self.draw_text_box(lines, 0xFF101010, true);
+    }
+
+    fn draw_text_box(&mut self, lines: &[String], bg: u32, centered: bool) {
+        let font = Font::default();
+        let padding = 8;
+        let line_height = font.char_height() + 2;
+        let max_width = lines.iter().map(|l| font.text_width(l)).max().unwrap_or(0);
+        let box_width = (max_width + padding * 2).min(self.width);
+        let box_height = (lines.len() * line_height + padding * 2).min(self.height);
+        let box_x = (self.width - box_width) / 2;
+        let box_y = if centered {
+            (self.height - box_height) / 2
+        } else {
+            10.min(self.height - box_height)
+        };
+
+        for y in box_y..box_y + box_height {
+            let row = y * self.stride;
+            simd::fill(&mut self.pixels[row + box_x..row + box_x + box_width], bg);
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            let y = box_y + padding + i * line_height;
+            if y + font.char_height() > self.height {
+                break;
+            }
+            let stride = self.stride;
+            font.draw_text(
+                &mut self.pixels,
+                stride,
+                box_x + padding,
+                y,
+                line,
+                0xFFFFFFFF,
+            );
+        }
+    }
+
+    pub fn draw_title(&mut self, text: &str, x: i32, y: i32, scale: usize, color: u32) {
+        if x < 0 || y < 0 {
+            return;
+        }
+        let font = Font::new(scale);
+        let (x, y) = (x as usize, y as usize);
+        if y + font.char_height() > self.height || x + font.text_width(text) > self.width {
+            return;
+        }
+        let stride = self.stride;
+        font.draw_text(&mut self.pixels, stride, x, y, text, color);
+    }
+
+    pub fn draw_border(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        color: u32,
+        thickness: i32,
+    ) {
+        let band = thickness.min(height);
+        let column = thickness.min(width);
+        self.fill_rect(
+            Rectangle {
+                x,
+                y,
+                width,
+                height: band,
+            },
+            color,
+        );
+        self.fill_rect(
+            Rectangle {
+                x,
+                y: y + height - band,
+                width,
+                height: band,
+            },
+            color,
+        );
+        self.fill_rect(
+            Rectangle {
+                x,
+                y,
+                width: column,
+                height,
+            },
+            color,
+        );
+        self.fill_rect(
+            Rectangle {
+                x: x + width - column,
+                y,
+                width: column,
+                height,
+            },
+            color,
+        );
+    }
+
+    pub fn blit(
+        &mut self,
+        src: &[u32],
+        src_width: usize,
+        src_height: usize,
+        dst_x: i32,
+        dst_y: i32,
+    ) {
+        self.blit_fast(src, src_width, src_height, src_width, dst_x, dst_y);
+    }
+
+    pub fn blit_fast(
+        &mut self,
+        src: &[u32],
+        src_width: usize,
+        src_height: usize,
+        src_stride: usize,
+        dst_x: i32,
+        dst_y: i32,
+    ) {
+        let dst_x = dst_x.max(0) as usize;
+        let dst_y = dst_y.max(0) as usize;
+        let copy_width = src_width.min(self.width.saturating_sub(dst_x));
+
+        for y in 0..src_height.min(self.height.saturating_sub(dst_y)) {
+            let src_offset = y * src_stride;
+            let dst_offset = (dst_y + y) * self.stride + dst_x;
+
+            if src_offset + copy_width <= src.len() && dst_offset + copy_width <= self.pixels.len()
+            {
+                simd::copy_opaque(
+                    &mut self.pixels[dst_offset..dst_offset + copy_width],
+                    &src[src_offset..src_offset + copy_width],
+                );
+            }
+        }
+    }
+
+    // Nearest-neighbour copy of src_rect (buffer pixels) stretched over dst.
+    pub fn blit_scaled(
+        &mut self,
+        src: &[u32],
+        src_stride: usize,
+        src_rect: [f64; 4],
+        dst: Rectangle,
+    ) {
+        if dst.width <= 0 || dst.height <= 0 {
+            return;
+        }
+        let [sx, sy, sw, sh] = src_rect;
+        let step_x = sw / dst.width as f64;
+        let step_y = sh / dst.height as f64;
+
+        let xs = self.clip_x(dst.x, dst.width);
+        let columns: Vec<usize> = xs
+            .clone()
+            .map(|px| (sx + ((px as i32 - dst.x) as f64 + 0.5) * step_x) as usize)
+            .collect();
+
+        let mut previous: Option<(usize, usize)> = None;
+        for py in self.clip_y(dst.y, dst.height) {
+            let dy = py as i32 - dst.y;
+            let src_row = (sy + (dy as f64 + 0.5) * step_y) as usize * src_stride;
+            let dst_row = py * self.stride;
+
+            // scaling up repeats source rows; reuse the one already drawn
+            if let Some((previous_src, previous_dst)) = previous {
+                if previous_src == src_row {
+                    self.pixels.copy_within(
+                        previous_dst + xs.start..previous_dst + xs.end,
+                        dst_row + xs.start,
+                    );
+                    continue;
+                }
+            }
+
+            let row = &mut self.pixels[dst_row + xs.start..dst_row + xs.end];
+            for (pixel, &src_col) in row.iter_mut().zip(&columns) {
+                if let Some(&color) = src.get(src_row + src_col) {
+                    *pixel = color | 0xFF000000;
+                }
+            }
+            previous = Some((src_row, dst_row));
+        }
+    }
+
+    pub fn blit_direct(
+        &mut self,
+        src: &[u32],
+        src_width: usize,
+        src_height: usize,
+        src_stride: usize,
+        dst_x: i32,
+        dst_y: i32,
+    ) {
+        if dst_x >= self.width as i32 || dst_y >= self.height as i32 {
+            return;
+        }
+
+        let dst_x_usize = dst_x.max(0) as usize;
+        let dst_y_usize = dst_y.max(0) as usize;
+        let src_skip_x = if dst_x < 0 { (-dst_x) as usize } else { 0 };
+        let src_skip_y = if dst_y < 0 { (-dst_y) as usize } else { 0 };
+
+        let actual_src_width = src_width.saturating_sub(src_skip_x);
+        let actual_src_height = src_height.saturating_sub(src_skip_y);
+        let copy_width = actual_src_width.min(self.width.saturating_sub(dst_x_usize));
+        let copy_height = actual_src_height.min(self.height.saturating_sub(dst_y_usize));
+
+        if copy_width == 0 || copy_height == 0 {
+            return;
+        }
+
+        let dst_ptr = self.pixels.as_mut_ptr();
+        let src_ptr = src.as_ptr();
+
+        unsafe {
+            for y in 0..copy_height {
+                let src_row = src_skip_y + y;
+                let dst_row = dst_y_usize + y;
+                let src_offset = src_row * src_stride + src_skip_x;
+                let dst_offset = dst_row * self.stride + dst_x_usize;
+
+                if src_offset + copy_width <= src.len()
+                    && dst_offset + copy_width <= self.pixels.len()
+                {
+                    std::ptr::copy_nonoverlapping(
+                        src_ptr.add(src_offset),
+                        dst_ptr.add(dst_offset),
+                        copy_width,
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u32] {
+        &mut self.pixels
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_decorations(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        title_height: i32,
+        is_focused: bool,
+        title_focused: u32,
+        title_unfocused: u32,
+        border_focused: u32,
+        border_unfocused: u32,
+    ) {
+        let title_bg = if is_focused {
+            title_focused
+        } else {
+            title_unfocused
+        };
+        let border_color = if is_focused {
+            border_focused
+        } else {
+            border_unfocused
+        };
+
+        let x = x.max(0);
+        let y = y.max(0);
+        let total_height = height + title_height;
+        let xs = self.clip_x(x, width);
+
+        for py in self.clip_y(y, title_height) {
+            self.fill_span(py, xs.clone(), title_bg);
+        }
+        for border_y in [y, y + total_height - 1, y + title_height] {
+            if let Some(py) = self.clip_y(border_y, 1).next() {
+                self.fill_span(py, xs.clone(), border_color);
+            }
+        }
+
+        let right_x = x + width - 1;
+        for py in self.clip_y(y, total_height) {
+            let row = py * self.stride;
+            for px in [x, right_x] {
+                if px >= 0 && (px as usize) < self.width {
+                    self.pixels[row + px as usize] = border_color;
+                }
+            }
+        }
+    }
+
+    // averages the color into what's already there
+    pub fn blend_rect(&mut self, rect: Rectangle, color: u32) {
+        let xs = self.clip_x(rect.x, rect.width);
+        for py in self.clip_y(rect.y, rect.height) {
+            let row = py * self.stride;
+            simd::blend_half(&mut self.pixels[row + xs.start..row + xs.end], color);
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rectangle, color: u32) {
+        let xs = self.clip_x(rect.x, rect.width);
+        for py in self.clip_y(rect.y, rect.height) {
+            self.fill_span(py, xs.clone(), color);
+        }
+    }
+
+    // columns of a span clipped to the canvas, empty when it's off screen
+    fn clip_x(&self, x: i32, width: i32) -> std::ops::Range<usize> {
+        let start = x.clamp(0, self.width as i32) as usize;
+        let end = x.saturating_add(width).clamp(0, self.width as i32) as usize;
+        start..end.max(start)
+    }
+
+    fn clip_y(&self, y: i32, height: i32) -> std::ops::Range<usize> {
+        let start = y.clamp(0, self.height as i32) as usize;
+        let end = y.saturating_add(height).clamp(0, self.height as i32) as usize;
+        start..end.max(start)
+    }
+
+    fn fill_span(&mut self, y: usize, xs: std::ops::Range<usize>, color: u32) {
+        let row = y * self.stride;
+        simd::fill(&mut self.pixels[row + xs.start..row + xs.end], color);
+    }
+
+    pub fn draw_tabs(
+        &mut self,
+        tabs: &[(Rectangle, bool)],
+        tab_active: u32,
+        tab_inactive: u32,
+        separator: u32,
+    ) {
+        for (rect, active) in tabs {
+            let color = if *active { tab_active } else { tab_inactive };
+            let xs = self.clip_x(rect.x, rect.width);
+            if xs.is_empty() {
+                continue;
+            }
+            let right = rect.x + rect.width - 1;
+            let bottom = rect.y + rect.height - 1;
+
+            for py in self.clip_y(rect.y, rect.height) {
+                let fill = if py as i32 == bottom {
+                    separator
+                } else {
+                    color
+                };
+                self.fill_span(py, xs.clone(), fill);
+                if right >= 0 && xs.contains(&(right as usize)) {
+                    self.pixels[py * self.stride + right as usize] = separator;
+                }
+            }
+        }
+    }
+
+    // premultiplied image with its top left at x, y; what it covers is
+    // saved for restore_cursor
+    pub fn draw_cursor(&mut self, x: i32, y: i32, width: usize, height: usize, pixels: &[u32]) {
+        self.save_under_cursor(x, y, width, height);
+
+        for dy in 0..height {
+            let py = y + dy as i32;
+            if py < 0 || py as usize >= self.height {
+                continue;
+            }
+            for dx in 0..width {
+                let px = x + dx as i32;
+                if px < 0 || px as usize >= self.width {
+                    continue;
+                }
+                let color = pixels[dy * width + dx];
+                let dst = &mut self.pixels[py as usize * self.stride + px as usize];
+                *dst = blend_premultiplied(color, *dst);
+            }
+        }
+    }
+
+    fn save_under_cursor(&mut self, x: i32, y: i32, width: usize, height: usize) {
+        // a hotspot can put the image past the top left corner, while
+        // negative positions mean nothing is saved
+        let (x, y) = (x.max(0), y.max(0));
+        self.cursor_save_x = x;
+        self.cursor_save_y = y;
+        self.cursor_save_w = width;
+        self.cursor_save_h = height;
+        self.cursor_save.resize(width * height, 0);
+        let x = x.max(0) as usize;
+        let y = y.max(0) as usize;
+        let copy_width = width.min(self.width.saturating_sub(x));
+
+        for dy in 0..height.min(self.height.saturating_sub(y)) {
+            let row = (y + dy) * self.stride + x;
+            self.cursor_save[dy * width..dy * width + copy_width]
+                .copy_from_slice(&self.pixels[row..row + copy_width]);
+        }
+    }
+
+    // area the cursor currently covers, empty when nothing is saved under it
+    pub fn cursor_rect(&self) -> Rectangle {
+        if self.cursor_save_x < 0 && self.cursor_save_y < 0 {
+            return Rectangle::default();
+        }
+        Rectangle {
+            x: self.cursor_save_x.max(0),
+            y: self.cursor_save_y.max(0),
+            width: self.cursor_save_w as i32,
+            height: self.cursor_save_h as i32,
+        }
+    }
+
+    // puts what the cursor covers back into pixels read from the canvas at
+    // (x, y), rows of width
+    pub fn uncover_cursor(&self, pixels: &mut [u32], x: i32, y: i32, width: i32) {
+        let cursor = self.cursor_rect();
+        let read = Rectangle {
+            x,
+            y,
+            width,
+            height: pixels.len() as i32 / width.max(1),
+        };
+        let Some(area) = cursor.intersection(&read) else {
+            return;
+        };
+        let save_width = self.cursor_save_w;
+        for py in area.y..area.y + area.height {
+            let src = (py - cursor.y) as usize * save_width + (area.x - cursor.x) as usize;
+            let dst = (py - y) as usize * width as usize + (area.x - x) as usize;
+            let len = area.width as usize;
+            pixels[dst..dst + len].copy_from_slice(&self.cursor_save[src..src + len]);
+        }
+    }
+
+    pub fn restore_cursor(&mut self) {
+        if self.cursor_save_x < 0 && self.cursor_save_y < 0 {
+            return;
+        }
+        let x = self.cursor_save_x.max(0) as usize;
+        let y = self.cursor_save_y.max(0) as usize;
+        let width = self.cursor_save_w;
+        let copy_width = width.min(self.width.saturating_sub(x));
+
+        for dy in 0..self.cursor_save_h.min(self.height.saturating_sub(y)) {
+            let row = (y + dy) * self.stride + x;
+            self.pixels[row..row + copy_width]
+                .copy_from_slice(&self.cursor_save[dy * width..dy * width + copy_width]);
+        }
+
+        self.cursor_save_x = -100;
+        self.cursor_save_y = -100;
+    }
+}
+
+// src over dst, both premultiplied ARGB
+fn blend_premultiplied(src: u32, dst: u32) -> u32 {
+    let alpha = src >> 24;
+    if alpha == 0xFF {
+        return src;
+    }
+    if alpha == 0 {
+        return dst;
+    }
+    let inverse = 255 - alpha;
+    let channel = |shift: u32| {
+        let s = (src >> shift) & 0xFF;
+        let d = (dst >> shift) & 0xFF;
+        (s + (d * inverse + 127) / 255).min(255) << shift
+    };
+    channel(24) | channel(16) | channel(8) | channel(0)
+}
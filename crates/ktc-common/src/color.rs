@@ -16,6 +16,38 @@ pub fn parse_color(s: &str) -> Option<u32> {
     }
 }
 
+pub const NEUTRAL_TEMPERATURE: u32 = 6500;
+
+// Per-channel gains for a white point at the given color temperature, from
+// Tanner Helland's blackbody fit, normalized so 6500K is [1.0, 1.0, 1.0]
+pub fn temperature_gain(kelvin: u32) -> [f32; 3] {
+    fn blackbody(kelvin: u32) -> [f64; 3] {
+        let t = kelvin.clamp(1000, 40000) as f64 / 100.0;
+        let r = if t <= 66.0 {
+            255.0
+        } else {
+            329.698727446 * (t - 60.0).powf(-0.1332047592)
+        };
+        let g = if t <= 66.0 {
+            99.4708025861 * t.ln() - 161.1195681661
+        } else {
+            288.1221695283 * (t - 60.0).powf(-0.0755148492)
+        };
+        let b = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.5177312231 * (t - 10.0).ln() - 305.0447927307
+        };
+        [r, g, b]
+    }
+
+    let color = blackbody(kelvin);
+    let white = blackbody(NEUTRAL_TEMPERATURE);
+    [0, 1, 2].map(|i| (color[i] / white[i]).clamp(0.0, 1.0) as f32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -34,6 +66,18 @@ mod tests {
         assert_eq!(parse_color("00000000"), Some(0x00000000));
     }
 
+    #[test]
+    fn test_temperature_gain() {
+        assert_eq!(temperature_gain(NEUTRAL_TEMPERATURE), [1.0, 1.0, 1.0]);
+
+        let warm = temperature_gain(3000);
+        assert_eq!(warm[0], 1.0);
+        assert!(warm[1] < 1.0 && warm[2] < warm[1]);
+
+        let warmer = temperature_gain(2000);
+        assert!(warmer[2] < warm[2]);
+    }
+
     #[test]
     fn test_invalid() {
         assert_eq!(parse_color(""), None);
@@ -26,8 +26,22 @@ impl Font {
         FONT_CHAR_HEIGHT * self.scale
     }
 
+    // one glyph per char, the same unit draw_text and truncate advance by
     pub fn text_width(&self, text: &str) -> usize {
-        text.len() * self.char_width()
+        text.chars().count() * self.char_width()
+    }
+
+    pub fn truncate(&self, text: &str, max_width: usize) -> String {
+        let max_chars = max_width / self.char_width();
+        if text.chars().count() <= max_chars {
+            return text.to_string();
+        }
+        if max_chars <= 3 {
+            return text.chars().take(max_chars).collect();
+        }
+        let mut truncated: String = text.chars().take(max_chars - 3).collect();
+        truncated.push_str("...");
+        truncated
     }
 
     pub fn draw_char(
@@ -99,3 +113,25 @@ impl Font {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_width_counts_chars() {
+        let font = Font::new(1);
+        assert_eq!(font.text_width("abc"), 3 * FONT_CHAR_WIDTH);
+        assert_eq!(font.text_width("héllo"), 5 * FONT_CHAR_WIDTH);
+    }
+
+    #[test]
+    fn test_truncate_fits_width() {
+        let font = Font::new(1);
+        let max_width = 6 * FONT_CHAR_WIDTH;
+        let text = font.truncate("ünïcödé title", max_width);
+        assert_eq!(text, "ünï...");
+        assert!(font.text_width(&text) <= max_width);
+        assert_eq!(font.truncate("short", max_width), "short");
+    }
+}
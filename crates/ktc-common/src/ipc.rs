@@ -1,4 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+// layer-shell namespace of ktcbar's bar surface; toggle_bar only gives
+// back the exclusive zone of surfaces with this namespace
+pub const BAR_NAMESPACE: &str = "ktcbar";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -18,6 +26,44 @@ pub enum IpcEvent {
     FocusChanged { window_title: Option<String> },
     #[serde(rename = "title")]
     TitleChanged { window_title: String },
+    #[serde(rename = "hidden_windows")]
+    HiddenWindows { windows: Vec<HiddenWindowInfo> },
+    #[serde(rename = "tree")]
+    Tree { windows: Vec<WindowTreeInfo> },
+    #[serde(rename = "renderer_stats")]
+    RendererStats { stats: RendererStats },
+    // sent by the toggle_bar action; a hidden bar's exclusive zone is
+    // already given back to windows
+    #[serde(rename = "bar")]
+    BarVisibility { visible: bool },
+    // pixels are ARGB8888 rows, see encode_pixels
+    #[serde(rename = "workspace_preview")]
+    WorkspacePreview {
+        workspace: usize,
+        width: u32,
+        height: u32,
+        pixels: String,
+    },
+    // a screenshot action finished writing this PNG
+    #[serde(rename = "screenshot")]
+    Screenshot { path: String },
+    // sent when a recording starts and again once its file is complete
+    #[serde(rename = "recording")]
+    Recording { recording: bool, path: String },
+    // the active xkb layout group and its name from keyboard.layout, sent
+    // whenever it changes and in answer to get_state
+    #[serde(rename = "keyboard_layout")]
+    KeyboardLayout { name: String, index: u32 },
+    // the active [[keybinds.mode]], "default" outside of one; sent whenever
+    // it changes and in answer to get_state
+    #[serde(rename = "mode")]
+    Mode { name: String },
+    // whether a passthrough binding has turned the other keybinds off; sent
+    // whenever it changes and in answer to get_state
+    #[serde(rename = "passthrough")]
+    Passthrough { enabled: bool },
+    #[serde(rename = "tablets")]
+    Tablets { tablets: Vec<TabletInfo> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +73,129 @@ pub enum IpcCommand {
     GetState,
     #[serde(rename = "switch_workspace")]
     SwitchWorkspace { workspace: usize },
+    #[serde(rename = "get_hidden_windows")]
+    GetHiddenWindows,
+    #[serde(rename = "restore_window")]
+    RestoreWindow { id: u64 },
+    #[serde(rename = "raise_window")]
+    RaiseWindow {
+        #[serde(default)]
+        id: Option<u64>,
+    },
+    #[serde(rename = "lower_window")]
+    LowerWindow {
+        #[serde(default)]
+        id: Option<u64>,
+    },
+    #[serde(rename = "move_to_workspace")]
+    MoveToWorkspace {
+        #[serde(default)]
+        id: Option<u64>,
+        workspace: usize,
+        #[serde(default)]
+        follow: bool,
+    },
+    #[serde(rename = "mark")]
+    Mark {
+        #[serde(default)]
+        id: Option<u64>,
+        mark: String,
+    },
+    #[serde(rename = "jump_to_mark")]
+    JumpToMark { mark: String },
+    // the height follows the output's aspect ratio
+    #[serde(rename = "get_workspace_preview")]
+    GetWorkspacePreview {
+        workspace: usize,
+        #[serde(default = "default_preview_width")]
+        width: u32,
+    },
+    #[serde(rename = "set_opacity")]
+    SetOpacity {
+        #[serde(default)]
+        id: Option<u64>,
+        opacity: f64,
+    },
+    #[serde(rename = "get_tree")]
+    GetTree,
+    #[serde(rename = "get_renderer_stats")]
+    GetRendererStats,
+    #[serde(rename = "get_tablets")]
+    GetTablets,
+    #[serde(rename = "set_output_mode")]
+    SetOutputMode {
+        #[serde(default)]
+        output: String,
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        refresh: Option<u32>,
+    },
+    #[serde(rename = "set_output_vrr")]
+    SetOutputVrr {
+        #[serde(default)]
+        output: String,
+        enabled: bool,
+    },
+    // Neither temperature nor brightness hands the output back to the
+    // night light schedule
+    #[serde(rename = "set_color_temp")]
+    SetColorTemp {
+        #[serde(default)]
+        output: String,
+        #[serde(default)]
+        temperature: Option<u32>,
+        #[serde(default)]
+        brightness: Option<f64>,
+    },
+    // An empty output is the primary one. A path or mode lasts until the
+    // next config reload; with neither, the configured wallpaper comes back,
+    // read from disk again.
+    #[serde(rename = "set_wallpaper")]
+    SetWallpaper {
+        #[serde(default)]
+        output: String,
+        #[serde(default)]
+        path: Option<String>,
+        // "fill", "fit" or "tile"
+        #[serde(default)]
+        mode: Option<String>,
+    },
+    // path defaults to a dated file in [recording] directory, encoder and
+    // framerate to the [recording] settings
+    #[serde(rename = "start_recording")]
+    StartRecording {
+        #[serde(default)]
+        path: Option<String>,
+        #[serde(default)]
+        encoder: Option<String>,
+        #[serde(default)]
+        framerate: Option<u32>,
+    },
+    #[serde(rename = "stop_recording")]
+    StopRecording,
+    // Without enabled the overlay is toggled; the [debug] setting comes back
+    // on the next config reload
+    #[serde(rename = "set_profiler")]
+    SetProfiler {
+        #[serde(default)]
+        enabled: Option<bool>,
+    },
+    // Changes [input.pointer], or with device the [[input.device]] block of
+    // that exact name, until the next config reload; unset fields are kept
+    #[serde(rename = "set_pointer")]
+    SetPointer {
+        #[serde(default)]
+        device: Option<String>,
+        #[serde(default)]
+        accel_profile: Option<String>,
+        #[serde(default)]
+        speed: Option<f64>,
+        #[serde(default)]
+        left_handed: Option<bool>,
+        #[serde(default)]
+        middle_emulation: Option<bool>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +204,55 @@ pub struct WorkspaceInfo {
     pub name: String,
     pub window_count: usize,
     pub urgent: bool,
+    // "tile", "monocle" or "tabbed"
+    #[serde(default)]
+    pub layout: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HiddenWindowInfo {
+    pub id: u64,
+    pub title: String,
+    pub app_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowTreeInfo {
+    pub id: u64,
+    pub title: String,
+    pub app_id: String,
+    // None while the window sits in the scratchpad or is minimized
+    pub workspace: Option<usize>,
+    pub focused: bool,
+    pub floating: bool,
+    pub minimized: bool,
+    #[serde(default)]
+    pub marks: Vec<String>,
+}
+
+// Counters are cumulative since the renderer was created
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RendererStats {
+    pub backend: String,
+    pub texture_count: usize,
+    pub texture_bytes: u64,
+    #[serde(default)]
+    pub dmabuf_import_failures: u64,
+    #[serde(default)]
+    pub page_flip_misses: u64,
+    #[serde(default)]
+    pub swapchain_recreations: u64,
+}
+
+// area is [x, y, width, height] in layout coordinates, what the whole
+// tablet maps to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TabletInfo {
+    pub name: String,
+    pub vendor: u32,
+    pub product: u32,
+    pub path: String,
+    pub area: [i32; 4],
 }
 
 impl WorkspaceInfo {
@@ -44,10 +262,68 @@ impl WorkspaceInfo {
             name: id.to_string(),
             window_count: 0,
             urgent: false,
+            layout: None,
         }
     }
 }
 
+fn default_preview_width() -> u32 {
+    240
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Pixels as base64 of their little-endian bytes, to keep events on one line
+pub fn encode_pixels(pixels: &[u32]) -> String {
+    let bytes: Vec<u8> = pixels.iter().flat_map(|p| p.to_le_bytes()).collect();
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// Only what encode_pixels produces: whole groups of four, padded at the end
+pub fn decode_pixels(data: &str) -> Option<Vec<u32>> {
+    let data = data.as_bytes();
+    if !data.len().is_multiple_of(4) {
+        return None;
+    }
+    let groups = data.len() / 4;
+    let mut bytes = Vec::with_capacity(groups * 3);
+    for (index, chunk) in data.chunks_exact(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && index + 1 != groups) {
+            return None;
+        }
+        let mut n = 0u32;
+        for (i, &c) in chunk[..4 - padding].iter().enumerate() {
+            let value = BASE64.iter().position(|&b| b == c)? as u32;
+            n |= value << (18 - 6 * i);
+        }
+        bytes.extend(&n.to_be_bytes()[1..4 - padding]);
+    }
+    if bytes.len() % 4 != 0 {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    )
+}
+
 pub fn ipc_socket_path() -> std::path::PathBuf {
     if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
         std::path::PathBuf::from(runtime_dir).join("ktc.sock")
@@ -55,3 +331,400 @@ pub fn ipc_socket_path() -> std::path::PathBuf {
         std::path::PathBuf::from("/tmp").join(format!("ktc-{}.sock", unsafe { libc::getuid() }))
     }
 }
+
+pub struct Client {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+    line: String,
+    connected: bool,
+}
+
+impl Client {
+    pub fn connect() -> std::io::Result<Self> {
+        Self::connect_to(ipc_socket_path())
+    }
+
+    pub fn connect_to(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::from_stream(UnixStream::connect(path)?)
+    }
+
+    pub fn from_stream(stream: UnixStream) -> std::io::Result<Self> {
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok(Self {
+            stream,
+            reader,
+            line: String::new(),
+            connected: true,
+        })
+    }
+
+    pub fn subscribe() -> std::io::Result<Self> {
+        let mut client = Self::connect()?;
+        client.set_nonblocking(true)?;
+        client.request_state()?;
+        Ok(client)
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn send(&mut self, cmd: &IpcCommand) -> std::io::Result<()> {
+        let json = serde_json::to_string(cmd)?;
+        let result = writeln!(self.stream, "{}", json);
+        if result.is_err() {
+            self.connected = false;
+        }
+        result
+    }
+
+    pub fn request_state(&mut self) -> std::io::Result<()> {
+        self.send(&IpcCommand::GetState)
+    }
+
+    pub fn switch_workspace(&mut self, workspace: usize) -> std::io::Result<()> {
+        self.send(&IpcCommand::SwitchWorkspace { workspace })
+    }
+
+    pub fn move_to_workspace(&mut self, workspace: usize, follow: bool) -> std::io::Result<()> {
+        self.send(&IpcCommand::MoveToWorkspace {
+            id: None,
+            workspace,
+            follow,
+        })
+    }
+
+    pub fn restore_window(&mut self, id: u64) -> std::io::Result<()> {
+        self.send(&IpcCommand::RestoreWindow { id })
+    }
+
+    pub fn request_workspace_preview(
+        &mut self,
+        workspace: usize,
+        width: u32,
+    ) -> std::io::Result<()> {
+        self.send(&IpcCommand::GetWorkspacePreview { workspace, width })
+    }
+
+    pub fn jump_to_mark(&mut self, mark: &str) -> std::io::Result<()> {
+        self.send(&IpcCommand::JumpToMark {
+            mark: mark.to_string(),
+        })
+    }
+
+    pub fn recv(&mut self) -> std::io::Result<Option<IpcEvent>> {
+        loop {
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => {
+                    self.connected = false;
+                    return Ok(None);
+                }
+                Ok(_) => {
+                    let parsed = serde_json::from_str::<IpcEvent>(self.line.trim());
+                    self.line.clear();
+                    if let Ok(event) = parsed {
+                        return Ok(Some(event));
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::WouldBlock {
+                        self.connected = false;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    pub fn poll_events(&mut self) -> Vec<IpcEvent> {
+        let mut events = Vec::new();
+        while let Ok(Some(event)) = self.recv() {
+            events.push(event);
+        }
+        events
+    }
+}
+
+impl AsFd for Client {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_roundtrip() {
+        let (ours, mut theirs) = UnixStream::pair().unwrap();
+        let mut client = Client::from_stream(ours).unwrap();
+        client.set_nonblocking(true).unwrap();
+
+        client.request_state().unwrap();
+        let mut reader = BufReader::new(theirs.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(matches!(
+            serde_json::from_str::<IpcCommand>(line.trim()),
+            Ok(IpcCommand::GetState)
+        ));
+
+        let event = IpcEvent::FocusChanged {
+            window_title: Some("foot".to_string()),
+        };
+        writeln!(theirs, "{}", serde_json::to_string(&event).unwrap()).unwrap();
+        writeln!(theirs, "not json").unwrap();
+        write!(theirs, "{{\"type\":\"title\",").unwrap();
+
+        let events = client.poll_events();
+        assert_eq!(events.len(), 1);
+
+        writeln!(theirs, "\"window_title\":\"vim\"}}").unwrap();
+        let events = client.poll_events();
+        assert!(matches!(
+            events.as_slice(),
+            [IpcEvent::TitleChanged { window_title }] if window_title == "vim"
+        ));
+        assert!(client.is_connected());
+
+        drop(reader);
+        drop(theirs);
+        assert!(client.poll_events().is_empty());
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn test_move_to_workspace_defaults() {
+        let cmd =
+            serde_json::from_str::<IpcCommand>(r#"{"type":"move_to_workspace","workspace":3}"#)
+                .unwrap();
+        assert!(matches!(
+            cmd,
+            IpcCommand::MoveToWorkspace {
+                id: None,
+                workspace: 3,
+                follow: false
+            }
+        ));
+    }
+
+    #[test]
+    fn test_tree_marks_default() {
+        let event = serde_json::from_str::<IpcEvent>(
+            r#"{"type":"tree","windows":[{"id":4,"title":"vim","app_id":"foot","workspace":2,"focused":true,"floating":false,"minimized":false}]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            event,
+            IpcEvent::Tree { ref windows }
+                if windows.len() == 1 && windows[0].workspace == Some(2) && windows[0].marks.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_renderer_stats_roundtrip() {
+        let cmd = serde_json::from_str::<IpcCommand>(r#"{"type":"get_renderer_stats"}"#).unwrap();
+        assert!(matches!(cmd, IpcCommand::GetRendererStats));
+
+        let event = serde_json::from_str::<IpcEvent>(
+            r#"{"type":"renderer_stats","stats":{"backend":"cpu","texture_count":0,"texture_bytes":0}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            event,
+            IpcEvent::RendererStats { ref stats }
+                if stats.backend == "cpu" && stats.page_flip_misses == 0
+        ));
+    }
+
+    #[test]
+    fn test_workspace_layout_default() {
+        let event = serde_json::from_str::<IpcEvent>(
+            r#"{"type":"workspace","workspaces":[{"id":1,"name":"1","window_count":2,"urgent":false}],"active_workspace":1}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            event,
+            IpcEvent::WorkspaceChanged { ref workspaces, .. }
+                if workspaces[0].layout.is_none()
+        ));
+    }
+
+    #[test]
+    fn test_set_opacity_defaults() {
+        let cmd =
+            serde_json::from_str::<IpcCommand>(r#"{"type":"set_opacity","opacity":0.8}"#).unwrap();
+        assert!(matches!(cmd, IpcCommand::SetOpacity { id: None, opacity } if opacity == 0.8));
+    }
+
+    #[test]
+    fn test_set_profiler_defaults() {
+        let cmd = serde_json::from_str::<IpcCommand>(r#"{"type":"set_profiler"}"#).unwrap();
+        assert!(matches!(cmd, IpcCommand::SetProfiler { enabled: None }));
+    }
+
+    #[test]
+    fn test_set_pointer_partial() {
+        let cmd = serde_json::from_str::<IpcCommand>(
+            r#"{"type":"set_pointer","device":"*Mouse*","speed":-0.5}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            cmd,
+            IpcCommand::SetPointer {
+                device: Some(ref name),
+                accel_profile: None,
+                speed: Some(speed),
+                left_handed: None,
+                middle_emulation: None,
+            } if name == "*Mouse*" && speed == -0.5
+        ));
+    }
+
+    #[test]
+    fn test_pixels_roundtrip() {
+        for pixels in [vec![], vec![0xFF102030], vec![0x80FFFFFF, 0, 0x01020304]] {
+            let encoded = encode_pixels(&pixels);
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(decode_pixels(&encoded), Some(pixels));
+        }
+        assert_eq!(encode_pixels(&[0x64636261]), "YWJjZA==");
+        assert_eq!(decode_pixels("YWJj"), None);
+        assert_eq!(decode_pixels("YW*j"), None);
+        assert_eq!(decode_pixels("YWJjZA"), None);
+        assert_eq!(decode_pixels("YWJjZA="), None);
+        assert_eq!(decode_pixels("YW==YWJj"), None);
+    }
+
+    #[test]
+    fn test_bar_visibility() {
+        let event = IpcEvent::BarVisibility { visible: false };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"{"type":"bar","visible":false}"#);
+        assert!(matches!(
+            serde_json::from_str::<IpcEvent>(&json),
+            Ok(IpcEvent::BarVisibility { visible: false })
+        ));
+    }
+
+    #[test]
+    fn test_screenshot_event() {
+        let event = IpcEvent::Screenshot {
+            path: "/home/k/Pictures/ktc.png".to_string(),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"screenshot","path":"/home/k/Pictures/ktc.png"}"#
+        );
+    }
+
+    #[test]
+    fn test_keyboard_layout_event() {
+        let event = IpcEvent::KeyboardLayout {
+            name: "de".to_string(),
+            index: 1,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"{"type":"keyboard_layout","name":"de","index":1}"#);
+    }
+
+    #[test]
+    fn test_mode_event() {
+        let event = IpcEvent::Mode {
+            name: "resize".to_string(),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"{"type":"mode","name":"resize"}"#);
+    }
+
+    #[test]
+    fn test_passthrough_event() {
+        let event = IpcEvent::Passthrough { enabled: true };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"{"type":"passthrough","enabled":true}"#);
+    }
+
+    #[test]
+    fn test_tablets_event() {
+        let cmd = serde_json::from_str::<IpcCommand>(r#"{"type":"get_tablets"}"#).unwrap();
+        assert!(matches!(cmd, IpcCommand::GetTablets));
+
+        let event = IpcEvent::Tablets {
+            tablets: vec![TabletInfo {
+                name: "Wacom Intuos S Pen".to_string(),
+                vendor: 0x056a,
+                product: 0x0374,
+                path: "/dev/input/event7".to_string(),
+                area: [0, 0, 1920, 1080],
+            }],
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"tablets","tablets":[{"name":"Wacom Intuos S Pen","vendor":1386,"product":884,"path":"/dev/input/event7","area":[0,0,1920,1080]}]}"#
+        );
+    }
+
+    #[test]
+    fn test_start_recording_defaults() {
+        let cmd =
+            serde_json::from_str::<IpcCommand>(r#"{"type":"start_recording","encoder":"y4m"}"#)
+                .unwrap();
+        assert!(matches!(
+            cmd,
+            IpcCommand::StartRecording { path: None, ref encoder, framerate: None }
+                if encoder.as_deref() == Some("y4m")
+        ));
+    }
+
+    #[test]
+    fn test_set_color_temp_defaults() {
+        let cmd =
+            serde_json::from_str::<IpcCommand>(r#"{"type":"set_color_temp","temperature":4000}"#)
+                .unwrap();
+        assert!(matches!(
+            cmd,
+            IpcCommand::SetColorTemp { ref output, temperature: Some(4000), brightness: None }
+                if output.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_set_wallpaper_defaults() {
+        let cmd = serde_json::from_str::<IpcCommand>(r#"{"type":"set_wallpaper"}"#).unwrap();
+        assert!(matches!(
+            cmd,
+            IpcCommand::SetWallpaper { ref output, path: None, mode: None } if output.is_empty()
+        ));
+
+        let cmd = serde_json::from_str::<IpcCommand>(
+            r#"{"type":"set_wallpaper","path":"~/bg.jpg","mode":"tile"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            cmd,
+            IpcCommand::SetWallpaper { path: Some(ref p), mode: Some(ref m), .. }
+                if p == "~/bg.jpg" && m == "tile"
+        ));
+    }
+
+    #[test]
+    fn test_set_output_mode_defaults() {
+        let cmd = serde_json::from_str::<IpcCommand>(
+            r#"{"type":"set_output_mode","width":2560,"height":1440}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            cmd,
+            IpcCommand::SetOutputMode { ref output, width: 2560, height: 1440, refresh: None }
+                if output.is_empty()
+        ));
+    }
+}
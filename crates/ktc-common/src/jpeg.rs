@@ -0,0 +1,811 @@
+// JPEG reader for wallpapers: Huffman-coded baseline and progressive DCT at
+// 8 bits per sample, grayscale or YCbCr with any chroma subsampling, restart
+// intervals. Lossless, arithmetic-coded and CMYK files are refused, as is
+// anything bigger than png::MAX_DIMENSION or png::MAX_PIXELS. Coefficients are collected for
+// the whole image and transformed once the last scan is in; chroma is
+// upsampled by repeating samples, which is plenty for a background.
+
+use crate::png::{MAX_DIMENSION, MAX_PIXELS};
+
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+pub fn is_jpeg(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8, 0xFF])
+}
+
+// Returns width, height and opaque ARGB pixels
+pub fn decode_jpeg(data: &[u8]) -> Result<(u32, u32, Vec<u32>), String> {
+    if !is_jpeg(data) {
+        return Err("not a JPEG file".to_string());
+    }
+
+    let mut decoder = Decoder {
+        width: 0,
+        height: 0,
+        components: Vec::new(),
+        quant: [[1; 64]; 4],
+        dc_tables: Default::default(),
+        ac_tables: Default::default(),
+        restart_interval: 0,
+        adobe_transform: None,
+        progressive: false,
+        eob_run: 0,
+        max_h: 1,
+        max_v: 1,
+    };
+    let mut pos = 2;
+    loop {
+        // markers may be padded with any number of 0xFF bytes
+        while data.get(pos) == Some(&0xFF) && data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        let (Some(&0xFF), Some(&marker)) = (data.get(pos), data.get(pos + 1)) else {
+            return Err("JPEG ends before its image".to_string());
+        };
+        pos += 2;
+        if marker == 0xD9 {
+            break;
+        }
+        if (0xD0..=0xD7).contains(&marker) || marker == 0x01 {
+            continue;
+        }
+
+        let len = match data.get(pos..pos + 2) {
+            Some(b) => u16::from_be_bytes([b[0], b[1]]) as usize,
+            None => return Err("JPEG segment is truncated".to_string()),
+        };
+        let segment = data
+            .get(pos + 2..pos + len)
+            .ok_or("JPEG segment runs past the end of the file")?;
+        pos += len;
+
+        match marker {
+            0xC0..=0xC2 => {
+                decoder.progressive = marker == 0xC2;
+                decoder.read_frame(segment)?;
+            }
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                return Err("lossless and arithmetic-coded JPEG are not supported".to_string())
+            }
+            0xC4 => decoder.read_huffman_tables(segment)?,
+            0xDB => decoder.read_quant_tables(segment)?,
+            0xDD => {
+                let interval = segment.get(..2).ok_or("JPEG DRI is short")?;
+                decoder.restart_interval = u16::from_be_bytes([interval[0], interval[1]]) as usize;
+            }
+            // Adobe: transform 0 means the components are RGB as stored
+            0xEE if segment.starts_with(b"Adobe") && segment.len() >= 12 => {
+                decoder.adobe_transform = Some(segment[11]);
+            }
+            0xDA => pos = decoder.read_scan(segment, data, pos)?,
+            _ => {}
+        }
+    }
+    decoder.to_argb()
+}
+
+#[derive(Default)]
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant: usize,
+    dc_table: usize,
+    ac_table: usize,
+    dc_pred: i32,
+    // blocks across and down, padded to whole MCUs
+    blocks_w: usize,
+    blocks_h: usize,
+    // quantized, in natural order, 64 per block
+    coefficients: Vec<i16>,
+}
+
+struct Decoder {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    quant: [[u16; 64]; 4],
+    dc_tables: [Option<Huffman>; 4],
+    ac_tables: [Option<Huffman>; 4],
+    restart_interval: usize,
+    adobe_transform: Option<u8>,
+    progressive: bool,
+    // blocks left in an end-of-band run of a progressive AC scan
+    eob_run: u32,
+    max_h: usize,
+    max_v: usize,
+}
+
+// Which part of each block a scan codes: the spectral band start..=end, and
+// the successive approximation bit positions high (0 on a first pass) and low
+#[derive(Clone, Copy)]
+struct Scan {
+    start: usize,
+    end: usize,
+    high: u8,
+    low: u8,
+}
+
+impl Decoder {
+    fn read_frame(&mut self, segment: &[u8]) -> Result<(), String> {
+        if segment.len() < 6 {
+            return Err("JPEG frame header is short".to_string());
+        }
+        if segment[0] != 8 {
+            return Err(format!("{}-bit JPEG is not supported", segment[0]));
+        }
+        self.height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+        self.width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+        let count = segment[5] as usize;
+        if self.width == 0 || self.height == 0 {
+            return Err("JPEG without a height in its frame header is not supported".to_string());
+        }
+        if self.width > MAX_DIMENSION as usize
+            || self.height > MAX_DIMENSION as usize
+            || (self.width * self.height) as u64 > MAX_PIXELS
+        {
+            return Err(format!(
+                "unsupported JPEG size {}x{}",
+                self.width, self.height
+            ));
+        }
+        if count != 1 && count != 3 {
+            return Err(format!("JPEG with {} components is not supported", count));
+        }
+        let specs = segment
+            .get(6..6 + count * 3)
+            .ok_or("JPEG frame header is short")?;
+
+        self.components = specs
+            .chunks_exact(3)
+            .map(|c| Component {
+                id: c[0],
+                h: (c[1] >> 4) as usize,
+                v: (c[1] & 0x0F) as usize,
+                quant: (c[2] & 3) as usize,
+                ..Component::default()
+            })
+            .collect();
+        if self
+            .components
+            .iter()
+            .any(|c| !(1..=4).contains(&c.h) || !(1..=4).contains(&c.v))
+        {
+            return Err("invalid JPEG sampling factors".to_string());
+        }
+
+        self.max_h = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        self.max_v = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        let (mcus_x, mcus_y) = self.mcu_counts();
+        for c in &mut self.components {
+            c.blocks_w = mcus_x * c.h;
+            c.blocks_h = mcus_y * c.v;
+            c.coefficients = vec![0; c.blocks_w * c.blocks_h * 64];
+        }
+        Ok(())
+    }
+
+    fn mcu_counts(&self) -> (usize, usize) {
+        (
+            self.width.div_ceil(8 * self.max_h),
+            self.height.div_ceil(8 * self.max_v),
+        )
+    }
+
+    fn read_huffman_tables(&mut self, mut segment: &[u8]) -> Result<(), String> {
+        while !segment.is_empty() {
+            let header = segment.get(..17).ok_or("JPEG DHT is short")?;
+            let (class, index) = (header[0] >> 4, (header[0] & 0x0F) as usize);
+            if index > 3 || class > 1 {
+                return Err("invalid JPEG Huffman table".to_string());
+            }
+            let mut counts = [0u8; 16];
+            counts.copy_from_slice(&header[1..17]);
+            let total: usize = counts.iter().map(|&c| c as usize).sum();
+            let symbols = segment.get(17..17 + total).ok_or("JPEG DHT is short")?;
+            let table = Some(Huffman::new(&counts, symbols)?);
+            if class == 0 {
+                self.dc_tables[index] = table;
+            } else {
+                self.ac_tables[index] = table;
+            }
+            segment = &segment[17 + total..];
+        }
+        Ok(())
+    }
+
+    fn read_quant_tables(&mut self, mut segment: &[u8]) -> Result<(), String> {
+        while let Some(&spec) = segment.first() {
+            let (wide, index) = (spec >> 4 == 1, (spec & 3) as usize);
+            let size = if wide { 128 } else { 64 };
+            let values = segment.get(1..1 + size).ok_or("JPEG DQT is short")?;
+            for (i, q) in self.quant[index].iter_mut().enumerate() {
+                *q = if wide {
+                    u16::from_be_bytes([values[i * 2], values[i * 2 + 1]])
+                } else {
+                    values[i] as u16
+                };
+            }
+            segment = &segment[1 + size..];
+        }
+        Ok(())
+    }
+
+    // Decodes the entropy-coded data after a scan header; returns where the
+    // next marker starts
+    fn read_scan(&mut self, segment: &[u8], data: &[u8], start: usize) -> Result<usize, String> {
+        if self.components.is_empty() {
+            return Err("JPEG scan before its frame header".to_string());
+        }
+        let count = *segment.first().ok_or("JPEG scan header is short")? as usize;
+        let specs = segment
+            .get(1..1 + count * 2)
+            .ok_or("JPEG scan header is short")?;
+        let mut indices = Vec::with_capacity(count);
+        for spec in specs.chunks_exact(2) {
+            let index = self
+                .components
+                .iter()
+                .position(|c| c.id == spec[0])
+                .ok_or("JPEG scan names an unknown component")?;
+            let component = &mut self.components[index];
+            component.dc_table = (spec[1] >> 4) as usize & 3;
+            component.ac_table = (spec[1] & 0x0F) as usize & 3;
+            component.dc_pred = 0;
+            indices.push(index);
+        }
+        if indices.is_empty() {
+            return Err("JPEG scan has no components".to_string());
+        }
+
+        let band = segment
+            .get(1 + count * 2..4 + count * 2)
+            .ok_or("JPEG scan header is short")?;
+        let scan = if self.progressive {
+            Scan {
+                start: band[0] as usize,
+                end: band[1] as usize,
+                high: band[2] >> 4,
+                low: band[2] & 0x0F,
+            }
+        } else {
+            Scan {
+                start: 0,
+                end: 63,
+                high: 0,
+                low: 0,
+            }
+        };
+        if scan.start > scan.end
+            || scan.end > 63
+            || scan.low > 13
+            || (scan.start == 0 && scan.end != 0 && self.progressive)
+            || (scan.start > 0 && indices.len() != 1)
+        {
+            return Err("invalid JPEG progressive scan".to_string());
+        }
+        self.eob_run = 0;
+
+        // one component alone is coded block by block over its own area,
+        // several are interleaved MCU by MCU
+        let (units_x, units_y) = match indices[..] {
+            [index] => {
+                let c = &self.components[index];
+                (
+                    (self.width * c.h).div_ceil(8 * self.max_h),
+                    (self.height * c.v).div_ceil(8 * self.max_v),
+                )
+            }
+            _ => self.mcu_counts(),
+        };
+
+        let mut reader = BitReader::new(data, start);
+        for n in 0..units_x * units_y {
+            if self.restart_interval > 0 && n > 0 && n % self.restart_interval == 0 {
+                reader.restart()?;
+                for &index in &indices {
+                    self.components[index].dc_pred = 0;
+                }
+                self.eob_run = 0;
+            }
+            let (ux, uy) = (n % units_x, n / units_x);
+            if let [index] = indices[..] {
+                self.decode_block(&mut reader, index, ux, uy, scan)?;
+                continue;
+            }
+            for &index in &indices {
+                let (h, v) = (self.components[index].h, self.components[index].v);
+                for by in 0..v {
+                    for bx in 0..h {
+                        self.decode_block(&mut reader, index, ux * h + bx, uy * v + by, scan)?;
+                    }
+                }
+            }
+        }
+        Ok(reader.marker_position())
+    }
+
+    // Decodes one block's part of a scan, G.1.2 for the progressive passes
+    fn decode_block(
+        &mut self,
+        reader: &mut BitReader,
+        index: usize,
+        bx: usize,
+        by: usize,
+        scan: Scan,
+    ) -> Result<(), String> {
+        let c = &mut self.components[index];
+        let offset = (by * c.blocks_w + bx) * 64;
+        let block = &mut c.coefficients[offset..offset + 64];
+
+        if scan.start == 0 {
+            if scan.high == 0 {
+                let dc = self.dc_tables[c.dc_table]
+                    .as_ref()
+                    .ok_or("JPEG scan uses a missing DC table")?;
+                let size = dc.decode(reader)?;
+                c.dc_pred = c.dc_pred.wrapping_add(extend(reader.read(size)?, size));
+                block[0] = (c.dc_pred << scan.low) as i16;
+            } else if reader.read(1)? == 1 {
+                block[0] |= 1 << scan.low;
+            }
+            if scan.end == 0 {
+                return Ok(());
+            }
+        }
+
+        let ac = self.ac_tables[c.ac_table]
+            .as_ref()
+            .ok_or("JPEG scan uses a missing AC table")?;
+        let eob_run = &mut self.eob_run;
+        let mut k = scan.start.max(1);
+
+        if scan.high == 0 {
+            if *eob_run > 0 {
+                *eob_run -= 1;
+                return Ok(());
+            }
+            while k <= scan.end {
+                let symbol = ac.decode(reader)?;
+                let (run, size) = ((symbol >> 4) as usize, symbol & 0x0F);
+                if size == 0 {
+                    if run < 15 {
+                        *eob_run = (1 << run) - 1 + reader.read(run as u8)?;
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run;
+                if k > scan.end {
+                    return Err("JPEG block has too many coefficients".to_string());
+                }
+                block[ZIGZAG[k]] = (extend(reader.read(size)?, size) << scan.low) as i16;
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        // a refinement pass adds one bit to every coefficient already
+        // nonzero and places new ones of magnitude 1 << low
+        let bit = 1i16 << scan.low;
+        if *eob_run == 0 {
+            while k <= scan.end {
+                let symbol = ac.decode(reader)?;
+                let (mut run, size) = (symbol >> 4, symbol & 0x0F);
+                let mut value = 0;
+                if size == 0 {
+                    if run < 15 {
+                        *eob_run = (1 << run) + reader.read(run)?;
+                        break;
+                    }
+                } else {
+                    value = if reader.read(1)? == 1 { bit } else { -bit };
+                }
+                while k <= scan.end {
+                    let coefficient = &mut block[ZIGZAG[k]];
+                    if *coefficient != 0 {
+                        refine(coefficient, bit, reader)?;
+                    } else if run == 0 {
+                        break;
+                    } else {
+                        run -= 1;
+                    }
+                    k += 1;
+                }
+                if value != 0 {
+                    if k > scan.end {
+                        return Err("JPEG block has too many coefficients".to_string());
+                    }
+                    block[ZIGZAG[k]] = value;
+                }
+                k += 1;
+            }
+        }
+        if *eob_run > 0 {
+            while k <= scan.end {
+                if block[ZIGZAG[k]] != 0 {
+                    refine(&mut block[ZIGZAG[k]], bit, reader)?;
+                }
+                k += 1;
+            }
+            *eob_run -= 1;
+        }
+        Ok(())
+    }
+
+    // Dequantizes and transforms a component's blocks into 8-bit samples
+    fn samples(&self, c: &Component) -> Vec<u8> {
+        let mut quant = [0f32; 64];
+        for (k, &q) in self.quant[c.quant].iter().enumerate() {
+            quant[ZIGZAG[k]] = q as f32;
+        }
+        let stride = c.blocks_w * 8;
+        let mut plane = vec![0u8; stride * c.blocks_h * 8];
+        for (n, block) in c.coefficients.chunks_exact(64).enumerate() {
+            let mut coefficients = [0f32; 64];
+            for (i, out) in coefficients.iter_mut().enumerate() {
+                *out = block[i] as f32 * quant[i];
+            }
+            let (bx, by) = (n % c.blocks_w, n / c.blocks_w);
+            idct(
+                &coefficients,
+                &mut plane[by * 8 * stride + bx * 8..],
+                stride,
+            );
+        }
+        plane
+    }
+
+    fn to_argb(&self) -> Result<(u32, u32, Vec<u32>), String> {
+        if self.components.is_empty() {
+            return Err("JPEG has no frame".to_string());
+        }
+        let planes: Vec<Vec<u8>> = self.components.iter().map(|c| self.samples(c)).collect();
+        let sample = |i: usize, x: usize, y: usize| -> f32 {
+            let c = &self.components[i];
+            let sx = x * c.h / self.max_h;
+            let sy = y * c.v / self.max_v;
+            planes[i][sy * c.blocks_w * 8 + sx] as f32
+        };
+        // JFIF is always YCbCr; Adobe says so itself, or the ids spell RGB
+        let rgb =
+            self.adobe_transform == Some(0) || self.components.iter().map(|c| c.id).eq(*b"RGB");
+
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (r, g, b) = if planes.len() == 1 {
+                    let v = sample(0, x, y);
+                    (v, v, v)
+                } else if rgb {
+                    (sample(0, x, y), sample(1, x, y), sample(2, x, y))
+                } else {
+                    let l = sample(0, x, y);
+                    let cb = sample(1, x, y) - 128.0;
+                    let cr = sample(2, x, y) - 128.0;
+                    (
+                        l + 1.402 * cr,
+                        l - 0.344136 * cb - 0.714136 * cr,
+                        l + 1.772 * cb,
+                    )
+                };
+                let channel = |v: f32| v.round().clamp(0.0, 255.0) as u32;
+                pixels.push(0xFF000000 | channel(r) << 16 | channel(g) << 8 | channel(b));
+            }
+        }
+        Ok((self.width as u32, self.height as u32, pixels))
+    }
+}
+
+// a correction bit raises the magnitude of an already nonzero coefficient
+fn refine(coefficient: &mut i16, bit: i16, reader: &mut BitReader) -> Result<(), String> {
+    if reader.read(1)? == 1 && *coefficient & bit == 0 {
+        // a corrupt scan can keep refining the same coefficient
+        *coefficient = coefficient.saturating_add(if *coefficient >= 0 { bit } else { -bit });
+    }
+    Ok(())
+}
+
+// the value of a size-bit magnitude category, F.2.2.1
+fn extend(value: u32, size: u8) -> i32 {
+    if size == 0 {
+        return 0;
+    }
+    let value = value as i32;
+    if value < 1 << (size - 1) {
+        value - (1 << size) + 1
+    } else {
+        value
+    }
+}
+
+// Separable float inverse DCT into 8 rows of an 8-bit plane
+fn idct(coefficients: &[f32; 64], out: &mut [u8], stride: usize) {
+    let table = idct_table();
+    let mut rows = [0f32; 64];
+    for y in 0..8 {
+        let row = &coefficients[y * 8..y * 8 + 8];
+        if row[1..].iter().all(|&c| c == 0.0) {
+            rows[y * 8..y * 8 + 8].fill(row[0] * table[0]);
+            continue;
+        }
+        for x in 0..8 {
+            rows[y * 8 + x] = (0..8).map(|u| row[u] * table[x * 8 + u]).sum();
+        }
+    }
+    for x in 0..8 {
+        for y in 0..8 {
+            let value: f32 = (0..8).map(|v| rows[v * 8 + x] * table[y * 8 + v]).sum();
+            out[y * stride + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+// table[x * 8 + u] = C(u) / 2 * cos((2x + 1) u pi / 16)
+fn idct_table() -> &'static [f32; 64] {
+    static TABLE: std::sync::OnceLock<[f32; 64]> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0f32; 64];
+        for x in 0..8 {
+            for u in 0..8 {
+                let scale = if u == 0 {
+                    std::f32::consts::FRAC_1_SQRT_2
+                } else {
+                    1.0
+                };
+                let angle = (2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0;
+                table[x * 8 + u] = scale / 2.0 * angle.cos();
+            }
+        }
+        table
+    })
+}
+
+// Entropy-coded data, most significant bit first, with 0xFF00 unstuffed.
+// Reading into a marker yields zeros, as the spec asks.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self {
+            data,
+            pos,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.count <= 56 {
+            let byte = match self.data.get(self.pos) {
+                Some(&0xFF) if self.data.get(self.pos + 1) == Some(&0) => {
+                    self.pos += 2;
+                    0xFF
+                }
+                // a marker, or the end of the file
+                Some(&0xFF) | None => 0,
+                Some(&byte) => {
+                    self.pos += 1;
+                    byte
+                }
+            };
+            self.bits |= (byte as u64) << (56 - self.count);
+            self.count += 8;
+        }
+    }
+
+    fn peek(&mut self, n: u32) -> u32 {
+        if self.count < n {
+            self.refill();
+        }
+        (self.bits >> (64 - n)) as u32
+    }
+
+    fn consume(&mut self, n: u32) {
+        self.bits <<= n;
+        self.count -= n;
+    }
+
+    fn read(&mut self, n: u8) -> Result<u32, String> {
+        if n == 0 {
+            return Ok(0);
+        }
+        if n > 16 {
+            return Err("JPEG coefficient is too large".to_string());
+        }
+        let value = self.peek(n as u32);
+        self.consume(n as u32);
+        Ok(value)
+    }
+
+    // drops what's left of the current byte and steps over an RSTn marker
+    fn restart(&mut self) -> Result<(), String> {
+        self.bits = 0;
+        self.count = 0;
+        while self.data.get(self.pos) == Some(&0xFF) && self.data.get(self.pos + 1) == Some(&0xFF) {
+            self.pos += 1;
+        }
+        match self.data.get(self.pos..self.pos + 2) {
+            Some([0xFF, 0xD0..=0xD7]) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err("JPEG restart marker is missing".to_string()),
+        }
+    }
+
+    // the next marker after the scan's data
+    fn marker_position(&self) -> usize {
+        let mut pos = self.pos;
+        while pos + 1 < self.data.len() {
+            if self.data[pos] == 0xFF && !matches!(self.data[pos + 1], 0x00 | 0xD0..=0xD7 | 0xFF) {
+                return pos;
+            }
+            pos += 1;
+        }
+        self.data.len()
+    }
+}
+
+const FAST_BITS: u32 = 9;
+
+struct Huffman {
+    // (length << 8) | symbol for codes up to FAST_BITS long, 0 otherwise
+    fast: Vec<u16>,
+    // per length: the largest code, or -1, and where its symbols start
+    max_code: [i32; 17],
+    offsets: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8; 16], symbols: &[u8]) -> Result<Self, String> {
+        let mut fast = vec![0u16; 1 << FAST_BITS];
+        let mut max_code = [-1i32; 17];
+        let mut offsets = [0i32; 17];
+        let mut code = 0i32;
+        let mut index = 0usize;
+        for len in 1..=16 {
+            let count = counts[len - 1] as usize;
+            offsets[len] = index as i32 - code;
+            for _ in 0..count {
+                if code >= 1 << len {
+                    return Err("invalid JPEG Huffman table".to_string());
+                }
+                if len as u32 <= FAST_BITS {
+                    let shift = FAST_BITS - len as u32;
+                    let first = (code as usize) << shift;
+                    let entry = (len << 8) as u16 | symbols[index] as u16;
+                    fast[first..first + (1 << shift)].fill(entry);
+                }
+                code += 1;
+                index += 1;
+            }
+            if count > 0 {
+                max_code[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        Ok(Self {
+            fast,
+            max_code,
+            offsets,
+            symbols: symbols.to_vec(),
+        })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u8, String> {
+        let entry = self.fast[reader.peek(FAST_BITS) as usize];
+        if entry != 0 {
+            reader.consume((entry >> 8) as u32);
+            return Ok(entry as u8);
+        }
+        let bits = reader.peek(16) as i32;
+        for len in FAST_BITS as usize + 1..=16 {
+            let code = bits >> (16 - len);
+            if code <= self.max_code[len] {
+                reader.consume(len as u32);
+                return Ok(self.symbols[(self.offsets[len] + code) as usize]);
+            }
+        }
+        Err("invalid JPEG Huffman code".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) {
+        out.extend_from_slice(&[0xFF, marker]);
+        out.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(data);
+    }
+
+    // 16x8 baseline YCbCr without subsampling: a red MCU, a restart marker
+    // and a blue MCU. Quantization is all 1s and every block is DC only.
+    fn two_color_jpeg() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        let mut dqt = vec![0];
+        dqt.extend_from_slice(&[1; 64]);
+        segment(&mut jpeg, 0xDB, &dqt);
+        segment(
+            &mut jpeg,
+            0xC0,
+            &[8, 0, 8, 0, 16, 3, 1, 0x11, 0, 2, 0x11, 0, 3, 0x11, 0],
+        );
+        // DC categories 0-11 as 4-bit codes, AC with only end-of-block as "0"
+        let mut dc = vec![0x00, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        dc.extend(0..12);
+        segment(&mut jpeg, 0xC4, &dc);
+        segment(
+            &mut jpeg,
+            0xC4,
+            &[0x10, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        );
+        segment(&mut jpeg, 0xDD, &[0, 1]);
+        segment(&mut jpeg, 0xDA, &[3, 1, 0x00, 2, 0x00, 3, 0x00, 0, 63, 0]);
+        jpeg.extend_from_slice(&[0x92, 0xFA, 0x54, 0xEA, 0xFE, 0x1F, 0xFF, 0xD0]);
+        jpeg.extend_from_slice(&[0xA3, 0x9D, 0x5F, 0xC2, 0x15, 0xDF, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    fn close(pixel: u32, expected: u32) -> bool {
+        (0..3).all(|i| {
+            let (a, b) = ((pixel >> (i * 8)) & 0xFF, (expected >> (i * 8)) & 0xFF);
+            a.abs_diff(b) <= 3
+        }) && pixel >> 24 == 0xFF
+    }
+
+    #[test]
+    fn test_decode_baseline() {
+        let jpeg = two_color_jpeg();
+        assert!(is_jpeg(&jpeg));
+        let (width, height, pixels) = decode_jpeg(&jpeg).unwrap();
+        assert_eq!((width, height), (16, 8));
+        for (i, &pixel) in pixels.iter().enumerate() {
+            let expected = if i % 16 < 8 { 0xFF0000 } else { 0x0000FF };
+            assert!(close(pixel, expected), "pixel {} is {:08x}", i, pixel);
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects() {
+        assert!(decode_jpeg(b"\x89PNG").is_err());
+
+        let jpeg = two_color_jpeg();
+        // the restart marker is missing
+        let mut broken = jpeg.clone();
+        let rst = broken.len() - 10;
+        broken.drain(rst..rst + 2);
+        assert!(decode_jpeg(&broken).is_err());
+
+        // lossless
+        let mut lossless = jpeg.clone();
+        let sof = lossless.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        lossless[sof + 1] = 0xC3;
+        assert!(decode_jpeg(&lossless).is_err());
+
+        assert!(decode_jpeg(&jpeg[..jpeg.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_extend() {
+        assert_eq!(extend(0, 0), 0);
+        assert_eq!(extend(0, 1), -1);
+        assert_eq!(extend(1, 1), 1);
+        assert_eq!(extend(0, 2), -3);
+        assert_eq!(extend(3, 2), 3);
+        assert_eq!(extend(0b0111, 4), -8);
+    }
+}
@@ -1,11 +1,22 @@
+pub mod canvas;
 pub mod color;
 pub mod font;
 pub mod ipc;
+pub mod jpeg;
 pub mod logging;
 pub mod paths;
+pub mod png;
+pub mod rect;
+pub mod simd;
 
-pub use color::parse_color;
+pub use canvas::Canvas;
+pub use color::{parse_color, temperature_gain, NEUTRAL_TEMPERATURE};
 pub use font::Font;
-pub use ipc::{ipc_socket_path, IpcCommand, IpcEvent, WorkspaceInfo};
+pub use ipc::{
+    decode_pixels, encode_pixels, ipc_socket_path, Client as IpcClient, HiddenWindowInfo,
+    IpcCommand, IpcEvent, RendererStats, TabletInfo, WindowTreeInfo, WorkspaceInfo, BAR_NAMESPACE,
+};
 pub use logging::{current_session_dir, AppLogger, FileLogger};
 pub use paths::{config_dir, data_dir, ktc_config_dir, ktc_data_dir, ktc_log_dir};
+pub use png::encode_png;
+pub use rect::Rectangle;
@@ -0,0 +1,1065 @@
+// Minimal PNG writer for screenshots: 8-bit RGB, rows filtered with
+// whichever filter leaves the smallest differences, then one deflate block
+// of LZ77 matches under the fixed Huffman codes. Data that doesn't shrink is
+// written as stored blocks instead.
+//
+// The reader is for wallpapers and takes any standard PNG: every color type
+// and bit depth, interlaced or not. 16-bit samples are cut to 8 bits.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+// deflate's window and match lengths; MAX_CHAIN caps how many earlier
+// positions with the same hash are compared, trading ratio for speed
+const WINDOW: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 15;
+// larger images are refused rather than allocated; a side may go up to
+// MAX_DIMENSION but the whole image stays under MAX_PIXELS (256 MiB of ARGB)
+pub const MAX_DIMENSION: u32 = 16384;
+pub const MAX_PIXELS: u64 = 1 << 26;
+
+// pixels are (A)RGB rows of width; alpha is dropped
+pub fn encode_png(width: u32, height: u32, pixels: &[u32]) -> Vec<u8> {
+    let row_len = width as usize * 3;
+    let mut raw = Vec::with_capacity((row_len + 1) * height as usize);
+    let mut previous = vec![0u8; row_len];
+    let mut current = Vec::with_capacity(row_len);
+    let (mut best, mut scratch) = (Vec::with_capacity(row_len), Vec::with_capacity(row_len));
+    for row in pixels
+        .chunks_exact(width.max(1) as usize)
+        .take(height as usize)
+    {
+        current.clear();
+        for &pixel in row {
+            current.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]);
+        }
+        // the usual heuristic: smallest sum of differences taken as signed
+        let mut best_cost = (u64::MAX, 0);
+        for kind in 0..5 {
+            filter(kind, &current, &previous, 3, &mut scratch);
+            let cost = scratch
+                .iter()
+                .map(|&b| (b as i8).unsigned_abs() as u64)
+                .sum();
+            if cost < best_cost.0 {
+                best_cost = (cost, kind);
+                std::mem::swap(&mut best, &mut scratch);
+            }
+        }
+        raw.push(best_cost.1);
+        raw.extend_from_slice(&best);
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // bit depth 8, color type RGB, deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let idat = zlib_compress(&raw);
+    let mut png = Vec::with_capacity(idat.len() + 64);
+    png.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &idat);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// the inverse of unfilter, row into out
+fn filter(kind: u8, row: &[u8], previous: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.clear();
+    for (i, (&byte, &up)) in row.iter().zip(previous).enumerate() {
+        let (left, up_left) = if i >= bpp {
+            (row[i - bpp], previous[i - bpp])
+        } else {
+            (0, 0)
+        };
+        let predicted = match kind {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            _ => paeth(left, up, up_left),
+        };
+        out.push(byte.wrapping_sub(predicted));
+    }
+}
+
+fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // deflate with a 32K window, default compression, no preset dictionary
+    writer.out.extend_from_slice(&[0x78, 0x9C]);
+    deflate_fixed(data, &mut writer);
+    let mut out = writer.finish();
+    // fixed codes spend 9 bits on half the byte values, so noise grows
+    if out.len() > data.len() + data.len() / MAX_STORED_BLOCK * 5 + 7 {
+        return zlib_stored(data);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+fn write_fixed_symbol(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, len: usize, distance: usize) {
+    let index = LENGTH_BASE.partition_point(|&base| base as usize <= len) - 1;
+    write_fixed_symbol(writer, 257 + index as u32);
+    let extra = (len - LENGTH_BASE[index] as usize) as u32;
+    writer.write(extra, LENGTH_EXTRA[index] as u32);
+
+    let index = DIST_BASE.partition_point(|&base| base as usize <= distance) - 1;
+    writer.write_code(index as u32, 5);
+    let extra = (distance - DIST_BASE[index] as usize) as u32;
+    writer.write(extra, DIST_EXTRA[index] as u32);
+}
+
+// One final block with fixed Huffman codes. Matches are found greedily
+// through hash chains of the three bytes starting at each position.
+fn deflate_fixed(data: &[u8], writer: &mut BitWriter) {
+    const NONE: usize = usize::MAX;
+    let hash = |i: usize| {
+        let value = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (value.wrapping_mul(0x9E3779B1) >> (32 - HASH_BITS)) as usize
+    };
+    let mut head = vec![NONE; 1 << HASH_BITS];
+    let mut prev = vec![NONE; WINDOW];
+
+    // final block, fixed codes
+    writer.write(1, 1);
+    writer.write(1, 2);
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_distance) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            let max = (data.len() - i).min(MAX_MATCH);
+            let mut candidate = head[h];
+            let mut chain = MAX_CHAIN;
+            while candidate != NONE && i - candidate <= WINDOW && chain > 0 {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    (best_len, best_distance) = (len, i - candidate);
+                    if len == max {
+                        break;
+                    }
+                }
+                candidate = prev[candidate % WINDOW];
+                chain -= 1;
+            }
+            prev[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+
+        if best_len < MIN_MATCH {
+            write_fixed_symbol(writer, data[i] as u32);
+            i += 1;
+            continue;
+        }
+        write_match(writer, best_len, best_distance);
+        for j in i + 1..(i + best_len).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+            let h = hash(j);
+            prev[j % WINDOW] = head[h];
+            head[h] = j;
+        }
+        i += best_len;
+    }
+    write_fixed_symbol(writer, 256);
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_STORED_BLOCK * 5 + 11);
+    // deflate with a 32K window, no preset dictionary
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn is_png(data: &[u8]) -> bool {
+    data.starts_with(&SIGNATURE)
+}
+
+// Returns width, height and ARGB pixels, not premultiplied
+pub fn decode_png(data: &[u8]) -> Result<(u32, u32, Vec<u32>), String> {
+    if !is_png(data) {
+        return Err("not a PNG file".to_string());
+    }
+
+    let mut header = None;
+    let mut palette: Vec<u32> = Vec::new();
+    let mut transparent: Option<Vec<u8>> = None;
+    let mut idat = Vec::new();
+    let mut pos = SIGNATURE.len();
+    loop {
+        let Some(chunk) = data.get(pos..pos + 8) else {
+            return Err("PNG ends before IEND".to_string());
+        };
+        let len = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+        let kind = &chunk[4..8];
+        let body = data
+            .get(pos + 8..pos + 8 + len)
+            .ok_or("PNG chunk runs past the end of the file")?;
+        // crc is skipped; a damaged file fails in inflate or the size checks
+        pos += 12 + len;
+
+        match kind {
+            b"IHDR" => header = Some(Header::parse(body)?),
+            b"PLTE" => {
+                palette = body
+                    .chunks_exact(3)
+                    .map(|c| 0xFF000000 | (c[0] as u32) << 16 | (c[1] as u32) << 8 | c[2] as u32)
+                    .collect();
+            }
+            b"tRNS" => transparent = Some(body.to_vec()),
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or("PNG has no IHDR")?;
+    if header.color == COLOR_PALETTE {
+        if palette.is_empty() {
+            return Err("palette PNG has no PLTE".to_string());
+        }
+        // tRNS holds one alpha per palette entry, the rest stay opaque
+        for (entry, &alpha) in palette.iter_mut().zip(transparent.iter().flatten()) {
+            *entry = (*entry & 0x00FFFFFF) | (alpha as u32) << 24;
+        }
+    }
+
+    let raw = zlib_inflate(&idat, header.raw_size())?;
+    let mut pixels = vec![0u32; header.width as usize * header.height as usize];
+    let key = header.color_key(transparent.as_deref());
+    let mut offset = 0;
+    for pass in header.passes() {
+        let row_len = header.row_bytes(pass.width);
+        let mut previous = vec![0u8; row_len];
+        let mut row = vec![0u8; row_len];
+        for y in 0..pass.height {
+            let filter = *raw.get(offset).ok_or("PNG image data is short")?;
+            let line = raw
+                .get(offset + 1..offset + 1 + row_len)
+                .ok_or("PNG image data is short")?;
+            offset += row_len + 1;
+            row.copy_from_slice(line);
+            unfilter(filter, &mut row, &previous, header.bytes_per_pixel())?;
+
+            let py = pass.y0 + y * pass.dy;
+            for x in 0..pass.width {
+                let px = pass.x0 + x * pass.dx;
+                pixels[py * header.width as usize + px] = header.pixel(&row, x, &palette, key);
+            }
+            std::mem::swap(&mut row, &mut previous);
+        }
+    }
+    Ok((header.width, header.height, pixels))
+}
+
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+struct Header {
+    width: u32,
+    height: u32,
+    depth: u8,
+    color: u8,
+    interlaced: bool,
+}
+
+// one Adam7 pass, or the whole image
+struct Pass {
+    x0: usize,
+    y0: usize,
+    dx: usize,
+    dy: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Header {
+    fn parse(body: &[u8]) -> Result<Self, String> {
+        if body.len() < 13 {
+            return Err("PNG IHDR is short".to_string());
+        }
+        let width = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+        let height = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+        let (depth, color) = (body[8], body[9]);
+        if width == 0
+            || height == 0
+            || width > MAX_DIMENSION
+            || height > MAX_DIMENSION
+            || width as u64 * height as u64 > MAX_PIXELS
+        {
+            return Err(format!("unsupported PNG size {}x{}", width, height));
+        }
+        let valid = match color {
+            COLOR_GRAY => matches!(depth, 1 | 2 | 4 | 8 | 16),
+            COLOR_PALETTE => matches!(depth, 1 | 2 | 4 | 8),
+            COLOR_RGB | COLOR_GRAY_ALPHA | COLOR_RGBA => matches!(depth, 8 | 16),
+            _ => false,
+        };
+        if !valid {
+            return Err(format!(
+                "unsupported PNG color type {} at depth {}",
+                color, depth
+            ));
+        }
+        if body[10] != 0 || body[11] != 0 || body[12] > 1 {
+            return Err("unknown PNG compression, filter or interlace method".to_string());
+        }
+        Ok(Self {
+            width,
+            height,
+            depth,
+            color,
+            interlaced: body[12] == 1,
+        })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color {
+            COLOR_RGB => 3,
+            COLOR_GRAY_ALPHA => 2,
+            COLOR_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.channels() * self.depth as usize).div_ceil(8)
+    }
+
+    // the distance filters look back, at least one byte
+    fn bytes_per_pixel(&self) -> usize {
+        (self.channels() * self.depth as usize).div_ceil(8)
+    }
+
+    fn passes(&self) -> Vec<Pass> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let layout: &[(usize, usize, usize, usize)] = if self.interlaced {
+            &[
+                (0, 0, 8, 8),
+                (4, 0, 8, 8),
+                (0, 4, 4, 8),
+                (2, 0, 4, 4),
+                (0, 2, 2, 4),
+                (1, 0, 2, 2),
+                (0, 1, 1, 2),
+            ]
+        } else {
+            &[(0, 0, 1, 1)]
+        };
+        layout
+            .iter()
+            .map(|&(x0, y0, dx, dy)| Pass {
+                x0,
+                y0,
+                dx,
+                dy,
+                width: (w + dx - 1 - x0) / dx,
+                height: (h + dy - 1 - y0) / dy,
+            })
+            .filter(|p| p.width > 0 && p.height > 0)
+            .collect()
+    }
+
+    fn raw_size(&self) -> usize {
+        self.passes()
+            .iter()
+            .map(|p| (self.row_bytes(p.width) + 1) * p.height)
+            .sum()
+    }
+
+    // gray and RGB images may name one sample value as fully transparent
+    fn color_key(&self, transparent: Option<&[u8]>) -> Option<[u16; 3]> {
+        let t = transparent?;
+        let sample = |i: usize| Some(u16::from_be_bytes([*t.get(i)?, *t.get(i + 1)?]));
+        match self.color {
+            COLOR_GRAY => {
+                let gray = sample(0)?;
+                Some([gray; 3])
+            }
+            COLOR_RGB => Some([sample(0)?, sample(2)?, sample(4)?]),
+            _ => None,
+        }
+    }
+
+    // sample i of the row at full depth
+    fn sample(&self, row: &[u8], i: usize) -> u16 {
+        match self.depth {
+            16 => u16::from_be_bytes([row[i * 2], row[i * 2 + 1]]),
+            8 => row[i] as u16,
+            depth => {
+                let bit = i * depth as usize;
+                let shift = 8 - depth as usize - bit % 8;
+                (row[bit / 8] as u16 >> shift) & ((1 << depth) - 1)
+            }
+        }
+    }
+
+    // a sample scaled to 8 bits
+    fn level(&self, sample: u16) -> u32 {
+        match self.depth {
+            16 => (sample >> 8) as u32,
+            8 => sample as u32,
+            depth => sample as u32 * 255 / ((1 << depth) - 1),
+        }
+    }
+
+    fn pixel(&self, row: &[u8], x: usize, palette: &[u32], key: Option<[u16; 3]>) -> u32 {
+        let n = self.channels();
+        let s = |c: usize| self.sample(row, x * n + c);
+        let argb = |a: u32, r: u32, g: u32, b: u32| a << 24 | r << 16 | g << 8 | b;
+        match self.color {
+            COLOR_PALETTE => palette.get(s(0) as usize).copied().unwrap_or(0xFF000000),
+            COLOR_GRAY => {
+                let gray = s(0);
+                let alpha = if key == Some([gray; 3]) { 0 } else { 255 };
+                let v = self.level(gray);
+                argb(alpha, v, v, v)
+            }
+            COLOR_GRAY_ALPHA => {
+                let v = self.level(s(0));
+                argb(self.level(s(1)), v, v, v)
+            }
+            COLOR_RGB => {
+                let rgb = [s(0), s(1), s(2)];
+                let alpha = if key == Some(rgb) { 0 } else { 255 };
+                argb(
+                    alpha,
+                    self.level(rgb[0]),
+                    self.level(rgb[1]),
+                    self.level(rgb[2]),
+                )
+            }
+            _ => argb(
+                self.level(s(3)),
+                self.level(s(0)),
+                self.level(s(1)),
+                self.level(s(2)),
+            ),
+        }
+    }
+}
+
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), String> {
+    match filter {
+        0 => {}
+        1 => {
+            for i in bpp..row.len() {
+                row[i] = row[i].wrapping_add(row[i - bpp]);
+            }
+        }
+        2 => {
+            for (byte, &up) in row.iter_mut().zip(previous) {
+                *byte = byte.wrapping_add(up);
+            }
+        }
+        3 => {
+            for i in 0..row.len() {
+                let left = if i >= bpp { row[i - bpp] as u16 } else { 0 };
+                row[i] = row[i].wrapping_add(((left + previous[i] as u16) / 2) as u8);
+            }
+        }
+        4 => {
+            for i in 0..row.len() {
+                let (left, up_left) = if i >= bpp {
+                    (row[i - bpp], previous[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                row[i] = row[i].wrapping_add(paeth(left, previous[i], up_left));
+            }
+        }
+        _ => return Err(format!("unknown PNG filter type {}", filter)),
+    }
+    Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// zlib stream to bytes; a stream that would grow past limit is refused
+pub fn zlib_inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    if data.len() < 2 || !(data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31) {
+        return Err("bad zlib header".to_string());
+    }
+    if data[0] & 0x0F != 8 || data[1] & 0x20 != 0 {
+        return Err("unsupported zlib compression method".to_string());
+    }
+    let mut out = Vec::with_capacity(limit);
+    if inflate(&data[2..], &mut out, limit)? != adler32(&out) {
+        return Err("zlib checksum mismatch".to_string());
+    }
+    Ok(out)
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.count <= 56 {
+            let Some(&byte) = self.data.get(self.pos) else {
+                break;
+            };
+            self.bits |= (byte as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+    }
+
+    // up to 16 bits without consuming them; zeros past the end
+    fn peek(&mut self) -> u32 {
+        if self.count < 16 {
+            self.refill();
+        }
+        (self.bits & 0xFFFF) as u32
+    }
+
+    fn consume(&mut self, n: u32) -> Result<(), String> {
+        if n > self.count {
+            return Err("deflate stream is truncated".to_string());
+        }
+        self.bits >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    fn read(&mut self, n: u32) -> Result<u32, String> {
+        if self.count < n {
+            self.refill();
+        }
+        let value = (self.bits & ((1u64 << n) - 1)) as u32;
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        let skip = self.count % 8;
+        self.bits >>= skip;
+        self.count -= skip;
+    }
+}
+
+const FAST_BITS: u32 = 9;
+
+// Canonical Huffman code. Codes up to FAST_BITS long are looked up in one
+// step, longer ones are walked a bit at a time.
+struct Huffman {
+    // (length << 9) | symbol, 0 where no short code matches
+    fast: Vec<u16>,
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, String> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        let mut fast = vec![0u16; 1 << FAST_BITS];
+        let mut code = 0u32;
+        let mut next_code = [0u32; 16];
+        for len in 1..16 {
+            code = (code + counts[len - 1] as u32) << 1;
+            next_code[len] = code;
+        }
+        for (symbol, &len) in lengths.iter().enumerate() {
+            let len = len as u32;
+            if len == 0 {
+                continue;
+            }
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            if code >= 1 << len {
+                return Err("over-subscribed Huffman code".to_string());
+            }
+            if len <= FAST_BITS {
+                // the stream holds codes most significant bit first
+                let reversed = code.reverse_bits() >> (32 - len);
+                let mut index = reversed as usize;
+                while index < fast.len() {
+                    fast[index] = (len << 9) as u16 | symbol as u16;
+                    index += 1 << len;
+                }
+            }
+        }
+        Ok(Self {
+            fast,
+            counts,
+            symbols,
+        })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let bits = reader.peek();
+        let entry = self.fast[(bits & ((1 << FAST_BITS) - 1)) as usize];
+        if entry != 0 {
+            reader.consume(entry as u32 >> 9)?;
+            return Ok(entry & 0x1FF);
+        }
+
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= ((bits >> (len - 1)) & 1) as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                reader.consume(len as u32)?;
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// order code length code lengths are sent in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// Inflates a raw deflate stream into out, at most limit bytes; returns the
+// big-endian word that follows it, which zlib uses for its checksum
+fn inflate(data: &[u8], out: &mut Vec<u8>, limit: usize) -> Result<u32, String> {
+    let mut reader = BitReader::new(data);
+    loop {
+        let last = reader.read(1)? == 1;
+        match reader.read(2)? {
+            0 => {
+                reader.align();
+                let len = reader.read(16)?;
+                let nlen = reader.read(16)?;
+                if len != !nlen & 0xFFFF {
+                    return Err("stored block length mismatch".to_string());
+                }
+                check_limit(out, len as usize, limit)?;
+                for _ in 0..len {
+                    out.push(reader.read(8)? as u8);
+                }
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                inflate_block(&mut reader, out, &literals, &distances, limit)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, out, &literals, &distances, limit)?;
+            }
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+        if last {
+            reader.align();
+            let mut trailer = 0;
+            for _ in 0..4 {
+                trailer = trailer << 8 | reader.read(8)?;
+            }
+            return Ok(trailer);
+        }
+    }
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.read(5)? as usize + 257;
+    let distance_count = reader.read(5)? as usize + 1;
+    let code_count = reader.read(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_count] {
+        code_lengths[i] = reader.read(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let (value, repeat) = match codes.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..i]
+                    .last()
+                    .ok_or("length repeat with no previous length")?;
+                (previous, 3 + reader.read(2)? as usize)
+            }
+            17 => (0, 3 + reader.read(3)? as usize),
+            _ => (0, 11 + reader.read(7)? as usize),
+        };
+        let end = i + repeat;
+        if end > lengths.len() {
+            return Err("code lengths overrun".to_string());
+        }
+        lengths[i..end].fill(value);
+        i = end;
+    }
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals)?, Huffman::new(distances)?))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+    limit: usize,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            check_limit(out, 1, limit)?;
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err("invalid length symbol".to_string());
+        }
+        let len = LENGTH_BASE[index] as usize + reader.read(LENGTH_EXTRA[index] as u32)? as usize;
+        let index = distances.decode(reader)? as usize;
+        if index >= DIST_BASE.len() {
+            return Err("invalid distance symbol".to_string());
+        }
+        let distance = DIST_BASE[index] as usize + reader.read(DIST_EXTRA[index] as u32)? as usize;
+        if distance > out.len() {
+            return Err("distance reaches before the start of the output".to_string());
+        }
+        check_limit(out, len, limit)?;
+        let start = out.len() - distance;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+// a few bytes of deflate can expand to gigabytes, so output stops at what
+// the caller can use
+fn check_limit(out: &[u8], more: usize, limit: usize) -> Result<(), String> {
+    if out.len() + more > limit {
+        return Err("deflate stream is larger than expected".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let png = encode_png(2, 1, &[0xFFFF0000, 0x000000FF]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // IDAT holds the row unfiltered, as no filter makes it smaller
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let idat = &png[41..41 + idat_len];
+        let row = [0, 0xFF, 0, 0, 0, 0, 0xFF];
+        assert_eq!(zlib_inflate(idat, row.len()), Ok(row.to_vec()));
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let previous: Vec<u8> = (0..12u8).map(|i| i * 19).collect();
+        let row: Vec<u8> = (0..12u8).map(|i| 200 - i * 7).collect();
+        let mut filtered = Vec::new();
+        for kind in 0..5 {
+            filter(kind, &row, &previous, 3, &mut filtered);
+            unfilter(kind, &mut filtered, &previous, 3).unwrap();
+            assert_eq!(filtered, row);
+        }
+    }
+
+    #[test]
+    fn test_compress() {
+        // a flat screen shrinks to a fraction of its size
+        let pixels = vec![0xFF336699; 256 * 64];
+        let png = encode_png(256, 64, &pixels);
+        assert!(png.len() < 1024);
+        assert_eq!(decode_png(&png), Ok((256, 64, pixels)));
+
+        // long runs, matches at the window's far end, and plain literals
+        let mut data: Vec<u8> = (0..40000u32).map(|i| (i * 7 % 253) as u8).collect();
+        data.extend(std::iter::repeat_n(9, 1000));
+        data.extend_from_within(..WINDOW);
+        let zlib = zlib_compress(&data);
+        assert!(zlib.len() < data.len() / 2);
+        assert_eq!(zlib_inflate(&zlib, data.len()), Ok(data));
+
+        // noise that fixed codes would grow is stored
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..5000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let zlib = zlib_compress(&noise);
+        assert_eq!(zlib[2] & 0b110, 0);
+        assert_eq!(zlib_inflate(&zlib, noise.len()), Ok(noise));
+        assert_eq!(zlib_inflate(&zlib_compress(&[]), 0), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_stored_blocks_split() {
+        let data = vec![7u8; MAX_STORED_BLOCK + 10];
+        let zlib = zlib_stored(&data);
+        assert_eq!(zlib.len(), 2 + 5 + MAX_STORED_BLOCK + 5 + 10 + 4);
+        assert_eq!(zlib[2], 0);
+        assert_eq!(zlib[2 + 5 + MAX_STORED_BLOCK], 1);
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let pixels: Vec<u32> = (0..35u32)
+            .map(|i| 0xFF000000 | (i * 7) << 16 | (255 - i) << 8 | (i * i % 256))
+            .collect();
+        let png = encode_png(7, 5, &pixels);
+        assert!(is_png(&png));
+        assert_eq!(decode_png(&png), Ok((7, 5, pixels)));
+    }
+
+    #[test]
+    fn test_decode_palette() {
+        // 3x2 at 2 bits per index, rows filtered Sub and Up, index 0 half
+        // transparent through tRNS
+        let png = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x02, 0x03, 0x00, 0x00,
+            0x00, 0xe0, 0x1a, 0x8e, 0x89, 0x00, 0x00, 0x00, 0x0c, 0x50, 0x4c, 0x54, 0x45, 0xff,
+            0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xfb, 0x00, 0x60,
+            0xf6, 0x00, 0x00, 0x00, 0x01, 0x74, 0x52, 0x4e, 0x53, 0x80, 0xad, 0x5e, 0x5b, 0x46,
+            0x00, 0x00, 0x00, 0x0c, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x94, 0x60, 0xca,
+            0x01, 0x00, 0x00, 0xc0, 0x00, 0x88, 0x29, 0xb5, 0x23, 0xf2, 0x00, 0x00, 0x00, 0x00,
+            0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+        let (red, green, blue) = (0x80FF0000, 0xFF00FF00, 0xFF0000FF);
+        assert_eq!(
+            decode_png(&png),
+            Ok((3, 2, vec![red, green, blue, blue, red, green]))
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects() {
+        assert!(decode_png(b"GIF89a").is_err());
+        let png = encode_png(4, 4, &[0xFF000000; 16]);
+        // cut off inside IDAT
+        assert!(decode_png(&png[..png.len() - 20]).is_err());
+
+        // each side is allowed but the area is not
+        let mut ihdr = [0u8; 13];
+        ihdr[..8].copy_from_slice(&[0, 0, 0x40, 0, 0, 0, 0x40, 0]);
+        ihdr[8..10].copy_from_slice(&[8, COLOR_RGB]);
+        assert!(Header::parse(&ihdr).is_err());
+        ihdr[2] = 0x10;
+        assert!(Header::parse(&ihdr).is_ok());
+    }
+
+    #[test]
+    fn test_inflate() {
+        // fixed Huffman codes with back references
+        let fixed = [
+            0x78, 0xda, 0x4b, 0x4c, 0x2a, 0x4a, 0x4c, 0x4e, 0x4c, 0x49, 0x04, 0x52, 0x0a, 0x89,
+            0x14, 0xb0, 0xb3, 0x4b, 0x92, 0x01, 0x2a, 0xed, 0x1b, 0xfb,
+        ];
+        let mut expected = b"abracadabra ".repeat(6);
+        expected.extend_from_slice(b"ktc");
+        assert_eq!(zlib_inflate(&fixed, expected.len()), Ok(expected.clone()));
+        assert!(zlib_inflate(&fixed, expected.len() - 1).is_err());
+
+        // dynamic Huffman codes
+        let dynamic = [
+            0x78, 0xda, 0x25, 0x8c, 0x5b, 0x02, 0x40, 0x20, 0x10, 0x45, 0xb7, 0x72, 0x17, 0x60,
+            0x53, 0x61, 0x28, 0x46, 0x43, 0x0f, 0x19, 0xab, 0x57, 0xf9, 0x3c, 0xe7, 0x3e, 0x92,
+            0x25, 0x5c, 0xd9, 0x4d, 0x3b, 0xc6, 0x20, 0xc5, 0x63, 0x91, 0x07, 0x5b, 0x3e, 0xce,
+            0x08, 0xb9, 0x29, 0x20, 0xd5, 0x98, 0xcd, 0xab, 0x98, 0x65, 0x1d, 0x1a, 0xf9, 0xae,
+            0x2a, 0x21, 0x32, 0x51, 0xad, 0x15, 0xeb, 0x98, 0xba, 0x6c, 0xd3, 0x90, 0x7d, 0x84,
+            0x29, 0x46, 0xff, 0x53, 0xd6, 0x0f, 0xa5, 0xcd, 0x23, 0x3f,
+        ];
+        let text = b"the quick brown fox jumps over the lazy dog, then the dog sleeps while the fox runs away quickly";
+        assert_eq!(zlib_inflate(&dynamic, text.len()), Ok(text.to_vec()));
+
+        // stored blocks, as written by encode_png
+        let data: Vec<u8> = (0..70000u32).map(|i| (i * 31 % 251) as u8).collect();
+        assert_eq!(zlib_inflate(&zlib_stored(&data), data.len()), Ok(data));
+
+        let mut corrupt = fixed.to_vec();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(zlib_inflate(&corrupt, expected.len()).is_err());
+    }
+}
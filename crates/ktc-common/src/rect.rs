@@ -0,0 +1,100 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rectangle {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rectangle {
+    pub fn union(&self, other: &Rectangle) -> Rectangle {
+        if self.width == 0 || self.height == 0 {
+            return *other;
+        }
+        if other.width == 0 || other.height == 0 {
+            return *self;
+        }
+        let x1 = self.x.min(other.x);
+        let y1 = self.y.min(other.y);
+        let x2 = (self.x + self.width).max(other.x + other.width);
+        let y2 = (self.y + self.height).max(other.y + other.height);
+        Rectangle {
+            x: x1,
+            y: y1,
+            width: x2 - x1,
+            height: y2 - y1,
+        }
+    }
+
+    pub fn intersection(&self, other: &Rectangle) -> Option<Rectangle> {
+        let x1 = self.x.max(other.x);
+        let y1 = self.y.max(other.y);
+        let x2 = (self.x + self.width).min(other.x + other.width);
+        let y2 = (self.y + self.height).min(other.y + other.height);
+        if x2 <= x1 || y2 <= y1 {
+            return None;
+        }
+        Some(Rectangle {
+            x: x1,
+            y: y1,
+            width: x2 - x1,
+            height: y2 - y1,
+        })
+    }
+
+    pub fn intersects(&self, other: &Rectangle) -> bool {
+        self.x < other.x + other.width
+            && self.x + self.width > other.x
+            && self.y < other.y + other.height
+            && self.y + self.height > other.y
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    // what is left of self outside other, as up to four bands
+    pub fn subtract(&self, other: &Rectangle) -> Vec<Rectangle> {
+        let Some(cut) = self.intersection(other) else {
+            return vec![*self];
+        };
+        let (right, bottom) = (self.x + self.width, self.y + self.height);
+        let (cut_right, cut_bottom) = (cut.x + cut.width, cut.y + cut.height);
+        [
+            Rectangle {
+                x: self.x,
+                y: self.y,
+                width: self.width,
+                height: cut.y - self.y,
+            },
+            Rectangle {
+                x: self.x,
+                y: cut_bottom,
+                width: self.width,
+                height: bottom - cut_bottom,
+            },
+            Rectangle {
+                x: self.x,
+                y: cut.y,
+                width: cut.x - self.x,
+                height: cut.height,
+            },
+            Rectangle {
+                x: cut_right,
+                y: cut.y,
+                width: right - cut_right,
+                height: cut.height,
+            },
+        ]
+        .into_iter()
+        .filter(|r| !r.is_empty())
+        .collect()
+    }
+
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x as f64
+            && x < (self.x + self.width) as f64
+            && y >= self.y as f64
+            && y < (self.y + self.height) as f64
+    }
+}
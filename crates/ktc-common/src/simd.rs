@@ -0,0 +1,143 @@
+// Row kernels the CPU renderer spends most of a full redraw in. SSE2 and NEON
+// are part of the x86_64 and aarch64 baselines, so no runtime detection is
+// needed; each kernel handles whole 4-pixel lanes and leaves the tail to the
+// scalar loop.
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+const ALPHA: u32 = 0xFF000000;
+
+pub const BACKEND: &str = if cfg!(target_arch = "x86_64") {
+    "sse2"
+} else if cfg!(target_arch = "aarch64") {
+    "neon"
+} else {
+    "scalar"
+};
+
+pub fn fill(dst: &mut [u32], color: u32) {
+    let done = fill_lanes(dst, color);
+    dst[done..].fill(color);
+}
+
+// rounding per-channel average of each pixel with color, made opaque
+pub fn blend_half(dst: &mut [u32], color: u32) {
+    let done = blend_half_lanes(dst, color);
+    for pixel in &mut dst[done..] {
+        let (a, b) = (*pixel, color);
+        *pixel = ALPHA | ((a | b) - (((a ^ b) & 0xFEFEFEFE) >> 1));
+    }
+}
+
+// ARGB to XRGB: the canvas is scanned out opaque, whatever alpha clients sent
+pub fn copy_opaque(dst: &mut [u32], src: &[u32]) {
+    let len = dst.len().min(src.len());
+    let (dst, src) = (&mut dst[..len], &src[..len]);
+    let done = copy_opaque_lanes(dst, src);
+    for (d, s) in dst[done..].iter_mut().zip(&src[done..]) {
+        *d = *s | ALPHA;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn fill_lanes(dst: &mut [u32], color: u32) -> usize {
+    let lanes = dst.len() / 4;
+    unsafe {
+        let v = _mm_set1_epi32(color as i32);
+        let ptr = dst.as_mut_ptr() as *mut __m128i;
+        for i in 0..lanes {
+            _mm_storeu_si128(ptr.add(i), v);
+        }
+    }
+    lanes * 4
+}
+
+#[cfg(target_arch = "x86_64")]
+fn blend_half_lanes(dst: &mut [u32], color: u32) -> usize {
+    let lanes = dst.len() / 4;
+    unsafe {
+        let c = _mm_set1_epi32(color as i32);
+        let alpha = _mm_set1_epi32(ALPHA as i32);
+        let ptr = dst.as_mut_ptr() as *mut __m128i;
+        for i in 0..lanes {
+            let d = _mm_loadu_si128(ptr.add(i));
+            _mm_storeu_si128(ptr.add(i), _mm_or_si128(_mm_avg_epu8(d, c), alpha));
+        }
+    }
+    lanes * 4
+}
+
+#[cfg(target_arch = "x86_64")]
+fn copy_opaque_lanes(dst: &mut [u32], src: &[u32]) -> usize {
+    let lanes = dst.len() / 4;
+    unsafe {
+        let alpha = _mm_set1_epi32(ALPHA as i32);
+        let src_ptr = src.as_ptr() as *const __m128i;
+        let dst_ptr = dst.as_mut_ptr() as *mut __m128i;
+        for i in 0..lanes {
+            let s = _mm_loadu_si128(src_ptr.add(i));
+            _mm_storeu_si128(dst_ptr.add(i), _mm_or_si128(s, alpha));
+        }
+    }
+    lanes * 4
+}
+
+#[cfg(target_arch = "aarch64")]
+fn fill_lanes(dst: &mut [u32], color: u32) -> usize {
+    let lanes = dst.len() / 4;
+    unsafe {
+        let v = vdupq_n_u32(color);
+        let ptr = dst.as_mut_ptr();
+        for i in 0..lanes {
+            vst1q_u32(ptr.add(i * 4), v);
+        }
+    }
+    lanes * 4
+}
+
+#[cfg(target_arch = "aarch64")]
+fn blend_half_lanes(dst: &mut [u32], color: u32) -> usize {
+    let lanes = dst.len() / 4;
+    unsafe {
+        let c = vreinterpretq_u8_u32(vdupq_n_u32(color));
+        let alpha = vdupq_n_u32(ALPHA);
+        let ptr = dst.as_mut_ptr();
+        for i in 0..lanes {
+            let d = vreinterpretq_u8_u32(vld1q_u32(ptr.add(i * 4)));
+            let avg = vreinterpretq_u32_u8(vrhaddq_u8(d, c));
+            vst1q_u32(ptr.add(i * 4), vorrq_u32(avg, alpha));
+        }
+    }
+    lanes * 4
+}
+
+#[cfg(target_arch = "aarch64")]
+fn copy_opaque_lanes(dst: &mut [u32], src: &[u32]) -> usize {
+    let lanes = dst.len() / 4;
+    unsafe {
+        let alpha = vdupq_n_u32(ALPHA);
+        for i in 0..lanes {
+            let s = vld1q_u32(src.as_ptr().add(i * 4));
+            vst1q_u32(dst.as_mut_ptr().add(i * 4), vorrq_u32(s, alpha));
+        }
+    }
+    lanes * 4
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn fill_lanes(_dst: &mut [u32], _color: u32) -> usize {
+    0
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn blend_half_lanes(_dst: &mut [u32], _color: u32) -> usize {
+    0
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn copy_opaque_lanes(_dst: &mut [u32], _src: &[u32]) -> usize {
+    0
+}
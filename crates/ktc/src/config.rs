@@ -1,3 +1,4 @@
+use crate::state::{LayoutMemory, OutputTransform, Rectangle, TitleButton};
 use ktc_common::{ktc_config_dir, parse_color};
 use serde::Deserialize;
 use std::path::PathBuf;
@@ -5,12 +6,44 @@ use std::path::PathBuf;
 fn default_title_bar_height() -> i32 {
     24
 }
+fn default_decorations_enabled() -> bool {
+    true
+}
 fn default_border_width() -> i32 {
     1
 }
 fn default_gap() -> i32 {
     0
 }
+fn default_layout() -> String {
+    "tile".to_string()
+}
+fn default_insert_position() -> String {
+    "end".to_string()
+}
+fn default_min_slot_width() -> i32 {
+    200
+}
+fn default_min_slot_height() -> i32 {
+    120
+}
+fn default_overflow() -> String {
+    "scroll".to_string()
+}
+fn default_swallow() -> bool {
+    false
+}
+
+fn default_inactive_opacity() -> f64 {
+    1.0
+}
+
+fn default_unresponsive_timeout() -> u64 {
+    5
+}
+fn default_unresponsive_tint() -> String {
+    "#000000".to_string()
+}
 
 fn default_background_dark() -> String {
     "#1A1A2E".to_string()
@@ -30,6 +63,18 @@ fn default_border_focused() -> String {
 fn default_border_unfocused() -> String {
     "#505050".to_string()
 }
+fn default_title_text_focused() -> String {
+    "#FFFFFF".to_string()
+}
+fn default_title_text_unfocused() -> String {
+    "#A0A0A0".to_string()
+}
+fn default_close_button() -> String {
+    "#E06C75".to_string()
+}
+fn default_maximize_button() -> String {
+    "#98C379".to_string()
+}
 
 fn default_keyboard_layout() -> String {
     "us".to_string()
@@ -40,6 +85,9 @@ fn default_keyboard_model() -> String {
 fn default_keyboard_options() -> String {
     String::new()
 }
+fn default_layout_memory() -> String {
+    "global".to_string()
+}
 fn default_repeat_rate() -> i32 {
     25
 }
@@ -53,6 +101,34 @@ fn default_cursor_theme() -> String {
 fn default_cursor_size() -> i32 {
     24
 }
+fn default_launch_timeout() -> u64 {
+    5
+}
+fn default_cursor_hide_after() -> u64 {
+    0
+}
+
+fn default_touchpad_tap() -> bool {
+    true
+}
+fn default_touchpad_tap_drag() -> bool {
+    true
+}
+fn default_touchpad_disable_while_typing() -> bool {
+    true
+}
+fn default_touchpad_scroll_method() -> String {
+    "two_finger".to_string()
+}
+fn default_pointer_accel_profile() -> String {
+    "adaptive".to_string()
+}
+fn default_scroll_factor() -> f64 {
+    1.0
+}
+fn default_tablet_pressure_curve() -> [f64; 4] {
+    [0.0, 0.0, 1.0, 1.0]
+}
 
 fn default_drm_device() -> String {
     "auto".to_string()
@@ -71,13 +147,101 @@ fn default_gpu() -> bool {
 }
 
 fn default_renderer() -> String {
-    "opengl".to_string()
+    "auto".to_string()
+}
+fn default_transform() -> String {
+    "normal".to_string()
+}
+fn default_scale() -> f64 {
+    1.0
+}
+fn default_scanout_format() -> String {
+    "xrgb8888".to_string()
+}
+fn default_render_ahead_ms() -> f64 {
+    0.0
+}
+
+fn default_night_temperature() -> u32 {
+    4000
+}
+fn default_night_brightness() -> f64 {
+    1.0
+}
+fn default_night_start() -> String {
+    "20:00".to_string()
+}
+fn default_night_end() -> String {
+    "07:00".to_string()
+}
+fn default_screenshot_directory() -> String {
+    "~/Pictures".to_string()
+}
+
+fn default_recording_encoder() -> String {
+    "ffmpeg".to_string()
+}
+fn default_recording_directory() -> String {
+    "~/Videos".to_string()
+}
+fn default_recording_framerate() -> u32 {
+    30
+}
+fn default_recording_ffmpeg_args() -> Vec<String> {
+    [
+        "-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_wallpaper_mode() -> String {
+    "fill".to_string()
+}
+
+fn default_metrics_listen() -> String {
+    "127.0.0.1:9750".to_string()
+}
+
+fn default_blur_passes() -> u32 {
+    2
+}
+fn default_blur_offset() -> f64 {
+    2.0
+}
+fn default_ipc_trust_same_user() -> bool {
+    true
+}
+fn default_ipc_allowed_commands() -> Vec<String> {
+    [
+        "get_state",
+        "get_hidden_windows",
+        "get_tree",
+        "get_renderer_stats",
+        "get_tablets",
+    ]
+    .map(String::from)
+    .to_vec()
 }
 
 fn default_mod_key() -> String {
     "alt".to_string()
 }
 
+fn default_mouse_bindings() -> Vec<MouseBindEntry> {
+    [
+        ("mod+left", "move_drag"),
+        ("mod+right", "resize_drag"),
+        ("mod+scroll_up", "workspace prev"),
+        ("mod+scroll_down", "workspace next"),
+    ]
+    .map(|(button, action)| MouseBindEntry {
+        button: button.to_string(),
+        action: action.to_string(),
+    })
+    .to_vec()
+}
+
 fn default_bindings() -> Vec<KeybindEntry> {
     vec![
         KeybindEntry {
@@ -116,6 +280,14 @@ fn default_bindings() -> Vec<KeybindEntry> {
             key: "mod+shift+k".to_string(),
             action: "move prev".to_string(),
         },
+        KeybindEntry {
+            key: "mod+shift+h".to_string(),
+            action: "swap left".to_string(),
+        },
+        KeybindEntry {
+            key: "mod+shift+l".to_string(),
+            action: "swap right".to_string(),
+        },
         KeybindEntry {
             key: "mod+shift+q".to_string(),
             action: "close".to_string(),
@@ -124,10 +296,18 @@ fn default_bindings() -> Vec<KeybindEntry> {
             key: "mod+f".to_string(),
             action: "fullscreen".to_string(),
         },
+        KeybindEntry {
+            key: "mod+m".to_string(),
+            action: "maximize".to_string(),
+        },
         KeybindEntry {
             key: "mod+shift+space".to_string(),
             action: "floating toggle".to_string(),
         },
+        KeybindEntry {
+            key: "mod+space".to_string(),
+            action: "layout next".to_string(),
+        },
         KeybindEntry {
             key: "mod+1".to_string(),
             action: "workspace 1".to_string(),
@@ -200,10 +380,50 @@ fn default_bindings() -> Vec<KeybindEntry> {
             key: "mod+shift+9".to_string(),
             action: "move_to_workspace 9".to_string(),
         },
+        KeybindEntry {
+            key: "mod+shift+minus".to_string(),
+            action: "move_to_scratchpad".to_string(),
+        },
+        KeybindEntry {
+            key: "mod+s".to_string(),
+            action: "scratchpad".to_string(),
+        },
+        KeybindEntry {
+            key: "mod+n".to_string(),
+            action: "minimize".to_string(),
+        },
+        KeybindEntry {
+            key: "mod+shift+n".to_string(),
+            action: "restore".to_string(),
+        },
+        KeybindEntry {
+            key: "mod+i".to_string(),
+            action: "insert_marker".to_string(),
+        },
+        KeybindEntry {
+            key: "mod+b".to_string(),
+            action: "toggle_bar".to_string(),
+        },
+        KeybindEntry {
+            key: "print".to_string(),
+            action: "screenshot".to_string(),
+        },
+        KeybindEntry {
+            key: "mod+print".to_string(),
+            action: "screenshot window".to_string(),
+        },
+        KeybindEntry {
+            key: "shift+print".to_string(),
+            action: "screenshot region".to_string(),
+        },
         KeybindEntry {
             key: "mod+shift+c".to_string(),
             action: "reload".to_string(),
         },
+        KeybindEntry {
+            key: "mod+escape".to_string(),
+            action: "passthrough".to_string(),
+        },
     ]
 }
 
@@ -255,6 +475,125 @@ impl ResizeDirection {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RendererBackend {
+    Auto,
+    Gl,
+    Cpu,
+}
+
+impl RendererBackend {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "auto" | "" => Some(RendererBackend::Auto),
+            "gl" | "opengl" | "gles" => Some(RendererBackend::Gl),
+            "cpu" | "software" => Some(RendererBackend::Cpu),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordingEncoder {
+    // uncompressed YUV4MPEG2, written straight to the file or FIFO
+    Y4m,
+    // raw frames piped into an ffmpeg child process
+    Ffmpeg,
+}
+
+impl RecordingEncoder {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "y4m" | "raw" => Some(RecordingEncoder::Y4m),
+            "ffmpeg" => Some(RecordingEncoder::Ffmpeg),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingEncoder::Y4m => "y4m",
+            RecordingEncoder::Ffmpeg => "mp4",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointerAccel {
+    // faster movement moves the pointer further
+    Adaptive,
+    // a constant factor set by speed
+    Flat,
+}
+
+impl PointerAccel {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "adaptive" => Some(PointerAccel::Adaptive),
+            "flat" | "none" => Some(PointerAccel::Flat),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TouchpadScroll {
+    TwoFinger,
+    // along the right and bottom edges
+    Edge,
+    None,
+}
+
+impl TouchpadScroll {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "two_finger" | "twofinger" | "2fg" => Some(TouchpadScroll::TwoFinger),
+            "edge" => Some(TouchpadScroll::Edge),
+            "none" | "off" => Some(TouchpadScroll::None),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TouchpadClick {
+    // the bottom of the pad is split into left/middle/right areas
+    ButtonAreas,
+    // the number of fingers down picks the button
+    Clickfinger,
+}
+
+impl TouchpadClick {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "button_areas" | "areas" => Some(TouchpadClick::ButtonAreas),
+            "clickfinger" | "fingers" => Some(TouchpadClick::Clickfinger),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WallpaperMode {
+    // scaled to cover the output, cropping what hangs over
+    Fill,
+    // scaled to fit inside the output, the rest in background_dark
+    Fit,
+    // repeated at its own size from the top left corner
+    Tile,
+}
+
+impl WallpaperMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "fill" | "cover" => Some(WallpaperMode::Fill),
+            "fit" | "contain" => Some(WallpaperMode::Fit),
+            "tile" => Some(WallpaperMode::Tile),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ToggleState {
     On,
@@ -273,6 +612,71 @@ impl ToggleState {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScreenshotTarget {
+    Output,
+    Window,
+    Region,
+}
+
+impl ScreenshotTarget {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "" | "output" | "screen" | "full" => Some(ScreenshotTarget::Output),
+            "window" | "focused" => Some(ScreenshotTarget::Window),
+            "region" | "area" | "select" => Some(ScreenshotTarget::Region),
+            _ => None,
+        }
+    }
+}
+
+// the gap action on the active workspace: "8" sets it, "+4"/"-4" step it
+// and "default" goes back to appearance.gap
+#[derive(Debug, Clone, PartialEq)]
+pub enum GapChange {
+    Set(i32),
+    Adjust(i32),
+    Default,
+}
+
+impl GapChange {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("default") || s.eq_ignore_ascii_case("reset") {
+            return Some(GapChange::Default);
+        }
+        let amount = s.parse().ok()?;
+        if s.starts_with('+') || s.starts_with('-') {
+            Some(GapChange::Adjust(amount))
+        } else {
+            Some(GapChange::Set(amount))
+        }
+    }
+}
+
+// which of the comma-separated keyboard.layout entries to switch to
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyboardLayoutTarget {
+    Next,
+    Prev,
+    // 0-based xkb group; the action takes it 1-based like workspaces
+    Index(u32),
+    Name(String),
+}
+
+impl KeyboardLayoutTarget {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "" | "next" | "n" | "+1" => Some(KeyboardLayoutTarget::Next),
+            "prev" | "previous" | "p" | "-1" => Some(KeyboardLayoutTarget::Prev),
+            s => match s.parse::<u32>() {
+                Ok(n) => n.checked_sub(1).map(KeyboardLayoutTarget::Index),
+                Err(_) => Some(KeyboardLayoutTarget::Name(s.to_string())),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum WorkspaceTarget {
     Number(usize),
@@ -296,6 +700,15 @@ impl WorkspaceTarget {
     }
 }
 
+// A floating window following the pointer while a [[keybinds.mouse]]
+// button is held: moved along, or resized from the corner nearest to where
+// the drag started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerGrab {
+    Move,
+    Resize,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Action {
     Exit,
@@ -308,6 +721,7 @@ pub enum Action {
     Kill,
 
     Focus(Direction),
+    FocusIndex(usize),
     Move(Direction),
     Swap(Direction),
 
@@ -324,6 +738,20 @@ pub enum Action {
     MoveToWorkspace(WorkspaceTarget),
     MoveToWorkspaceSilent(WorkspaceTarget),
 
+    MoveToScratchpad,
+    Scratchpad,
+
+    Minimize,
+    Restore,
+
+    Raise,
+    Lower,
+
+    InsertMarker,
+
+    Mark(String),
+    JumpToMark(String),
+
     SplitHorizontal,
     SplitVertical,
     SplitToggle,
@@ -331,11 +759,37 @@ pub enum Action {
     LayoutNext,
     LayoutPrev,
     LayoutSet(String),
+    Gap(GapChange),
 
     CursorTheme(String),
+    KeyboardLayout(KeyboardLayoutTarget),
+
+    Bar(ToggleState),
+    Opacity(f64),
+    Screenshot(ScreenshotTarget),
+
+    // only on [[keybinds.mouse]] buttons
+    Drag(PointerGrab),
+    // a [[keybinds.mode]] by name, "default" going back to the usual bindings
+    Mode(String),
+    // every other key and mouse binding off, for nested compositors and VMs
+    Passthrough(ToggleState),
 }
 
 impl Action {
+    // Bindings that make sense to repeat while their key is held: stepping
+    // focus, windows or workspaces along, and resizing
+    pub fn repeats(&self) -> bool {
+        matches!(
+            self,
+            Action::Focus(_)
+                | Action::Move(_)
+                | Action::Swap(_)
+                | Action::Resize { .. }
+                | Action::Workspace(WorkspaceTarget::Next | WorkspaceTarget::Prev)
+        )
+    }
+
     pub fn parse(s: &str) -> Option<Self> {
         let s = s.trim();
         let (cmd, args) = match s.find(' ') {
@@ -368,6 +822,8 @@ impl Action {
             "focus" | "focus_window" => {
                 if args.is_empty() {
                     Some(Action::Focus(Direction::Next))
+                } else if let Ok(index) = args.parse::<usize>() {
+                    (index > 0).then_some(Action::FocusIndex(index))
                 } else {
                     Direction::parse(args).map(Action::Focus)
                 }
@@ -407,6 +863,18 @@ impl Action {
                 }
             }
 
+            "bar" | "toggle_bar" | "togglebar" => {
+                if args.is_empty() {
+                    Some(Action::Bar(ToggleState::Toggle))
+                } else {
+                    ToggleState::parse(args).map(Action::Bar)
+                }
+            }
+
+            "opacity" => args.parse().ok().map(Action::Opacity),
+
+            "screenshot" => ScreenshotTarget::parse(args).map(Action::Screenshot),
+
             "maximize" | "togglemaximize" => {
                 if args.is_empty() {
                     Some(Action::Maximize(ToggleState::Toggle))
@@ -443,7 +911,10 @@ impl Action {
                 }
             }
 
-            "move_to_workspace" | "movetoworkspace" => {
+            "move_to_workspace"
+            | "movetoworkspace"
+            | "move_to_workspace_follow"
+            | "movetoworkspacefollow" => {
                 if args.is_empty() {
                     None
                 } else {
@@ -459,6 +930,32 @@ impl Action {
                 }
             }
 
+            "move_to_scratchpad" | "movetoscratchpad" => Some(Action::MoveToScratchpad),
+            "scratchpad" | "scratchpad_show" | "togglescratchpad" => Some(Action::Scratchpad),
+
+            "minimize" | "hide" => Some(Action::Minimize),
+            "restore" | "unminimize" | "unhide" => Some(Action::Restore),
+
+            "raise" => Some(Action::Raise),
+            "lower" => Some(Action::Lower),
+
+            "insert_marker" | "mark_insert" => Some(Action::InsertMarker),
+
+            "mark" => {
+                if args.is_empty() {
+                    None
+                } else {
+                    Some(Action::Mark(args.to_string()))
+                }
+            }
+            "jump_to_mark" | "goto_mark" => {
+                if args.is_empty() {
+                    None
+                } else {
+                    Some(Action::JumpToMark(args.to_string()))
+                }
+            }
+
             "split_horizontal" | "splith" => Some(Action::SplitHorizontal),
             "split_vertical" | "splitv" => Some(Action::SplitVertical),
             "split_toggle" | "splitt" => Some(Action::SplitToggle),
@@ -472,6 +969,7 @@ impl Action {
                     Some(Action::LayoutSet(args.to_string()))
                 }
             }
+            "gap" | "gaps" => GapChange::parse(args).map(Action::Gap),
 
             "cursor_theme" | "setcursor" => {
                 if args.is_empty() {
@@ -480,6 +978,27 @@ impl Action {
                     Some(Action::CursorTheme(args.to_string()))
                 }
             }
+            "keyboard_layout" | "xkb_layout" => {
+                KeyboardLayoutTarget::parse(args).map(Action::KeyboardLayout)
+            }
+
+            "move_drag" | "drag_move" => Some(Action::Drag(PointerGrab::Move)),
+            "resize_drag" | "drag_resize" => Some(Action::Drag(PointerGrab::Resize)),
+
+            "mode" | "submap" => {
+                if args.is_empty() {
+                    Some(Action::Mode(DEFAULT_MODE.to_string()))
+                } else {
+                    Some(Action::Mode(args.to_string()))
+                }
+            }
+            "passthrough" | "inhibit_shortcuts" => {
+                if args.is_empty() {
+                    Some(Action::Passthrough(ToggleState::Toggle))
+                } else {
+                    ToggleState::parse(args).map(Action::Passthrough)
+                }
+            }
 
             _ => None,
         }
@@ -492,6 +1011,91 @@ pub struct KeybindEntry {
     pub action: String,
 }
 
+// A set of bindings that replaces the usual ones while it is active, e.g.
+// a "resize" mode where the arrow keys resize until Escape leaves it
+pub const DEFAULT_MODE: &str = "default";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ModeEntry {
+    pub name: String,
+    #[serde(default)]
+    pub bind: Vec<KeybindEntry>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GestureEntry {
+    pub gesture: String,
+    pub action: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MouseBindEntry {
+    pub button: String,
+    pub action: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct WindowRule {
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub workspace: Option<usize>,
+    pub floating: Option<bool>,
+    pub fullscreen: Option<bool>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub decorations: Option<bool>,
+    pub swallow: Option<bool>,
+    pub hide_on_close: Option<String>,
+    pub blur: Option<bool>,
+    pub opacity: Option<f64>,
+}
+
+impl WindowRule {
+    pub fn matches(&self, app_id: &str, title: &str) -> bool {
+        if self.app_id.is_none() && self.title.is_none() {
+            return false;
+        }
+        let app_id_ok = self
+            .app_id
+            .as_deref()
+            .map(|p| glob_match(p, app_id))
+            .unwrap_or(true);
+        let title_ok = self
+            .title
+            .as_deref()
+            .map(|p| glob_match(p, title))
+            .unwrap_or(true);
+        app_id_ok && title_ok
+    }
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 #[derive(Debug, Deserialize, Clone, Default)]
 #[serde(default)]
 pub struct Config {
@@ -499,19 +1103,296 @@ pub struct Config {
     pub display: DisplayConfig,
     pub keyboard: KeyboardConfig,
     pub cursor: CursorConfig,
+    pub input: InputConfig,
     pub keybinds: KeybindsConfig,
     pub debug: DebugConfig,
+    pub decorations: DecorationsConfig,
+    pub night_light: NightLightConfig,
+    pub blur: BlurConfig,
+    pub screenshot: ScreenshotConfig,
+    pub recording: RecordingConfig,
+    pub wallpaper: WallpaperConfig,
+    pub metrics: MetricsConfig,
+    pub ipc: IpcConfig,
+    pub rules: Vec<WindowRule>,
 }
 
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone)]
 #[serde(default)]
-pub struct DebugConfig {
+pub struct IpcConfig {
+    #[serde(default = "default_ipc_trust_same_user")]
+    pub trust_same_user: bool,
     #[serde(default)]
-    pub profiler: bool,
+    pub trusted_binaries: Vec<String>,
+    #[serde(default = "default_ipc_allowed_commands")]
+    pub allowed_commands: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-#[serde(default)]
+impl Default for IpcConfig {
+    fn default() -> Self {
+        Self {
+            trust_same_user: default_ipc_trust_same_user(),
+            trusted_binaries: Vec::new(),
+            allowed_commands: default_ipc_allowed_commands(),
+        }
+    }
+}
+
+impl IpcConfig {
+    // trusted peers may send anything, everyone else only allowed_commands
+    pub fn allows(&self, uid: Option<u32>, exe: Option<&std::path::Path>, command: &str) -> bool {
+        let same_user = uid == Some(unsafe { libc::getuid() });
+        let trusted_binary = exe.is_some_and(|exe| {
+            self.trusted_binaries
+                .iter()
+                .any(|b| std::path::Path::new(b) == exe)
+        });
+        (self.trust_same_user && same_user)
+            || trusted_binary
+            || self
+                .allowed_commands
+                .iter()
+                .any(|c| c == "*" || c == command)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct NightLightConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_night_temperature")]
+    pub temperature: u32,
+    #[serde(default = "default_night_brightness")]
+    pub brightness: f64,
+    #[serde(default = "default_night_start")]
+    pub start: String,
+    #[serde(default = "default_night_end")]
+    pub end: String,
+}
+
+impl Default for NightLightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            temperature: default_night_temperature(),
+            brightness: default_night_brightness(),
+            start: default_night_start(),
+            end: default_night_end(),
+        }
+    }
+}
+
+impl NightLightConfig {
+    // start..end may wrap past midnight; unparsable times keep it off
+    pub fn is_active_at(&self, time: chrono::NaiveTime) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let parse = |s: &str| chrono::NaiveTime::parse_from_str(s.trim(), "%H:%M").ok();
+        let (Some(start), Some(end)) = (parse(&self.start), parse(&self.end)) else {
+            return false;
+        };
+        if start <= end {
+            start <= time && time < end
+        } else {
+            time >= start || time < end
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct BlurConfig {
+    #[serde(default = "default_blur_passes")]
+    pub passes: u32,
+    #[serde(default = "default_blur_offset")]
+    pub offset: f64,
+    #[serde(default)]
+    pub layers: Vec<String>,
+}
+
+impl Default for BlurConfig {
+    fn default() -> Self {
+        Self {
+            passes: default_blur_passes(),
+            offset: default_blur_offset(),
+            layers: Vec::new(),
+        }
+    }
+}
+
+impl BlurConfig {
+    pub fn blurs_layer(&self, namespace: &str) -> bool {
+        self.layers.iter().any(|p| glob_match(p, namespace))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ScreenshotConfig {
+    #[serde(default = "default_screenshot_directory")]
+    pub directory: String,
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> Self {
+        Self {
+            directory: default_screenshot_directory(),
+        }
+    }
+}
+
+impl ScreenshotConfig {
+    pub fn directory(&self) -> std::path::PathBuf {
+        expand_home(&self.directory)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RecordingConfig {
+    #[serde(default = "default_recording_encoder")]
+    pub encoder: String,
+    #[serde(default = "default_recording_directory")]
+    pub directory: String,
+    #[serde(default = "default_recording_framerate")]
+    pub framerate: u32,
+    // passed to ffmpeg between the raw input and the output path
+    #[serde(default = "default_recording_ffmpeg_args")]
+    pub ffmpeg_args: Vec<String>,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            encoder: default_recording_encoder(),
+            directory: default_recording_directory(),
+            framerate: default_recording_framerate(),
+            ffmpeg_args: default_recording_ffmpeg_args(),
+        }
+    }
+}
+
+impl RecordingConfig {
+    pub fn encoder(&self) -> RecordingEncoder {
+        RecordingEncoder::parse(&self.encoder).unwrap_or_else(|| {
+            log::warn!(
+                "[config] Unknown recording encoder {:?}, using ffmpeg",
+                self.encoder
+            );
+            RecordingEncoder::Ffmpeg
+        })
+    }
+
+    pub fn directory(&self) -> std::path::PathBuf {
+        expand_home(&self.directory)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct WallpaperConfig {
+    // PNG or JPEG; empty keeps the plain background
+    #[serde(default)]
+    pub path: String,
+    #[serde(default = "default_wallpaper_mode")]
+    pub mode: String,
+    // keyed by output name, e.g. [wallpaper.outputs.GPU]
+    #[serde(default)]
+    pub outputs: std::collections::HashMap<String, OutputWallpaperConfig>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(default)]
+pub struct OutputWallpaperConfig {
+    pub path: Option<String>,
+    pub mode: Option<String>,
+}
+
+impl Default for WallpaperConfig {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            mode: default_wallpaper_mode(),
+            outputs: std::collections::HashMap::new(),
+        }
+    }
+}
+
+impl WallpaperConfig {
+    // the image and mode for an output, or None for the plain background
+    pub fn for_output(&self, name: &str) -> Option<(PathBuf, WallpaperMode)> {
+        let output = self.outputs.get(name);
+        let path = output.and_then(|o| o.path.as_deref()).unwrap_or(&self.path);
+        if path.is_empty() {
+            return None;
+        }
+        let mode = output.and_then(|o| o.mode.as_deref()).unwrap_or(&self.mode);
+        Some((expand_home(path), wallpaper_mode(mode)))
+    }
+}
+
+pub fn wallpaper_mode(mode: &str) -> WallpaperMode {
+    WallpaperMode::parse(mode).unwrap_or_else(|| {
+        log::warn!("[config] Unknown wallpaper mode {:?}, using fill", mode);
+        WallpaperMode::Fill
+    })
+}
+
+pub fn expand_home(path: &str) -> std::path::PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) => {
+            let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+            std::path::PathBuf::from(home + rest)
+        }
+        None => std::path::PathBuf::from(path),
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // "host:port", or "unix:/path/to/socket"
+    #[serde(default = "default_metrics_listen")]
+    pub listen: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_metrics_listen(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct DecorationsConfig {
+    #[serde(default = "default_decorations_enabled")]
+    pub enabled: bool,
+}
+
+impl Default for DecorationsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_decorations_enabled(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(default)]
+pub struct DebugConfig {
+    #[serde(default)]
+    pub profiler: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
 pub struct DisplayConfig {
     #[serde(default = "default_drm_device")]
     pub device: String,
@@ -523,15 +1404,25 @@ pub struct DisplayConfig {
     pub vsync: bool,
 
     #[serde(default = "default_vrr")]
-    #[allow(dead_code)]
     pub vrr: bool,
 
     #[serde(default = "default_gpu")]
     pub gpu: bool,
 
     #[serde(default = "default_renderer")]
-    #[allow(dead_code)]
     pub renderer: String,
+
+    #[serde(default = "default_transform")]
+    pub transform: String,
+
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+
+    #[serde(default = "default_scanout_format")]
+    pub format: String,
+
+    #[serde(default = "default_render_ahead_ms")]
+    pub render_ahead_ms: f64,
 }
 
 impl Default for DisplayConfig {
@@ -543,11 +1434,63 @@ impl Default for DisplayConfig {
             vrr: default_vrr(),
             gpu: default_gpu(),
             renderer: default_renderer(),
+            transform: default_transform(),
+            scale: default_scale(),
+            format: default_scanout_format(),
+            render_ahead_ms: default_render_ahead_ms(),
         }
     }
 }
 
 impl DisplayConfig {
+    pub fn output_scale(&self) -> f64 {
+        if self.scale.is_finite() {
+            self.scale.clamp(0.5, 4.0)
+        } else {
+            1.0
+        }
+    }
+
+    pub fn output_transform(&self) -> OutputTransform {
+        OutputTransform::parse(&self.transform).unwrap_or_else(|| {
+            log::warn!(
+                "[config] Unknown display transform {:?}, using normal",
+                self.transform
+            );
+            OutputTransform::Normal
+        })
+    }
+
+    // zero (or anything unusable) draws as soon as the previous flip lands
+    pub fn render_ahead(&self) -> std::time::Duration {
+        if self.render_ahead_ms.is_finite() && self.render_ahead_ms > 0.0 {
+            std::time::Duration::from_secs_f64(self.render_ahead_ms.min(1000.0) / 1000.0)
+        } else {
+            std::time::Duration::ZERO
+        }
+    }
+
+    pub fn renderer_backend(&self) -> RendererBackend {
+        RendererBackend::parse(&self.renderer).unwrap_or_else(|| {
+            log::warn!("[config] Unknown renderer {:?}, using auto", self.renderer);
+            RendererBackend::Auto
+        })
+    }
+
+    pub fn scanout_format(&self) -> drm_fourcc::DrmFourcc {
+        match self.format.to_lowercase().as_str() {
+            "xrgb8888" | "8" => drm_fourcc::DrmFourcc::Xrgb8888,
+            "xrgb2101010" | "10" => drm_fourcc::DrmFourcc::Xrgb2101010,
+            _ => {
+                log::warn!(
+                    "[config] Unknown scanout format {:?}, using xrgb8888",
+                    self.format
+                );
+                drm_fourcc::DrmFourcc::Xrgb8888
+            }
+        }
+    }
+
     pub fn drm_device_path(&self) -> Option<String> {
         match self.device.as_str() {
             "auto" | "" => None,
@@ -587,7 +1530,6 @@ pub struct AppearanceConfig {
     #[allow(dead_code)]
     pub border_width: i32,
     #[serde(default = "default_gap")]
-    #[allow(dead_code)]
     pub gap: i32,
     #[serde(default = "default_background_dark")]
     pub background_dark: String,
@@ -601,8 +1543,37 @@ pub struct AppearanceConfig {
     pub border_focused: String,
     #[serde(default = "default_border_unfocused")]
     pub border_unfocused: String,
+    #[serde(default = "default_title_text_focused")]
+    pub title_text_focused: String,
+    #[serde(default = "default_title_text_unfocused")]
+    pub title_text_unfocused: String,
+    #[serde(default = "default_close_button")]
+    pub close_button: String,
+    #[serde(default = "default_maximize_button")]
+    pub maximize_button: String,
+    #[serde(default = "default_layout")]
+    pub layout: String,
+    #[serde(default = "default_insert_position")]
+    pub insert_position: String,
+    #[serde(default = "default_min_slot_width")]
+    pub min_slot_width: i32,
+    #[serde(default = "default_min_slot_height")]
+    pub min_slot_height: i32,
+    #[serde(default = "default_overflow")]
+    pub overflow: String,
+    #[serde(default = "default_swallow")]
+    pub swallow: bool,
+    #[serde(default = "default_inactive_opacity")]
+    pub inactive_opacity: f64,
+    #[serde(default = "default_unresponsive_timeout")]
+    pub unresponsive_timeout: u64,
+    #[serde(default = "default_unresponsive_tint")]
+    pub unresponsive_tint: String,
 }
 
+// keys per second; past this a held key would outrun frames anyway
+const MAX_REPEAT_RATE: i32 = 1000;
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(default)]
 pub struct KeyboardConfig {
@@ -612,22 +1583,407 @@ pub struct KeyboardConfig {
     pub model: String,
     #[serde(default = "default_keyboard_options")]
     pub options: String,
+    #[serde(default = "default_layout_memory")]
+    pub layout_memory: String,
+    // keys per second, 0 turns repeat off
     #[serde(default = "default_repeat_rate")]
-    #[allow(dead_code)]
     pub repeat_rate: i32,
+    // milliseconds a key is held before it repeats
     #[serde(default = "default_repeat_delay")]
-    #[allow(dead_code)]
     pub repeat_delay: i32,
+    // NumLock turned on when ktc starts
+    #[serde(default)]
+    pub numlock: bool,
+}
+
+impl KeyboardConfig {
+    // one entry per xkb layout group, "us,de" -> ["us", "de"]
+    pub fn layout_names(&self) -> Vec<&str> {
+        self.layout.split(',').map(str::trim).collect()
+    }
+
+    pub fn layout_name(&self, group: u32) -> String {
+        match self.layout_names().get(group as usize) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => (group + 1).to_string(),
+        }
+    }
+
+    // 0 when repeat is off; capped so the interval never rounds down to nothing
+    pub fn repeat_rate(&self) -> i32 {
+        self.repeat_rate.clamp(0, MAX_REPEAT_RATE)
+    }
+
+    pub fn layout_memory(&self) -> LayoutMemory {
+        LayoutMemory::parse(&self.layout_memory).unwrap_or_else(|| {
+            log::warn!(
+                "[config] Unknown layout memory {:?}, using global",
+                self.layout_memory
+            );
+            LayoutMemory::Global
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(default)]
-#[allow(dead_code)]
 pub struct CursorConfig {
     #[serde(default = "default_cursor_theme")]
     pub theme: String,
     #[serde(default = "default_cursor_size")]
     pub size: i32,
+    #[serde(default = "default_launch_timeout")]
+    pub launch_timeout: u64,
+    #[serde(default = "default_cursor_hide_after")]
+    pub hide_after: u64,
+    #[serde(default, alias = "hide_when_typing")]
+    pub hide_on_typing: bool,
+}
+
+impl CursorConfig {
+    // XCURSOR_THEME and XCURSOR_SIZE, which the session sets for every
+    // toolkit, win over theme and size
+    pub fn theme_name(&self) -> String {
+        std::env::var("XCURSOR_THEME")
+            .ok()
+            .filter(|theme| !theme.is_empty())
+            .unwrap_or_else(|| self.theme.clone())
+    }
+
+    pub fn base_size(&self) -> i32 {
+        std::env::var("XCURSOR_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .filter(|&size: &i32| size > 0)
+            .unwrap_or(self.size)
+            .max(1)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(default)]
+pub struct InputConfig {
+    pub touchpad: TouchpadConfig,
+    pub pointer: PointerConfig,
+    pub tablet: TabletConfig,
+    pub device: Vec<DeviceConfig>,
+}
+
+impl InputConfig {
+    fn devices_for<'a>(
+        &'a self,
+        id: &'a DeviceId<'a>,
+    ) -> impl Iterator<Item = &'a DeviceConfig> + 'a {
+        self.device.iter().filter(move |d| d.matches(id))
+    }
+
+    // [input.pointer] with every matching [[input.device]] laid over it,
+    // later blocks winning
+    pub fn pointer_for(&self, id: &DeviceId) -> PointerConfig {
+        let mut pointer = self.pointer.clone();
+        for device in self.devices_for(id) {
+            if let Some(ref profile) = device.accel_profile {
+                pointer.accel_profile = profile.clone();
+            }
+            if let Some(speed) = device.speed {
+                pointer.speed = speed;
+            }
+            if let Some(left_handed) = device.left_handed {
+                pointer.left_handed = left_handed;
+            }
+            if let Some(middle_emulation) = device.middle_emulation {
+                pointer.middle_emulation = middle_emulation;
+            }
+            pointer.natural_scroll = device.natural_scroll.unwrap_or(pointer.natural_scroll);
+            pointer.scroll_factor = device.scroll_factor.unwrap_or(pointer.scroll_factor);
+        }
+        pointer
+    }
+
+    // [input.touchpad], overridden the same way
+    pub fn touchpad_for(&self, id: &DeviceId) -> TouchpadConfig {
+        let mut touchpad = self.touchpad.clone();
+        for device in self.devices_for(id) {
+            touchpad.tap = device.tap.unwrap_or(touchpad.tap);
+            touchpad.tap_drag = device.tap_drag.unwrap_or(touchpad.tap_drag);
+            touchpad.drag_lock = device.drag_lock.unwrap_or(touchpad.drag_lock);
+            touchpad.disable_while_typing = device
+                .disable_while_typing
+                .unwrap_or(touchpad.disable_while_typing);
+            if let Some(ref method) = device.scroll_method {
+                touchpad.scroll_method = method.clone();
+            }
+            if let Some(ref method) = device.click_method {
+                touchpad.click_method = method.clone();
+            }
+            touchpad.natural_scroll = device.natural_scroll.unwrap_or(touchpad.natural_scroll);
+            touchpad.scroll_factor = device.scroll_factor.unwrap_or(touchpad.scroll_factor);
+        }
+        touchpad
+    }
+
+    // the [[input.device]] block matching exactly this name and no ids,
+    // added if missing
+    pub fn device_mut(&mut self, name: &str) -> &mut DeviceConfig {
+        let index = match self
+            .device
+            .iter()
+            .position(|d| d.name == name && d.vendor.is_none() && d.product.is_none())
+        {
+            Some(index) => index,
+            None => {
+                self.device.push(DeviceConfig {
+                    name: name.to_string(),
+                    ..DeviceConfig::default()
+                });
+                self.device.len() - 1
+            }
+        };
+        &mut self.device[index]
+    }
+}
+
+// libinput settings for mice, trackpoints and touchpads alike
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct PointerConfig {
+    #[serde(default = "default_pointer_accel_profile")]
+    pub accel_profile: String,
+    // from -1.0 (slowest) to 1.0 (fastest)
+    #[serde(default)]
+    pub speed: f64,
+    #[serde(default)]
+    pub left_handed: bool,
+    // pressing left and right together clicks the middle button
+    #[serde(default)]
+    pub middle_emulation: bool,
+    // wheels and other non-touchpad scrolling; the content follows the
+    // fingers instead of the wheel direction
+    #[serde(default)]
+    pub natural_scroll: bool,
+    // multiplies every scroll step, wheel clicks included
+    #[serde(default = "default_scroll_factor")]
+    pub scroll_factor: f64,
+}
+
+impl Default for PointerConfig {
+    fn default() -> Self {
+        Self {
+            accel_profile: default_pointer_accel_profile(),
+            speed: 0.0,
+            left_handed: false,
+            middle_emulation: false,
+            natural_scroll: false,
+            scroll_factor: default_scroll_factor(),
+        }
+    }
+}
+
+impl PointerConfig {
+    // what a scroll delta from the device is multiplied by
+    pub fn scroll_scale(&self) -> f64 {
+        scroll_scale(self.natural_scroll, self.scroll_factor)
+    }
+
+    pub fn accel_profile(&self) -> PointerAccel {
+        PointerAccel::parse(&self.accel_profile).unwrap_or_else(|| {
+            log::warn!(
+                "[config] Unknown accel profile {:?}, using adaptive",
+                self.accel_profile
+            );
+            PointerAccel::Adaptive
+        })
+    }
+}
+
+// Where tablet tools land and how hard they press, for every tablet
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct TabletConfig {
+    // the output the whole tablet area maps to; empty follows the pointer
+    #[serde(default)]
+    pub output: String,
+    // [x, y, width, height] in layout coordinates, wins over output
+    #[serde(default)]
+    pub region: Option<[i32; 4]>,
+    // turns the tablet around for use with the left hand
+    #[serde(default)]
+    pub left_handed: bool,
+    // control points [x1, y1, x2, y2] of a cubic bezier from (0, 0) to
+    // (1, 1), as in CSS; the default is linear
+    #[serde(default = "default_tablet_pressure_curve")]
+    pub pressure_curve: [f64; 4],
+}
+
+impl Default for TabletConfig {
+    fn default() -> Self {
+        Self {
+            output: String::new(),
+            region: None,
+            left_handed: false,
+            pressure_curve: default_tablet_pressure_curve(),
+        }
+    }
+}
+
+impl TabletConfig {
+    // a region with no area maps nothing
+    pub fn region(&self) -> Option<Rectangle> {
+        let [x, y, width, height] = self.region?;
+        (width > 0 && height > 0).then_some(Rectangle {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    // The curve's y where its x is the raw pressure. Control points outside
+    // 0..1 on x would let the curve turn back on itself, so they're clamped.
+    pub fn apply_pressure_curve(&self, pressure: f64) -> f64 {
+        let [x1, y1, x2, y2] = self.pressure_curve;
+        let (x1, x2) = (x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0));
+        let bezier = |t: f64, p1: f64, p2: f64| {
+            let u = 1.0 - t;
+            3.0 * u * u * t * p1 + 3.0 * u * t * t * p2 + t * t * t
+        };
+        let pressure = pressure.clamp(0.0, 1.0);
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..32 {
+            let mid = (low + high) / 2.0;
+            if bezier(mid, x1, x2) < pressure {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        bezier((low + high) / 2.0, y1, y2).clamp(0.0, 1.0)
+    }
+}
+
+// What [[input.device]] blocks are matched against
+pub struct DeviceId<'a> {
+    pub name: &'a str,
+    pub vendor: u32,
+    pub product: u32,
+}
+
+// [[input.device]]: overrides for the devices matching every identifier the
+// block gives: a glob over the name, and the USB/Bluetooth vendor and product
+// ids libinput reports
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DeviceConfig {
+    pub name: String,
+    pub vendor: Option<u32>,
+    pub product: Option<u32>,
+    pub accel_profile: Option<String>,
+    pub speed: Option<f64>,
+    pub left_handed: Option<bool>,
+    pub middle_emulation: Option<bool>,
+    // apply to the device's wheel or touchpad scrolling, whichever it has
+    pub natural_scroll: Option<bool>,
+    pub scroll_factor: Option<f64>,
+    pub tap: Option<bool>,
+    pub tap_drag: Option<bool>,
+    pub drag_lock: Option<bool>,
+    pub disable_while_typing: Option<bool>,
+    pub scroll_method: Option<String>,
+    pub click_method: Option<String>,
+}
+
+impl DeviceConfig {
+    pub fn matches(&self, id: &DeviceId) -> bool {
+        // a block without any identifier would apply to every device
+        if self.name.is_empty() && self.vendor.is_none() && self.product.is_none() {
+            return false;
+        }
+        (self.name.is_empty() || glob_match(&self.name, id.name))
+            && self.vendor.is_none_or(|vendor| vendor == id.vendor)
+            && self.product.is_none_or(|product| product == id.product)
+    }
+}
+
+fn scroll_scale(natural: bool, factor: f64) -> f64 {
+    let factor = if factor.is_finite() {
+        factor.abs()
+    } else {
+        1.0
+    };
+    if natural {
+        -factor
+    } else {
+        factor
+    }
+}
+
+// libinput settings for devices that report tap-to-click support
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct TouchpadConfig {
+    #[serde(default = "default_touchpad_tap")]
+    pub tap: bool,
+    #[serde(default = "default_touchpad_tap_drag")]
+    pub tap_drag: bool,
+    #[serde(default)]
+    pub drag_lock: bool,
+    #[serde(default = "default_touchpad_disable_while_typing")]
+    pub disable_while_typing: bool,
+    #[serde(default = "default_touchpad_scroll_method")]
+    pub scroll_method: String,
+    // empty keeps the device's default
+    #[serde(default)]
+    pub click_method: String,
+    // as in [input.pointer], for finger scrolling
+    #[serde(default)]
+    pub natural_scroll: bool,
+    #[serde(default = "default_scroll_factor")]
+    pub scroll_factor: f64,
+}
+
+impl Default for TouchpadConfig {
+    fn default() -> Self {
+        Self {
+            tap: default_touchpad_tap(),
+            tap_drag: default_touchpad_tap_drag(),
+            drag_lock: false,
+            disable_while_typing: default_touchpad_disable_while_typing(),
+            scroll_method: default_touchpad_scroll_method(),
+            click_method: String::new(),
+            natural_scroll: false,
+            scroll_factor: default_scroll_factor(),
+        }
+    }
+}
+
+impl TouchpadConfig {
+    pub fn scroll_scale(&self) -> f64 {
+        scroll_scale(self.natural_scroll, self.scroll_factor)
+    }
+
+    pub fn scroll_method(&self) -> TouchpadScroll {
+        TouchpadScroll::parse(&self.scroll_method).unwrap_or_else(|| {
+            log::warn!(
+                "[config] Unknown touchpad scroll method {:?}, using two_finger",
+                self.scroll_method
+            );
+            TouchpadScroll::TwoFinger
+        })
+    }
+
+    pub fn click_method(&self) -> Option<TouchpadClick> {
+        if self.click_method.is_empty() {
+            return None;
+        }
+        let method = TouchpadClick::parse(&self.click_method);
+        if method.is_none() {
+            log::warn!(
+                "[config] Unknown touchpad click method {:?}, keeping the device default",
+                self.click_method
+            );
+        }
+        method
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -638,6 +1994,15 @@ pub struct KeybindsConfig {
 
     #[serde(default = "default_bindings")]
     pub bind: Vec<KeybindEntry>,
+
+    #[serde(default)]
+    pub gesture: Vec<GestureEntry>,
+
+    #[serde(default = "default_mouse_bindings")]
+    pub mouse: Vec<MouseBindEntry>,
+
+    #[serde(default)]
+    pub mode: Vec<ModeEntry>,
 }
 
 impl Default for KeybindsConfig {
@@ -645,6 +2010,63 @@ impl Default for KeybindsConfig {
         Self {
             mod_key: default_mod_key(),
             bind: default_bindings(),
+            gesture: Vec::new(),
+            mouse: default_mouse_bindings(),
+            mode: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+// A touchpad gesture as bound in [[keybinds.gesture]]: "swipe:3:left",
+// "pinch:4:in", "pinch:3:out"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gesture {
+    Swipe {
+        fingers: u32,
+        direction: SwipeDirection,
+    },
+    // inward when the fingers move together
+    Pinch {
+        fingers: u32,
+        inward: bool,
+    },
+}
+
+impl Gesture {
+    pub fn parse(s: &str) -> Option<Self> {
+        let parts: Vec<String> = s.split(':').map(|p| p.trim().to_lowercase()).collect();
+        let [kind, fingers, direction] = parts.as_slice() else {
+            return None;
+        };
+        let fingers: u32 = fingers.parse().ok().filter(|n| (2..=5).contains(n))?;
+        match kind.as_str() {
+            "swipe" => {
+                let direction = match direction.as_str() {
+                    "left" => SwipeDirection::Left,
+                    "right" => SwipeDirection::Right,
+                    "up" => SwipeDirection::Up,
+                    "down" => SwipeDirection::Down,
+                    _ => return None,
+                };
+                Some(Gesture::Swipe { fingers, direction })
+            }
+            "pinch" => {
+                let inward = match direction.as_str() {
+                    "in" => true,
+                    "out" => false,
+                    _ => return None,
+                };
+                Some(Gesture::Pinch { fingers, inward })
+            }
+            _ => None,
         }
     }
 }
@@ -658,43 +2080,96 @@ pub struct Keybind {
     pub keysym: u32,
 }
 
+// What a [[keybinds.mouse]] entry fires on: a button by its evdev code, or
+// a notch of the wheel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PointerTrigger {
+    Button(u32),
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+}
+
+impl PointerTrigger {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s.to_lowercase().as_str() {
+            "left" | "button1" => PointerTrigger::Button(0x110),
+            "right" | "button3" => PointerTrigger::Button(0x111),
+            "middle" | "button2" => PointerTrigger::Button(0x112),
+            "side" | "back" | "button8" => PointerTrigger::Button(0x113),
+            "extra" | "forward" | "button9" => PointerTrigger::Button(0x114),
+            "scroll_up" | "wheel_up" => PointerTrigger::ScrollUp,
+            "scroll_down" | "wheel_down" => PointerTrigger::ScrollDown,
+            "scroll_left" | "wheel_left" => PointerTrigger::ScrollLeft,
+            "scroll_right" | "wheel_right" => PointerTrigger::ScrollRight,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PointerBind {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub super_key: bool,
+    pub trigger: PointerTrigger,
+}
+
 impl KeybindsConfig {
-    pub fn parse_keybind(&self, bind_str: &str) -> Option<Keybind> {
-        let mut ctrl = false;
-        let mut alt = false;
-        let mut shift = false;
-        let mut super_key = false;
-        let mut key_part = "";
+    // The modifiers in a "mod+shift+x" string, with the keysym left at 0,
+    // and the one part that isn't a modifier
+    fn parse_modifiers(&self, bind_str: &str) -> (Keybind, String) {
+        let mut keybind = Keybind {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            super_key: false,
+            keysym: 0,
+        };
+        let mut key_part = String::new();
 
         for part in bind_str.split('+') {
             let part = part.trim().to_lowercase();
             match part.as_str() {
-                "ctrl" | "control" => ctrl = true,
-                "alt" => alt = true,
-                "shift" => shift = true,
-                "super" | "mod4" | "logo" | "win" | "meta" => super_key = true,
+                "ctrl" | "control" => keybind.ctrl = true,
+                "alt" => keybind.alt = true,
+                "shift" => keybind.shift = true,
+                "super" | "mod4" | "logo" | "win" | "meta" => keybind.super_key = true,
                 "mod" => match self.mod_key.to_lowercase().as_str() {
-                    "alt" => alt = true,
-                    "super" | "mod4" | "logo" | "win" | "meta" => super_key = true,
-                    "ctrl" | "control" => ctrl = true,
-                    _ => alt = true,
+                    "alt" => keybind.alt = true,
+                    "super" | "mod4" | "logo" | "win" | "meta" => keybind.super_key = true,
+                    "ctrl" | "control" => keybind.ctrl = true,
+                    _ => keybind.alt = true,
                 },
-                _ => key_part = Box::leak(part.into_boxed_str()),
+                _ => key_part = part,
             }
         }
+        (keybind, key_part)
+    }
 
-        let keysym = keysym_from_name(key_part)?;
+    pub fn parse_keybind(&self, bind_str: &str) -> Option<Keybind> {
+        let (keybind, key_part) = self.parse_modifiers(bind_str);
         Some(Keybind {
-            ctrl,
-            alt,
-            shift,
-            super_key,
-            keysym,
+            keysym: keysym_from_name(&key_part)?,
+            ..keybind
         })
     }
 
-    pub fn get_all_bindings(&self) -> Vec<(Action, Keybind)> {
-        self.bind
+    pub fn parse_pointer_bind(&self, bind_str: &str) -> Option<PointerBind> {
+        let (modifiers, trigger) = self.parse_modifiers(bind_str);
+        Some(PointerBind {
+            ctrl: modifiers.ctrl,
+            alt: modifiers.alt,
+            shift: modifiers.shift,
+            super_key: modifiers.super_key,
+            trigger: PointerTrigger::parse(&trigger)?,
+        })
+    }
+
+    fn parse_bindings(&self, entries: &[KeybindEntry]) -> Vec<(Action, Keybind)> {
+        entries
             .iter()
             .filter_map(|entry| {
                 let keybind = self.parse_keybind(&entry.key)?;
@@ -704,6 +2179,80 @@ impl KeybindsConfig {
             .collect()
     }
 
+    pub fn get_all_bindings(&self) -> Vec<(Action, Keybind)> {
+        self.parse_bindings(&self.bind)
+    }
+
+    // A mode without a way out would leave the keyboard stuck in it, so
+    // Escape goes back to the default mode unless the mode binds it itself
+    pub fn get_mode_bindings(&self) -> Vec<(String, Vec<(Action, Keybind)>)> {
+        use xkbcommon::xkb::keysyms::KEY_Escape;
+
+        self.mode
+            .iter()
+            .filter(|mode| {
+                let reserved = mode.name.eq_ignore_ascii_case(DEFAULT_MODE);
+                if reserved {
+                    log::warn!("[config] The {:?} mode can't be redefined", mode.name);
+                }
+                !reserved
+            })
+            .map(|mode| {
+                let mut bindings = self.parse_bindings(&mode.bind);
+                let escape = Keybind {
+                    ctrl: false,
+                    alt: false,
+                    shift: false,
+                    super_key: false,
+                    keysym: KEY_Escape,
+                };
+                if !bindings.iter().any(|(_, keybind)| *keybind == escape) {
+                    bindings.push((Action::Mode(DEFAULT_MODE.to_string()), escape));
+                }
+                (mode.name.clone(), bindings)
+            })
+            .collect()
+    }
+
+    pub fn get_gesture_bindings(&self) -> Vec<(Action, Gesture)> {
+        self.gesture
+            .iter()
+            .filter_map(|entry| {
+                let gesture = Gesture::parse(&entry.gesture);
+                if gesture.is_none() {
+                    log::warn!("[config] Unknown gesture {:?}", entry.gesture);
+                }
+                Some((Action::parse(&entry.action)?, gesture?))
+            })
+            .collect()
+    }
+
+    // the drag actions only make sense on a button
+    pub fn get_pointer_bindings(&self) -> Vec<(Action, PointerBind)> {
+        self.mouse
+            .iter()
+            .filter_map(|entry| {
+                let bind = self.parse_pointer_bind(&entry.button);
+                if bind.is_none() {
+                    log::warn!("[config] Unknown mouse binding {:?}", entry.button);
+                }
+                let bind = bind?;
+                let action = Action::parse(&entry.action)?;
+                if matches!(action, Action::Drag(_))
+                    && !matches!(bind.trigger, PointerTrigger::Button(_))
+                {
+                    log::warn!(
+                        "[config] {:?} needs a button, not {:?}",
+                        entry.action,
+                        entry.button
+                    );
+                    return None;
+                }
+                Some((action, bind))
+            })
+            .collect()
+    }
+
     #[allow(dead_code)]
     pub fn get_all_bindings_raw(&self) -> Vec<(String, Keybind)> {
         self.bind
@@ -841,6 +2390,19 @@ impl Default for AppearanceConfig {
             title_unfocused: default_title_unfocused(),
             border_focused: default_border_focused(),
             border_unfocused: default_border_unfocused(),
+            title_text_focused: default_title_text_focused(),
+            title_text_unfocused: default_title_text_unfocused(),
+            close_button: default_close_button(),
+            maximize_button: default_maximize_button(),
+            layout: default_layout(),
+            insert_position: default_insert_position(),
+            min_slot_width: default_min_slot_width(),
+            min_slot_height: default_min_slot_height(),
+            overflow: default_overflow(),
+            swallow: default_swallow(),
+            inactive_opacity: default_inactive_opacity(),
+            unresponsive_timeout: default_unresponsive_timeout(),
+            unresponsive_tint: default_unresponsive_tint(),
         }
     }
 }
@@ -851,8 +2413,10 @@ impl Default for KeyboardConfig {
             layout: default_keyboard_layout(),
             model: default_keyboard_model(),
             options: default_keyboard_options(),
+            layout_memory: default_layout_memory(),
             repeat_rate: default_repeat_rate(),
             repeat_delay: default_repeat_delay(),
+            numlock: false,
         }
     }
 }
@@ -862,12 +2426,41 @@ impl Default for CursorConfig {
         Self {
             theme: default_cursor_theme(),
             size: default_cursor_size(),
+            launch_timeout: default_launch_timeout(),
+            hide_after: default_cursor_hide_after(),
+            hide_on_typing: false,
         }
     }
 }
 
+const DEFAULT_CONFIG: &str = include_str!("../example.config.toml");
+
 impl Config {
-    pub fn load() -> Self {
+    // Seeds the user config with the documented example on first start,
+    // returning the path written.
+    pub fn write_default_if_missing() -> Option<PathBuf> {
+        let user_config = ktc_config_dir().join("config.toml");
+        if user_config.exists() || PathBuf::from("/etc/ktc/config.toml").exists() {
+            return None;
+        }
+
+        if let Err(e) = std::fs::create_dir_all(ktc_config_dir()) {
+            log::warn!("Failed to create {}: {}", ktc_config_dir().display(), e);
+            return None;
+        }
+        match std::fs::write(&user_config, DEFAULT_CONFIG) {
+            Ok(()) => {
+                log::info!("Wrote default config to {}", user_config.display());
+                Some(user_config)
+            }
+            Err(e) => {
+                log::warn!("Failed to write {}: {}", user_config.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn load() -> Result<Self, String> {
         let user_config = ktc_config_dir().join("config.toml");
         let system_config = PathBuf::from("/etc/ktc/config.toml");
 
@@ -875,10 +2468,11 @@ impl Config {
             match Self::load_from_path(&user_config) {
                 Ok(config) => {
                     log::info!("Loaded config from {}", user_config.display());
-                    return config;
+                    return Ok(config);
                 }
                 Err(e) => {
                     log::warn!("Failed to load {}: {}", user_config.display(), e);
+                    return Err(format!("{}: {}", user_config.display(), e));
                 }
             }
         }
@@ -887,16 +2481,17 @@ impl Config {
             match Self::load_from_path(&system_config) {
                 Ok(config) => {
                     log::info!("Loaded config from {}", system_config.display());
-                    return config;
+                    return Ok(config);
                 }
                 Err(e) => {
                     log::warn!("Failed to load {}: {}", system_config.display(), e);
+                    return Err(format!("{}: {}", system_config.display(), e));
                 }
             }
         }
 
         log::info!("Using default configuration");
-        Self::default()
+        Ok(Self::default())
     }
 
     fn load_from_path(path: &PathBuf) -> Result<Self, String> {
@@ -905,8 +2500,21 @@ impl Config {
         toml::from_str(&content).map_err(|e| format!("Failed to parse TOML: {}", e))
     }
 
+    // with decorations disabled no space is reserved for title bars anywhere
     pub fn title_bar_height(&self) -> i32 {
-        self.appearance.title_bar_height
+        if self.decorations.enabled {
+            self.appearance.title_bar_height
+        } else {
+            0
+        }
+    }
+
+    pub fn matching_rules<'a>(
+        &'a self,
+        app_id: &'a str,
+        title: &'a str,
+    ) -> impl Iterator<Item = &'a WindowRule> {
+        self.rules.iter().filter(move |r| r.matches(app_id, title))
     }
 
     pub fn background_dark(&self) -> u32 {
@@ -932,4 +2540,25 @@ impl Config {
     pub fn border_unfocused(&self) -> u32 {
         parse_color(&self.appearance.border_unfocused).unwrap_or(0xFF505050)
     }
+
+    pub fn unresponsive_tint(&self) -> u32 {
+        parse_color(&self.appearance.unresponsive_tint).unwrap_or(0xFF000000)
+    }
+
+    pub fn title_text_color(&self, focused: bool) -> u32 {
+        if focused {
+            parse_color(&self.appearance.title_text_focused).unwrap_or(0xFFFFFFFF)
+        } else {
+            parse_color(&self.appearance.title_text_unfocused).unwrap_or(0xFFA0A0A0)
+        }
+    }
+
+    pub fn title_button_color(&self, button: TitleButton) -> u32 {
+        match button {
+            TitleButton::Close => parse_color(&self.appearance.close_button).unwrap_or(0xFFE06C75),
+            TitleButton::Maximize => {
+                parse_color(&self.appearance.maximize_button).unwrap_or(0xFF98C379)
+            }
+        }
+    }
 }
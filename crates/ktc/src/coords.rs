@@ -0,0 +1,244 @@
+// Coordinate spaces and the conversions between them. Everything that takes
+// a position from one part of the compositor to another goes through here:
+//
+// - layout: the logical space all outputs are placed in. Window and layer
+//   surface geometry, the cursor and damage live here.
+// - output-local: layout minus the output's origin, still logical. What
+//   screencopy regions and wl_output sizes are expressed in.
+// - buffer: pixels of whatever gets presented. For the GPU renderer that is
+//   the framebuffer, after output scale and transform; the CPU canvas is
+//   drawn untransformed at scale 1 and rotated on the way out, so for it
+//   buffer and output-local are the same.
+// - surface-local: layout minus the surface's origin, what wl_pointer sends.
+// - panel: a fraction of the physical display as it is mounted, what
+//   touchscreens report. The output transform turns it into output-local.
+use crate::state::{Output, OutputTransform, Rectangle, State, Window};
+
+impl OutputTransform {
+    // Maps rect, inside a w x h logical image, to the area it covers in the
+    // framebuffer. The rect is clipped to the image first.
+    pub fn map_rect(self, rect: Rectangle, w: i32, h: i32) -> Rectangle {
+        let x1 = rect.x.clamp(0, w);
+        let y1 = rect.y.clamp(0, h);
+        let x2 = (rect.x + rect.width).clamp(0, w);
+        let y2 = (rect.y + rect.height).clamp(0, h);
+        if x2 <= x1 || y2 <= y1 {
+            return Rectangle::default();
+        }
+
+        let (w, h) = (w as usize, h as usize);
+        let (ax, ay) = self.map_pixel(x1 as usize, y1 as usize, w, h);
+        let (bx, by) = self.map_pixel(x2 as usize - 1, y2 as usize - 1, w, h);
+        Rectangle {
+            x: ax.min(bx) as i32,
+            y: ay.min(by) as i32,
+            width: ax.abs_diff(bx) as i32 + 1,
+            height: ay.abs_diff(by) as i32 + 1,
+        }
+    }
+
+    // The inverse of map_pixel for fractions of the image: a point on the
+    // panel, 0.0 to 1.0 on both axes, to where it is in the logical image.
+    pub fn unmap_fraction(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            Self::Normal => (x, y),
+            Self::Rotate90 => (y, 1.0 - x),
+            Self::Rotate180 => (1.0 - x, 1.0 - y),
+            Self::Rotate270 => (1.0 - y, x),
+            Self::Flipped => (1.0 - x, y),
+            Self::FlippedRotate90 => (y, x),
+            Self::FlippedRotate180 => (x, 1.0 - y),
+            Self::FlippedRotate270 => (1.0 - y, 1.0 - x),
+        }
+    }
+}
+
+impl Output {
+    pub fn layout_to_local(&self, x: f64, y: f64) -> (f64, f64) {
+        (x - self.x as f64, y - self.y as f64)
+    }
+
+    pub fn local_to_layout(&self, x: f64, y: f64) -> (f64, f64) {
+        (x + self.x as f64, y + self.y as f64)
+    }
+
+    // output-local logical rect to framebuffer pixels: scale, then transform
+    pub fn local_to_buffer(&self, rect: Rectangle) -> Rectangle {
+        let scale = self.scale.max(0.1);
+        let x1 = (rect.x as f64 * scale).round() as i32;
+        let y1 = (rect.y as f64 * scale).round() as i32;
+        let x2 = ((rect.x + rect.width) as f64 * scale).round() as i32;
+        let y2 = ((rect.y + rect.height) as f64 * scale).round() as i32;
+        let scaled = Rectangle {
+            x: x1,
+            y: y1,
+            width: x2 - x1,
+            height: y2 - y1,
+        };
+
+        let (width, height) = if self.transform.swaps_axes() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        self.transform.map_rect(scaled, width, height)
+    }
+}
+
+impl Window {
+    // layout position of the client surface's (0, 0), below any title bar
+    pub fn surface_origin(&self, title_bar_height: i32) -> (i32, i32) {
+        (
+            self.geometry.x,
+            self.geometry.y + self.decoration_height(title_bar_height),
+        )
+    }
+}
+
+// layout is already in logical pixels, which is what surfaces are sized
+// in, so the output scale never applies here
+pub fn surface_local(origin: (i32, i32), x: f64, y: f64) -> (f64, f64) {
+    (x - origin.0 as f64, y - origin.1 as f64)
+}
+
+impl State {
+    // the transform between output-local and buffer pixels; the CPU canvas
+    // is only rotated when it is copied to the framebuffer
+    pub fn buffer_transform(&self) -> OutputTransform {
+        match (&self.gpu_renderer, self.primary_output()) {
+            (Some(_), Some(output)) => output.transform,
+            _ => OutputTransform::Normal,
+        }
+    }
+
+    // only the primary output is rendered, so that is the one buffers belong to
+    pub fn local_to_buffer(&self, rect: Rectangle) -> Rectangle {
+        match (&self.gpu_renderer, self.primary_output()) {
+            (Some(_), Some(output)) => output.local_to_buffer(rect),
+            _ => rect,
+        }
+    }
+
+    pub fn layout_to_buffer(&self, rect: Rectangle) -> Rectangle {
+        let (x, y) = match self.primary_output() {
+            Some(output) => output.layout_to_local(rect.x as f64, rect.y as f64),
+            None => (rect.x as f64, rect.y as f64),
+        };
+        self.local_to_buffer(Rectangle {
+            x: x as i32,
+            y: y as i32,
+            ..rect
+        })
+    }
+
+    fn pointer_output(&self) -> Option<&Output> {
+        self.outputs
+            .iter()
+            .find(|o| o.rect().contains(self.pointer_x, self.pointer_y))
+            .or_else(|| self.primary_output())
+    }
+
+    fn pointer_bounds(&self) -> Rectangle {
+        match self.pointer_output() {
+            Some(output) => output.rect(),
+            None => {
+                let (width, height) = self.screen_size();
+                Rectangle {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                }
+            }
+        }
+    }
+
+    // absolute devices report a fraction of their range, which covers the
+    // output the pointer is on
+    pub fn absolute_to_layout(&self, x: f64, y: f64) -> (f64, f64) {
+        let x = x.clamp(0.0, 1.0);
+        let y = y.clamp(0.0, 1.0);
+        match self.pointer_output() {
+            Some(output) => {
+                let (width, height) = output.logical_size();
+                output.local_to_layout(
+                    x * (width - 1).max(0) as f64,
+                    y * (height - 1).max(0) as f64,
+                )
+            }
+            None => {
+                let (width, height) = self.screen_size();
+                (
+                    x * (width - 1).max(0) as f64,
+                    y * (height - 1).max(0) as f64,
+                )
+            }
+        }
+    }
+
+    // Touchscreens are built into a panel, taken to be the internal one if
+    // there is one. Rotating that output rotates what a touch lands on.
+    pub fn touch_to_layout(&self, x: f64, y: f64) -> (f64, f64) {
+        let output = self
+            .outputs
+            .iter()
+            .find(|o| o.is_internal())
+            .or_else(|| self.primary_output());
+        let Some(output) = output else {
+            return self.absolute_to_layout(x, y);
+        };
+        let (x, y) = output
+            .transform
+            .unmap_fraction(x.clamp(0.0, 1.0), y.clamp(0.0, 1.0));
+        let (width, height) = output.logical_size();
+        output.local_to_layout(
+            x * (width - 1).max(0) as f64,
+            y * (height - 1).max(0) as f64,
+        )
+    }
+
+    // [input.tablet] region, else the whole of its output; None, also while
+    // that output is unplugged, leaves the tablet on the pointer's output
+    pub fn tablet_area(&self) -> Option<Rectangle> {
+        let tablet = &self.config.input.tablet;
+        if let Some(region) = tablet.region() {
+            return Some(region);
+        }
+        if tablet.output.is_empty() {
+            return None;
+        }
+        self.outputs
+            .iter()
+            .find(|o| o.name == tablet.output)
+            .map(|o| o.rect())
+    }
+
+    pub fn tablet_to_layout(&self, x: f64, y: f64) -> (f64, f64) {
+        let Some(area) = self.tablet_area() else {
+            return self.absolute_to_layout(x, y);
+        };
+        (
+            area.x as f64 + x.clamp(0.0, 1.0) * (area.width - 1).max(0) as f64,
+            area.y as f64 + y.clamp(0.0, 1.0) * (area.height - 1).max(0) as f64,
+        )
+    }
+
+    // relative motion may cross onto any output, but not off the layout;
+    // a point outside all of them stays on the output the pointer left
+    pub fn clamp_to_layout(&self, x: f64, y: f64) -> (f64, f64) {
+        if self.outputs.iter().any(|o| o.rect().contains(x, y)) {
+            return (x, y);
+        }
+        let bounds = self.pointer_bounds();
+        (
+            x.clamp(
+                bounds.x as f64,
+                (bounds.x + bounds.width - 1).max(bounds.x) as f64,
+            ),
+            y.clamp(
+                bounds.y as f64,
+                (bounds.y + bounds.height - 1).max(bounds.y) as f64,
+            ),
+        )
+    }
+}
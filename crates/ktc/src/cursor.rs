@@ -0,0 +1,409 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// W = white, B = black outline, . = transparent
+const ARROW: &[&str] = &[
+    "BW",
+    "BWWB",
+    "BWWWB",
+    "BWWWWB",
+    "BWWWWWB",
+    "BWWWWWWB",
+    "BWWWWWWWB",
+    "BWWWWWWWWB",
+    "BWWWWWWWWWB",
+    "BWWWWWWWWWWB",
+    "BWWWWWWBBBBB",
+    "BWWWBWWB",
+    "BWWBBWWWB",
+    "BWB.BWWWB",
+    "BB..BWWWB",
+    "B....BWWWB",
+    ".....BWWWB",
+    "......BWWB",
+    "......BBB",
+];
+
+const ARROW_W: usize = 12;
+const ARROW_H: usize = 19;
+
+// the bitmap is drawn for a nominal 24px cursor
+const BASE_SIZE: i32 = 24;
+
+const WAIT_FRAMES: usize = 8;
+const WAIT_DELAY_MS: u32 = 100;
+
+const XCURSOR_MAGIC: &[u8] = b"Xcur";
+const XCURSOR_IMAGE: u32 = 0xfffd_0002;
+// libXcursor refuses anything larger
+const XCURSOR_MAX_SIZE: u32 = 0x7fff;
+// deep enough for any real theme, and stops inheritance loops
+const MAX_INHERIT_DEPTH: usize = 8;
+
+static NEXT_CURSOR_SERIAL: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorShape {
+    Default,
+    Wait,
+    // corners of a window being resized by a drag
+    ResizeNw,
+    ResizeNe,
+    ResizeSw,
+    ResizeSe,
+}
+
+impl CursorShape {
+    // the CSS name first, then what older themes call it
+    fn names(self) -> &'static [&'static str] {
+        match self {
+            CursorShape::Default => &["default", "left_ptr", "arrow"],
+            CursorShape::Wait => &["progress", "left_ptr_watch", "half-busy"],
+            CursorShape::ResizeNw => &["nw-resize", "top_left_corner", "size_fdiag"],
+            CursorShape::ResizeNe => &["ne-resize", "top_right_corner", "size_bdiag"],
+            CursorShape::ResizeSw => &["sw-resize", "bottom_left_corner", "size_bdiag"],
+            CursorShape::ResizeSe => &["se-resize", "bottom_right_corner", "size_fdiag"],
+        }
+    }
+}
+
+// Premultiplied ARGB, with the hotspot in image pixels
+pub struct CursorImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+    pub delay: u32,
+    pub xhot: usize,
+    pub yhot: usize,
+}
+
+// The cursors directories of an XCursor theme and of everything it
+// inherits, in lookup order. Empty when the theme isn't installed, which
+// leaves every shape to the built-in bitmaps.
+#[derive(Debug)]
+pub struct CursorTheme {
+    pub name: String,
+    // logical pixels, before output scale
+    pub size: i32,
+    dirs: Vec<PathBuf>,
+}
+
+impl CursorTheme {
+    pub fn load(name: &str, size: i32) -> Self {
+        let search_path = search_path();
+        let mut dirs = Vec::new();
+        add_theme(&search_path, name, &mut dirs, 0);
+        if dirs.is_empty() {
+            log::warn!(
+                "[cursor] Theme {:?} not found, using the built-in arrow",
+                name
+            );
+        } else {
+            log::info!("[cursor] Theme {:?} ({} directories)", name, dirs.len());
+        }
+        Self {
+            name: name.to_string(),
+            size: size.max(1),
+            dirs,
+        }
+    }
+
+    // Frames for the shape at size pixels, None when the theme has no file
+    // for any of its names or the file doesn't parse
+    fn frames(&self, shape: CursorShape, size: i32) -> Option<Vec<CursorImage>> {
+        let path = self.dirs.iter().find_map(|dir| {
+            shape
+                .names()
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|path| path.is_file())
+        })?;
+        let data = std::fs::read(&path)
+            .map_err(|e| log::warn!("[cursor] Failed to read {}: {}", path.display(), e))
+            .ok()?;
+        let frames = parse_xcursor(&data, size);
+        if frames.is_none() {
+            log::warn!("[cursor] {} is not an XCursor file", path.display());
+        }
+        frames
+    }
+}
+
+// XCURSOR_PATH, or where libXcursor looks by default
+fn search_path() -> Vec<PathBuf> {
+    if let Ok(path) = std::env::var("XCURSOR_PATH") {
+        return std::env::split_paths(&path).collect();
+    }
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".local/share")));
+    data_home
+        .map(|d| d.join("icons"))
+        .into_iter()
+        .chain(home.map(|h| h.join(".icons")))
+        .chain(["/usr/share/icons", "/usr/share/pixmaps"].map(PathBuf::from))
+        .collect()
+}
+
+fn add_theme(search_path: &[PathBuf], name: &str, dirs: &mut Vec<PathBuf>, depth: usize) {
+    if depth > MAX_INHERIT_DEPTH {
+        return;
+    }
+    let mut inherits = None;
+    for base in search_path {
+        let theme = base.join(name);
+        let cursors = theme.join("cursors");
+        if cursors.is_dir() && !dirs.contains(&cursors) {
+            dirs.push(cursors);
+        }
+        if inherits.is_none() {
+            inherits = read_inherits(&theme.join("index.theme"));
+        }
+    }
+    for parent in inherits.unwrap_or_default() {
+        add_theme(search_path, &parent, dirs, depth + 1);
+    }
+}
+
+// the Inherits= line of an index.theme
+fn read_inherits(path: &Path) -> Option<Vec<String>> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let line = content
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Inherits"))?;
+    let value = line.trim_start().strip_prefix('=')?;
+    Some(
+        value
+            .split([',', ';'])
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+// Every image of the nominal size closest to size, scaled to it; more than
+// one is an animation
+fn parse_xcursor(data: &[u8], size: i32) -> Option<Vec<CursorImage>> {
+    let u32_at = |at: usize| {
+        data.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if data.get(..4)? != XCURSOR_MAGIC {
+        return None;
+    }
+    let header = u32_at(4)? as usize;
+    let count = u32_at(12)? as usize;
+    // table of contents entries: type, nominal size, file position
+    let images: Vec<(u32, usize)> = (0..count)
+        .map_while(|i| {
+            let at = header + i * 12;
+            Some((u32_at(at)?, u32_at(at + 4)?, u32_at(at + 8)? as usize))
+        })
+        .filter(|&(kind, _, _)| kind == XCURSOR_IMAGE)
+        .map(|(_, nominal, position)| (nominal, position))
+        .collect();
+    let nominal = images
+        .iter()
+        .map(|&(nominal, _)| nominal)
+        .min_by_key(|&nominal| (nominal as i64 - size as i64).abs())?;
+
+    let frames: Vec<CursorImage> = images
+        .iter()
+        .filter(|&&(n, _)| n == nominal)
+        .filter_map(|&(_, at)| {
+            let (chunk_header, width, height) = (u32_at(at)?, u32_at(at + 16)?, u32_at(at + 20)?);
+            if width == 0 || height == 0 || width > XCURSOR_MAX_SIZE || height > XCURSOR_MAX_SIZE {
+                return None;
+            }
+            let (xhot, yhot, delay) = (u32_at(at + 24)?, u32_at(at + 28)?, u32_at(at + 32)?);
+            let start = at + chunk_header as usize;
+            let pixels = (0..(width * height) as usize)
+                .map(|i| u32_at(start + i * 4))
+                .collect::<Option<Vec<u32>>>()?;
+            let image = CursorImage {
+                width: width as usize,
+                height: height as usize,
+                pixels,
+                delay,
+                xhot: xhot.min(width - 1) as usize,
+                yhot: yhot.min(height - 1) as usize,
+            };
+            Some(image.scaled(nominal as i32, size))
+        })
+        .collect();
+    (!frames.is_empty()).then_some(frames)
+}
+
+pub struct Cursor {
+    pub shape: CursorShape,
+    pub size: i32,
+    // tells apart cursors of the same shape and size from different themes
+    pub serial: u64,
+    pub frames: Vec<CursorImage>,
+}
+
+impl Cursor {
+    // from the theme, or drawn from the built-in arrow when it lacks the shape
+    pub fn load(theme: &CursorTheme, shape: CursorShape, size: i32) -> Self {
+        let frames = theme.frames(shape, size).unwrap_or_else(|| match shape {
+            CursorShape::Wait => (0..WAIT_FRAMES)
+                .map(|frame| CursorImage::wait(size, frame))
+                .collect(),
+            _ => vec![CursorImage::arrow(size)],
+        });
+        Self {
+            shape,
+            size,
+            serial: NEXT_CURSOR_SERIAL.fetch_add(1, Ordering::Relaxed),
+            frames,
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+
+    pub fn frame(&self, index: usize) -> &CursorImage {
+        &self.frames[index % self.frames.len()]
+    }
+
+    pub fn frame_at(&self, elapsed_ms: u64) -> usize {
+        let total: u64 = self.frames.iter().map(|f| f.delay.max(1) as u64).sum();
+        if !self.is_animated() || total == 0 {
+            return 0;
+        }
+
+        let mut t = elapsed_ms % total;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let delay = frame.delay.max(1) as u64;
+            if t < delay {
+                return i;
+            }
+            t -= delay;
+        }
+        0
+    }
+}
+
+impl CursorImage {
+    pub fn arrow(size: i32) -> Self {
+        let size = size.max(1);
+        let width = (ARROW_W * size as usize)
+            .div_ceil(BASE_SIZE as usize)
+            .max(1);
+        let height = (ARROW_H * size as usize)
+            .div_ceil(BASE_SIZE as usize)
+            .max(1);
+        let mut pixels = vec![0u32; width * height];
+
+        for dy in 0..height {
+            let sy = dy * BASE_SIZE as usize / size as usize;
+            let Some(row) = ARROW.get(sy) else {
+                continue;
+            };
+            let row = row.as_bytes();
+            for dx in 0..width {
+                let sx = dx * BASE_SIZE as usize / size as usize;
+                pixels[dy * width + dx] = match row.get(sx) {
+                    Some(b'W') => 0xFFFFFFFF,
+                    Some(b'B') => 0xFF000000,
+                    _ => 0,
+                };
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            delay: 0,
+            xhot: 0,
+            yhot: 0,
+        }
+    }
+
+    // nearest-neighbour from a from-pixel cursor to a to-pixel one
+    fn scaled(self, from: i32, to: i32) -> Self {
+        if from == to || from <= 0 || to <= 0 {
+            return self;
+        }
+        let (from, to) = (from as usize, to as usize);
+        let width = (self.width * to).div_ceil(from).max(1);
+        let height = (self.height * to).div_ceil(from).max(1);
+        let mut pixels = vec![0u32; width * height];
+        for dy in 0..height {
+            let sy = (dy * from / to).min(self.height - 1);
+            for dx in 0..width {
+                let sx = (dx * from / to).min(self.width - 1);
+                pixels[dy * width + dx] = self.pixels[sy * self.width + sx];
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+            delay: self.delay,
+            xhot: self.xhot * to / from,
+            yhot: self.yhot * to / from,
+        }
+    }
+
+    // arrow with a ring of dots in the lower right, the lit dot walks around the ring
+    pub fn wait(size: i32, frame: usize) -> Self {
+        let size = size.max(1);
+        let arrow = Self::arrow(size);
+        let width = size as usize;
+        let height = size as usize;
+        let mut pixels = vec![0u32; width * height];
+
+        for y in 0..arrow.height.min(height) {
+            for x in 0..arrow.width.min(width) {
+                pixels[y * width + x] = arrow.pixels[y * arrow.width + x];
+            }
+        }
+
+        let s = size as f32;
+        let (cx, cy) = (s * 0.74, s * 0.74);
+        let ring = s * 0.17;
+        let dot = (s * 0.06).max(1.0);
+
+        for i in 0..WAIT_FRAMES {
+            let angle = i as f32 * std::f32::consts::TAU / WAIT_FRAMES as f32;
+            let (dx, dy) = (cx + ring * angle.cos(), cy + ring * angle.sin());
+            let age = (frame + WAIT_FRAMES - i) % WAIT_FRAMES;
+            let shade = 255 - (age as u32 * 200 / WAIT_FRAMES as u32);
+            let color = 0xFF000000 | (shade << 16) | (shade << 8) | shade;
+
+            let x0 = (dx - dot - 1.0).floor().max(0.0) as usize;
+            let y0 = (dy - dot - 1.0).floor().max(0.0) as usize;
+            let x1 = ((dx + dot + 1.0).ceil() as usize).min(width);
+            let y1 = ((dy + dot + 1.0).ceil() as usize).min(height);
+            for py in y0..y1 {
+                for px in x0..x1 {
+                    let dist =
+                        ((px as f32 + 0.5 - dx).powi(2) + (py as f32 + 0.5 - dy).powi(2)).sqrt();
+                    if dist <= dot {
+                        pixels[py * width + px] = color;
+                    } else if dist <= dot + 1.0 && pixels[py * width + px] >> 24 == 0 {
+                        pixels[py * width + px] = 0xFF000000;
+                    }
+                }
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            delay: WAIT_DELAY_MS,
+            xhot: 0,
+            yhot: 0,
+        }
+    }
+
+    pub fn to_bgra_bytes(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| p.to_le_bytes()).collect()
+    }
+}
@@ -1,16 +1,44 @@
+use input::event::gesture::{
+    GestureEndEvent, GestureEvent, GestureEventCoordinates, GestureEventTrait, GesturePinchEvent,
+    GesturePinchEventTrait, GestureSwipeEvent,
+};
 pub use input::event::keyboard::KeyState;
 use input::event::keyboard::KeyboardEventTrait;
-use input::event::pointer::PointerScrollEvent;
-use input::event::{Event, EventTrait};
-use input::{Libinput, LibinputInterface};
+use input::event::pointer::{Axis, PointerScrollEvent};
+pub use input::event::tablet_tool::TabletToolType;
+use input::event::tablet_tool::{ProximityState, TabletToolEvent, TabletToolEventTrait, TipState};
+use input::event::touch::{TouchEvent, TouchEventPosition, TouchEventSlot};
+use input::event::{DeviceEvent, Event, EventTrait};
+use input::{
+    AccelProfile, ClickMethod, Device, DeviceConfigResult, Led, Libinput, LibinputInterface,
+    ScrollMethod,
+};
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
+use std::time::{Duration, Instant};
 use xkbcommon::xkb;
 
-use crate::config::{Action, Keybind};
+use crate::config::{
+    Action, DeviceId, Gesture, InputConfig, Keybind, KeyboardConfig, PointerAccel, PointerBind,
+    PointerConfig, PointerGrab, PointerTrigger, SwipeDirection, ToggleState, TouchpadClick,
+    TouchpadConfig, TouchpadScroll, DEFAULT_MODE,
+};
+
+// how far (unaccelerated, roughly pixels) the fingers travel before a swipe
+// has a direction, and how much a pinch has to shrink or grow
+const SWIPE_DISTANCE: f64 = 80.0;
+const PINCH_IN_SCALE: f64 = 0.8;
+const PINCH_OUT_SCALE: f64 = 1.25;
+
+// how far (mm) a finger moves before a touch is a drag rather than a tap,
+// and how long it has to rest to right-click instead
+const TOUCH_SLOP_MM: f64 = 3.0;
+const LONG_PRESS: Duration = Duration::from_millis(500);
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
 
 struct Interface;
 
@@ -35,6 +63,14 @@ impl LibinputInterface for Interface {
     }
 }
 
+// what produced a frame's scrolling, sent as wl_pointer.axis_source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrollSource {
+    Wheel,
+    Finger,
+    Continuous,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct PointerState {
     pub accumulated_dx: f64,
@@ -43,6 +79,13 @@ pub struct PointerState {
     pub absolute_y: Option<f64>,
     pub scroll_horizontal: f64,
     pub scroll_vertical: f64,
+    // wheel clicks as (horizontal, vertical), 120 per detent
+    pub scroll_v120: (f64, f64),
+    // the same in whole clicks, for wl_pointer before v8
+    pub scroll_discrete: (i32, i32),
+    pub scroll_source: Option<ScrollSource>,
+    // a finger scroll ended on (horizontal, vertical)
+    pub scroll_stop: (bool, bool),
     pub has_motion: bool,
     pub has_scroll: bool,
 }
@@ -56,6 +99,10 @@ impl Default for PointerState {
             absolute_y: None,
             scroll_horizontal: 0.0,
             scroll_vertical: 0.0,
+            scroll_v120: (0.0, 0.0),
+            scroll_discrete: (0, 0),
+            scroll_source: None,
+            scroll_stop: (false, false),
             has_motion: false,
             has_scroll: false,
         }
@@ -70,6 +117,10 @@ impl PointerState {
         self.absolute_y = None;
         self.scroll_horizontal = 0.0;
         self.scroll_vertical = 0.0;
+        self.scroll_v120 = (0.0, 0.0);
+        self.scroll_discrete = (0, 0);
+        self.scroll_source = None;
+        self.scroll_stop = (false, false);
         self.has_motion = false;
         self.has_scroll = false;
     }
@@ -86,9 +137,10 @@ impl PointerState {
         self.has_motion = true;
     }
 
-    pub fn accumulate_scroll(&mut self, h: f64, v: f64) {
+    pub fn accumulate_scroll(&mut self, source: ScrollSource, h: f64, v: f64) {
         self.scroll_horizontal += h;
         self.scroll_vertical += v;
+        self.scroll_source = Some(source);
         self.has_scroll = true;
     }
 }
@@ -97,6 +149,71 @@ impl PointerState {
 pub struct ButtonEvent {
     pub button: u32,
     pub pressed: bool,
+    // pressed for a [[keybinds.mouse]] drag; clients see neither it nor
+    // its release
+    pub grab: Option<PointerGrab>,
+}
+
+// Touchscreens drive the pointer: the first finger down moves it, a tap
+// clicks, dragging holds the left button and a long press right-clicks.
+// Positions are fractions of the panel, see State::touch_to_layout.
+#[derive(Clone, Debug)]
+pub enum TouchInput {
+    Motion(f64, f64),
+    Button(ButtonEvent),
+}
+
+// a device libinput reports tablet tool events from
+#[derive(Clone, Debug, PartialEq)]
+pub struct TabletDevice {
+    // the event node under /dev/input, unique while it's plugged in
+    pub sysname: String,
+    pub name: String,
+    pub vendor: u32,
+    pub product: u32,
+}
+
+// A pen, eraser or puck. Tools without a serial can't be told apart from
+// others of the same kind.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolInfo {
+    pub kind: TabletToolType,
+    pub serial: u64,
+    pub pressure: bool,
+    pub tilt: bool,
+}
+
+// Tool positions are fractions of the tablet area, see
+// State::tablet_to_layout. Pressure is 0.0 to 1.0 with [input.tablet]
+// pressure_curve already applied; tilt is in degrees.
+#[derive(Clone, Debug)]
+pub enum TabletInput {
+    Added(TabletDevice),
+    // by sysname
+    Removed(String),
+    Proximity {
+        tablet: String,
+        tool: ToolInfo,
+        near: bool,
+        x: f64,
+        y: f64,
+    },
+    Motion {
+        tool: ToolInfo,
+        x: f64,
+        y: f64,
+        pressure: Option<f64>,
+        tilt: Option<(f64, f64)>,
+    },
+    Tip {
+        tool: ToolInfo,
+        down: bool,
+    },
+    Button {
+        tool: ToolInfo,
+        button: u32,
+        pressed: bool,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -109,12 +226,35 @@ pub struct KeyEvent {
     pub group: u32,
 }
 
+// What wl_seat advertises. Touchscreens and tablets count as pointers, as
+// that's what they drive for clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SeatCapabilities {
+    pub keyboard: bool,
+    pub pointer: bool,
+}
+
+impl Default for SeatCapabilities {
+    // until libinput says otherwise
+    fn default() -> Self {
+        Self {
+            keyboard: true,
+            pointer: true,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct InputFrame {
     pub pointer: PointerState,
     pub buttons: Vec<ButtonEvent>,
+    // in the order they happened, motion and buttons interleaved
+    pub touch: Vec<TouchInput>,
+    pub tablet: Vec<TabletInput>,
     pub keys: Vec<KeyEvent>,
     pub actions: Vec<Action>,
+    // set when a device was plugged in or removed
+    pub capabilities: Option<SeatCapabilities>,
 }
 
 impl InputFrame {
@@ -125,33 +265,96 @@ impl InputFrame {
     pub fn reset(&mut self) {
         self.pointer.reset();
         self.buttons.clear();
+        self.touch.clear();
+        self.tablet.clear();
         self.keys.clear();
         self.actions.clear();
+        self.capabilities = None;
     }
 
     pub fn has_events(&self) -> bool {
         self.pointer.has_motion
             || self.pointer.has_scroll
             || !self.buttons.is_empty()
+            || !self.touch.is_empty()
+            || !self.tablet.is_empty()
             || !self.keys.is_empty()
             || !self.actions.is_empty()
+            || self.capabilities.is_some()
     }
 }
 
+// a keybind whose key is held down, run again every interval once due
+struct KeyRepeat {
+    key: u32,
+    action: Action,
+    next: Instant,
+}
+
+// the finger driving the pointer, from touch down to up
+struct TouchPress {
+    slot: u32,
+    // where it went down, in mm
+    origin: (f64, f64),
+    // until then a touch that hasn't moved right-clicks; None once it
+    // started dragging or already did
+    long_press_at: Option<Instant>,
+    dragging: bool,
+}
+
+// a touchpad gesture between libinput's begin and end events
+enum GestureProgress {
+    Swipe { fingers: u32, dx: f64, dy: f64 },
+    Pinch { fingers: u32, scale: f64 },
+}
+
 pub struct InputHandler {
     libinput: Libinput,
+    // every device libinput opened, to configure them again on reload
+    devices: Vec<Device>,
+    config: InputConfig,
     xkb_context: xkb::Context,
     xkb_state: Option<xkb::State>,
+    xkb_names: (String, String, String),
+    // lit on every keyboard; None until first set, or after a keymap change
+    leds: Option<Led>,
     ctrl: bool,
     alt: bool,
     shift: bool,
     super_key: bool,
     frame: InputFrame,
     keybinds: HashMap<Keybind, Action>,
+    // [[keybinds.mode]] bindings by mode name, used instead of keybinds
+    // while that mode is active
+    modes: HashMap<String, HashMap<Keybind, Action>>,
+    mode: String,
+    gestures: HashMap<Gesture, Action>,
+    pointer_binds: HashMap<PointerBind, Action>,
+    // buttons pressed for a pointer binding, whose release is kept from
+    // clients too; true for drags, which State ends on the release
+    bound_buttons: Vec<(u32, bool)>,
+    // wheel travel in v120 units towards the next bound notch
+    bound_scroll: (f64, f64),
+    // only passthrough bindings run, everything else reaches the client
+    passthrough: bool,
+    // the same towards the next whole click for pre-v120 clients
+    wheel_travel: (f64, f64),
+    gesture: Option<GestureProgress>,
+    repeat: Option<KeyRepeat>,
+    touch: Option<TouchPress>,
+    // None when repeat is off
+    repeat_timing: Option<(Duration, Duration)>,
 }
 
 impl InputHandler {
-    pub fn new(keybinds: Vec<(Action, Keybind)>) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(
+        keybinds: Vec<(Action, Keybind)>,
+        modes: Vec<(String, Vec<(Action, Keybind)>)>,
+        gestures: Vec<(Action, Gesture)>,
+        pointer_binds: Vec<(Action, PointerBind)>,
+        keyboard: &KeyboardConfig,
+        input: &InputConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let mut libinput = Libinput::new_with_udev(Interface);
         libinput
             .udev_assign_seat("seat0")
@@ -160,17 +363,46 @@ impl InputHandler {
         libinput.dispatch()?;
         let mut keyboard_count = 0;
         let mut pointer_count = 0;
+        let mut devices = Vec::new();
         for event in &mut libinput {
-            if let Event::Device(input::event::DeviceEvent::Added(added)) = event {
-                let device = added.device();
+            if let Event::Device(DeviceEvent::Added(added)) = event {
+                let mut device = added.device();
                 if device.has_capability(input::DeviceCapability::Keyboard) {
                     keyboard_count += 1;
-                    log::info!("[input] Keyboard device: {}", device.name());
+                    log::info!(
+                        "[input] Keyboard device: {} ({:04x}:{:04x})",
+                        device.name(),
+                        device.id_vendor(),
+                        device.id_product()
+                    );
                 }
                 if device.has_capability(input::DeviceCapability::Pointer) {
                     pointer_count += 1;
-                    log::info!("[input] Pointer device: {}", device.name());
+                    log::info!(
+                        "[input] Pointer device: {} ({:04x}:{:04x})",
+                        device.name(),
+                        device.id_vendor(),
+                        device.id_product()
+                    );
+                }
+                if device.has_capability(input::DeviceCapability::Touch) {
+                    log::info!(
+                        "[input] Touch device: {} ({:04x}:{:04x})",
+                        device.name(),
+                        device.id_vendor(),
+                        device.id_product()
+                    );
                 }
+                if device.has_capability(input::DeviceCapability::TabletTool) {
+                    log::info!(
+                        "[input] Tablet: {} ({:04x}:{:04x})",
+                        device.name(),
+                        device.id_vendor(),
+                        device.id_product()
+                    );
+                }
+                configure_device(&mut device, input);
+                devices.push(device);
             }
         }
 
@@ -188,17 +420,57 @@ impl InputHandler {
             .map(|(action, keybind)| (keybind, action))
             .collect();
 
-        Ok(InputHandler {
+        let mut handler = InputHandler {
             libinput,
+            devices,
+            config: input.clone(),
             xkb_context,
             xkb_state: None,
+            xkb_names: (
+                keyboard.layout.clone(),
+                keyboard.model.clone(),
+                keyboard.options.clone(),
+            ),
+            leds: None,
             ctrl: false,
             alt: false,
             shift: false,
             super_key: false,
             frame: InputFrame::new(),
             keybinds: keybind_map,
-        })
+            modes: modes
+                .into_iter()
+                .map(|(name, bindings)| {
+                    let map = bindings
+                        .into_iter()
+                        .map(|(action, keybind)| (keybind, action))
+                        .collect();
+                    (name, map)
+                })
+                .collect(),
+            mode: DEFAULT_MODE.to_string(),
+            gestures: gestures
+                .into_iter()
+                .map(|(action, gesture)| (gesture, action))
+                .collect(),
+            pointer_binds: pointer_binds
+                .into_iter()
+                .map(|(action, bind)| (bind, action))
+                .collect(),
+            bound_buttons: Vec::new(),
+            bound_scroll: (0.0, 0.0),
+            passthrough: false,
+            wheel_travel: (0.0, 0.0),
+            gesture: None,
+            repeat: None,
+            touch: None,
+            repeat_timing: repeat_timing(keyboard),
+        };
+        handler.init_xkb_state();
+        if keyboard.numlock {
+            handler.lock_numlock();
+        }
+        Ok(handler)
     }
 
     pub fn dispatch(&mut self) -> Result<(), Box<dyn std::error::Error>> {
@@ -211,7 +483,10 @@ impl InputHandler {
 
         let mut keyboard_events = Vec::new();
         let mut pointer_events = Vec::new();
-        let mut has_keyboard_device = false;
+        let mut gesture_events = Vec::new();
+        let mut touch_events = Vec::new();
+        let mut tablet_events = Vec::new();
+        let mut devices_changed = false;
 
         for event in &mut self.libinput {
             match event {
@@ -220,18 +495,54 @@ impl InputHandler {
                     let state = keyboard_event.key_state();
                     keyboard_events.push((key, state));
                 }
-                Event::Device(device_event) => {
-                    use input::event::DeviceEvent;
-                    if let DeviceEvent::Added(added) = device_event {
-                        let device = added.device();
-                        if device.has_capability(input::DeviceCapability::Keyboard) {
-                            has_keyboard_device = true;
-                        }
+                Event::Device(DeviceEvent::Added(added)) => {
+                    let mut device = added.device();
+                    log::info!(
+                        "[input] Device added: {} ({:04x}:{:04x})",
+                        device.name(),
+                        device.id_vendor(),
+                        device.id_product()
+                    );
+                    // the xkb state is shared, the new keyboard only needs
+                    // to show its locks
+                    if let Some(leds) = self
+                        .leds
+                        .filter(|_| device.has_capability(input::DeviceCapability::Keyboard))
+                    {
+                        device.led_update(leds);
+                    }
+                    if let Some(tablet) = tablet_device(&device) {
+                        self.frame.tablet.push(TabletInput::Added(tablet));
+                    }
+                    configure_device(&mut device, &self.config);
+                    self.devices.push(device);
+                    devices_changed = true;
+                }
+                // libinput has already released whatever keys, buttons and
+                // touches the device held
+                Event::Device(DeviceEvent::Removed(removed)) => {
+                    let device = removed.device();
+                    log::info!("[input] Device removed: {}", device.name());
+                    if device.has_capability(input::DeviceCapability::TabletTool) {
+                        self.frame
+                            .tablet
+                            .push(TabletInput::Removed(device.sysname().to_string()));
                     }
+                    self.devices.retain(|d| *d != device);
+                    devices_changed = true;
                 }
                 Event::Pointer(pointer_event) => {
                     pointer_events.push(pointer_event);
                 }
+                Event::Gesture(gesture_event) => {
+                    gesture_events.push(gesture_event);
+                }
+                Event::Touch(touch_event) => {
+                    touch_events.push(touch_event);
+                }
+                Event::Tablet(tool_event) => {
+                    tablet_events.push(tool_event);
+                }
                 _ => {}
             }
         }
@@ -240,13 +551,27 @@ impl InputHandler {
             self.handle_pointer_event(pointer_event);
         }
 
-        if has_keyboard_device {
-            self.init_xkb_state();
+        for gesture_event in gesture_events {
+            self.handle_gesture_event(gesture_event);
+        }
+
+        for touch_event in touch_events {
+            self.handle_touch_event(touch_event);
+        }
+
+        for tool_event in tablet_events {
+            self.handle_tablet_event(tool_event);
+        }
+
+        if devices_changed {
+            self.frame.capabilities = Some(self.capabilities());
         }
 
         for (key, state) in keyboard_events {
             self.handle_keyboard_key_batched(key, state);
         }
+        self.run_due_repeat();
+        self.run_due_long_press();
 
         &self.frame
     }
@@ -262,40 +587,458 @@ impl InputHandler {
                     .accumulate_relative(motion.dx(), motion.dy());
             }
             PointerEvent::MotionAbsolute(abs) => {
+                // fraction of the device's range; State::absolute_to_layout
+                // places it on an output
                 self.frame
                     .pointer
-                    .set_absolute(abs.absolute_x(), abs.absolute_y());
+                    .set_absolute(abs.absolute_x_transformed(1), abs.absolute_y_transformed(1));
             }
             PointerEvent::Button(btn) => {
-                self.frame.buttons.push(ButtonEvent {
-                    button: btn.button(),
-                    pressed: btn.button_state() == ButtonState::Pressed,
-                });
+                let button = btn.button();
+                let pressed = btn.button_state() == ButtonState::Pressed;
+                self.handle_button(button, pressed);
             }
             PointerEvent::ScrollWheel(scroll) => {
-                let h = scroll.scroll_value_v120(input::event::pointer::Axis::Horizontal) / 120.0
-                    * 15.0;
-                let v =
-                    scroll.scroll_value_v120(input::event::pointer::Axis::Vertical) / 120.0 * 15.0;
-                self.frame.pointer.accumulate_scroll(h, v);
+                let h = scroll.scroll_value_v120(Axis::Horizontal);
+                let v = scroll.scroll_value_v120(Axis::Vertical);
+                // bindings see the wheel as it turned, before natural
+                // scrolling flips it
+                let (h, v) = self.take_bound_scroll(h, v);
+                if h == 0.0 && v == 0.0 {
+                    return;
+                }
+                let scale = self
+                    .config
+                    .pointer_for(&device_id(&scroll.device()))
+                    .scroll_scale();
+                let (h, v) = (h * scale, v * scale);
+                let travel = (self.wheel_travel.0 + h, self.wheel_travel.1 + v);
+                let clicks = ((travel.0 / 120.0).trunc(), (travel.1 / 120.0).trunc());
+                self.wheel_travel = (travel.0 - clicks.0 * 120.0, travel.1 - clicks.1 * 120.0);
+                let pointer = &mut self.frame.pointer;
+                pointer.accumulate_scroll(ScrollSource::Wheel, h / 120.0 * 15.0, v / 120.0 * 15.0);
+                pointer.scroll_v120.0 += h;
+                pointer.scroll_v120.1 += v;
+                pointer.scroll_discrete.0 += clicks.0 as i32;
+                pointer.scroll_discrete.1 += clicks.1 as i32;
             }
             PointerEvent::ScrollFinger(scroll) => {
-                let h = scroll.scroll_value(input::event::pointer::Axis::Horizontal);
-                let v = scroll.scroll_value(input::event::pointer::Axis::Vertical);
-                self.frame.pointer.accumulate_scroll(h, v);
+                let scale = self
+                    .config
+                    .touchpad_for(&device_id(&scroll.device()))
+                    .scroll_scale();
+                self.accumulate_smooth_scroll(ScrollSource::Finger, &scroll, scale);
             }
             PointerEvent::ScrollContinuous(scroll) => {
-                let h = scroll.scroll_value(input::event::pointer::Axis::Horizontal);
-                let v = scroll.scroll_value(input::event::pointer::Axis::Vertical);
-                self.frame.pointer.accumulate_scroll(h, v);
+                let scale = self
+                    .config
+                    .pointer_for(&device_id(&scroll.device()))
+                    .scroll_scale();
+                self.accumulate_smooth_scroll(ScrollSource::Continuous, &scroll, scale);
+            }
+            _ => {}
+        }
+    }
+
+    // A zero on an axis the event has ends a finger or continuous scroll
+    fn accumulate_smooth_scroll(
+        &mut self,
+        source: ScrollSource,
+        scroll: &impl PointerScrollEvent,
+        scale: f64,
+    ) {
+        let value = |axis| {
+            let stopped = scroll.has_axis(axis) && scroll.scroll_value(axis) == 0.0;
+            (scroll.scroll_value(axis) * scale, stopped)
+        };
+        let (h, stop_h) = value(Axis::Horizontal);
+        let (v, stop_v) = value(Axis::Vertical);
+        let pointer = &mut self.frame.pointer;
+        pointer.accumulate_scroll(source, h, v);
+        pointer.scroll_stop.0 |= stop_h;
+        pointer.scroll_stop.1 |= stop_v;
+    }
+
+    fn pointer_bind(&self, trigger: PointerTrigger) -> Option<&Action> {
+        self.pointer_binds
+            .get(&PointerBind {
+                ctrl: self.ctrl,
+                alt: self.alt,
+                shift: self.shift,
+                super_key: self.super_key,
+                trigger,
+            })
+            .filter(|action| !self.passthrough || matches!(action, Action::Passthrough(_)))
+    }
+
+    fn handle_button(&mut self, button: u32, pressed: bool) {
+        if !pressed {
+            if let Some(index) = self.bound_buttons.iter().position(|&(b, _)| b == button) {
+                let (_, drag) = self.bound_buttons.swap_remove(index);
+                if drag {
+                    self.frame.buttons.push(ButtonEvent {
+                        button,
+                        pressed,
+                        grab: None,
+                    });
+                }
+                return;
+            }
+        } else if let Some(action) = self.pointer_bind(PointerTrigger::Button(button)).cloned() {
+            let grab = match action {
+                Action::Drag(grab) => Some(grab),
+                action => {
+                    self.frame.actions.push(action);
+                    None
+                }
+            };
+            self.bound_buttons.push((button, grab.is_some()));
+            if grab.is_some() {
+                self.frame.buttons.push(ButtonEvent {
+                    button,
+                    pressed,
+                    grab,
+                });
+            }
+            return;
+        }
+        self.frame.buttons.push(ButtonEvent {
+            button,
+            pressed,
+            grab: None,
+        });
+    }
+
+    // Wheel notches in directions bound with the modifiers held run their
+    // action, once per 120 v120 units, and go no further; what's left
+    // scrolls as usual
+    fn take_bound_scroll(&mut self, h: f64, v: f64) -> (f64, f64) {
+        let (travel_h, travel_v) = self.bound_scroll;
+        let (h, travel_h) = self.bound_notches(
+            h,
+            travel_h,
+            PointerTrigger::ScrollLeft,
+            PointerTrigger::ScrollRight,
+        );
+        let (v, travel_v) = self.bound_notches(
+            v,
+            travel_v,
+            PointerTrigger::ScrollUp,
+            PointerTrigger::ScrollDown,
+        );
+        self.bound_scroll = (travel_h, travel_v);
+        (h, v)
+    }
+
+    // (what scrolls, travel towards the next notch) along one axis
+    fn bound_notches(
+        &mut self,
+        delta: f64,
+        mut travel: f64,
+        back: PointerTrigger,
+        forward: PointerTrigger,
+    ) -> (f64, f64) {
+        if delta == 0.0 {
+            return (0.0, travel);
+        }
+        let trigger = if delta < 0.0 { back } else { forward };
+        let Some(action) = self.pointer_bind(trigger).cloned() else {
+            return (delta, 0.0);
+        };
+        if travel.signum() != delta.signum() {
+            travel = 0.0;
+        }
+        travel += delta;
+        while travel.abs() >= 120.0 {
+            travel -= 120.0 * travel.signum();
+            self.frame.actions.push(action.clone());
+        }
+        (0.0, travel)
+    }
+
+    fn handle_tablet_event(&mut self, tool_event: TabletToolEvent) {
+        let tool = tool_event.tool();
+        let tool = ToolInfo {
+            kind: tool.tool_type().unwrap_or(TabletToolType::Pen),
+            serial: tool.serial(),
+            pressure: tool.has_pressure(),
+            tilt: tool.has_tilt(),
+        };
+        match tool_event {
+            TabletToolEvent::Proximity(proximity) => {
+                let near = proximity.proximity_state() == ProximityState::In;
+                self.frame.tablet.push(TabletInput::Proximity {
+                    tablet: proximity.device().sysname().to_string(),
+                    tool: tool.clone(),
+                    near,
+                    x: proximity.x_transformed(1),
+                    y: proximity.y_transformed(1),
+                });
+                // the axes' starting values come with proximity in
+                if near {
+                    self.push_tablet_motion(&proximity, tool);
+                }
+            }
+            TabletToolEvent::Axis(axis) => {
+                self.push_tablet_motion(&axis, tool);
+            }
+            TabletToolEvent::Tip(tip) => {
+                // the tip event carries the axes as they were when it landed
+                self.push_tablet_motion(&tip, tool.clone());
+                self.frame.tablet.push(TabletInput::Tip {
+                    tool,
+                    down: tip.tip_state() == TipState::Down,
+                });
+            }
+            TabletToolEvent::Button(button) => {
+                self.frame.tablet.push(TabletInput::Button {
+                    tool,
+                    button: button.button(),
+                    pressed: button.button_state() == input::event::pointer::ButtonState::Pressed,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn push_tablet_motion(&mut self, event: &impl TabletToolEventTrait, tool: ToolInfo) {
+        let pressure = tool
+            .pressure
+            .then(|| self.config.tablet.apply_pressure_curve(event.pressure()));
+        let tilt = tool.tilt.then(|| (event.tilt_x(), event.tilt_y()));
+        self.frame.tablet.push(TabletInput::Motion {
+            tool,
+            x: event.x_transformed(1),
+            y: event.y_transformed(1),
+            pressure,
+            tilt,
+        });
+    }
+
+    // the tablets plugged in so far, including those found at startup
+    pub fn tablets(&self) -> Vec<TabletDevice> {
+        self.devices.iter().filter_map(tablet_device).collect()
+    }
+
+    pub fn capabilities(&self) -> SeatCapabilities {
+        let has = |capability| self.devices.iter().any(|d| d.has_capability(capability));
+        SeatCapabilities {
+            keyboard: has(input::DeviceCapability::Keyboard),
+            pointer: has(input::DeviceCapability::Pointer)
+                || has(input::DeviceCapability::Touch)
+                || has(input::DeviceCapability::TabletTool),
+        }
+    }
+
+    // Only the first finger counts; the others are ignored until it lifts.
+    fn handle_touch_event(&mut self, touch_event: TouchEvent) {
+        let press = |pressed| {
+            TouchInput::Button(ButtonEvent {
+                button: BTN_LEFT,
+                pressed,
+                grab: None,
+            })
+        };
+        match touch_event {
+            TouchEvent::Down(down) => {
+                if self.touch.is_some() {
+                    return;
+                }
+                self.frame.touch.push(TouchInput::Motion(
+                    down.x_transformed(1),
+                    down.y_transformed(1),
+                ));
+                self.touch = Some(TouchPress {
+                    slot: down.seat_slot(),
+                    origin: (down.x(), down.y()),
+                    long_press_at: Some(Instant::now() + LONG_PRESS),
+                    dragging: false,
+                });
+            }
+            TouchEvent::Motion(motion) => {
+                let Some(touch) = self.touch.as_mut().filter(|t| t.slot == motion.seat_slot())
+                else {
+                    return;
+                };
+                if !touch.dragging && touch.long_press_at.is_some() {
+                    let (dx, dy) = (motion.x() - touch.origin.0, motion.y() - touch.origin.1);
+                    if dx.hypot(dy) < TOUCH_SLOP_MM {
+                        return;
+                    }
+                    // pressed where the finger went down, so a drag on a
+                    // title bar picks up the window under it
+                    touch.dragging = true;
+                    touch.long_press_at = None;
+                    self.frame.touch.push(press(true));
+                }
+                if touch.dragging {
+                    self.frame.touch.push(TouchInput::Motion(
+                        motion.x_transformed(1),
+                        motion.y_transformed(1),
+                    ));
+                }
+            }
+            TouchEvent::Up(up) => {
+                let Some(touch) = self.lift_touch(up.seat_slot()) else {
+                    return;
+                };
+                if touch.dragging {
+                    self.frame.touch.push(press(false));
+                } else if touch.long_press_at.is_some() {
+                    self.frame.touch.push(press(true));
+                    self.frame.touch.push(press(false));
+                }
+            }
+            TouchEvent::Cancel(cancel) => {
+                let lifted = self.lift_touch(cancel.seat_slot());
+                if lifted.is_some_and(|touch| touch.dragging) {
+                    self.frame.touch.push(press(false));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn lift_touch(&mut self, slot: u32) -> Option<TouchPress> {
+        if self.touch.as_ref()?.slot != slot {
+            return None;
+        }
+        self.touch.take()
+    }
+
+    fn run_due_long_press(&mut self) {
+        let Some(touch) = self.touch.as_mut() else {
+            return;
+        };
+        if touch.long_press_at.is_none_or(|at| Instant::now() < at) {
+            return;
+        }
+        touch.long_press_at = None;
+        for pressed in [true, false] {
+            self.frame.touch.push(TouchInput::Button(ButtonEvent {
+                button: BTN_RIGHT,
+                pressed,
+                grab: None,
+            }));
+        }
+    }
+
+    // Follows swipes and pinches to their end and runs the action bound to
+    // what they turned out to be. Gestures aren't forwarded to clients.
+    fn handle_gesture_event(&mut self, gesture_event: GestureEvent) {
+        match gesture_event {
+            GestureEvent::Swipe(GestureSwipeEvent::Begin(begin)) => {
+                self.gesture = Some(GestureProgress::Swipe {
+                    fingers: begin.finger_count() as u32,
+                    dx: 0.0,
+                    dy: 0.0,
+                });
+            }
+            GestureEvent::Swipe(GestureSwipeEvent::Update(update)) => {
+                if let Some(GestureProgress::Swipe { dx, dy, .. }) = &mut self.gesture {
+                    *dx += update.dx_unaccelerated();
+                    *dy += update.dy_unaccelerated();
+                }
+            }
+            GestureEvent::Swipe(GestureSwipeEvent::End(end)) => {
+                let progress = self.gesture.take();
+                if let Some(GestureProgress::Swipe { fingers, dx, dy }) = progress {
+                    if !end.cancelled() {
+                        self.finish_swipe(fingers, dx, dy);
+                    }
+                }
+            }
+            GestureEvent::Pinch(GesturePinchEvent::Begin(begin)) => {
+                self.gesture = Some(GestureProgress::Pinch {
+                    fingers: begin.finger_count() as u32,
+                    scale: 1.0,
+                });
+            }
+            GestureEvent::Pinch(GesturePinchEvent::Update(update)) => {
+                if let Some(GestureProgress::Pinch { scale, .. }) = &mut self.gesture {
+                    *scale = update.scale();
+                }
+            }
+            GestureEvent::Pinch(GesturePinchEvent::End(end)) => {
+                let progress = self.gesture.take();
+                if let Some(GestureProgress::Pinch { fingers, scale }) = progress {
+                    if !end.cancelled() {
+                        self.finish_pinch(fingers, scale);
+                    }
+                }
             }
             _ => {}
         }
     }
 
+    fn finish_swipe(&mut self, fingers: u32, dx: f64, dy: f64) {
+        if dx.abs().max(dy.abs()) < SWIPE_DISTANCE {
+            return;
+        }
+        let direction = if dx.abs() > dy.abs() {
+            if dx < 0.0 {
+                SwipeDirection::Left
+            } else {
+                SwipeDirection::Right
+            }
+        } else if dy < 0.0 {
+            SwipeDirection::Up
+        } else {
+            SwipeDirection::Down
+        };
+        self.run_gesture(Gesture::Swipe { fingers, direction });
+    }
+
+    fn finish_pinch(&mut self, fingers: u32, scale: f64) {
+        let inward = if scale <= PINCH_IN_SCALE {
+            true
+        } else if scale >= PINCH_OUT_SCALE {
+            false
+        } else {
+            return;
+        };
+        self.run_gesture(Gesture::Pinch { fingers, inward });
+    }
+
+    fn run_gesture(&mut self, gesture: Gesture) {
+        let action = self
+            .gestures
+            .get(&gesture)
+            .filter(|action| !self.passthrough || matches!(action, Action::Passthrough(_)));
+        match action {
+            Some(action) => self.frame.actions.push(action.clone()),
+            None => log::debug!("[input] Unbound gesture {:?}", gesture),
+        }
+    }
+
+    // when the held keybind runs next, or a resting touch right-clicks
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let repeat = self.repeat.as_ref().map(|r| r.next);
+        let long_press = self.touch.as_ref().and_then(|t| t.long_press_at);
+        repeat.into_iter().chain(long_press).min()
+    }
+
+    fn run_due_repeat(&mut self) {
+        let (Some(repeat), Some((_, interval))) = (self.repeat.as_mut(), self.repeat_timing) else {
+            return;
+        };
+        let now = Instant::now();
+        if now < repeat.next {
+            return;
+        }
+        self.frame.actions.push(repeat.action.clone());
+        // a late tick runs the action once rather than catching up
+        repeat.next = (repeat.next + interval).max(now);
+    }
+
     fn handle_keyboard_key_batched(&mut self, key: u32, state: input::event::keyboard::KeyState) {
         use input::event::keyboard::KeyState;
 
+        // another key, or letting go, ends a repeat
+        if state == KeyState::Pressed || self.repeat.as_ref().is_some_and(|r| r.key == key) {
+            self.repeat = None;
+        }
+
         if self.xkb_state.is_none() {
             self.init_xkb_state();
         }
@@ -317,6 +1060,7 @@ impl InputHandler {
                 xkb_state.mod_name_is_active(xkb::MOD_NAME_SHIFT, xkb::STATE_MODS_EFFECTIVE);
             self.super_key =
                 xkb_state.mod_name_is_active(xkb::MOD_NAME_LOGO, xkb::STATE_MODS_EFFECTIVE);
+            let leds = keyboard_leds(xkb_state);
 
             if state == KeyState::Pressed {
                 let keysym: u32 = xkb_state
@@ -332,8 +1076,20 @@ impl InputHandler {
                     keysym: keysym_lower,
                 };
 
-                if let Some(action) = self.keybinds.get(&current_keybind) {
+                let bindings = self.modes.get(&self.mode).unwrap_or(&self.keybinds);
+                let binding = bindings
+                    .get(&current_keybind)
+                    .filter(|action| !self.passthrough || matches!(action, Action::Passthrough(_)));
+                if let Some(action) = binding {
+                    if let Some((delay, _)) = self.repeat_timing.filter(|_| action.repeats()) {
+                        self.repeat = Some(KeyRepeat {
+                            key,
+                            action: action.clone(),
+                            next: Instant::now() + delay,
+                        });
+                    }
                     self.frame.actions.push(action.clone());
+                    self.set_leds(leds);
                     return;
                 }
             }
@@ -346,24 +1102,192 @@ impl InputHandler {
                 mods_locked: xkb_state.serialize_mods(xkb::STATE_MODS_LOCKED),
                 group: xkb_state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE),
             });
+            self.set_leds(leds);
         } else {
             log::error!("XKB state unavailable, key event dropped");
         }
     }
 
     fn init_xkb_state(&mut self) {
+        let (layout, model, options) = &self.xkb_names;
+        let options = (!options.is_empty()).then(|| options.clone());
         let keymap = xkb::Keymap::new_from_names(
             &self.xkb_context,
             "",
+            model,
+            layout,
             "",
-            "",
-            "",
-            None,
+            options,
             xkb::KEYMAP_COMPILE_NO_FLAGS,
-        );
+        )
+        .or_else(|| {
+            log::warn!(
+                "[input] Invalid keyboard layout {:?}, using default",
+                layout
+            );
+            xkb::Keymap::new_from_names(
+                &self.xkb_context,
+                "",
+                "",
+                "",
+                "",
+                None,
+                xkb::KEYMAP_COMPILE_NO_FLAGS,
+            )
+        });
 
         if let Some(keymap) = keymap {
-            self.xkb_state = Some(xkb::State::new(&keymap));
+            let group = self.layout_group();
+            // NumLock and CapsLock stay on across a new keymap or keyboard
+            let locked = self
+                .xkb_state
+                .as_ref()
+                .map(|s| s.serialize_mods(xkb::STATE_MODS_LOCKED))
+                .unwrap_or(0);
+            let mut xkb_state = xkb::State::new(&keymap);
+            xkb_state.update_mask(0, 0, locked, 0, 0, group);
+            let leds = keyboard_leds(&xkb_state);
+            self.xkb_state = Some(xkb_state);
+            // sent again, whatever the keyboards showed before
+            self.leds = None;
+            self.set_leds(leds);
+        }
+    }
+
+    fn lock_numlock(&mut self) {
+        let Some(xkb_state) = self.xkb_state.as_mut() else {
+            return;
+        };
+        let index = xkb_state.get_keymap().mod_get_index(xkb::MOD_NAME_NUM);
+        if index == xkb::MOD_INVALID {
+            log::warn!("[input] The keymap has no NumLock modifier");
+            return;
+        }
+        let depressed = xkb_state.serialize_mods(xkb::STATE_MODS_DEPRESSED);
+        let latched = xkb_state.serialize_mods(xkb::STATE_MODS_LATCHED);
+        let locked = xkb_state.serialize_mods(xkb::STATE_MODS_LOCKED) | 1 << index;
+        let group = xkb_state.serialize_layout(xkb::STATE_LAYOUT_LOCKED);
+        xkb_state.update_mask(depressed, latched, locked, 0, 0, group);
+        let leds = keyboard_leds(xkb_state);
+        self.set_leds(leds);
+    }
+
+    fn set_leds(&mut self, leds: Led) {
+        if self.leds == Some(leds) {
+            return;
+        }
+        self.leds = Some(leds);
+        for device in &mut self.devices {
+            if device.has_capability(input::DeviceCapability::Keyboard) {
+                device.led_update(leds);
+            }
+        }
+    }
+
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+
+    // false for a mode the config doesn't define
+    pub fn set_mode(&mut self, name: &str) -> bool {
+        if name.eq_ignore_ascii_case(DEFAULT_MODE) {
+            self.mode = DEFAULT_MODE.to_string();
+        } else if self.modes.contains_key(name) {
+            self.mode = name.to_string();
+        } else {
+            return false;
+        }
+        // a binding held across the switch may not exist in the new mode
+        self.repeat = None;
+        true
+    }
+
+    pub fn passthrough(&self) -> bool {
+        self.passthrough
+    }
+
+    // Returns whether it changed
+    pub fn set_passthrough(&mut self, toggle: &ToggleState) -> bool {
+        let enabled = match toggle {
+            ToggleState::Toggle => !self.passthrough,
+            ToggleState::On => true,
+            ToggleState::Off => false,
+        };
+        if enabled == self.passthrough {
+            return false;
+        }
+        self.passthrough = enabled;
+        self.repeat = None;
+        true
+    }
+
+    pub fn layout_group(&self) -> u32 {
+        self.xkb_state
+            .as_ref()
+            .map(|s| s.serialize_layout(xkb::STATE_LAYOUT_LOCKED))
+            .unwrap_or(0)
+    }
+
+    // groups in the keymap, one per comma-separated keyboard.layout entry
+    pub fn layout_count(&self) -> u32 {
+        self.xkb_state
+            .as_ref()
+            .map(|s| s.get_keymap().num_layouts())
+            .unwrap_or(1)
+    }
+
+    // Locks the given layout group, returning the serialized modifiers
+    // (depressed, latched, locked, group) to forward to clients.
+    pub fn set_layout_group(&mut self, group: u32) -> Option<(u32, u32, u32, u32)> {
+        let xkb_state = self.xkb_state.as_mut()?;
+        let depressed = xkb_state.serialize_mods(xkb::STATE_MODS_DEPRESSED);
+        let latched = xkb_state.serialize_mods(xkb::STATE_MODS_LATCHED);
+        let locked = xkb_state.serialize_mods(xkb::STATE_MODS_LOCKED);
+        xkb_state.update_mask(depressed, latched, locked, 0, 0, group);
+        self.modifiers()
+    }
+
+    // (depressed, latched, locked, group) as wl_keyboard.modifiers sends them
+    pub fn modifiers(&self) -> Option<(u32, u32, u32, u32)> {
+        let xkb_state = self.xkb_state.as_ref()?;
+        Some((
+            xkb_state.serialize_mods(xkb::STATE_MODS_DEPRESSED),
+            xkb_state.serialize_mods(xkb::STATE_MODS_LATCHED),
+            xkb_state.serialize_mods(xkb::STATE_MODS_LOCKED),
+            xkb_state.serialize_layout(xkb::STATE_LAYOUT_EFFECTIVE),
+        ))
+    }
+
+    // Recompiles the keymap when layout, model or options changed, keeping
+    // the locked group. Returns whether clients need the new keymap.
+    pub fn set_keymap(&mut self, keyboard: &KeyboardConfig) -> bool {
+        let names = (
+            keyboard.layout.clone(),
+            keyboard.model.clone(),
+            keyboard.options.clone(),
+        );
+        if names == self.xkb_names {
+            return false;
+        }
+        self.xkb_names = names;
+        if self.xkb_state.is_some() {
+            self.init_xkb_state();
+        }
+        true
+    }
+
+    pub fn set_repeat(&mut self, keyboard: &KeyboardConfig) {
+        self.repeat_timing = repeat_timing(keyboard);
+        if self.repeat_timing.is_none() {
+            self.repeat = None;
+        }
+    }
+
+    // new settings for the devices already open, and those added later
+    pub fn set_input_config(&mut self, input: &InputConfig) {
+        self.config = input.clone();
+        for device in &mut self.devices {
+            configure_device(device, &self.config);
         }
     }
 
@@ -372,6 +1296,142 @@ impl InputHandler {
     }
 }
 
+fn keyboard_leds(xkb_state: &xkb::State) -> Led {
+    let mut leds = Led::empty();
+    if xkb_state.led_name_is_active(xkb::LED_NAME_NUM) {
+        leds |= Led::NUMLOCK;
+    }
+    if xkb_state.led_name_is_active(xkb::LED_NAME_CAPS) {
+        leds |= Led::CAPSLOCK;
+    }
+    if xkb_state.led_name_is_active(xkb::LED_NAME_SCROLL) {
+        leds |= Led::SCROLLLOCK;
+    }
+    leds
+}
+
+// (delay, interval) of keybind repeat, as clients are told in repeat_info
+fn repeat_timing(keyboard: &KeyboardConfig) -> Option<(Duration, Duration)> {
+    let rate = keyboard.repeat_rate();
+    if rate == 0 {
+        return None;
+    }
+    let delay = Duration::from_millis(keyboard.repeat_delay.max(0) as u64);
+    Some((delay, Duration::from_secs(1) / rate as u32))
+}
+
+// Settings a device doesn't have are skipped. Touchpads are told apart by
+// their tap support.
+fn configure_device(device: &mut Device, input: &InputConfig) {
+    let name = device.name().to_string();
+    let id = DeviceId {
+        name: &name,
+        vendor: device.id_vendor(),
+        product: device.id_product(),
+    };
+    if device.has_capability(input::DeviceCapability::Pointer) {
+        let pointer = input.pointer_for(&id);
+        configure_pointer(device, &pointer);
+    }
+    if device.config_tap_finger_count() > 0 {
+        let touchpad = input.touchpad_for(&id);
+        configure_touchpad(device, &touchpad);
+    }
+    if device.has_capability(input::DeviceCapability::TabletTool)
+        && device.config_left_handed_is_available()
+    {
+        let result = device.config_left_handed_set(input.tablet.left_handed);
+        check(device, "left_handed", result);
+    }
+}
+
+// what [[input.device]] blocks match against
+fn device_id(device: &Device) -> DeviceId<'_> {
+    DeviceId {
+        name: device.name(),
+        vendor: device.id_vendor(),
+        product: device.id_product(),
+    }
+}
+
+fn tablet_device(device: &Device) -> Option<TabletDevice> {
+    if !device.has_capability(input::DeviceCapability::TabletTool) {
+        return None;
+    }
+    Some(TabletDevice {
+        sysname: device.sysname().to_string(),
+        name: device.name().to_string(),
+        vendor: device.id_vendor(),
+        product: device.id_product(),
+    })
+}
+
+fn check(device: &Device, setting: &str, result: DeviceConfigResult) {
+    if let Err(e) = result {
+        log::warn!("[input] {}: can't set {}: {:?}", device.name(), setting, e);
+    }
+}
+
+fn configure_pointer(device: &mut Device, pointer: &PointerConfig) {
+    if device.config_accel_is_available() {
+        let profile = match pointer.accel_profile() {
+            PointerAccel::Adaptive => AccelProfile::Adaptive,
+            PointerAccel::Flat => AccelProfile::Flat,
+        };
+        if device.config_accel_profiles().contains(&profile) {
+            let result = device.config_accel_set_profile(profile);
+            check(device, "accel_profile", result);
+        }
+        let result = device.config_accel_set_speed(pointer.speed.clamp(-1.0, 1.0));
+        check(device, "speed", result);
+    }
+    if device.config_left_handed_is_available() {
+        let result = device.config_left_handed_set(pointer.left_handed);
+        check(device, "left_handed", result);
+    }
+    if device.config_middle_emulation_is_available() {
+        let result = device.config_middle_emulation_set_enabled(pointer.middle_emulation);
+        check(device, "middle_emulation", result);
+    }
+}
+
+fn configure_touchpad(device: &mut Device, touchpad: &TouchpadConfig) {
+    let result = device.config_tap_set_enabled(touchpad.tap);
+    check(device, "tap", result);
+    let result = device.config_tap_set_drag_enabled(touchpad.tap_drag);
+    check(device, "tap_drag", result);
+    let result = device.config_tap_set_drag_lock_enabled(touchpad.drag_lock);
+    check(device, "drag_lock", result);
+    if device.config_dwt_is_available() {
+        let result = device.config_dwt_set_enabled(touchpad.disable_while_typing);
+        check(device, "disable_while_typing", result);
+    }
+
+    let scroll = match touchpad.scroll_method() {
+        TouchpadScroll::TwoFinger => ScrollMethod::TwoFinger,
+        TouchpadScroll::Edge => ScrollMethod::Edge,
+        TouchpadScroll::None => ScrollMethod::NoScroll,
+    };
+    if device.config_scroll_methods().contains(&scroll) {
+        let result = device.config_scroll_set_method(scroll);
+        check(device, "scroll_method", result);
+    } else {
+        log::warn!("[input] {}: no {:?} scrolling", device.name(), scroll);
+    }
+
+    let click = touchpad.click_method().map(|method| match method {
+        TouchpadClick::ButtonAreas => ClickMethod::ButtonAreas,
+        TouchpadClick::Clickfinger => ClickMethod::Clickfinger,
+    });
+    if let Some(click) = click {
+        if device.config_click_methods().contains(&click) {
+            let result = device.config_click_set_method(click);
+            check(device, "click_method", result);
+        }
+    }
+    log::info!("[input] Configured touchpad {}", device.name());
+}
+
 fn keysym_to_lower(keysym: u32) -> u32 {
     use xkbcommon::xkb::keysyms::*;
     if (KEY_A..=KEY_Z).contains(&keysym) {
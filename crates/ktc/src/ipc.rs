@@ -1,8 +1,19 @@
-use ktc_common::{ipc_socket_path, IpcCommand, IpcEvent, WorkspaceInfo};
+use ktc_common::{
+    encode_pixels, ipc_socket_path, HiddenWindowInfo, IpcCommand, IpcEvent, RendererStats,
+    TabletInfo, WindowTreeInfo, WorkspaceInfo,
+};
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Write};
-use std::os::fd::{AsFd, BorrowedFd};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use crate::config::IpcConfig;
+use crate::state::Canvas;
+
+// a client this far behind on reading events is dropped rather than
+// buffered for without end
+const MAX_OUTBOX: usize = 4 << 20;
 
 pub struct IpcServer {
     listener: UnixListener,
@@ -13,6 +24,65 @@ pub struct IpcServer {
 struct IpcClient {
     stream: UnixStream,
     reader: BufReader<UnixStream>,
+    uid: Option<u32>,
+    exe: Option<PathBuf>,
+    // events not yet taken by the socket; lines are only ever written whole
+    // from here, so a full socket can't leave half a line behind
+    outbox: Vec<u8>,
+}
+
+impl IpcClient {
+    // false once the client is gone or too far behind to keep
+    fn queue(&mut self, msg: &[u8]) -> bool {
+        if self.outbox.len() + msg.len() > MAX_OUTBOX {
+            return false;
+        }
+        self.outbox.extend_from_slice(msg);
+        self.flush()
+    }
+
+    fn flush(&mut self) -> bool {
+        while !self.outbox.is_empty() {
+            match self.stream.write(&self.outbox) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.outbox.drain(..n);
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+// uid and pid of the process on the other end of the socket
+fn peer_credentials(stream: &UnixStream) -> Option<(u32, i32)> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_fd().as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (ret == 0).then_some((cred.uid, cred.pid))
+}
+
+// the serde tag of a command, which is what the allow-list names
+fn command_name(cmd: &IpcCommand) -> String {
+    serde_json::to_value(cmd)
+        .ok()
+        .and_then(|v| v.get("type")?.as_str().map(String::from))
+        .unwrap_or_default()
 }
 
 impl IpcServer {
@@ -20,6 +90,14 @@ impl IpcServer {
         let socket_path = ipc_socket_path();
 
         if socket_path.exists() {
+            // only take over the socket if nothing answers on it any more
+            if UnixStream::connect(&socket_path).is_ok() {
+                return Err(format!(
+                    "another compositor is listening on {}",
+                    socket_path.display()
+                )
+                .into());
+            }
             std::fs::remove_file(&socket_path)?;
         }
 
@@ -59,8 +137,25 @@ impl IpcServer {
                         }
                     });
 
-                    self.clients.insert(id, IpcClient { stream, reader });
-                    log::info!("IPC client {} connected", id);
+                    let cred = peer_credentials(&stream);
+                    let exe = cred
+                        .and_then(|(_, pid)| std::fs::read_link(format!("/proc/{}/exe", pid)).ok());
+                    self.clients.insert(
+                        id,
+                        IpcClient {
+                            stream,
+                            reader,
+                            uid: cred.map(|(uid, _)| uid),
+                            exe,
+                            outbox: Vec::new(),
+                        },
+                    );
+                    match cred {
+                        Some((uid, pid)) => {
+                            log::info!("IPC client {} connected (uid {}, pid {})", id, uid, pid)
+                        }
+                        None => log::info!("IPC client {} connected", id),
+                    }
                 }
                 Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
                 Err(e) => {
@@ -71,7 +166,8 @@ impl IpcServer {
         }
     }
 
-    pub fn poll_commands(&mut self) -> Vec<IpcCommand> {
+    // commands paired with the id of the client that sent them
+    pub fn poll_commands(&mut self, policy: &IpcConfig) -> Vec<(u64, IpcCommand)> {
         let mut commands = Vec::new();
         let mut disconnected = Vec::new();
 
@@ -90,7 +186,20 @@ impl IpcServer {
                             continue;
                         }
                         match serde_json::from_str::<IpcCommand>(line) {
-                            Ok(cmd) => commands.push(cmd),
+                            Ok(cmd) => {
+                                let name = command_name(&cmd);
+                                if policy.allows(client.uid, client.exe.as_deref(), &name) {
+                                    commands.push((id, cmd));
+                                } else {
+                                    log::warn!(
+                                        "[ipc] Rejected '{}' from client {} (uid {:?}, {:?})",
+                                        name,
+                                        id,
+                                        client.uid,
+                                        client.exe
+                                    );
+                                }
+                            }
                             Err(e) => log::warn!("Invalid IPC command from {}: {}", id, e),
                         }
                     }
@@ -113,26 +222,52 @@ impl IpcServer {
     }
 
     pub fn broadcast(&mut self, event: &IpcEvent) {
-        let json = match serde_json::to_string(event) {
-            Ok(j) => j,
-            Err(e) => {
-                log::warn!("Failed to serialize IPC event: {}", e);
-                return;
-            }
+        let Some(msg) = event_line(event) else {
+            return;
         };
-
-        let msg = format!("{}\n", json);
         let mut disconnected = Vec::new();
 
         for (&id, client) in &mut self.clients {
-            if let Err(e) = client.stream.write_all(msg.as_bytes()) {
-                log::warn!("Failed to send to IPC client {}: {}", id, e);
+            if !client.queue(msg.as_bytes()) {
                 disconnected.push(id);
             }
         }
 
         for id in disconnected {
-            self.clients.remove(&id);
+            self.drop_client(id);
+        }
+    }
+
+    // replies meant for the client that asked only
+    fn send_to(&mut self, id: u64, event: &IpcEvent) {
+        let Some(msg) = event_line(event) else {
+            return;
+        };
+        if let Some(client) = self.clients.get_mut(&id) {
+            if !client.queue(msg.as_bytes()) {
+                self.drop_client(id);
+            }
+        }
+    }
+
+    // writes out what clients couldn't take before
+    pub fn flush(&mut self) {
+        let stuck: Vec<u64> = self
+            .clients
+            .iter_mut()
+            .filter_map(|(&id, client)| (!client.flush()).then_some(id))
+            .collect();
+        for id in stuck {
+            self.drop_client(id);
+        }
+    }
+
+    fn drop_client(&mut self, id: u64) {
+        if self.clients.remove(&id).is_some() {
+            log::warn!(
+                "Dropped IPC client {}: disconnected or not reading events",
+                id
+            );
         }
     }
 
@@ -177,12 +312,87 @@ impl IpcServer {
         self.broadcast(&event);
     }
 
+    pub fn notify_hidden_windows(&mut self, windows: Vec<HiddenWindowInfo>) {
+        let event = IpcEvent::HiddenWindows { windows };
+        self.broadcast(&event);
+    }
+
+    pub fn send_tree(&mut self, windows: Vec<WindowTreeInfo>) {
+        let event = IpcEvent::Tree { windows };
+        self.broadcast(&event);
+    }
+
+    pub fn send_renderer_stats(&mut self, stats: RendererStats) {
+        let event = IpcEvent::RendererStats { stats };
+        self.broadcast(&event);
+    }
+
+    pub fn send_tablets(&mut self, tablets: Vec<TabletInfo>) {
+        let event = IpcEvent::Tablets { tablets };
+        self.broadcast(&event);
+    }
+
+    pub fn send_workspace_preview(&mut self, client: u64, workspace: usize, preview: &Canvas) {
+        let event = IpcEvent::WorkspacePreview {
+            workspace,
+            width: preview.width as u32,
+            height: preview.height as u32,
+            pixels: encode_pixels(preview.as_slice()),
+        };
+        self.send_to(client, &event);
+    }
+
+    pub fn notify_bar_visibility(&mut self, visible: bool) {
+        let event = IpcEvent::BarVisibility { visible };
+        self.broadcast(&event);
+    }
+
+    pub fn notify_screenshot(&mut self, path: &std::path::Path) {
+        let event = IpcEvent::Screenshot {
+            path: path.to_string_lossy().into_owned(),
+        };
+        self.broadcast(&event);
+    }
+
+    pub fn notify_recording(&mut self, recording: bool, path: &std::path::Path) {
+        let event = IpcEvent::Recording {
+            recording,
+            path: path.to_string_lossy().into_owned(),
+        };
+        self.broadcast(&event);
+    }
+
+    pub fn notify_keyboard_layout(&mut self, name: String, index: u32) {
+        let event = IpcEvent::KeyboardLayout { name, index };
+        self.broadcast(&event);
+    }
+
+    pub fn notify_mode(&mut self, name: String) {
+        let event = IpcEvent::Mode { name };
+        self.broadcast(&event);
+    }
+
+    pub fn notify_passthrough(&mut self, enabled: bool) {
+        let event = IpcEvent::Passthrough { enabled };
+        self.broadcast(&event);
+    }
+
     #[allow(dead_code)]
     pub fn client_count(&self) -> usize {
         self.clients.len()
     }
 }
 
+fn event_line(event: &IpcEvent) -> Option<String> {
+    match serde_json::to_string(event) {
+        Ok(json) => Some(format!("{}\n", json)),
+        Err(e) => {
+            log::warn!("Failed to serialize IPC event: {}", e);
+            None
+        }
+    }
+}
+
 impl Drop for IpcServer {
     fn drop(&mut self) {
         let socket_path = ipc_socket_path();
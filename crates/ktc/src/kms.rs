@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use drm::control::atomic::AtomicModeReq;
+use drm::control::{
+    connector, crtc, framebuffer, plane, property, AtomicCommitFlags, Device as ControlDevice,
+    Mode, PlaneType, RawResourceHandle, ResourceHandle,
+};
+use drm::ClientCapability;
+
+// Drives one connector and CRTC through atomic commits. Property handles are
+// looked up by name once, every commit names the same few of them.
+pub struct AtomicOutput {
+    connector: connector::Handle,
+    crtc: crtc::Handle,
+    primary: plane::Handle,
+    // cursor and overlay planes that can reach the CRTC; nothing is drawn
+    // on them, they are only switched off at modeset
+    other_planes: Vec<plane::Handle>,
+    // (format, modifier) pairs the primary plane scans out
+    primary_formats: Vec<(u32, u64)>,
+    properties: HashMap<RawResourceHandle, HashMap<String, property::Handle>>,
+    // the mode blob the CRTC is set to, freed once it is replaced
+    mode_blob: Option<u64>,
+    size: (u32, u32),
+    // VRR_ENABLED as sent with every commit
+    vrr: bool,
+}
+
+// name -> (handle, current value) of everything the object exposes
+fn read_properties(
+    card: &impl ControlDevice,
+    object: impl ResourceHandle,
+) -> Result<HashMap<String, (property::Handle, u64)>, String> {
+    let props = card.get_properties(object).map_err(|e| e.to_string())?;
+    Ok(props
+        .iter()
+        .filter_map(|(&handle, &value)| {
+            let info = card.get_property(handle).ok()?;
+            let name = info.name().to_string_lossy().into_owned();
+            Some((name, (handle, value)))
+        })
+        .collect())
+}
+
+impl AtomicOutput {
+    // Fails when the driver has no atomic support; callers stay on
+    // set_crtc/page_flip then
+    pub fn new(
+        card: &impl ControlDevice,
+        connector: connector::Handle,
+        crtc: crtc::Handle,
+    ) -> Result<Self, String> {
+        card.set_client_capability(ClientCapability::UniversalPlanes, true)
+            .map_err(|e| format!("universal planes unsupported: {}", e))?;
+        card.set_client_capability(ClientCapability::Atomic, true)
+            .map_err(|e| format!("atomic modesetting unsupported: {}", e))?;
+
+        let resources = card.resource_handles().map_err(|e| e.to_string())?;
+        let mut properties = HashMap::new();
+        let mut primary = None;
+        let mut primary_formats_blob = None;
+        let mut other_planes = Vec::new();
+        for handle in card.plane_handles().map_err(|e| e.to_string())? {
+            let Ok(info) = card.get_plane(handle) else {
+                continue;
+            };
+            if !resources
+                .filter_crtcs(info.possible_crtcs())
+                .contains(&crtc)
+            {
+                continue;
+            }
+            let props = read_properties(card, handle)?;
+            let Some(&(_, kind)) = props.get("type") else {
+                continue;
+            };
+            // a primary plane already showing this CRTC wins over the others
+            if kind == PlaneType::Primary as u64 && (primary.is_none() || info.crtc() == Some(crtc))
+            {
+                primary = Some(handle);
+                primary_formats_blob = props.get("IN_FORMATS").map(|&(_, blob)| blob);
+            } else if kind == PlaneType::Cursor as u64 || kind == PlaneType::Overlay as u64 {
+                other_planes.push(handle);
+            } else {
+                continue;
+            }
+            properties.insert(handle.into(), strip_values(props));
+        }
+        let primary = primary.ok_or("no primary plane for the CRTC")?;
+        // drivers without modifier support have no IN_FORMATS
+        let primary_formats = primary_formats_blob
+            .filter(|&blob| blob != 0)
+            .and_then(|blob| card.get_property_blob(blob).ok())
+            .map(|data| parse_in_formats(&data))
+            .unwrap_or_default();
+        properties.retain(|&object, _| {
+            object == primary.into() || other_planes.iter().any(|&o| object == o.into())
+        });
+        properties.insert(
+            connector.into(),
+            strip_values(read_properties(card, connector)?),
+        );
+        properties.insert(crtc.into(), strip_values(read_properties(card, crtc)?));
+
+        log::info!(
+            "[kms] Atomic modesetting, {} cursor/overlay plane(s) kept off",
+            other_planes.len()
+        );
+
+        Ok(Self {
+            connector,
+            crtc,
+            primary,
+            other_planes,
+            primary_formats,
+            properties,
+            mode_blob: None,
+            size: (0, 0),
+            vrr: false,
+        })
+    }
+
+    fn has(&self, object: impl ResourceHandle, name: &str) -> bool {
+        self.properties
+            .get(&object.into())
+            .is_some_and(|props| props.contains_key(name))
+    }
+
+    fn set(
+        &self,
+        req: &mut AtomicModeReq,
+        object: impl ResourceHandle,
+        name: &str,
+        value: u64,
+    ) -> Result<(), String> {
+        let handle = self
+            .properties
+            .get(&object.into())
+            .and_then(|props| props.get(name))
+            .ok_or_else(|| format!("no {} property", name))?;
+        req.add_raw_property(object.into(), *handle, value);
+        Ok(())
+    }
+
+    // the whole framebuffer, unscaled, covering the whole CRTC
+    fn show_on_primary(
+        &self,
+        req: &mut AtomicModeReq,
+        fb: framebuffer::Handle,
+        (width, height): (u32, u32),
+    ) -> Result<(), String> {
+        let plane = self.primary;
+        self.set(req, plane, "FB_ID", u32::from(fb) as u64)?;
+        self.set(req, plane, "CRTC_ID", u32::from(self.crtc) as u64)?;
+        // source coordinates are 16.16 fixed point
+        self.set(req, plane, "SRC_X", 0)?;
+        self.set(req, plane, "SRC_Y", 0)?;
+        self.set(req, plane, "SRC_W", (width as u64) << 16)?;
+        self.set(req, plane, "SRC_H", (height as u64) << 16)?;
+        self.set(req, plane, "CRTC_X", 0)?;
+        self.set(req, plane, "CRTC_Y", 0)?;
+        self.set(req, plane, "CRTC_W", width as u64)?;
+        self.set(req, plane, "CRTC_H", height as u64)
+    }
+
+    fn set_vrr_property(&self, req: &mut AtomicModeReq) -> Result<(), String> {
+        if self.has(self.crtc, "VRR_ENABLED") {
+            self.set(req, self.crtc, "VRR_ENABLED", self.vrr as u64)?;
+        }
+        Ok(())
+    }
+
+    // Lights up the CRTC with fb in mode. The cursor and overlay planes are
+    // switched off; whatever a previous client left there would sit on top.
+    pub fn modeset(
+        &mut self,
+        card: &impl ControlDevice,
+        fb: framebuffer::Handle,
+        mode: &Mode,
+    ) -> Result<(), String> {
+        let blob = match card.create_property_blob(mode) {
+            Ok(property::Value::Blob(blob)) => blob,
+            Ok(_) => return Err("mode blob has the wrong type".to_string()),
+            Err(e) => return Err(format!("creating the mode blob failed: {}", e)),
+        };
+        let (width, height) = mode.size();
+        let size = (width as u32, height as u32);
+
+        let mut req = AtomicModeReq::new();
+        let built = (|| {
+            self.set(
+                &mut req,
+                self.connector,
+                "CRTC_ID",
+                u32::from(self.crtc) as u64,
+            )?;
+            self.set(&mut req, self.crtc, "MODE_ID", blob)?;
+            self.set(&mut req, self.crtc, "ACTIVE", 1)?;
+            self.set_vrr_property(&mut req)?;
+            self.show_on_primary(&mut req, fb, size)?;
+            for plane in &self.other_planes {
+                self.set(&mut req, *plane, "FB_ID", 0)?;
+                self.set(&mut req, *plane, "CRTC_ID", 0)?;
+            }
+            Ok::<_, String>(())
+        })();
+        let result = built.and_then(|()| {
+            card.atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req)
+                .map_err(|e| format!("atomic modeset failed: {}", e))
+        });
+
+        match result {
+            Ok(()) => {
+                if let Some(old) = self.mode_blob.replace(blob) {
+                    card.destroy_property_blob(old).ok();
+                }
+                self.size = size;
+                Ok(())
+            }
+            Err(e) => {
+                card.destroy_property_blob(blob).ok();
+                Err(e)
+            }
+        }
+    }
+
+    // Queues fb for the next vblank; the page flip event arrives on the DRM
+    // fd as with page_flip
+    pub fn flip(&self, card: &impl ControlDevice, fb: framebuffer::Handle) -> Result<(), String> {
+        let mut req = AtomicModeReq::new();
+        self.set(&mut req, self.primary, "FB_ID", u32::from(fb) as u64)?;
+        self.set_vrr_property(&mut req)?;
+        card.atomic_commit(
+            AtomicCommitFlags::PAGE_FLIP_EVENT | AtomicCommitFlags::NONBLOCK,
+            req,
+        )
+        .map_err(|e| format!("atomic flip failed: {}", e))
+    }
+
+    // Tells the display which rects (x1, y1, x2, y2) of fb, already on the
+    // primary plane, were drawn into. Drivers that scan out of a shadow copy,
+    // like virtio-gpu or udl, only pick changes up this way. No rects means
+    // all of it.
+    pub fn flush_damage(
+        &self,
+        card: &impl ControlDevice,
+        fb: framebuffer::Handle,
+        rects: &[[i32; 4]],
+    ) -> Result<(), String> {
+        let mut req = AtomicModeReq::new();
+        self.set(&mut req, self.primary, "FB_ID", u32::from(fb) as u64)?;
+        let mut blob = None;
+        if !rects.is_empty() && self.has(self.primary, "FB_DAMAGE_CLIPS") {
+            // an array of drm_mode_rect
+            let mut data: Vec<u8> = rects.iter().flatten().flat_map(|v| v.to_ne_bytes()).collect();
+            let created = drm_ffi::mode::create_property_blob(card.as_fd(), &mut data)
+                .map_err(|e| format!("creating the damage blob failed: {}", e))?;
+            self.set(&mut req, self.primary, "FB_DAMAGE_CLIPS", created.blob_id as u64)?;
+            blob = Some(created.blob_id as u64);
+        }
+        let result = card
+            .atomic_commit(AtomicCommitFlags::NONBLOCK, req)
+            .map_err(|e| format!("atomic damage commit failed: {}", e));
+        // the commit holds its own reference
+        if let Some(blob) = blob {
+            card.destroy_property_blob(blob).ok();
+        }
+        result
+    }
+
+    // Whether fb could replace the primary plane's buffer as it is, without
+    // touching the display
+    pub fn test_flip(&self, card: &impl ControlDevice, fb: framebuffer::Handle) -> bool {
+        let mut req = AtomicModeReq::new();
+        self.show_on_primary(&mut req, fb, self.size).is_ok()
+            && card
+                .atomic_commit(AtomicCommitFlags::TEST_ONLY, req)
+                .is_ok()
+    }
+
+    // Empty when the plane doesn't list them; only implicit modifiers can be
+    // assumed to work then
+    pub fn primary_modifiers(&self, format: u32) -> Vec<u64> {
+        self.primary_formats
+            .iter()
+            .filter(|&&(f, _)| f == format)
+            .map(|&(_, modifier)| modifier)
+            .collect()
+    }
+
+    pub fn has_vrr(&self) -> bool {
+        self.has(self.crtc, "VRR_ENABLED")
+    }
+
+    // sent along with the next commit
+    pub fn set_vrr(&mut self, enabled: bool) {
+        self.vrr = enabled;
+    }
+}
+
+fn strip_values(
+    props: HashMap<String, (property::Handle, u64)>,
+) -> HashMap<String, property::Handle> {
+    props
+        .into_iter()
+        .map(|(name, (handle, _))| (name, handle))
+        .collect()
+}
+
+// Reads a drm_format_modifier_blob: a format list, then entries naming a
+// modifier and a 64-bit mask of the formats (from offset on) it works with
+fn parse_in_formats(blob: &[u8]) -> Vec<(u32, u64)> {
+    let u32_at = |at: usize| {
+        blob.get(at..at + 4)
+            .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let u64_at = |at: usize| Some(u32_at(at)? as u64 | (u32_at(at + 4)? as u64) << 32);
+    let header = (|| Some((u32_at(8)?, u32_at(12)?, u32_at(16)?, u32_at(20)?)))();
+    let Some((count_formats, formats_offset, count_modifiers, modifiers_offset)) = header else {
+        return Vec::new();
+    };
+    let formats: Vec<u32> = (0..count_formats as usize)
+        .map_while(|i| u32_at(formats_offset as usize + i * 4))
+        .collect();
+
+    let mut pairs = Vec::new();
+    for i in 0..count_modifiers as usize {
+        // struct drm_format_modifier is 24 bytes
+        let at = modifiers_offset as usize + i * 24;
+        let (Some(mask), Some(offset), Some(modifier)) =
+            (u64_at(at), u32_at(at + 8), u64_at(at + 16))
+        else {
+            break;
+        };
+        for bit in 0..64 {
+            if mask & (1 << bit) != 0 {
+                if let Some(&format) = formats.get(offset as usize + bit) {
+                    pairs.push((format, modifier));
+                }
+            }
+        }
+    }
+    pairs
+}
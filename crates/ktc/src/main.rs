@@ -1,21 +1,37 @@
 mod config;
+mod coords;
+mod cursor;
 mod input;
 mod ipc;
+mod kms;
 mod logging;
+mod metrics;
 mod protocols;
+mod recorder;
+mod render_thread;
 mod renderer;
+mod safe_mode;
+mod scheduler;
 mod session;
 mod state;
+mod upload;
+mod wallpaper;
 
-use config::Config;
+use config::{Config, RecordingEncoder, RendererBackend, ScreenshotTarget};
 use input::KeyState;
 use protocols::dmabuf::DmaBufGlobal;
 use protocols::layer_shell::LayerShellGlobal;
 use protocols::output_management::OutputManagerGlobal;
+use protocols::tablet::TabletManagerGlobal;
+use protocols::viewporter::ViewporterGlobal;
+use protocols::xdg_activation::XdgActivationGlobal;
 use protocols::xdg_decoration::XdgDecorationGlobal;
-use state::State;
+use state::{BufferDamage, Rectangle, State, TitleLabel};
 use std::sync::Arc;
 use wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1;
+use wayland_protocols::wp::tablet::zv2::server::zwp_tablet_manager_v2::ZwpTabletManagerV2;
+use wayland_protocols::wp::viewporter::server::wp_viewporter::WpViewporter;
+use wayland_protocols::xdg::activation::v1::server::xdg_activation_v1::XdgActivationV1;
 use wayland_protocols::xdg::decoration::zv1::server::zxdg_decoration_manager_v1::ZxdgDecorationManagerV1;
 use wayland_protocols::xdg::shell::server::xdg_wm_base::XdgWmBase;
 use wayland_protocols::xdg::xdg_output::zv1::server::zxdg_output_manager_v1::ZxdgOutputManagerV1;
@@ -94,20 +110,128 @@ fn main() {
 
     logging::FileLogger::init().expect("Failed to initialize logging");
 
-    let config = Config::load();
+    let socket = match bind_socket(requested_socket_name()) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::error!("{}", e);
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let first_run = Config::write_default_if_missing();
+    let crashes = safe_mode::begin_startup();
+    let (config, safe_mode_reason) = match Config::load() {
+        Ok(config) if crashes < safe_mode::CRASH_LIMIT => (config, None),
+        Ok(_) => {
+            let mut config = Config::default();
+            config.display.gpu = false;
+            (
+                config,
+                Some(format!(
+                    "compositor crashed {} times during startup, GPU disabled",
+                    crashes
+                )),
+            )
+        }
+        Err(e) => (Config::default(), Some(format!("config error: {}", e))),
+    };
+
+    if let Some(ref reason) = safe_mode_reason {
+        log::warn!("[safe-mode] Booting into safe mode: {}", reason);
+    }
 
     log::info!("Starting KTC compositor");
-    run(config);
+    run(socket, config, safe_mode_reason, first_run);
+}
+
+// --socket <name> wins over an inherited WAYLAND_DISPLAY; neither means bind_auto
+fn requested_socket_name() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--socket" {
+            return args.next();
+        }
+        if let Some(name) = arg.strip_prefix("--socket=") {
+            return Some(name.to_string());
+        }
+    }
+    std::env::var("WAYLAND_DISPLAY")
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+fn bind_socket(name: Option<String>) -> Result<ListeningSocket, String> {
+    remove_stale_sockets();
+    if let Some(name) = name {
+        return ListeningSocket::bind(&name)
+            .map_err(|e| format!("Failed to bind Wayland socket '{}': {}", name, e));
+    }
+    // like bind_auto, but say why we didn't get wayland-0
+    for i in 0..32 {
+        let name = format!("wayland-{}", i);
+        match ListeningSocket::bind(&name) {
+            Ok(socket) => return Ok(socket),
+            Err(wayland_server::BindError::AlreadyInUse) => {
+                log::warn!("[wayland] {} is held by another compositor", name);
+            }
+            Err(e) => return Err(format!("Failed to create Wayland socket: {}", e)),
+        }
+    }
+    Err("Failed to create Wayland socket: wayland-0 to wayland-31 are all in use".to_string())
+}
+
+// A crashed compositor leaves its wayland-N socket and lock file behind. The
+// lock is an flock, so it is free again once nobody holds it; such sockets
+// (and ones without any lock file) belong to no one and go.
+fn remove_stale_sockets() {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::FileTypeExt;
+
+    let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") else {
+        return;
+    };
+    let Ok(entries) = std::fs::read_dir(&runtime_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(index) = name.strip_prefix("wayland-") else {
+            continue;
+        };
+        if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        let socket_path = entry.path();
+        let lock_path = socket_path.with_extension("lock");
+        let unlocked = match std::fs::File::open(&lock_path) {
+            Ok(lock) => unsafe {
+                libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0
+            },
+            Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+        };
+        // the lock alone can mislead, e.g. a server that never took one; a
+        // socket something still accepts on is in use whatever the lock says
+        let stale = unlocked
+            && entry.file_type().is_ok_and(|t| t.is_socket())
+            && std::os::unix::net::UnixStream::connect(&socket_path).is_err();
+        if stale {
+            log::info!("[wayland] Removing stale socket {}", socket_path.display());
+            let _ = std::fs::remove_file(&socket_path);
+            let _ = std::fs::remove_file(&lock_path);
+        }
+    }
 }
 
-fn setup_wayland(has_gpu: bool) -> (Display<State>, ListeningSocket) {
+fn setup_wayland(has_gpu: bool, socket: ListeningSocket) -> (Display<State>, ListeningSocket) {
     let display = Display::<State>::new().expect("Failed to create display");
     let dh = display.handle();
 
     dh.create_global::<State, WlCompositor, _>(6, ());
     dh.create_global::<State, WlSubcompositor, _>(1, ());
     dh.create_global::<State, XdgWmBase, _>(5, ());
-    dh.create_global::<State, WlSeat, _>(7, ());
+    dh.create_global::<State, WlSeat, _>(8, ());
     dh.create_global::<State, WlOutput, _>(4, ());
     dh.create_global::<State, WlShm, _>(1, ());
     dh.create_global::<State, WlDataDeviceManager, _>(3, ());
@@ -116,14 +240,15 @@ fn setup_wayland(has_gpu: bool) -> (Display<State>, ListeningSocket) {
     dh.create_global::<State, ZwlrOutputManagerV1, _>(4, OutputManagerGlobal);
     dh.create_global::<State, ZwlrLayerShellV1, _>(4, LayerShellGlobal);
     dh.create_global::<State, ZxdgDecorationManagerV1, _>(1, XdgDecorationGlobal);
+    dh.create_global::<State, XdgActivationV1, _>(1, XdgActivationGlobal);
+    dh.create_global::<State, WpViewporter, _>(1, ViewporterGlobal);
+    dh.create_global::<State, ZwpTabletManagerV2, _>(1, TabletManagerGlobal);
 
     if has_gpu {
         dh.create_global::<State, ZwpLinuxDmabufV1, _>(4, DmaBufGlobal);
         log::info!("DMA-BUF protocol enabled (GPU acceleration available)");
     }
 
-    let socket = ListeningSocket::bind_auto("wayland", 0..32).expect("Failed to create socket");
-
     log::info!(
         "Listening on: {}",
         socket.socket_name().unwrap().to_string_lossy()
@@ -132,7 +257,12 @@ fn setup_wayland(has_gpu: bool) -> (Display<State>, ListeningSocket) {
     (display, socket)
 }
 
-fn run(config: Config) {
+fn run(
+    socket: ListeningSocket,
+    config: Config,
+    safe_mode_reason: Option<String>,
+    first_run: Option<std::path::PathBuf>,
+) {
     use input::InputHandler;
     use std::fs::OpenOptions;
 
@@ -167,7 +297,11 @@ fn run(config: Config) {
 
     let preferred_mode = config.display.parse_mode();
     let vsync_enabled = config.display.vsync;
-    let gpu_enabled = config.display.gpu;
+    // auto tries OpenGL ES and falls back to the CPU renderer
+    let gpu_enabled = match config.display.renderer_backend() {
+        RendererBackend::Cpu => false,
+        RendererBackend::Auto | RendererBackend::Gl => config.display.gpu,
+    };
 
     let (gpu_renderer, drm_info) = match drm_device {
         Ok(device) => {
@@ -179,6 +313,7 @@ fn run(config: Config) {
                     device.try_clone().unwrap(),
                     preferred_mode,
                     vsync_enabled,
+                    config.display.scanout_format(),
                 ) {
                     Ok(gpu) => {
                         let (w, h) = gpu.size();
@@ -224,7 +359,7 @@ fn run(config: Config) {
     };
 
     let has_gpu = gpu_renderer.is_some();
-    let (mut display, socket) = setup_wayland(has_gpu);
+    let (mut display, socket) = setup_wayland(has_gpu, socket);
 
     let socket_name = socket
         .socket_name()
@@ -238,7 +373,14 @@ fn run(config: Config) {
         log::debug!("[keybind] Registered action: {:?}", action);
     }
 
-    let input_handler = match InputHandler::new(keybinds) {
+    let input_handler = match InputHandler::new(
+        keybinds,
+        config.keybinds.get_mode_bindings(),
+        config.keybinds.get_gesture_bindings(),
+        config.keybinds.get_pointer_bindings(),
+        &config.keyboard,
+        &config.input,
+    ) {
         Ok(handler) => {
             log::info!("Input handler initialized");
             Some(handler)
@@ -263,8 +405,9 @@ fn run(config: Config) {
     calloop_loop
         .handle()
         .insert_source(
-            calloop::generic::Generic::new(&socket, calloop::Interest::READ, calloop::Mode::Level),
+            calloop::generic::Generic::new(socket, calloop::Interest::READ, calloop::Mode::Level),
             |_, socket, data| {
+                wake(data);
                 if let Some(stream) = socket.accept().ok().flatten() {
                     log::info!("New client connecting to Wayland socket");
                     match data.display.handle().insert_client(stream, Arc::new(())) {
@@ -286,12 +429,14 @@ fn run(config: Config) {
         .insert_source(
             calloop::generic::Generic::new(poll_fd, calloop::Interest::READ, calloop::Mode::Level),
             |_, _, data| {
+                wake(data);
                 if let Err(e) = data.display.dispatch_clients(&mut data.state) {
                     log::warn!("[wayland] dispatch_clients error: {:?}", e);
                 }
                 if let Err(e) = data.display.flush_clients() {
                     log::warn!("[wayland] flush_clients error: {:?}", e);
                 }
+                flip_fullscreen_now(data);
                 Ok(calloop::PostAction::Continue)
             },
         )
@@ -313,6 +458,7 @@ fn run(config: Config) {
                 ),
                 |_, _, data| {
                     data.input_pending = true;
+                    wake(data);
                     Ok(calloop::PostAction::Continue)
                 },
             )
@@ -334,90 +480,14 @@ fn run(config: Config) {
                     calloop::Mode::Level,
                 ),
                 |_, _, data| {
-                    data.vsync_pending = true;
+                    page_flipped(data);
+                    flip_fullscreen_now(data);
                     Ok(calloop::PostAction::Continue)
                 },
             )
             .expect("Failed to insert DRM source");
     }
 
-    let _timer = calloop_loop
-        .handle()
-        .insert_source(
-            calloop::timer::Timer::immediate(),
-            |_deadline, _: &mut (), data| {
-                let frame_start = std::time::Instant::now();
-
-                let input_start = std::time::Instant::now();
-                if data.input_pending {
-                    data.input_pending = false;
-                    process_input(data);
-                }
-                let input_time = input_start.elapsed().as_micros() as u64;
-
-                if data.ipc_pending {
-                    data.ipc_pending = false;
-                    process_ipc(data);
-                }
-
-                if let Some(title) = data.state.pending_title_change.take() {
-                    if let Some(ref mut ipc) = data.ipc_server {
-                        ipc.notify_title_change(title);
-                    }
-                }
-
-                if data.vsync_pending {
-                    data.vsync_pending = false;
-                    if let Some(ref mut gpu) = data.state.gpu_renderer {
-                        gpu.handle_drm_event();
-                    }
-                }
-
-                data.display.dispatch_clients(&mut data.state).ok();
-
-                if data.state.cleanup_dead_windows() {
-                    data.display.flush_clients().ok();
-                }
-
-                let profiler_stats = data.frame_profiler.get_stats(&data.state);
-                let show_profiler = data.state.config.debug.profiler;
-
-                let can_render = data
-                    .state
-                    .gpu_renderer
-                    .as_ref()
-                    .map(|gpu| !gpu.is_flip_pending())
-                    .unwrap_or(true);
-
-                let render_start = std::time::Instant::now();
-                if can_render {
-                    render(
-                        &mut data.state,
-                        &mut data.display,
-                        data.drm_info.as_mut(),
-                        if show_profiler {
-                            Some(&profiler_stats)
-                        } else {
-                            None
-                        },
-                    );
-                }
-                let render_time = render_start.elapsed().as_micros() as u64;
-
-                let total_time = frame_start.elapsed().as_micros() as u64;
-                data.frame_profiler
-                    .record_frame(input_time, render_time, total_time, &data.state);
-
-                let timeout = if data.state.gpu_renderer.is_some() {
-                    std::time::Duration::from_millis(1)
-                } else {
-                    std::time::Duration::from_millis(16)
-                };
-                calloop::timer::TimeoutAction::ToDuration(timeout)
-            },
-        )
-        .expect("Failed to insert timer");
-
     let ipc_server = match ipc::IpcServer::new() {
         Ok(server) => {
             let ipc_fd = server
@@ -435,6 +505,7 @@ fn run(config: Config) {
                     ),
                     |_, _, data| {
                         data.ipc_pending = true;
+                        wake(data);
                         Ok(calloop::PostAction::Continue)
                     },
                 )
@@ -448,6 +519,8 @@ fn run(config: Config) {
         }
     };
 
+    let (screenshot_saved, screenshot_channel) = calloop::channel::channel();
+    let layout_memory = config.keyboard.layout_memory();
     let mut loop_data = LoopData {
         display,
         state: State::new(config),
@@ -456,16 +529,55 @@ fn run(config: Config) {
         ipc_server,
         socket_name,
         input_pending: false,
-        vsync_pending: false,
         ipc_pending: false,
         frame_profiler: FrameProfiler::new(),
+        startup_time: Some(std::time::Instant::now()),
+        layout_memory,
+        layout_focus: (None, 0),
+        keyboard_layout: None,
+        loop_handle: calloop_loop.handle(),
+        tick_timer: None,
+        scheduler: scheduler::FrameScheduler::new(),
+        metrics: None,
+        screenshot_saved,
     };
+    wake(&mut loop_data);
 
     loop_data.state.gpu_renderer = gpu_renderer;
+    loop_data.state.safe_mode = safe_mode_reason;
+    if let Some(ref handler) = loop_data.input_handler {
+        if let Some(modifiers) = handler.modifiers() {
+            loop_data.state.keyboard_modifiers = modifiers;
+        }
+        loop_data.state.seat_capabilities = handler.capabilities();
+        let dh = loop_data.display.handle();
+        for tablet in handler.tablets() {
+            loop_data
+                .state
+                .handle_tablet_input(&input::TabletInput::Added(tablet), &dh);
+        }
+    }
+    if let Some(ref path) = first_run {
+        loop_data.state.help_overlay = Some(first_run_help(path));
+    }
+
+    let metrics_config = loop_data.state.config.metrics.clone();
+    if metrics_config.enabled {
+        match metrics::MetricsServer::start(&metrics_config.listen) {
+            Ok(server) => loop_data.metrics = Some(server),
+            Err(e) => log::warn!(
+                "[metrics] Failed to listen on {}: {}",
+                metrics_config.listen,
+                e
+            ),
+        }
+    }
 
     if let Some(ref gpu) = loop_data.state.gpu_renderer {
         let (w, h) = gpu.size();
         let (phys_w, phys_h) = gpu.physical_size();
+        let modes = gpu.available_modes();
+        let vrr_capable = gpu.vrr_capable();
         use state::OutputConfig;
         let output_id = loop_data
             .state
@@ -483,6 +595,10 @@ fn run(config: Config) {
                 make: Some("GPU".to_string()),
                 model: Some("OpenGL".to_string()),
                 physical_size,
+                scale: Some(loop_data.state.config.display.output_scale()),
+                transform: Some(loop_data.state.config.display.output_transform()),
+                modes: Some(modes),
+                vrr_capable: Some(vrr_capable),
                 ..Default::default()
             },
         );
@@ -507,6 +623,9 @@ fn run(config: Config) {
                 model: Some(drm.name.clone()),
                 physical_size: Some((drm.physical_width as i32, drm.physical_height as i32)),
                 refresh: Some(drm.refresh),
+                scale: Some(loop_data.state.config.display.output_scale()),
+                transform: Some(loop_data.state.config.display.output_transform()),
+                modes: Some(drm.modes.clone()),
                 ..Default::default()
             },
         );
@@ -523,14 +642,105 @@ fn run(config: Config) {
             .add_output("headless".to_string(), 1366, 768);
     }
 
+    let (wallpaper_sender, wallpaper_channel) = calloop::channel::channel();
+    calloop_loop
+        .handle()
+        .insert_source(wallpaper_channel, |event, _, data| {
+            if let calloop::channel::Event::Msg(loaded) = event {
+                data.state.wallpaper_loaded(loaded);
+                wake(data);
+            }
+        })
+        .expect("Failed to insert wallpaper source");
+    loop_data.state.wallpaper.set_sender(wallpaper_sender);
+    loop_data.state.refresh_wallpaper(false);
+
+    calloop_loop
+        .handle()
+        .insert_source(screenshot_channel, |event, _, data| {
+            if let calloop::channel::Event::Msg(path) = event {
+                if let Some(ref mut ipc) = data.ipc_server {
+                    ipc.notify_screenshot(&path);
+                }
+            }
+        })
+        .expect("Failed to insert screenshot source");
+
+    if let Some(ref mut gpu) = loop_data.state.gpu_renderer {
+        let (ping, frame_done) = calloop::ping::make_ping().expect("Failed to create ping");
+        calloop_loop
+            .handle()
+            .insert_source(frame_done, |_, _, data| {
+                let presented = data
+                    .state
+                    .gpu_renderer
+                    .as_mut()
+                    .is_some_and(|gpu| gpu.poll_frame());
+                if presented {
+                    redraw_lost_frame(&mut data.state);
+                    schedule_next_tick(data);
+                }
+            })
+            .expect("Failed to insert render thread source");
+        if let Err(e) = gpu.start_render_thread(ping) {
+            log::warn!("[gpu] Compositing on the main thread: {}", e);
+        }
+    }
+
     log::info!("Compositor running. Press Ctrl+Alt+Q to exit.");
 
     spawn_ktcbar(&loop_data.socket_name);
 
+    if first_run.is_some() {
+        match find_terminal() {
+            Some(terminal) => {
+                log::info!("First run, launching {}", terminal);
+                spawn_command(&terminal, &loop_data.socket_name, None);
+            }
+            None => log::warn!("First run, but no terminal emulator found in PATH"),
+        }
+    }
+
     while session::is_running() {
+        // signals don't interrupt calloop, so an idle loop still polls the
+        // shutdown flag, just rarely
         calloop_loop
-            .dispatch(Some(std::time::Duration::from_millis(16)), &mut loop_data)
+            .dispatch(Some(IDLE_POLL), &mut loop_data)
             .expect("Event loop error");
+
+        // the night light schedule and ping timeouts move on even while
+        // nothing else happens
+        if loop_data.state.update_color() | loop_data.state.check_unresponsive() {
+            wake(&mut loop_data);
+        }
+        // picks up output resizes and rotations
+        loop_data.state.refresh_wallpaper(false);
+        update_metrics(&mut loop_data);
+        reap_recordings(&mut loop_data.state, loop_data.ipc_server.as_mut());
+        // events a client's full socket couldn't take earlier
+        if let Some(ref mut ipc) = loop_data.ipc_server {
+            ipc.flush();
+        }
+    }
+
+    if loop_data.startup_time.is_some() {
+        safe_mode::startup_succeeded();
+    }
+
+    // ffmpeg only writes a playable file once its input ends; a writer
+    // still waiting for a FIFO reader is given up on
+    stop_recording(&mut loop_data.state);
+    let deadline = std::time::Instant::now() + RECORDING_FINISH_TIMEOUT;
+    while !loop_data.state.finishing_recordings.is_empty() && std::time::Instant::now() < deadline {
+        reap_recordings(&mut loop_data.state, loop_data.ipc_server.as_mut());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    reap_recordings(&mut loop_data.state, loop_data.ipc_server.as_mut());
+    for recording in &loop_data.state.finishing_recordings {
+        log::warn!(
+            "[recording] Gave up waiting for {}",
+            recording.path().display()
+        );
     }
 
     log::info!("Main loop exited, cleaning up...");
@@ -551,6 +761,17 @@ fn process_input(data: &mut LoopData) {
         return;
     }
 
+    if (!frame.keys.is_empty() || !frame.actions.is_empty())
+        && data.state.help_overlay.take().is_some()
+    {
+        data.state.damage_tracker.mark_full_damage();
+    }
+
+    let mut reloaded = false;
+    // applied once the frame lets go of the input handler
+    let mut layout_switch = None;
+    let mut mode_switch = None;
+    let mut passthrough_switch = None;
     for action in &frame.actions {
         match action {
             Action::Exit => {
@@ -558,14 +779,32 @@ fn process_input(data: &mut LoopData) {
                 return;
             }
 
-            Action::Reload => {
-                let new_config = Config::load();
-                data.state.config = new_config;
-                log::info!("Configuration reloaded");
-            }
+            Action::Reload => match Config::load() {
+                Ok(new_config) => {
+                    data.state.config = new_config;
+                    data.state.reload_workspace_config();
+                    let cursor = &data.state.config.cursor;
+                    let (theme, size) = (cursor.theme_name(), cursor.base_size());
+                    data.state.set_cursor_theme(&theme, size);
+                    data.state.wallpaper.clear_overrides();
+                    data.state.refresh_wallpaper(true);
+                    reloaded = true;
+                    if data.state.safe_mode.take().is_some() {
+                        log::info!("[safe-mode] Leaving safe mode after config reload");
+                        data.state.damage_tracker.mark_full_damage();
+                    }
+                    log::info!("Configuration reloaded");
+                }
+                Err(e) => {
+                    log::warn!("Keeping current configuration: {}", e);
+                }
+            },
 
             Action::Exec(cmd) | Action::ExecSpawn(cmd) => {
-                spawn_command(cmd, &data.socket_name);
+                let token = data.state.new_activation_token(true);
+                if let Some(pid) = spawn_command(cmd, &data.socket_name, token.as_deref()) {
+                    data.state.note_launch(pid, token.unwrap_or_default());
+                }
             }
 
             Action::Close => {
@@ -617,14 +856,7 @@ fn process_input(data: &mut LoopData) {
 
             Action::Move(direction) | Action::Swap(direction) => {
                 let old_focus = data.state.focused_window;
-                match direction {
-                    Direction::Next | Direction::Right | Direction::Down => {
-                        data.state.swap_window_next();
-                    }
-                    Direction::Prev | Direction::Left | Direction::Up => {
-                        data.state.swap_window_prev();
-                    }
-                }
+                data.state.move_window_direction(direction.clone());
                 if data.state.focused_window != old_focus {
                     if let Some(ref mut ipc) = data.ipc_server {
                         let focused_title = data
@@ -671,6 +903,47 @@ fn process_input(data: &mut LoopData) {
                 }
             }
 
+            Action::Bar(toggle) => {
+                let hidden = match toggle {
+                    ToggleState::Toggle => !data.state.bar_hidden,
+                    ToggleState::On => false,
+                    ToggleState::Off => true,
+                };
+                data.state.set_bar_hidden(hidden);
+                if let Some(ref mut ipc) = data.ipc_server {
+                    ipc.notify_bar_visibility(!hidden);
+                }
+                data.display.flush_clients().ok();
+            }
+
+            Action::Screenshot(target) => match target {
+                ScreenshotTarget::Output => {
+                    let rect = output_rect(&data.state);
+                    data.state.request_screenshot(rect);
+                }
+                ScreenshotTarget::Window => {
+                    if let Some(id) = data.state.focused_window {
+                        // windows without a CPU copy are taken from the screen
+                        match data.state.capture_window(id) {
+                            Some((width, height, pixels)) => write_screenshot(
+                                &data.state,
+                                &data.screenshot_saved,
+                                width,
+                                height,
+                                pixels,
+                            ),
+                            None => {
+                                let window = data.state.windows.iter().find(|w| w.id == id);
+                                if let Some(geometry) = window.map(|w| w.geometry) {
+                                    data.state.request_screenshot(geometry);
+                                }
+                            }
+                        }
+                    }
+                }
+                ScreenshotTarget::Region => data.state.begin_region_select(),
+            },
+
             Action::Resize { direction, amount } => {
                 if let Some(focused_id) = data.state.focused_window {
                     data.state
@@ -701,84 +974,209 @@ fn process_input(data: &mut LoopData) {
                 }
             }
 
-            Action::MoveToWorkspace(target) => {
+            Action::MoveToWorkspace(target) | Action::MoveToWorkspaceSilent(target) => {
+                let follow = matches!(action, Action::MoveToWorkspace(_));
                 if let Some(focused_id) = data.state.focused_window {
-                    let workspace = resolve_workspace_target(target, &data.state);
-                    if let Some(ws) = workspace {
-                        data.state.move_window_to_workspace(focused_id, ws);
-                        data.state.switch_workspace(ws);
-                        if let Some(ref mut ipc) = data.ipc_server {
-                            let workspaces = get_workspace_info(&data.state);
-                            ipc.notify_workspace_change(workspaces, data.state.active_workspace);
-                        }
+                    if let Some(ws) = resolve_workspace_target(target, &data.state) {
+                        move_window_to_workspace(
+                            &mut data.state,
+                            data.ipc_server.as_mut(),
+                            focused_id,
+                            ws,
+                            follow,
+                        );
+                    }
+                }
+                data.display.flush_clients().ok();
+            }
+
+            Action::MoveToScratchpad | Action::Scratchpad => {
+                let old_focus = data.state.focused_window;
+                if matches!(action, Action::Scratchpad) {
+                    data.state.toggle_scratchpad();
+                } else if let Some(focused_id) = data.state.focused_window {
+                    data.state.move_to_scratchpad(focused_id);
+                }
+                if let Some(ref mut ipc) = data.ipc_server {
+                    let workspaces = get_workspace_info(&data.state);
+                    ipc.notify_workspace_change(workspaces, data.state.active_workspace);
+                    if data.state.focused_window != old_focus {
+                        let focused_title = data
+                            .state
+                            .focused_window
+                            .and_then(|id| data.state.windows.iter().find(|w| w.id == id))
+                            .map(|w| w.title.clone());
+                        ipc.notify_focus_change(focused_title);
+                    }
+                }
+                data.display.flush_clients().ok();
+            }
+
+            Action::Minimize | Action::Restore => {
+                let old_focus = data.state.focused_window;
+                if matches!(action, Action::Restore) {
+                    data.state.restore_last_minimized();
+                } else if let Some(focused_id) = data.state.focused_window {
+                    data.state.minimize_window(focused_id);
+                }
+                if data.state.focused_window != old_focus {
+                    if let Some(ref mut ipc) = data.ipc_server {
+                        let focused_title = data
+                            .state
+                            .focused_window
+                            .and_then(|id| data.state.windows.iter().find(|w| w.id == id))
+                            .map(|w| w.title.clone());
+                        ipc.notify_focus_change(focused_title);
                     }
                 }
                 data.display.flush_clients().ok();
             }
 
-            Action::MoveToWorkspaceSilent(target) => {
+            Action::Raise | Action::Lower => {
+                if let Some(focused_id) = data.state.focused_window {
+                    if matches!(action, Action::Raise) {
+                        data.state.raise_window(focused_id);
+                    } else {
+                        data.state.lower_window(focused_id);
+                    }
+                }
+            }
+
+            Action::InsertMarker => {
+                data.state.toggle_insert_marker();
+            }
+
+            Action::Gap(change) => data.state.set_gap(change),
+
+            Action::Opacity(opacity) => {
+                if let Some(focused_id) = data.state.focused_window {
+                    data.state.set_opacity(focused_id, *opacity);
+                }
+            }
+
+            Action::Mark(mark) => {
                 if let Some(focused_id) = data.state.focused_window {
-                    let workspace = resolve_workspace_target(target, &data.state);
-                    if let Some(ws) = workspace {
-                        data.state.move_window_to_workspace(focused_id, ws);
+                    data.state.set_mark(focused_id, mark);
+                }
+            }
+
+            Action::FocusIndex(index) => {
+                let old_workspace = data.state.active_workspace;
+                if data.state.focus_index(*index) {
+                    notify_jump(&data.state, data.ipc_server.as_mut(), old_workspace);
+                    data.display.flush_clients().ok();
+                }
+            }
+
+            Action::JumpToMark(mark) => {
+                let old_workspace = data.state.active_workspace;
+                if data.state.jump_to_mark(mark) {
+                    notify_jump(&data.state, data.ipc_server.as_mut(), old_workspace);
+                    data.display.flush_clients().ok();
+                }
+            }
+
+            Action::SplitHorizontal | Action::SplitVertical | Action::SplitToggle => {
+                log::debug!("Split actions not yet implemented");
+            }
+
+            Action::LayoutNext | Action::LayoutPrev | Action::LayoutSet(_) => {
+                let layout = match action {
+                    Action::LayoutNext => Some(data.state.layout().next()),
+                    Action::LayoutPrev => Some(data.state.layout().prev()),
+                    Action::LayoutSet(name) => {
+                        let layout = state::Layout::parse(name);
+                        if layout.is_none() {
+                            log::warn!("Unknown layout: {}", name);
+                        }
+                        layout
+                    }
+                    _ => None,
+                };
+                if let Some(layout) = layout {
+                    if layout != data.state.layout() {
+                        data.state.set_layout(layout);
                         if let Some(ref mut ipc) = data.ipc_server {
                             let workspaces = get_workspace_info(&data.state);
                             ipc.notify_workspace_change(workspaces, data.state.active_workspace);
                         }
                     }
                 }
-                data.display.flush_clients().ok();
             }
 
-            Action::SplitHorizontal | Action::SplitVertical | Action::SplitToggle => {
-                log::debug!("Split actions not yet implemented");
+            Action::CursorTheme(theme) => {
+                let size = data.state.cursor_theme.size;
+                data.state.set_cursor_theme(theme, size);
             }
 
-            Action::LayoutNext | Action::LayoutPrev | Action::LayoutSet(_) => {
-                log::debug!("Layout actions not yet implemented");
+            Action::KeyboardLayout(target) => {
+                layout_switch = Some(target.clone());
+            }
+
+            Action::Drag(_) => {
+                log::debug!("[keybind] Drag actions only work on mouse bindings");
+            }
+
+            Action::Mode(name) => {
+                mode_switch = Some(name.clone());
             }
 
-            Action::CursorTheme(_theme) => {
-                log::debug!("Cursor theme change not yet implemented");
+            Action::Passthrough(toggle) => {
+                passthrough_switch = Some(toggle.clone());
             }
         }
     }
 
     if frame.pointer.has_motion {
-        let (screen_w, screen_h) = data.state.screen_size();
-
         if let (Some(x), Some(y)) = (frame.pointer.absolute_x, frame.pointer.absolute_y) {
+            let (x, y) = data.state.absolute_to_layout(x, y);
             data.state.handle_pointer_motion(x, y);
         } else if frame.pointer.accumulated_dx != 0.0 || frame.pointer.accumulated_dy != 0.0 {
-            let new_x = (data.state.pointer_x + frame.pointer.accumulated_dx)
-                .clamp(0.0, screen_w as f64 - 1.0);
-            let new_y = (data.state.pointer_y + frame.pointer.accumulated_dy)
-                .clamp(0.0, screen_h as f64 - 1.0);
+            let (new_x, new_y) = data.state.clamp_to_layout(
+                data.state.pointer_x + frame.pointer.accumulated_dx,
+                data.state.pointer_y + frame.pointer.accumulated_dy,
+            );
             data.state.handle_pointer_motion(new_x, new_y);
         }
     }
 
     for button in &frame.buttons {
-        let old_focus = data.state.focused_window;
-        data.state
-            .handle_pointer_button(button.button, button.pressed);
-        if button.pressed && data.state.focused_window != old_focus {
-            if let Some(ref mut ipc) = data.ipc_server {
-                let focused_title = data
-                    .state
-                    .focused_window
-                    .and_then(|id| data.state.windows.iter().find(|w| w.id == id))
-                    .map(|w| w.title.clone());
-                ipc.notify_focus_change(focused_title);
+        pointer_button(&mut data.state, data.ipc_server.as_mut(), button);
+    }
+
+    for touch in &frame.touch {
+        match touch {
+            input::TouchInput::Motion(x, y) => {
+                let (x, y) = data.state.touch_to_layout(*x, *y);
+                data.state.handle_pointer_motion(x, y);
             }
+            input::TouchInput::Button(button) => {
+                pointer_button(&mut data.state, data.ipc_server.as_mut(), button);
+            }
+        }
+    }
+
+    if let Some(capabilities) = frame.capabilities {
+        data.state.set_seat_capabilities(capabilities);
+    }
+
+    if !frame.tablet.is_empty() {
+        let dh = data.display.handle();
+        for event in &frame.tablet {
+            tablet_input(&mut data.state, data.ipc_server.as_mut(), &dh, event);
         }
     }
 
     if frame.pointer.has_scroll {
-        data.state.handle_pointer_axis(
-            frame.pointer.scroll_horizontal,
-            frame.pointer.scroll_vertical,
-        );
+        data.state.handle_pointer_axis(&frame.pointer);
+    }
+
+    if frame
+        .keys
+        .iter()
+        .any(|key| matches!(key.state, KeyState::Pressed))
+    {
+        data.state.hide_cursor_for_typing();
     }
 
     let focused_keyboards = data.state.get_focused_keyboards();
@@ -802,21 +1200,232 @@ fn process_input(data: &mut LoopData) {
             }
         }
     }
+    // kept for keyboards entering a surface later, focused ones or not
+    if let Some(key) = frame.keys.last() {
+        data.state.keyboard_modifiers = (
+            key.mods_depressed,
+            key.mods_latched,
+            key.mods_locked,
+            key.group,
+        );
+    }
+
+    if reloaded {
+        data.state.send_repeat_info();
+        if let Some(handler) = data.input_handler.as_mut() {
+            handler.set_input_config(&data.state.config.input);
+            handler.set_repeat(&data.state.config.keyboard);
+            if handler.set_keymap(&data.state.config.keyboard) {
+                data.state.send_keymap(handler.modifiers());
+            }
+        }
+        // the layout names may have changed even if the group didn't
+        data.keyboard_layout = None;
+        data.layout_memory = data.state.config.keyboard.layout_memory();
+    }
+
+    if let Some(target) = layout_switch {
+        switch_keyboard_layout(data, &target);
+    }
+
+    if let Some(name) = mode_switch {
+        switch_mode(data, &name);
+    }
+
+    if let Some(toggle) = passthrough_switch {
+        switch_passthrough(data, &toggle);
+    }
 
     data.display.flush_clients().ok();
 }
 
-fn resolve_workspace_target(
-    target: &crate::config::WorkspaceTarget,
-    state: &State,
-) -> Option<usize> {
-    use crate::config::WorkspaceTarget;
+fn pointer_button(
+    state: &mut State,
+    ipc: Option<&mut ipc::IpcServer>,
+    button: &input::ButtonEvent,
+) {
+    let old_focus = state.focused_window;
+    match button.grab {
+        Some(grab) => state.begin_pointer_grab(grab, button.button),
+        None => state.handle_pointer_button(button.button, button.pressed),
+    }
+    if button.pressed && state.focused_window != old_focus {
+        if let Some(ipc) = ipc {
+            let focused_title = state
+                .focused_window
+                .and_then(|id| state.windows.iter().find(|w| w.id == id))
+                .map(|w| w.title.clone());
+            ipc.notify_focus_change(focused_title);
+        }
+    }
+}
 
-    match target {
-        WorkspaceTarget::Number(n) => Some(*n),
-        WorkspaceTarget::Next => {
+// a tool's tip focuses the window it lands on, as a click does
+fn tablet_input(
+    state: &mut State,
+    ipc: Option<&mut ipc::IpcServer>,
+    dh: &wayland_server::DisplayHandle,
+    event: &input::TabletInput,
+) {
+    let old_focus = state.focused_window;
+    state.handle_tablet_input(event, dh);
+    if state.focused_window != old_focus {
+        if let Some(ipc) = ipc {
+            let focused_title = state
+                .focused_window
+                .and_then(|id| state.windows.iter().find(|w| w.id == id))
+                .map(|w| w.title.clone());
+            ipc.notify_focus_change(focused_title);
+        }
+    }
+}
+
+fn switch_keyboard_layout(data: &mut LoopData, target: &crate::config::KeyboardLayoutTarget) {
+    use crate::config::KeyboardLayoutTarget;
+
+    let Some(handler) = data.input_handler.as_mut() else {
+        return;
+    };
+    let count = handler.layout_count();
+    let current = handler.layout_group();
+    let group = match target {
+        KeyboardLayoutTarget::Next => Some((current + 1) % count),
+        KeyboardLayoutTarget::Prev => Some((current + count - 1) % count),
+        KeyboardLayoutTarget::Index(index) => Some(*index).filter(|&index| index < count),
+        KeyboardLayoutTarget::Name(name) => data
+            .state
+            .config
+            .keyboard
+            .layout_names()
+            .iter()
+            .position(|layout| layout.eq_ignore_ascii_case(name))
+            .map(|index| index as u32),
+    };
+    let Some(group) = group else {
+        log::warn!("[input] No keyboard layout {:?} in the keymap", target);
+        return;
+    };
+    if group == current {
+        return;
+    }
+    let Some(modifiers) = handler.set_layout_group(group) else {
+        return;
+    };
+    log::debug!("[input] Switched to layout group {}", group);
+    data.state.send_modifiers(modifiers);
+}
+
+fn switch_mode(data: &mut LoopData, name: &str) {
+    let Some(handler) = data.input_handler.as_mut() else {
+        return;
+    };
+    let previous = handler.mode().to_string();
+    if !handler.set_mode(name) {
+        log::warn!("[keybind] No mode {:?} in [[keybinds.mode]]", name);
+        return;
+    }
+    if handler.mode() == previous {
+        return;
+    }
+    log::debug!("[keybind] Entered mode {:?}", handler.mode());
+    if let Some(ref mut ipc) = data.ipc_server {
+        ipc.notify_mode(handler.mode().to_string());
+    }
+}
+
+fn switch_passthrough(data: &mut LoopData, toggle: &config::ToggleState) {
+    let Some(handler) = data.input_handler.as_mut() else {
+        return;
+    };
+    if !handler.set_passthrough(toggle) {
+        return;
+    }
+    let enabled = handler.passthrough();
+    log::info!(
+        "[keybind] Passthrough {}",
+        if enabled { "on" } else { "off" }
+    );
+    if let Some(ref mut ipc) = data.ipc_server {
+        ipc.notify_passthrough(enabled);
+    }
+}
+
+// Tells IPC clients about layout changes from any source: the switch action,
+// layout memory and grp: toggles in keyboard.options
+fn notify_keyboard_layout(data: &mut LoopData) {
+    let Some(group) = data.input_handler.as_ref().map(|h| h.layout_group()) else {
+        return;
+    };
+    if data.keyboard_layout == Some(group) {
+        return;
+    }
+    data.keyboard_layout = Some(group);
+    if let Some(ref mut ipc) = data.ipc_server {
+        ipc.notify_keyboard_layout(data.state.config.keyboard.layout_name(group), group);
+    }
+}
+
+// Saves the xkb layout group of whatever lost focus and restores the one
+// remembered for whatever gained it.
+fn sync_layout_memory(data: &mut LoopData) {
+    use state::LayoutMemory;
+
+    let focus = (data.state.focused_window, data.state.active_workspace);
+    let previous = std::mem::replace(&mut data.layout_focus, focus);
+    if previous == focus {
+        return;
+    }
+
+    let memory = data.layout_memory;
+    let Some(handler) = data.input_handler.as_mut() else {
+        return;
+    };
+    let current = handler.layout_group();
+
+    let group = match memory {
+        LayoutMemory::Global => None,
+        LayoutMemory::Window => {
+            if let Some(window) = previous.0.and_then(|id| data.state.get_window_mut(id)) {
+                window.layout_group = current;
+            }
+            focus
+                .0
+                .and_then(|id| data.state.get_window_mut(id))
+                .map(|w| w.layout_group)
+        }
+        LayoutMemory::Workspace => {
+            if previous.1 == focus.1 {
+                return;
+            }
+            if let Some(workspace) = data.state.workspace_mut(previous.1) {
+                workspace.layout_group = current;
+            }
+            data.state.workspace(focus.1).map(|ws| ws.layout_group)
+        }
+    };
+
+    let Some(group) = group.filter(|&group| group != current) else {
+        return;
+    };
+    let Some(modifiers) = handler.set_layout_group(group) else {
+        return;
+    };
+    log::debug!("[input] Restored layout group {}", group);
+    data.state.send_modifiers(modifiers);
+    data.display.flush_clients().ok();
+}
+
+fn resolve_workspace_target(
+    target: &crate::config::WorkspaceTarget,
+    state: &State,
+) -> Option<usize> {
+    use crate::config::WorkspaceTarget;
+
+    match target {
+        WorkspaceTarget::Number(n) => Some(*n),
+        WorkspaceTarget::Next => {
             let next = state.active_workspace + 1;
-            if next <= state.workspace_count {
+            if next <= state.workspace_count() {
                 Some(next)
             } else {
                 Some(1)
@@ -826,13 +1435,13 @@ fn resolve_workspace_target(
             if state.active_workspace > 1 {
                 Some(state.active_workspace - 1)
             } else {
-                Some(state.workspace_count)
+                Some(state.workspace_count())
             }
         }
         WorkspaceTarget::First => Some(1),
-        WorkspaceTarget::Last => Some(state.workspace_count),
+        WorkspaceTarget::Last => Some(state.workspace_count()),
         WorkspaceTarget::Empty => {
-            for ws in 1..=state.workspace_count {
+            for ws in 1..=state.workspace_count() {
                 let has_windows = state.windows.iter().any(|w| w.workspace == ws && w.mapped);
                 if !has_windows {
                     return Some(ws);
@@ -843,7 +1452,496 @@ fn resolve_workspace_target(
     }
 }
 
-fn spawn_command(cmd: &str, socket_name: &str) {
+fn color_to_rgba(color: u32) -> [f32; 4] {
+    [
+        ((color >> 16) & 0xFF) as f32 / 255.0,
+        ((color >> 8) & 0xFF) as f32 / 255.0,
+        (color & 0xFF) as f32 / 255.0,
+        1.0,
+    ]
+}
+
+const IDLE_POLL: std::time::Duration = std::time::Duration::from_secs(1);
+// how long shutdown waits for recordings to be written out
+const RECORDING_FINISH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+// upload threads and screencopy fences have no fd to wake the loop with
+const WORKER_POLL: std::time::Duration = std::time::Duration::from_millis(1);
+
+fn frame_tick(data: &mut LoopData) {
+    let frame_start = std::time::Instant::now();
+
+    let input_start = std::time::Instant::now();
+    let deadline_due = data
+        .input_handler
+        .as_ref()
+        .and_then(|handler| handler.next_deadline())
+        .is_some_and(|next| next <= input_start);
+    if data.input_pending || deadline_due {
+        data.input_pending = false;
+        process_input(data);
+    }
+    let input_time = input_start.elapsed().as_micros() as u64;
+
+    if data.ipc_pending {
+        data.ipc_pending = false;
+        process_ipc(data);
+    }
+
+    sync_layout_memory(data);
+    notify_keyboard_layout(data);
+
+    if let Some(title) = data.state.pending_title_change.take() {
+        if let Some(ref mut ipc) = data.ipc_server {
+            ipc.notify_title_change(title);
+        }
+    }
+
+    if std::mem::take(&mut data.state.hidden_windows_changed) {
+        if let Some(ref mut ipc) = data.ipc_server {
+            ipc.notify_hidden_windows(get_hidden_windows(&data.state));
+            let workspaces = get_workspace_info(&data.state);
+            ipc.notify_workspace_change(workspaces, data.state.active_workspace);
+        }
+    }
+
+    data.display.dispatch_clients(&mut data.state).ok();
+
+    if data.state.cleanup_dead_windows() {
+        data.display.flush_clients().ok();
+    }
+
+    data.state.refresh_pointer_focus();
+    data.state.update_dmabuf_feedback();
+    // the frame timer is armed for the idle timeout, see schedule_next_tick;
+    // hiding only damages where the cursor was
+    data.state.hide_idle_cursor();
+    data.state.update_cursor();
+    data.state.update_window_visibility();
+    data.state.sweep_textures();
+
+    if data
+        .startup_time
+        .is_some_and(|t| t.elapsed() >= safe_mode::STARTUP_GRACE)
+    {
+        data.startup_time = None;
+        safe_mode::startup_succeeded();
+    }
+
+    let profiler_stats = data.frame_profiler.get_stats(&data.state);
+    let show_profiler = data.state.config.debug.profiler;
+
+    let frame_wanted = wants_frame(&data.state);
+
+    let can_render = data.scheduler.is_due()
+        && data
+            .state
+            .gpu_renderer
+            .as_ref()
+            .map(|gpu| !gpu.is_flip_pending())
+            .unwrap_or(true);
+
+    let screenshot_ready = can_render && data.state.pending_screenshot.is_some();
+
+    let render_start = std::time::Instant::now();
+    if can_render {
+        data.state.fullscreen_commit = false;
+        render(
+            &mut data.state,
+            &mut data.display,
+            data.drm_info.as_mut(),
+            if show_profiler {
+                Some(&profiler_stats)
+            } else {
+                None
+            },
+        );
+    }
+    let render_time = render_start.elapsed().as_micros() as u64;
+
+    if screenshot_ready {
+        if let Some(rect) = data.state.pending_screenshot.take() {
+            save_screenshot(data, rect);
+        }
+    }
+    if can_render {
+        record_frame(data);
+    }
+
+    let drew = can_render && frame_wanted;
+    let flip_pending = data
+        .state
+        .gpu_renderer
+        .as_ref()
+        .is_some_and(|gpu| gpu.is_flip_pending());
+    if drew && !flip_pending {
+        data.scheduler
+            .drawn_without_flip(output_frame_period(&data.state));
+    }
+
+    if drew {
+        let total_time = frame_start.elapsed().as_micros() as u64;
+        data.frame_profiler
+            .record_frame(input_time, render_time, total_time, &data.state);
+    }
+
+    schedule_next_tick(data);
+}
+
+// Something only a render can deliver: new contents, frame callbacks,
+// screencopy frames, a screenshot or the profiler overlay
+fn wants_frame(state: &State) -> bool {
+    state.config.debug.profiler
+        || state.needs_relayout
+        || state.pending_screenshot.is_some()
+        || state.damage_tracker.has_damage()
+        || state.has_due_frame_callbacks()
+        || state.screencopy_needs_render()
+}
+
+fn output_frame_period(state: &State) -> std::time::Duration {
+    let refresh = match state.gpu_renderer {
+        Some(ref gpu) => gpu.refresh(),
+        None => state.primary_output().map(|o| o.refresh).unwrap_or(0),
+    };
+    scheduler::frame_period(refresh)
+}
+
+// Arms the frame timer for whatever still needs a tick. With nothing left
+// the loop sleeps until a client, input, IPC or a page flip wakes it.
+fn schedule_next_tick(data: &mut LoopData) {
+    let now = std::time::Instant::now();
+    let flip_pending = data
+        .state
+        .gpu_renderer
+        .as_ref()
+        .is_some_and(|gpu| gpu.is_flip_pending());
+
+    // while a flip is pending the next frame is scheduled when it lands, and
+    // a recording waits for it too
+    let mut next = (!flip_pending && wants_frame(&data.state)).then(|| data.scheduler.next_frame());
+    let mut at = |deadline: std::time::Instant| {
+        next = Some(next.map_or(deadline, |n| n.min(deadline)));
+    };
+
+    let workers_busy = data
+        .state
+        .gpu_renderer
+        .as_ref()
+        .is_some_and(|gpu| gpu.has_pending_uploads())
+        || data
+            .state
+            .screencopy_frames
+            .iter()
+            .any(|f| f.fence.is_some());
+    if workers_busy {
+        at(now + WORKER_POLL);
+    }
+    if let Some(started) = data.startup_time {
+        at(started + safe_mode::STARTUP_GRACE);
+    }
+    if let Some(recorder) = data.state.recorder.as_ref().filter(|_| !flip_pending) {
+        at(recorder.next_frame_at());
+    }
+    if let Some(deadline) = data.input_handler.as_ref().and_then(|h| h.next_deadline()) {
+        at(deadline);
+    }
+    if let Some(deadline) = data.state.cursor_idle_deadline() {
+        at(deadline);
+    }
+
+    if let Some(deadline) = next {
+        schedule_tick(data, deadline);
+    }
+}
+
+// the renderer dropped a frame to swap its buffers, so all of it is drawn again
+fn redraw_lost_frame(state: &mut State) {
+    if state
+        .gpu_renderer
+        .as_mut()
+        .is_some_and(|gpu| gpu.take_lost_frame())
+    {
+        state.damage_tracker.mark_full_damage();
+    }
+}
+
+fn page_flipped(data: &mut LoopData) {
+    let flipped = data
+        .state
+        .gpu_renderer
+        .as_mut()
+        .is_some_and(|gpu| gpu.handle_drm_event());
+    if flipped {
+        let render_ahead = data.state.config.display.render_ahead();
+        data.scheduler
+            .flipped(output_frame_period(&data.state), render_ahead);
+    }
+    schedule_next_tick(data);
+}
+
+fn save_screenshot(data: &mut LoopData, rect: Rectangle) {
+    let Some((width, height, pixels)) = data.state.read_layout_region(rect) else {
+        return;
+    };
+    write_screenshot(&data.state, &data.screenshot_saved, width, height, pixels);
+}
+
+// Encoding a large screenshot takes long enough to drop frames, so it and
+// the write happen on a thread; IPC clients hear about the file once it
+// exists.
+fn write_screenshot(
+    state: &State,
+    saved: &calloop::channel::Sender<std::path::PathBuf>,
+    width: i32,
+    height: i32,
+    pixels: Vec<u32>,
+) {
+    let directory = state.config.screenshot.directory();
+    let stamp = chrono::Local::now()
+        .format("ktc-%Y-%m-%d-%H%M%S-%3f")
+        .to_string();
+    let saved = saved.clone();
+
+    let spawned = std::thread::Builder::new()
+        .name("ktc-screenshot".to_string())
+        .spawn(move || {
+            let png = ktc_common::encode_png(width as u32, height as u32, &pixels);
+            let result = std::fs::create_dir_all(&directory)
+                .and_then(|_| create_screenshot_file(&directory, &stamp));
+            let result = result.and_then(|(path, mut file)| {
+                use std::io::Write;
+                file.write_all(&png).map(|_| path)
+            });
+            match result {
+                Ok(path) => {
+                    log::info!(
+                        "[screenshot] Saved {}x{} to {}",
+                        width,
+                        height,
+                        path.display()
+                    );
+                    let _ = saved.send(path);
+                }
+                Err(e) => log::warn!(
+                    "[screenshot] Failed to write {} in {}: {}",
+                    stamp,
+                    directory.display(),
+                    e
+                ),
+            }
+        });
+    if let Err(e) = spawned {
+        log::warn!("[screenshot] Failed to spawn encoder thread: {}", e);
+    }
+}
+
+// Screenshots in the same millisecond get a counter rather than replacing
+// each other.
+fn create_screenshot_file(
+    directory: &std::path::Path,
+    stamp: &str,
+) -> std::io::Result<(std::path::PathBuf, std::fs::File)> {
+    let mut suffix = 0;
+    loop {
+        let name = match suffix {
+            0 => format!("{}.png", stamp),
+            n => format!("{}-{}.png", stamp, n),
+        };
+        let path = directory.join(name);
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => suffix += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn output_rect(state: &State) -> Rectangle {
+    let (width, height) = state.screen_size();
+    Rectangle {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    }
+}
+
+fn start_recording(
+    state: &mut State,
+    ipc: Option<&mut ipc::IpcServer>,
+    path: Option<String>,
+    encoder: Option<String>,
+    framerate: Option<u32>,
+) {
+    if let Some(ref recorder) = state.recorder {
+        log::warn!(
+            "[recording] Already recording to {}",
+            recorder.path().display()
+        );
+        return;
+    }
+
+    let config = &state.config.recording;
+    let encoder = match encoder {
+        Some(name) => match RecordingEncoder::parse(&name) {
+            Some(encoder) => encoder,
+            None => {
+                log::warn!("[recording] Unknown encoder {:?}", name);
+                return;
+            }
+        },
+        None => config.encoder(),
+    };
+    let framerate = framerate.unwrap_or(config.framerate);
+    let ffmpeg_args = config.ffmpeg_args.clone();
+    let path = match path {
+        Some(path) => std::path::PathBuf::from(path),
+        None => {
+            let directory = config.directory();
+            if let Err(e) = std::fs::create_dir_all(&directory) {
+                log::warn!(
+                    "[recording] Failed to create {}: {}",
+                    directory.display(),
+                    e
+                );
+                return;
+            }
+            let name = chrono::Local::now().format("ktc-%Y-%m-%d-%H%M%S");
+            directory.join(format!("{}.{}", name, encoder.extension()))
+        }
+    };
+
+    // the first frame also tells the encoder the size it will get
+    let rect = output_rect(state);
+    let Some((width, height, pixels)) = state.read_layout_region(rect) else {
+        return;
+    };
+    let mut recorder =
+        match recorder::Recorder::start(encoder, path, width, height, framerate, &ffmpeg_args) {
+            Ok(recorder) => recorder,
+            Err(e) => {
+                log::warn!("[recording] Failed to start: {}", e);
+                return;
+            }
+        };
+    recorder.push_frame(pixels);
+
+    log::info!(
+        "[recording] Recording {}x{} at {} fps to {}",
+        width,
+        height,
+        framerate,
+        recorder.path().display()
+    );
+    if let Some(ipc) = ipc {
+        ipc.notify_recording(true, recorder.path());
+    }
+    state.recorder = Some(recorder);
+}
+
+// The writer finishes the file on its own thread; reap_recordings reports
+// it once it has
+fn stop_recording(state: &mut State) {
+    if let Some(recorder) = state.recorder.take() {
+        state.finishing_recordings.push(recorder.stop());
+    }
+}
+
+fn reap_recordings(state: &mut State, mut ipc: Option<&mut ipc::IpcServer>) {
+    let (done, finishing) = std::mem::take(&mut state.finishing_recordings)
+        .into_iter()
+        .partition(|recording| recording.is_done());
+    state.finishing_recordings = finishing;
+    for recording in done {
+        let path = recording.path().to_path_buf();
+        match recording.finish() {
+            Ok(()) => log::info!("[recording] Saved {}", path.display()),
+            Err(e) => log::warn!("[recording] {} is incomplete: {}", path.display(), e),
+        }
+        if let Some(ipc) = ipc.as_deref_mut() {
+            ipc.notify_recording(false, &path);
+        }
+    }
+}
+
+// reads back the frame just drawn, through the same path screencopy uses
+fn record_frame(data: &mut LoopData) {
+    let Some(ref recorder) = data.state.recorder else {
+        return;
+    };
+    if recorder.failed() {
+        stop_recording(&mut data.state);
+        return;
+    }
+    if !recorder.frame_due() {
+        return;
+    }
+    let size = recorder.size();
+
+    let rect = output_rect(&data.state);
+    let Some((width, height, pixels)) = data.state.read_layout_region(rect) else {
+        return;
+    };
+    if (width, height) != size {
+        log::warn!("[recording] Output size changed, stopping");
+        stop_recording(&mut data.state);
+        return;
+    }
+    if let Some(ref mut recorder) = data.state.recorder {
+        recorder.push_frame(pixels);
+    }
+}
+
+// With VRR the display waits for us, so a fullscreen frame is drawn as soon
+// as it is committed (or the previous flip lands) rather than on the next tick.
+fn flip_fullscreen_now(data: &mut LoopData) {
+    if !data.state.fullscreen_commit || !data.state.vrr_active() {
+        return;
+    }
+    let flip_pending = data
+        .state
+        .gpu_renderer
+        .as_ref()
+        .is_some_and(|gpu| gpu.is_flip_pending());
+    if flip_pending {
+        return;
+    }
+    data.state.fullscreen_commit = false;
+    data.scheduler.allow_now();
+    frame_tick(data);
+}
+
+// Runs a tick as soon as the loop gets to it. Every event source calls this;
+// whether the tick also draws is up to the frame scheduler.
+fn wake(data: &mut LoopData) {
+    schedule_tick(data, std::time::Instant::now());
+}
+
+// A single frame timer is armed at a time, at the earliest deadline asked for
+fn schedule_tick(data: &mut LoopData, deadline: std::time::Instant) {
+    if let Some((token, armed)) = data.tick_timer {
+        if armed <= deadline {
+            return;
+        }
+        data.loop_handle.remove(token);
+        data.tick_timer = None;
+    }
+    let timer = calloop::timer::Timer::from_deadline(deadline);
+    match data.loop_handle.insert_source(timer, |_, _, data| {
+        data.tick_timer = None;
+        frame_tick(data);
+        calloop::timer::TimeoutAction::Drop
+    }) {
+        Ok(token) => data.tick_timer = Some((token, deadline)),
+        Err(e) => log::error!("[schedule] Failed to arm frame timer: {}", e),
+    }
+}
+
+fn spawn_command(cmd: &str, socket_name: &str, activation_token: Option<&str>) -> Option<u32> {
     let xdg_runtime_dir = std::env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| "/tmp".to_string());
 
     let parts: Vec<&str> = cmd.split_whitespace().collect();
@@ -853,8 +1951,14 @@ fn spawn_command(cmd: &str, socket_name: &str) {
         command
             .args(args)
             .env("WAYLAND_DISPLAY", socket_name)
+            .env_remove("WAYLAND_SOCKET")
             .env("XDG_RUNTIME_DIR", &xdg_runtime_dir)
             .stderr(std::process::Stdio::null());
+        if let Some(token) = activation_token {
+            command
+                .env("XDG_ACTIVATION_TOKEN", token)
+                .env("DESKTOP_STARTUP_ID", token);
+        }
 
         unsafe {
             command.pre_exec(|| {
@@ -867,12 +1971,14 @@ fn spawn_command(cmd: &str, socket_name: &str) {
             Ok(child) => {
                 session::register_child(child.id());
                 log::info!("Launched: {}", cmd);
+                return Some(child.id());
             }
             Err(e) => {
                 log::error!("Failed to launch '{}': {}", cmd, e);
             }
         }
     }
+    None
 }
 
 fn render(
@@ -899,31 +2005,40 @@ fn render_gpu(
         state.relayout_windows();
         display.flush_clients().ok();
     }
+    state.update_window_visibility();
+
+    damage_uploaded_textures(state);
 
-    let has_pending_screencopy = !state.screencopy_frames.is_empty();
-    let has_frame_callbacks = !state.frame_callbacks.is_empty();
+    // frames copied with damage only need a render once there is some
+    let has_pending_screencopy = state.screencopy_needs_render();
+    let has_screencopy = !state.screencopy_frames.is_empty();
+    let has_frame_callbacks = state.has_due_frame_callbacks();
     let has_damage = state.damage_tracker.has_damage();
     let has_profiler = profiler_stats.is_some();
 
-    if !has_damage && !has_pending_screencopy && !has_frame_callbacks && !has_profiler {
+    if !has_damage && !has_screencopy && !has_frame_callbacks && !has_profiler {
         return;
     }
 
     let needs_render = has_damage || has_pending_screencopy || has_profiler;
+    let scanned_out = needs_render && !has_screencopy && !has_profiler && try_direct_scanout(state);
 
-    if needs_render {
+    if scanned_out && has_damage {
+        state.accumulate_screencopy_damage();
+        state.damage_tracker.clear();
+    }
+    state.prepare_screencopy();
+
+    if needs_render && !scanned_out {
         let bg_dark = state.config.background_dark();
         let title_focused = state.config.title_focused();
         let title_unfocused = state.config.title_unfocused();
         let title_bar_height = state.config.title_bar_height();
         let focused_id = state.focused_window;
-        let active_workspace = state.active_workspace;
         let windows_needing_update: Vec<_> = state
             .windows
             .iter()
-            .filter(|w| {
-                w.mapped && w.buffer.is_some() && w.needs_redraw && w.workspace == active_workspace
-            })
+            .filter(|w| w.visible && w.needs_redraw)
             .map(|w| w.id)
             .collect();
 
@@ -931,10 +2046,11 @@ fn render_gpu(
             state.update_window_pixel_cache(*id);
         }
 
-        let window_render_info: Vec<_> = state
-            .windows
-            .iter()
-            .filter(|w| w.mapped && w.buffer.is_some() && w.workspace == active_workspace)
+        let mut stacked: Vec<_> = state.windows.iter().filter(|w| w.visible).collect();
+        stacked.sort_by_key(|w| state.stack_key(w));
+
+        let window_render_info: Vec<_> = stacked
+            .into_iter()
             .map(|w| {
                 let buffer_id = w.buffer.as_ref().map(|b| b.id());
                 let is_shm = buffer_id
@@ -949,14 +2065,26 @@ fn render_gpu(
                     w.cache_stride,
                     is_shm,
                     buffer_id,
-                    w.fullscreen,
+                    w.decoration_height(title_bar_height) == 0,
                 )
             })
             .collect();
 
+        let tabs = state.tabs();
+        let border_unfocused = state.config.border_unfocused();
+
         let gpu = state.gpu_renderer.as_mut().unwrap();
 
         gpu.begin_frame();
+        gpu.set_blur_strength(state.config.blur.passes, state.config.blur.offset);
+        // the profiler redraws every frame anyway
+        if state.damage_tracker.is_full_damage() || has_profiler {
+            gpu.damage_all();
+        } else {
+            for rect in state.damage_tracker.damage_regions() {
+                gpu.add_damage(rect.x, rect.y, rect.width, rect.height);
+            }
+        }
 
         let (width, height) = gpu.size();
         let bg_color = [
@@ -966,14 +2094,48 @@ fn render_gpu(
             1.0,
         ];
         gpu.draw_rect(0, 0, width as i32, height as i32, bg_color);
+        gpu.draw_wallpaper(state.wallpaper.current.as_ref());
 
-        for (id, geom, cache_w, cache_h, cache_stride, is_shm, buffer_id, is_fullscreen) in
+        for (id, geom, cache_w, cache_h, cache_stride, is_shm, buffer_id, undecorated) in
             &window_render_info
         {
             let is_focused = focused_id == Some(*id);
+            // decorations stay opaque; only the content takes the window's opacity
+            state.gpu_renderer.as_mut().unwrap().set_alpha(1.0);
 
-            let (content_y, effective_title_height) = if *is_fullscreen {
+            let (content_y, effective_title_height) = if *undecorated {
                 (geom.y, 0)
+            } else if tabs.iter().any(|(tab_id, _)| tab_id == id) {
+                let gpu = state.gpu_renderer.as_mut().unwrap();
+                for (tab_id, rect) in &tabs {
+                    let color = if tab_id == id {
+                        title_focused
+                    } else {
+                        title_unfocused
+                    };
+                    gpu.draw_rect(
+                        rect.x,
+                        rect.y,
+                        rect.width,
+                        rect.height,
+                        color_to_rgba(color),
+                    );
+                    gpu.draw_rect(
+                        rect.x + rect.width - 1,
+                        rect.y,
+                        1,
+                        rect.height,
+                        color_to_rgba(border_unfocused),
+                    );
+
+                    let title = state.windows.iter().find(|w| w.id == *tab_id);
+                    if let Some(label) = title.and_then(|w| TitleLabel::fit(&w.title, *rect)) {
+                        let color = state.config.title_text_color(tab_id == id);
+                        gpu.draw_title(*tab_id, &label.text, label.x, label.y, label.scale, color);
+                    }
+                }
+
+                (geom.y + title_bar_height, title_bar_height)
             } else {
                 let title_color = if is_focused {
                     title_focused
@@ -987,18 +2149,52 @@ fn render_gpu(
                     1.0,
                 ];
 
+                let window = state.windows.iter().find(|w| w.id == *id);
+                let buttons = window
+                    .map(|w| w.title_buttons(title_bar_height))
+                    .unwrap_or_default();
+                let label = window.and_then(|w| w.title_label(title_bar_height));
+
                 let gpu = state.gpu_renderer.as_mut().unwrap();
                 gpu.draw_rect(geom.x, geom.y, geom.width, title_bar_height, title_rgba);
+                for (button, rect) in buttons {
+                    let color = color_to_rgba(state.config.title_button_color(button));
+                    gpu.draw_rect(rect.x, rect.y, rect.width, rect.height, color);
+                }
+                if let Some(label) = label {
+                    let color = state.config.title_text_color(is_focused);
+                    gpu.draw_title(*id, &label.text, label.x, label.y, label.scale, color);
+                }
 
                 (geom.y + title_bar_height, title_bar_height)
             };
 
-            let _ = effective_title_height;
+            if state.windows.iter().any(|w| w.id == *id && w.blur) {
+                let gpu = state.gpu_renderer.as_mut().unwrap();
+                gpu.draw_blur(
+                    geom.x,
+                    content_y,
+                    geom.width,
+                    geom.height - effective_title_height,
+                );
+            }
+
+            if let Some(window) = state.windows.iter().find(|w| w.id == *id) {
+                let alpha = state.window_opacity(window);
+                state.gpu_renderer.as_mut().unwrap().set_alpha(alpha);
+            }
 
             if *is_shm {
-                let win = match state.windows.iter().find(|w| w.id == *id) {
-                    Some(w) if !w.pixel_cache.is_empty() && *cache_w > 0 && *cache_h > 0 => w,
-                    _ => continue,
+                let has_pixels = state.windows.iter().any(|w| {
+                    w.id == *id && !w.pixel_cache.is_empty() && *cache_w > 0 && *cache_h > 0
+                });
+                if !has_pixels {
+                    continue;
+                }
+                // taken only once there is something to upload it with
+                let damage = state.take_window_damage(*id, windows_needing_update.contains(id));
+                let Some(win) = state.windows.iter().find(|w| w.id == *id) else {
+                    continue;
                 };
 
                 let data: &[u8] = unsafe {
@@ -1007,22 +2203,32 @@ fn render_gpu(
                         win.pixel_cache.len() * 4,
                     )
                 };
+                let (draw_w, draw_h) = win.content_size(*cache_w, *cache_h);
+                let src = win.buffer_source();
+                // opaque formats leave alpha undefined, so it mustn't be
+                // blended with
+                let format = match (win.cache_deep_color, win.cache_opaque) {
+                    (true, _) => drm::buffer::DrmFourcc::Abgr2101010,
+                    (false, true) => drm::buffer::DrmFourcc::Xrgb8888,
+                    (false, false) => drm::buffer::DrmFourcc::Argb8888,
+                };
                 let gpu = state.gpu_renderer.as_mut().unwrap();
-                let texture = gpu.upload_shm_texture(
+                let texture = gpu.update_shm_texture(
                     *id,
                     *cache_w as u32,
                     *cache_h as u32,
-                    *cache_stride as u32,
+                    (*cache_stride * 4) as u32,
+                    format,
                     data,
+                    &damage,
                 );
 
                 let gpu = state.gpu_renderer.as_mut().unwrap();
-                gpu.draw_texture(texture, geom.x, content_y, *cache_w as i32, *cache_h as i32);
+                gpu.draw_texture_region(texture, src, geom.x, content_y, draw_w, draw_h);
             } else if let Some(buf_id) = buffer_id {
                 log::debug!("[render] Window {} has non-SHM buffer {:?}, checking dmabuf_buffers (count={})", 
                     id, buf_id, state.dmabuf_buffers.len());
                 if let Some(dmabuf_info) = state.dmabuf_buffers.get(buf_id) {
-                    let buffer_cache_id = buf_id.protocol_id() as u64;
                     let width = dmabuf_info.width;
                     let height = dmabuf_info.height;
                     let format = dmabuf_info.format;
@@ -1035,58 +2241,117 @@ fn render_gpu(
                         format,
                         planes.len()
                     );
-                    let gpu = state.gpu_renderer.as_mut().unwrap();
-                    let texture_result = if planes.is_empty() {
-                        use std::os::fd::AsRawFd;
-                        let raw_fd = dmabuf_info.fd.as_raw_fd();
-                        let modifier = dmabuf_info.modifier;
-                        let stride = dmabuf_info.stride;
-                        let offset = dmabuf_info.offset;
-                        gpu.import_dmabuf_texture(
-                            buffer_cache_id,
-                            raw_fd,
-                            width as u32,
-                            height as u32,
-                            format,
-                            stride,
-                            offset,
-                            modifier,
-                        )
+                    let Some(win) = state.windows.iter().find(|w| w.id == *id) else {
+                        continue;
+                    };
+                    let transform = win.buffer_transform;
+                    let (draw_width, draw_height) = if transform.swaps_axes() {
+                        win.content_size(height as usize, width as usize)
                     } else {
-                        gpu.import_dmabuf_texture_multiplane(
-                            buffer_cache_id,
+                        win.content_size(width as usize, height as usize)
+                    };
+                    let src = win.buffer_source();
+                    let gpu = state.gpu_renderer.as_mut().unwrap();
+                    if renderer::is_yuv_format(format) {
+                        if gpu.import_yuv_dmabuf(
+                            buf_id,
                             width as u32,
                             height as u32,
                             format,
                             planes,
-                        )
-                    };
-
-                    if let Some(texture) = texture_result {
-                        let is_external = gpu.is_dmabuf_external(buffer_cache_id);
-                        let draw_width = geom.width;
-                        let draw_height =
-                            geom.height - if *is_fullscreen { 0 } else { title_bar_height };
-                        log::debug!(
-                            "[render] Drawing DMA-BUF texture for window {}: {}x{} at ({},{}) external={}",
-                            id, draw_width, draw_height, geom.x, content_y, is_external
-                        );
-                        gpu.draw_dmabuf_texture(
-                            texture,
-                            geom.x,
-                            content_y,
-                            draw_width,
-                            draw_height,
-                            is_external,
-                        );
+                        ) {
+                            gpu.draw_yuv_texture(
+                                buf_id,
+                                geom.x,
+                                content_y,
+                                draw_width,
+                                draw_height,
+                                transform,
+                            );
+                        } else {
+                            log::warn!("[render] YUV DMA-BUF import failed for window {}", id);
+                        }
                     } else {
-                        log::warn!("[render] DMA-BUF texture import failed for window {}", id);
+                        let texture_result = if planes.is_empty() {
+                            use std::os::fd::AsRawFd;
+                            let raw_fd = dmabuf_info.fd.as_raw_fd();
+                            let modifier = dmabuf_info.modifier;
+                            let stride = dmabuf_info.stride;
+                            let offset = dmabuf_info.offset;
+                            gpu.import_dmabuf_texture(
+                                buf_id,
+                                raw_fd,
+                                width as u32,
+                                height as u32,
+                                format,
+                                stride,
+                                offset,
+                                modifier,
+                            )
+                        } else {
+                            gpu.import_dmabuf_texture_multiplane(
+                                buf_id,
+                                width as u32,
+                                height as u32,
+                                format,
+                                planes,
+                            )
+                        };
+
+                        if let Some(texture) = texture_result {
+                            let is_external = gpu.is_dmabuf_external(buf_id);
+                            log::debug!(
+                                "[render] Drawing DMA-BUF texture for window {}: {}x{} at ({},{}) external={}",
+                                id, draw_width, draw_height, geom.x, content_y, is_external
+                            );
+                            gpu.draw_dmabuf_texture(
+                                texture,
+                                geom.x,
+                                content_y,
+                                draw_width,
+                                draw_height,
+                                src,
+                                transform,
+                                is_external,
+                            );
+                        } else {
+                            log::warn!("[render] DMA-BUF texture import failed for window {}", id);
+                        }
                     }
                 } else {
                     log::debug!("[render] Window {} buffer {:?} not in dmabuf_buffers (count={}), not in shm buffers either", 
                         id, buf_id, state.dmabuf_buffers.len());
                 }
             }
+
+            if let Some(rect) = state.unresponsive_rect(*id) {
+                let mut color = color_to_rgba(state.config.unresponsive_tint());
+                color[3] = 0.5;
+                let gpu = state.gpu_renderer.as_mut().unwrap();
+                gpu.set_alpha(1.0);
+                gpu.draw_rect(rect.x, rect.y, rect.width, rect.height, color);
+            }
+        }
+        state.gpu_renderer.as_mut().unwrap().set_alpha(1.0);
+
+        if let Some(rect) = state.insert_marker_rect() {
+            let color = color_to_rgba(state.config.border_focused());
+            let gpu = state.gpu_renderer.as_mut().unwrap();
+            gpu.draw_rect(rect.x, rect.y, rect.width, rect.height, color);
+        }
+
+        if let Some(rect) = state.split_preview_rect() {
+            let mut color = color_to_rgba(state.config.border_focused());
+            color[3] = 0.5;
+            let gpu = state.gpu_renderer.as_mut().unwrap();
+            gpu.draw_rect(rect.x, rect.y, rect.width, rect.height, color);
+        }
+
+        if let Some(rect) = state.region_select_rect() {
+            let mut color = color_to_rgba(state.config.border_focused());
+            color[3] = 0.3;
+            let gpu = state.gpu_renderer.as_mut().unwrap();
+            gpu.draw_rect(rect.x, rect.y, rect.width, rect.height, color);
         }
 
         let layer_surfaces_needing_update: Vec<_> = state
@@ -1113,11 +2378,12 @@ fn render_gpu(
                     ls.cache_height,
                     ls.cache_stride,
                     buffer_id,
+                    state.config.blur.blurs_layer(&ls.namespace),
                 )
             })
             .collect();
 
-        for (id, geom, cache_w, cache_h, cache_stride, _buffer_id) in &layer_render_info {
+        for (id, geom, cache_w, cache_h, cache_stride, _buffer_id, blur) in &layer_render_info {
             let ls = match state.layer_surfaces.iter().find(|ls| ls.id == *id) {
                 Some(ls) if !ls.pixel_cache.is_empty() && *cache_w > 0 && *cache_h > 0 => ls,
                 _ => continue,
@@ -1129,19 +2395,39 @@ fn render_gpu(
                     ls.pixel_cache.len() * 4,
                 )
             };
+            let format = if ls.cache_opaque {
+                drm::buffer::DrmFourcc::Xrgb8888
+            } else {
+                drm::buffer::DrmFourcc::Argb8888
+            };
 
-            let texture_id = *id + 1_000_000;
+            let texture_id = *id + renderer::LAYER_TEXTURE_OFFSET;
+            let damage = if layer_surfaces_needing_update.contains(id) {
+                BufferDamage::full()
+            } else {
+                BufferDamage::default()
+            };
             let gpu = state.gpu_renderer.as_mut().unwrap();
-            let texture = gpu.upload_shm_texture(
+            let texture = gpu.update_shm_texture(
                 texture_id,
                 *cache_w as u32,
                 *cache_h as u32,
-                *cache_stride as u32,
+                (*cache_stride * 4) as u32,
+                format,
                 data,
+                &damage,
             );
 
+            let Some(ls) = state.layer_surfaces.iter().find(|ls| ls.id == *id) else {
+                continue;
+            };
+            let (draw_w, draw_h) = ls.content_size(*cache_w, *cache_h);
+            let src = ls.buffer_source();
             let gpu = state.gpu_renderer.as_mut().unwrap();
-            gpu.draw_texture(texture, geom.x, geom.y, *cache_w as i32, *cache_h as i32);
+            if *blur {
+                gpu.draw_blur(geom.x, geom.y, draw_w, draw_h);
+            }
+            gpu.draw_texture_region(texture, src, geom.x, geom.y, draw_w, draw_h);
         }
 
         for id in &layer_surfaces_needing_update {
@@ -1161,13 +2447,30 @@ fn render_gpu(
             gpu.draw_profiler(stats);
         }
 
+        if let Some(ref reason) = state.safe_mode {
+            let gpu = state.gpu_renderer.as_mut().unwrap();
+            gpu.draw_banner(&safe_mode::banner_lines(reason));
+        }
+
+        if let Some(ref lines) = state.help_overlay {
+            let gpu = state.gpu_renderer.as_mut().unwrap();
+            gpu.draw_help(lines);
+        }
+
         if state.cursor_visible {
             let gpu = state.gpu_renderer.as_mut().unwrap();
-            gpu.draw_cursor(state.cursor_x, state.cursor_y);
+            gpu.draw_cursor(
+                state.cursor_x,
+                state.cursor_y,
+                &state.cursor,
+                state.cursor_frame,
+            );
         }
 
         let gpu = state.gpu_renderer.as_mut().unwrap();
         gpu.end_frame();
+        redraw_lost_frame(state);
+        state.rotate_scanout_buffer(None);
 
         for id in &windows_needing_update {
             if let Some(win) = state.windows.iter_mut().find(|w| w.id == *id) {
@@ -1183,12 +2486,13 @@ fn render_gpu(
         }
 
         if has_damage {
+            state.accumulate_screencopy_damage();
             state.damage_tracker.clear();
         }
     }
 
-    if has_pending_screencopy {
-        state.process_screencopy_frames(true);
+    if has_screencopy {
+        state.process_screencopy_frames();
     }
 
     if has_frame_callbacks {
@@ -1197,8 +2501,13 @@ fn render_gpu(
             .unwrap()
             .as_millis() as u32;
 
-        log::debug!("[render] Sending {} frame callbacks at time {}", state.frame_callbacks.len(), time);
-        for callback in state.frame_callbacks.drain(..) {
+        let callbacks = state.take_due_frame_callbacks();
+        log::debug!(
+            "[render] Sending {} frame callbacks at time {}",
+            callbacks.len(),
+            time
+        );
+        for callback in callbacks {
             callback.done(time);
         }
     }
@@ -1212,77 +2521,110 @@ fn render_cpu(state: &mut State, display: &mut Display<State>, drm_info: Option<
         state.relayout_windows();
         display.flush_clients().ok();
     }
+    state.update_window_visibility();
 
-    let has_pending_screencopy = !state.screencopy_frames.is_empty();
-    let has_frame_callbacks = !state.frame_callbacks.is_empty();
+    let has_pending_screencopy = state.screencopy_needs_render();
+    let has_screencopy = !state.screencopy_frames.is_empty();
+    let has_frame_callbacks = state.has_due_frame_callbacks();
     let has_damage = state.damage_tracker.has_damage();
     let cursor_only = state.damage_tracker.is_cursor_only() && !has_pending_screencopy;
 
-    if !has_damage && !has_pending_screencopy && !has_frame_callbacks {
+    if !has_damage && !has_screencopy && !has_frame_callbacks {
         return;
     }
 
     let needs_render = has_damage || has_pending_screencopy;
+    let mut copy_regions = Vec::new();
 
     if needs_render {
+        copy_regions.push(state.canvas.cursor_rect());
         if cursor_only {
             state.canvas.restore_cursor();
             if state.cursor_visible {
-                state.canvas.draw_cursor(state.cursor_x, state.cursor_y);
+                let image = state.cursor.frame(state.cursor_frame);
+                state.canvas.draw_cursor(
+                    state.cursor_x - image.xhot as i32,
+                    state.cursor_y - image.yhot as i32,
+                    image.width,
+                    image.height,
+                    &image.pixels,
+                );
             }
         } else {
             state.canvas.restore_cursor();
 
             let focused_id = state.focused_window;
-            let active_workspace = state.active_workspace;
 
-            let windows_to_render: Vec<_> = state
+            let title_bar_height = state.config.title_bar_height();
+
+            let mut windows_to_render: Vec<_> = state
                 .windows
                 .iter()
-                .filter(|w| w.mapped && w.buffer.is_some() && w.workspace == active_workspace)
-                .map(|w| (w.id, w.fullscreen))
+                .filter(|w| w.visible)
+                .map(|w| {
+                    let undecorated = w.decoration_height(title_bar_height) == 0;
+                    (w.id, undecorated, state.stack_key(w))
+                })
                 .collect();
+            windows_to_render.sort_by_key(|(_, _, stack_key)| *stack_key);
 
-            for (id, _) in &windows_to_render {
+            for (id, _, _) in &windows_to_render {
                 state.update_window_pixel_cache(*id);
             }
 
-            state.canvas.clear_with_pattern(
-                state.config.background_dark(),
-                state.config.background_light(),
-            );
+            // the wallpaper lags a resize until the loader catches up
+            match state.wallpaper.current {
+                Some(ref wallpaper)
+                    if (wallpaper.width as usize, wallpaper.height as usize)
+                        == (state.canvas.width, state.canvas.height) =>
+                {
+                    state.canvas.copy_from(&wallpaper.pixels);
+                }
+                _ => state.canvas.clear_with_pattern(
+                    state.config.background_dark(),
+                    state.config.background_light(),
+                ),
+            }
 
             let title_focused = state.config.title_focused();
             let title_unfocused = state.config.title_unfocused();
             let border_focused = state.config.border_focused();
             let border_unfocused = state.config.border_unfocused();
-            let title_bar_height = state.config.title_bar_height();
+            let unresponsive_tint = state.config.unresponsive_tint();
+
+            let tabs = state.tabs();
 
-            for (id, is_fullscreen) in &windows_to_render {
+            for (id, undecorated, _) in &windows_to_render {
                 if let Some(win) = state.windows.iter().find(|w| w.id == *id) {
+                    if win.layout_hidden {
+                        continue;
+                    }
                     if win.cache_width > 0 && win.cache_height > 0 {
                         let is_focused = focused_id == Some(*id);
 
-                        if *is_fullscreen {
-                            let render_width = (win.cache_width as i32).min(win.geometry.width);
-                            let render_height = (win.cache_height as i32).min(win.geometry.height);
+                        let (content_w, content_h) =
+                            win.content_size(win.cache_width, win.cache_height);
+
+                        if *undecorated {
+                            let render_width = content_w.min(win.geometry.width);
+                            let render_height = content_h.min(win.geometry.height);
 
                             if render_width <= 0 || render_height <= 0 {
                                 continue;
                             }
 
-                            state.canvas.blit_fast(
-                                &win.pixel_cache,
-                                render_width as usize,
-                                render_height as usize,
-                                win.cache_stride,
+                            blit_window_content(
+                                &mut state.canvas,
+                                win,
                                 win.geometry.x,
                                 win.geometry.y,
+                                render_width,
+                                render_height,
                             );
                         } else {
-                            let render_width = (win.cache_width as i32).min(win.geometry.width);
-                            let render_height = (win.cache_height as i32)
-                                .min(win.geometry.height - title_bar_height);
+                            let render_width = content_w.min(win.geometry.width);
+                            let render_height =
+                                content_h.min(win.geometry.height - title_bar_height);
 
                             if render_width <= 0 || render_height <= 0 {
                                 continue;
@@ -1301,21 +2643,66 @@ fn render_cpu(state: &mut State, display: &mut Display<State>, drm_info: Option<
                                 border_unfocused,
                             );
 
+                            if tabs.iter().any(|(tab_id, _)| tab_id == id) {
+                                let tab_rects: Vec<_> =
+                                    tabs.iter().map(|(tab_id, r)| (*r, tab_id == id)).collect();
+                                state.canvas.draw_tabs(
+                                    &tab_rects,
+                                    title_focused,
+                                    title_unfocused,
+                                    border_unfocused,
+                                );
+                                for (tab_id, rect) in &tabs {
+                                    let title = state.windows.iter().find(|w| w.id == *tab_id);
+                                    if let Some(label) =
+                                        title.and_then(|w| TitleLabel::fit(&w.title, *rect))
+                                    {
+                                        let color = state.config.title_text_color(tab_id == id);
+                                        state.canvas.draw_title(
+                                            &label.text,
+                                            label.x,
+                                            label.y,
+                                            label.scale,
+                                            color,
+                                        );
+                                    }
+                                }
+                            } else {
+                                for (button, rect) in win.title_buttons(title_bar_height) {
+                                    let color = state.config.title_button_color(button);
+                                    state.canvas.fill_rect(rect, color);
+                                }
+                                if let Some(label) = win.title_label(title_bar_height) {
+                                    let color = state.config.title_text_color(is_focused);
+                                    state.canvas.draw_title(
+                                        &label.text,
+                                        label.x,
+                                        label.y,
+                                        label.scale,
+                                        color,
+                                    );
+                                }
+                            }
+
                             let content_y = win.geometry.y + title_bar_height;
-                            state.canvas.blit_fast(
-                                &win.pixel_cache,
-                                render_width as usize,
-                                render_height as usize,
-                                win.cache_stride,
+                            blit_window_content(
+                                &mut state.canvas,
+                                win,
                                 win.geometry.x,
                                 content_y,
+                                render_width,
+                                render_height,
                             );
                         }
+
+                        if let Some(rect) = state.unresponsive_rect(*id) {
+                            state.canvas.blend_rect(rect, unresponsive_tint);
+                        }
                     }
                 }
             }
 
-            for (id, _) in &windows_to_render {
+            for (id, _, _) in &windows_to_render {
                 if let Some(win) = state.windows.iter_mut().find(|w| w.id == *id) {
                     win.needs_redraw = false;
                     if !win.buffer_released {
@@ -1327,6 +2714,17 @@ fn render_cpu(state: &mut State, display: &mut Display<State>, drm_info: Option<
                 }
             }
 
+            if let Some(rect) = state.insert_marker_rect() {
+                state.canvas.fill_rect(rect, border_focused);
+            }
+
+            if let Some(rect) = state
+                .split_preview_rect()
+                .or_else(|| state.region_select_rect())
+            {
+                state.canvas.blend_rect(rect, border_focused);
+            }
+
             let layer_surfaces_to_render: Vec<_> = state
                 .layer_surfaces
                 .iter()
@@ -1341,20 +2739,27 @@ fn render_cpu(state: &mut State, display: &mut Display<State>, drm_info: Option<
             for id in &layer_surfaces_to_render {
                 if let Some(ls) = state.layer_surfaces.iter().find(|ls| ls.id == *id) {
                     if ls.cache_width > 0 && ls.cache_height > 0 {
-                        let render_width = (ls.cache_width as i32).min(ls.geometry.width);
-                        let render_height = (ls.cache_height as i32).min(ls.geometry.height);
+                        let content = ls.content_size(ls.cache_width, ls.cache_height);
+                        let render_width = content.0.min(ls.geometry.width);
+                        let render_height = content.1.min(ls.geometry.height);
 
                         if render_width <= 0 || render_height <= 0 {
                             continue;
                         }
 
-                        state.canvas.blit_fast(
+                        blit_content(
+                            &mut state.canvas,
                             &ls.pixel_cache,
-                            render_width as usize,
-                            render_height as usize,
                             ls.cache_stride,
-                            ls.geometry.x,
-                            ls.geometry.y,
+                            (ls.cache_width, ls.cache_height),
+                            content,
+                            ls.buffer_source(),
+                            Rectangle {
+                                x: ls.geometry.x,
+                                y: ls.geometry.y,
+                                width: render_width,
+                                height: render_height,
+                            },
                         );
                     }
                 }
@@ -1372,44 +2777,63 @@ fn render_cpu(state: &mut State, display: &mut Display<State>, drm_info: Option<
                 }
             }
 
+            if let Some(ref reason) = state.safe_mode {
+                state.canvas.draw_banner(&safe_mode::banner_lines(reason));
+            }
+
+            if let Some(ref lines) = state.help_overlay {
+                state.canvas.draw_help(lines);
+            }
+
             if state.cursor_visible {
-                state.canvas.draw_cursor(state.cursor_x, state.cursor_y);
+                let image = state.cursor.frame(state.cursor_frame);
+                state.canvas.draw_cursor(
+                    state.cursor_x - image.xhot as i32,
+                    state.cursor_y - image.yhot as i32,
+                    image.width,
+                    image.height,
+                    &image.pixels,
+                );
             }
         }
 
+        // the canvas is redrawn in full, but only damaged pixels differ from
+        // what the framebuffer already holds
+        copy_regions.push(state.canvas.cursor_rect());
+        if state.damage_tracker.is_full_damage() {
+            copy_regions.push(Rectangle {
+                x: 0,
+                y: 0,
+                width: state.canvas.width as i32,
+                height: state.canvas.height as i32,
+            });
+        } else {
+            copy_regions.extend_from_slice(state.damage_tracker.damage_regions());
+        }
+
         if has_damage {
+            state.accumulate_screencopy_damage();
             state.damage_tracker.clear();
         }
     }
 
-    if has_pending_screencopy {
-        state.process_screencopy_frames(true);
+    if has_screencopy {
+        state.process_screencopy_frames();
     }
 
-    if needs_render {
-        if let Some(drm) = drm_info {
-            unsafe {
-                let fb_pixels = std::slice::from_raw_parts_mut(drm.fb_ptr, drm.width * drm.height);
-                let canvas_pixels = state.canvas.as_slice();
-                let copy_height = state.canvas.height.min(drm.height);
-                let copy_width = state.canvas.width.min(drm.width);
-
-                for y in 0..copy_height {
-                    let src_offset = y * state.canvas.stride;
-                    let dst_offset = y * drm.width;
-
-                    if src_offset + copy_width <= canvas_pixels.len()
-                        && dst_offset + copy_width <= fb_pixels.len()
-                    {
-                        std::ptr::copy_nonoverlapping(
-                            canvas_pixels.as_ptr().add(src_offset),
-                            fb_pixels.as_mut_ptr().add(dst_offset),
-                            copy_width,
-                        );
-                    }
-                }
-            }
-        }
+    if let Some(drm) = drm_info {
+        let transform = state
+            .primary_output()
+            .map(|o| o.transform)
+            .unwrap_or_default();
+        let written = copy_canvas_regions(
+            &state.canvas,
+            drm,
+            &copy_regions,
+            transform,
+            state.color_lut.as_ref(),
+        );
+        drm.flush_damage(&written);
     }
 
     if has_damage || has_frame_callbacks {
@@ -1418,7 +2842,7 @@ fn render_cpu(state: &mut State, display: &mut Display<State>, drm_info: Option<
             .unwrap()
             .as_millis() as u32;
 
-        for callback in state.frame_callbacks.drain(..) {
+        for callback in state.take_due_frame_callbacks() {
             callback.done(time);
         }
 
@@ -1426,6 +2850,282 @@ fn render_cpu(state: &mut State, display: &mut Display<State>, drm_info: Option<
     }
 }
 
+fn damage_uploaded_textures(state: &mut State) {
+    let Some(ref mut gpu) = state.gpu_renderer else {
+        return;
+    };
+    for id in gpu.collect_uploads() {
+        let geometry = match id.checked_sub(renderer::LAYER_TEXTURE_OFFSET) {
+            Some(layer_id) => state
+                .layer_surfaces
+                .iter()
+                .find(|ls| ls.id == layer_id)
+                .map(|ls| ls.geometry),
+            None => state
+                .windows
+                .iter()
+                .find(|w| w.id == id)
+                .map(|w| w.geometry),
+        };
+        if let Some(geometry) = geometry {
+            state.damage_tracker.add_damage(geometry);
+        }
+    }
+}
+
+fn try_direct_scanout(state: &mut State) -> bool {
+    use std::os::fd::AsFd;
+
+    let Some(window_id) = state.scanout_candidate() else {
+        return false;
+    };
+    let Some(window) = state.windows.iter().find(|w| w.id == window_id) else {
+        return false;
+    };
+    let Some(buffer) = window.buffer.clone() else {
+        return false;
+    };
+    // a released buffer may already be getting redrawn by the client
+    if window.buffer_released && state.scanout_buffer.as_ref() != Some(&buffer) {
+        return false;
+    }
+    let Some(info) = state.dmabuf_buffers.get(&buffer.id()) else {
+        return false;
+    };
+    let (fd, stride, offset, modifier) = match info.planes.as_slice() {
+        [] => (info.fd.as_fd(), info.stride, info.offset, info.modifier),
+        [plane] => (plane.fd.as_fd(), plane.stride, plane.offset, plane.modifier),
+        _ => return false,
+    };
+    let Some(gpu) = state.gpu_renderer.as_mut() else {
+        return false;
+    };
+    if !gpu.scanout_dmabuf(
+        &buffer.id(),
+        fd,
+        info.width as u32,
+        info.height as u32,
+        info.format,
+        stride,
+        offset,
+        modifier,
+    ) {
+        return false;
+    }
+
+    if let Some(window) = state.windows.iter_mut().find(|w| w.id == window_id) {
+        window.needs_redraw = false;
+        window.buffer_released = true;
+    }
+    state.rotate_scanout_buffer(Some(buffer));
+
+    // nothing else is visible, but clients still need their buffers back
+    let covered: Vec<_> = state
+        .windows
+        .iter()
+        .filter(|w| w.mapped && w.needs_redraw && w.workspace == state.active_workspace)
+        .map(|w| w.id)
+        .collect();
+    for id in covered {
+        state.update_window_pixel_cache(id);
+        if let Some(win) = state.windows.iter_mut().find(|w| w.id == id) {
+            win.needs_redraw = false;
+            if !win.buffer_released {
+                if let Some(ref buffer) = win.buffer {
+                    buffer.release();
+                    win.buffer_released = true;
+                }
+            }
+        }
+    }
+    let covered: Vec<_> = state
+        .layer_surfaces
+        .iter()
+        .filter(|ls| ls.mapped && ls.needs_redraw)
+        .map(|ls| ls.id)
+        .collect();
+    for id in covered {
+        state.update_layer_surface_pixel_cache(id);
+        if let Some(ls) = state.layer_surfaces.iter_mut().find(|ls| ls.id == id) {
+            ls.needs_redraw = false;
+            if !ls.buffer_released {
+                if let Some(ref buffer) = ls.buffer {
+                    buffer.release();
+                    ls.buffer_released = true;
+                }
+            }
+        }
+    }
+
+    true
+}
+
+fn blit_window_content(
+    canvas: &mut state::Canvas,
+    win: &state::Window,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+) {
+    blit_content(
+        canvas,
+        &win.pixel_cache,
+        win.cache_stride,
+        (win.cache_width, win.cache_height),
+        win.content_size(win.cache_width, win.cache_height),
+        win.buffer_source(),
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        },
+    );
+}
+
+// Draws the visible part of a surface's cached buffer into dest, resampling
+// when buffer scale or a viewport make the buffer differ from its logical
+// content size.
+fn blit_content(
+    canvas: &mut state::Canvas,
+    pixels: &[u32],
+    stride: usize,
+    (buffer_w, buffer_h): (usize, usize),
+    (content_w, content_h): (i32, i32),
+    source: Option<[f64; 4]>,
+    dest: Rectangle,
+) {
+    let resampled =
+        source.is_some() || (content_w, content_h) != (buffer_w as i32, buffer_h as i32);
+    if !resampled {
+        canvas.blit_fast(
+            pixels,
+            dest.width as usize,
+            dest.height as usize,
+            stride,
+            dest.x,
+            dest.y,
+        );
+        return;
+    }
+
+    let [sx, sy, sw, sh] = source.unwrap_or([0.0, 0.0, buffer_w as f64, buffer_h as f64]);
+    // clipped to dest, so only the matching part of the source is used
+    let src = [
+        sx,
+        sy,
+        sw * dest.width as f64 / content_w.max(1) as f64,
+        sh * dest.height as f64 / content_h.max(1) as f64,
+    ];
+    canvas.blit_scaled(pixels, stride, src, dest);
+}
+
+// returns the framebuffer rects written, as x1, y1, x2, y2
+fn copy_canvas_regions(
+    canvas: &state::Canvas,
+    drm: &mut DrmInfo,
+    regions: &[Rectangle],
+    transform: state::OutputTransform,
+    lut: Option<&state::ColorLut>,
+) -> Vec<[i32; 4]> {
+    let scaled = (canvas.width, canvas.height) != (drm.width, drm.height);
+    if transform != state::OutputTransform::Normal || scaled || lut.is_some() {
+        return copy_canvas_regions_mapped(canvas, drm, regions, transform, lut);
+    }
+
+    let copy_width = canvas.width.min(drm.width) as i32;
+    let copy_height = canvas.height.min(drm.height) as i32;
+    let canvas_pixels = canvas.as_slice();
+    let mut written = Vec::with_capacity(regions.len());
+
+    unsafe {
+        let fb_pixels = std::slice::from_raw_parts_mut(drm.fb_ptr, drm.width * drm.height);
+
+        for rect in regions {
+            let x1 = rect.x.clamp(0, copy_width) as usize;
+            let y1 = rect.y.clamp(0, copy_height) as usize;
+            let x2 = (rect.x + rect.width).clamp(0, copy_width) as usize;
+            let y2 = (rect.y + rect.height).clamp(0, copy_height) as usize;
+            if x2 <= x1 || y2 <= y1 {
+                continue;
+            }
+            written.push([x1 as i32, y1 as i32, x2 as i32, y2 as i32]);
+
+            for y in y1..y2 {
+                let src_offset = y * canvas.stride + x1;
+                let dst_offset = y * drm.width + x1;
+                let len = x2 - x1;
+
+                if src_offset + len <= canvas_pixels.len() && dst_offset + len <= fb_pixels.len() {
+                    std::ptr::copy_nonoverlapping(
+                        canvas_pixels.as_ptr().add(src_offset),
+                        fb_pixels.as_mut_ptr().add(dst_offset),
+                        len,
+                    );
+                }
+            }
+        }
+    }
+    written
+}
+
+// the canvas is laid out in the rotated, scaled-down space, so each
+// framebuffer pixel is mapped back to the canvas pixel it shows
+fn copy_canvas_regions_mapped(
+    canvas: &state::Canvas,
+    drm: &mut DrmInfo,
+    regions: &[Rectangle],
+    transform: state::OutputTransform,
+    lut: Option<&state::ColorLut>,
+) -> Vec<[i32; 4]> {
+    let (w, h) = (canvas.width, canvas.height);
+    let (pw, ph) = if transform.swaps_axes() {
+        (drm.height, drm.width)
+    } else {
+        (drm.width, drm.height)
+    };
+    if w == 0 || h == 0 {
+        return Vec::new();
+    }
+    let canvas_pixels = canvas.as_slice();
+    let mut written = Vec::with_capacity(regions.len());
+
+    unsafe {
+        let fb_pixels = std::slice::from_raw_parts_mut(drm.fb_ptr, drm.width * drm.height);
+
+        for rect in regions {
+            let x1 = rect.x.clamp(0, w as i32) as usize * pw / w;
+            let y1 = rect.y.clamp(0, h as i32) as usize * ph / h;
+            let x2 = ((rect.x + rect.width).clamp(0, w as i32) as usize * pw).div_ceil(w);
+            let y2 = ((rect.y + rect.height).clamp(0, h as i32) as usize * ph).div_ceil(h);
+            let (x2, y2) = (x2.min(pw), y2.min(ph));
+            if x2 <= x1 || y2 <= y1 {
+                continue;
+            }
+            let area = Rectangle {
+                x: x1 as i32,
+                y: y1 as i32,
+                width: (x2 - x1) as i32,
+                height: (y2 - y1) as i32,
+            };
+            let fb = transform.map_rect(area, pw as i32, ph as i32);
+            written.push([fb.x, fb.y, fb.x + fb.width, fb.y + fb.height]);
+
+            for py in y1..y2 {
+                let row = (py * h / ph).min(h - 1) * canvas.stride;
+                for px in x1..x2 {
+                    let cx = (px * w / pw).min(w - 1);
+                    let (fx, fy) = transform.map_pixel(px, py, pw, ph);
+                    let pixel = canvas_pixels[row + cx];
+                    fb_pixels[fy * drm.width + fx] = lut.map_or(pixel, |lut| lut.apply(pixel));
+                }
+            }
+        }
+    }
+    written
+}
+
 struct LoopData {
     display: Display<State>,
     state: State,
@@ -1434,13 +3134,28 @@ struct LoopData {
     ipc_server: Option<ipc::IpcServer>,
     socket_name: String,
     input_pending: bool,
-    vsync_pending: bool,
     ipc_pending: bool,
     frame_profiler: FrameProfiler,
+    startup_time: Option<std::time::Instant>,
+    // parsed once per config load so a bad value is only warned about once
+    layout_memory: state::LayoutMemory,
+    layout_focus: (Option<state::WindowId>, usize),
+    // the layout group IPC clients were last told about
+    keyboard_layout: Option<u32>,
+    loop_handle: calloop::LoopHandle<'static, LoopData>,
+    // the armed frame timer and when it fires
+    tick_timer: Option<(calloop::RegistrationToken, std::time::Instant)>,
+    scheduler: scheduler::FrameScheduler,
+    metrics: Option<metrics::MetricsServer>,
+    // finished screenshot files, see write_screenshot
+    screenshot_saved: calloop::channel::Sender<std::path::PathBuf>,
 }
 
 struct FrameProfiler {
     frame_count: u64,
+    // unlike frame_count these never reset; the metrics exporter reads them
+    frames_total: u64,
+    slow_frames_total: u64,
     last_log_time: std::time::Instant,
     input_time_us: u64,
     render_time_us: u64,
@@ -1452,12 +3167,18 @@ struct FrameProfiler {
     last_input_us: u64,
     fps_update_time: std::time::Instant,
     fps_frame_count: u64,
+    // frame times in ms for the overlay's graph, oldest first
+    frame_history: std::collections::VecDeque<f32>,
 }
 
+const FRAME_HISTORY: usize = 240;
+
 impl FrameProfiler {
     fn new() -> Self {
         Self {
             frame_count: 0,
+            frames_total: 0,
+            slow_frames_total: 0,
             last_log_time: std::time::Instant::now(),
             input_time_us: 0,
             render_time_us: 0,
@@ -1469,11 +3190,13 @@ impl FrameProfiler {
             last_input_us: 0,
             fps_update_time: std::time::Instant::now(),
             fps_frame_count: 0,
+            frame_history: std::collections::VecDeque::with_capacity(FRAME_HISTORY),
         }
     }
 
     fn record_frame(&mut self, input_us: u64, render_us: u64, total_us: u64, state: &State) {
         self.frame_count += 1;
+        self.frames_total += 1;
         self.fps_frame_count += 1;
         self.input_time_us += input_us;
         self.render_time_us += render_us;
@@ -1481,9 +3204,14 @@ impl FrameProfiler {
         self.last_render_us = render_us;
         self.last_input_us = input_us;
         self.last_frame_time_ms = total_us as f32 / 1000.0;
+        if self.frame_history.len() == FRAME_HISTORY {
+            self.frame_history.pop_front();
+        }
+        self.frame_history.push_back(self.last_frame_time_ms);
 
         if total_us > 16666 {
             self.slow_frames += 1;
+            self.slow_frames_total += 1;
         }
 
         let fps_elapsed = self.fps_update_time.elapsed();
@@ -1520,20 +3248,26 @@ impl FrameProfiler {
         let window_cache_bytes: usize = state.windows.iter().map(|w| w.pixel_cache.len() * 4).sum();
         let memory_mb = (canvas_bytes + window_cache_bytes) as f32 / (1024.0 * 1024.0);
 
-        let texture_count = state
-            .gpu_renderer
-            .as_ref()
-            .map(|r| r.texture_count())
-            .unwrap_or(0);
+        let gpu = state.gpu_renderer.as_ref();
+        let texture_count = gpu.map(|r| r.texture_count()).unwrap_or(0);
+        let texture_bytes = gpu.map(|r| r.texture_bytes()).unwrap_or(0);
 
         renderer::ProfilerStats {
             fps: self.last_fps,
             frame_time_ms: self.last_frame_time_ms,
+            max_frame_time_ms: self.frame_history.iter().copied().fold(0.0, f32::max),
             render_time_us: self.last_render_us,
             input_time_us: self.last_input_us,
+            gpu_time_us: gpu
+                .map(|r| r.last_gpu_time().as_micros() as u64)
+                .unwrap_or(0),
+            damage_rects: gpu.map(|r| r.last_damage_rects()).unwrap_or(0),
             memory_mb,
             window_count: state.windows.len(),
             texture_count,
+            texture_mb: texture_bytes as f32 / (1024.0 * 1024.0),
+            frame_history: self.frame_history.iter().copied().collect(),
+            frame_budget_ms: output_frame_period(state).as_secs_f32() * 1000.0,
         }
     }
 
@@ -1560,8 +3294,8 @@ fn process_ipc(data: &mut LoopData) {
 
     ipc.accept_connections();
 
-    let commands = ipc.poll_commands();
-    for cmd in commands {
+    let commands = ipc.poll_commands(&data.state.config.ipc);
+    for (client, cmd) in commands {
         match cmd {
             ktc_common::IpcCommand::GetState => {
                 let workspaces = get_workspace_info(&data.state);
@@ -1572,18 +3306,323 @@ fn process_ipc(data: &mut LoopData) {
                     .and_then(|id| data.state.windows.iter().find(|w| w.id == id))
                     .map(|w| w.title.clone());
                 ipc.send_state(workspaces, active, focused_title);
+                ipc.notify_hidden_windows(get_hidden_windows(&data.state));
+                ipc.notify_bar_visibility(!data.state.bar_hidden);
+                if let Some(handler) = data.input_handler.as_ref() {
+                    let group = handler.layout_group();
+                    let name = data.state.config.keyboard.layout_name(group);
+                    ipc.notify_keyboard_layout(name, group);
+                    ipc.notify_mode(handler.mode().to_string());
+                    ipc.notify_passthrough(handler.passthrough());
+                }
             }
             ktc_common::IpcCommand::SwitchWorkspace { workspace } => {
                 data.state.switch_workspace(workspace);
                 let workspaces = get_workspace_info(&data.state);
                 ipc.notify_workspace_change(workspaces, workspace);
             }
+            ktc_common::IpcCommand::MoveToWorkspace {
+                id,
+                workspace,
+                follow,
+            } => {
+                if let Some(id) = id.or(data.state.focused_window) {
+                    move_window_to_workspace(&mut data.state, Some(ipc), id, workspace, follow);
+                    data.display.flush_clients().ok();
+                }
+            }
+            ktc_common::IpcCommand::SetOutputMode {
+                output,
+                width,
+                height,
+                refresh,
+            } => match data.state.set_output_mode(&output, width, height, refresh) {
+                Ok(()) => {
+                    data.state.broadcast_output_manager_done();
+                    data.display.flush_clients().ok();
+                }
+                Err(e) => log::warn!("[ipc] Failed to set output mode: {}", e),
+            },
+            ktc_common::IpcCommand::SetOutputVrr { output, enabled } => {
+                if let Err(e) = data.state.set_output_vrr(&output, enabled) {
+                    log::warn!("[ipc] Failed to toggle VRR: {}", e);
+                }
+            }
+            ktc_common::IpcCommand::SetColorTemp {
+                output,
+                temperature,
+                brightness,
+            } => {
+                let adjust =
+                    (temperature.is_some() || brightness.is_some()).then(|| state::ColorAdjust {
+                        temperature: temperature.unwrap_or(ktc_common::NEUTRAL_TEMPERATURE),
+                        brightness: brightness.unwrap_or(1.0),
+                    });
+                if let Err(e) = data.state.set_output_color(&output, adjust) {
+                    log::warn!("[ipc] Failed to set color temperature: {}", e);
+                }
+            }
+            ktc_common::IpcCommand::SetWallpaper { output, path, mode } => {
+                if let Err(e) = data.state.set_wallpaper(&output, path, mode) {
+                    log::warn!("[ipc] Failed to set wallpaper: {}", e);
+                }
+            }
+            ktc_common::IpcCommand::StartRecording {
+                path,
+                encoder,
+                framerate,
+            } => start_recording(&mut data.state, Some(ipc), path, encoder, framerate),
+            ktc_common::IpcCommand::StopRecording => stop_recording(&mut data.state),
+            ktc_common::IpcCommand::SetProfiler { enabled } => {
+                let debug = &mut data.state.config.debug;
+                debug.profiler = enabled.unwrap_or(!debug.profiler);
+                // a full redraw takes the overlay off again
+                data.state.damage_tracker.mark_full_damage();
+            }
+            ktc_common::IpcCommand::SetPointer {
+                device,
+                accel_profile,
+                speed,
+                left_handed,
+                middle_emulation,
+            } => {
+                let input = &mut data.state.config.input;
+                match device {
+                    Some(name) => {
+                        let device = input.device_mut(&name);
+                        device.accel_profile = accel_profile.or(device.accel_profile.take());
+                        device.speed = speed.or(device.speed);
+                        device.left_handed = left_handed.or(device.left_handed);
+                        device.middle_emulation = middle_emulation.or(device.middle_emulation);
+                    }
+                    None => {
+                        let pointer = &mut input.pointer;
+                        if let Some(profile) = accel_profile {
+                            pointer.accel_profile = profile;
+                        }
+                        pointer.speed = speed.unwrap_or(pointer.speed);
+                        pointer.left_handed = left_handed.unwrap_or(pointer.left_handed);
+                        pointer.middle_emulation =
+                            middle_emulation.unwrap_or(pointer.middle_emulation);
+                    }
+                }
+                if let Some(handler) = data.input_handler.as_mut() {
+                    handler.set_input_config(&data.state.config.input);
+                }
+            }
+            ktc_common::IpcCommand::RaiseWindow { id } => {
+                if let Some(id) = id.or(data.state.focused_window) {
+                    data.state.raise_window(id);
+                }
+            }
+            ktc_common::IpcCommand::LowerWindow { id } => {
+                if let Some(id) = id.or(data.state.focused_window) {
+                    data.state.lower_window(id);
+                }
+            }
+            ktc_common::IpcCommand::Mark { id, mark } => {
+                if let Some(id) = id.or(data.state.focused_window) {
+                    data.state.set_mark(id, &mark);
+                }
+            }
+            ktc_common::IpcCommand::GetWorkspacePreview { workspace, width } => {
+                let preview = data.state.workspace_preview(workspace, width);
+                ipc.send_workspace_preview(client, workspace, &preview);
+            }
+            ktc_common::IpcCommand::SetOpacity { id, opacity } => {
+                if let Some(id) = id.or(data.state.focused_window) {
+                    data.state.set_opacity(id, opacity);
+                }
+            }
+            ktc_common::IpcCommand::JumpToMark { mark } => {
+                let old_workspace = data.state.active_workspace;
+                if data.state.jump_to_mark(&mark) {
+                    notify_jump(&data.state, Some(ipc), old_workspace);
+                    data.display.flush_clients().ok();
+                }
+            }
+            ktc_common::IpcCommand::GetTree => {
+                ipc.send_tree(get_window_tree(&data.state));
+            }
+            ktc_common::IpcCommand::GetRendererStats => {
+                ipc.send_renderer_stats(get_renderer_stats(&data.state));
+            }
+            ktc_common::IpcCommand::GetTablets => {
+                ipc.send_tablets(get_tablets(&data.state));
+            }
+            ktc_common::IpcCommand::GetHiddenWindows => {
+                ipc.notify_hidden_windows(get_hidden_windows(&data.state));
+            }
+            ktc_common::IpcCommand::RestoreWindow { id } => {
+                if data.state.restore_window(id) {
+                    let focused_title = data
+                        .state
+                        .windows
+                        .iter()
+                        .find(|w| w.id == id)
+                        .map(|w| w.title.clone());
+                    ipc.notify_focus_change(focused_title);
+                    data.display.flush_clients().ok();
+                }
+            }
+        }
+    }
+}
+
+fn move_window_to_workspace(
+    state: &mut State,
+    ipc: Option<&mut ipc::IpcServer>,
+    window_id: state::WindowId,
+    workspace: usize,
+    follow: bool,
+) {
+    state.move_window_to_workspace(window_id, workspace);
+    if follow {
+        state.switch_workspace(workspace);
+        if state.active_workspace == workspace {
+            state.set_focus(window_id);
         }
     }
+
+    if let Some(ipc) = ipc {
+        ipc.notify_workspace_change(get_workspace_info(state), state.active_workspace);
+        let focused_title = state
+            .focused_window
+            .and_then(|id| state.windows.iter().find(|w| w.id == id))
+            .map(|w| w.title.clone());
+        ipc.notify_focus_change(focused_title);
+    }
+}
+
+fn notify_jump(state: &State, ipc: Option<&mut ipc::IpcServer>, old_workspace: usize) {
+    let Some(ipc) = ipc else {
+        return;
+    };
+    if state.active_workspace != old_workspace {
+        ipc.notify_workspace_change(get_workspace_info(state), state.active_workspace);
+    }
+    let focused_title = state
+        .focused_window
+        .and_then(|id| state.windows.iter().find(|w| w.id == id))
+        .map(|w| w.title.clone());
+    ipc.notify_focus_change(focused_title);
+}
+
+fn get_window_tree(state: &State) -> Vec<ktc_common::WindowTreeInfo> {
+    state
+        .windows
+        .iter()
+        .filter(|w| w.mapped)
+        .map(|w| ktc_common::WindowTreeInfo {
+            id: w.id,
+            title: w.title.clone(),
+            app_id: w.app_id.clone(),
+            workspace: (!w.is_hidden()).then_some(w.workspace),
+            focused: state.focused_window == Some(w.id),
+            floating: w.floating,
+            minimized: w.minimized,
+            marks: state.marks_of(w.id),
+        })
+        .collect()
+}
+
+fn update_metrics(data: &mut LoopData) {
+    let Some(ref mut server) = data.metrics else {
+        return;
+    };
+    if !server.due() {
+        return;
+    }
+
+    let state = &data.state;
+    let mut clients = 0;
+    data.display
+        .backend()
+        .handle()
+        .with_all_clients(|_| clients += 1);
+    let stats = get_renderer_stats(state);
+    let pixel_cache_bytes = state.canvas.pixels.len() * 4
+        + state
+            .windows
+            .iter()
+            .map(|w| w.pixel_cache.len() * 4)
+            .sum::<usize>();
+
+    server.update(&metrics::Snapshot {
+        frames_total: data.frame_profiler.frames_total,
+        slow_frames_total: data.frame_profiler.slow_frames_total,
+        dropped_frames_total: stats.page_flip_misses,
+        clients,
+        windows: state.windows.len(),
+        mapped_windows: state.windows.iter().filter(|w| w.mapped).count(),
+        outputs: state.outputs.len(),
+        texture_bytes: stats.texture_bytes,
+        pixel_cache_bytes: pixel_cache_bytes as u64,
+        backend: if state.gpu_renderer.is_some() {
+            "gles"
+        } else {
+            "cpu"
+        },
+    });
+}
+
+// area is where the tablet maps to right now, so the pointer's output when
+// [input.tablet] names none
+fn get_tablets(state: &State) -> Vec<ktc_common::TabletInfo> {
+    let (x, y) = state.tablet_to_layout(0.0, 0.0);
+    let (right, bottom) = state.tablet_to_layout(1.0, 1.0);
+    let area = [
+        x as i32,
+        y as i32,
+        (right - x) as i32 + 1,
+        (bottom - y) as i32 + 1,
+    ];
+    state
+        .tablets
+        .iter()
+        .map(|tablet| ktc_common::TabletInfo {
+            name: tablet.device.name.clone(),
+            vendor: tablet.device.vendor,
+            product: tablet.device.product,
+            path: tablet.path(),
+            area,
+        })
+        .collect()
+}
+
+fn get_renderer_stats(state: &State) -> ktc_common::RendererStats {
+    let Some(ref gpu) = state.gpu_renderer else {
+        return ktc_common::RendererStats {
+            backend: "cpu".to_string(),
+            ..Default::default()
+        };
+    };
+    let counters = gpu.counters();
+    ktc_common::RendererStats {
+        backend: "gles".to_string(),
+        texture_count: gpu.texture_count(),
+        texture_bytes: gpu.texture_bytes(),
+        dmabuf_import_failures: counters.dmabuf_import_failures,
+        page_flip_misses: counters.page_flip_misses,
+        swapchain_recreations: counters.swapchain_recreations,
+    }
+}
+
+fn get_hidden_windows(state: &State) -> Vec<ktc_common::HiddenWindowInfo> {
+    state
+        .minimized_windows
+        .iter()
+        .filter_map(|id| state.windows.iter().find(|w| w.id == *id))
+        .map(|w| ktc_common::HiddenWindowInfo {
+            id: w.id,
+            title: w.title.clone(),
+            app_id: w.app_id.clone(),
+        })
+        .collect()
 }
 
 fn get_workspace_info(state: &State) -> Vec<ktc_common::WorkspaceInfo> {
-    (1..=state.workspace_count)
+    (1..=state.workspace_count())
         .map(|id| {
             let window_count = state
                 .windows
@@ -1595,6 +3634,7 @@ fn get_workspace_info(state: &State) -> Vec<ktc_common::WorkspaceInfo> {
                 name: id.to_string(),
                 window_count,
                 urgent: false,
+                layout: state.workspace(id).map(|ws| ws.layout.name().to_string()),
             }
         })
         .collect()
@@ -1617,6 +3657,7 @@ fn spawn_ktcbar(socket_name: &str) {
     let mut command = Command::new(&ktcbar_path);
     command
         .env("WAYLAND_DISPLAY", socket_name)
+        .env_remove("WAYLAND_SOCKET")
         .env("XDG_RUNTIME_DIR", &xdg_runtime_dir)
         .stdout(Stdio::null())
         .stderr(Stdio::null());
@@ -1639,6 +3680,35 @@ fn spawn_ktcbar(socket_name: &str) {
     }
 }
 
+fn first_run_help(config_path: &std::path::Path) -> Vec<String> {
+    vec![
+        "Welcome to KTC".to_string(),
+        format!("A default config was written to {}", config_path.display()),
+        String::new(),
+        "Alt+Return        open a terminal (foot)".to_string(),
+        "Alt+D             application launcher (fuzzel)".to_string(),
+        "Alt+J / Alt+K     focus next / previous window".to_string(),
+        "Alt+Shift+Q       close window".to_string(),
+        "Alt+1..9          switch workspace".to_string(),
+        "Alt+Shift+C       reload config".to_string(),
+        "Ctrl+Alt+Q        exit".to_string(),
+        String::new(),
+        "Press any key to dismiss".to_string(),
+    ]
+}
+
+fn find_terminal() -> Option<String> {
+    let path_var = std::env::var("PATH").ok()?;
+    ["foot", "alacritty", "kitty", "wezterm", "xterm"]
+        .into_iter()
+        .find(|name| {
+            path_var
+                .split(':')
+                .any(|dir| std::path::Path::new(dir).join(name).is_file())
+        })
+        .map(str::to_string)
+}
+
 fn which_ktcbar() -> Option<String> {
     if std::path::Path::new("ktcbar").exists() {
         return Some("ktcbar".to_string());
@@ -1678,36 +3748,73 @@ fn which_ktcbar() -> Option<String> {
     None
 }
 
+struct Card(std::fs::File);
+
+impl std::os::fd::AsFd for Card {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl drm::Device for Card {}
+impl drm::control::Device for Card {}
+
 struct DrmInfo {
-    _device: std::fs::File,
+    card: Card,
     _mapping: drm::control::dumbbuffer::DumbMapping<'static>,
     fb_ptr: *mut u32,
     width: usize,
     height: usize,
-    _fb_id: u32,
+    fb: drm::control::framebuffer::Handle,
     _crtc: drm::control::crtc::Handle,
+    // None when the CRTC was set up with legacy set_crtc
+    atomic: Option<kms::AtomicOutput>,
+    // the last atomic flush couldn't be queued, so the next one covers all
+    flush_missed: bool,
     physical_width: u32,
     physical_height: u32,
     refresh: i32,
+    modes: Vec<state::OutputMode>,
     name: String,
 }
 
 unsafe impl Send for DrmInfo {}
 
-fn setup_drm(device: &std::fs::File) -> Result<DrmInfo, Box<dyn std::error::Error>> {
-    use drm::control::{connector, Device as ControlDevice};
-    use std::os::fd::{AsFd, BorrowedFd};
-
-    struct Card(std::fs::File);
+impl DrmInfo {
+    // The CPU renderer draws straight into the scanned-out buffer. Drivers
+    // that scan out of a shadow copy only show what is flushed to them.
+    fn flush_damage(&mut self, rects: &[[i32; 4]]) {
+        use drm::control::{ClipRect, Device as ControlDevice};
 
-    impl AsFd for Card {
-        fn as_fd(&self) -> BorrowedFd<'_> {
-            self.0.as_fd()
+        if rects.is_empty() && !self.flush_missed {
+            return;
+        }
+        match &self.atomic {
+            Some(atomic) => {
+                let rects = if self.flush_missed { &[][..] } else { rects };
+                let result = atomic.flush_damage(&self.card, self.fb, rects);
+                if let Err(ref e) = result {
+                    log::debug!("[kms] {}", e);
+                }
+                self.flush_missed = result.is_err();
+            }
+            None => {
+                let clips: Vec<ClipRect> = rects
+                    .iter()
+                    .map(|r| {
+                        let [x1, y1, x2, y2] = r.map(|v| v.clamp(0, u16::MAX as i32) as u16);
+                        ClipRect::new(x1, y1, x2, y2)
+                    })
+                    .collect();
+                // most drivers without atomic have nothing to flush and say so
+                self.card.dirty_framebuffer(self.fb, &clips).ok();
+            }
         }
     }
+}
 
-    impl drm::Device for Card {}
-    impl ControlDevice for Card {}
+fn setup_drm(device: &std::fs::File) -> Result<DrmInfo, Box<dyn std::error::Error>> {
+    use drm::control::{connector, Device as ControlDevice};
 
     let card = Card(device.try_clone()?);
 
@@ -1735,6 +3842,22 @@ fn setup_drm(device: &std::fs::File) -> Result<DrmInfo, Box<dyn std::error::Erro
 
     let (phys_width, phys_height) = connector.size().unwrap_or((0, 0));
 
+    let modes = connector
+        .modes()
+        .iter()
+        .map(|m| {
+            let (w, h) = m.size();
+            state::OutputMode {
+                width: w as i32,
+                height: h as i32,
+                refresh: m.vrefresh() as i32 * 1000,
+                preferred: m
+                    .mode_type()
+                    .contains(drm::control::ModeTypeFlags::PREFERRED),
+            }
+        })
+        .collect();
+
     log::info!(
         "Using display mode: {}x{} @{}Hz (physical: {}x{}mm) on {}",
         width,
@@ -1755,13 +3878,22 @@ fn setup_drm(device: &std::fs::File) -> Result<DrmInfo, Box<dyn std::error::Erro
 
     let fb_handle = card.add_framebuffer(&db, 24, 32)?;
 
-    card.set_crtc(
-        crtc_handle,
-        Some(fb_handle),
-        (0, 0),
-        &[connector.handle()],
-        Some(*mode),
-    )?;
+    let atomic = kms::AtomicOutput::new(&card, connector.handle(), crtc_handle)
+        .and_then(|mut atomic| atomic.modeset(&card, fb_handle, mode).map(|()| atomic));
+    let atomic = match atomic {
+        Ok(atomic) => Some(atomic),
+        Err(e) => {
+            log::warn!("[kms] {}, using legacy modesetting", e);
+            card.set_crtc(
+                crtc_handle,
+                Some(fb_handle),
+                (0, 0),
+                &[connector.handle()],
+                Some(*mode),
+            )?;
+            None
+        }
+    };
 
     let db_leaked: &'static mut drm::control::dumbbuffer::DumbBuffer = Box::leak(Box::new(db));
 
@@ -1769,16 +3901,19 @@ fn setup_drm(device: &std::fs::File) -> Result<DrmInfo, Box<dyn std::error::Erro
     let fb_ptr = map_handle.as_ptr() as *mut u32;
 
     Ok(DrmInfo {
-        _device: card.0,
+        card,
         _mapping: map_handle,
         fb_ptr,
         width: width as usize,
         height: height as usize,
-        _fb_id: fb_handle.into(),
+        fb: fb_handle,
         _crtc: crtc_handle,
+        atomic,
+        flush_missed: false,
         physical_width: phys_width,
         physical_height: phys_height,
         refresh,
+        modes,
         name: connector_name,
     })
 }
@@ -0,0 +1,298 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// how often the exposition is rebuilt; scrapes in between get the last one
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// a scraper that connects and says nothing must not hold up the next one
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+// nor send an endless request line; a scrape's request is a few hundred bytes
+const MAX_REQUEST: u64 = 8192;
+
+// Values sampled from the compositor for one exposition.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub frames_total: u64,
+    pub slow_frames_total: u64,
+    pub dropped_frames_total: u64,
+    pub clients: usize,
+    pub windows: usize,
+    pub mapped_windows: usize,
+    pub outputs: usize,
+    pub texture_bytes: u64,
+    pub pixel_cache_bytes: u64,
+    pub backend: &'static str,
+}
+
+// Serves Prometheus text exposition on GET /metrics, over TCP or a unix
+// socket. The main loop refreshes the text once a second; a worker thread
+// answers scrapes from the copy, so a slow scraper never stalls a frame.
+pub struct MetricsServer {
+    exposition: Arc<Mutex<String>>,
+    socket_path: Option<PathBuf>,
+    started: Instant,
+    last_update: Option<Instant>,
+    last_frames: u64,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl MetricsServer {
+    // listen is "host:port" or "unix:/path/to/socket"
+    pub fn start(listen: &str) -> io::Result<Self> {
+        let (listener, socket_path) = match listen.strip_prefix("unix:") {
+            Some(path) => {
+                let path = PathBuf::from(path);
+                remove_stale_socket(&path);
+                (Listener::Unix(UnixListener::bind(&path)?), Some(path))
+            }
+            None => (Listener::Tcp(TcpListener::bind(listen)?), None),
+        };
+
+        let exposition = Arc::new(Mutex::new(String::new()));
+        let shared = Arc::clone(&exposition);
+        std::thread::Builder::new()
+            .name("ktc-metrics".to_string())
+            .spawn(move || serve(listener, shared))?;
+
+        log::info!("[metrics] Serving on {}", listen);
+        Ok(Self {
+            exposition,
+            socket_path,
+            started: Instant::now(),
+            last_update: None,
+            last_frames: 0,
+        })
+    }
+
+    pub fn due(&self) -> bool {
+        self.last_update
+            .is_none_or(|last| last.elapsed() >= UPDATE_INTERVAL)
+    }
+
+    pub fn update(&mut self, snapshot: &Snapshot) {
+        let now = Instant::now();
+        let fps = match self.last_update {
+            Some(last) => {
+                let frames = snapshot.frames_total.saturating_sub(self.last_frames);
+                frames as f64 / now.duration_since(last).as_secs_f64().max(0.001)
+            }
+            None => 0.0,
+        };
+        self.last_update = Some(now);
+        self.last_frames = snapshot.frames_total;
+
+        let text = render(snapshot, fps, self.started.elapsed(), resident_bytes());
+        if let Ok(mut exposition) = self.exposition.lock() {
+            *exposition = text;
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.socket_path {
+            if is_socket(path) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+fn is_socket(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket())
+}
+
+// A socket left behind by a previous run is removed so bind can succeed.
+// Anything else at the path, or a socket something still listens on, is
+// left for bind to fail on.
+fn remove_stale_socket(path: &Path) {
+    if is_socket(path) && UnixStream::connect(path).is_err() {
+        log::info!("[metrics] Removing stale socket {}", path.display());
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+fn serve(listener: Listener, exposition: Arc<Mutex<String>>) {
+    loop {
+        let result = match listener {
+            Listener::Tcp(ref listener) => listener.accept().and_then(|(stream, _)| {
+                stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                respond(stream, &exposition)
+            }),
+            Listener::Unix(ref listener) => listener.accept().and_then(|(stream, _)| {
+                stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                respond(stream, &exposition)
+            }),
+        };
+        if let Err(e) = result {
+            log::debug!("[metrics] Scrape failed: {}", e);
+        }
+    }
+}
+
+fn respond<S: Read + Write>(mut stream: S, exposition: &Mutex<String>) -> io::Result<()> {
+    let mut reader = BufReader::new(&mut stream).take(MAX_REQUEST);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers are read and ignored, so the client sees a clean close
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next());
+    let path = target.map(|t| t.split('?').next().unwrap_or(t));
+    let (status, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            exposition.lock().map(|e| e.clone()).unwrap_or_default(),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "only GET is supported\n".to_string(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn push_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn render(snapshot: &Snapshot, fps: f64, uptime: Duration, resident: Option<u64>) -> String {
+    let mut out = String::new();
+    let info = format!(
+        "ktc_info{{version=\"{}\",backend=\"{}\"}}",
+        env!("CARGO_PKG_VERSION"),
+        snapshot.backend
+    );
+    let _ = writeln!(
+        out,
+        "# HELP ktc_info Compositor version and renderer backend."
+    );
+    let _ = writeln!(out, "# TYPE ktc_info gauge");
+    let _ = writeln!(out, "{} 1", info);
+
+    push_metric(
+        &mut out,
+        "ktc_uptime_seconds",
+        "gauge",
+        "Seconds since the metrics exporter started.",
+        format!("{:.3}", uptime.as_secs_f64()),
+    );
+    push_metric(
+        &mut out,
+        "ktc_frames_per_second",
+        "gauge",
+        "Frames rendered per second over the last update interval.",
+        format!("{:.2}", fps),
+    );
+    push_metric(
+        &mut out,
+        "ktc_frames_total",
+        "counter",
+        "Frames rendered.",
+        snapshot.frames_total,
+    );
+    push_metric(
+        &mut out,
+        "ktc_slow_frames_total",
+        "counter",
+        "Frames that took longer than 16.7ms to process and render.",
+        snapshot.slow_frames_total,
+    );
+    push_metric(
+        &mut out,
+        "ktc_dropped_frames_total",
+        "counter",
+        "Frames that missed their page flip.",
+        snapshot.dropped_frames_total,
+    );
+    push_metric(
+        &mut out,
+        "ktc_clients",
+        "gauge",
+        "Connected Wayland clients.",
+        snapshot.clients,
+    );
+    push_metric(
+        &mut out,
+        "ktc_windows",
+        "gauge",
+        "Toplevel windows, mapped or not.",
+        snapshot.windows,
+    );
+    push_metric(
+        &mut out,
+        "ktc_mapped_windows",
+        "gauge",
+        "Toplevel windows that have committed content.",
+        snapshot.mapped_windows,
+    );
+    push_metric(
+        &mut out,
+        "ktc_outputs",
+        "gauge",
+        "Enabled outputs.",
+        snapshot.outputs,
+    );
+    push_metric(
+        &mut out,
+        "ktc_texture_bytes",
+        "gauge",
+        "GPU memory held by window textures.",
+        snapshot.texture_bytes,
+    );
+    push_metric(
+        &mut out,
+        "ktc_pixel_cache_bytes",
+        "gauge",
+        "CPU copies of window contents and the canvas.",
+        snapshot.pixel_cache_bytes,
+    );
+    if let Some(resident) = resident {
+        push_metric(
+            &mut out,
+            "ktc_resident_memory_bytes",
+            "gauge",
+            "Resident set size of the compositor process.",
+            resident,
+        );
+    }
+    out
+}
+
+fn resident_bytes() -> Option<u64> {
+    // statm is in pages: size, then resident
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size.max(0) as u64)
+}
@@ -1,12 +1,60 @@
-use crate::state::State;
+use std::sync::Mutex;
+
+use crate::state::{BufferDamage, OutputTransform, Rectangle, State};
 use wayland_server::protocol::{
+    wl_buffer::WlBuffer,
     wl_callback::WlCallback,
     wl_compositor::{self, WlCompositor},
+    wl_output::Transform,
     wl_region::{self, WlRegion},
     wl_surface::{self, WlSurface},
 };
 use wayland_server::{Dispatch, GlobalDispatch, Resource};
 
+// A buffer replaced before it was ever drawn goes straight back to the
+// client, so one that renders faster than we present only has its newest
+// frame drawn and never runs out of buffers waiting on us
+fn release_superseded(old: Option<WlBuffer>, new: &Option<WlBuffer>, released: bool) {
+    if let Some(old) = old {
+        if !released && new.as_ref() != Some(&old) {
+            old.release();
+        }
+    }
+}
+
+// wl_region as added and subtracted. Only culling reads it, so it is kept
+// conservative: added rectangles any subtraction touches are dropped.
+#[derive(Default)]
+pub struct RegionData(Mutex<Vec<(bool, Rectangle)>>);
+
+impl RegionData {
+    fn rects(&self) -> Vec<Rectangle> {
+        let ops = self.0.lock().unwrap();
+        ops.iter()
+            .enumerate()
+            .filter(|(i, (add, rect))| {
+                *add && !ops[i + 1..]
+                    .iter()
+                    .any(|(add, later)| !add && later.intersects(rect))
+            })
+            .map(|(_, (_, rect))| *rect)
+            .collect()
+    }
+}
+
+fn buffer_transform(transform: Transform) -> OutputTransform {
+    match transform {
+        Transform::_90 => OutputTransform::Rotate90,
+        Transform::_180 => OutputTransform::Rotate180,
+        Transform::_270 => OutputTransform::Rotate270,
+        Transform::Flipped => OutputTransform::Flipped,
+        Transform::Flipped90 => OutputTransform::FlippedRotate90,
+        Transform::Flipped180 => OutputTransform::FlippedRotate180,
+        Transform::Flipped270 => OutputTransform::FlippedRotate270,
+        _ => OutputTransform::Normal,
+    }
+}
+
 impl GlobalDispatch<WlCompositor, ()> for State {
     fn bind(
         _state: &mut Self,
@@ -35,7 +83,7 @@ impl Dispatch<WlCompositor, ()> for State {
                 data_init.init(id, ());
             }
             wl_compositor::Request::CreateRegion { id } => {
-                data_init.init(id, ());
+                data_init.init(id, RegionData::default());
             }
             _ => {}
         }
@@ -45,11 +93,11 @@ impl Dispatch<WlCompositor, ()> for State {
 impl Dispatch<WlSurface, ()> for State {
     fn request(
         state: &mut Self,
-        _client: &wayland_server::Client,
+        client: &wayland_server::Client,
         resource: &WlSurface,
         request: wl_surface::Request,
         _data: &(),
-        _dhandle: &wayland_server::DisplayHandle,
+        dhandle: &wayland_server::DisplayHandle,
         data_init: &mut wayland_server::DataInit<'_, Self>,
     ) {
         match request {
@@ -72,14 +120,72 @@ impl Dispatch<WlSurface, ()> for State {
             wl_surface::Request::Commit => {
                 let surface_id = resource.id();
 
+                let buffer_size = state.committed_buffer_size(resource);
                 if let Some(window) = state.get_window_by_surface(resource) {
                     if window.pending_buffer_set {
-                        window.buffer = window.pending_buffer.take();
+                        let superseded =
+                            std::mem::replace(&mut window.buffer, window.pending_buffer.take());
+                        release_superseded(superseded, &window.buffer, window.buffer_released);
                         window.pending_buffer_set = false;
                         window.buffer_released = false;
                     }
+                    let mut damage = BufferDamage::default();
+                    if let Some(scale) = window.pending_buffer_scale.take() {
+                        if scale != window.buffer_scale {
+                            damage.add_full();
+                        }
+                        window.buffer_scale = scale;
+                    }
+                    if let Some(transform) = window.pending_buffer_transform.take() {
+                        if transform != window.buffer_transform {
+                            damage.add_full();
+                        }
+                        window.buffer_transform = transform;
+                    }
+                    if let Some(region) = window.pending_opaque_region.take() {
+                        window.opaque_region = region;
+                    }
+                    if let Some(viewport) = window.pending_viewport.take() {
+                        if viewport != window.viewport {
+                            damage.add_full();
+                        }
+                        window.viewport = viewport;
+                    }
+                    // both kinds of damage end up in upright buffer pixels,
+                    // with the scale and transform this commit applies
+                    let (buffer_width, buffer_height) = buffer_size.unwrap_or_default();
+                    damage.merge(window.pending_damage.take().untransformed(
+                        window.buffer_transform,
+                        buffer_width,
+                        buffer_height,
+                    ));
+                    let surface_damage = window.pending_surface_damage.take();
+                    if window.viewport == Default::default() {
+                        damage.merge(surface_damage.scaled(window.buffer_scale));
+                    } else if !surface_damage.is_empty() {
+                        // surface to buffer pixels is only a scale without one
+                        damage.add_full();
+                    }
+                    window.buffer_damage.merge(damage);
+                    let was_mapped = window.mapped;
                     window.mapped = window.buffer.is_some();
+                    let window_id = window.id;
+                    let fullscreen = window.fullscreen;
+                    let apply_rules = window.mapped && !window.rules_applied;
+                    let unmapped = was_mapped && !window.mapped;
+                    state.mark_alive(window_id);
                     state.mark_surface_damage(surface_id.clone());
+                    state.fullscreen_commit |= fullscreen;
+                    if apply_rules {
+                        let pid = client.get_credentials(dhandle).ok().map(|c| c.pid);
+                        if let Some(window) = state.get_window_mut(window_id) {
+                            window.pid = pid;
+                        }
+                        state.launch_mapped(pid);
+                        state.apply_window_rules(window_id);
+                    } else if unmapped {
+                        state.restore_swallowed(window_id);
+                    }
                 } else if state
                     .layer_surfaces
                     .iter()
@@ -98,10 +204,21 @@ impl Dispatch<WlSurface, ()> for State {
                                 || (size_changed && ls.desired_width > 0 && ls.desired_height > 0);
 
                             if ls.pending_buffer_set {
-                                ls.buffer = ls.pending_buffer.take();
+                                let superseded =
+                                    std::mem::replace(&mut ls.buffer, ls.pending_buffer.take());
+                                release_superseded(superseded, &ls.buffer, ls.buffer_released);
                                 ls.pending_buffer_set = false;
                                 ls.buffer_released = false;
                             }
+                            if let Some(scale) = ls.pending_buffer_scale.take() {
+                                ls.buffer_scale = scale;
+                            }
+                            if let Some(transform) = ls.pending_buffer_transform.take() {
+                                ls.buffer_transform = transform;
+                            }
+                            if let Some(viewport) = ls.pending_viewport.take() {
+                                ls.viewport = viewport;
+                            }
                             let was_mapped = ls.mapped;
                             ls.mapped = ls.buffer.is_some();
                             ls.needs_redraw = true;
@@ -123,6 +240,7 @@ impl Dispatch<WlSurface, ()> for State {
 
                     if needs_map {
                         state.damage_tracker.mark_full_damage();
+                        state.needs_relayout = true;
                     }
 
                     if needs_keyboard_focus {
@@ -134,7 +252,19 @@ impl Dispatch<WlSurface, ()> for State {
             }
             wl_surface::Request::Frame { callback } => {
                 let cb = data_init.init(callback, ());
-                state.frame_callbacks.push(cb);
+                state.frame_callbacks.push((resource.clone(), cb));
+            }
+            wl_surface::Request::SetOpaqueRegion { region } => {
+                let rects = region
+                    .map(|r| {
+                        r.data::<RegionData>()
+                            .map(|d| d.rects())
+                            .unwrap_or_default()
+                    })
+                    .unwrap_or_default();
+                if let Some(window) = state.get_window_by_surface(resource) {
+                    window.pending_opaque_region = Some(rects);
+                }
             }
             wl_surface::Request::Damage {
                 x,
@@ -145,10 +275,16 @@ impl Dispatch<WlSurface, ()> for State {
                 let title_bar_height = state.title_bar_height();
                 let damage_info = state.get_window_by_surface(resource).map(|window| {
                     window.needs_redraw = true;
+                    window.pending_surface_damage.add(crate::state::Rectangle {
+                        x,
+                        y,
+                        width,
+                        height,
+                    });
                     let g = window.geometry;
                     crate::state::Rectangle {
                         x: g.x + x,
-                        y: g.y + title_bar_height + y,
+                        y: g.y + window.decoration_height(title_bar_height) + y,
                         width,
                         height,
                     }
@@ -177,11 +313,35 @@ impl Dispatch<WlSurface, ()> for State {
                 let damage_info = state.get_window_by_surface(resource).map(|window| {
                     window.needs_redraw = true;
                     let g = window.geometry;
-                    crate::state::Rectangle {
-                        x: g.x + x,
-                        y: g.y + title_bar_height + y,
+                    // the pixel cache is upright, so transformed buffer damage
+                    // isn't in surface coordinates either
+                    if window.buffer_transform != OutputTransform::Normal {
+                        window.pending_damage.add(crate::state::Rectangle {
+                            x,
+                            y,
+                            width,
+                            height,
+                        });
+                        return crate::state::Rectangle {
+                            x: g.x,
+                            y: g.y,
+                            width: g.width,
+                            height: g.height,
+                        };
+                    }
+                    window.pending_damage.add(crate::state::Rectangle {
+                        x,
+                        y,
                         width,
                         height,
+                    });
+                    // buffer pixels to surface coordinates, rounded outwards
+                    let scale = window.buffer_scale.max(1);
+                    crate::state::Rectangle {
+                        x: g.x + x / scale,
+                        y: g.y + window.decoration_height(title_bar_height) + y / scale,
+                        width: (x + width + scale - 1) / scale - x / scale,
+                        height: (y + height + scale - 1) / scale - y / scale,
                     }
                 });
                 if let Some(rect) = damage_info {
@@ -189,15 +349,52 @@ impl Dispatch<WlSurface, ()> for State {
                 } else if let Some(ls) = state.get_layer_surface_by_wl_surface(resource) {
                     ls.needs_redraw = true;
                     let g = ls.geometry;
-                    let rect = crate::state::Rectangle {
-                        x: g.x + x,
-                        y: g.y + y,
-                        width,
-                        height,
+                    // a viewport or transform makes buffer pixels hard to
+                    // place, take it all
+                    let rect = if ls.viewport != Default::default()
+                        || ls.buffer_transform != OutputTransform::Normal
+                    {
+                        g
+                    } else {
+                        let scale = ls.buffer_scale.max(1);
+                        crate::state::Rectangle {
+                            x: g.x + x / scale,
+                            y: g.y + y / scale,
+                            width: (x + width + scale - 1) / scale - x / scale,
+                            height: (y + height + scale - 1) / scale - y / scale,
+                        }
                     };
                     state.damage_tracker.add_damage(rect);
                 }
             }
+            wl_surface::Request::SetBufferScale { scale } => {
+                if scale < 1 {
+                    resource.post_error(
+                        wl_surface::Error::InvalidScale,
+                        format!("buffer scale {} is not positive", scale),
+                    );
+                    return;
+                }
+                if let Some(window) = state.get_window_by_surface(resource) {
+                    window.pending_buffer_scale = Some(scale);
+                } else if let Some(ls) = state.get_layer_surface_by_wl_surface(resource) {
+                    ls.pending_buffer_scale = Some(scale);
+                }
+            }
+            wl_surface::Request::SetBufferTransform { transform } => {
+                let Some(transform) = transform.into_result().ok().map(buffer_transform) else {
+                    resource.post_error(
+                        wl_surface::Error::InvalidTransform,
+                        "unknown buffer transform",
+                    );
+                    return;
+                };
+                if let Some(window) = state.get_window_by_surface(resource) {
+                    window.pending_buffer_transform = Some(transform);
+                } else if let Some(ls) = state.get_layer_surface_by_wl_surface(resource) {
+                    ls.pending_buffer_transform = Some(transform);
+                }
+            }
             wl_surface::Request::Destroy => {
                 let surface_id = resource.id();
                 log::info!("[surface] Destroy request for surface {:?}", surface_id);
@@ -209,7 +406,7 @@ impl Dispatch<WlSurface, ()> for State {
                     let window_id = state.windows[pos].id;
                     log::info!("[surface] Found window {} for surface, removing", window_id);
                     state.remove_window(window_id);
-                    state.relayout_windows();
+                    state.needs_relayout = true;
                 } else if state
                     .layer_surfaces
                     .iter()
@@ -250,7 +447,7 @@ impl Dispatch<WlSurface, ()> for State {
                 window_id
             );
             state.remove_window(window_id);
-            state.relayout_windows();
+            state.needs_relayout = true;
         } else if state
             .layer_surfaces
             .iter()
@@ -278,15 +475,39 @@ impl Dispatch<WlCallback, ()> for State {
     }
 }
 
-impl Dispatch<WlRegion, ()> for State {
+impl Dispatch<WlRegion, RegionData> for State {
     fn request(
         _state: &mut Self,
         _client: &wayland_server::Client,
         _resource: &WlRegion,
-        _request: wl_region::Request,
-        _data: &(),
+        request: wl_region::Request,
+        data: &RegionData,
         _dhandle: &wayland_server::DisplayHandle,
         _data_init: &mut wayland_server::DataInit<'_, Self>,
     ) {
+        let (add, x, y, width, height) = match request {
+            wl_region::Request::Add {
+                x,
+                y,
+                width,
+                height,
+            } => (true, x, y, width, height),
+            wl_region::Request::Subtract {
+                x,
+                y,
+                width,
+                height,
+            } => (false, x, y, width, height),
+            _ => return,
+        };
+        let rect = Rectangle {
+            x,
+            y,
+            width,
+            height,
+        };
+        if !rect.is_empty() {
+            data.0.lock().unwrap().push((add, rect));
+        }
     }
 }
@@ -1,5 +1,6 @@
 use crate::state::State;
 use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 use wayland_protocols::wp::linux_dmabuf::zv1::server::{
     zwp_linux_buffer_params_v1::{self, ZwpLinuxBufferParamsV1},
@@ -7,13 +8,15 @@ use wayland_protocols::wp::linux_dmabuf::zv1::server::{
     zwp_linux_dmabuf_v1::{self, ZwpLinuxDmabufV1},
 };
 use wayland_server::protocol::wl_buffer::WlBuffer;
+use wayland_server::protocol::wl_surface::WlSurface;
 use wayland_server::{Dispatch, GlobalDispatch, Resource};
 
 pub struct DmaBufGlobal;
 
 pub struct DmaBufFeedbackData {
-    #[allow(dead_code)]
-    pub for_surface: bool,
+    pub surface: Option<WlSurface>,
+    // whether the last feedback sent led with a scanout tranche
+    scanout: AtomicBool,
 }
 
 #[repr(C, packed)]
@@ -111,20 +114,38 @@ impl Dispatch<ZwpLinuxDmabufV1, ()> for State {
             zwp_linux_dmabuf_v1::Request::Destroy => {}
             zwp_linux_dmabuf_v1::Request::GetDefaultFeedback { id } => {
                 log::debug!("[dmabuf] GetDefaultFeedback requested");
-                let feedback = data_init.init(id, DmaBufFeedbackData { for_surface: false });
-                send_feedback_events(state, &feedback);
+                let feedback = data_init.init(
+                    id,
+                    DmaBufFeedbackData {
+                        surface: None,
+                        scanout: AtomicBool::new(false),
+                    },
+                );
+                send_feedback_events(state, &feedback, false);
             }
             zwp_linux_dmabuf_v1::Request::GetSurfaceFeedback { id, surface } => {
                 log::debug!("[dmabuf] GetSurfaceFeedback requested for surface {:?}", surface.id());
-                let feedback = data_init.init(id, DmaBufFeedbackData { for_surface: true });
-                send_feedback_events(state, &feedback);
+                let scanout = state.wants_scanout_feedback(&surface);
+                let feedback = data_init.init(
+                    id,
+                    DmaBufFeedbackData {
+                        surface: Some(surface),
+                        scanout: AtomicBool::new(scanout),
+                    },
+                );
+                send_feedback_events(state, &feedback, scanout);
+                state.dmabuf_feedbacks.push(feedback);
             }
             _ => {}
         }
     }
 }
 
-fn send_feedback_events(state: &State, feedback: &ZwpLinuxDmabufFeedbackV1) {
+// One tranche for the render node with everything we can import, preceded
+// for scanout-eligible surfaces by a tranche for the display device limited
+// to what the primary plane takes, so clients allocate tiled buffers that
+// can skip composition.
+fn send_feedback_events(state: &State, feedback: &ZwpLinuxDmabufFeedbackV1, scanout: bool) {
     let formats = if let Some(ref renderer) = state.gpu_renderer {
         renderer.supported_formats.clone()
     } else {
@@ -142,9 +163,10 @@ fn send_feedback_events(state: &State, feedback: &ZwpLinuxDmabufFeedbackV1) {
 
     let table_size = formats.len() * std::mem::size_of::<FormatModifierEntry>();
     log::debug!(
-        "[dmabuf] Sending feedback: {} formats, table_size={}",
+        "[dmabuf] Sending feedback: {} formats, table_size={}, scanout={}",
         formats.len(),
-        table_size
+        table_size,
+        scanout
     );
 
     let fd = match create_format_table_fd(&formats) {
@@ -184,23 +206,70 @@ fn send_feedback_events(state: &State, feedback: &ZwpLinuxDmabufFeedbackV1) {
         scanout_dev
     );
 
-    let main_dev_bytes = main_dev.to_ne_bytes();
-    feedback.main_device(main_dev_bytes.to_vec());
+    feedback.main_device(main_dev.to_ne_bytes().to_vec());
 
-    let scanout_dev_bytes = scanout_dev.to_ne_bytes();
-    feedback.tranche_target_device(scanout_dev_bytes.to_vec());
-    feedback.tranche_flags(zwp_linux_dmabuf_feedback_v1::TrancheFlags::Scanout);
+    let scanout_formats = match state.gpu_renderer {
+        Some(ref renderer) if scanout => renderer.scanout_formats(),
+        _ => Vec::new(),
+    };
+    let scanout_indices: Vec<u8> = formats
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| {
+            scanout_formats
+                .iter()
+                .any(|s| s.format == f.format && s.modifier == f.modifier)
+        })
+        .flat_map(|(i, _)| (i as u16).to_ne_bytes())
+        .collect();
+    if !scanout_indices.is_empty() {
+        feedback.tranche_target_device(scanout_dev.to_ne_bytes().to_vec());
+        feedback.tranche_flags(zwp_linux_dmabuf_feedback_v1::TrancheFlags::Scanout);
+        feedback.tranche_formats(scanout_indices);
+        feedback.tranche_done();
+    }
 
     let indices: Vec<u8> = (0..formats.len() as u16)
         .flat_map(|i| i.to_ne_bytes())
         .collect();
+    feedback.tranche_target_device(main_dev.to_ne_bytes().to_vec());
+    feedback.tranche_flags(zwp_linux_dmabuf_feedback_v1::TrancheFlags::empty());
     feedback.tranche_formats(indices);
-
     feedback.tranche_done();
+
     feedback.done();
     log::debug!("[dmabuf] Feedback sent successfully");
 }
 
+impl State {
+    // fullscreen windows on the active workspace may get scanned out directly
+    fn wants_scanout_feedback(&self, surface: &WlSurface) -> bool {
+        self.gpu_renderer.is_some()
+            && self.windows.iter().any(|w| {
+                w.wl_surface == *surface
+                    && w.mapped
+                    && w.fullscreen
+                    && w.workspace == self.active_workspace
+            })
+    }
+
+    pub fn update_dmabuf_feedback(&mut self) {
+        self.dmabuf_feedbacks.retain(|f| f.is_alive());
+        for feedback in &self.dmabuf_feedbacks {
+            let Some(data) = feedback.data::<DmaBufFeedbackData>() else {
+                continue;
+            };
+            let Some(ref surface) = data.surface else {
+                continue;
+            };
+            let scanout = self.wants_scanout_feedback(surface);
+            if data.scanout.swap(scanout, Ordering::Relaxed) != scanout {
+                send_feedback_events(self, feedback, scanout);
+            }
+        }
+    }
+}
+
 fn create_format_table_fd(
     formats: &[crate::renderer::DmaBufFormat],
 ) -> Result<OwnedFd, std::io::Error> {
@@ -440,7 +509,7 @@ impl Dispatch<WlBuffer, DmaBufBufferData> for State {
     ) {
         if let wayland_server::protocol::wl_buffer::Request::Destroy = request {
             if let Some(ref mut renderer) = state.gpu_renderer {
-                renderer.remove_texture(resource.id().protocol_id() as u64);
+                renderer.remove_buffer(&resource.id());
             }
         }
     }
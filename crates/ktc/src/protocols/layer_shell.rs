@@ -1,4 +1,4 @@
-use crate::state::{Layer, LayerSurface, Rectangle, State};
+use crate::state::{Layer, LayerSurface, OutputTransform, Rectangle, State, Viewport};
 use wayland_protocols_wlr::layer_shell::v1::server::{
     zwlr_layer_shell_v1::{self, Layer as WlrLayer, ZwlrLayerShellV1},
     zwlr_layer_surface_v1::{self, Anchor, KeyboardInteractivity, ZwlrLayerSurfaceV1},
@@ -106,6 +106,13 @@ impl Dispatch<ZwlrLayerShellV1, ()> for State {
                     cache_width: 0,
                     cache_height: 0,
                     cache_stride: 0,
+                    cache_opaque: false,
+                    buffer_scale: 1,
+                    pending_buffer_scale: None,
+                    buffer_transform: OutputTransform::Normal,
+                    pending_buffer_transform: None,
+                    viewport: Viewport::default(),
+                    pending_viewport: None,
                 });
 
                 log::debug!("[layer_shell] Created layer surface {}", id);
@@ -168,7 +175,10 @@ impl Dispatch<ZwlrLayerSurfaceV1, LayerSurfaceData> for State {
                     .iter_mut()
                     .find(|ls| ls.wl_surface.id() == surface_id)
                 {
-                    ls.exclusive_zone = zone;
+                    if ls.exclusive_zone != zone {
+                        ls.exclusive_zone = zone;
+                        state.needs_relayout = true;
+                    }
                 }
             }
             zwlr_layer_surface_v1::Request::SetMargin {
@@ -330,6 +340,12 @@ impl State {
             );
             ls.layer_surface.closed();
             self.damage_tracker.add_damage(ls.geometry);
+            if ls.exclusive_zone > 0 {
+                self.needs_relayout = true;
+            }
+            if self.pointer_layer_focus == Some(ls.id) {
+                self.pointer_layer_focus = None;
+            }
             self.layer_surfaces.swap_remove(pos);
             self.damage_tracker.mark_full_damage();
         }
@@ -339,10 +355,19 @@ impl State {
         &mut self,
         surface: &WlSurface,
     ) -> Option<&mut LayerSurface> {
-        let surface_id = surface.id();
-        self.layer_surfaces
-            .iter_mut()
-            .find(|ls| ls.wl_surface.id() == surface_id)
+        // subsurfaces resolve to the layer surface they belong to, as in
+        // get_window_by_surface
+        let mut surface_id = surface.id();
+        loop {
+            if let Some(idx) = self
+                .layer_surfaces
+                .iter()
+                .position(|ls| ls.wl_surface.id() == surface_id)
+            {
+                return Some(&mut self.layer_surfaces[idx]);
+            }
+            surface_id = self.subsurfaces.get(&surface_id)?.clone();
+        }
     }
 
     #[allow(dead_code)]
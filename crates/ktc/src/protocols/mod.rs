@@ -7,6 +7,9 @@ pub mod output_management;
 pub mod screencopy;
 pub mod seat;
 pub mod subcompositor;
+pub mod tablet;
+pub mod viewporter;
+pub mod xdg_activation;
 pub mod xdg_decoration;
 pub mod xdg_output;
 pub mod xdg_shell;
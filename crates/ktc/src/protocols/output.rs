@@ -47,6 +47,13 @@ impl GlobalDispatch<WlShm, ()> for State {
         let shm = data_init.init(resource, ());
         shm.format(wl_shm::Format::Argb8888);
         shm.format(wl_shm::Format::Xrgb8888);
+        shm.format(wl_shm::Format::Abgr8888);
+        shm.format(wl_shm::Format::Xbgr8888);
+        shm.format(wl_shm::Format::Rgb565);
+        shm.format(wl_shm::Format::Argb2101010);
+        shm.format(wl_shm::Format::Xrgb2101010);
+        shm.format(wl_shm::Format::Abgr2101010);
+        shm.format(wl_shm::Format::Xbgr2101010);
     }
 }
 
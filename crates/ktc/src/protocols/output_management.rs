@@ -1,8 +1,8 @@
-use crate::state::{OutputId, OutputTransform, State};
+use crate::state::{Output, OutputId, OutputMode, OutputTransform, State};
 use std::collections::HashMap;
 use std::sync::{
     atomic::{AtomicU32, Ordering},
-    Mutex,
+    Arc, Mutex,
 };
 use wayland_protocols_wlr::output_management::v1::server::{
     zwlr_output_configuration_head_v1::{self, ZwlrOutputConfigurationHeadV1},
@@ -25,7 +25,9 @@ pub struct OutputManagerData {
 
 pub struct OutputManagerDataInner {
     pub heads: HashMap<OutputId, ZwlrOutputHeadV1>,
-    pub modes: HashMap<OutputId, ZwlrOutputModeV1>,
+    pub modes: HashMap<OutputId, Vec<ZwlrOutputModeV1>>,
+    // index into modes of the head's current mode
+    pub current_modes: HashMap<OutputId, usize>,
 }
 
 impl Default for OutputManagerData {
@@ -34,6 +36,7 @@ impl Default for OutputManagerData {
             inner: Mutex::new(OutputManagerDataInner {
                 heads: HashMap::new(),
                 modes: HashMap::new(),
+                current_modes: HashMap::new(),
             }),
         }
     }
@@ -43,7 +46,6 @@ pub struct OutputHeadData {
     pub output_id: OutputId,
 }
 
-#[allow(dead_code)]
 pub struct OutputModeData {
     pub output_id: OutputId,
     pub width: i32,
@@ -54,6 +56,17 @@ pub struct OutputModeData {
 pub struct OutputConfigurationData {
     pub serial: u32,
     pub used: bool,
+    // modes asked for through the configuration's heads
+    pub modes: Arc<Mutex<Vec<RequestedMode>>>,
+}
+
+// refresh is in mHz, 0 for any
+#[derive(Clone, Copy)]
+pub struct RequestedMode {
+    pub output_id: OutputId,
+    pub width: i32,
+    pub height: i32,
+    pub refresh: i32,
 }
 
 #[allow(dead_code)]
@@ -73,6 +86,7 @@ pub struct ConfiguredHead {
 pub struct OutputConfigurationHeadData {
     pub output_id: OutputId,
     pub config_id: ObjectId,
+    pub modes: Arc<Mutex<Vec<RequestedMode>>>,
 }
 
 fn output_transform_to_wl(t: OutputTransform) -> Transform {
@@ -99,12 +113,13 @@ impl GlobalDispatch<ZwlrOutputManagerV1, OutputManagerGlobal> for State {
     ) {
         let manager = data_init.init(resource, OutputManagerData::default());
         state.send_output_manager_state(&manager, dhandle, client);
+        state.output_managers.push(manager);
     }
 }
 
 impl Dispatch<ZwlrOutputManagerV1, OutputManagerData> for State {
     fn request(
-        _state: &mut Self,
+        state: &mut Self,
         _client: &wayland_server::Client,
         resource: &ZwlrOutputManagerV1,
         request: zwlr_output_manager_v1::Request,
@@ -118,6 +133,7 @@ impl Dispatch<ZwlrOutputManagerV1, OutputManagerData> for State {
                 let config_data = OutputConfigurationData {
                     serial,
                     used: false,
+                    modes: Arc::new(Mutex::new(Vec::new())),
                 };
                 let config = data_init.init(id, config_data);
 
@@ -130,10 +146,11 @@ impl Dispatch<ZwlrOutputManagerV1, OutputManagerData> for State {
                 for head in inner.heads.values() {
                     head.finished();
                 }
-                for mode in inner.modes.values() {
+                for mode in inner.modes.values().flatten() {
                     mode.finished();
                 }
                 resource.finished();
+                state.output_managers.retain(|m| m != resource);
             }
             _ => {}
         }
@@ -186,6 +203,7 @@ impl Dispatch<ZwlrOutputConfigurationV1, OutputConfigurationData> for State {
                 let config_head_data = OutputConfigurationHeadData {
                     output_id,
                     config_id: resource.id(),
+                    modes: data.modes.clone(),
                 };
                 let _config_head = data_init.init(id, config_head_data);
             }
@@ -201,9 +219,17 @@ impl Dispatch<ZwlrOutputConfigurationV1, OutputConfigurationData> for State {
                     return;
                 }
 
-                resource.succeeded();
-
-                CONFIG_SERIAL.fetch_add(1, Ordering::Relaxed);
+                let modes = data.modes.lock().unwrap().clone();
+                match modes
+                    .iter()
+                    .try_for_each(|mode| state.apply_requested_mode(mode))
+                {
+                    Ok(()) => resource.succeeded(),
+                    Err(e) => {
+                        log::warn!("[output] Output configuration failed: {}", e);
+                        resource.failed();
+                    }
+                }
                 state.broadcast_output_manager_done();
             }
             zwlr_output_configuration_v1::Request::Test => {
@@ -217,7 +243,17 @@ impl Dispatch<ZwlrOutputConfigurationV1, OutputConfigurationData> for State {
                     return;
                 }
 
-                resource.succeeded();
+                let modes = data.modes.lock().unwrap();
+                match modes
+                    .iter()
+                    .try_for_each(|mode| state.check_requested_mode(mode))
+                {
+                    Ok(()) => resource.succeeded(),
+                    Err(e) => {
+                        log::info!("[output] Output configuration test failed: {}", e);
+                        resource.failed();
+                    }
+                }
             }
             zwlr_output_configuration_v1::Request::Destroy => {}
             _ => {}
@@ -229,29 +265,114 @@ impl Dispatch<ZwlrOutputConfigurationHeadV1, OutputConfigurationHeadData> for St
     fn request(
         _state: &mut Self,
         _client: &wayland_server::Client,
-        _resource: &ZwlrOutputConfigurationHeadV1,
+        resource: &ZwlrOutputConfigurationHeadV1,
         request: zwlr_output_configuration_head_v1::Request,
-        _data: &OutputConfigurationHeadData,
+        data: &OutputConfigurationHeadData,
         _dhandle: &DisplayHandle,
         _data_init: &mut wayland_server::DataInit<'_, Self>,
     ) {
-        match request {
-            zwlr_output_configuration_head_v1::Request::SetMode { mode: _ } => {}
+        use zwlr_output_configuration_head_v1::Error;
+
+        let requested = match request {
+            zwlr_output_configuration_head_v1::Request::SetMode { mode } => {
+                let mode_data: &OutputModeData = mode.data().unwrap();
+                if mode_data.output_id != data.output_id {
+                    resource.post_error(Error::InvalidMode, "mode belongs to another head");
+                    return;
+                }
+                RequestedMode {
+                    output_id: data.output_id,
+                    width: mode_data.width,
+                    height: mode_data.height,
+                    refresh: mode_data.refresh,
+                }
+            }
             zwlr_output_configuration_head_v1::Request::SetCustomMode {
-                width: _,
-                height: _,
-                refresh: _,
-            } => {}
-            zwlr_output_configuration_head_v1::Request::SetPosition { x: _, y: _ } => {}
-            zwlr_output_configuration_head_v1::Request::SetTransform { transform: _ } => {}
-            zwlr_output_configuration_head_v1::Request::SetScale { scale: _ } => {}
-            zwlr_output_configuration_head_v1::Request::SetAdaptiveSync { state: _ } => {}
-            _ => {}
+                width,
+                height,
+                refresh,
+            } => {
+                if width <= 0 || height <= 0 || refresh < 0 {
+                    resource.post_error(Error::InvalidCustomMode, "invalid custom mode");
+                    return;
+                }
+                RequestedMode {
+                    output_id: data.output_id,
+                    width,
+                    height,
+                    refresh,
+                }
+            }
+            zwlr_output_configuration_head_v1::Request::SetPosition { x: _, y: _ } => return,
+            zwlr_output_configuration_head_v1::Request::SetTransform { transform: _ } => return,
+            zwlr_output_configuration_head_v1::Request::SetScale { scale: _ } => return,
+            zwlr_output_configuration_head_v1::Request::SetAdaptiveSync { state: _ } => return,
+            _ => return,
+        };
+
+        let mut modes = data.modes.lock().unwrap();
+        if modes.iter().any(|m| m.output_id == data.output_id) {
+            resource.post_error(Error::AlreadySet, "mode already set");
+            return;
         }
+        modes.push(requested);
     }
 }
 
 impl State {
+    // None when the output already runs that mode, so re-sending the current
+    // configuration works without a live modeset
+    fn requested_mode_change(
+        &self,
+        mode: &RequestedMode,
+    ) -> Result<Option<(u32, u32, Option<u32>)>, String> {
+        let output = self
+            .outputs
+            .iter()
+            .find(|o| o.id == mode.output_id)
+            .ok_or("output is gone")?;
+        let same_refresh = mode.refresh == 0 || (mode.refresh - output.refresh).abs() < 500;
+        if (mode.width, mode.height) == (output.width, output.height) && same_refresh {
+            return Ok(None);
+        }
+        // DRM modes are matched in whole Hz
+        let refresh = (mode.refresh > 0).then(|| ((mode.refresh + 500) / 1000) as u32);
+        Ok(Some((mode.width as u32, mode.height as u32, refresh)))
+    }
+
+    fn check_requested_mode(&self, mode: &RequestedMode) -> Result<(), String> {
+        let Some((width, height, refresh)) = self.requested_mode_change(mode)? else {
+            return Ok(());
+        };
+        if self.outputs.first().map(|o| o.id) != Some(mode.output_id) {
+            return Err("only the primary output can change modes".to_string());
+        }
+        if self.gpu_renderer.is_none() {
+            return Err("live mode changes need the GPU renderer".to_string());
+        }
+        let output = &self.outputs[0];
+        let listed = output.modes.iter().any(|m| {
+            (m.width as u32, m.height as u32) == (width, height)
+                && refresh.is_none_or(|r| ((m.refresh + 500) / 1000) as u32 == r)
+        });
+        if !listed {
+            return Err(format!("mode {}x{} not supported", width, height));
+        }
+        Ok(())
+    }
+
+    fn apply_requested_mode(&mut self, mode: &RequestedMode) -> Result<(), String> {
+        let Some((width, height, refresh)) = self.requested_mode_change(mode)? else {
+            return Ok(());
+        };
+        let name = self
+            .outputs
+            .iter()
+            .find(|o| o.id == mode.output_id)
+            .map(|o| o.name.clone());
+        self.set_output_mode(&name.unwrap_or_default(), width, height, refresh)
+    }
+
     fn send_output_manager_state(
         &self,
         manager: &ZwlrOutputManagerV1,
@@ -281,53 +402,122 @@ impl State {
             if output.physical_width > 0 && output.physical_height > 0 {
                 head.physical_size(output.physical_width, output.physical_height);
             }
-
-            let mode_version = head_version.min(3);
-            let mode: ZwlrOutputModeV1 = client
-                .create_resource::<ZwlrOutputModeV1, _, Self>(
-                    dhandle,
-                    mode_version,
-                    OutputModeData {
-                        output_id: output.id,
-                        width: output.width,
-                        height: output.height,
-                        refresh: output.refresh,
-                    },
-                )
-                .unwrap();
-
-            head.mode(&mode);
-
-            mode.size(output.width, output.height);
-            mode.refresh(output.refresh);
-            mode.preferred();
-
-            head.enabled(1);
-            head.current_mode(&mode);
-            head.position(output.x, output.y);
-            head.transform(output_transform_to_wl(output.transform));
-            head.scale(output.scale as f64);
-
             if head.version() >= 2 {
                 head.make(output.make.clone());
                 head.model(output.model.clone());
             }
 
-            if head.version() >= 4 {
-                head.adaptive_sync(zwlr_output_head_v1::AdaptiveSyncState::Disabled);
+            let mode_version = head_version.min(3);
+            let current = OutputMode {
+                width: output.width,
+                height: output.height,
+                refresh: output.refresh,
+                preferred: false,
+            };
+            let mut available = output.modes.clone();
+            let current_idx = available
+                .iter()
+                .position(|m| {
+                    m.width == current.width
+                        && m.height == current.height
+                        && m.refresh == current.refresh
+                })
+                .or_else(|| {
+                    available
+                        .iter()
+                        .position(|m| m.width == current.width && m.height == current.height)
+                })
+                .unwrap_or_else(|| {
+                    available.push(current);
+                    available.len() - 1
+                });
+            let has_preferred = available.iter().any(|m| m.preferred);
+
+            let mut modes = Vec::with_capacity(available.len());
+            for (idx, info) in available.iter().enumerate() {
+                let mode: ZwlrOutputModeV1 = client
+                    .create_resource::<ZwlrOutputModeV1, _, Self>(
+                        dhandle,
+                        mode_version,
+                        OutputModeData {
+                            output_id: output.id,
+                            width: info.width,
+                            height: info.height,
+                            refresh: info.refresh,
+                        },
+                    )
+                    .unwrap();
+
+                head.mode(&mode);
+
+                mode.size(info.width, info.height);
+                if info.refresh > 0 {
+                    mode.refresh(info.refresh);
+                }
+                if info.preferred || (!has_preferred && idx == current_idx) {
+                    mode.preferred();
+                }
+
+                modes.push(mode);
             }
 
+            send_head_state(&head, output, &modes[current_idx]);
+
             let mut inner = manager_data.inner.lock().unwrap();
             inner.heads.insert(output.id, head);
-            inner.modes.insert(output.id, mode);
+            inner.modes.insert(output.id, modes);
+            inner.current_modes.insert(output.id, current_idx);
         }
 
         let serial = CONFIG_SERIAL.load(Ordering::Relaxed);
         manager.done(serial);
     }
 
-    pub fn broadcast_output_manager_done(&self) {
-        let serial = CONFIG_SERIAL.load(Ordering::Relaxed);
-        let _ = serial;
+    // an output changed mode: resend every head's state under a new serial,
+    // which also cancels configurations made against the old one
+    pub fn broadcast_output_manager_done(&mut self) {
+        let serial = CONFIG_SERIAL.fetch_add(1, Ordering::Relaxed) + 1;
+        self.output_managers.retain(|m| m.is_alive());
+        for manager in &self.output_managers {
+            let manager_data = manager.data::<OutputManagerData>().unwrap();
+            let inner = manager_data.inner.lock().unwrap();
+            for output in &self.outputs {
+                let (Some(head), Some(modes)) =
+                    (inner.heads.get(&output.id), inner.modes.get(&output.id))
+                else {
+                    continue;
+                };
+                // the mode may have changed since the head was sent
+                let current = modes
+                    .iter()
+                    .position(|mode| {
+                        mode.data::<OutputModeData>().is_some_and(|m| {
+                            (m.width, m.height, m.refresh)
+                                == (output.width, output.height, output.refresh)
+                        })
+                    })
+                    .or_else(|| inner.current_modes.get(&output.id).copied());
+                if let Some(current) = current {
+                    send_head_state(head, output, &modes[current]);
+                }
+            }
+            manager.done(serial);
+        }
+    }
+}
+
+fn send_head_state(head: &ZwlrOutputHeadV1, output: &Output, current_mode: &ZwlrOutputModeV1) {
+    head.enabled(1);
+    head.current_mode(current_mode);
+    head.position(output.x, output.y);
+    head.transform(output_transform_to_wl(output.transform));
+    head.scale(output.scale);
+
+    if head.version() >= 4 {
+        head.adaptive_sync(if output.vrr_capable && output.vrr_enabled {
+            zwlr_output_head_v1::AdaptiveSyncState::Enabled
+        } else {
+            zwlr_output_head_v1::AdaptiveSyncState::Disabled
+        });
     }
 }
@@ -1,22 +1,28 @@
-use crate::state::{Rectangle, ScreencopyFrameState, State};
+use crate::state::{OutputTransform, Rectangle, ScreencopyFrameState, State, WindowId};
+use drm_fourcc::DrmFourcc;
+use smithay::backend::renderer::sync::SyncPoint;
 use wayland_protocols_wlr::screencopy::v1::server::{
     zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
     zwlr_screencopy_manager_v1::{self, ZwlrScreencopyManagerV1},
 };
+use wayland_server::backend::ObjectId;
 use wayland_server::protocol::wl_buffer::WlBuffer;
 use wayland_server::protocol::wl_shm;
 use wayland_server::{Dispatch, GlobalDispatch, Resource};
 
 impl GlobalDispatch<ZwlrScreencopyManagerV1, ()> for State {
     fn bind(
-        _state: &mut Self,
+        state: &mut Self,
         _handle: &wayland_server::DisplayHandle,
         _client: &wayland_server::Client,
         resource: wayland_server::New<ZwlrScreencopyManagerV1>,
         _global_data: &(),
         data_init: &mut wayland_server::DataInit<'_, Self>,
     ) {
-        data_init.init(resource, ());
+        let manager = data_init.init(resource, ());
+        state
+            .screencopy_clients
+            .insert(manager.id(), ScreencopyClient::default());
     }
 }
 
@@ -24,7 +30,7 @@ impl Dispatch<ZwlrScreencopyManagerV1, ()> for State {
     fn request(
         state: &mut Self,
         _client: &wayland_server::Client,
-        _resource: &ZwlrScreencopyManagerV1,
+        resource: &ZwlrScreencopyManagerV1,
         request: zwlr_screencopy_manager_v1::Request,
         _data: &(),
         _dhandle: &wayland_server::DisplayHandle,
@@ -33,41 +39,60 @@ impl Dispatch<ZwlrScreencopyManagerV1, ()> for State {
         match request {
             zwlr_screencopy_manager_v1::Request::CaptureOutput {
                 frame,
-                overlay_cursor: _,
+                overlay_cursor,
                 output: _,
             } => {
-                let (width, height) = state.screen_size();
+                let (width, height) = state.capture_size();
                 let frame_data = ScreencopyFrameState {
                     x: 0,
                     y: 0,
                     width,
                     height,
+                    overlay_cursor: overlay_cursor != 0,
+                    manager: resource.id(),
                 };
                 let screencopy_frame = data_init.init(frame, frame_data);
                 state.send_screencopy_buffer_info(&screencopy_frame, width, height);
             }
             zwlr_screencopy_manager_v1::Request::CaptureOutputRegion {
                 frame,
-                overlay_cursor: _,
+                overlay_cursor,
                 output: _,
                 x,
                 y,
                 width,
                 height,
             } => {
+                let (x, y, width, height) = state.capture_region(x, y, width, height);
                 let frame_data = ScreencopyFrameState {
                     x,
                     y,
                     width,
                     height,
+                    overlay_cursor: overlay_cursor != 0,
+                    manager: resource.id(),
                 };
                 let screencopy_frame = data_init.init(frame, frame_data);
+                // nothing of the region is on the output
+                if width <= 0 || height <= 0 {
+                    screencopy_frame.failed();
+                    return;
+                }
                 state.send_screencopy_buffer_info(&screencopy_frame, width, height);
             }
             zwlr_screencopy_manager_v1::Request::Destroy => {}
             _ => {}
         }
     }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        resource: &ZwlrScreencopyManagerV1,
+        _data: &(),
+    ) {
+        state.screencopy_clients.remove(&resource.id());
+    }
 }
 
 impl Dispatch<ZwlrScreencopyFrameV1, ScreencopyFrameState> for State {
@@ -87,14 +112,33 @@ impl Dispatch<ZwlrScreencopyFrameV1, ScreencopyFrameState> for State {
             zwlr_screencopy_frame_v1::Request::CopyWithDamage { buffer } => {
                 state.queue_screencopy_frame(resource.clone(), buffer, data, true);
             }
-            zwlr_screencopy_frame_v1::Request::Destroy => {
-                state
-                    .screencopy_frames
-                    .retain(|f| f.frame.id() != resource.id());
-            }
+            zwlr_screencopy_frame_v1::Request::Destroy => {}
             _ => {}
         }
     }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        resource: &ZwlrScreencopyFrameV1,
+        _data: &ScreencopyFrameState,
+    ) {
+        state
+            .screencopy_frames
+            .retain(|f| f.frame.id() != resource.id());
+    }
+}
+
+// What changed on screen since a screencopy manager's client last got a
+// frame, in layout coordinates. New clients start with everything damaged.
+#[derive(Default)]
+pub struct ScreencopyClient {
+    // None is the whole output
+    content: Option<Rectangle>,
+    // where the cursor moved, only damage to clients that overlay it
+    cursor: Rectangle,
+    // has asked for frames without the cursor
+    cursorless: bool,
 }
 
 impl State {
@@ -108,6 +152,9 @@ impl State {
         );
 
         if frame.version() >= 3 {
+            if self.gpu_renderer.is_some() {
+                frame.linux_dmabuf(DrmFourcc::Xrgb8888 as u32, width as u32, height as u32);
+            }
             frame.buffer_done();
         }
     }
@@ -119,6 +166,26 @@ impl State {
         region: &ScreencopyFrameState,
         with_damage: bool,
     ) {
+        if self
+            .screencopy_frames
+            .iter()
+            .any(|f| f.frame.id() == frame.id())
+        {
+            frame.post_error(
+                zwlr_screencopy_frame_v1::Error::AlreadyUsed,
+                "frame was already copied",
+            );
+            return;
+        }
+        if let Err(reason) = self.check_screencopy_buffer(&buffer, region) {
+            frame.post_error(zwlr_screencopy_frame_v1::Error::InvalidBuffer, reason);
+            return;
+        }
+        if !region.overlay_cursor {
+            if let Some(client) = self.screencopy_clients.get_mut(&region.manager) {
+                client.cursorless = true;
+            }
+        }
         self.screencopy_frames.push(PendingScreencopy {
             frame,
             buffer,
@@ -126,11 +193,311 @@ impl State {
             y: region.y,
             width: region.width,
             height: region.height,
+            overlay_cursor: region.overlay_cursor,
+            manager: region.manager.clone(),
             with_damage,
+            damage: None,
+            fence: None,
         });
     }
 
-    pub fn process_screencopy_frames(&mut self, has_damage: bool) {
+    // the buffer has to be one of those advertised in send_screencopy_buffer_info
+    fn check_screencopy_buffer(
+        &self,
+        buffer: &WlBuffer,
+        region: &ScreencopyFrameState,
+    ) -> Result<(), &'static str> {
+        let buffer_id = buffer.id();
+        if let Some(info) = self.dmabuf_buffers.get(&buffer_id) {
+            if self.gpu_renderer.is_none() {
+                return Err("dmabuf copies need the GPU renderer");
+            }
+            if info.format != DrmFourcc::Xrgb8888 as u32 {
+                return Err("dmabuf format must be XRGB8888");
+            }
+            if info.width != region.width || info.height != region.height {
+                return Err("buffer size does not match the frame");
+            }
+            return Ok(());
+        }
+
+        let Some(data) = self.buffers.get(&buffer_id) else {
+            return Err("not a shm or dmabuf buffer");
+        };
+        let format = wl_shm::Format::Xrgb8888 as u32;
+        if data.format != format {
+            return Err("shm format must be xrgb8888");
+        }
+        if data.width != region.width
+            || data.height != region.height
+            || data.stride < region.width * 4
+        {
+            return Err("buffer size or stride does not match the frame");
+        }
+        let pool_size = self
+            .shm_pools
+            .get(&data.pool_id)
+            .map_or(0, |p| p.size as i64);
+        if data.offset as i64 + data.stride as i64 * data.height as i64 > pool_size {
+            return Err("buffer runs past the end of its pool");
+        }
+        Ok(())
+    }
+
+    // Keeps a cursorless copy of frames in the GPU renderer while any client
+    // wants frames without the cursor. Call before rendering.
+    pub fn prepare_screencopy(&mut self) {
+        let keep = self.screencopy_clients.values().any(|c| c.cursorless);
+        if let Some(ref mut gpu) = self.gpu_renderer {
+            gpu.set_keep_cursorless(keep);
+        }
+    }
+
+    // layout area the cursor covers, empty while it is hidden
+    fn drawn_cursor_rect(&self) -> Rectangle {
+        if !self.cursor_visible {
+            return Rectangle::default();
+        }
+        let image = self.cursor.frame(self.cursor_frame);
+        // see cursor_size: the GPU loads cursor images at output scale
+        let scale = if self.gpu_renderer.is_some() {
+            self.output_scale().max(0.1)
+        } else {
+            1.0
+        };
+        Rectangle {
+            x: self.cursor_x - (image.xhot as f64 / scale).round() as i32,
+            y: self.cursor_y - (image.yhot as f64 / scale).round() as i32,
+            width: (image.width as f64 / scale).ceil() as i32,
+            height: (image.height as f64 / scale).ceil() as i32,
+        }
+    }
+
+    // Folds the damage of the frame being presented into every screencopy
+    // client's. Call before the damage tracker is cleared.
+    pub fn accumulate_screencopy_damage(&mut self) {
+        let (width, height) = self.screen_size();
+        let content = if self.damage_tracker.is_full_damage() {
+            None
+        } else {
+            Some(self.damage_tracker.merged_damage(width, height))
+        };
+        let cursor = self.drawn_cursor_rect();
+        let moved = if cursor == self.screencopy_cursor {
+            Rectangle::default()
+        } else {
+            cursor.union(&self.screencopy_cursor)
+        };
+        self.screencopy_cursor = cursor;
+
+        for client in self.screencopy_clients.values_mut() {
+            client.content = match (client.content, content) {
+                (Some(old), Some(new)) => Some(old.union(&new)),
+                _ => None,
+            };
+            client.cursor = client.cursor.union(&moved);
+        }
+    }
+
+    // damage to report for a frame, relative to its region in buffer pixels;
+    // None when nothing in the region changed since its client's last copy
+    fn screencopy_damage(&self, pending: &PendingScreencopy) -> Option<Rectangle> {
+        let region = Rectangle {
+            x: pending.x,
+            y: pending.y,
+            width: pending.width,
+            height: pending.height,
+        };
+        let full = Rectangle {
+            width: pending.width,
+            height: pending.height,
+            ..Rectangle::default()
+        };
+        let client = self.screencopy_clients.get(&pending.manager);
+        let Some(content) = client.and_then(|c| c.content) else {
+            return Some(full);
+        };
+        let damage = match client {
+            Some(c) if pending.overlay_cursor => content.union(&c.cursor),
+            _ => content,
+        };
+        if damage.is_empty() {
+            return None;
+        }
+        let area = self.layout_to_buffer(damage).intersection(&region)?;
+        Some(Rectangle {
+            x: area.x - region.x,
+            y: area.y - region.y,
+            ..area
+        })
+    }
+
+    // the GPU renderer has no cursorless copy of the presented frame yet;
+    // the next render makes one
+    fn cursorless_missing(&self, pending: &PendingScreencopy) -> bool {
+        !pending.overlay_cursor
+            && self
+                .gpu_renderer
+                .as_ref()
+                .is_some_and(|gpu| !gpu.has_cursorless())
+    }
+
+    fn screencopy_ready(&self, pending: &PendingScreencopy) -> bool {
+        !self.cursorless_missing(pending)
+            && (!pending.with_damage || self.screencopy_damage(pending).is_some())
+    }
+
+    // a frame is waiting on something only a new render can give it; frames
+    // copied with damage otherwise wait for damage to come along
+    pub fn screencopy_needs_render(&self) -> bool {
+        self.screencopy_frames.iter().any(|f| {
+            f.fence.is_none()
+                && (!f.with_damage
+                    || self.cursorless_missing(f)
+                    || self.screencopy_damage(f).is_some())
+        })
+    }
+
+    // captures come from the framebuffer, which the GPU renderer draws at
+    // output scale; the CPU canvas is in logical pixels
+    pub fn capture_size(&self) -> (i32, i32) {
+        match self.gpu_renderer {
+            Some(ref gpu) => {
+                let (width, height) = gpu.size();
+                (width as i32, height as i32)
+            }
+            None => (self.canvas.width as i32, self.canvas.height as i32),
+        }
+    }
+
+    // an output-local region in capture coordinates, clipped to the output;
+    // empty when none of it is on the output
+    pub fn capture_region(&self, x: i32, y: i32, width: i32, height: i32) -> (i32, i32, i32, i32) {
+        let (output_width, output_height) = self.screen_size();
+        let output = Rectangle {
+            x: 0,
+            y: 0,
+            width: output_width,
+            height: output_height,
+        };
+        let region = Rectangle {
+            x,
+            y,
+            width,
+            height,
+        };
+        let Some(region) = region.intersection(&output) else {
+            return (0, 0, 0, 0);
+        };
+        let rect = self.local_to_buffer(region);
+        (rect.x, rect.y, rect.width, rect.height)
+    }
+
+    // last presented frame in capture coordinates, see capture_region; rows
+    // of width, black where the area runs off the canvas
+    pub fn read_screen(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        overlay_cursor: bool,
+    ) -> Vec<u32> {
+        if let Some(ref mut gpu) = self.gpu_renderer {
+            return gpu.read_pixels(x, y, width, height, overlay_cursor);
+        }
+
+        let mut pixels = vec![0u32; (width.max(0) * height.max(0)) as usize];
+        let canvas_pixels = self.canvas.as_slice();
+        let canvas_width = self.canvas.width as i32;
+        let canvas_height = self.canvas.height as i32;
+        let canvas_stride = self.canvas.stride;
+
+        // the part of the area on the canvas, and where it lands in pixels
+        let (x1, y1) = (x.clamp(0, canvas_width), y.clamp(0, canvas_height));
+        let x2 = (x + width).clamp(0, canvas_width);
+        let y2 = (y + height).clamp(0, canvas_height);
+        let copy_width = (x2 - x1).max(0) as usize;
+
+        for src_y in y1..y2 {
+            let src_row = src_y as usize * canvas_stride + x1 as usize;
+            let dst_row = (src_y - y) as usize * width as usize + (x1 - x) as usize;
+            if src_row + copy_width <= canvas_pixels.len() {
+                pixels[dst_row..dst_row + copy_width]
+                    .copy_from_slice(&canvas_pixels[src_row..src_row + copy_width]);
+            }
+        }
+        if !overlay_cursor {
+            self.canvas.uncover_cursor(&mut pixels, x, y, width);
+        }
+        pixels
+    }
+
+    // a layout rect of the last frame, turned back upright: rows of the
+    // returned width, in buffer pixels
+    pub fn read_layout_region(&mut self, rect: Rectangle) -> Option<(i32, i32, Vec<u32>)> {
+        let area = self.layout_to_buffer(rect);
+        if area.is_empty() {
+            return None;
+        }
+        let pixels = self.read_screen(area.x, area.y, area.width, area.height, true);
+
+        let transform = self.buffer_transform();
+        if transform == OutputTransform::Normal {
+            return Some((area.width, area.height, pixels));
+        }
+        let (width, height) = if transform.swaps_axes() {
+            (area.height, area.width)
+        } else {
+            (area.width, area.height)
+        };
+        let (w, h) = (width as usize, height as usize);
+        let mut upright = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                let (fx, fy) = transform.map_pixel(x, y, w, h);
+                upright.push(pixels[fy * area.width as usize + fx]);
+            }
+        }
+        Some((width, height, upright))
+    }
+
+    // A toplevel's own contents, upright and unscaled, whatever covers it
+    // on screen: the viewport's source crop of its pixel cache. None for
+    // windows without an 8-bit CPU copy (DMA-BUF, or 10-bit shm buffers the
+    // GPU renderer samples as is).
+    pub fn capture_window(&mut self, id: WindowId) -> Option<(i32, i32, Vec<u32>)> {
+        // hidden windows' caches aren't kept up to date, but they also keep
+        // their buffer; a released one may already be reused by the client,
+        // and the cache holds what was last read from it
+        if !self.windows.iter().find(|w| w.id == id)?.buffer_released {
+            self.update_window_pixel_cache(id);
+        }
+        let window = self.windows.iter().find(|w| w.id == id)?;
+        if window.pixel_cache.is_empty() || window.cache_deep_color {
+            return None;
+        }
+
+        let (cache_width, cache_height) = (window.cache_width, window.cache_height);
+        let whole = [0.0, 0.0, cache_width as f64, cache_height as f64];
+        let [sx, sy, sw, sh] = window.buffer_source().unwrap_or(whole);
+        let x1 = (sx.round() as usize).min(cache_width);
+        let y1 = (sy.round() as usize).min(cache_height);
+        let x2 = ((sx + sw).round() as usize).clamp(x1, cache_width);
+        let y2 = ((sy + sh).round() as usize).clamp(y1, cache_height);
+        if x2 == x1 || y2 == y1 {
+            return None;
+        }
+
+        let mut pixels = Vec::with_capacity((x2 - x1) * (y2 - y1));
+        for row in y1..y2 {
+            let start = row * window.cache_stride;
+            pixels.extend_from_slice(window.pixel_cache.get(start + x1..start + x2)?);
+        }
+        Some(((x2 - x1) as i32, (y2 - y1) as i32, pixels))
+    }
+
+    pub fn process_screencopy_frames(&mut self) {
         if self.screencopy_frames.is_empty() {
             return;
         }
@@ -138,44 +505,33 @@ impl State {
         let mut frames = std::mem::take(&mut self.screencopy_frames);
         let mut deferred = Vec::new();
 
-        let damage_region = if has_damage {
-            let (w, h) = self.screen_size();
-            self.damage_tracker.merged_damage(w, h)
-        } else {
-            Rectangle::default()
-        };
+        for mut pending in frames.drain(..) {
+            // gpu copies into dmabufs stay queued until their fence signals
+            if let Some(ref fence) = pending.fence {
+                if fence.is_reached() {
+                    Self::send_screencopy_ready(&pending);
+                } else {
+                    deferred.push(pending);
+                }
+                continue;
+            }
 
-        for pending in frames.drain(..) {
-            if pending.with_damage && !has_damage {
+            if !self.screencopy_ready(&pending) {
                 deferred.push(pending);
                 continue;
             }
+            pending.damage = self.screencopy_damage(&pending);
 
-            if self.copy_frame_to_buffer(&pending) {
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap();
-                let secs = now.as_secs();
-                let nsecs = now.subsec_nanos();
-
-                if pending.with_damage && pending.frame.version() >= 2 {
-                    if damage_region.is_empty() {
-                        pending
-                            .frame
-                            .damage(0, 0, pending.width as u32, pending.height as u32);
-                    } else {
-                        let rel_x = (damage_region.x - pending.x).max(0) as u32;
-                        let rel_y = (damage_region.y - pending.y).max(0) as u32;
-                        let rel_w = damage_region.width.min(pending.width) as u32;
-                        let rel_h = damage_region.height.min(pending.height) as u32;
-                        pending.frame.damage(rel_x, rel_y, rel_w, rel_h);
-                    }
+            if self.copy_frame_to_buffer(&mut pending) {
+                if let Some(client) = self.screencopy_clients.get_mut(&pending.manager) {
+                    client.content = Some(Rectangle::default());
+                    client.cursor = Rectangle::default();
+                }
+                if pending.fence.is_some() {
+                    deferred.push(pending);
+                } else {
+                    Self::send_screencopy_ready(&pending);
                 }
-
-                pending
-                    .frame
-                    .flags(zwlr_screencopy_frame_v1::Flags::empty());
-                pending.frame.ready((secs >> 32) as u32, secs as u32, nsecs);
             } else {
                 log::error!("[screencopy] Frame copy failed");
                 pending.frame.failed();
@@ -185,8 +541,52 @@ impl State {
         self.screencopy_frames = deferred;
     }
 
-    fn copy_frame_to_buffer(&mut self, pending: &PendingScreencopy) -> bool {
+    fn send_screencopy_ready(pending: &PendingScreencopy) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        let secs = now.as_secs();
+        let nsecs = now.subsec_nanos();
+
+        if pending.with_damage && pending.frame.version() >= 2 {
+            if let Some(damage) = pending.damage.filter(|d| !d.is_empty()) {
+                pending.frame.damage(
+                    damage.x as u32,
+                    damage.y as u32,
+                    damage.width as u32,
+                    damage.height as u32,
+                );
+            }
+        }
+
+        pending.buffer.release();
+        pending
+            .frame
+            .flags(zwlr_screencopy_frame_v1::Flags::empty());
+        pending.frame.ready((secs >> 32) as u32, secs as u32, nsecs);
+    }
+
+    fn copy_frame_to_buffer(&mut self, pending: &mut PendingScreencopy) -> bool {
         let buffer_id = pending.buffer.id();
+
+        if let Some(info) = self.dmabuf_buffers.get(&buffer_id) {
+            if info.width != pending.width || info.height != pending.height {
+                return false;
+            }
+            let Some(ref mut gpu) = self.gpu_renderer else {
+                return false;
+            };
+            pending.fence = gpu.copy_to_dmabuf(
+                pending.x,
+                pending.y,
+                pending.width,
+                pending.height,
+                info,
+                pending.overlay_cursor,
+            );
+            return pending.fence.is_some();
+        }
+
         let buffer_data = match self.buffers.get(&buffer_id) {
             Some(data) => data,
             None => return false,
@@ -197,6 +597,8 @@ impl State {
         }
 
         let pool_id = buffer_data.pool_id.clone();
+        let offset = buffer_data.offset as usize;
+        let stride = buffer_data.stride as usize;
         let pool_data = match self.shm_pools.get_mut(&pool_id) {
             Some(data) => data,
             None => return false,
@@ -227,43 +629,24 @@ impl State {
             None => return false,
         };
 
-        if let Some(ref mut gpu) = self.gpu_renderer {
-            let pixels = gpu.read_pixels(pending.x, pending.y, pending.width, pending.height);
-
-            unsafe {
-                let dst_ptr = mmap_ptr.as_ptr().add(buffer_data.offset as usize) as *mut u32;
-                std::ptr::copy_nonoverlapping(pixels.as_ptr(), dst_ptr, pixels.len());
-            }
-        } else {
-            let canvas_pixels = self.canvas.as_slice();
-            let canvas_width = self.canvas.width as i32;
-            let canvas_height = self.canvas.height as i32;
-            let canvas_stride = self.canvas.stride;
-
-            let src_x = pending.x.max(0).min(canvas_width);
-            let src_y = pending.y.max(0).min(canvas_height);
-            let copy_width = pending.width.min(canvas_width - src_x) as usize;
-            let copy_height = pending.height.min(canvas_height - src_y) as usize;
-
+        let pixels = self.read_screen(
+            pending.x,
+            pending.y,
+            pending.width,
+            pending.height,
+            pending.overlay_cursor,
+        );
+        // check_screencopy_buffer made sure every row fits in the pool
+        for (row, line) in pixels
+            .chunks_exact(pending.width.max(1) as usize)
+            .enumerate()
+        {
             unsafe {
-                let dst_ptr = mmap_ptr.as_ptr().add(buffer_data.offset as usize) as *mut u32;
-
-                for row in 0..copy_height {
-                    let src_row = (src_y as usize + row) * canvas_stride + src_x as usize;
-                    let dst_row = row * pending.width as usize;
-
-                    if src_row + copy_width <= canvas_pixels.len() {
-                        std::ptr::copy_nonoverlapping(
-                            canvas_pixels.as_ptr().add(src_row),
-                            dst_ptr.add(dst_row),
-                            copy_width,
-                        );
-                    }
-                }
+                let dst_ptr = mmap_ptr.as_ptr().add(offset + row * stride) as *mut u32;
+                std::ptr::copy_nonoverlapping(line.as_ptr(), dst_ptr, line.len());
             }
         }
 
-        pending.buffer.release();
         true
     }
 }
@@ -275,5 +658,9 @@ pub struct PendingScreencopy {
     pub y: i32,
     pub width: i32,
     pub height: i32,
+    pub overlay_cursor: bool,
+    pub manager: ObjectId,
     pub with_damage: bool,
+    pub damage: Option<Rectangle>,
+    pub fence: Option<SyncPoint>,
 }
@@ -1,3 +1,4 @@
+use crate::input::SeatCapabilities;
 use crate::state::State;
 use std::os::fd::AsFd;
 use wayland_server::protocol::{
@@ -10,7 +11,7 @@ use wayland_server::{Dispatch, GlobalDispatch, Resource};
 
 impl GlobalDispatch<WlSeat, ()> for State {
     fn bind(
-        _state: &mut Self,
+        state: &mut Self,
         _handle: &wayland_server::DisplayHandle,
         _client: &wayland_server::Client,
         resource: wayland_server::New<WlSeat>,
@@ -21,8 +22,20 @@ impl GlobalDispatch<WlSeat, ()> for State {
         if seat.version() >= 2 {
             seat.name("ktc".to_string());
         }
-        seat.capabilities(wl_seat::Capability::Pointer | wl_seat::Capability::Keyboard);
+        seat.capabilities(seat_capabilities(state.seat_capabilities));
+        state.seats.push(seat);
+    }
+}
+
+fn seat_capabilities(capabilities: SeatCapabilities) -> wl_seat::Capability {
+    let mut caps = wl_seat::Capability::empty();
+    if capabilities.keyboard {
+        caps |= wl_seat::Capability::Keyboard;
+    }
+    if capabilities.pointer {
+        caps |= wl_seat::Capability::Pointer;
     }
+    caps
 }
 
 impl Dispatch<WlSeat, ()> for State {
@@ -52,7 +65,8 @@ impl Dispatch<WlSeat, ()> for State {
                 }
 
                 if keyboard.version() >= 4 {
-                    keyboard.repeat_info(25, 600);
+                    let config = &state.config.keyboard;
+                    keyboard.repeat_info(config.repeat_rate(), config.repeat_delay.max(0));
                 }
 
                 let enter_info = state.focused_window.and_then(|focused_id| {
@@ -67,6 +81,8 @@ impl Dispatch<WlSeat, ()> for State {
                 if let Some((focused_id, surface)) = enter_info {
                     let serial = state.next_keyboard_serial();
                     keyboard.enter(serial, &surface, vec![]);
+                    let (depressed, latched, locked, group) = state.keyboard_modifiers;
+                    keyboard.modifiers(serial, depressed, latched, locked, group);
                     state.keyboard_to_window.insert(keyboard.id(), focused_id);
                     log::info!("[seat] Sent keyboard.enter to newly created keyboard for focused window {}", focused_id);
                 }
@@ -79,6 +95,78 @@ impl Dispatch<WlSeat, ()> for State {
             _ => {}
         }
     }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        resource: &WlSeat,
+        _data: &(),
+    ) {
+        let seat_id = resource.id();
+        state.seats.retain(|seat| seat.id() != seat_id);
+    }
+}
+
+impl State {
+    // Clients drop their wl_pointer or wl_keyboard when told the last device
+    // of its kind is gone, and ask again once one is plugged in
+    pub fn set_seat_capabilities(&mut self, capabilities: SeatCapabilities) {
+        if capabilities == self.seat_capabilities {
+            return;
+        }
+        log::info!(
+            "[seat] Capabilities: keyboard {}, pointer {}",
+            if capabilities.keyboard { "yes" } else { "no" },
+            if capabilities.pointer { "yes" } else { "no" }
+        );
+        self.seat_capabilities = capabilities;
+        for seat in &self.seats {
+            seat.capabilities(seat_capabilities(capabilities));
+        }
+    }
+
+    // after a reload; wl_keyboard before version 4 has no repeat_info
+    pub fn send_repeat_info(&self) {
+        let config = &self.config.keyboard;
+        for keyboard in self.keyboards.iter().filter(|k| k.version() >= 4) {
+            keyboard.repeat_info(config.repeat_rate(), config.repeat_delay.max(0));
+        }
+    }
+
+    // after a reload changed keyboard.layout, model or options. Clients drop
+    // their modifier state with the old keymap, so the focused ones get it
+    // again.
+    pub fn send_keymap(&mut self, modifiers: Option<(u32, u32, u32, u32)>) {
+        let Some(keymap_data) = Self::create_keymap(&self.config) else {
+            log::warn!(
+                "[seat] Keymap for layout {:?} failed to compile, clients keep the old one",
+                self.config.keyboard.layout
+            );
+            return;
+        };
+        for keyboard in &self.keyboards {
+            keyboard.keymap(
+                KeymapFormat::XkbV1,
+                keymap_data.fd.as_fd(),
+                keymap_data.size,
+            );
+        }
+        self.keymap_data = Some(keymap_data);
+
+        if let Some(modifiers) = modifiers {
+            self.send_modifiers(modifiers);
+        }
+    }
+
+    // to the focused client, and to whatever the keyboard enters next
+    pub fn send_modifiers(&mut self, modifiers: (u32, u32, u32, u32)) {
+        self.keyboard_modifiers = modifiers;
+        let (depressed, latched, locked, group) = modifiers;
+        let serial = self.next_keyboard_serial();
+        for keyboard in self.get_focused_keyboards() {
+            keyboard.modifiers(serial, depressed, latched, locked, group);
+        }
+    }
 }
 
 impl Dispatch<WlPointer, ()> for State {
@@ -0,0 +1,491 @@
+use crate::coords::surface_local;
+use crate::input::{TabletDevice, TabletInput, TabletToolType, ToolInfo};
+use crate::state::{State, WindowId};
+use wayland_protocols::wp::tablet::zv2::server::{
+    zwp_tablet_manager_v2::{self, ZwpTabletManagerV2},
+    zwp_tablet_seat_v2::{self, ZwpTabletSeatV2},
+    zwp_tablet_tool_v2::{self, ZwpTabletToolV2},
+    zwp_tablet_v2::{self, ZwpTabletV2},
+};
+use wayland_server::{Client, Dispatch, DisplayHandle, GlobalDispatch, Resource};
+
+pub struct TabletManagerGlobal;
+
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+const BTN_STYLUS: u32 = 0x14b;
+const BTN_STYLUS2: u32 = 0x14c;
+
+// a tablet libinput reported, and its wp_tablet in every tablet seat
+pub struct Tablet {
+    pub device: TabletDevice,
+    resources: Vec<ZwpTabletV2>,
+}
+
+impl Tablet {
+    pub fn path(&self) -> String {
+        format!("/dev/input/{}", self.device.sysname)
+    }
+}
+
+// A tool is announced the first time it comes near a tablet. Over a window
+// whose client bound a tablet seat it talks wp_tablet_tool; anywhere else it
+// moves the pointer, the tip being the left button.
+pub struct TabletTool {
+    info: ToolInfo,
+    resources: Vec<ZwpTabletToolV2>,
+    // sysname of the tablet it was last near
+    tablet: String,
+    // the window proximity_in was sent for
+    focus: Option<WindowId>,
+    down: bool,
+}
+
+impl GlobalDispatch<ZwpTabletManagerV2, TabletManagerGlobal> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: wayland_server::New<ZwpTabletManagerV2>,
+        _global_data: &TabletManagerGlobal,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwpTabletManagerV2, ()> for State {
+    fn request(
+        state: &mut Self,
+        client: &Client,
+        _resource: &ZwpTabletManagerV2,
+        request: zwp_tablet_manager_v2::Request,
+        _data: &(),
+        dhandle: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_tablet_manager_v2::Request::GetTabletSeat { tablet_seat, .. } => {
+                let seat = data_init.init(tablet_seat, ());
+                for index in 0..state.tablets.len() {
+                    state.announce_tablet(index, &seat, client, dhandle);
+                }
+                for index in 0..state.tablet_tools.len() {
+                    state.announce_tool(index, &seat, client, dhandle);
+                }
+                state.tablet_seats.push(seat);
+            }
+            zwp_tablet_manager_v2::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwpTabletSeatV2, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwpTabletSeatV2,
+        _request: zwp_tablet_seat_v2::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        resource: &ZwpTabletSeatV2,
+        _data: &(),
+    ) {
+        state.tablet_seats.retain(|s| s.id() != resource.id());
+    }
+}
+
+impl Dispatch<ZwpTabletV2, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwpTabletV2,
+        _request: zwp_tablet_v2::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        resource: &ZwpTabletV2,
+        _data: &(),
+    ) {
+        for tablet in &mut state.tablets {
+            tablet
+                .resources
+                .retain(|r| Resource::id(r) != Resource::id(resource));
+        }
+    }
+}
+
+impl Dispatch<ZwpTabletToolV2, ()> for State {
+    // set_cursor is ignored, tools show the compositor's cursor
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwpTabletToolV2,
+        _request: zwp_tablet_tool_v2::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        resource: &ZwpTabletToolV2,
+        _data: &(),
+    ) {
+        for tool in &mut state.tablet_tools {
+            tool.resources.retain(|r| r.id() != resource.id());
+        }
+    }
+}
+
+fn tool_type(kind: TabletToolType) -> zwp_tablet_tool_v2::Type {
+    use zwp_tablet_tool_v2::Type;
+    match kind {
+        TabletToolType::Eraser => Type::Eraser,
+        TabletToolType::Brush => Type::Brush,
+        TabletToolType::Pencil => Type::Pencil,
+        TabletToolType::Airbrush => Type::Airbrush,
+        TabletToolType::Mouse => Type::Mouse,
+        TabletToolType::Lens => Type::Lens,
+        _ => Type::Pen,
+    }
+}
+
+fn now_ms() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u32
+}
+
+impl State {
+    fn announce_tablet(
+        &mut self,
+        index: usize,
+        seat: &ZwpTabletSeatV2,
+        client: &Client,
+        dhandle: &DisplayHandle,
+    ) {
+        let Ok(resource) =
+            client.create_resource::<ZwpTabletV2, _, Self>(dhandle, seat.version(), ())
+        else {
+            return;
+        };
+        let tablet = &mut self.tablets[index];
+        seat.tablet_added(&resource);
+        resource.name(tablet.device.name.clone());
+        resource.id(tablet.device.vendor, tablet.device.product);
+        resource.path(tablet.path());
+        resource.done();
+        tablet.resources.push(resource);
+    }
+
+    fn announce_tool(
+        &mut self,
+        index: usize,
+        seat: &ZwpTabletSeatV2,
+        client: &Client,
+        dhandle: &DisplayHandle,
+    ) {
+        use zwp_tablet_tool_v2::Capability;
+
+        let Ok(resource) =
+            client.create_resource::<ZwpTabletToolV2, _, Self>(dhandle, seat.version(), ())
+        else {
+            return;
+        };
+        let tool = &mut self.tablet_tools[index];
+        seat.tool_added(&resource);
+        resource._type(tool_type(tool.info.kind));
+        resource.hardware_serial((tool.info.serial >> 32) as u32, tool.info.serial as u32);
+        if tool.info.pressure {
+            resource.capability(Capability::Pressure);
+        }
+        if tool.info.tilt {
+            resource.capability(Capability::Tilt);
+        }
+        resource.done();
+        tool.resources.push(resource);
+    }
+
+    fn tablet_added(&mut self, device: &TabletDevice, dhandle: &DisplayHandle) {
+        if self
+            .tablets
+            .iter()
+            .any(|t| t.device.sysname == device.sysname)
+        {
+            return;
+        }
+        self.tablets.push(Tablet {
+            device: device.clone(),
+            resources: Vec::new(),
+        });
+        let index = self.tablets.len() - 1;
+        for seat in self.tablet_seats.clone() {
+            if let Some(client) = seat.client() {
+                self.announce_tablet(index, &seat, &client, dhandle);
+            }
+        }
+    }
+
+    // Tools without a serial can't be recognised on another tablet, they go
+    // with the one they were last near
+    fn tablet_removed(&mut self, sysname: &str) {
+        let Some(index) = self
+            .tablets
+            .iter()
+            .position(|t| t.device.sysname == sysname)
+        else {
+            return;
+        };
+        let tablet = self.tablets.remove(index);
+        for resource in &tablet.resources {
+            resource.removed();
+        }
+        self.tablet_tools.retain_mut(|tool| {
+            if tool.tablet != sysname {
+                return true;
+            }
+            tool.focus = None;
+            tool.down = false;
+            if tool.info.serial != 0 {
+                return true;
+            }
+            for resource in &tool.resources {
+                resource.removed();
+            }
+            false
+        });
+    }
+
+    fn tool_index(&mut self, info: &ToolInfo, dhandle: &DisplayHandle) -> usize {
+        if let Some(index) = self
+            .tablet_tools
+            .iter()
+            .position(|t| t.info.kind == info.kind && t.info.serial == info.serial)
+        {
+            return index;
+        }
+        self.tablet_tools.push(TabletTool {
+            info: info.clone(),
+            resources: Vec::new(),
+            tablet: String::new(),
+            focus: None,
+            down: false,
+        });
+        let index = self.tablet_tools.len() - 1;
+        for seat in self.tablet_seats.clone() {
+            if let Some(client) = seat.client() {
+                self.announce_tool(index, &seat, &client, dhandle);
+            }
+        }
+        index
+    }
+
+    // The window under (x, y) when its client takes tablet events there;
+    // title bars and panels get the emulated pointer
+    fn tablet_target(&self, index: usize, x: f64, y: f64) -> Option<WindowId> {
+        if self.layer_surface_at(x, y).is_some() {
+            return None;
+        }
+        let tool = &self.tablet_tools[index];
+        let window = self
+            .window_at(x, y)
+            .and_then(|id| self.windows.iter().find(|w| w.id == id))?;
+        let (_, local_y) = surface_local(window.surface_origin(self.title_bar_height()), x, y);
+        let client = window.wl_surface.client();
+        let bound = tool.resources.iter().any(|r| r.client() == client)
+            && self
+                .tablets
+                .iter()
+                .find(|t| t.device.sysname == tool.tablet)
+                .is_some_and(|t| t.resources.iter().any(|r| r.client() == client));
+        (bound && local_y >= 0.0).then_some(window.id)
+    }
+
+    // proximity_out from the window the tool was over, proximity_in to the
+    // new one
+    fn set_tool_focus(&mut self, index: usize, focus: Option<WindowId>) {
+        let tool = &self.tablet_tools[index];
+        if tool.focus == focus {
+            return;
+        }
+        let time = now_ms();
+        if let Some(old) = tool
+            .focus
+            .and_then(|id| self.windows.iter().find(|w| w.id == id))
+        {
+            let client = old.wl_surface.client();
+            for resource in tool.resources.iter().filter(|r| r.client() == client) {
+                resource.proximity_out();
+                resource.frame(time);
+            }
+        }
+        if let Some(new) = focus.and_then(|id| self.windows.iter().find(|w| w.id == id)) {
+            let client = new.wl_surface.client();
+            let tablet = self
+                .tablets
+                .iter()
+                .find(|t| t.device.sysname == tool.tablet)
+                .and_then(|t| t.resources.iter().find(|r| r.client() == client))
+                .cloned();
+            let surface = new.wl_surface.clone();
+            if let Some(tablet) = tablet {
+                let serial = self.next_pointer_serial();
+                let tool = &self.tablet_tools[index];
+                for resource in tool.resources.iter().filter(|r| r.client() == client) {
+                    resource.proximity_in(serial, &tablet, &surface);
+                }
+            }
+        }
+        self.tablet_tools[index].focus = focus;
+    }
+
+    // the focused window's wp_tablet_tool objects
+    fn focused_tool_resources(&self, index: usize) -> Vec<ZwpTabletToolV2> {
+        let tool = &self.tablet_tools[index];
+        let Some(window) = tool
+            .focus
+            .and_then(|id| self.windows.iter().find(|w| w.id == id))
+        else {
+            return Vec::new();
+        };
+        let client = window.wl_surface.client();
+        tool.resources
+            .iter()
+            .filter(|r| r.client() == client)
+            .cloned()
+            .collect()
+    }
+
+    pub fn handle_tablet_input(&mut self, input: &TabletInput, dhandle: &DisplayHandle) {
+        match input {
+            TabletInput::Added(device) => self.tablet_added(device, dhandle),
+            TabletInput::Removed(sysname) => self.tablet_removed(sysname),
+            TabletInput::Proximity {
+                tablet,
+                tool,
+                near,
+                x,
+                y,
+            } => {
+                let index = self.tool_index(tool, dhandle);
+                if *near {
+                    self.tablet_tools[index].tablet = tablet.clone();
+                    let (x, y) = self.tablet_to_layout(*x, *y);
+                    self.move_cursor(x, y);
+                    return;
+                }
+                if self.tablet_tools[index].down && self.tablet_tools[index].focus.is_none() {
+                    self.handle_pointer_button(BTN_LEFT, false);
+                }
+                self.set_tool_focus(index, None);
+                self.tablet_tools[index].down = false;
+            }
+            TabletInput::Motion {
+                tool,
+                x,
+                y,
+                pressure,
+                tilt,
+            } => {
+                let index = self.tool_index(tool, dhandle);
+                let (x, y) = self.tablet_to_layout(*x, *y);
+                // a tool that's down stays with the window it went down on
+                let focus = if self.tablet_tools[index].down {
+                    self.tablet_tools[index].focus
+                } else {
+                    self.tablet_target(index, x, y)
+                };
+                self.set_tool_focus(index, focus);
+                let Some(window) = focus.and_then(|id| self.windows.iter().find(|w| w.id == id))
+                else {
+                    self.handle_pointer_motion(x, y);
+                    return;
+                };
+                let (local_x, local_y) =
+                    surface_local(window.surface_origin(self.title_bar_height()), x, y);
+                self.move_cursor(x, y);
+                let time = now_ms();
+                for resource in self.focused_tool_resources(index) {
+                    resource.motion(local_x, local_y);
+                    if let Some(pressure) = pressure {
+                        resource.pressure((pressure * 65535.0).round() as u32);
+                    }
+                    if let Some((tilt_x, tilt_y)) = tilt {
+                        resource.tilt(*tilt_x, *tilt_y);
+                    }
+                    resource.frame(time);
+                }
+            }
+            TabletInput::Tip { tool, down } => {
+                let index = self.tool_index(tool, dhandle);
+                self.tablet_tools[index].down = *down;
+                let Some(focus) = self.tablet_tools[index].focus else {
+                    self.handle_pointer_button(BTN_LEFT, *down);
+                    return;
+                };
+                if *down {
+                    if self.focused_window != Some(focus) {
+                        self.set_focus(focus);
+                    }
+                    self.raise_window(focus);
+                }
+                let serial = self.next_pointer_serial();
+                let time = now_ms();
+                for resource in self.focused_tool_resources(index) {
+                    if *down {
+                        resource.down(serial);
+                    } else {
+                        resource.up();
+                    }
+                    resource.frame(time);
+                }
+            }
+            TabletInput::Button {
+                tool,
+                button,
+                pressed,
+            } => {
+                let index = self.tool_index(tool, dhandle);
+                if self.tablet_tools[index].focus.is_none() {
+                    let button = match *button {
+                        BTN_STYLUS => BTN_RIGHT,
+                        BTN_STYLUS2 => BTN_MIDDLE,
+                        other => other,
+                    };
+                    self.handle_pointer_button(button, *pressed);
+                    return;
+                }
+                let state = if *pressed {
+                    zwp_tablet_tool_v2::ButtonState::Pressed
+                } else {
+                    zwp_tablet_tool_v2::ButtonState::Released
+                };
+                let serial = self.next_pointer_serial();
+                let time = now_ms();
+                for resource in self.focused_tool_resources(index) {
+                    resource.button(serial, *button, state);
+                    resource.frame(time);
+                }
+            }
+        }
+    }
+}
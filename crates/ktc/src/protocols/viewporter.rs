@@ -0,0 +1,120 @@
+use crate::state::{State, Viewport};
+use wayland_protocols::wp::viewporter::server::{
+    wp_viewport::{self, WpViewport},
+    wp_viewporter::{self, WpViewporter},
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+use wayland_server::{Dispatch, GlobalDispatch, Resource};
+
+pub struct ViewporterGlobal;
+
+impl GlobalDispatch<WpViewporter, ViewporterGlobal> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &wayland_server::DisplayHandle,
+        _client: &wayland_server::Client,
+        resource: wayland_server::New<WpViewporter>,
+        _global_data: &ViewporterGlobal,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &wayland_server::Client,
+        resource: &WpViewporter,
+        request: wp_viewporter::Request,
+        _data: &(),
+        _dhandle: &wayland_server::DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_viewporter::Request::GetViewport { id, surface } => {
+                if !state.viewports.insert(surface.id()) {
+                    resource.post_error(
+                        wp_viewporter::Error::ViewportExists,
+                        "surface already has a viewport",
+                    );
+                    return;
+                }
+                data_init.init(id, surface);
+            }
+            wp_viewporter::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WpViewport, WlSurface> for State {
+    fn request(
+        state: &mut Self,
+        _client: &wayland_server::Client,
+        resource: &WpViewport,
+        request: wp_viewport::Request,
+        surface: &WlSurface,
+        _dhandle: &wayland_server::DisplayHandle,
+        _data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        if !surface.is_alive() && !matches!(request, wp_viewport::Request::Destroy) {
+            resource.post_error(wp_viewport::Error::NoSurface, "surface was destroyed");
+            return;
+        }
+        let current = match state.get_window_by_surface(surface) {
+            Some(window) => Some(window.pending_viewport.unwrap_or(window.viewport)),
+            None => state
+                .get_layer_surface_by_wl_surface(surface)
+                .map(|ls| ls.pending_viewport.unwrap_or(ls.viewport)),
+        };
+        let Some(mut viewport) = current else {
+            return;
+        };
+
+        match request {
+            wp_viewport::Request::SetSource {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                if x == -1.0 && y == -1.0 && width == -1.0 && height == -1.0 {
+                    viewport.source = None;
+                } else if x < 0.0 || y < 0.0 || width <= 0.0 || height <= 0.0 {
+                    resource.post_error(wp_viewport::Error::BadValue, "invalid source rectangle");
+                    return;
+                } else {
+                    viewport.source = Some([x, y, width, height]);
+                }
+            }
+            wp_viewport::Request::SetDestination { width, height } => {
+                if width == -1 && height == -1 {
+                    viewport.destination = None;
+                } else if width <= 0 || height <= 0 {
+                    resource.post_error(wp_viewport::Error::BadValue, "invalid destination size");
+                    return;
+                } else {
+                    viewport.destination = Some((width, height));
+                }
+            }
+            wp_viewport::Request::Destroy => viewport = Viewport::default(),
+            _ => return,
+        }
+
+        if let Some(window) = state.get_window_by_surface(surface) {
+            window.pending_viewport = Some(viewport);
+        } else if let Some(ls) = state.get_layer_surface_by_wl_surface(surface) {
+            ls.pending_viewport = Some(viewport);
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        _resource: &WpViewport,
+        surface: &WlSurface,
+    ) {
+        state.viewports.remove(&surface.id());
+    }
+}
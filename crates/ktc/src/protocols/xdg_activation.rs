@@ -0,0 +1,97 @@
+use crate::state::State;
+use std::sync::Mutex;
+use wayland_protocols::xdg::activation::v1::server::{
+    xdg_activation_token_v1::{self, XdgActivationTokenV1},
+    xdg_activation_v1::{self, XdgActivationV1},
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+use wayland_server::{Dispatch, GlobalDispatch, Resource};
+
+pub struct XdgActivationGlobal;
+
+#[derive(Default)]
+pub struct ActivationTokenData {
+    committed: Mutex<bool>,
+    serial: Mutex<Option<u32>>,
+    surface: Mutex<Option<WlSurface>>,
+}
+
+impl GlobalDispatch<XdgActivationV1, XdgActivationGlobal> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &wayland_server::DisplayHandle,
+        _client: &wayland_server::Client,
+        resource: wayland_server::New<XdgActivationV1>,
+        _global_data: &XdgActivationGlobal,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<XdgActivationV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &wayland_server::Client,
+        _resource: &XdgActivationV1,
+        request: xdg_activation_v1::Request,
+        _data: &(),
+        _dhandle: &wayland_server::DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_activation_v1::Request::GetActivationToken { id } => {
+                data_init.init(id, ActivationTokenData::default());
+            }
+            xdg_activation_v1::Request::Activate { token, surface } => {
+                log::debug!("[activation] Activate request for token {}", token);
+                state.activate(&token, &surface);
+            }
+            xdg_activation_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<XdgActivationTokenV1, ActivationTokenData> for State {
+    fn request(
+        state: &mut Self,
+        _client: &wayland_server::Client,
+        resource: &XdgActivationTokenV1,
+        request: xdg_activation_token_v1::Request,
+        data: &ActivationTokenData,
+        _dhandle: &wayland_server::DisplayHandle,
+        _data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_activation_token_v1::Request::Commit => {
+                let mut committed = data.committed.lock().unwrap();
+                if *committed {
+                    resource.post_error(
+                        xdg_activation_token_v1::Error::AlreadyUsed,
+                        "activation token already committed",
+                    );
+                    return;
+                }
+                *committed = true;
+                let allowed = state.activation_request_allowed(
+                    *data.serial.lock().unwrap(),
+                    data.surface.lock().unwrap().as_ref(),
+                );
+                // without randomness the client still gets an answer, just
+                // one that activates nothing
+                let token = state.new_activation_token(allowed).unwrap_or_default();
+                resource.done(token);
+            }
+            xdg_activation_token_v1::Request::SetSerial { serial, .. } => {
+                *data.serial.lock().unwrap() = Some(serial);
+            }
+            xdg_activation_token_v1::Request::SetAppId { .. } => {}
+            xdg_activation_token_v1::Request::SetSurface { surface } => {
+                *data.surface.lock().unwrap() = Some(surface);
+            }
+            xdg_activation_token_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
@@ -59,7 +59,7 @@ impl State {
         if let Some(output) = self.outputs.first() {
             xdg_output.logical_position(output.x, output.y);
 
-            let (logical_width, logical_height) = output.scaled_size();
+            let (logical_width, logical_height) = output.logical_size();
             xdg_output.logical_size(logical_width, logical_height);
 
             if xdg_output.version() >= 2 {
@@ -10,14 +10,15 @@ use wayland_server::{Dispatch, GlobalDispatch, Resource};
 
 impl GlobalDispatch<XdgWmBase, ()> for State {
     fn bind(
-        _state: &mut Self,
+        state: &mut Self,
         _handle: &wayland_server::DisplayHandle,
         _client: &wayland_server::Client,
         resource: wayland_server::New<XdgWmBase>,
         _global_data: &(),
         data_init: &mut wayland_server::DataInit<'_, Self>,
     ) {
-        data_init.init(resource, ());
+        let wm_base = data_init.init(resource, ());
+        state.wm_bases.push(wm_base);
     }
 }
 
@@ -25,7 +26,7 @@ impl Dispatch<XdgWmBase, ()> for State {
     fn request(
         state: &mut Self,
         _client: &wayland_server::Client,
-        _resource: &XdgWmBase,
+        resource: &XdgWmBase,
         request: xdg_wm_base::Request,
         _data: &(),
         _dhandle: &wayland_server::DisplayHandle,
@@ -42,6 +43,9 @@ impl Dispatch<XdgWmBase, ()> for State {
                     .pending_xdg_surfaces
                     .insert(xdg_id, (xdg_surface, surface));
             }
+            xdg_wm_base::Request::Pong { serial } => {
+                state.handle_pong(resource, serial);
+            }
             _ => {}
         }
     }
@@ -87,7 +91,9 @@ impl Dispatch<XdgSurface, ()> for State {
                     let tiling_states = state.get_toplevel_states(window_id);
                     let (geometry_width, geometry_height) =
                         if let Some(window) = state.get_window_mut(window_id) {
-                            (window.geometry.width, window.geometry.height)
+                            let size = (window.geometry.width, window.geometry.height);
+                            window.last_configure = Some((size.0, size.1, tiling_states.clone()));
+                            size
                         } else {
                             state.screen_size()
                         };
@@ -129,25 +135,98 @@ impl Dispatch<XdgToplevel, ()> for State {
                     let old_title = window.title.clone();
                     window.title = title.clone();
                     let window_id = window.id;
+                    let title_bar = crate::state::Rectangle {
+                        height: window.decoration_height(state.config.title_bar_height()),
+                        ..window.geometry
+                    };
                     let is_focused = state.focused_window == Some(window_id);
 
+                    if old_title != title && !title_bar.is_empty() {
+                        state.damage_tracker.add_damage(title_bar);
+                    }
                     if is_focused && old_title != title {
                         state.pending_title_change = Some(title);
                     }
                 }
             }
-            xdg_toplevel::Request::SetAppId { .. } => {}
-            xdg_toplevel::Request::SetParent { .. } => {}
+            xdg_toplevel::Request::SetAppId { app_id } => {
+                if let Some(window) = state
+                    .windows
+                    .iter_mut()
+                    .find(|w| w.xdg_toplevel.id() == resource.id())
+                {
+                    window.app_id = app_id;
+                }
+            }
+            xdg_toplevel::Request::SetParent { parent } => {
+                let parent_id = parent.and_then(|parent| {
+                    state
+                        .windows
+                        .iter()
+                        .find(|w| w.xdg_toplevel.id() == parent.id())
+                        .map(|w| w.id)
+                });
+                if let Some(window) = state
+                    .windows
+                    .iter_mut()
+                    .find(|w| w.xdg_toplevel.id() == resource.id())
+                {
+                    window.parent = parent_id;
+                }
+            }
             xdg_toplevel::Request::ShowWindowMenu { .. } => {}
             xdg_toplevel::Request::Move { .. } => {}
             xdg_toplevel::Request::Resize { .. } => {}
-            xdg_toplevel::Request::SetMaxSize { .. } => {}
-            xdg_toplevel::Request::SetMinSize { .. } => {}
-            xdg_toplevel::Request::SetMaximized => {}
-            xdg_toplevel::Request::UnsetMaximized => {}
+            xdg_toplevel::Request::SetMaxSize { width, height } => {
+                if let Some(window) = state
+                    .windows
+                    .iter_mut()
+                    .find(|w| w.xdg_toplevel.id() == resource.id())
+                {
+                    window.max_size = (width, height);
+                }
+            }
+            xdg_toplevel::Request::SetMinSize { width, height } => {
+                if let Some(window) = state
+                    .windows
+                    .iter_mut()
+                    .find(|w| w.xdg_toplevel.id() == resource.id())
+                {
+                    window.min_size = (width, height);
+                }
+            }
+            xdg_toplevel::Request::SetMaximized => {
+                if let Some(window_id) = state
+                    .windows
+                    .iter()
+                    .find(|w| w.xdg_toplevel.id() == resource.id())
+                    .map(|w| w.id)
+                {
+                    state.set_maximize(window_id, true);
+                }
+            }
+            xdg_toplevel::Request::UnsetMaximized => {
+                if let Some(window_id) = state
+                    .windows
+                    .iter()
+                    .find(|w| w.xdg_toplevel.id() == resource.id())
+                    .map(|w| w.id)
+                {
+                    state.set_maximize(window_id, false);
+                }
+            }
             xdg_toplevel::Request::SetFullscreen { .. } => {}
             xdg_toplevel::Request::UnsetFullscreen => {}
-            xdg_toplevel::Request::SetMinimized => {}
+            xdg_toplevel::Request::SetMinimized => {
+                if let Some(window_id) = state
+                    .windows
+                    .iter()
+                    .find(|w| w.xdg_toplevel.id() == resource.id())
+                    .map(|w| w.id)
+                {
+                    state.minimize_window(window_id);
+                }
+            }
             _ => {}
         }
     }
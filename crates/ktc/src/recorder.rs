@@ -0,0 +1,298 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::config::RecordingEncoder;
+
+// frames waiting for the writer; past this the main loop drops new ones
+// rather than block on a slow disk or encoder
+const QUEUE_DEPTH: usize = 4;
+
+// Where recorded frames go. Frames are upright ARGB rows of the size the
+// sink was created with.
+pub trait FrameSink: Send {
+    fn write_frame(&mut self, pixels: &[u32]) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+struct Y4mSink {
+    out: BufWriter<File>,
+    planes: Vec<u8>,
+}
+
+impl Y4mSink {
+    fn create(path: &Path, width: i32, height: i32, framerate: u32) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        // 4:4:4 keeps the writer a plain per-pixel conversion
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+            width, height, framerate
+        )?;
+        Ok(Self {
+            out,
+            planes: vec![0; width as usize * height as usize * 3],
+        })
+    }
+}
+
+impl FrameSink for Y4mSink {
+    fn write_frame(&mut self, pixels: &[u32]) -> io::Result<()> {
+        let (y_plane, chroma) = self.planes.split_at_mut(pixels.len());
+        let (u_plane, v_plane) = chroma.split_at_mut(pixels.len());
+        for (i, &pixel) in pixels.iter().enumerate() {
+            // BT.601, limited range
+            let r = ((pixel >> 16) & 0xFF) as i32;
+            let g = ((pixel >> 8) & 0xFF) as i32;
+            let b = (pixel & 0xFF) as i32;
+            y_plane[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            u_plane[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v_plane[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&self.planes)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+struct FfmpegSink {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    bytes: Vec<u8>,
+}
+
+impl FfmpegSink {
+    fn spawn(
+        path: &Path,
+        width: i32,
+        height: i32,
+        framerate: u32,
+        args: &[String],
+    ) -> io::Result<Self> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pixel_format", "bgr0"])
+            .args(["-video_size", &format!("{}x{}", width, height)])
+            .args(["-framerate", &framerate.to_string()])
+            .args(["-i", "-"])
+            .args(args)
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| io::Error::other("ffmpeg has no stdin"))?;
+        Ok(Self {
+            child,
+            stdin: BufWriter::new(stdin),
+            bytes: Vec::new(),
+        })
+    }
+}
+
+impl FrameSink for FfmpegSink {
+    fn write_frame(&mut self, pixels: &[u32]) -> io::Result<()> {
+        // ARGB words in memory order are bgr0
+        self.bytes.clear();
+        self.bytes
+            .extend(pixels.iter().flat_map(|p| p.to_le_bytes()));
+        self.stdin.write_all(&self.bytes)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.stdin.flush()?;
+        drop(self.stdin);
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("ffmpeg exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+enum Message {
+    // slot is the frame's position in the output at the recording framerate
+    Frame { slot: u64, pixels: Vec<u32> },
+    End { slot: u64 },
+}
+
+// Records the output to a file. The main loop offers it a frame whenever one
+// is due; a worker thread does the conversion and writing.
+pub struct Recorder {
+    path: PathBuf,
+    width: i32,
+    height: i32,
+    framerate: u32,
+    started: Instant,
+    next_slot: u64,
+    dropped: u64,
+    frames: Option<SyncSender<Message>>,
+    worker: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Recorder {
+    pub fn start(
+        encoder: RecordingEncoder,
+        path: PathBuf,
+        width: i32,
+        height: i32,
+        framerate: u32,
+        ffmpeg_args: &[String],
+    ) -> io::Result<Self> {
+        let framerate = framerate.clamp(1, 240);
+        let sink_path = path.clone();
+        let ffmpeg_args = ffmpeg_args.to_vec();
+
+        let (frames, frame_rx) = mpsc::sync_channel(QUEUE_DEPTH);
+        // the sink is opened by the worker: opening a FIFO blocks until
+        // something reads it. A bad path or a missing ffmpeg ends the worker,
+        // which failed() reports.
+        let worker = std::thread::Builder::new()
+            .name("ktc-recorder".to_string())
+            .spawn(move || {
+                let sink: Box<dyn FrameSink> = match encoder {
+                    RecordingEncoder::Y4m => {
+                        Box::new(Y4mSink::create(&sink_path, width, height, framerate)?)
+                    }
+                    RecordingEncoder::Ffmpeg => Box::new(FfmpegSink::spawn(
+                        &sink_path,
+                        width,
+                        height,
+                        framerate,
+                        &ffmpeg_args,
+                    )?),
+                };
+                write_frames(sink, frame_rx)
+            })?;
+
+        Ok(Self {
+            path,
+            width,
+            height,
+            framerate,
+            started: Instant::now(),
+            next_slot: 0,
+            dropped: 0,
+            frames: Some(frames),
+            worker: Some(worker),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn current_slot(&self) -> u64 {
+        (self.started.elapsed().as_secs_f64() * self.framerate as f64) as u64
+    }
+
+    pub fn frame_due(&self) -> bool {
+        self.current_slot() >= self.next_slot
+    }
+
+    pub fn next_frame_at(&self) -> Instant {
+        self.started + Duration::from_secs_f64(self.next_slot as f64 / self.framerate as f64)
+    }
+
+    // the writer gave up, e.g. the disk filled or ffmpeg died
+    pub fn failed(&self) -> bool {
+        self.worker.as_ref().is_some_and(|w| w.is_finished())
+    }
+
+    pub fn push_frame(&mut self, pixels: Vec<u32>) {
+        let slot = self.current_slot();
+        self.next_slot = slot + 1;
+        let Some(ref frames) = self.frames else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = frames.try_send(Message::Frame { slot, pixels }) {
+            self.dropped += 1;
+        }
+    }
+
+    // Ends the recording without waiting for the writer to drain; the file
+    // is complete once the returned handle is done
+    pub fn stop(mut self) -> FinishingRecording {
+        let slot = self.current_slot() + 1;
+        if let Some(frames) = self.frames.take() {
+            // never blocks on a busy writer; with a full queue, dropping the
+            // sender ends the recording after the queued frames instead
+            let _ = frames.try_send(Message::End { slot });
+        }
+        if self.dropped > 0 {
+            log::info!(
+                "[recording] {} frames dropped while the writer was busy",
+                self.dropped
+            );
+        }
+        FinishingRecording {
+            path: self.path,
+            worker: self.worker.take(),
+        }
+    }
+}
+
+// A stopped recording whose writer is still flushing, or ffmpeg still
+// encoding, on its own thread
+pub struct FinishingRecording {
+    path: PathBuf,
+    worker: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl FinishingRecording {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.worker.as_ref().is_none_or(|w| w.is_finished())
+    }
+
+    // the writer's result; blocks until it is done
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.worker.take().map(|w| w.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("recorder thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_frames(mut sink: Box<dyn FrameSink>, frames: Receiver<Message>) -> io::Result<()> {
+    let mut last: Option<Vec<u32>> = None;
+    let mut next_slot = 0;
+    while let Ok(message) = frames.recv() {
+        let (slot, pixels) = match message {
+            Message::Frame { slot, pixels } => (slot, Some(pixels)),
+            Message::End { slot } => (slot, None),
+        };
+        // nothing is drawn while the screen is still; repeat the last frame
+        // so the recording keeps real time
+        if let Some(ref previous) = last {
+            while next_slot < slot {
+                sink.write_frame(previous)?;
+                next_slot += 1;
+            }
+        }
+        let Some(pixels) = pixels else {
+            break;
+        };
+        sink.write_frame(&pixels)?;
+        next_slot = slot + 1;
+        last = Some(pixels);
+    }
+    sink.finish()
+}
@@ -0,0 +1,826 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use calloop::ping::Ping;
+use smithay::backend::allocator::dmabuf::Dmabuf;
+use smithay::backend::egl::{EGLContext, EGLDisplay};
+use smithay::backend::renderer::gles::{
+    ffi, GlesRenderer, GlesTexProgram, GlesTexture, Uniform, UniformName, UniformType,
+};
+use smithay::backend::renderer::{
+    Bind, BlitFrame, Color32F, Frame, ImportDma, Renderer, Texture, TextureFilter,
+};
+use smithay::utils::{Logical, Physical, Point, Rectangle, Size, Transform};
+
+// BT.709 limited range; chroma planes are bound to texture units 1 and 2
+const YUV_SHADER: &str = r#"#version 100
+
+//_DEFINES_
+#if defined(EXTERNAL)
+#extension GL_OES_EGL_image_external : require
+#endif
+
+precision mediump float;
+#if defined(EXTERNAL)
+uniform samplerExternalOES tex;
+#else
+uniform sampler2D tex;
+#endif
+uniform sampler2D tex_u;
+uniform sampler2D tex_v;
+uniform float planar;
+uniform float alpha;
+varying vec2 v_coords;
+
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+void main() {
+    float y = texture2D(tex, v_coords).r;
+    vec2 uv = texture2D(tex_u, v_coords).rg;
+    if (planar > 0.5) {
+        uv.g = texture2D(tex_v, v_coords).r;
+    }
+
+    y = 1.1643 * (y - 0.0625);
+    uv = uv - 0.5;
+    vec3 rgb = vec3(
+        y + 1.7927 * uv.g,
+        y - 0.2132 * uv.r - 0.5329 * uv.g,
+        y + 2.1124 * uv.r
+    );
+    vec4 color = vec4(clamp(rgb, 0.0, 1.0), 1.0) * alpha;
+
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        color = vec4(0.0, 0.3, 0.0, 0.2) + color * 0.8;
+#endif
+
+    gl_FragColor = color;
+}
+"#;
+
+// Final pass for night light: scales each channel of the composited frame
+const COLOR_SHADER: &str = r#"#version 100
+
+//_DEFINES_
+#if defined(EXTERNAL)
+#extension GL_OES_EGL_image_external : require
+#endif
+
+precision mediump float;
+#if defined(EXTERNAL)
+uniform samplerExternalOES tex;
+#else
+uniform sampler2D tex;
+#endif
+uniform vec3 gain;
+uniform float alpha;
+varying vec2 v_coords;
+
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+void main() {
+    vec4 color = texture2D(tex, v_coords);
+#if defined(NO_ALPHA)
+    color = vec4(color.rgb, 1.0);
+#endif
+    color = vec4(color.rgb * gain, color.a) * alpha;
+
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        color = vec4(0.0, 0.3, 0.0, 0.2) + color * 0.8;
+#endif
+
+    gl_FragColor = color;
+}
+"#;
+
+// Dual kawase blur: each down pass halves the size, each up pass doubles it
+// back; halfpixel is half a source texel scaled by the blur offset
+const BLUR_DOWN_SHADER: &str = r#"#version 100
+
+//_DEFINES_
+#if defined(EXTERNAL)
+#extension GL_OES_EGL_image_external : require
+#endif
+
+precision mediump float;
+#if defined(EXTERNAL)
+uniform samplerExternalOES tex;
+#else
+uniform sampler2D tex;
+#endif
+uniform vec2 halfpixel;
+uniform float alpha;
+varying vec2 v_coords;
+
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+void main() {
+    vec4 sum = texture2D(tex, v_coords) * 4.0;
+    sum += texture2D(tex, v_coords - halfpixel);
+    sum += texture2D(tex, v_coords + halfpixel);
+    sum += texture2D(tex, v_coords + vec2(halfpixel.x, -halfpixel.y));
+    sum += texture2D(tex, v_coords - vec2(halfpixel.x, -halfpixel.y));
+    vec4 color = vec4((sum / 8.0).rgb, 1.0) * alpha;
+
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        color = vec4(0.0, 0.3, 0.0, 0.2) + color * 0.8;
+#endif
+
+    gl_FragColor = color;
+}
+"#;
+
+const BLUR_UP_SHADER: &str = r#"#version 100
+
+//_DEFINES_
+#if defined(EXTERNAL)
+#extension GL_OES_EGL_image_external : require
+#endif
+
+precision mediump float;
+#if defined(EXTERNAL)
+uniform samplerExternalOES tex;
+#else
+uniform sampler2D tex;
+#endif
+uniform vec2 halfpixel;
+uniform float alpha;
+varying vec2 v_coords;
+
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+void main() {
+    vec4 sum = texture2D(tex, v_coords + vec2(-halfpixel.x * 2.0, 0.0));
+    sum += texture2D(tex, v_coords + vec2(-halfpixel.x, halfpixel.y)) * 2.0;
+    sum += texture2D(tex, v_coords + vec2(0.0, halfpixel.y * 2.0));
+    sum += texture2D(tex, v_coords + vec2(halfpixel.x, halfpixel.y)) * 2.0;
+    sum += texture2D(tex, v_coords + vec2(halfpixel.x * 2.0, 0.0));
+    sum += texture2D(tex, v_coords + vec2(halfpixel.x, -halfpixel.y)) * 2.0;
+    sum += texture2D(tex, v_coords + vec2(0.0, -halfpixel.y * 2.0));
+    sum += texture2D(tex, v_coords + vec2(-halfpixel.x, -halfpixel.y)) * 2.0;
+    vec4 color = vec4((sum / 12.0).rgb, 1.0) * alpha;
+
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        color = vec4(0.0, 0.3, 0.0, 0.2) + color * 0.8;
+#endif
+
+    gl_FragColor = color;
+}
+"#;
+
+// Custom shaders, compiled once per GL context since programs aren't shared
+// between contexts the way textures are
+pub struct Programs {
+    pub yuv: Option<GlesTexProgram>,
+    pub color: Option<GlesTexProgram>,
+    // down and up
+    pub blur: Option<(GlesTexProgram, GlesTexProgram)>,
+}
+
+impl Programs {
+    pub fn compile(renderer: &mut GlesRenderer) -> Self {
+        let yuv = renderer
+            .compile_custom_texture_shader(
+                YUV_SHADER,
+                &[
+                    UniformName::new("tex_u", UniformType::_1i),
+                    UniformName::new("tex_v", UniformType::_1i),
+                    UniformName::new("planar", UniformType::_1f),
+                ],
+            )
+            .map_err(|e| log::warn!("[gpu] YUV shader failed to compile: {:?}", e))
+            .ok();
+
+        let color = renderer
+            .compile_custom_texture_shader(
+                COLOR_SHADER,
+                &[UniformName::new("gain", UniformType::_3f)],
+            )
+            .map_err(|e| log::warn!("[gpu] Color shader failed to compile: {:?}", e))
+            .ok();
+
+        let halfpixel = || [UniformName::new("halfpixel", UniformType::_2f)];
+        let blur = renderer
+            .compile_custom_texture_shader(BLUR_DOWN_SHADER, &halfpixel())
+            .and_then(|down| {
+                let up = renderer.compile_custom_texture_shader(BLUR_UP_SHADER, &halfpixel())?;
+                Ok((down, up))
+            })
+            .map_err(|e| log::warn!("[gpu] Blur shaders failed to compile: {:?}", e))
+            .ok();
+
+        Self { yuv, color, blur }
+    }
+}
+
+// One step of a frame, in physical pixels with textures already looked up
+pub enum DrawCommand {
+    Clear {
+        rect: Rectangle<i32, Physical>,
+        color: [f32; 4],
+    },
+    // blurs what has been drawn so far behind this rect
+    Blur {
+        rect: Rectangle<i32, Physical>,
+    },
+    Texture {
+        texture: GlesTexture,
+        dst: Rectangle<i32, Physical>,
+        // crop in upright buffer pixels; None samples the whole texture
+        src: Option<[f64; 4]>,
+        alpha: f32,
+        // the client's buffer transform
+        transform: Transform,
+    },
+    // luma plane first
+    Yuv {
+        planes: Vec<GlesTexture>,
+        dst: Rectangle<i32, Physical>,
+        alpha: f32,
+        transform: Transform,
+    },
+}
+
+pub struct BlurLevels {
+    // blur_textures[i] is 1 / 2^(i+1) of the output size
+    pub textures: Vec<GlesTexture>,
+    pub offset: f32,
+}
+
+// Everything needed to draw a frame into a render buffer, so it can be drawn
+// on the render thread while the main loop goes on
+pub struct FrameJob {
+    pub target: Dmabuf,
+    pub output_size: Size<i32, Physical>,
+    pub transform: Transform,
+    pub damage: Vec<Rectangle<i32, Physical>>,
+    pub commands: Vec<DrawCommand>,
+    // held back to go on after the cursorless copy
+    pub cursor: Option<DrawCommand>,
+    // the night light pass: offscreen buffer the frame is composited into,
+    // then copied over with this gain
+    pub color: Option<(GlesTexture, [f32; 3])>,
+    pub blur: Option<BlurLevels>,
+    pub cursorless: Option<GlesTexture>,
+}
+
+// Draws the frame, returning whether the cursorless copy was made
+// Returns whether the cursorless copy was kept, or None when the frame
+// couldn't be drawn
+pub fn composite(
+    renderer: &mut GlesRenderer,
+    programs: &Programs,
+    mut job: FrameJob,
+) -> Option<bool> {
+    let output_size = job.output_size;
+    let damage = &job.damage;
+    // the part of the damage that falls inside dst, relative to dst
+    let clip = |dst: Rectangle<i32, Physical>| -> Vec<Rectangle<i32, Physical>> {
+        damage
+            .iter()
+            .filter_map(|d| d.intersection(dst))
+            .map(|r| Rectangle::new(r.loc - dst.loc, r.size))
+            .collect()
+    };
+
+    // blurs read back what the frame has drawn so far
+    let blur_source = match (job.blur.as_ref(), job.color.as_ref()) {
+        (None, _) => None,
+        (Some(_), Some((buffer, _))) => Some(buffer.clone()),
+        (Some(_), None) => renderer.import_dmabuf(&job.target, None).ok(),
+    };
+    let mut color_buffer = job.color.as_ref().map(|(buffer, _)| buffer.clone());
+
+    // blurs split the commands: the frame so far is finished, blurred
+    // offscreen, and the next frame starts by drawing the result
+    let mut remaining = &job.commands[..];
+    let mut blurred: Option<Rectangle<i32, Physical>> = None;
+    loop {
+        let split = remaining
+            .iter()
+            .position(|cmd| matches!(cmd, DrawCommand::Blur { .. }))
+            .unwrap_or(remaining.len());
+        let (commands, rest) = remaining.split_at(split);
+
+        // with a color pass the frame is composited offscreen first
+        let Ok(mut target) = (match color_buffer.as_mut() {
+            Some(buffer) => renderer.bind(buffer),
+            None => renderer.bind(&mut job.target),
+        }) else {
+            return None;
+        };
+        let Ok(mut frame) = renderer.render(&mut target, output_size, job.transform) else {
+            return None;
+        };
+        if let (Some(rect), Some((_, up)), Some(levels)) =
+            (blurred.take(), programs.blur.as_ref(), job.blur.as_ref())
+        {
+            let level = &levels.textures[0];
+            let size = level.size();
+            let src = Rectangle::new(
+                Point::from((rect.loc.x as f64 / 2.0, rect.loc.y as f64 / 2.0)),
+                Size::from((rect.size.w as f64 / 2.0, rect.size.h as f64 / 2.0)),
+            );
+            let halfpixel = [
+                levels.offset * 0.5 / size.w as f32,
+                levels.offset * 0.5 / size.h as f32,
+            ];
+            let _ = frame.render_texture_from_to(
+                level,
+                src,
+                rect,
+                &clip(rect),
+                &[Rectangle::from_size(rect.size)],
+                Transform::Normal,
+                1.0,
+                Some(up),
+                &[Uniform::new("halfpixel", halfpixel)],
+            );
+        }
+        for cmd in commands {
+            match cmd {
+                DrawCommand::Blur { .. } => {}
+                DrawCommand::Clear { rect, color } => {
+                    let rect_damage = clip(*rect);
+                    if rect_damage.is_empty() {
+                        continue;
+                    }
+                    let color = Color32F::from(*color);
+                    if !color.is_opaque() {
+                        let _ = frame.draw_solid(*rect, &rect_damage, color);
+                    } else {
+                        // clear takes target coordinates
+                        let at: Vec<_> = rect_damage
+                            .iter()
+                            .map(|r| Rectangle::new(r.loc + rect.loc, r.size))
+                            .collect();
+                        let _ = frame.clear(color, &at);
+                    }
+                }
+                DrawCommand::Yuv {
+                    planes,
+                    dst,
+                    alpha,
+                    transform,
+                } => {
+                    let dst_damage = clip(*dst);
+                    if dst_damage.is_empty() {
+                        continue;
+                    }
+                    let Some(program) = programs.yuv.as_ref() else {
+                        continue;
+                    };
+                    let luma = &planes[0];
+                    let chroma: Vec<_> = planes[1..].iter().map(|t| t.tex_id()).collect();
+                    let _ = frame.with_context(|gl| unsafe {
+                        for (unit, tex) in chroma.iter().enumerate() {
+                            gl.ActiveTexture(ffi::TEXTURE1 + unit as u32);
+                            gl.BindTexture(ffi::TEXTURE_2D, *tex);
+                            gl.TexParameteri(
+                                ffi::TEXTURE_2D,
+                                ffi::TEXTURE_MIN_FILTER,
+                                ffi::LINEAR as i32,
+                            );
+                            gl.TexParameteri(
+                                ffi::TEXTURE_2D,
+                                ffi::TEXTURE_MAG_FILTER,
+                                ffi::LINEAR as i32,
+                            );
+                        }
+                        gl.ActiveTexture(ffi::TEXTURE0);
+                    });
+
+                    let size = luma.size();
+                    let src = Rectangle::new(
+                        Point::from((0.0, 0.0)),
+                        Size::from((size.w as f64, size.h as f64)),
+                    );
+                    let opaque_regions = if *alpha < 1.0 {
+                        Vec::new()
+                    } else {
+                        vec![Rectangle::from_size(dst.size)]
+                    };
+                    let _ = frame.render_texture_from_to(
+                        luma,
+                        src,
+                        *dst,
+                        &dst_damage,
+                        &opaque_regions,
+                        *transform,
+                        *alpha,
+                        Some(program),
+                        &[
+                            Uniform::new("tex_u", 1),
+                            Uniform::new("tex_v", 2),
+                            Uniform::new("planar", if chroma.len() > 1 { 1.0 } else { 0.0 }),
+                        ],
+                    );
+                }
+                DrawCommand::Texture {
+                    texture,
+                    dst,
+                    src,
+                    alpha,
+                    transform,
+                } => {
+                    let damage = clip(*dst);
+                    if damage.is_empty() {
+                        continue;
+                    }
+                    let tex_size = texture.size();
+                    let upright = transform.transform_size(Size::<f64, Logical>::from((
+                        tex_size.w as f64,
+                        tex_size.h as f64,
+                    )));
+                    let [src_x, src_y, src_w, src_h] =
+                        src.unwrap_or([0.0, 0.0, upright.w, upright.h]);
+                    let src = Rectangle::<f64, Logical>::new(
+                        (src_x, src_y).into(),
+                        (src_w, src_h).into(),
+                    )
+                    .to_buffer(1.0, *transform, &upright);
+                    let _ = frame.render_texture_from_to(
+                        texture,
+                        src,
+                        *dst,
+                        &damage,
+                        &[],
+                        *transform,
+                        *alpha,
+                        None,
+                        &[],
+                    );
+                }
+            }
+        }
+        if frame.finish().is_err() {
+            return None;
+        }
+
+        let Some((blur, rest)) = rest.split_first() else {
+            break;
+        };
+        remaining = rest;
+        let DrawCommand::Blur { rect } = *blur else {
+            continue;
+        };
+        let (Some(source), Some(programs), Some(levels)) = (
+            blur_source.as_ref(),
+            programs.blur.as_ref(),
+            job.blur.as_mut(),
+        ) else {
+            continue;
+        };
+        if clip(rect).is_empty() {
+            continue;
+        }
+        let reach = (levels.offset * (2 << levels.textures.len()) as f32).ceil() as i32;
+        let region = Rectangle::new(
+            rect.loc - Point::from((reach, reach)),
+            rect.size + Size::from((2 * reach, 2 * reach)),
+        );
+        let Some(region) = region.intersection(Rectangle::from_size(output_size)) else {
+            continue;
+        };
+        let rect = rect.intersection(region).unwrap_or(rect);
+        if blur_passes(
+            renderer,
+            programs,
+            source,
+            &mut levels.textures,
+            region,
+            levels.offset,
+        ) {
+            blurred = Some(rect);
+        }
+    }
+
+    if let (Some((buffer, gain)), Some(program)) = (job.color.as_ref(), programs.color.as_ref()) {
+        draw_color_pass(
+            renderer,
+            program,
+            buffer,
+            *gain,
+            &mut job.target,
+            output_size,
+        );
+    }
+
+    let kept_cursorless = match job.cursorless.as_mut() {
+        Some(buffer) => copy_cursorless(renderer, &mut job.target, buffer, output_size),
+        None => false,
+    };
+    if let Some(DrawCommand::Texture {
+        texture,
+        dst,
+        alpha,
+        ..
+    }) = job.cursor.as_ref()
+    {
+        // it still gets the color pass's gain
+        let color = job
+            .color
+            .as_ref()
+            .zip(programs.color.as_ref())
+            .map(|((_, gain), program)| (program, *gain));
+        draw_late_cursor(
+            renderer,
+            &mut job.target,
+            output_size,
+            job.transform,
+            (texture, *dst, *alpha),
+            color,
+        );
+    }
+    Some(kept_cursorless)
+}
+
+fn draw_color_pass(
+    renderer: &mut GlesRenderer,
+    program: &GlesTexProgram,
+    buffer: &GlesTexture,
+    gain: [f32; 3],
+    target: &mut Dmabuf,
+    output_size: Size<i32, Physical>,
+) {
+    let Ok(mut target) = renderer.bind(target) else {
+        return;
+    };
+    // the offscreen frame is already transformed, so this is a 1:1 copy
+    let Ok(mut frame) = renderer.render(&mut target, output_size, Transform::Normal) else {
+        return;
+    };
+    let src = Rectangle::new(
+        Point::from((0.0, 0.0)),
+        Size::from((output_size.w as f64, output_size.h as f64)),
+    );
+    let dst = Rectangle::new(Point::from((0, 0)), output_size);
+    let _ = frame.render_texture_from_to(
+        buffer,
+        src,
+        dst,
+        &[dst],
+        &[dst],
+        Transform::Normal,
+        1.0,
+        Some(program),
+        &[Uniform::new("gain", gain)],
+    );
+    let _ = frame.finish();
+}
+
+// Copies the frame as it stands into the cursorless buffer
+fn copy_cursorless(
+    renderer: &mut GlesRenderer,
+    target: &mut Dmabuf,
+    buffer: &mut GlesTexture,
+    output_size: Size<i32, Physical>,
+) -> bool {
+    let Ok(source) = renderer.bind(target) else {
+        return false;
+    };
+    let Ok(mut target) = renderer.bind(buffer) else {
+        return false;
+    };
+    let Ok(mut frame) = renderer.render(&mut target, output_size, Transform::Normal) else {
+        return false;
+    };
+    let area = Rectangle::from_size(output_size);
+    let copied = frame
+        .blit_from(&source, area, area, TextureFilter::Nearest)
+        .is_ok();
+    frame.finish().is_ok() && copied
+}
+
+// the cursor held back from the frame, drawn straight into the scanout buffer
+fn draw_late_cursor(
+    renderer: &mut GlesRenderer,
+    target: &mut Dmabuf,
+    output_size: Size<i32, Physical>,
+    transform: Transform,
+    (texture, dst, alpha): (&GlesTexture, Rectangle<i32, Physical>, f32),
+    color: Option<(&GlesTexProgram, [f32; 3])>,
+) {
+    let Ok(mut target) = renderer.bind(target) else {
+        return;
+    };
+    let Ok(mut frame) = renderer.render(&mut target, output_size, transform) else {
+        return;
+    };
+    let size = texture.size();
+    let src = Rectangle::new(
+        Point::from((0.0, 0.0)),
+        Size::from((size.w as f64, size.h as f64)),
+    );
+    let (program, gain) = match color {
+        Some((program, gain)) => (Some(program), gain),
+        None => (None, [1.0; 3]),
+    };
+    let _ = frame.render_texture_from_to(
+        texture,
+        src,
+        dst,
+        &[Rectangle::from_size(dst.size)],
+        &[],
+        Transform::Normal,
+        alpha,
+        program,
+        &[Uniform::new("gain", gain)],
+    );
+    let _ = frame.finish();
+}
+
+// Composites frames on a worker thread with its own GL context sharing
+// textures with the renderer's, so a slow frame doesn't hold up Wayland
+// dispatch and input. The main loop is pinged when a frame is done.
+pub struct RenderThread {
+    jobs: Option<Sender<FrameJob>>,
+    // whether the cursorless copy was kept, None if the frame wasn't drawn,
+    // and how long it took
+    done: Receiver<(Option<bool>, Duration)>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl RenderThread {
+    pub fn new(display: &EGLDisplay, share: &EGLContext, ping: Ping) -> Result<Self, String> {
+        let context = EGLContext::new_shared(display, share)
+            .map_err(|e| format!("shared EGL context failed: {:?}", e))?;
+
+        let (jobs, job_rx) = mpsc::channel::<FrameJob>();
+        let (done_tx, done) = mpsc::channel();
+
+        let worker = std::thread::Builder::new()
+            .name("ktc-render".to_string())
+            .spawn(move || {
+                let mut renderer = match unsafe { GlesRenderer::new(context) } {
+                    Ok(r) => r,
+                    Err(e) => {
+                        log::error!("[render] Worker renderer failed: {:?}", e);
+                        return;
+                    }
+                };
+                let programs = Programs::compile(&mut renderer);
+
+                while let Ok(job) = job_rx.recv() {
+                    let start = Instant::now();
+                    let drawn = composite(&mut renderer, &programs, job);
+                    // the frame is scanned out and read back from the main
+                    // thread as soon as it is reported
+                    let _ = renderer.with_context(|gl| unsafe { gl.Finish() });
+                    if done_tx.send((drawn, start.elapsed())).is_err() {
+                        return;
+                    }
+                    ping.ping();
+                }
+            })
+            .map_err(|e| format!("failed to spawn render thread: {}", e))?;
+
+        Ok(Self {
+            jobs: Some(jobs),
+            done,
+            worker: Some(worker),
+        })
+    }
+
+    // hands the job back when the worker is gone
+    pub fn submit(&mut self, job: FrameJob) -> Result<(), Box<FrameJob>> {
+        let Some(ref jobs) = self.jobs else {
+            return Err(Box::new(job));
+        };
+        jobs.send(job).map_err(|e| {
+            log::warn!("[render] Worker gone, compositing on the main thread");
+            self.jobs = None;
+            Box::new(e.0)
+        })
+    }
+
+    // None while the submitted frame is still being drawn
+    pub fn try_finished(&self) -> Option<Finished> {
+        match self.done.try_recv() {
+            Ok(done) => Some(Finished::from(done)),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Finished::Lost),
+        }
+    }
+
+    pub fn wait(&self) -> Finished {
+        match self.done.recv() {
+            Ok(done) => Finished::from(done),
+            Err(_) => Finished::Lost,
+        }
+    }
+}
+
+pub enum Finished {
+    Drawn {
+        kept_cursorless: bool,
+        // compositing until the GPU was done with the frame
+        gpu_time: Duration,
+    },
+    // rendering into the buffer failed, nothing is to be presented
+    Failed,
+    // the worker died before drawing it
+    Lost,
+}
+
+impl From<(Option<bool>, Duration)> for Finished {
+    fn from((kept_cursorless, gpu_time): (Option<bool>, Duration)) -> Self {
+        match kept_cursorless {
+            Some(kept_cursorless) => Finished::Drawn {
+                kept_cursorless,
+                gpu_time,
+            },
+            None => Finished::Failed,
+        }
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+// rect at 1 / 2^level of its size, rounded outwards
+fn scale_down(rect: Rectangle<i32, Physical>, level: usize) -> Rectangle<i32, Physical> {
+    let div = 1 << level;
+    let x1 = rect.loc.x / div;
+    let y1 = rect.loc.y / div;
+    let x2 = (rect.loc.x + rect.size.w + div - 1) / div;
+    let y2 = (rect.loc.y + rect.size.h + div - 1) / div;
+    Rectangle::new(Point::from((x1, y1)), Size::from((x2 - x1, y2 - y1)))
+}
+
+// Blurs region of source into blur_textures[0] at half size: down through
+// every level, then back up to the first
+fn blur_passes(
+    renderer: &mut GlesRenderer,
+    (down, up): &(GlesTexProgram, GlesTexProgram),
+    source: &GlesTexture,
+    levels: &mut [GlesTexture],
+    region: Rectangle<i32, Physical>,
+    offset: f32,
+) -> bool {
+    let pass = |renderer: &mut GlesRenderer,
+                from: &GlesTexture,
+                from_level: usize,
+                to: &mut GlesTexture,
+                to_level: usize,
+                program: &GlesTexProgram| {
+        let src = scale_down(region, from_level);
+        let dst = scale_down(region, to_level);
+        let src = Rectangle::new(
+            Point::from((src.loc.x as f64, src.loc.y as f64)),
+            Size::from((src.size.w as f64, src.size.h as f64)),
+        );
+        let size = from.size();
+        let halfpixel = [offset * 0.5 / size.w as f32, offset * 0.5 / size.h as f32];
+        let to_size = Size::from((to.size().w, to.size().h));
+        let mut target = renderer.bind(to)?;
+        let mut frame = renderer.render(&mut target, to_size, Transform::Normal)?;
+        frame.render_texture_from_to(
+            from,
+            src,
+            dst,
+            &[Rectangle::from_size(dst.size)],
+            &[Rectangle::from_size(dst.size)],
+            Transform::Normal,
+            1.0,
+            Some(program),
+            &[Uniform::new("halfpixel", halfpixel)],
+        )?;
+        frame.finish().map(|_| ())
+    };
+
+    let result = (|| {
+        pass(renderer, source, 0, &mut levels[0], 1, down)?;
+        for i in 1..levels.len() {
+            let from = levels[i - 1].clone();
+            pass(renderer, &from, i, &mut levels[i], i + 1, down)?;
+        }
+        for i in (1..levels.len()).rev() {
+            let from = levels[i].clone();
+            pass(renderer, &from, i + 1, &mut levels[i - 1], i, up)?;
+        }
+        Ok::<_, smithay::backend::renderer::gles::GlesError>(())
+    })();
+    result
+        .map_err(|e| log::warn!("[gpu] Blur failed: {:?}", e))
+        .is_ok()
+}
@@ -1,5 +1,7 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
 
 use smithay::backend::allocator::dmabuf::Dmabuf;
 use smithay::backend::allocator::gbm::GbmDevice;
@@ -7,16 +9,98 @@ use smithay::backend::allocator::Fourcc;
 use smithay::backend::egl::context::{GlAttributes, PixelFormatRequirements};
 use smithay::backend::egl::{EGLContext, EGLDisplay};
 use smithay::backend::renderer::gles::{GlesRenderer, GlesTexture};
-use smithay::backend::renderer::{Bind, ExportMem, Frame, ImportDma, ImportMem, Renderer, Texture};
+use smithay::backend::renderer::sync::SyncPoint;
+use smithay::backend::renderer::{
+    Bind, BlitFrame, ExportMem, Frame, ImportDma, ImportMem, Offscreen, Renderer, Texture,
+    TextureFilter,
+};
 use smithay::utils::Buffer as BufferCoord;
-use smithay::backend::renderer::Color32F;
-use smithay::utils::{Point, Rectangle, Size, Transform};
+use smithay::utils::{Physical, Point, Rectangle, Size, Transform};
+
+use crate::cursor::Cursor;
+use crate::kms::AtomicOutput;
+use crate::render_thread::{
+    self, BlurLevels, DrawCommand, Finished, FrameJob, Programs, RenderThread,
+};
+use crate::upload::TextureUploader;
+use crate::wallpaper::Wallpaper;
 
 use drm::control::{connector, crtc, framebuffer, Device as ControlDevice};
 use drm_fourcc::{DrmFourcc, DrmModifier};
+use wayland_server::backend::ObjectId;
 
 use smithay::reexports::gbm::{BufferObject, BufferObjectFlags};
 
+pub const LAYER_TEXTURE_OFFSET: u64 = 1_000_000;
+pub const TITLE_TEXTURE_OFFSET: u64 = 2_000_000;
+const CURSOR_TEXTURE_ID: u64 = u64::MAX - 1;
+const PROFILER_TEXTURE_ID: u64 = u64::MAX - 2;
+const BANNER_TEXTURE_ID: u64 = u64::MAX - 3;
+const HELP_TEXTURE_ID: u64 = u64::MAX - 4;
+const WALLPAPER_TEXTURE_ID: u64 = u64::MAX - 5;
+
+// luma plane format, chroma plane format, chroma plane count
+fn yuv_plane_formats(format: u32) -> Option<(DrmFourcc, DrmFourcc, usize)> {
+    match DrmFourcc::try_from(format).ok()? {
+        DrmFourcc::Nv12 => Some((DrmFourcc::R8, DrmFourcc::Gr88, 1)),
+        DrmFourcc::P010 => Some((DrmFourcc::R16, DrmFourcc::Gr1616, 1)),
+        DrmFourcc::Yuv420 => Some((DrmFourcc::R8, DrmFourcc::R8, 2)),
+        _ => None,
+    }
+}
+
+pub fn is_yuv_format(format: u32) -> bool {
+    yuv_plane_formats(format).is_some()
+}
+
+// logical to physical; edges are rounded separately so neighbouring rects
+// still meet
+fn smithay_transform(transform: crate::state::OutputTransform) -> Transform {
+    use crate::state::OutputTransform;
+    match transform {
+        OutputTransform::Normal => Transform::Normal,
+        OutputTransform::Rotate90 => Transform::_90,
+        OutputTransform::Rotate180 => Transform::_180,
+        OutputTransform::Rotate270 => Transform::_270,
+        OutputTransform::Flipped => Transform::Flipped,
+        OutputTransform::FlippedRotate90 => Transform::Flipped90,
+        OutputTransform::FlippedRotate180 => Transform::Flipped180,
+        OutputTransform::FlippedRotate270 => Transform::Flipped270,
+    }
+}
+
+fn to_physical(scale: f64, x: i32, y: i32, width: i32, height: i32) -> Rectangle<i32, Physical> {
+    let x1 = (x as f64 * scale).round() as i32;
+    let y1 = (y as f64 * scale).round() as i32;
+    let x2 = ((x + width) as f64 * scale).round() as i32;
+    let y2 = ((y + height) as f64 * scale).round() as i32;
+    Rectangle::new(Point::from((x1, y1)), Size::from((x2 - x1, y2 - y1)))
+}
+
+// import_memory wants rows exactly width pixels long, so a buffer whose
+// stride has padding is copied without it
+fn packed_rows(data: &[u8], width: u32, height: u32, stride: u32) -> Cow<'_, [u8]> {
+    let row = width as usize * 4;
+    let stride = stride as usize;
+    if stride <= row {
+        return Cow::Borrowed(data);
+    }
+    let mut packed = Vec::with_capacity(row * height as usize);
+    for line in data.chunks(stride).take(height as usize) {
+        packed.extend_from_slice(&line[..row.min(line.len())]);
+    }
+    Cow::Owned(packed)
+}
+
+// Cumulative since the renderer was created, reported over IPC
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RendererCounters {
+    pub dmabuf_import_failures: u64,
+    // flips the driver refused or that didn't land within a frame
+    pub page_flip_misses: u64,
+    pub swapchain_recreations: u64,
+}
+
 #[derive(Clone, Debug)]
 pub struct DmaBufFormat {
     pub format: u32,
@@ -31,6 +115,13 @@ enum RenderCommand {
         height: i32,
         color: [f32; 4],
     },
+    // blurs what has been drawn so far behind this rect
+    Blur {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    },
     Texture {
         texture_id: u64,
         x: i32,
@@ -38,12 +129,25 @@ enum RenderCommand {
         width: i32,
         height: i32,
         is_dmabuf: bool,
+        // crop in upright buffer pixels; None samples the whole texture
+        src: Option<[f64; 4]>,
+        alpha: f32,
+        // wl_surface.set_buffer_transform of a dmabuf
+        transform: Transform,
+    },
+    Yuv {
+        buffer: ObjectId,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        alpha: f32,
+        transform: Transform,
     },
 }
 
 pub struct GpuRenderer {
     renderer: GlesRenderer,
-    #[allow(dead_code)]
     egl_display: EGLDisplay,
     drm_device: std::fs::File,
     drm_fd: i32,
@@ -54,25 +158,96 @@ pub struct GpuRenderer {
     physical_width: u32,
     physical_height: u32,
     mode: drm::control::Mode,
+    modes: Vec<drm::control::Mode>,
+    vrr_capable: bool,
+    vrr_property: Option<drm::control::property::Handle>,
+    vrr_enabled: bool,
+    transform: Transform,
+    // output scale; draw commands are in logical pixels
+    scale: f64,
+    // XRGB8888 or XRGB2101010; client buffers only go straight to scanout
+    // when they match, legacy page flips can't change the format
+    scanout_format: DrmFourcc,
+    // what the render buffers may be allocated with: the primary plane's
+    // modifiers for the format that EGL can also render to, or none to let
+    // GBM pick an implicit layout
+    scanout_modifiers: Vec<u64>,
     connector: connector::Handle,
     crtc: crtc::Handle,
+    // None drives the CRTC with legacy set_crtc and page_flip
+    atomic: Option<AtomicOutput>,
     render_buffers: [RenderBuffer; 2],
     current_buffer: usize,
     mode_set: bool,
+    // a page flip has gone through since the render buffers were made
+    flipped: bool,
+    // the last frame never reached the display and has to be drawn again
+    lost_frame: bool,
     flip_pending: bool,
     pending_fb: Option<framebuffer::Handle>,
     current_fb: Option<framebuffer::Handle>,
     shm_textures: HashMap<u64, GlesTexture>,
-    dmabuf_textures: HashMap<u64, GlesTexture>,
+    // client buffers by wl_buffer; protocol ids repeat across clients
+    dmabuf_textures: HashMap<ObjectId, GlesTexture>,
+    // one texture per plane, luma first
+    yuv_textures: HashMap<ObjectId, Vec<GlesTexture>>,
+    // dmabuf textures drawn this frame, by the id in their RenderCommand
+    frame_dmabufs: HashMap<u64, GlesTexture>,
+    programs: Programs,
+    // [1.0; 3] skips the color pass and draws straight into the scanout buffer
+    color_gain: [f32; 3],
+    color_buffer: Option<GlesTexture>,
+    // blur_textures[i] is 1 / 2^(i+1) of the mode size
+    blur_textures: Vec<GlesTexture>,
+    blur_passes: usize,
+    blur_offset: f32,
+    alpha: f32,
     render_commands: Vec<RenderCommand>,
+    // logical rects redrawn this frame; full_damage redraws everything
+    frame_damage: Vec<Rectangle<i32, Physical>>,
+    full_damage: bool,
+    // serial and frame of the cursor image in CURSOR_TEXTURE_ID
+    cursor_key: Option<(u64, usize)>,
+    cursor_dims: (i32, i32),
+    // serial of the wallpaper in WALLPAPER_TEXTURE_ID
+    wallpaper_key: Option<u64>,
+    // where the cursor was drawn this frame and the one before
+    cursor_rect: Option<Rectangle<i32, Physical>>,
+    last_cursor_rect: Option<Rectangle<i32, Physical>>,
+    // while a screencopy client asks for frames without the cursor, each
+    // frame is also copied here just before the cursor goes on
+    keep_cursorless: bool,
+    cursorless: Option<GlesTexture>,
+    // the copy matches the presented buffer
+    cursorless_ready: bool,
+    title_keys: HashMap<u64, (String, u32, usize)>,
+    // None marks a client buffer the display engine refused
+    scanout_fbs: HashMap<ObjectId, Option<framebuffer::Handle>>,
+    stale_scanout_fbs: Vec<framebuffer::Handle>,
+    uploader: Option<TextureUploader>,
+    // None composites on the main thread
+    render_thread: Option<RenderThread>,
+    // framebuffer of the frame the render thread is drawing, presented once
+    // it is done
+    in_flight: Option<framebuffer::Handle>,
+    // what the frame being drawn repairs in the current buffer; handed back
+    // to it when the frame never reaches the screen
+    frame_repairs: Vec<Rectangle<i32, Physical>>,
+    // for the profiler: how long the last frame took to composite and how
+    // many rectangles it redrew
+    last_gpu_time: Duration,
+    last_damage_rects: usize,
+    counters: RendererCounters,
     pub supported_formats: Vec<DmaBufFormat>,
 }
 
 struct RenderBuffer {
-    #[allow(dead_code)]
     bo: BufferObject<()>,
     dmabuf: Dmabuf,
     fb: Option<framebuffer::Handle>,
+    // what changed since this buffer was last drawn (its buffer age, in
+    // effect); None when its contents can't be trusted
+    damage: Option<Vec<Rectangle<i32, Physical>>>,
 }
 
 struct DrmCard(std::fs::File);
@@ -87,14 +262,16 @@ impl drm::Device for DrmCard {}
 impl ControlDevice for DrmCard {}
 
 impl GpuRenderer {
+    #[allow(dead_code)]
     pub fn new(drm_device: std::fs::File) -> Result<Self, Box<dyn std::error::Error>> {
-        Self::new_with_config(drm_device, None, true)
+        Self::new_with_config(drm_device, None, true, DrmFourcc::Xrgb8888)
     }
 
     pub fn new_with_config(
         drm_device: std::fs::File,
         preferred_mode: Option<(u16, u16, Option<u32>)>,
         _vsync: bool,
+        scanout_format: DrmFourcc,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let gbm = GbmDevice::new(drm_device.try_clone()?)?;
         let card = DrmCard(drm_device.try_clone()?);
@@ -168,7 +345,12 @@ impl GpuRenderer {
             mode.vrefresh()
         );
 
+        let modes = connector_info.modes().to_vec();
+        let vrr_capable = Self::query_vrr_capable(&card, connector_handle);
+        log::info!("[gpu] VRR capable: {}", vrr_capable);
+
         let crtc_handle = resources.crtcs().first().copied().ok_or("No CRTC available")?;
+        let vrr_property = Self::find_property(&card, crtc_handle, b"VRR_ENABLED");
 
         let gbm_for_egl = GbmDevice::new(drm_device.try_clone()?)?;
 
@@ -181,26 +363,88 @@ impl GpuRenderer {
             debug: false,
             vsync: false,
         };
-        let egl_context =
-            EGLContext::new_with_config(&egl_display, gl_attrs, PixelFormatRequirements::_10_bit())
-                .or_else(|_| EGLContext::new_with_config(&egl_display, gl_attrs, PixelFormatRequirements::_8_bit()))
-                .map_err(|e| format!("EGL context failed: {:?}", e))?;
+        let deep_color = scanout_format == DrmFourcc::Xrgb2101010;
+        let pixel_format = if deep_color {
+            PixelFormatRequirements::_10_bit()
+        } else {
+            PixelFormatRequirements::_8_bit()
+        };
+        let egl_context = EGLContext::new_with_config(&egl_display, gl_attrs, pixel_format)
+            .or_else(|e| {
+                if !deep_color {
+                    return Err(e);
+                }
+                log::warn!("[gpu] No 10-bit EGL config ({:?}), trying 8-bit", e);
+                EGLContext::new_with_config(&egl_display, gl_attrs, PixelFormatRequirements::_8_bit())
+            })
+            .map_err(|e| format!("EGL context failed: {:?}", e))?;
 
-        let renderer = unsafe { GlesRenderer::new(egl_context) }
+        let mut renderer = unsafe { GlesRenderer::new(egl_context) }
             .map_err(|e| format!("GLES renderer failed: {:?}", e))?;
 
         log::info!("[gpu] Smithay GLES renderer initialized");
 
-        let supported_formats = Self::query_dmabuf_formats(&egl_display);
+        let uploader = match TextureUploader::new(&egl_display, renderer.egl_context()) {
+            Ok(uploader) => Some(uploader),
+            Err(e) => {
+                log::warn!("[gpu] Async texture uploads unavailable: {}", e);
+                None
+            }
+        };
+
+        let programs = Programs::compile(&mut renderer);
+
+        let mut supported_formats = Self::query_dmabuf_formats(&egl_display);
+        if programs.yuv.is_some() {
+            supported_formats.extend(Self::query_yuv_formats(&egl_display));
+        }
         log::info!(
             "[gpu] Supported DMA-BUF formats: {}",
             supported_formats.len()
         );
 
-        let render_buffers = [
-            Self::create_render_buffer(&gbm, &card, width, height)?,
-            Self::create_render_buffer(&gbm, &card, width, height)?,
-        ];
+        let atomic = match AtomicOutput::new(&card, connector_handle, crtc_handle) {
+            Ok(atomic) => Some(atomic),
+            Err(e) => {
+                log::warn!("[gpu] {}, using legacy modesetting", e);
+                None
+            }
+        };
+        let modifiers_for =
+            |format: DrmFourcc| Self::render_modifiers(&supported_formats, atomic.as_ref(), format);
+
+        let modifiers = modifiers_for(scanout_format);
+        let (render_buffers, scanout_format, scanout_modifiers) = match Self::create_render_buffers(
+            &gbm,
+            &card,
+            (width, height),
+            scanout_format,
+            &modifiers,
+        ) {
+            Ok(buffers) => (buffers, scanout_format, modifiers),
+            Err(e) if scanout_format != DrmFourcc::Xrgb8888 => {
+                log::warn!(
+                    "[gpu] {} scanout unavailable ({}), falling back to XRGB8888",
+                    scanout_format,
+                    e
+                );
+                let modifiers = modifiers_for(DrmFourcc::Xrgb8888);
+                let buffers = Self::create_render_buffers(
+                    &gbm,
+                    &card,
+                    (width, height),
+                    DrmFourcc::Xrgb8888,
+                    &modifiers,
+                )?;
+                (buffers, DrmFourcc::Xrgb8888, modifiers)
+            }
+            Err(e) => return Err(e),
+        };
+        log::info!(
+            "[gpu] Scanout format: {}, {:?} modifier",
+            scanout_format,
+            render_buffers[0].bo.modifier()
+        );
 
         let drm_fd = drm_device.as_raw_fd();
 
@@ -215,74 +459,200 @@ impl GpuRenderer {
             physical_width,
             physical_height,
             mode,
+            modes,
+            vrr_capable,
+            vrr_property,
+            vrr_enabled: false,
+            transform: Transform::Normal,
+            scale: 1.0,
+            scanout_format,
+            scanout_modifiers,
             connector: connector_handle,
             crtc: crtc_handle,
+            atomic,
             render_buffers,
             current_buffer: 0,
             mode_set: false,
+            flipped: false,
+            lost_frame: false,
             flip_pending: false,
             pending_fb: None,
             current_fb: None,
             shm_textures: HashMap::new(),
             dmabuf_textures: HashMap::new(),
+            yuv_textures: HashMap::new(),
+            frame_dmabufs: HashMap::new(),
+            programs,
+            color_gain: [1.0; 3],
+            color_buffer: None,
+            keep_cursorless: false,
+            cursorless: None,
+            cursorless_ready: false,
+            blur_textures: Vec::new(),
+            blur_passes: 2,
+            blur_offset: 2.0,
+            alpha: 1.0,
             render_commands: Vec::with_capacity(64),
+            frame_damage: Vec::new(),
+            full_damage: true,
+            cursor_key: None,
+            cursor_dims: (0, 0),
+            wallpaper_key: None,
+            cursor_rect: None,
+            last_cursor_rect: None,
+            title_keys: HashMap::new(),
+            scanout_fbs: HashMap::new(),
+            stale_scanout_fbs: Vec::new(),
+            uploader,
+            render_thread: None,
+            in_flight: None,
+            frame_repairs: Vec::new(),
+            last_gpu_time: Duration::ZERO,
+            last_damage_rects: 0,
+            counters: RendererCounters::default(),
             supported_formats,
         })
     }
 
+    fn render_modifiers(
+        render_formats: &[DmaBufFormat],
+        atomic: Option<&AtomicOutput>,
+        format: DrmFourcc,
+    ) -> Vec<u64> {
+        let Some(atomic) = atomic else {
+            return Vec::new();
+        };
+        atomic
+            .primary_modifiers(format as u32)
+            .into_iter()
+            .filter(|&modifier| modifier != u64::from(DrmModifier::Invalid))
+            .filter(|&modifier| {
+                render_formats
+                    .iter()
+                    .any(|f| f.format == format as u32 && f.modifier == modifier)
+            })
+            .collect()
+    }
+
+    // Falls back to an implicit layout when the driver turns down all of
+    // the modifiers
+    fn create_render_buffers(
+        gbm: &GbmDevice<std::fs::File>,
+        card: &DrmCard,
+        size: (u32, u32),
+        format: DrmFourcc,
+        modifiers: &[u64],
+    ) -> Result<[RenderBuffer; 2], Box<dyn std::error::Error>> {
+        let create = |modifiers: &[u64]| -> Result<_, Box<dyn std::error::Error>> {
+            Ok([
+                Self::create_render_buffer(gbm, card, size, format, modifiers)?,
+                Self::create_render_buffer(gbm, card, size, format, modifiers)?,
+            ])
+        };
+        match create(modifiers) {
+            Err(e) if !modifiers.is_empty() => {
+                log::warn!(
+                    "[gpu] Explicit modifiers failed ({}), using an implicit one",
+                    e
+                );
+                create(&[])
+            }
+            result => result,
+        }
+    }
+
     fn create_render_buffer(
         gbm: &GbmDevice<std::fs::File>,
         card: &DrmCard,
-        width: u32,
-        height: u32,
+        (width, height): (u32, u32),
+        format: DrmFourcc,
+        modifiers: &[u64],
     ) -> Result<RenderBuffer, Box<dyn std::error::Error>> {
-        use smithay::reexports::gbm::Format as GbmFormat;
-
-        let bo = gbm
-            .create_buffer_object::<()>(
-                width,
-                height,
-                GbmFormat::Xrgb8888,
-                BufferObjectFlags::SCANOUT | BufferObjectFlags::RENDERING,
-            )
-            .map_err(|e| format!("Failed to create GBM buffer: {:?}", e))?;
-
-        let fd = bo.fd().map_err(|e| format!("Failed to get BO fd: {:?}", e))?;
-        let stride = bo.stride();
-        let modifier: DrmModifier = bo.modifier().into();
+        let usage = BufferObjectFlags::SCANOUT | BufferObjectFlags::RENDERING;
+        let bo = if modifiers.is_empty() {
+            gbm.create_buffer_object::<()>(width, height, format, usage)
+        } else {
+            let modifiers = modifiers.iter().map(|&m| DrmModifier::from(m));
+            gbm.create_buffer_object_with_modifiers2::<()>(width, height, format, modifiers, usage)
+        }
+        .map_err(|e| format!("Failed to create GBM buffer: {:?}", e))?;
 
+        let modifier: DrmModifier = bo.modifier();
         let mut builder = Dmabuf::builder(
             (width as i32, height as i32),
-            DrmFourcc::Xrgb8888,
-            modifier.into(),
+            format,
+            modifier,
             smithay::backend::allocator::dmabuf::DmabufFlags::empty(),
         );
 
-        let plane_fd = unsafe { OwnedFd::from_raw_fd(libc::dup(fd.as_raw_fd())) };
-        if !builder.add_plane(plane_fd, 0, 0, stride) {
-            return Err("Failed to add plane to Dmabuf".into());
+        // compressed layouts carry their metadata in extra planes
+        for plane in 0..bo.plane_count() as i32 {
+            let fd = bo
+                .fd_for_plane(plane)
+                .map_err(|e| format!("Failed to get BO fd: {:?}", e))?;
+            let offset = bo.offset(plane);
+            let stride = bo.stride_for_plane(plane);
+            if !builder.add_plane(fd, plane as u32, offset, stride) {
+                return Err("Failed to add plane to Dmabuf".into());
+            }
         }
 
         let dmabuf = builder.build().ok_or("Failed to build Dmabuf")?;
 
-        let handle = unsafe { bo.handle().u32_ };
-        let fb = card
-            .add_framebuffer(
+        let fb = if modifiers.is_empty() {
+            let handle = unsafe { bo.handle().u32_ };
+            let depth = if format == DrmFourcc::Xrgb2101010 {
+                30
+            } else {
+                24
+            };
+            card.add_framebuffer(
                 &DrmBuffer {
                     handle,
                     width,
                     height,
-                    stride,
+                    stride: bo.stride(),
+                    format,
                 },
-                24,
+                depth,
                 32,
             )
-            .map_err(|e| format!("Failed to create framebuffer: {:?}", e))?;
+        } else {
+            card.add_planar_framebuffer(&bo, drm::control::FbCmd2Flags::MODIFIERS)
+        }
+        .map_err(|e| format!("Failed to create framebuffer: {:?}", e))?;
 
         Ok(RenderBuffer {
             bo,
             dmabuf,
             fb: Some(fb),
+            damage: None,
+        })
+    }
+
+    fn query_vrr_capable(card: &DrmCard, connector: connector::Handle) -> bool {
+        let Ok(props) = card.get_properties(connector) else {
+            return false;
+        };
+        let (handles, values) = props.as_props_and_values();
+        handles.iter().zip(values).any(|(&handle, &value)| {
+            card.get_property(handle)
+                .map(|info| info.name().to_bytes() == b"vrr_capable" && value != 0)
+                .unwrap_or(false)
+        })
+    }
+
+    fn find_property(
+        card: &DrmCard,
+        object: impl drm::control::ResourceHandle,
+        name: &[u8],
+    ) -> Option<drm::control::property::Handle> {
+        let props = card.get_properties(object).ok()?;
+        let (handles, _) = props.as_props_and_values();
+        handles.iter().copied().find(|&handle| {
+            card.get_property(handle)
+                .map(|info| info.name().to_bytes() == name)
+                .unwrap_or(false)
         })
     }
 
@@ -321,240 +691,1001 @@ impl GpuRenderer {
         formats
     }
 
-    pub fn begin_frame(&mut self) {
-        if self.flip_pending {
-            self.wait_for_flip();
-            self.flip_pending = false;
-            self.current_fb = self.pending_fb.take();
+    // YUV layouts we can sample plane by plane, for every modifier the
+    // matching single-channel formats import with
+    fn query_yuv_formats(egl_display: &EGLDisplay) -> Vec<DmaBufFormat> {
+        let importable: std::collections::HashSet<(u32, u64)> = egl_display
+            .dmabuf_texture_formats()
+            .iter()
+            .map(|f| (f.code as u32, f.modifier.into()))
+            .collect();
+
+        let mut formats = Vec::new();
+        for yuv in [DrmFourcc::Nv12, DrmFourcc::P010, DrmFourcc::Yuv420] {
+            let Some((luma, chroma, _)) = yuv_plane_formats(yuv as u32) else {
+                continue;
+            };
+            for &(code, modifier) in &importable {
+                if code == luma as u32 && importable.contains(&(chroma as u32, modifier)) {
+                    formats.push(DmaBufFormat {
+                        format: yuv as u32,
+                        modifier,
+                    });
+                }
+            }
         }
+        log::info!("[gpu] YUV DMA-BUF formats: {}", formats.len());
+        formats
+    }
 
+    pub fn begin_frame(&mut self) {
+        self.finish_pending_flip();
         self.render_commands.clear();
+        self.frame_dmabufs.clear();
+        self.last_cursor_rect = self.cursor_rect.take();
+        self.alpha = 1.0;
     }
 
-    pub fn end_frame(&mut self) {
-        let fb = match self.render_buffers[self.current_buffer].fb {
-            Some(fb) => fb,
-            None => {
-                log::error!("[gpu] No framebuffer for current buffer");
-                return;
-            }
-        };
+    // opacity of the textures drawn from here on, until the next frame
+    pub fn set_alpha(&mut self, alpha: f32) {
+        self.alpha = alpha.clamp(0.0, 1.0);
+    }
 
-        let dmabuf = &mut self.render_buffers[self.current_buffer].dmabuf;
-        let output_size = Size::from((self.width as i32, self.height as i32));
-
-        if let Ok(mut target) = self.renderer.bind(dmabuf) {
-            if let Ok(mut frame) = self.renderer.render(&mut target, output_size, Transform::Normal) {
-                for cmd in &self.render_commands {
-                    match cmd {
-                        RenderCommand::Clear { x, y, width, height, color } => {
-                            let rect = Rectangle::new(
-                                Point::from((*x, *y)),
-                                Size::from((*width, *height)),
-                            );
-                            let _ = frame.clear(Color32F::from(*color), &[rect]);
-                        }
-                        RenderCommand::Texture { texture_id, x, y, width, height, is_dmabuf } => {
-                            let texture = if *is_dmabuf {
-                                self.dmabuf_textures.get(texture_id)
-                            } else {
-                                self.shm_textures.get(texture_id)
-                            };
-                            
-                            if let Some(texture) = texture {
-                                let tex_size = texture.size();
-                                let src = Rectangle::new(
-                                    Point::from((0.0, 0.0)),
-                                    Size::from((tex_size.w as f64, tex_size.h as f64)),
-                                );
-                                let dst = Rectangle::new(
-                                    Point::from((*x, *y)),
-                                    Size::from((*width, *height)),
-                                );
-                                let damage = [dst];
-                                let opaque_regions: [Rectangle<i32, smithay::utils::Physical>; 0] = [];
-                                
-                                let _ = frame.render_texture_from_to(
-                                    texture,
-                                    src,
-                                    dst,
-                                    &damage,
-                                    &opaque_regions,
-                                    Transform::Normal,
-                                    1.0,
-                                    None,
-                                    &[],
-                                );
-                            }
-                        }
-                    }
-                }
-                
-                let _ = frame.finish();
-            }
+    pub fn add_damage(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        if width > 0 && height > 0 {
+            let rect = to_physical(self.scale, x, y, width, height);
+            self.frame_damage.push(rect);
         }
+    }
 
-        let card = match self.drm_device.try_clone().map(DrmCard) {
-            Ok(c) => c,
-            Err(e) => {
-                log::error!("[gpu] Failed to clone DRM device: {:?}", e);
-                return;
-            }
-        };
+    pub fn damage_all(&mut self) {
+        self.full_damage = true;
+    }
 
-        if !self.mode_set {
-            if let Err(e) = card.set_crtc(
-                self.crtc,
-                Some(fb),
-                (0, 0),
-                &[self.connector],
-                Some(self.mode),
-            ) {
-                log::error!("[gpu] set_crtc failed: {}", e);
-                return;
-            }
-            self.mode_set = true;
-            self.current_fb = Some(fb);
-        } else {
-            use drm::control::PageFlipFlags;
+    // forget what the render buffers hold, e.g. after a direct scanout or
+    // a change in how logical coordinates map onto them
+    fn invalidate_buffers(&mut self) {
+        for buffer in &mut self.render_buffers {
+            buffer.damage = None;
+        }
+        self.cursorless_ready = false;
+    }
 
-            match card.page_flip(self.crtc, fb, PageFlipFlags::EVENT, None) {
-                Ok(()) => {
-                    self.pending_fb = Some(fb);
-                    self.flip_pending = true;
-                }
-                Err(e) => {
-                    log::warn!("[gpu] page_flip failed: {}, falling back to set_crtc", e);
-                    if let Err(e) = card.set_crtc(
-                        self.crtc,
-                        Some(fb),
-                        (0, 0),
-                        &[self.connector],
-                        Some(self.mode),
-                    ) {
-                        log::error!("[gpu] set_crtc fallback failed: {}", e);
-                        return;
-                    }
-                    self.current_fb = Some(fb);
+    // The region to redraw in the current buffer: this frame's damage plus
+    // whatever changed since the buffer was last drawn. Also hands this
+    // frame's damage on to the other buffers.
+    fn take_buffer_damage(&mut self, force_full: bool) -> Vec<Rectangle<i32, Physical>> {
+        let output = Rectangle::from_size(
+            self.transform
+                .transform_size(Size::from((self.width as i32, self.height as i32))),
+        );
+        let mut frame_damage = std::mem::take(&mut self.frame_damage);
+        frame_damage.extend(self.cursor_rect);
+        frame_damage.extend(self.last_cursor_rect);
+        let full = std::mem::take(&mut self.full_damage) || force_full;
+
+        for (i, buffer) in self.render_buffers.iter_mut().enumerate() {
+            if i == self.current_buffer {
+                continue;
+            }
+            match buffer.damage {
+                Some(ref mut damage) if !full && damage.len() + frame_damage.len() <= 16 => {
+                    damage.extend_from_slice(&frame_damage)
                 }
+                _ => buffer.damage = None,
             }
         }
 
-        self.current_buffer = 1 - self.current_buffer;
-    }
+        let current = &mut self.render_buffers[self.current_buffer];
+        let mut damage = match current.damage.replace(Vec::new()) {
+            Some(mut damage) if !full && damage.len() + frame_damage.len() <= 16 => {
+                damage.append(&mut frame_damage);
+                damage
+            }
+            _ => return vec![output],
+        };
 
-    fn wait_for_flip(&self) {
-        let mut fds = [libc::pollfd {
-            fd: self.drm_fd,
-            events: libc::POLLIN,
-            revents: 0,
-        }];
+        // a blur samples everything around it, so any damage there has it
+        // redrawn whole, or it would pick up last frame's window on top
+        let mut blurs = self.blur_regions();
+        while let Some(i) = blurs
+            .iter()
+            .position(|b| damage.iter().any(|d| d.overlaps(*b)))
+        {
+            damage.push(blurs.swap_remove(i));
+        }
 
-        let timeout_ms = 16;
+        damage
+            .into_iter()
+            .filter_map(|rect| rect.intersection(output))
+            .collect()
+    }
 
-        unsafe {
-            let ret = libc::poll(fds.as_mut_ptr(), 1, timeout_ms);
-            if ret > 0 && (fds[0].revents & libc::POLLIN) != 0 {
-                let mut buf = [0u8; 1024];
-                libc::read(self.drm_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len());
-            }
+    // blurred rects this frame, grown by how far the blur reaches
+    fn blur_regions(&self) -> Vec<Rectangle<i32, Physical>> {
+        if self.programs.blur.is_none() || self.transform != Transform::Normal {
+            return Vec::new();
         }
+        let reach = (self.blur_offset * (2 << self.blur_passes) as f32).ceil() as i32;
+        self.render_commands
+            .iter()
+            .filter_map(|cmd| match cmd {
+                RenderCommand::Blur { x, y, width, height } => {
+                    let rect = to_physical(self.scale, *x, *y, *width, *height);
+                    Some(Rectangle::new(
+                        rect.loc - Point::from((reach, reach)),
+                        rect.size + Size::from((2 * reach, 2 * reach)),
+                    ))
+                }
+                _ => None,
+            })
+            .collect()
     }
 
-    pub fn draw_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: [f32; 4]) {
-        self.render_commands.push(RenderCommand::Clear {
+    // passes (1-5) and offset come from the [blur] config
+    pub fn set_blur_strength(&mut self, passes: u32, offset: f64) {
+        self.blur_passes = passes.clamp(1, 5) as usize;
+        self.blur_offset = offset.clamp(0.5, 10.0) as f32;
+    }
+
+    pub fn draw_blur(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        self.render_commands.push(RenderCommand::Blur {
             x,
             y,
             width,
             height,
-            color,
         });
     }
 
-    pub fn upload_shm_texture(
-        &mut self,
-        id: u64,
-        width: u32,
-        height: u32,
-        _stride: u32,
-        data: &[u8],
-    ) -> GlesTexture {
-        self.shm_textures.remove(&id);
-
-        let format = Fourcc::Argb8888;
-        let size = Size::from((width as i32, height as i32));
-
-        match self.renderer.import_memory(data, format, size, false) {
-            Ok(texture) => {
-                self.shm_textures.insert(id, texture.clone());
-                texture
-            }
-            Err(e) => {
-                log::error!("[gpu] Failed to upload SHM texture: {:?}", e);
-                panic!("Failed to create texture");
-            }
+    fn finish_pending_flip(&mut self) {
+        self.wait_for_frame();
+        if self.flip_pending {
+            self.wait_for_flip();
+            self.flip_pending = false;
+            self.current_fb = self.pending_fb.take();
         }
+        self.destroy_stale_scanout_fbs();
     }
 
-    pub fn import_dmabuf_texture(
+    // Puts a fullscreen client buffer straight on the primary plane. Returns
+    // false when the buffer can't be scanned out and has to be composited.
+    #[allow(clippy::too_many_arguments)]
+    pub fn scanout_dmabuf(
         &mut self,
-        id: u64,
-        fd: i32,
+        id: &ObjectId,
+        fd: BorrowedFd<'_>,
         width: u32,
         height: u32,
         format: u32,
         stride: u32,
         offset: u32,
         modifier: u64,
-    ) -> Option<GlesTexture> {
-        if let Some(tex) = self.dmabuf_textures.get(&id) {
-            return Some(tex.clone());
+    ) -> bool {
+        if !self.mode_set
+            || self.transform != Transform::Normal
+            || self.color_gain != [1.0; 3]
+            || (width, height) != (self.width, self.height)
+        {
+            return false;
         }
 
-        let fourcc = DrmFourcc::try_from(format).ok()?;
-        let drm_mod = DrmModifier::from(modifier);
+        let fb = match self.scanout_fbs.get(id) {
+            Some(fb) => *fb,
+            None => {
+                let mut fb =
+                    self.import_scanout_fb(fd, width, height, format, stride, offset, modifier);
+                // the display engine may still turn down a buffer it imported
+                // fine, over bandwidth or a modifier the plane can't scan out;
+                // it is asked once per buffer
+                if let Some(handle) = fb.filter(|&handle| !self.test_scanout(handle)) {
+                    log::debug!("[gpu] Scanout of buffer {} refused by the display", id);
+                    self.stale_scanout_fbs.push(handle);
+                    fb = None;
+                }
+                self.scanout_fbs.insert(id.clone(), fb);
+                fb
+            }
+        };
+        let Some(fb) = fb else {
+            return false;
+        };
 
-        let mut builder = Dmabuf::builder(
-            (width as i32, height as i32),
-            fourcc,
-            drm_mod.into(),
-            smithay::backend::allocator::dmabuf::DmabufFlags::empty(),
-        );
+        self.finish_pending_flip();
+        if !self.present(fb) {
+            return false;
+        }
+        // damage cleared while a client buffer is on screen never reaches
+        // the render buffers
+        self.invalidate_buffers();
+        true
+    }
 
-        let plane_fd = unsafe { OwnedFd::from_raw_fd(libc::dup(fd)) };
-        if !builder.add_plane(plane_fd, 0, offset, stride) {
-            log::warn!("[gpu] Failed to add plane to DMA-BUF");
+    // the primary plane keeps the format it was set up with; alpha is
+    // ignored there anyway
+    fn scanout_capable_formats(&self) -> [u32; 2] {
+        match self.scanout_format {
+            DrmFourcc::Xrgb2101010 => [DrmFourcc::Xrgb2101010, DrmFourcc::Argb2101010],
+            _ => [DrmFourcc::Xrgb8888, DrmFourcc::Argb8888],
+        }
+        .map(|f| f as u32)
+    }
+
+    // what a fullscreen client can allocate to land on the primary plane
+    pub fn scanout_formats(&self) -> Vec<DmaBufFormat> {
+        let capable = self.scanout_capable_formats();
+        // the buffer is added as the plane's own format, alpha or not
+        let plane_modifiers = self
+            .atomic
+            .as_ref()
+            .map(|atomic| atomic.primary_modifiers(self.scanout_format as u32))
+            .unwrap_or_default();
+        self.supported_formats
+            .iter()
+            .filter(|f| capable.contains(&f.format))
+            .filter(|f| {
+                plane_modifiers.is_empty()
+                    || f.modifier == u64::from(DrmModifier::Invalid)
+                    || plane_modifiers.contains(&f.modifier)
+            })
+            .cloned()
+            .collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn import_scanout_fb(
+        &self,
+        fd: BorrowedFd<'_>,
+        width: u32,
+        height: u32,
+        format: u32,
+        stride: u32,
+        offset: u32,
+        modifier: u64,
+    ) -> Option<framebuffer::Handle> {
+        if !self.scanout_capable_formats().contains(&format) {
+            log::debug!("[scanout] Format {:#x} not scanout-capable", format);
             return None;
         }
 
-        let dmabuf = builder.build()?;
+        let card = self.drm_device.try_clone().map(DrmCard).ok()?;
+        let handle = match card.prime_fd_to_buffer(fd) {
+            Ok(handle) => handle,
+            Err(e) => {
+                log::debug!("[scanout] PRIME import failed: {}", e);
+                return None;
+            }
+        };
 
-        match self.renderer.import_dmabuf(&dmabuf, None) {
-            Ok(texture) => {
-                self.dmabuf_textures.insert(id, texture.clone());
-                Some(texture)
+        let buffer = ScanoutBuffer {
+            handle,
+            width,
+            height,
+            stride,
+            offset,
+            modifier: DrmModifier::from(modifier),
+            format: self.scanout_format,
+        };
+        let flags = if buffer.modifier == DrmModifier::Invalid {
+            drm::control::FbCmd2Flags::empty()
+        } else {
+            drm::control::FbCmd2Flags::MODIFIERS
+        };
+        let fb = card.add_planar_framebuffer(&buffer, flags);
+        // the framebuffer keeps its own reference to the GEM object
+        let _ = card.close_buffer(handle);
+
+        match fb {
+            Ok(fb) => {
+                log::info!("[scanout] Imported {}x{} client buffer for direct scanout", width, height);
+                Some(fb)
             }
             Err(e) => {
-                log::warn!("[gpu] Failed to import DMA-BUF: {:?}", e);
+                log::debug!("[scanout] addfb2 failed: {}", e);
                 None
             }
         }
     }
 
-    pub fn import_dmabuf_texture_multiplane(
-        &mut self,
-        id: u64,
-        width: u32,
-        height: u32,
-        format: u32,
-        planes: &[crate::state::DmaBufPlaneInfo],
-    ) -> Option<GlesTexture> {
-        if planes.is_empty() {
-            return None;
+    // legacy page flips can't be tried out first, so they are assumed to work
+    fn test_scanout(&self, fb: framebuffer::Handle) -> bool {
+        let Some(atomic) = &self.atomic else {
+            return true;
+        };
+        match self.drm_device.try_clone() {
+            Ok(card) => atomic.test_flip(&DrmCard(card), fb),
+            Err(_) => false,
         }
+    }
 
-        if let Some(tex) = self.dmabuf_textures.get(&id) {
-            return Some(tex.clone());
+    fn destroy_stale_scanout_fbs(&mut self) {
+        if self.stale_scanout_fbs.is_empty() {
+            return;
+        }
+        let Ok(card) = self.drm_device.try_clone().map(DrmCard) else {
+            return;
+        };
+        let (current, pending) = (self.current_fb, self.pending_fb);
+        self.stale_scanout_fbs.retain(|fb| {
+            if Some(*fb) == current || Some(*fb) == pending {
+                return true;
+            }
+            card.destroy_framebuffer(*fb).ok();
+            false
+        });
+    }
+
+    pub fn end_frame(&mut self) {
+        let fb = match self.render_buffers[self.current_buffer].fb {
+            Some(fb) => fb,
+            None => {
+                log::error!("[gpu] No framebuffer for current buffer");
+                return;
+            }
+        };
+
+        let color_pass = self.color_gain != [1.0; 3] && self.prepare_color_buffer();
+        // the offscreen color buffer is copied over whole, so it is redrawn whole
+        let damage = self.take_buffer_damage(color_pass);
+        self.last_damage_rects = damage.len();
+        self.frame_repairs = damage.clone();
+        let blur = !self.blur_regions().is_empty() && self.prepare_blur_textures();
+        let cursorless = self.keep_cursorless && self.prepare_cursorless();
+
+        let scale = self.scale;
+        let (shm, dmabuf, yuv) = (
+            &self.shm_textures,
+            &self.frame_dmabufs,
+            &self.yuv_textures,
+        );
+        let resolve = |cmd: RenderCommand| match cmd {
+            RenderCommand::Clear {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => Some(DrawCommand::Clear {
+                rect: to_physical(scale, x, y, width, height),
+                color,
+            }),
+            RenderCommand::Blur {
+                x,
+                y,
+                width,
+                height,
+            } => Some(DrawCommand::Blur {
+                rect: to_physical(scale, x, y, width, height),
+            }),
+            RenderCommand::Texture {
+                texture_id,
+                x,
+                y,
+                width,
+                height,
+                is_dmabuf,
+                src,
+                alpha,
+                transform,
+            } => {
+                let textures = if is_dmabuf { dmabuf } else { shm };
+                Some(DrawCommand::Texture {
+                    texture: textures.get(&texture_id)?.clone(),
+                    dst: to_physical(scale, x, y, width, height),
+                    src,
+                    alpha,
+                    transform,
+                })
+            }
+            RenderCommand::Yuv {
+                buffer,
+                x,
+                y,
+                width,
+                height,
+                alpha,
+                transform,
+            } => Some(DrawCommand::Yuv {
+                planes: yuv.get(&buffer)?.clone(),
+                dst: to_physical(scale, x, y, width, height),
+                alpha,
+                transform,
+            }),
+        };
+        // draw_cursor is always the last command
+        let cursor = if self.keep_cursorless
+            && matches!(
+                self.render_commands.last(),
+                Some(RenderCommand::Texture {
+                    texture_id: CURSOR_TEXTURE_ID,
+                    ..
+                })
+            ) {
+            self.render_commands.pop().and_then(resolve)
+        } else {
+            None
+        };
+        let commands = self.render_commands.drain(..).filter_map(resolve).collect();
+        self.frame_dmabufs.clear();
+
+        let job = FrameJob {
+            target: self.render_buffers[self.current_buffer].dmabuf.clone(),
+            output_size: Size::from((self.width as i32, self.height as i32)),
+            transform: self.transform,
+            damage,
+            commands,
+            cursor,
+            color: self
+                .color_buffer
+                .clone()
+                .filter(|_| color_pass)
+                .map(|buffer| (buffer, self.color_gain)),
+            blur: blur.then(|| BlurLevels {
+                textures: self.blur_textures.clone(),
+                offset: self.blur_offset,
+            }),
+            cursorless: self.cursorless.clone().filter(|_| cursorless),
+        };
+        let job = match self.render_thread.as_mut() {
+            Some(thread) => match thread.submit(job) {
+                Ok(()) => {
+                    self.in_flight = Some(fb);
+                    return;
+                }
+                Err(job) => {
+                    self.render_thread = None;
+                    *job
+                }
+            },
+            None => job,
+        };
+        let start = Instant::now();
+        let drawn = render_thread::composite(&mut self.renderer, &self.programs, job);
+        self.last_gpu_time = start.elapsed();
+        match drawn {
+            Some(kept_cursorless) => self.present_frame(fb, kept_cursorless),
+            None => self.restore_buffer_damage(),
+        }
+    }
+
+    fn present_frame(&mut self, fb: framebuffer::Handle, kept_cursorless: bool) {
+        if self.present(fb) {
+            self.current_buffer = 1 - self.current_buffer;
+            self.cursorless_ready = kept_cursorless;
+            self.frame_repairs.clear();
+        } else {
+            self.restore_buffer_damage();
+        }
+    }
+
+    // A frame that failed to draw or present left the current buffer as
+    // stale as before, and it is the one drawn next
+    fn restore_buffer_damage(&mut self) {
+        let repairs = std::mem::take(&mut self.frame_repairs);
+        let buffer = &mut self.render_buffers[self.current_buffer];
+        match buffer.damage {
+            Some(ref mut damage) if damage.len() + repairs.len() <= 16 => damage.extend(repairs),
+            _ => buffer.damage = None,
+        }
+    }
+
+    // Composites on a worker thread from now on; the ping wakes the main
+    // loop to present each frame with poll_frame
+    pub fn start_render_thread(&mut self, ping: calloop::ping::Ping) -> Result<(), String> {
+        let thread = RenderThread::new(&self.egl_display, self.renderer.egl_context(), ping)?;
+        self.render_thread = Some(thread);
+        Ok(())
+    }
+
+    // Presents the frame the render thread was drawing if it is done; false
+    // while it isn't
+    pub fn poll_frame(&mut self) -> bool {
+        let finished = self
+            .in_flight
+            .and(self.render_thread.as_ref())
+            .and_then(|thread| thread.try_finished());
+        match finished {
+            Some(finished) => {
+                self.frame_finished(finished);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // reading back or replacing the render buffers has to wait for the frame
+    // being drawn into them
+    fn wait_for_frame(&mut self) {
+        if self.in_flight.is_none() {
+            return;
+        }
+        let finished = match self.render_thread.as_ref() {
+            Some(thread) => thread.wait(),
+            None => Finished::Lost,
+        };
+        self.frame_finished(finished);
+    }
+
+    fn frame_finished(&mut self, finished: Finished) {
+        let Some(fb) = self.in_flight.take() else {
+            return;
+        };
+        match finished {
+            Finished::Drawn {
+                kept_cursorless,
+                gpu_time,
+            } => {
+                self.last_gpu_time = gpu_time;
+                self.present_frame(fb, kept_cursorless);
+            }
+            Finished::Failed => self.restore_buffer_damage(),
+            Finished::Lost => {
+                log::warn!("[gpu] Render thread died, compositing on the main thread");
+                self.render_thread = None;
+                self.invalidate_buffers();
+            }
+        }
+    }
+
+    // (Re)creates the buffer each frame is copied into before the cursor
+    // goes on; false when it can't be made
+    fn prepare_cursorless(&mut self) -> bool {
+        let size = Size::from((self.width as i32, self.height as i32));
+        if self.cursorless.as_ref().is_some_and(|b| b.size() == size) {
+            return true;
+        }
+        let format = if self.scanout_format == DrmFourcc::Xrgb2101010 {
+            Fourcc::Abgr2101010
+        } else {
+            Fourcc::Abgr8888
+        };
+        self.cursorless =
+            match Offscreen::<GlesTexture>::create_buffer(&mut self.renderer, format, size) {
+                Ok(buffer) => Some(buffer),
+                Err(e) => {
+                    log::warn!("[gpu] Failed to create cursorless capture buffer: {:?}", e);
+                    None
+                }
+            };
+        self.cursorless.is_some()
+    }
+
+    // whether end_frame keeps a copy of each frame without the cursor, for
+    // screencopy clients that don't want it overlaid
+    pub fn set_keep_cursorless(&mut self, keep: bool) {
+        self.keep_cursorless = keep;
+        if !keep {
+            self.cursorless = None;
+            self.cursorless_ready = false;
+        }
+    }
+
+    pub fn has_cursorless(&self) -> bool {
+        self.cursorless_ready
+    }
+
+    // (Re)creates the offscreen buffer at the current mode size; false when
+    // the color pass is unavailable
+    fn prepare_color_buffer(&mut self) -> bool {
+        if self.programs.color.is_none() {
+            return false;
+        }
+        let size = Size::from((self.width as i32, self.height as i32));
+        if self.color_buffer.as_ref().is_some_and(|b| b.size() == size) {
+            return true;
+        }
+        let format = if self.scanout_format == DrmFourcc::Xrgb2101010 {
+            Fourcc::Abgr2101010
+        } else {
+            Fourcc::Abgr8888
+        };
+        match Offscreen::<GlesTexture>::create_buffer(&mut self.renderer, format, size) {
+            Ok(buffer) => {
+                self.color_buffer = Some(buffer);
+                true
+            }
+            Err(e) => {
+                log::warn!("[gpu] Failed to create color pass buffer: {:?}", e);
+                self.color_buffer = None;
+                false
+            }
+        }
+    }
+
+    // (Re)creates one texture per blur level, each half the size of the
+    // last; false when blurring is unavailable
+    fn prepare_blur_textures(&mut self) -> bool {
+        if self.programs.blur.is_none() || self.transform != Transform::Normal {
+            return false;
+        }
+        let size = |level: usize| {
+            Size::from((
+                (self.width as i32 >> (level + 1)).max(1),
+                (self.height as i32 >> (level + 1)).max(1),
+            ))
+        };
+        if self.blur_textures.len() == self.blur_passes
+            && self
+                .blur_textures
+                .iter()
+                .enumerate()
+                .all(|(i, t)| t.size() == size(i))
+        {
+            return true;
+        }
+        let format = if self.scanout_format == DrmFourcc::Xrgb2101010 {
+            Fourcc::Abgr2101010
+        } else {
+            Fourcc::Abgr8888
+        };
+        let textures: Result<Vec<_>, _> = (0..self.blur_passes)
+            .map(|i| Offscreen::<GlesTexture>::create_buffer(&mut self.renderer, format, size(i)))
+            .collect();
+        match textures {
+            Ok(textures) => {
+                self.blur_textures = textures;
+                true
+            }
+            Err(e) => {
+                log::warn!("[gpu] Failed to create blur buffers: {:?}", e);
+                self.blur_textures.clear();
+                false
+            }
+        }
+    }
+
+    pub fn set_color_gain(&mut self, gain: [f32; 3]) {
+        self.color_gain = gain;
+        if gain == [1.0; 3] {
+            self.color_buffer = None;
+        }
+    }
+
+    fn present(&mut self, fb: framebuffer::Handle) -> bool {
+        let card = match self.drm_device.try_clone().map(DrmCard) {
+            Ok(c) => c,
+            Err(e) => {
+                log::error!("[gpu] Failed to clone DRM device: {:?}", e);
+                return false;
+            }
+        };
+
+        if !self.mode_set {
+            if let Err(e) = self.modeset(&card, fb) {
+                log::error!("[gpu] {}", e);
+                self.fall_back_to_implicit_modifier(&card);
+                return false;
+            }
+            self.mode_set = true;
+            self.current_fb = Some(fb);
+        } else {
+            use drm::control::PageFlipFlags;
+
+            let flipped = match self.atomic {
+                Some(ref atomic) => atomic.flip(&card, fb),
+                None => card
+                    .page_flip(self.crtc, fb, PageFlipFlags::EVENT, None)
+                    .map_err(|e| format!("page_flip failed: {}", e)),
+            };
+            match flipped {
+                Ok(()) => {
+                    self.pending_fb = Some(fb);
+                    self.flip_pending = true;
+                    self.flipped = true;
+                }
+                Err(e) if !self.flipped && self.fall_back_to_implicit_modifier(&card) => {
+                    log::warn!("[gpu] First {}", e);
+                    return false;
+                }
+                Err(e) => {
+                    self.counters.page_flip_misses += 1;
+                    log::warn!("[gpu] {}, falling back to a modeset", e);
+                    if let Err(e) = self.modeset(&card, fb) {
+                        log::error!("[gpu] Modeset fallback failed: {}", e);
+                        return false;
+                    }
+                    self.current_fb = Some(fb);
+                }
+            }
+        }
+
+        true
+    }
+
+    // Drivers can allocate and import buffers with an explicit modifier and
+    // still refuse to scan them out. Swaps in implicit-layout buffers, which
+    // any driver shows, for a fresh modeset on the next frame. False when
+    // the buffers were implicit already or new ones couldn't be made.
+    fn fall_back_to_implicit_modifier(&mut self, card: &DrmCard) -> bool {
+        if self.scanout_modifiers.is_empty() {
+            return false;
+        }
+        let size = (self.width, self.height);
+        match Self::create_render_buffers(&self.gbm, card, size, self.scanout_format, &[]) {
+            Ok(buffers) => {
+                log::warn!("[gpu] Scanout with explicit modifiers failed, using an implicit one");
+                let old = std::mem::replace(&mut self.render_buffers, buffers);
+                // the one on screen goes once the modeset replaces it
+                self.stale_scanout_fbs
+                    .extend(old.iter().filter_map(|buffer| buffer.fb));
+                self.scanout_modifiers.clear();
+                self.current_buffer = 0;
+                self.mode_set = false;
+                self.flipped = false;
+                self.lost_frame = true;
+                true
+            }
+            Err(e) => {
+                log::error!("[gpu] Implicit modifier fallback failed: {}", e);
+                false
+            }
+        }
+    }
+
+    // true once after a frame was dropped to change the render buffers
+    pub fn take_lost_frame(&mut self) -> bool {
+        std::mem::take(&mut self.lost_frame)
+    }
+
+    // blocks until the CRTC shows fb in self.mode
+    fn modeset(&mut self, card: &DrmCard, fb: framebuffer::Handle) -> Result<(), String> {
+        match self.atomic {
+            Some(ref mut atomic) => atomic.modeset(card, fb, &self.mode),
+            None => card
+                .set_crtc(
+                    self.crtc,
+                    Some(fb),
+                    (0, 0),
+                    &[self.connector],
+                    Some(self.mode),
+                )
+                .map_err(|e| format!("set_crtc failed: {}", e)),
+        }
+    }
+
+    fn wait_for_flip(&mut self) {
+        let mut fds = [libc::pollfd {
+            fd: self.drm_fd,
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+
+        let timeout_ms = 16;
+
+        unsafe {
+            let ret = libc::poll(fds.as_mut_ptr(), 1, timeout_ms);
+            if ret > 0 && (fds[0].revents & libc::POLLIN) != 0 {
+                let mut buf = [0u8; 1024];
+                libc::read(self.drm_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+            } else {
+                self.counters.page_flip_misses += 1;
+            }
+        }
+    }
+
+    pub fn draw_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: [f32; 4]) {
+        self.render_commands.push(RenderCommand::Clear {
+            x,
+            y,
+            width,
+            height,
+            color,
+        });
+    }
+
+    // Returns the ids whose background upload has landed since the last call;
+    // their windows need a redraw to show the new contents.
+    pub fn collect_uploads(&mut self) -> Vec<u64> {
+        let Some(ref mut uploader) = self.uploader else {
+            return Vec::new();
+        };
+        uploader
+            .collect(&self.renderer)
+            .into_iter()
+            .map(|(id, texture)| {
+                if let Some(texture) = texture {
+                    self.shm_textures.insert(id, texture);
+                }
+                id
+            })
+            .collect()
+    }
+
+    pub fn has_pending_uploads(&self) -> bool {
+        self.uploader.as_ref().is_some_and(|u| u.has_pending())
+    }
+
+    // the texture still shows older contents than the last commit
+    pub fn upload_pending(&self, id: u64) -> bool {
+        self.uploader.as_ref().is_some_and(|u| u.is_pending(id))
+    }
+
+    // Like upload_shm_texture, but once a texture of the right size exists it
+    // is kept: small damage is written into it with glTexSubImage2D, larger
+    // changes go through the upload thread and the old texture is drawn until
+    // they arrive. stride is in bytes, damage in upright buffer pixels.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_shm_texture(
+        &mut self,
+        id: u64,
+        width: u32,
+        height: u32,
+        stride: u32,
+        format: Fourcc,
+        data: &[u8],
+        damage: &crate::state::BufferDamage,
+    ) -> GlesTexture {
+        if let Some(texture) = self.shm_textures.get(&id).cloned() {
+            let size = texture.size();
+            let reusable = (size.w as u32, size.h as u32) == (width, height)
+                && texture.format() == Some(format);
+            if reusable {
+                if damage.is_empty() {
+                    return texture;
+                }
+                // a queued full upload would land after these and undo them
+                let queued = self.uploader.as_ref().is_some_and(|u| u.is_pending(id));
+                // update_memory reads rows exactly as wide as the texture, a
+                // padded buffer is left to a full upload of its packed rows
+                let packed = stride == width * 4;
+                if !queued
+                    && packed
+                    && self.update_shm_regions(&texture, width, height, data, damage)
+                {
+                    return texture;
+                }
+                if let Some(ref mut uploader) = self.uploader {
+                    let rows = packed_rows(data, width, height, stride);
+                    if uploader.submit(id, width, height, format, &rows) {
+                        return texture;
+                    }
+                }
+            }
+        }
+        if let Some(ref mut uploader) = self.uploader {
+            uploader.cancel(id);
+        }
+        self.upload_shm_texture_as(id, width, height, stride, format, data)
+    }
+
+    // Writes the damaged parts of data into the texture in place. Declines
+    // when more than half of it changed and the upload thread can take a full
+    // copy instead.
+    fn update_shm_regions(
+        &mut self,
+        texture: &GlesTexture,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        damage: &crate::state::BufferDamage,
+    ) -> bool {
+        let (width, height) = (width as i32, height as i32);
+        let regions = match damage.regions(width, height) {
+            Some(regions) => regions,
+            None if self.uploader.is_none() => vec![crate::state::Rectangle {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }],
+            None => return false,
+        };
+        let area: i64 = regions
+            .iter()
+            .map(|r| r.width as i64 * r.height as i64)
+            .sum();
+        if self.uploader.is_some() && area * 2 > width as i64 * height as i64 {
+            return false;
+        }
+
+        for r in regions {
+            let region: Rectangle<i32, BufferCoord> =
+                Rectangle::new((r.x, r.y).into(), (r.width, r.height).into());
+            if let Err(e) = self.renderer.update_memory(texture, data, region) {
+                log::warn!("[gpu] Partial texture update failed: {:?}", e);
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn upload_shm_texture(
+        &mut self,
+        id: u64,
+        width: u32,
+        height: u32,
+        stride: u32,
+        data: &[u8],
+    ) -> GlesTexture {
+        self.upload_shm_texture_as(id, width, height, stride, Fourcc::Argb8888, data)
+    }
+
+    fn upload_shm_texture_as(
+        &mut self,
+        id: u64,
+        width: u32,
+        height: u32,
+        stride: u32,
+        format: Fourcc,
+        data: &[u8],
+    ) -> GlesTexture {
+        self.shm_textures.remove(&id);
+
+        let size = Size::from((width as i32, height as i32));
+        let rows = packed_rows(data, width, height, stride);
+
+        match self.renderer.import_memory(&rows, format, size, false) {
+            Ok(texture) => {
+                self.shm_textures.insert(id, texture.clone());
+                texture
+            }
+            Err(e) => {
+                log::error!("[gpu] Failed to upload SHM texture: {:?}", e);
+                panic!("Failed to create texture");
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn import_dmabuf_texture(
+        &mut self,
+        id: &ObjectId,
+        fd: i32,
+        width: u32,
+        height: u32,
+        format: u32,
+        stride: u32,
+        offset: u32,
+        modifier: u64,
+    ) -> Option<GlesTexture> {
+        if let Some(tex) = self.dmabuf_textures.get(id) {
+            return Some(tex.clone());
+        }
+
+        let fourcc = DrmFourcc::try_from(format).ok()?;
+        let drm_mod = DrmModifier::from(modifier);
+
+        let mut builder = Dmabuf::builder(
+            (width as i32, height as i32),
+            fourcc,
+            drm_mod,
+            smithay::backend::allocator::dmabuf::DmabufFlags::empty(),
+        );
+
+        let plane_fd = unsafe { OwnedFd::from_raw_fd(libc::dup(fd)) };
+        if !builder.add_plane(plane_fd, 0, offset, stride) {
+            log::warn!("[gpu] Failed to add plane to DMA-BUF");
+            return None;
+        }
+
+        let dmabuf = builder.build()?;
+
+        match self.renderer.import_dmabuf(&dmabuf, None) {
+            Ok(texture) => {
+                self.dmabuf_textures.insert(id.clone(), texture.clone());
+                Some(texture)
+            }
+            Err(e) => {
+                self.counters.dmabuf_import_failures += 1;
+                log::warn!("[gpu] Failed to import DMA-BUF: {:?}", e);
+                None
+            }
+        }
+    }
+
+    pub fn import_dmabuf_texture_multiplane(
+        &mut self,
+        id: &ObjectId,
+        width: u32,
+        height: u32,
+        format: u32,
+        planes: &[crate::state::DmaBufPlaneInfo],
+    ) -> Option<GlesTexture> {
+        if planes.is_empty() {
+            return None;
+        }
+
+        if let Some(tex) = self.dmabuf_textures.get(id) {
+            return Some(tex.clone());
         }
 
         let fourcc = DrmFourcc::try_from(format).ok()?;
@@ -564,7 +1695,7 @@ impl GpuRenderer {
         let mut builder = Dmabuf::builder(
             (width as i32, height as i32),
             fourcc,
-            drm_mod.into(),
+            drm_mod,
             smithay::backend::allocator::dmabuf::DmabufFlags::empty(),
         );
 
@@ -580,16 +1711,92 @@ impl GpuRenderer {
 
         match self.renderer.import_dmabuf(&dmabuf, None) {
             Ok(texture) => {
-                self.dmabuf_textures.insert(id, texture.clone());
+                self.dmabuf_textures.insert(id.clone(), texture.clone());
                 Some(texture)
             }
             Err(e) => {
+                self.counters.dmabuf_import_failures += 1;
                 log::warn!("[gpu] Failed to import multi-plane DMA-BUF: {:?}", e);
                 None
             }
         }
     }
 
+    // Imports each plane of a YUV buffer as its own single-channel texture
+    // for the YUV shader to combine.
+    pub fn import_yuv_dmabuf(
+        &mut self,
+        id: &ObjectId,
+        width: u32,
+        height: u32,
+        format: u32,
+        planes: &[crate::state::DmaBufPlaneInfo],
+    ) -> bool {
+        if self.yuv_textures.contains_key(id) {
+            return true;
+        }
+        let Some((luma, chroma, chroma_planes)) = yuv_plane_formats(format) else {
+            return false;
+        };
+        if self.programs.yuv.is_none() || planes.len() != 1 + chroma_planes {
+            return false;
+        }
+
+        let mut textures = Vec::with_capacity(planes.len());
+        for (i, plane) in planes.iter().enumerate() {
+            let (fourcc, w, h) = if i == 0 {
+                (luma, width, height)
+            } else {
+                (chroma, width.div_ceil(2), height.div_ceil(2))
+            };
+            let mut builder = Dmabuf::builder(
+                (w as i32, h as i32),
+                fourcc,
+                DrmModifier::from(plane.modifier),
+                smithay::backend::allocator::dmabuf::DmabufFlags::empty(),
+            );
+            let fd = unsafe { OwnedFd::from_raw_fd(libc::dup(plane.fd.as_raw_fd())) };
+            if !builder.add_plane(fd, 0, plane.offset, plane.stride) {
+                return false;
+            }
+            let Some(dmabuf) = builder.build() else {
+                return false;
+            };
+            match self.renderer.import_dmabuf(&dmabuf, None) {
+                Ok(texture) => textures.push(texture),
+                Err(e) => {
+                    self.counters.dmabuf_import_failures += 1;
+                    log::warn!("[gpu] Failed to import YUV plane {} as {:?}: {:?}", i, fourcc, e);
+                    return false;
+                }
+            }
+        }
+
+        self.yuv_textures.insert(id.clone(), textures);
+        true
+    }
+
+    pub fn draw_yuv_texture(
+        &mut self,
+        id: &ObjectId,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        transform: crate::state::OutputTransform,
+    ) {
+        self.render_commands.push(RenderCommand::Yuv {
+            buffer: id.clone(),
+            x,
+            y,
+            width,
+            height,
+            alpha: self.alpha,
+            transform: smithay_transform(transform),
+        });
+    }
+
+    #[allow(dead_code)]
     pub fn is_format_supported(&self, format: u32, modifier: u64) -> bool {
         const MOD_INVALID: u64 = 0x00ffffffffffffff;
         self.supported_formats.iter().any(|f| {
@@ -600,13 +1807,14 @@ impl GpuRenderer {
         })
     }
 
-    pub fn is_dmabuf_external(&self, _id: u64) -> bool {
+    pub fn is_dmabuf_external(&self, _id: &ObjectId) -> bool {
         false
     }
 
-    pub fn draw_texture(
+    pub fn draw_texture_region(
         &mut self,
         texture: GlesTexture,
+        src: Option<[f64; 4]>,
         x: i32,
         y: i32,
         width: i32,
@@ -624,144 +1832,292 @@ impl GpuRenderer {
                 width,
                 height,
                 is_dmabuf: false,
+                src,
+                alpha: self.alpha,
+                transform: Transform::Normal,
             });
         } else {
             // TODO this is technically not best practice
             // and probably inefficient, ill address it
-            // later probably if i remember
-            let temp_id = u64::MAX - 100 - self.render_commands.len() as u64;
-            self.shm_textures.insert(temp_id, texture);
-            self.render_commands.push(RenderCommand::Texture {
-                texture_id: temp_id,
-                x,
-                y,
-                width,
-                height,
-                is_dmabuf: false,
-            });
-        }
-    }
-
-    pub fn draw_dmabuf_texture(
-        &mut self,
-        texture: GlesTexture,
-        x: i32,
-        y: i32,
-        width: i32,
-        height: i32,
-        _is_external: bool,
-    ) {
-        let texture_id = self.dmabuf_textures.iter()
-            .find(|(_, tex)| std::ptr::eq(*tex as *const _, &texture as *const _))
-            .map(|(id, _)| *id);
-        
-        if let Some(id) = texture_id {
-            self.render_commands.push(RenderCommand::Texture {
-                texture_id: id,
-                x,
-                y,
-                width,
-                height,
-                is_dmabuf: true,
-            });
-        } else {
-            let temp_id = u64::MAX - 200 - self.render_commands.len() as u64;
-            self.dmabuf_textures.insert(temp_id, texture);
+            // later probably if i remember
+            let temp_id = u64::MAX - 100 - self.render_commands.len() as u64;
+            self.shm_textures.insert(temp_id, texture);
             self.render_commands.push(RenderCommand::Texture {
                 texture_id: temp_id,
                 x,
                 y,
                 width,
                 height,
-                is_dmabuf: true,
+                is_dmabuf: false,
+                src,
+                alpha: self.alpha,
+                transform: Transform::Normal,
             });
         }
     }
 
-    pub fn draw_cursor(&mut self, x: i32, y: i32) {
-        const CURSOR: &[&str] = &[
-            "BW",
-            "BWWB",
-            "BWWWB",
-            "BWWWWB",
-            "BWWWWWB",
-            "BWWWWWWB",
-            "BWWWWWWWB",
-            "BWWWWWWWWB",
-            "BWWWWWWWWWB",
-            "BWWWWWWWWWWB",
-            "BWWWWWWBBBBB",
-            "BWWWBWWB",
-            "BWWBBWWWB",
-            "BWB.BWWWB",
-            "BB..BWWWB",
-            "B....BWWWB",
-            ".....BWWWB",
-            "......BWWB",
-            "......BBB",
-        ];
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_dmabuf_texture(
+        &mut self,
+        texture: GlesTexture,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        src: Option<[f64; 4]>,
+        transform: crate::state::OutputTransform,
+        _is_external: bool,
+    ) {
+        let texture_id = self.render_commands.len() as u64;
+        self.frame_dmabufs.insert(texture_id, texture);
+        self.render_commands.push(RenderCommand::Texture {
+            texture_id,
+            x,
+            y,
+            width,
+            height,
+            is_dmabuf: true,
+            src,
+            alpha: self.alpha,
+            transform: smithay_transform(transform),
+        });
+    }
 
-        const CURSOR_W: usize = 12;
-        const CURSOR_H: usize = 19;
-
-        let cursor_id = u64::MAX - 1;
-
-        if !self.shm_textures.contains_key(&cursor_id) {
-            let mut pixels = vec![0u8; CURSOR_W * CURSOR_H * 4];
-
-            for (dy, row) in CURSOR.iter().enumerate() {
-                for (dx, ch) in row.chars().enumerate() {
-                    let (r, g, b, a) = match ch {
-                        'W' => (255, 255, 255, 255),
-                        'B' => (0, 0, 0, 255),
-                        _ => (0, 0, 0, 0),
-                    };
-                    let idx = (dy * CURSOR_W + dx) * 4;
-                    pixels[idx] = b;
-                    pixels[idx + 1] = g;
-                    pixels[idx + 2] = r;
-                    pixels[idx + 3] = a;
-                }
-            }
+    // x, y is where the hotspot goes
+    pub fn draw_cursor(&mut self, x: i32, y: i32, cursor: &Cursor, frame: usize) {
+        let cursor_id = CURSOR_TEXTURE_ID;
+        let frame = frame % cursor.frames.len();
+        let key = (cursor.serial, frame);
 
+        if self.cursor_key != Some(key) || !self.shm_textures.contains_key(&cursor_id) {
+            let image = cursor.frame(frame);
             self.upload_shm_texture(
                 cursor_id,
-                CURSOR_W as u32,
-                CURSOR_H as u32,
-                (CURSOR_W * 4) as u32,
-                &pixels,
+                image.width as u32,
+                image.height as u32,
+                (image.width * 4) as u32,
+                &image.to_bgra_bytes(),
             );
+            self.cursor_key = Some(key);
+            self.cursor_dims = (image.width as i32, image.height as i32);
         }
 
+        // the image is loaded at output scale, so it covers fewer logical pixels
+        let width = (self.cursor_dims.0 as f64 / self.scale).round() as i32;
+        let height = (self.cursor_dims.1 as f64 / self.scale).round() as i32;
+        let image = cursor.frame(frame);
+        let x = x - (image.xhot as f64 / self.scale).round() as i32;
+        let y = y - (image.yhot as f64 / self.scale).round() as i32;
+        self.cursor_rect = Some(to_physical(self.scale, x, y, width, height));
         self.render_commands.push(RenderCommand::Texture {
             texture_id: cursor_id,
             x,
             y,
-            width: CURSOR_W as i32,
-            height: CURSOR_H as i32,
+            width,
+            height,
             is_dmabuf: false,
+            src: None,
+            alpha: self.alpha,
+            transform: Transform::Normal,
         });
     }
 
-    pub fn remove_texture(&mut self, id: u64) {
-        self.shm_textures.remove(&id);
-        self.dmabuf_textures.remove(&id);
+    // drops everything imported from a destroyed wl_buffer
+    pub fn remove_buffer(&mut self, id: &ObjectId) {
+        self.dmabuf_textures.remove(id);
+        self.yuv_textures.remove(id);
+        if let Some(Some(fb)) = self.scanout_fbs.remove(id) {
+            // may still be on screen, so it goes once the next flip lands
+            self.stale_scanout_fbs.push(fb);
+            self.destroy_stale_scanout_fbs();
+        }
     }
 
     pub fn size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
 
+    // size of the drawing space once the output transform and scale are applied
+    pub fn logical_size(&self) -> (u32, u32) {
+        let size: Size<u32, smithay::utils::Physical> = Size::from((self.width, self.height));
+        let size = self.transform.transform_size(size);
+        (
+            (size.w as f64 / self.scale).round() as u32,
+            (size.h as f64 / self.scale).round() as u32,
+        )
+    }
+
+    pub fn set_scale(&mut self, scale: f64) {
+        let scale = scale.max(0.1);
+        if scale != self.scale {
+            self.scale = scale;
+            self.invalidate_buffers();
+        }
+    }
+
+    pub fn set_transform(&mut self, transform: crate::state::OutputTransform) {
+        let transform = smithay_transform(transform);
+        if transform != self.transform {
+            self.transform = transform;
+            self.invalidate_buffers();
+        }
+    }
+
     pub fn physical_size(&self) -> (u32, u32) {
         (self.physical_width, self.physical_height)
     }
 
+    pub fn available_modes(&self) -> Vec<crate::state::OutputMode> {
+        self.modes
+            .iter()
+            .map(|m| {
+                let (w, h) = m.size();
+                crate::state::OutputMode {
+                    width: w as i32,
+                    height: h as i32,
+                    refresh: m.vrefresh() as i32 * 1000,
+                    preferred: m
+                        .mode_type()
+                        .contains(drm::control::ModeTypeFlags::PREFERRED),
+                }
+            })
+            .collect()
+    }
+
+    pub fn vrr_capable(&self) -> bool {
+        self.vrr_capable
+    }
+
+    // Toggles the CRTC's VRR_ENABLED property; the display then refreshes
+    // whenever a flip lands instead of on a fixed cadence.
+    pub fn set_vrr(&mut self, enabled: bool) -> Result<(), String> {
+        if enabled == self.vrr_enabled {
+            return Ok(());
+        }
+        if enabled && !self.vrr_capable {
+            return Err("display is not VRR capable".to_string());
+        }
+        if let Some(ref mut atomic) = self.atomic {
+            if !atomic.has_vrr() {
+                return Err("CRTC has no VRR_ENABLED property".to_string());
+            }
+            atomic.set_vrr(enabled);
+            self.vrr_enabled = enabled;
+            log::info!(
+                "[gpu] VRR {} from the next frame",
+                if enabled { "enabled" } else { "disabled" }
+            );
+            return Ok(());
+        }
+        let property = self
+            .vrr_property
+            .ok_or("CRTC has no VRR_ENABLED property")?;
+        let card = self
+            .drm_device
+            .try_clone()
+            .map(DrmCard)
+            .map_err(|e| e.to_string())?;
+        card.set_property(self.crtc, property, enabled as u64)
+            .map_err(|e| format!("setting VRR_ENABLED failed: {}", e))?;
+        self.vrr_enabled = enabled;
+        log::info!("[gpu] VRR {}", if enabled { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    pub fn refresh(&self) -> i32 {
+        self.mode.vrefresh() as i32 * 1000
+    }
+
+    // live modeset: swaps in scanout buffers of the new size, the crtc is reprogrammed on the next frame
+    pub fn set_mode(
+        &mut self,
+        width: u16,
+        height: u16,
+        refresh: Option<u32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mode = self
+            .modes
+            .iter()
+            .filter(|m| m.size() == (width, height))
+            .filter(|m| refresh.is_none_or(|r| m.vrefresh() == r))
+            .max_by_key(|m| m.vrefresh())
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "mode {}x{}{} not supported by this display",
+                    width,
+                    height,
+                    refresh.map(|r| format!("@{}Hz", r)).unwrap_or_default()
+                )
+            })?;
+
+        self.wait_for_frame();
+        if self.flip_pending {
+            self.wait_for_flip();
+            self.flip_pending = false;
+        }
+
+        let card = DrmCard(self.drm_device.try_clone()?);
+        let (width, height) = (width as u32, height as u32);
+        let render_buffers = Self::create_render_buffers(
+            &self.gbm,
+            &card,
+            (width, height),
+            self.scanout_format,
+            &self.scanout_modifiers,
+        )?;
+        let old_buffers = std::mem::replace(&mut self.render_buffers, render_buffers);
+        let old_mode = std::mem::replace(&mut self.mode, mode);
+        let old_size = (self.width, self.height);
+
+        self.width = width;
+        self.height = height;
+        self.current_buffer = 0;
+        self.mode_set = false;
+        self.flipped = false;
+        self.pending_fb = None;
+        self.current_fb = None;
+
+        // set the crtc right away with a blank frame, so the old framebuffers are off screen
+        self.begin_frame();
+        self.draw_rect(0, 0, width as i32, height as i32, [0.0, 0.0, 0.0, 1.0]);
+        self.end_frame();
+        self.wait_for_frame();
+
+        let stale = if self.mode_set {
+            old_buffers
+        } else {
+            self.mode = old_mode;
+            (self.width, self.height) = old_size;
+            self.current_buffer = 0;
+            std::mem::replace(&mut self.render_buffers, old_buffers)
+        };
+        for buffer in &stale {
+            if let Some(fb) = buffer.fb {
+                card.destroy_framebuffer(fb).ok();
+            }
+        }
+        if !self.mode_set {
+            return Err("the display rejected the new mode".into());
+        }
+        self.counters.swapchain_recreations += 1;
+
+        log::info!(
+            "[gpu] Mode set to {}x{}@{}Hz",
+            width,
+            height,
+            mode.vrefresh()
+        );
+        Ok(())
+    }
+
     pub fn drm_fd(&self) -> BorrowedFd<'_> {
         self.drm_device.as_fd()
     }
 
+    // a frame still on the render thread counts, it is flipped once done
     pub fn is_flip_pending(&self) -> bool {
-        self.flip_pending
+        self.flip_pending || self.in_flight.is_some()
     }
 
     pub fn handle_drm_event(&mut self) -> bool {
@@ -783,6 +2139,7 @@ impl GpuRenderer {
 
                 self.current_fb = self.pending_fb.take();
                 self.flip_pending = false;
+                self.destroy_stale_scanout_fbs();
 
                 return true;
             }
@@ -826,27 +2183,52 @@ impl GpuRenderer {
         card_dev
     }
 
-    pub fn read_pixels(&mut self, x: i32, y: i32, width: i32, height: i32) -> Vec<u32> {
+    // Without cursor, reads the cursorless copy when there is one. Rows of
+    // width, black where the area runs off the framebuffer.
+    pub fn read_pixels(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        cursor: bool,
+    ) -> Vec<u32> {
+        let mut pixels = vec![0u32; (width.max(0) * height.max(0)) as usize];
+        let (fb_width, fb_height) = (self.width as i32, self.height as i32);
+        let (x1, y1) = (x.clamp(0, fb_width), y.clamp(0, fb_height));
+        let x2 = (x + width).clamp(0, fb_width);
+        let y2 = (y + height).clamp(0, fb_height);
+        if x2 <= x1 || y2 <= y1 {
+            return pixels;
+        }
+
+        self.wait_for_frame();
         let presented_buffer = if self.current_buffer == 0 { 1 } else { 0 };
-        let dmabuf = &mut self.render_buffers[presented_buffer].dmabuf;
-        let target = match self.renderer.bind(dmabuf) {
+        let target = match self.cursorless.as_mut() {
+            Some(buffer) if !cursor && self.cursorless_ready => self.renderer.bind(buffer),
+            _ => self
+                .renderer
+                .bind(&mut self.render_buffers[presented_buffer].dmabuf),
+        };
+        let target = match target {
             Ok(t) => t,
             Err(e) => {
                 log::error!("[gpu] Failed to bind dmabuf for read_pixels: {:?}", e);
-                return vec![0u32; (width * height) as usize];
+                return pixels;
             }
         };
 
-        let region: Rectangle<i32, BufferCoord> = Rectangle::new(
-            Point::from((x, y)),
-            Size::from((width, height)),
-        );
+        let region: Rectangle<i32, BufferCoord> =
+            Rectangle::new(Point::from((x1, y1)), Size::from((x2 - x1, y2 - y1)));
 
-        let mapping = match self.renderer.copy_framebuffer(&target, region, Fourcc::Argb8888) {
+        let mapping = match self
+            .renderer
+            .copy_framebuffer(&target, region, Fourcc::Argb8888)
+        {
             Ok(m) => m,
             Err(e) => {
                 log::error!("[gpu] Failed to copy framebuffer: {:?}", e);
-                return vec![0u32; (width * height) as usize];
+                return pixels;
             }
         };
 
@@ -854,80 +2236,440 @@ impl GpuRenderer {
             Ok(b) => b,
             Err(e) => {
                 log::error!("[gpu] Failed to map texture: {:?}", e);
-                return vec![0u32; (width * height) as usize];
+                return pixels;
             }
         };
 
-        let mut pixels = Vec::with_capacity((width * height) as usize);
-        for chunk in bytes.chunks_exact(4) {
-            // TODO: we make a possibly bad assumption here (low priority)
-            let pixel = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
-            pixels.push(pixel);
+        // glReadPixels counts rows up from the bottom, but frames are drawn
+        // with y pointing down, so the first row read is the top one. Rows
+        // are packed, 4-byte pixels meet GL's default pack alignment.
+        let copy_width = (x2 - x1) as usize;
+        for (row, line) in bytes.chunks_exact(copy_width * 4).enumerate() {
+            let start = ((y1 - y) as usize + row) * width as usize + (x1 - x) as usize;
+            let dst = &mut pixels[start..start + copy_width];
+            for (pixel, chunk) in dst.iter_mut().zip(line.chunks_exact(4)) {
+                // TODO: we make a possibly bad assumption here (low priority)
+                *pixel = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            }
         }
 
         pixels
     }
 
+    // blits the last presented frame into a client dmabuf; the copy finishes when the fence signals
+    pub fn copy_to_dmabuf(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        info: &crate::state::DmaBufBufferInfo,
+        cursor: bool,
+    ) -> Option<SyncPoint> {
+        self.wait_for_frame();
+        let fourcc = DrmFourcc::try_from(info.format).ok()?;
+        let mut builder = Dmabuf::builder(
+            (info.width, info.height),
+            fourcc,
+            DrmModifier::from(info.modifier),
+            smithay::backend::allocator::dmabuf::DmabufFlags::empty(),
+        );
+        if info.planes.is_empty() {
+            let fd = unsafe { OwnedFd::from_raw_fd(libc::dup(info.fd.as_raw_fd())) };
+            if !builder.add_plane(fd, 0, info.offset, info.stride) {
+                return None;
+            }
+        } else {
+            for (i, plane) in info.planes.iter().enumerate() {
+                let fd = unsafe { OwnedFd::from_raw_fd(libc::dup(plane.fd.as_raw_fd())) };
+                if !builder.add_plane(fd, i as u32, plane.offset, plane.stride) {
+                    return None;
+                }
+            }
+        }
+        let mut client_dmabuf = builder.build()?;
+
+        let presented_buffer = if self.current_buffer == 0 { 1 } else { 0 };
+        let source = match self.cursorless.as_mut() {
+            Some(buffer) if !cursor && self.cursorless_ready => self.renderer.bind(buffer),
+            _ => self
+                .renderer
+                .bind(&mut self.render_buffers[presented_buffer].dmabuf),
+        };
+        let source = match source {
+            Ok(t) => t,
+            Err(e) => {
+                log::error!(
+                    "[gpu] Failed to bind presented buffer for screencopy: {:?}",
+                    e
+                );
+                return None;
+            }
+        };
+        let mut target = match self.renderer.bind(&mut client_dmabuf) {
+            Ok(t) => t,
+            Err(e) => {
+                log::warn!("[gpu] Failed to bind client dmabuf for screencopy: {:?}", e);
+                return None;
+            }
+        };
+
+        let size = Size::from((width, height));
+        let mut frame = self.renderer.render(&mut target, size, Transform::Normal).ok()?;
+        let src = Rectangle::new(Point::from((x, y)), size);
+        let dst = Rectangle::new(Point::from((0, 0)), size);
+        if let Err(e) = frame.blit_from(&source, src, dst, TextureFilter::Nearest) {
+            log::warn!("[gpu] Screencopy blit failed: {:?}", e);
+            return None;
+        }
+        frame.finish().ok()
+    }
+
     pub fn texture_count(&self) -> usize {
-        self.shm_textures.len() + self.dmabuf_textures.len()
+        self.shm_textures.len() + self.dmabuf_textures.len() + self.yuv_textures.len()
+    }
+
+    // Estimated at 4 bytes per texel, like retain_textures reports
+    pub fn texture_bytes(&self) -> u64 {
+        let single = self.shm_textures.values().chain(self.dmabuf_textures.values());
+        let planes = self.yuv_textures.values().flatten();
+        single
+            .chain(planes)
+            .map(|tex| tex.width() as u64 * tex.height() as u64 * 4)
+            .sum()
+    }
+
+    pub fn last_gpu_time(&self) -> Duration {
+        self.last_gpu_time
+    }
+
+    pub fn last_damage_rects(&self) -> usize {
+        self.last_damage_rects
+    }
+
+    pub fn counters(&self) -> RendererCounters {
+        self.counters
+    }
+
+    pub fn retain_textures(
+        &mut self,
+        keep: impl Fn(u64) -> bool,
+        keep_buffer: impl Fn(&ObjectId) -> bool,
+    ) -> (usize, usize) {
+        let mut count = 0;
+        let mut bytes = 0;
+        self.shm_textures.retain(|id, tex| {
+            if *id == CURSOR_TEXTURE_ID
+                || *id == PROFILER_TEXTURE_ID
+                || *id == BANNER_TEXTURE_ID
+                || *id == HELP_TEXTURE_ID
+                || *id == WALLPAPER_TEXTURE_ID
+                || keep(*id)
+            {
+                return true;
+            }
+            count += 1;
+            bytes += tex.width() as usize * tex.height() as usize * 4;
+            false
+        });
+        self.dmabuf_textures.retain(|id, tex| {
+            if keep_buffer(id) {
+                return true;
+            }
+            count += 1;
+            bytes += tex.width() as usize * tex.height() as usize * 4;
+            false
+        });
+        self.yuv_textures.retain(|id, planes| {
+            if keep_buffer(id) {
+                return true;
+            }
+            count += 1;
+            bytes += planes
+                .iter()
+                .map(|tex| tex.width() as usize * tex.height() as usize * 4)
+                .sum::<usize>();
+            false
+        });
+        let shm_textures = &self.shm_textures;
+        self.title_keys.retain(|id, _| shm_textures.contains_key(id));
+        (count, bytes)
     }
 
     pub fn draw_profiler(&mut self, stats: &ProfilerStats) {
         let lines = [
             format!("FPS: {:.1}", stats.fps),
-            format!("Frame: {:.2}ms", stats.frame_time_ms),
-            format!("Render: {}us", stats.render_time_us),
+            format!(
+                "Frame: {:.2}ms max {:.2}ms",
+                stats.frame_time_ms, stats.max_frame_time_ms
+            ),
             format!("Input: {}us", stats.input_time_us),
+            format!("Render: {}us", stats.render_time_us),
+            format!("GPU: {}us", stats.gpu_time_us),
+            format!("Damage: {} rects", stats.damage_rects),
             format!("Mem: {:.1}MB", stats.memory_mb),
             format!("Windows: {}", stats.window_count),
-            format!("Textures: {}", stats.texture_count),
+            format!(
+                "Textures: {} ({:.1}MB)",
+                stats.texture_count, stats.texture_mb
+            ),
         ];
 
-        let scale: usize = 2;
-        let char_w = FONT_CHAR_WIDTH * scale;
-        let char_h = FONT_CHAR_HEIGHT * scale;
-        let line_height = char_h + 2;
+        let bg = [0, 0, 0, 180];
+        let (mut pixels, box_width, text_height) = Self::text_box_pixels(&lines, bg);
+        let box_height = text_height
+            + Self::append_frame_graph(
+                &mut pixels,
+                box_width,
+                bg,
+                &stats.frame_history,
+                stats.frame_budget_ms,
+            );
+
+        let profiler_id = PROFILER_TEXTURE_ID;
+        self.upload_shm_texture(
+            profiler_id,
+            box_width as u32,
+            box_height as u32,
+            (box_width * 4) as u32,
+            &pixels,
+        );
+
+        let box_x = self.logical_size().0 as i32 - box_width as i32 - 10;
+        let box_y = 10;
+
+        self.render_commands.push(RenderCommand::Texture {
+            texture_id: profiler_id,
+            x: box_x,
+            y: box_y,
+            width: box_width as i32,
+            height: box_height as i32,
+            is_dmabuf: false,
+            src: None,
+            alpha: self.alpha,
+            transform: Transform::Normal,
+        });
+    }
+
+    // Adds a bar per frame below the text, newest on the right. The line
+    // across the middle is the refresh period; bars over it are yellow, over
+    // twice it red and clipped. Returns the rows added.
+    fn append_frame_graph(
+        pixels: &mut Vec<u8>,
+        width: usize,
+        bg: [u8; 4],
+        history: &[f32],
+        budget_ms: f32,
+    ) -> usize {
+        const GRAPH_HEIGHT: usize = 48;
+        const BAR_WIDTH: usize = 2;
         let padding: usize = 8;
 
-        let max_chars = lines.iter().map(|l| l.len()).max().unwrap_or(0);
-        let box_width = max_chars * char_w + padding * 2;
-        let box_height = lines.len() * line_height + padding * 2;
-        let mut pixels = vec![0u8; box_width * box_height * 4];
+        let top = pixels.len() / 4 / width;
+        pixels.extend(bg.repeat(width * (GRAPH_HEIGHT + padding)));
+        let bottom = top + GRAPH_HEIGHT;
+        let mut fill = |x: usize, y: usize, color: [u8; 4]| {
+            let i = (y * width + x) * 4;
+            pixels[i..i + 4].copy_from_slice(&color);
+        };
 
-        for i in 0..(box_width * box_height) {
-            pixels[i * 4] = 0;
-            pixels[i * 4 + 1] = 0;
-            pixels[i * 4 + 2] = 0;
-            pixels[i * 4 + 3] = 180;
+        let bars = width.saturating_sub(padding * 2) / BAR_WIDTH;
+        let budget_ms = budget_ms.max(1.0);
+        for (i, &ms) in history.iter().rev().take(bars).enumerate() {
+            let height = (ms / (budget_ms * 2.0) * GRAPH_HEIGHT as f32).round() as usize;
+            let height = height.clamp(1, GRAPH_HEIGHT);
+            // BGRA, as uploaded
+            let color = if ms <= budget_ms {
+                [80, 200, 80, 255]
+            } else if ms <= budget_ms * 2.0 {
+                [40, 200, 230, 255]
+            } else {
+                [60, 60, 230, 255]
+            };
+            let x = width - padding - (i + 1) * BAR_WIDTH;
+            for y in bottom - height..bottom {
+                for x in x..x + BAR_WIDTH {
+                    fill(x, y, color);
+                }
+            }
         }
+        for x in padding..width - padding {
+            fill(x, bottom - GRAPH_HEIGHT / 2, [160, 160, 160, 255]);
+        }
+        GRAPH_HEIGHT + padding
+    }
 
-        for (line_idx, line) in lines.iter().enumerate() {
-            let text_y = padding + line_idx * line_height;
-            for (char_idx, ch) in line.chars().enumerate() {
-                let text_x = padding + char_idx * char_w;
-                Self::draw_char_to_buffer(&mut pixels, box_width, text_x, text_y, ch, scale);
+    // The wallpaper arrives composed at the output's size, so it is uploaded
+    // once per change and stretched over the logical space 1:1 in pixels.
+    // None frees the texture.
+    pub fn draw_wallpaper(&mut self, wallpaper: Option<&Wallpaper>) {
+        let Some(wallpaper) = wallpaper else {
+            if self.wallpaper_key.take().is_some() {
+                self.shm_textures.remove(&WALLPAPER_TEXTURE_ID);
             }
+            return;
+        };
+        if self.wallpaper_key != Some(wallpaper.serial)
+            || !self.shm_textures.contains_key(&WALLPAPER_TEXTURE_ID)
+        {
+            let data: &[u8] = unsafe {
+                std::slice::from_raw_parts(
+                    wallpaper.pixels.as_ptr() as *const u8,
+                    wallpaper.pixels.len() * 4,
+                )
+            };
+            self.upload_shm_texture(
+                WALLPAPER_TEXTURE_ID,
+                wallpaper.width,
+                wallpaper.height,
+                wallpaper.width * 4,
+                data,
+            );
+            self.wallpaper_key = Some(wallpaper.serial);
         }
 
-        let profiler_id = u64::MAX - 2;
+        let (width, height) = self.logical_size();
+        self.render_commands.push(RenderCommand::Texture {
+            texture_id: WALLPAPER_TEXTURE_ID,
+            x: 0,
+            y: 0,
+            width: width as i32,
+            height: height as i32,
+            is_dmabuf: false,
+            src: None,
+            alpha: 1.0,
+            transform: Transform::Normal,
+        });
+    }
+
+    pub fn draw_banner(&mut self, lines: &[String]) {
+        self.draw_text_box(BANNER_TEXTURE_ID, lines, [32, 32, 176, 230], false);
+    }
+
+    pub fn draw_help(&mut self, lines: &[String]) {
+        self.draw_text_box(HELP_TEXTURE_ID, lines, [0, 0, 0, 200], true);
+    }
+
+    fn draw_text_box(&mut self, texture_id: u64, lines: &[String], bg: [u8; 4], centered: bool) {
+        let (pixels, box_width, box_height) = Self::text_box_pixels(lines, bg);
+
         self.upload_shm_texture(
-            profiler_id,
+            texture_id,
             box_width as u32,
             box_height as u32,
             (box_width * 4) as u32,
             &pixels,
         );
 
-        let box_x = self.width as i32 - box_width as i32 - 10;
-        let box_y = 10;
+        let (width, height) = self.logical_size();
+        let box_x = (width as i32 - box_width as i32).max(0) / 2;
+        let box_y = if centered {
+            (height as i32 - box_height as i32).max(0) / 2
+        } else {
+            10
+        };
 
         self.render_commands.push(RenderCommand::Texture {
-            texture_id: profiler_id,
+            texture_id,
             x: box_x,
             y: box_y,
             width: box_width as i32,
             height: box_height as i32,
             is_dmabuf: false,
+            src: None,
+            alpha: self.alpha,
+            transform: Transform::Normal,
+        });
+    }
+
+    // Renders white 5x7 text over a solid BGRA background, returning the
+    // pixels and box size.
+    fn text_box_pixels(lines: &[String], bg: [u8; 4]) -> (Vec<u8>, usize, usize) {
+        let scale: usize = 2;
+        let char_w = FONT_CHAR_WIDTH * scale;
+        let char_h = FONT_CHAR_HEIGHT * scale;
+        let line_height = char_h + 2;
+        let padding: usize = 8;
+
+        let max_chars = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+        let box_width = max_chars * char_w + padding * 2;
+        let box_height = lines.len() * line_height + padding * 2;
+        let mut pixels = bg.repeat(box_width * box_height);
+
+        for (line_idx, line) in lines.iter().enumerate() {
+            let text_y = padding + line_idx * line_height;
+            for (char_idx, ch) in line.chars().enumerate() {
+                let text_x = padding + char_idx * char_w;
+                Self::draw_char_to_buffer(
+                    &mut pixels,
+                    box_width,
+                    text_x,
+                    text_y,
+                    ch,
+                    scale,
+                    [255, 255, 255, 255],
+                );
+            }
+        }
+
+        (pixels, box_width, box_height)
+    }
+
+    // Title text is cached per window and only re-rasterized when the text,
+    // color or scale changes.
+    pub fn draw_title(
+        &mut self,
+        window_id: u64,
+        text: &str,
+        x: i32,
+        y: i32,
+        scale: usize,
+        color: u32,
+    ) {
+        let texture_id = TITLE_TEXTURE_OFFSET + window_id;
+        let char_w = FONT_CHAR_WIDTH * scale;
+        let width = text.chars().count() * char_w;
+        let height = FONT_CHAR_HEIGHT * scale;
+        if width == 0 {
+            return;
+        }
+
+        let key = (text.to_string(), color, scale);
+        if self.title_keys.get(&texture_id) != Some(&key)
+            || !self.shm_textures.contains_key(&texture_id)
+        {
+            let mut pixels = vec![0u8; width * height * 4];
+            for (i, ch) in text.chars().enumerate() {
+                Self::draw_char_to_buffer(
+                    &mut pixels,
+                    width,
+                    i * char_w,
+                    0,
+                    ch,
+                    scale,
+                    color.to_le_bytes(),
+                );
+            }
+            self.upload_shm_texture(
+                texture_id,
+                width as u32,
+                height as u32,
+                (width * 4) as u32,
+                &pixels,
+            );
+            self.title_keys.insert(texture_id, key);
+        }
+
+        self.render_commands.push(RenderCommand::Texture {
+            texture_id,
+            x,
+            y,
+            width: width as i32,
+            height: height as i32,
+            is_dmabuf: false,
+            src: None,
+            alpha: self.alpha,
+            transform: Transform::Normal,
         });
     }
 
@@ -938,6 +2680,7 @@ impl GpuRenderer {
         y: usize,
         ch: char,
         scale: usize,
+        color: [u8; 4],
     ) {
         let idx = if ch.is_ascii() && ch >= ' ' {
             (ch as usize) - 32
@@ -961,10 +2704,7 @@ impl GpuRenderer {
                             let screen_y = y + cy * scale + sy;
                             let pixel_idx = (screen_y * stride + screen_x) * 4;
                             if pixel_idx + 3 < pixels.len() {
-                                pixels[pixel_idx] = 255;
-                                pixels[pixel_idx + 1] = 255;
-                                pixels[pixel_idx + 2] = 255;
-                                pixels[pixel_idx + 3] = 255;
+                                pixels[pixel_idx..pixel_idx + 4].copy_from_slice(&color);
                             }
                         }
                     }
@@ -979,6 +2719,7 @@ struct DrmBuffer {
     width: u32,
     height: u32,
     stride: u32,
+    format: DrmFourcc,
 }
 
 impl drm::buffer::Buffer for DrmBuffer {
@@ -986,7 +2727,7 @@ impl drm::buffer::Buffer for DrmBuffer {
         (self.width, self.height)
     }
     fn format(&self) -> drm::buffer::DrmFourcc {
-        drm::buffer::DrmFourcc::Xrgb8888
+        self.format
     }
     fn pitch(&self) -> u32 {
         self.stride
@@ -996,10 +2737,44 @@ impl drm::buffer::Buffer for DrmBuffer {
     }
 }
 
+struct ScanoutBuffer {
+    handle: drm::buffer::Handle,
+    width: u32,
+    height: u32,
+    stride: u32,
+    offset: u32,
+    modifier: DrmModifier,
+    format: DrmFourcc,
+}
+
+impl drm::buffer::PlanarBuffer for ScanoutBuffer {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+    fn format(&self) -> drm::buffer::DrmFourcc {
+        self.format
+    }
+    fn modifier(&self) -> Option<DrmModifier> {
+        (self.modifier != DrmModifier::Invalid).then_some(self.modifier)
+    }
+    fn pitches(&self) -> [u32; 4] {
+        [self.stride, 0, 0, 0]
+    }
+    fn handles(&self) -> [Option<drm::buffer::Handle>; 4] {
+        [Some(self.handle), None, None, None]
+    }
+    fn offsets(&self) -> [u32; 4] {
+        [self.offset, 0, 0, 0]
+    }
+}
+
 impl Drop for GpuRenderer {
     fn drop(&mut self) {
+        // the render thread may still be drawing into the buffers below
+        self.render_thread = None;
         self.shm_textures.clear();
         self.dmabuf_textures.clear();
+        self.frame_dmabufs.clear();
 
         if let Ok(card) = self.drm_device.try_clone().map(DrmCard) {
             for buffer in &self.render_buffers {
@@ -1007,6 +2782,10 @@ impl Drop for GpuRenderer {
                     card.destroy_framebuffer(fb).ok();
                 }
             }
+            let scanout = self.scanout_fbs.values().flatten();
+            for fb in scanout.chain(&self.stale_scanout_fbs) {
+                card.destroy_framebuffer(*fb).ok();
+            }
         }
     }
 }
@@ -1019,9 +2798,16 @@ const FONT_CHARS_PER_ROW: usize = 16;
 pub struct ProfilerStats {
     pub fps: f32,
     pub frame_time_ms: f32,
+    pub max_frame_time_ms: f32,
     pub render_time_us: u64,
     pub input_time_us: u64,
+    pub gpu_time_us: u64,
+    pub damage_rects: usize,
     pub memory_mb: f32,
     pub window_count: usize,
     pub texture_count: usize,
+    pub texture_mb: f32,
+    // frame times in ms, oldest first
+    pub frame_history: Vec<f32>,
+    pub frame_budget_ms: f32,
 }
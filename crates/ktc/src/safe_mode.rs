@@ -0,0 +1,46 @@
+use ktc_common::ktc_data_dir;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const CRASH_LIMIT: u32 = 2;
+pub const STARTUP_GRACE: Duration = Duration::from_secs(10);
+
+fn marker_path() -> PathBuf {
+    ktc_data_dir().join("startup-crashes")
+}
+
+// Bumps the startup marker and returns how many previous startups never
+// reached STARTUP_GRACE, i.e. crashed before settling.
+pub fn begin_startup() -> u32 {
+    let path = marker_path();
+    let crashes = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .unwrap_or(0);
+
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    if let Err(e) = std::fs::write(&path, (crashes + 1).to_string()) {
+        log::warn!("[safe-mode] Failed to write {}: {}", path.display(), e);
+    }
+
+    crashes
+}
+
+pub fn startup_succeeded() {
+    let path = marker_path();
+    if path.exists() {
+        if let Err(e) = std::fs::remove_file(&path) {
+            log::warn!("[safe-mode] Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub fn banner_lines(reason: &str) -> Vec<String> {
+    vec![
+        format!("SAFE MODE: {}", reason),
+        "Default keybinds active - Alt+Return: terminal, Ctrl+Alt+Q: exit".to_string(),
+        "Fix ~/.config/ktc/config.toml and press Alt+Shift+C to reload".to_string(),
+    ]
+}
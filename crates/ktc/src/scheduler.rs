@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+// Decides when the next frame may be drawn. The GPU renderer is paced by its
+// page flips: the frame after a flip starts right away, or render_ahead
+// before the following vblank. Frames that queue no flip (the CPU renderer,
+// or only frame callbacks to answer) are kept one refresh period apart.
+pub struct FrameScheduler {
+    next_frame: Instant,
+}
+
+impl FrameScheduler {
+    pub fn new() -> Self {
+        Self {
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn flipped(&mut self, period: Duration, render_ahead: Duration) {
+        let now = Instant::now();
+        self.next_frame = if render_ahead.is_zero() || render_ahead >= period {
+            now
+        } else {
+            now + (period - render_ahead)
+        };
+    }
+
+    pub fn drawn_without_flip(&mut self, period: Duration) {
+        self.next_frame = Instant::now() + period;
+    }
+
+    // VRR fullscreen frames don't wait for anything
+    pub fn allow_now(&mut self) {
+        self.next_frame = Instant::now();
+    }
+
+    pub fn is_due(&self) -> bool {
+        Instant::now() >= self.next_frame
+    }
+
+    pub fn next_frame(&self) -> Instant {
+        self.next_frame
+    }
+}
+
+// refresh is in mHz, as on wl_output
+pub fn frame_period(refresh: i32) -> Duration {
+    if refresh > 0 {
+        Duration::from_secs_f64(1000.0 / refresh as f64)
+    } else {
+        Duration::from_micros(16_667)
+    }
+}
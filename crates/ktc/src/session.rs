@@ -110,7 +110,7 @@ fn setup_signal_handlers() -> Result<(), Box<dyn std::error::Error>> {
         libc::signal(libc::SIGTTOU, libc::SIG_IGN);
 
         let mut sa: libc::sigaction = std::mem::zeroed();
-        sa.sa_sigaction = signal_handler as usize;
+        sa.sa_sigaction = signal_handler as *const () as usize;
         sa.sa_flags = libc::SA_RESTART;
 
         libc::sigemptyset(&mut sa.sa_mask);
@@ -1,66 +1,58 @@
-use crate::config::Config;
-use crate::protocols::screencopy::PendingScreencopy;
-use std::collections::HashMap;
+use crate::config::{Config, GapChange, PointerGrab};
+use crate::coords::surface_local;
+use crate::cursor::{Cursor, CursorShape, CursorTheme};
+use crate::input::{PointerState, ScrollSource, SeatCapabilities};
+use crate::protocols::screencopy::{PendingScreencopy, ScreencopyClient};
+use crate::protocols::tablet::{Tablet, TabletTool};
+use crate::recorder::{FinishingRecording, Recorder};
+use crate::wallpaper::WallpaperState;
+use std::collections::{HashMap, HashSet};
 use std::os::fd::{AsFd, AsRawFd, OwnedFd};
 use std::ptr::NonNull;
+use wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_dmabuf_feedback_v1::ZwpLinuxDmabufFeedbackV1;
+use wayland_protocols::wp::tablet::zv2::server::zwp_tablet_seat_v2::ZwpTabletSeatV2;
 use wayland_protocols::xdg::shell::server::{
     xdg_surface::XdgSurface,
     xdg_toplevel::{State as ToplevelState, XdgToplevel},
+    xdg_wm_base::XdgWmBase,
 };
 use wayland_protocols_wlr::layer_shell::v1::server::zwlr_layer_surface_v1::{
     Anchor, KeyboardInteractivity, ZwlrLayerSurfaceV1,
 };
+use wayland_protocols_wlr::output_management::v1::server::zwlr_output_manager_v1::ZwlrOutputManagerV1;
 use wayland_server::backend::ObjectId;
 use wayland_server::protocol::{
     wl_buffer::WlBuffer, wl_callback::WlCallback, wl_keyboard::WlKeyboard, wl_output::WlOutput,
-    wl_pointer::WlPointer, wl_shm_pool::WlShmPool, wl_surface::WlSurface,
+    wl_pointer::WlPointer, wl_seat::WlSeat, wl_shm, wl_shm_pool::WlShmPool, wl_surface::WlSurface,
 };
 use wayland_server::Resource;
 
+pub use ktc_common::{Canvas, Rectangle};
+
+pub const SCRATCHPAD_WORKSPACE: usize = 0;
+const DEFAULT_WORKSPACE_COUNT: usize = 4;
+const FLOATING_MOVE_STEP: i32 = 32;
+
+// app_ids that are always dialogs, unless a rule says otherwise
+const DIALOG_APP_IDS: &[&str] = &[
+    "*pinentry*",
+    "*polkit*",
+    "*zenity*",
+    "*-portal-*",
+    "*askpass*",
+    "*file-roller*",
+];
+
+const ACTIVATION_TOKEN_LIFETIME: std::time::Duration = std::time::Duration::from_secs(60);
+const TEXTURE_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+const SPLIT_GRAB_MARGIN: i32 = 6;
+const PREVIEW_MAX_SIDE: u32 = 256;
+const SPLIT_PREVIEW_WIDTH: i32 = 4;
+const BTN_LEFT: u32 = 0x110;
+
 pub type WindowId = u64;
 pub type OutputId = u64;
 
-#[derive(Clone, Copy, Debug, Default, PartialEq)]
-pub struct Rectangle {
-    pub x: i32,
-    pub y: i32,
-    pub width: i32,
-    pub height: i32,
-}
-
-impl Rectangle {
-    pub fn union(&self, other: &Rectangle) -> Rectangle {
-        if self.width == 0 || self.height == 0 {
-            return *other;
-        }
-        if other.width == 0 || other.height == 0 {
-            return *self;
-        }
-        let x1 = self.x.min(other.x);
-        let y1 = self.y.min(other.y);
-        let x2 = (self.x + self.width).max(other.x + other.width);
-        let y2 = (self.y + self.height).max(other.y + other.height);
-        Rectangle {
-            x: x1,
-            y: y1,
-            width: x2 - x1,
-            height: y2 - y1,
-        }
-    }
-
-    #[allow(dead_code)]
-    pub fn intersects(&self, other: &Rectangle) -> bool {
-        self.x < other.x + other.width
-            && self.x + self.width > other.x
-            && self.y < other.y + other.height
-            && self.y + self.height > other.y
-    }
-
-    pub fn is_empty(&self) -> bool {
-        self.width <= 0 || self.height <= 0
-    }
-}
-
 #[derive(Clone, Default)]
 pub struct DamageTracker {
     regions: Vec<Rectangle>,
@@ -117,12 +109,10 @@ impl DamageTracker {
         self.cursor_only && !self.full_damage && self.regions.is_empty()
     }
 
-    #[allow(dead_code)]
     pub fn is_full_damage(&self) -> bool {
         self.full_damage
     }
 
-    #[allow(dead_code)]
     pub fn damage_regions(&self) -> &[Rectangle] {
         &self.regions
     }
@@ -151,6 +141,133 @@ impl DamageTracker {
     }
 }
 
+// Parts of a surface's buffer that changed, in buffer pixels, collected from
+// wl_surface.damage(_buffer) until the GPU texture catches up
+#[derive(Clone, Debug, Default)]
+pub struct BufferDamage {
+    regions: Vec<Rectangle>,
+    full: bool,
+}
+
+impl BufferDamage {
+    pub fn full() -> Self {
+        Self {
+            regions: Vec::new(),
+            full: true,
+        }
+    }
+
+    pub fn add(&mut self, rect: Rectangle) {
+        if self.full || rect.is_empty() {
+            return;
+        }
+        // clients often damage (0, 0, INT32_MAX, INT32_MAX) to mean everything
+        let rect = Rectangle {
+            width: rect.width.min(i32::MAX - rect.x.max(0)),
+            height: rect.height.min(i32::MAX - rect.y.max(0)),
+            ..rect
+        };
+        // past a handful of rectangles one covering them all uploads faster
+        if self.regions.len() >= 16 {
+            let bounds = self.regions.iter().fold(rect, |acc, r| acc.union(r));
+            self.regions.clear();
+            self.regions.push(bounds);
+        } else {
+            self.regions.push(rect);
+        }
+    }
+
+    pub fn add_full(&mut self) {
+        self.full = true;
+        self.regions.clear();
+    }
+
+    pub fn merge(&mut self, other: BufferDamage) {
+        if other.full {
+            self.add_full();
+        }
+        for rect in other.regions {
+            self.add(rect);
+        }
+    }
+
+    pub fn take(&mut self) -> BufferDamage {
+        std::mem::take(self)
+    }
+
+    // wl_surface.damage rectangles in buffer pixels of a surface with this
+    // buffer_scale
+    pub fn scaled(self, scale: i32) -> BufferDamage {
+        let scale = scale.max(1);
+        let regions = self
+            .regions
+            .into_iter()
+            .map(|r| Rectangle {
+                x: r.x.saturating_mul(scale),
+                y: r.y.saturating_mul(scale),
+                width: r.width.saturating_mul(scale),
+                height: r.height.saturating_mul(scale),
+            })
+            .collect();
+        BufferDamage { regions, ..self }
+    }
+
+    // wl_surface.damage_buffer rectangles of a width x height buffer drawn
+    // with this transform, in the upright pixel cache's coordinates
+    pub fn untransformed(self, transform: OutputTransform, width: i32, height: i32) -> BufferDamage {
+        if self.full || transform == OutputTransform::Normal {
+            return self;
+        }
+        if width <= 0 || height <= 0 {
+            return BufferDamage::full();
+        }
+        let rects = self.regions(width, height).unwrap_or_default();
+        let inverse = transform.inverse();
+        let (w, h) = (width as usize, height as usize);
+        let mut damage = BufferDamage::default();
+        for r in rects.into_iter().filter(|r| !r.is_empty()) {
+            let (x1, y1) = inverse.map_pixel(r.x as usize, r.y as usize, w, h);
+            let (x2, y2) = inverse.map_pixel(
+                (r.x + r.width - 1) as usize,
+                (r.y + r.height - 1) as usize,
+                w,
+                h,
+            );
+            damage.add(Rectangle {
+                x: x1.min(x2) as i32,
+                y: y1.min(y2) as i32,
+                width: x1.abs_diff(x2) as i32 + 1,
+                height: y1.abs_diff(y2) as i32 + 1,
+            });
+        }
+        damage
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.full && self.regions.is_empty()
+    }
+
+    // The damaged rectangles inside a width x height buffer, or None when
+    // all of it has to be uploaded
+    pub fn regions(&self, width: i32, height: i32) -> Option<Vec<Rectangle>> {
+        if self.full {
+            return None;
+        }
+        let bounds = Rectangle {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        Some(
+            self.regions
+                .iter()
+                .filter_map(|r| r.intersection(&bounds))
+                .collect(),
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Output {
     pub id: OutputId,
@@ -164,13 +281,62 @@ pub struct Output {
     pub width: i32,
     pub height: i32,
     pub refresh: i32,
-    pub scale: i32,
+    pub scale: f64,
     pub transform: OutputTransform,
+    pub modes: Vec<OutputMode>,
+    pub vrr_capable: bool,
+    pub vrr_enabled: bool,
+    // set over IPC; None follows the night light schedule
+    pub color_override: Option<ColorAdjust>,
     pub wl_outputs: Vec<WlOutput>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorAdjust {
+    pub temperature: u32,
+    pub brightness: f64,
+}
+
+impl ColorAdjust {
+    pub const NEUTRAL: Self = Self {
+        temperature: ktc_common::NEUTRAL_TEMPERATURE,
+        brightness: 1.0,
+    };
+
+    pub fn gain(self) -> [f32; 3] {
+        let brightness = self.brightness.clamp(0.1, 1.0) as f32;
+        ktc_common::temperature_gain(self.temperature).map(|c| c * brightness)
+    }
+}
+
+// Per-channel lookup the CPU renderer applies while copying the canvas out
+pub struct ColorLut([[u8; 256]; 3]);
+
+impl ColorLut {
+    pub fn new(gain: [f32; 3]) -> Self {
+        Self(gain.map(|g| std::array::from_fn(|i| (i as f32 * g).round() as u8)))
+    }
+
+    pub fn apply(&self, pixel: u32) -> u32 {
+        let [b, g, r, a] = pixel.to_le_bytes();
+        u32::from_le_bytes([
+            self.0[2][b as usize],
+            self.0[1][g as usize],
+            self.0[0][r as usize],
+            a,
+        ])
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputMode {
+    pub width: i32,
+    pub height: i32,
+    pub refresh: i32,
+    pub preferred: bool,
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
-#[allow(dead_code)]
 pub enum OutputTransform {
     #[default]
     Normal,
@@ -183,6 +349,53 @@ pub enum OutputTransform {
     FlippedRotate270,
 }
 
+impl OutputTransform {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "normal" | "0" => Some(Self::Normal),
+            "90" => Some(Self::Rotate90),
+            "180" => Some(Self::Rotate180),
+            "270" => Some(Self::Rotate270),
+            "flipped" => Some(Self::Flipped),
+            "flipped-90" => Some(Self::FlippedRotate90),
+            "flipped-180" => Some(Self::FlippedRotate180),
+            "flipped-270" => Some(Self::FlippedRotate270),
+            _ => None,
+        }
+    }
+
+    pub fn swaps_axes(self) -> bool {
+        matches!(
+            self,
+            Self::Rotate90 | Self::Rotate270 | Self::FlippedRotate90 | Self::FlippedRotate270
+        )
+    }
+
+    // The transform undoing this one; only the quarter turns differ
+    pub fn inverse(self) -> Self {
+        match self {
+            Self::Rotate90 => Self::Rotate270,
+            Self::Rotate270 => Self::Rotate90,
+            other => other,
+        }
+    }
+
+    // Maps pixel (x, y) of a w x h logical image to its position in the
+    // framebuffer, matching the GPU renderer's projection.
+    pub fn map_pixel(self, x: usize, y: usize, w: usize, h: usize) -> (usize, usize) {
+        match self {
+            Self::Normal => (x, y),
+            Self::Rotate90 => (h - 1 - y, x),
+            Self::Rotate180 => (w - 1 - x, h - 1 - y),
+            Self::Rotate270 => (y, w - 1 - x),
+            Self::Flipped => (w - 1 - x, y),
+            Self::FlippedRotate90 => (y, x),
+            Self::FlippedRotate180 => (x, h - 1 - y),
+            Self::FlippedRotate270 => (h - 1 - y, w - 1 - x),
+        }
+    }
+}
+
 impl Output {
     pub fn new(id: OutputId, name: String, width: i32, height: i32) -> Self {
         Self {
@@ -197,487 +410,442 @@ impl Output {
             width,
             height,
             refresh: 60000,
-            scale: 1,
+            scale: 1.0,
             transform: OutputTransform::Normal,
+            modes: Vec::new(),
+            vrr_capable: false,
+            vrr_enabled: false,
+            color_override: None,
             wl_outputs: Vec::new(),
         }
     }
 
-    #[allow(dead_code)]
-    pub fn usable_area(&self) -> Rectangle {
+    pub fn is_internal(&self) -> bool {
+        ["eDP", "LVDS", "DSI"]
+            .iter()
+            .any(|prefix| self.name.starts_with(prefix))
+    }
+
+    // width/height are the mode's; layout and input work in the rotated,
+    // scaled-down space
+    pub fn logical_size(&self) -> (i32, i32) {
+        let (width, height) = if self.transform.swaps_axes() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        let scale = self.scale.max(0.1);
+        (
+            (width as f64 / scale).round() as i32,
+            (height as f64 / scale).round() as i32,
+        )
+    }
+
+    // the whole output in layout coordinates; State::usable_area is what is
+    // left of it after exclusive zones
+    pub fn rect(&self) -> Rectangle {
+        let (width, height) = self.logical_size();
         Rectangle {
             x: self.x,
             y: self.y,
-            width: self.width,
-            height: self.height,
+            width,
+            height,
         }
     }
+}
 
-    #[allow(dead_code)]
-    pub fn scaled_size(&self) -> (i32, i32) {
-        (self.width / self.scale, self.height / self.scale)
-    }
+#[derive(Default)]
+pub struct OutputConfig {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub physical_size: Option<(i32, i32)>,
+    pub resolution: Option<(i32, i32)>,
+    pub refresh: Option<i32>,
+    pub scale: Option<f64>,
+    pub transform: Option<OutputTransform>,
+    pub modes: Option<Vec<OutputMode>>,
+    pub vrr_capable: Option<bool>,
 }
 
-pub struct Canvas {
-    pub pixels: Vec<u32>,
-    pub cursor_save: Vec<u32>,
-    pub cursor_save_x: i32,
-    pub cursor_save_y: i32,
-    pub width: usize,
-    pub height: usize,
-    pub stride: usize,
+pub struct Window {
+    pub id: WindowId,
+    pub xdg_surface: XdgSurface,
+    pub xdg_toplevel: XdgToplevel,
+    pub wl_surface: WlSurface,
+    pub geometry: Rectangle,
+    pub mapped: bool,
+    pub buffer: Option<WlBuffer>,
+    pub pending_buffer: Option<WlBuffer>,
+    pub pending_buffer_set: bool,
+    pub buffer_released: bool,
+    pub needs_redraw: bool,
+    pub pixel_cache: Vec<u32>,
+    pub cache_width: usize,
+    pub cache_height: usize,
+    pub cache_stride: usize,
+    // pixel_cache holds ABGR2101010 rather than ARGB8888
+    pub cache_deep_color: bool,
+    // the buffer's alpha channel is padding, not coverage
+    pub cache_opaque: bool,
+    // damage requested since the last commit, as wl_surface.damage and
+    // damage_buffer sent it, and committed damage in upright pixel cache
+    // coordinates the GPU texture hasn't been updated with yet
+    pub pending_surface_damage: BufferDamage,
+    pub pending_damage: BufferDamage,
+    pub buffer_damage: BufferDamage,
+    pub title: String,
+    pub workspace: usize,
+    pub fullscreen: bool,
+    pub floating: bool,
+    pub maximized: bool,
+    pub saved_geometry: Option<Rectangle>,
+    pub app_id: String,
+    pub decorations: bool,
+    pub rules_applied: bool,
+    pub layout_hidden: bool,
+    pub scratchpad: bool,
+    pub parent: Option<WindowId>,
+    pub min_size: (i32, i32),
+    pub max_size: (i32, i32),
+    pub last_configure: Option<(i32, i32, Vec<u8>)>,
+    pub pid: Option<i32>,
+    pub swallowed_by: Option<WindowId>,
+    pub minimized: bool,
+    pub layout_group: u32,
+    pub buffer_scale: i32,
+    pub pending_buffer_scale: Option<i32>,
+    // how the client rotated or flipped its buffer; the pixel cache is
+    // turned back upright, dmabufs are transformed when drawn
+    pub buffer_transform: OutputTransform,
+    pub pending_buffer_transform: Option<OutputTransform>,
+    pub viewport: Viewport,
+    pub pending_viewport: Option<Viewport>,
+    pub hide_on_close: Option<HideOnClose>,
+    pub blur: bool,
+    // from a rule or set_opacity; appearance.inactive_opacity is applied on top
+    pub opacity: f64,
+    // last commit or answered ping, see check_unresponsive
+    pub last_alive: std::time::Instant,
+    pub pending_ping: Option<(u32, std::time::Instant)>,
+    pub unresponsive: bool,
+    // wl_surface.set_opaque_region, in surface coordinates
+    pub opaque_region: Vec<Rectangle>,
+    pub pending_opaque_region: Option<Vec<Rectangle>>,
+    // whether anything of it shows this frame, see update_window_visibility
+    pub visible: bool,
 }
 
-impl Canvas {
-    const CURSOR_W: usize = 16;
-    const CURSOR_H: usize = 20;
+// wp_viewport state; source is in surface coordinates, before buffer scale
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Viewport {
+    pub source: Option<[f64; 4]>,
+    pub destination: Option<(i32, i32)>,
+}
 
-    pub fn new(width: usize, height: usize, bg_color: u32) -> Self {
-        let stride = width;
-        let pixels = vec![bg_color; width * height];
-        Self {
-            pixels,
-            cursor_save: vec![0; Self::CURSOR_W * Self::CURSOR_H],
-            cursor_save_x: -100,
-            cursor_save_y: -100,
-            width,
-            height,
-            stride,
+impl Viewport {
+    // logical size a buffer with this scale covers on screen
+    pub fn content_size(&self, scale: i32, width: usize, height: usize) -> (i32, i32) {
+        if let Some(size) = self.destination {
+            return size;
         }
-    }
-
-    pub fn resize(&mut self, width: usize, height: usize, bg_color: u32) {
-        if self.width != width || self.height != height {
-            self.width = width;
-            self.height = height;
-            self.stride = width;
-            self.pixels = vec![bg_color; width * height];
-            self.cursor_save_x = -100;
-            self.cursor_save_y = -100;
+        if let Some([_, _, w, h]) = self.source {
+            return (w as i32, h as i32);
         }
+        let scale = scale.max(1);
+        (width as i32 / scale, height as i32 / scale)
     }
 
-    #[allow(dead_code)]
-    pub fn clear(&mut self, color: u32) {
-        self.pixels.fill(color);
+    // source rectangle in buffer pixels
+    pub fn buffer_source(&self, scale: i32) -> Option<[f64; 4]> {
+        let scale = scale.max(1) as f64;
+        self.source
+            .map(|[x, y, w, h]| [x * scale, y * scale, w * scale, h * scale])
     }
+}
 
-    pub fn clear_with_pattern(&mut self, bg_dark: u32, bg_light: u32) {
-        let tile_size = 32;
+impl Window {
+    pub fn is_hidden(&self) -> bool {
+        self.workspace == SCRATCHPAD_WORKSPACE
+    }
 
-        let width = self.width;
-        let stride = self.stride;
-        let pixels = &mut self.pixels;
+    pub fn is_fixed_size(&self) -> bool {
+        self.min_size.0 > 0 && self.min_size == self.max_size
+    }
 
-        for y in 0..self.height {
-            let ty = y / tile_size;
-            let row_start = y * stride;
-            let base_color = if ty % 2 == 0 { bg_dark } else { bg_light };
-            let alt_color = if ty % 2 == 0 { bg_light } else { bg_dark };
+    pub fn is_dialog(&self) -> bool {
+        self.parent.is_some()
+            || self.is_fixed_size()
+            || DIALOG_APP_IDS
+                .iter()
+                .any(|pattern| crate::config::glob_match(pattern, &self.app_id))
+    }
 
-            let mut x = 0;
-            while x < width {
-                let tx = x / tile_size;
-                let color = if tx % 2 == 0 { base_color } else { alt_color };
-                let tile_end = ((tx + 1) * tile_size).min(width);
-                let fill_len = tile_end - x;
+    // logical size the committed buffer covers on screen
+    pub fn content_size(&self, buffer_width: usize, buffer_height: usize) -> (i32, i32) {
+        self.viewport
+            .content_size(self.buffer_scale, buffer_width, buffer_height)
+    }
 
-                let start = row_start + x;
-                let end = start + fill_len;
-                pixels[start..end].fill(color);
+    // viewport source rectangle in buffer pixels
+    pub fn buffer_source(&self) -> Option<[f64; 4]> {
+        self.viewport.buffer_source(self.buffer_scale)
+    }
 
-                x = tile_end;
-            }
+    pub fn decoration_height(&self, title_bar_height: i32) -> i32 {
+        if self.fullscreen || !self.decorations {
+            0
+        } else {
+            title_bar_height
         }
     }
 
-    #[allow(dead_code)]
-    pub fn draw_border(
-        &mut self,
-        x: i32,
-        y: i32,
-        width: i32,
-        height: i32,
-        color: u32,
-        thickness: i32,
-    ) {
-        let x = x.max(0) as usize;
-        let y = y.max(0) as usize;
-        let width = width as usize;
-        let height = height as usize;
-        let thickness = thickness as usize;
-
-        for dy in 0..thickness.min(height) {
-            for dx in 0..width {
-                let px = x + dx;
-                let py = y + dy;
-                if px < self.width && py < self.height {
-                    self.pixels[py * self.stride + px] = color;
-                }
-            }
+    // square buttons packed against the right end of the title bar, close outermost
+    pub fn title_buttons(&self, title_bar_height: i32) -> Vec<(TitleButton, Rectangle)> {
+        let height = self.decoration_height(title_bar_height);
+        let inset = height / 4;
+        let size = height - inset * 2;
+        if size <= 0 {
+            return Vec::new();
         }
 
-        for dy in 0..thickness.min(height) {
-            for dx in 0..width {
-                let px = x + dx;
-                let py = y + height.saturating_sub(1 + dy);
-                if px < self.width && py < self.height && py >= y {
-                    self.pixels[py * self.stride + px] = color;
-                }
-            }
-        }
+        let right = self.geometry.x + self.geometry.width;
+        [TitleButton::Close, TitleButton::Maximize]
+            .into_iter()
+            .enumerate()
+            .map(|(i, button)| {
+                let rect = Rectangle {
+                    x: right - height * (i as i32 + 1) + inset,
+                    y: self.geometry.y + inset,
+                    width: size,
+                    height: size,
+                };
+                (button, rect)
+            })
+            .filter(|(_, rect)| rect.x >= self.geometry.x)
+            .collect()
+    }
 
-        for dy in 0..height {
-            for dx in 0..thickness.min(width) {
-                let px = x + dx;
-                let py = y + dy;
-                if px < self.width && py < self.height {
-                    self.pixels[py * self.stride + px] = color;
-                }
-            }
+    // title text fitted between the left edge and the buttons
+    pub fn title_label(&self, title_bar_height: i32) -> Option<TitleLabel> {
+        let buttons_left = self
+            .title_buttons(title_bar_height)
+            .iter()
+            .map(|(_, rect)| rect.x)
+            .min()
+            .unwrap_or(self.geometry.x + self.geometry.width);
+        let area = Rectangle {
+            x: self.geometry.x,
+            y: self.geometry.y,
+            width: buttons_left - self.geometry.x,
+            height: self.decoration_height(title_bar_height),
+        };
+        TitleLabel::fit(&self.title, area)
+    }
+}
+
+pub struct TitleLabel {
+    pub text: String,
+    pub x: i32,
+    pub y: i32,
+    pub scale: usize,
+}
+
+impl TitleLabel {
+    // truncates the text to the area and centers it vertically
+    pub fn fit(title: &str, area: Rectangle) -> Option<Self> {
+        let scale = ((area.height - 6) / ktc_common::font::FONT_CHAR_HEIGHT as i32).max(1) as usize;
+        let font = ktc_common::Font::new(scale);
+        if title.is_empty() || (font.char_height() as i32) > area.height {
+            return None;
         }
 
-        for dy in 0..height {
-            for dx in 0..thickness.min(width) {
-                let px = x + width.saturating_sub(1 + dx);
-                let py = y + dy;
-                if px < self.width && py < self.height && px >= x {
-                    self.pixels[py * self.stride + px] = color;
-                }
-            }
+        let padding = 6;
+        let available = (area.width - padding * 2).max(0) as usize;
+        let text = font.truncate(title, available);
+        if text.is_empty() {
+            return None;
         }
-    }
 
-    #[allow(dead_code)]
-    pub fn blit(
-        &mut self,
-        src: &[u32],
-        src_width: usize,
-        src_height: usize,
-        dst_x: i32,
-        dst_y: i32,
-    ) {
-        let dst_x = dst_x.max(0) as usize;
-        let dst_y = dst_y.max(0) as usize;
+        Some(Self {
+            text,
+            x: area.x + padding,
+            y: area.y + (area.height - font.char_height() as i32) / 2,
+            scale,
+        })
+    }
+}
 
-        for y in 0..src_height {
-            let dst_row = dst_y + y;
-            if dst_row >= self.height {
-                break;
-            }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TitleButton {
+    Close,
+    Maximize,
+}
 
-            for x in 0..src_width {
-                let dst_col = dst_x + x;
-                if dst_col >= self.width {
-                    break;
-                }
+// screenshot region being picked with the pointer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionSelect {
+    Armed,
+    Dragging { x: i32, y: i32 },
+}
 
-                let src_idx = y * src_width + x;
-                let dst_idx = dst_row * self.stride + dst_col;
+// a floating window resized by a pointer binding: where the pointer and
+// the window were when it started, and the edges that follow the pointer,
+// -1 for left or top and 1 for right or bottom
+#[derive(Clone, Copy, Debug)]
+pub struct ResizeDrag {
+    id: WindowId,
+    start: (f64, f64),
+    geometry: Rectangle,
+    edges: (i32, i32),
+}
 
-                if src_idx < src.len() && dst_idx < self.pixels.len() {
-                    self.pixels[dst_idx] = src[src_idx];
-                }
-            }
-        }
-    }
+pub type LayerSurfaceId = u64;
 
-    pub fn blit_fast(
-        &mut self,
-        src: &[u32],
-        src_width: usize,
-        src_height: usize,
-        src_stride: usize,
-        dst_x: i32,
-        dst_y: i32,
-    ) {
-        let dst_x = dst_x.max(0) as usize;
-        let dst_y = dst_y.max(0) as usize;
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    #[default]
+    Tile,
+    Monocle,
+    Tabbed,
+}
 
-        for y in 0..src_height.min(self.height.saturating_sub(dst_y)) {
-            let dst_row = dst_y + y;
-            let src_offset = y * src_stride;
-            let dst_offset = dst_row * self.stride + dst_x;
-            let copy_width = src_width.min(self.width.saturating_sub(dst_x));
+impl Layout {
+    const ALL: [Layout; 3] = [Layout::Tile, Layout::Monocle, Layout::Tabbed];
 
-            if src_offset + copy_width <= src.len() && dst_offset + copy_width <= self.pixels.len()
-            {
-                self.pixels[dst_offset..dst_offset + copy_width]
-                    .copy_from_slice(&src[src_offset..src_offset + copy_width]);
-            }
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "tile" | "tiled" | "tiling" => Some(Layout::Tile),
+            "monocle" | "max" => Some(Layout::Monocle),
+            "tabbed" | "tabs" => Some(Layout::Tabbed),
+            _ => None,
         }
     }
 
-    #[allow(dead_code)]
-    pub fn blit_direct(
-        &mut self,
-        src: &[u32],
-        src_width: usize,
-        src_height: usize,
-        src_stride: usize,
-        dst_x: i32,
-        dst_y: i32,
-    ) {
-        if dst_x >= self.width as i32 || dst_y >= self.height as i32 {
-            return;
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Tile => "tile",
+            Layout::Monocle => "monocle",
+            Layout::Tabbed => "tabbed",
         }
+    }
 
-        let dst_x_usize = dst_x.max(0) as usize;
-        let dst_y_usize = dst_y.max(0) as usize;
-        let src_skip_x = if dst_x < 0 { (-dst_x) as usize } else { 0 };
-        let src_skip_y = if dst_y < 0 { (-dst_y) as usize } else { 0 };
-
-        let actual_src_width = src_width.saturating_sub(src_skip_x);
-        let actual_src_height = src_height.saturating_sub(src_skip_y);
-        let copy_width = actual_src_width.min(self.width.saturating_sub(dst_x_usize));
-        let copy_height = actual_src_height.min(self.height.saturating_sub(dst_y_usize));
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&l| l == self).unwrap_or(0);
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
 
-        if copy_width == 0 || copy_height == 0 {
-            return;
-        }
+    pub fn prev(self) -> Self {
+        let idx = Self::ALL.iter().position(|&l| l == self).unwrap_or(0);
+        Self::ALL[(idx + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
 
-        let dst_ptr = self.pixels.as_mut_ptr();
-        let src_ptr = src.as_ptr();
+pub struct Workspace {
+    pub id: usize,
+    pub layout: Layout,
+    // set by the gap action; None follows appearance.gap, also across reloads
+    pub gap: Option<i32>,
+    pub split_ratio: f32,
+    pub scroll_row: i32,
+    pub focus_history: Vec<WindowId>,
+    pub layout_group: u32,
+}
 
-        unsafe {
-            for y in 0..copy_height {
-                let src_row = src_skip_y + y;
-                let dst_row = dst_y_usize + y;
-                let src_offset = src_row * src_stride + src_skip_x;
-                let dst_offset = dst_row * self.stride + dst_x_usize;
-
-                if src_offset + copy_width <= src.len()
-                    && dst_offset + copy_width <= self.pixels.len()
-                {
-                    std::ptr::copy_nonoverlapping(
-                        src_ptr.add(src_offset),
-                        dst_ptr.add(dst_offset),
-                        copy_width,
-                    );
-                }
-            }
+impl Workspace {
+    pub fn new(id: usize, config: &Config) -> Self {
+        Self {
+            id,
+            layout: Layout::parse(&config.appearance.layout).unwrap_or_default(),
+            gap: None,
+            split_ratio: 0.5,
+            scroll_row: 0,
+            focus_history: Vec::new(),
+            layout_group: 0,
         }
     }
 
-    pub fn as_slice(&self) -> &[u32] {
-        &self.pixels
+    pub fn note_focus(&mut self, window_id: WindowId) {
+        self.focus_history.retain(|&id| id != window_id);
+        self.focus_history.push(window_id);
     }
 
-    #[allow(dead_code)]
-    pub fn as_mut_slice(&mut self) -> &mut [u32] {
-        &mut self.pixels
+    pub fn forget(&mut self, window_id: WindowId) {
+        self.focus_history.retain(|&id| id != window_id);
     }
+}
 
-    #[allow(clippy::too_many_arguments)]
-    pub fn draw_decorations(
-        &mut self,
-        x: i32,
-        y: i32,
-        width: i32,
-        height: i32,
-        title_height: i32,
-        is_focused: bool,
-        title_focused: u32,
-        title_unfocused: u32,
-        border_focused: u32,
-        border_unfocused: u32,
-    ) {
-        let title_bg = if is_focused {
-            title_focused
-        } else {
-            title_unfocused
-        };
-        let border_color = if is_focused {
-            border_focused
-        } else {
-            border_unfocused
-        };
-
-        let x = x.max(0) as usize;
-        let y = y.max(0) as usize;
-        let width = width as usize;
-        let title_height = title_height as usize;
-        let total_height = height as usize + title_height;
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    #[default]
+    Scroll,
+    Float,
+}
 
-        for dy in 0..title_height {
-            for dx in 0..width {
-                let px = x + dx;
-                let py = y + dy;
-                if px < self.width && py < self.height {
-                    self.pixels[py * self.stride + px] = title_bg;
-                }
-            }
+impl Overflow {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "scroll" => Some(Overflow::Scroll),
+            "float" | "refuse" => Some(Overflow::Float),
+            _ => None,
         }
+    }
+}
 
-        for dx in 0..width {
-            let px = x + dx;
-            if px < self.width && y < self.height {
-                self.pixels[y * self.stride + px] = border_color;
-            }
-        }
-        let bottom_y = y + total_height.saturating_sub(1);
-        if bottom_y < self.height {
-            for dx in 0..width {
-                let px = x + dx;
-                if px < self.width {
-                    self.pixels[bottom_y * self.stride + px] = border_color;
-                }
-            }
-        }
-        for dy in 0..total_height {
-            let py = y + dy;
-            if x < self.width && py < self.height {
-                self.pixels[py * self.stride + x] = border_color;
-            }
-        }
-        let right_x = x + width.saturating_sub(1);
-        if right_x < self.width {
-            for dy in 0..total_height {
-                let py = y + dy;
-                if py < self.height {
-                    self.pixels[py * self.stride + right_x] = border_color;
-                }
-            }
-        }
-
-        let title_bottom = y + title_height;
-        if title_bottom < self.height {
-            for dx in 0..width {
-                let px = x + dx;
-                if px < self.width {
-                    self.pixels[title_bottom * self.stride + px] = border_color;
-                }
-            }
-        }
-    }
-
-    pub fn draw_cursor(&mut self, x: i32, y: i32) {
-        self.save_under_cursor(x, y);
-
-        // W = white, B = black outline, . = transparent
-        const CURSOR: &[&str] = &[
-            "BW",
-            "BWWB",
-            "BWWWB",
-            "BWWWWB",
-            "BWWWWWB",
-            "BWWWWWWB",
-            "BWWWWWWWB",
-            "BWWWWWWWWB",
-            "BWWWWWWWWWB",
-            "BWWWWWWWWWWB",
-            "BWWWWWWBBBBB",
-            "BWWWBWWB",
-            "BWWBBWWWB",
-            "BWB.BWWWB",
-            "BB..BWWWB",
-            "B....BWWWB",
-            ".....BWWWB",
-            "......BWWB",
-            "......BBB",
-        ];
-
-        for (dy, row) in CURSOR.iter().enumerate() {
-            for (dx, ch) in row.chars().enumerate() {
-                let px = x as usize + dx;
-                let py = y as usize + dy;
-                if px < self.width && py < self.height {
-                    let color = match ch {
-                        'W' => 0xFFFFFFFF,
-                        'B' => 0xFF000000,
-                        _ => continue,
-                    };
-                    self.pixels[py * self.stride + px] = color;
-                }
-            }
-        }
-    }
-
-    fn save_under_cursor(&mut self, x: i32, y: i32) {
-        self.cursor_save_x = x;
-        self.cursor_save_y = y;
-        let x = x.max(0) as usize;
-        let y = y.max(0) as usize;
+// what a close request does to a window matched by a hide_on_close rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HideOnClose {
+    Scratchpad,
+    Minimize,
+}
 
-        for dy in 0..Self::CURSOR_H {
-            let py = y + dy;
-            if py >= self.height {
-                break;
-            }
-            for dx in 0..Self::CURSOR_W {
-                let px = x + dx;
-                if px >= self.width {
-                    break;
-                }
-                self.cursor_save[dy * Self::CURSOR_W + dx] = self.pixels[py * self.stride + px];
-            }
+impl HideOnClose {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "scratchpad" => Some(HideOnClose::Scratchpad),
+            "minimize" | "minimized" => Some(HideOnClose::Minimize),
+            _ => None,
         }
     }
+}
 
-    pub fn restore_cursor(&mut self) {
-        if self.cursor_save_x < 0 && self.cursor_save_y < 0 {
-            return;
-        }
-        let x = self.cursor_save_x.max(0) as usize;
-        let y = self.cursor_save_y.max(0) as usize;
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LayoutMemory {
+    #[default]
+    Global,
+    Window,
+    Workspace,
+}
 
-        for dy in 0..Self::CURSOR_H {
-            let py = y + dy;
-            if py >= self.height {
-                break;
-            }
-            for dx in 0..Self::CURSOR_W {
-                let px = x + dx;
-                if px >= self.width {
-                    break;
-                }
-                self.pixels[py * self.stride + px] = self.cursor_save[dy * Self::CURSOR_W + dx];
-            }
+impl LayoutMemory {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "global" | "none" => Some(LayoutMemory::Global),
+            "window" => Some(LayoutMemory::Window),
+            "workspace" => Some(LayoutMemory::Workspace),
+            _ => None,
         }
-
-        self.cursor_save_x = -100;
-        self.cursor_save_y = -100;
     }
 }
 
-#[derive(Default)]
-pub struct OutputConfig {
-    pub make: Option<String>,
-    pub model: Option<String>,
-    pub physical_size: Option<(i32, i32)>,
-    pub resolution: Option<(i32, i32)>,
-    pub refresh: Option<i32>,
-    pub scale: Option<i32>,
-    pub transform: Option<OutputTransform>,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InsertPosition {
+    #[default]
+    End,
+    AfterFocused,
+    Master,
 }
 
-pub struct Window {
-    pub id: WindowId,
-    pub xdg_surface: XdgSurface,
-    pub xdg_toplevel: XdgToplevel,
-    pub wl_surface: WlSurface,
-    pub geometry: Rectangle,
-    pub mapped: bool,
-    pub buffer: Option<WlBuffer>,
-    pub pending_buffer: Option<WlBuffer>,
-    pub pending_buffer_set: bool,
-    pub buffer_released: bool,
-    pub needs_redraw: bool,
-    pub pixel_cache: Vec<u32>,
-    pub cache_width: usize,
-    pub cache_height: usize,
-    pub cache_stride: usize,
-    pub title: String,
-    pub workspace: usize,
-    pub fullscreen: bool,
-    pub floating: bool,
-    pub maximized: bool,
-    pub saved_geometry: Option<Rectangle>,
+impl InsertPosition {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "end" | "last" => Some(InsertPosition::End),
+            "after_focused" | "after-focused" | "focused" => Some(InsertPosition::AfterFocused),
+            "master" | "first" => Some(InsertPosition::Master),
+            _ => None,
+        }
+    }
 }
 
-pub type LayerSurfaceId = u64;
-
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Layer {
     Background = 0,
@@ -711,10 +879,31 @@ pub struct LayerSurface {
     pub cache_width: usize,
     pub cache_height: usize,
     pub cache_stride: usize,
+    pub cache_opaque: bool,
+    pub buffer_scale: i32,
+    pub pending_buffer_scale: Option<i32>,
+    // as on Window, the pixel cache is turned back upright
+    pub buffer_transform: OutputTransform,
+    pub pending_buffer_transform: Option<OutputTransform>,
+    pub viewport: Viewport,
+    pub pending_viewport: Option<Viewport>,
+}
+
+impl LayerSurface {
+    pub fn content_size(&self, buffer_width: usize, buffer_height: usize) -> (i32, i32) {
+        self.viewport
+            .content_size(self.buffer_scale, buffer_width, buffer_height)
+    }
+
+    pub fn buffer_source(&self) -> Option<[f64; 4]> {
+        self.viewport.buffer_source(self.buffer_scale)
+    }
 }
 
 pub struct State {
     pub config: Config,
+    pub safe_mode: Option<String>,
+    pub help_overlay: Option<Vec<String>>,
     pub windows: Vec<Window>,
     pub focused_window: Option<WindowId>,
     pub next_window_id: WindowId,
@@ -725,17 +914,31 @@ pub struct State {
 
     pub layer_surfaces: Vec<LayerSurface>,
     pub next_layer_surface_id: LayerSurfaceId,
+    // toggle_bar: exclusive zones are ignored while the bar is hidden
+    pub bar_hidden: bool,
 
     pub shm_pools: HashMap<ObjectId, ShmPoolData>,
     pub buffers: HashMap<ObjectId, BufferData>,
     pub dmabuf_buffers: HashMap<ObjectId, DmaBufBufferInfo>,
 
     pub subsurfaces: HashMap<ObjectId, ObjectId>,
-
-    pub frame_callbacks: Vec<WlCallback>,
-
+    // surfaces with a wp_viewport, each may only have one
+    pub viewports: HashSet<ObjectId>,
+    // shm contents of a transformed buffer before they are turned upright
+    // into a pixel cache; kept to spare an allocation per commit
+    pub transform_staging: Vec<u32>,
+
+    // with the surface that asked, so hidden windows' callbacks can wait
+    pub frame_callbacks: Vec<(WlSurface, WlCallback)>,
+
+    pub seats: Vec<WlSeat>,
+    // follows the devices plugged in
+    pub seat_capabilities: SeatCapabilities,
     pub keyboards: Vec<WlKeyboard>,
     pub keyboard_to_window: HashMap<ObjectId, WindowId>,
+    // (depressed, latched, locked, group) as last sent, for keyboards
+    // entering a surface; clients assume no modifiers until told
+    pub keyboard_modifiers: (u32, u32, u32, u32),
     pub pointers: Vec<WlPointer>,
     pub keyboard_serial: u32,
     pub pointer_serial: u32,
@@ -743,25 +946,75 @@ pub struct State {
     pub pointer_x: f64,
     pub pointer_y: f64,
     pub pointer_focus: Option<WindowId>,
+    pub pointer_layer_focus: Option<LayerSurfaceId>,
+    // surface-local position last sent to the focused surface
+    pointer_local: Option<(f64, f64)>,
+
+    pub tablet_seats: Vec<ZwpTabletSeatV2>,
+    pub tablets: Vec<Tablet>,
+    pub tablet_tools: Vec<TabletTool>,
 
     pub cursor_x: i32,
     pub cursor_y: i32,
     pub cursor_visible: bool,
+    pub cursor: Cursor,
+    pub cursor_theme: CursorTheme,
+    pub cursor_frame: usize,
+    cursor_anim_start: std::time::Instant,
+    last_pointer_activity: std::time::Instant,
+    pending_launches: Vec<PendingLaunch>,
+    activation_tokens: HashMap<String, ActivationToken>,
 
     pub keymap_data: Option<KeymapData>,
 
     pub pending_xdg_surfaces: HashMap<u32, (XdgSurface, WlSurface)>,
+    // one per client that bound xdg_wm_base, to ping its windows
+    pub wm_bases: Vec<XdgWmBase>,
+    ping_serial: u32,
 
     pub needs_relayout: bool,
 
     pub screencopy_frames: Vec<PendingScreencopy>,
+    pub screencopy_clients: HashMap<ObjectId, ScreencopyClient>,
+    // where the cursor was in the last presented frame
+    pub screencopy_cursor: Rectangle,
+    // per-surface dmabuf feedback, resent when scanout eligibility changes
+    pub dmabuf_feedbacks: Vec<ZwpLinuxDmabufFeedbackV1>,
+    pub scanout_buffer: Option<WlBuffer>,
+    scanout_retiring: Option<WlBuffer>,
 
     pub damage_tracker: DamageTracker,
     pub last_cursor_pos: (i32, i32),
+    last_texture_sweep: std::time::Instant,
 
     pub active_workspace: usize,
-    pub workspace_count: usize,
+    pub workspaces: Vec<Workspace>,
+    pub insert_marker: Option<WindowId>,
+    pub marks: HashMap<String, WindowId>,
+    pub split_drag: Option<i32>,
+    // floating window dragged by its title bar, and the pointer's offset
+    // from its top left corner
+    pub move_drag: Option<(WindowId, (f64, f64))>,
+    pub resize_drag: Option<ResizeDrag>,
+    // the button a [[keybinds.mouse]] drag is held with; until it's
+    // released clients get nothing from the pointer
+    pub pointer_grab: Option<u32>,
+    pub region_select: Option<RegionSelect>,
+    // logical area to save once the next frame is drawn
+    pub pending_screenshot: Option<Rectangle>,
+    pub recorder: Option<Recorder>,
+    // stopped recordings whose writer hasn't finished the file yet
+    pub finishing_recordings: Vec<FinishingRecording>,
+    pub wallpaper: WallpaperState,
+    pub output_managers: Vec<ZwlrOutputManagerV1>,
+    pub stack: Vec<WindowId>,
+    pub minimized_windows: Vec<WindowId>,
+    pub hidden_windows_changed: bool,
     pub pending_title_change: Option<String>,
+    // a fullscreen window committed; with VRR it is drawn right away
+    pub fullscreen_commit: bool,
+    applied_color: ColorAdjust,
+    pub color_lut: Option<ColorLut>,
 }
 
 impl Drop for State {
@@ -795,7 +1048,6 @@ pub struct BufferData {
     pub width: i32,
     pub height: i32,
     pub stride: i32,
-    #[allow(dead_code)]
     pub format: u32,
 }
 
@@ -810,6 +1062,18 @@ pub struct DmaBufBufferInfo {
     pub planes: Vec<DmaBufPlaneInfo>,
 }
 
+struct PendingLaunch {
+    pid: u32,
+    token: String,
+    started: std::time::Instant,
+}
+
+struct ActivationToken {
+    created: std::time::Instant,
+    // handed out for a launch or to a client that had focus
+    grants_focus: bool,
+}
+
 pub struct KeymapData {
     pub fd: OwnedFd,
     pub size: u32,
@@ -821,6 +1085,9 @@ pub struct ScreencopyFrameState {
     pub y: i32,
     pub width: i32,
     pub height: i32,
+    pub overlay_cursor: bool,
+    // the zwlr_screencopy_manager_v1 that made the frame
+    pub manager: ObjectId,
 }
 
 impl State {
@@ -830,9 +1097,17 @@ impl State {
 
         let keymap_data = Self::create_keymap(&config);
         let bg_color = config.background_dark();
+        let cursor_theme =
+            CursorTheme::load(&config.cursor.theme_name(), config.cursor.base_size());
+        let cursor = Cursor::load(&cursor_theme, CursorShape::Default, cursor_theme.size);
+        let workspaces = (1..=DEFAULT_WORKSPACE_COUNT)
+            .map(|id| Workspace::new(id, &config))
+            .collect();
 
         Self {
             config,
+            safe_mode: None,
+            help_overlay: None,
             windows: Vec::new(),
             focused_window: None,
             next_window_id: 1,
@@ -842,35 +1117,79 @@ impl State {
             gpu_renderer: None,
             layer_surfaces: Vec::new(),
             next_layer_surface_id: 1,
+            bar_hidden: false,
             shm_pools: HashMap::new(),
             buffers: HashMap::new(),
             dmabuf_buffers: HashMap::new(),
             subsurfaces: HashMap::new(),
+            viewports: HashSet::new(),
+            transform_staging: Vec::new(),
             frame_callbacks: Vec::new(),
+            seats: Vec::new(),
+            seat_capabilities: SeatCapabilities::default(),
             keyboards: Vec::new(),
             keyboard_to_window: HashMap::new(),
             pointers: Vec::new(),
             keyboard_serial: 0,
+            keyboard_modifiers: (0, 0, 0, 0),
             pointer_serial: 0,
             pointer_x: 0.0,
             pointer_y: 0.0,
             pointer_focus: None,
+            pointer_layer_focus: None,
+            pointer_local: None,
+            tablet_seats: Vec::new(),
+            tablets: Vec::new(),
+            tablet_tools: Vec::new(),
             cursor_x: 0,
             cursor_y: 0,
             cursor_visible: true,
+            cursor,
+            cursor_theme,
+            cursor_frame: 0,
+            cursor_anim_start: std::time::Instant::now(),
+            last_pointer_activity: std::time::Instant::now(),
+            pending_launches: Vec::new(),
+            activation_tokens: HashMap::new(),
             keymap_data,
             pending_xdg_surfaces: HashMap::new(),
+            wm_bases: Vec::new(),
+            ping_serial: 0,
             needs_relayout: false,
             screencopy_frames: Vec::new(),
+            screencopy_clients: HashMap::new(),
+            screencopy_cursor: Rectangle::default(),
+            dmabuf_feedbacks: Vec::new(),
+            scanout_buffer: None,
+            scanout_retiring: None,
             damage_tracker: DamageTracker::new(),
             last_cursor_pos: (0, 0),
+            last_texture_sweep: std::time::Instant::now(),
             active_workspace: 1,
-            workspace_count: 4,
+            workspaces,
+            insert_marker: None,
+            marks: HashMap::new(),
+            split_drag: None,
+            move_drag: None,
+            resize_drag: None,
+            pointer_grab: None,
+            region_select: None,
+            pending_screenshot: None,
+            recorder: None,
+            finishing_recordings: Vec::new(),
+            wallpaper: WallpaperState::default(),
+            output_managers: Vec::new(),
+            stack: Vec::new(),
+            minimized_windows: Vec::new(),
+            hidden_windows_changed: false,
+            fullscreen_commit: false,
             pending_title_change: None,
+            applied_color: ColorAdjust::NEUTRAL,
+            color_lut: None,
         }
     }
 
-    fn create_keymap(config: &Config) -> Option<KeymapData> {
+    pub fn create_keymap(config: &Config) -> Option<KeymapData> {
         use std::io::Write;
         use std::os::fd::FromRawFd;
 
@@ -931,7 +1250,7 @@ impl State {
                 .resize(width as usize, height as usize, bg_color);
         }
 
-        self.relayout_windows();
+        self.needs_relayout = true;
 
         id
     }
@@ -959,13 +1278,46 @@ impl State {
                     output.refresh = refresh;
                 }
                 if let Some(scale) = config.scale {
-                    output.scale = scale;
+                    if output.scale != scale {
+                        output.scale = scale;
+                        self.needs_relayout = true;
+                        self.damage_tracker.mark_full_damage();
+                    }
+                    if is_primary {
+                        if let Some(ref mut gpu) = self.gpu_renderer {
+                            gpu.set_scale(scale);
+                        }
+                    }
                 }
                 if let Some(transform) = config.transform {
-                    output.transform = transform;
+                    if output.transform != transform {
+                        output.transform = transform;
+                        self.needs_relayout = true;
+                        self.damage_tracker.mark_full_damage();
+                    }
+                    if is_primary {
+                        if let Some(ref mut gpu) = self.gpu_renderer {
+                            gpu.set_transform(transform);
+                        }
+                    }
+                }
+                if let Some(modes) = config.modes {
+                    output.modes = modes;
+                }
+                if let Some(capable) = config.vrr_capable {
+                    output.vrr_capable = capable;
+                    output.vrr_enabled = capable && self.config.display.vrr;
+                    if is_primary {
+                        if let Some(ref mut gpu) = self.gpu_renderer {
+                            if let Err(e) = gpu.set_vrr(output.vrr_enabled) {
+                                log::warn!("[output] Failed to enable VRR: {}", e);
+                                output.vrr_enabled = false;
+                            }
+                        }
+                    }
                 }
 
-                (output.width, output.height)
+                output.logical_size()
             } else {
                 return;
             }
@@ -997,7 +1349,7 @@ impl State {
 
             for wl_output in &output.wl_outputs {
                 if wl_output.version() >= 2 {
-                    wl_output.scale(output.scale.max(1));
+                    wl_output.scale(output.scale.ceil().max(1.0) as i32);
                 }
                 wl_output.geometry(
                     output.x,
@@ -1025,6 +1377,130 @@ impl State {
         }
     }
 
+    pub fn set_output_mode(
+        &mut self,
+        output: &str,
+        width: u32,
+        height: u32,
+        refresh: Option<u32>,
+    ) -> Result<(), String> {
+        let id = self
+            .outputs
+            .iter()
+            .find(|o| output.is_empty() || o.name == output)
+            .map(|o| o.id)
+            .ok_or_else(|| format!("no output named {:?}", output))?;
+        if self.outputs.first().map(|o| o.id) != Some(id) {
+            return Err("only the primary output can change modes".to_string());
+        }
+        let gpu = self
+            .gpu_renderer
+            .as_mut()
+            .ok_or("live mode changes need the GPU renderer")?;
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(format!("mode {}x{} is too large", width, height));
+        }
+
+        gpu.set_mode(width as u16, height as u16, refresh)
+            .map_err(|e| e.to_string())?;
+        let (width, height) = gpu.size();
+        let refresh = gpu.refresh();
+
+        if let Some(output) = self.outputs.iter_mut().find(|o| o.id == id) {
+            output.refresh = refresh;
+        }
+        self.set_screen_size(width as i32, height as i32);
+        Ok(())
+    }
+
+    pub fn set_output_vrr(&mut self, output: &str, enabled: bool) -> Result<(), String> {
+        let id = self
+            .outputs
+            .iter()
+            .find(|o| output.is_empty() || o.name == output)
+            .map(|o| o.id)
+            .ok_or_else(|| format!("no output named {:?}", output))?;
+        if self.outputs.first().map(|o| o.id) != Some(id) {
+            return Err("only the primary output can toggle VRR".to_string());
+        }
+        let gpu = self
+            .gpu_renderer
+            .as_mut()
+            .ok_or("VRR needs the GPU renderer")?;
+        gpu.set_vrr(enabled)?;
+        if let Some(output) = self.outputs.iter_mut().find(|o| o.id == id) {
+            output.vrr_enabled = enabled;
+        }
+        Ok(())
+    }
+
+    pub fn set_output_color(
+        &mut self,
+        output: &str,
+        adjust: Option<ColorAdjust>,
+    ) -> Result<(), String> {
+        let id = self
+            .outputs
+            .iter()
+            .find(|o| output.is_empty() || o.name == output)
+            .map(|o| o.id)
+            .ok_or_else(|| format!("no output named {:?}", output))?;
+        if self.outputs.first().map(|o| o.id) != Some(id) {
+            return Err("only the primary output has color adjustment".to_string());
+        }
+        if let Some(output) = self.outputs.iter_mut().find(|o| o.id == id) {
+            output.color_override = adjust;
+        }
+        Ok(())
+    }
+
+    // An IPC override wins over the night light schedule
+    pub fn color_adjust(&self) -> ColorAdjust {
+        if let Some(adjust) = self.primary_output().and_then(|o| o.color_override) {
+            return adjust;
+        }
+        let night_light = &self.config.night_light;
+        if night_light.is_active_at(chrono::Local::now().time()) {
+            ColorAdjust {
+                temperature: night_light.temperature,
+                brightness: night_light.brightness,
+            }
+        } else {
+            ColorAdjust::NEUTRAL
+        }
+    }
+
+    // Hands a changed color adjustment to the renderer; true when the screen
+    // has to be redrawn with it
+    pub fn update_color(&mut self) -> bool {
+        let adjust = self.color_adjust();
+        if adjust == self.applied_color {
+            return false;
+        }
+        self.applied_color = adjust;
+        let gain = adjust.gain();
+        if let Some(ref mut gpu) = self.gpu_renderer {
+            gpu.set_color_gain(gain);
+        }
+        self.color_lut = (adjust != ColorAdjust::NEUTRAL).then(|| ColorLut::new(gain));
+        self.damage_tracker.mark_full_damage();
+        log::info!(
+            "[color] {}K at {:.0}% brightness",
+            adjust.temperature,
+            adjust.brightness.clamp(0.1, 1.0) * 100.0
+        );
+        true
+    }
+
+    // VRR only pays off while a fullscreen client drives the frame rate
+    pub fn vrr_active(&self) -> bool {
+        self.primary_output().is_some_and(|o| o.vrr_enabled)
+            && self
+                .focused_window
+                .and_then(|id| self.windows.iter().find(|w| w.id == id))
+                .is_some_and(|w| w.fullscreen && w.workspace == self.active_workspace)
+    }
+
     pub fn register_wl_output(&mut self, wl_output: WlOutput) {
         if let Some(output) = self.outputs.first_mut() {
             output.wl_outputs.push(wl_output);
@@ -1041,132 +1517,1667 @@ impl State {
         self.config.title_bar_height()
     }
 
-    pub fn screen_size(&self) -> (i32, i32) {
-        self.primary_output()
-            .map(|o| (o.width, o.height))
-            .unwrap_or((1920, 1080))
-    }
+    pub fn output_scale(&self) -> f64 {
+        self.primary_output().map(|o| o.scale).unwrap_or(1.0)
+    }
+
+    // the GPU draws the cursor at its logical size from an image loaded at
+    // output scale; the CPU canvas is in logical pixels already
+    pub fn cursor_size(&self) -> i32 {
+        let scale = if self.gpu_renderer.is_some() {
+            self.output_scale()
+        } else {
+            1.0
+        };
+        (self.cursor_theme.size as f64 * scale).round() as i32
+    }
+
+    pub fn cursor_shape(&self) -> CursorShape {
+        if let Some(drag) = self.resize_drag {
+            match drag.edges {
+                (-1, -1) => CursorShape::ResizeNw,
+                (_, -1) => CursorShape::ResizeNe,
+                (-1, _) => CursorShape::ResizeSw,
+                _ => CursorShape::ResizeSe,
+            }
+        } else if self.pending_launches.is_empty() {
+            CursorShape::Default
+        } else {
+            CursorShape::Wait
+        }
+    }
+
+    // None when no random bytes could be had; a guessable token would let
+    // any client take focus
+    pub fn new_activation_token(&mut self, grants_focus: bool) -> Option<String> {
+        let now = std::time::Instant::now();
+        self.activation_tokens
+            .retain(|_, t| now.duration_since(t.created) < ACTIVATION_TOKEN_LIFETIME);
+
+        let token = random_token()?;
+        self.activation_tokens.insert(
+            token.clone(),
+            ActivationToken {
+                created: now,
+                grants_focus,
+            },
+        );
+        Some(token)
+    }
+
+    // a client may only hand focus on if it asked for the token with the
+    // serial of an input event while it had keyboard focus
+    pub fn activation_request_allowed(
+        &self,
+        serial: Option<u32>,
+        surface: Option<&WlSurface>,
+    ) -> bool {
+        let (Some(serial), Some(surface)) = (serial, surface) else {
+            return false;
+        };
+        let issued =
+            serial != 0 && (serial <= self.keyboard_serial || serial <= self.pointer_serial);
+        let focused = self
+            .focused_window
+            .and_then(|id| self.windows.iter().find(|w| w.id == id))
+            .is_some_and(|w| w.wl_surface.client() == surface.client());
+        issued && focused
+    }
+
+    pub fn activate(&mut self, token: &str, surface: &WlSurface) {
+        let issued = self
+            .activation_tokens
+            .remove(token)
+            .filter(|t| t.created.elapsed() < ACTIVATION_TOKEN_LIFETIME);
+        self.pending_launches.retain(|l| l.token != token);
+
+        let Some(issued) = issued else {
+            log::debug!("[activation] Ignoring unknown or expired token");
+            return;
+        };
+        if !issued.grants_focus {
+            log::debug!("[activation] Token was requested without focus, not activating");
+            return;
+        }
+
+        let window = self
+            .windows
+            .iter()
+            .find(|w| w.wl_surface.id() == surface.id())
+            .map(|w| (w.id, w.workspace));
+        if let Some((window_id, workspace)) = window {
+            if workspace == self.active_workspace {
+                self.set_focus(window_id);
+            }
+        }
+    }
+
+    pub fn note_launch(&mut self, pid: u32, token: String) {
+        if self.config.cursor.launch_timeout == 0 {
+            return;
+        }
+        self.pending_launches.push(PendingLaunch {
+            pid,
+            token,
+            started: std::time::Instant::now(),
+        });
+    }
+
+    // launched children are session leaders, so anything they fork shares their sid
+    pub fn launch_mapped(&mut self, client_pid: Option<i32>) {
+        let Some(pid) = client_pid else {
+            return;
+        };
+        let sid = unsafe { libc::getsid(pid) };
+        self.pending_launches
+            .retain(|l| l.pid as i32 != pid && l.pid as i32 != sid);
+    }
+
+    pub fn sweep_textures(&mut self) {
+        if self.last_texture_sweep.elapsed() < TEXTURE_SWEEP_INTERVAL {
+            return;
+        }
+        self.last_texture_sweep = std::time::Instant::now();
+
+        let live: std::collections::HashSet<u64> = self
+            .windows
+            .iter()
+            .map(|w| w.id)
+            .chain(
+                self.layer_surfaces
+                    .iter()
+                    .map(|ls| ls.id + crate::renderer::LAYER_TEXTURE_OFFSET),
+            )
+            .chain(
+                self.windows
+                    .iter()
+                    .map(|w| w.id + crate::renderer::TITLE_TEXTURE_OFFSET),
+            )
+            .collect();
+        let Some(gpu) = self.gpu_renderer.as_mut() else {
+            return;
+        };
+        let (count, bytes) = gpu.retain_textures(
+            |id| live.contains(&id),
+            |buf| self.dmabuf_buffers.contains_key(buf),
+        );
+        if count > 0 {
+            log::info!(
+                "[gpu] Reclaimed {} stale textures ({}KB), {} remaining",
+                count,
+                bytes / 1024,
+                gpu.texture_count()
+            );
+        }
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) {
+        if self.cursor_visible != visible {
+            self.cursor_visible = visible;
+            self.damage_tracker.add_cursor_damage();
+        }
+    }
+
+    // after a reload or the cursor_theme action; a theme that isn't
+    // installed leaves the built-in arrow
+    pub fn set_cursor_theme(&mut self, name: &str, size: i32) {
+        if self.cursor_theme.name == name && self.cursor_theme.size == size {
+            return;
+        }
+        self.cursor_theme = CursorTheme::load(name, size);
+        self.cursor = Cursor::load(&self.cursor_theme, self.cursor_shape(), self.cursor_size());
+        self.cursor_anim_start = std::time::Instant::now();
+        self.cursor_frame = 0;
+        self.damage_tracker.add_cursor_damage();
+    }
+
+    // motion brings it back
+    pub fn hide_cursor_for_typing(&mut self) {
+        if self.config.cursor.hide_on_typing {
+            self.set_cursor_visible(false);
+        }
+    }
+
+    // when a visible cursor hides unless the pointer moves first
+    pub fn cursor_idle_deadline(&self) -> Option<std::time::Instant> {
+        let timeout = std::time::Duration::from_secs(self.config.cursor.hide_after);
+        (!timeout.is_zero() && self.cursor_visible).then(|| self.last_pointer_activity + timeout)
+    }
+
+    // after hide_after seconds without pointer activity
+    pub fn hide_idle_cursor(&mut self) {
+        let now = std::time::Instant::now();
+        if self
+            .cursor_idle_deadline()
+            .is_some_and(|deadline| deadline <= now)
+        {
+            self.set_cursor_visible(false);
+        }
+    }
+
+    pub fn update_cursor(&mut self) {
+        let now = std::time::Instant::now();
+        let timeout = std::time::Duration::from_secs(self.config.cursor.launch_timeout);
+        self.pending_launches
+            .retain(|l| now.duration_since(l.started) < timeout);
+
+        let shape = self.cursor_shape();
+        let size = self.cursor_size();
+        if self.cursor.shape != shape || self.cursor.size != size {
+            self.cursor = Cursor::load(&self.cursor_theme, shape, size);
+            self.cursor_anim_start = now;
+            self.cursor_frame = 0;
+            self.damage_tracker.add_cursor_damage();
+            return;
+        }
+
+        if self.cursor.is_animated() {
+            let elapsed = now.duration_since(self.cursor_anim_start).as_millis() as u64;
+            let frame = self.cursor.frame_at(elapsed);
+            if frame != self.cursor_frame {
+                self.cursor_frame = frame;
+                self.damage_tracker.add_cursor_damage();
+            }
+        }
+    }
+
+    pub fn workspace_count(&self) -> usize {
+        self.workspaces.len()
+    }
+
+    pub fn workspace(&self, id: usize) -> Option<&Workspace> {
+        self.workspaces.iter().find(|ws| ws.id == id)
+    }
+
+    pub fn workspace_mut(&mut self, id: usize) -> Option<&mut Workspace> {
+        self.workspaces.iter_mut().find(|ws| ws.id == id)
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.workspace(self.active_workspace)
+            .map(|ws| ws.layout)
+            .unwrap_or_default()
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        let active_workspace = self.active_workspace;
+        let Some(workspace) = self.workspace_mut(active_workspace) else {
+            return;
+        };
+        if workspace.layout == layout {
+            return;
+        }
+        workspace.layout = layout;
+        log::info!(
+            "[layout] Workspace {} layout: {:?}",
+            active_workspace,
+            layout
+        );
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+    }
+
+    // gaps set per workspace are kept; the others pick up appearance.gap
+    pub fn reload_workspace_config(&mut self) {
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+    }
+
+    pub fn gap(&self, workspace: usize) -> i32 {
+        self.workspace(workspace)
+            .and_then(|ws| ws.gap)
+            .unwrap_or(self.config.appearance.gap)
+            .max(0)
+    }
+
+    pub fn set_gap(&mut self, change: &GapChange) {
+        let active_workspace = self.active_workspace;
+        let current = self.gap(active_workspace);
+        let Some(workspace) = self.workspace_mut(active_workspace) else {
+            return;
+        };
+        workspace.gap = match *change {
+            GapChange::Set(gap) => Some(gap.max(0)),
+            GapChange::Adjust(step) => Some(current.saturating_add(step).max(0)),
+            GapChange::Default => None,
+        };
+        log::info!(
+            "[layout] Workspace {} gap: {:?}",
+            active_workspace,
+            workspace.gap
+        );
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+    }
+
+    // most recently focused mapped window on the workspace, falling back to the first one
+    fn focus_candidate(&self, workspace: usize, exclude: Option<WindowId>) -> Option<WindowId> {
+        let eligible = |w: &Window| w.workspace == workspace && w.mapped && Some(w.id) != exclude;
+        self.workspace(workspace)
+            .and_then(|ws| {
+                ws.focus_history
+                    .iter()
+                    .rev()
+                    .copied()
+                    .find(|&id| self.windows.iter().any(|w| w.id == id && eligible(w)))
+            })
+            .or_else(|| self.windows.iter().find(|w| eligible(w)).map(|w| w.id))
+    }
+
+    // tab strip across the top of the tiling area, one tab per tiled window
+    pub fn tabs(&self) -> Vec<(WindowId, Rectangle)> {
+        if self.layout() != Layout::Tabbed {
+            return Vec::new();
+        }
+
+        let tabbed: Vec<&Window> = self
+            .windows
+            .iter()
+            .filter(|w| {
+                w.workspace == self.active_workspace && w.mapped && !w.floating && !w.fullscreen
+            })
+            .collect();
+        let Some(visible) = tabbed.iter().find(|w| !w.layout_hidden) else {
+            return Vec::new();
+        };
+        if !visible.decorations {
+            return Vec::new();
+        }
+
+        let area = visible.geometry;
+        let count = tabbed.len() as i32;
+        let tab_height = self.config.title_bar_height();
+        if tab_height <= 0 {
+            return Vec::new();
+        }
+        tabbed
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                let i = i as i32;
+                let x = area.x + area.width * i / count;
+                let next_x = area.x + area.width * (i + 1) / count;
+                (
+                    w.id,
+                    Rectangle {
+                        x,
+                        y: area.y,
+                        width: next_x - x,
+                        height: tab_height,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub fn screen_size(&self) -> (i32, i32) {
+        self.primary_output()
+            .map(|o| o.logical_size())
+            .unwrap_or((1920, 1080))
+    }
+
+    pub fn usable_area(&self) -> Rectangle {
+        let mut area = match self.primary_output() {
+            Some(output) => output.rect(),
+            None => {
+                let (width, height) = self.screen_size();
+                Rectangle {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                }
+            }
+        };
+
+        for ls in &self.layer_surfaces {
+            if ls.exclusive_zone <= 0 || !ls.mapped {
+                continue;
+            }
+            if self.bar_hidden && ls.namespace == ktc_common::BAR_NAMESPACE {
+                continue;
+            }
+
+            let top = ls.anchor.contains(Anchor::Top);
+            let bottom = ls.anchor.contains(Anchor::Bottom);
+            let left = ls.anchor.contains(Anchor::Left);
+            let right = ls.anchor.contains(Anchor::Right);
+            let zone = ls.exclusive_zone;
+
+            if top && !bottom {
+                let reserved = zone + ls.margin.0;
+                area.y += reserved;
+                area.height -= reserved;
+            } else if bottom && !top {
+                area.height -= zone + ls.margin.2;
+            } else if left && !right {
+                let reserved = zone + ls.margin.3;
+                area.x += reserved;
+                area.width -= reserved;
+            } else if right && !left {
+                area.width -= zone + ls.margin.1;
+            }
+        }
+
+        area.width = area.width.max(1);
+        area.height = area.height.max(1);
+        area
+    }
+
+    // Scaled-down picture of a workspace, drawn from each window's last
+    // pixel cache; windows without one (DMA-BUF) show as their title color
+    pub fn workspace_preview(&self, workspace: usize, width: u32) -> Canvas {
+        let (screen_w, screen_h) = self.screen_size();
+        let (screen_w, screen_h) = (screen_w.max(1), screen_h.max(1));
+        // a portrait screen is limited by its height, so no side goes past
+        // PREVIEW_MAX_SIDE and the IPC reply stays a few hundred KB
+        let width = (width.clamp(16, PREVIEW_MAX_SIDE) as i32)
+            .min(PREVIEW_MAX_SIDE as i32 * screen_w / screen_h)
+            .max(1);
+        let height = (width * screen_h / screen_w).max(1);
+        let scale = width as f64 / screen_w as f64;
+        let scaled = |r: Rectangle| {
+            let x = (r.x as f64 * scale).round() as i32;
+            let y = (r.y as f64 * scale).round() as i32;
+            Rectangle {
+                x,
+                y,
+                width: ((r.x + r.width) as f64 * scale).round() as i32 - x,
+                height: ((r.y + r.height) as f64 * scale).round() as i32 - y,
+            }
+        };
+
+        let mut canvas = Canvas::new(
+            width as usize,
+            height as usize,
+            self.config.background_dark(),
+        );
+        let mut windows: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|w| w.mapped && w.workspace == workspace && !w.is_hidden() && !w.layout_hidden)
+            .collect();
+        windows.sort_by_key(|w| self.stack_key(w));
+
+        let title_bar_height = self.config.title_bar_height();
+        for window in windows {
+            let title_height = window.decoration_height(title_bar_height);
+            let geom = window.geometry;
+            let title = Rectangle {
+                height: title_height,
+                ..geom
+            };
+            let content = Rectangle {
+                y: geom.y + title_height,
+                height: geom.height - title_height,
+                ..geom
+            };
+            let title_color = if self.focused_window == Some(window.id) {
+                self.config.title_focused()
+            } else {
+                self.config.title_unfocused()
+            };
+            canvas.fill_rect(scaled(title), title_color);
+            if window.pixel_cache.is_empty() || window.cache_deep_color {
+                canvas.fill_rect(scaled(content), title_color);
+                continue;
+            }
+            let src = window.buffer_source().unwrap_or([
+                0.0,
+                0.0,
+                window.cache_width as f64,
+                window.cache_height as f64,
+            ]);
+            canvas.blit_scaled(
+                &window.pixel_cache,
+                window.cache_stride,
+                src,
+                scaled(content),
+            );
+        }
+        canvas
+    }
+
+    pub fn set_bar_hidden(&mut self, hidden: bool) {
+        if self.bar_hidden == hidden {
+            return;
+        }
+        self.bar_hidden = hidden;
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+    }
+
+    // the primary output's mode is now width x height; the canvas follows
+    // its logical size and layer surfaces are sized against it again
+    pub fn set_screen_size(&mut self, width: i32, height: i32) {
+        if self.outputs.is_empty() {
+            self.add_output("default".to_string(), width, height);
+        } else if let Some(output) = self.outputs.first_mut() {
+            output.width = width;
+            output.height = height;
+            let (logical_width, logical_height) = output.logical_size();
+            let id = output.id;
+            let bg_color = self.config.background_dark();
+            self.canvas
+                .resize(logical_width as usize, logical_height as usize, bg_color);
+            self.send_output_configuration(id);
+        }
+        let layer_surfaces: Vec<ObjectId> = self
+            .layer_surfaces
+            .iter()
+            .map(|ls| ls.wl_surface.id())
+            .collect();
+        for surface_id in layer_surfaces {
+            self.configure_layer_surface(surface_id);
+        }
+        self.damage_tracker.mark_full_damage();
+        self.needs_relayout = true;
+    }
+
+    pub fn mark_surface_damage(&mut self, surface_id: ObjectId) {
+        if let Some(window) = self
+            .windows
+            .iter_mut()
+            .find(|w| w.wl_surface.id() == surface_id)
+        {
+            window.needs_redraw = true;
+            let geometry = window.geometry;
+            self.damage_tracker.add_damage(geometry);
+        }
+    }
+
+    pub fn mark_layer_surface_damage(&mut self, surface_id: ObjectId) {
+        if let Some(ls) = self
+            .layer_surfaces
+            .iter_mut()
+            .find(|ls| ls.wl_surface.id() == surface_id)
+        {
+            ls.needs_redraw = true;
+            let geometry = ls.geometry;
+            self.damage_tracker.add_damage(geometry);
+        }
+    }
+
+    pub fn next_keyboard_serial(&mut self) -> u32 {
+        self.keyboard_serial = self.keyboard_serial.wrapping_add(1);
+        self.keyboard_serial
+    }
+
+    pub fn next_pointer_serial(&mut self) -> u32 {
+        self.pointer_serial = self.pointer_serial.wrapping_add(1);
+        self.pointer_serial
+    }
+
+    #[allow(dead_code)]
+    pub fn add_window(
+        &mut self,
+        xdg_surface: XdgSurface,
+        xdg_toplevel: XdgToplevel,
+        wl_surface: WlSurface,
+    ) -> WindowId {
+        let id = self.add_window_without_relayout(xdg_surface, xdg_toplevel, wl_surface);
+        self.needs_relayout = true;
+        id
+    }
+
+    pub fn add_window_without_relayout(
+        &mut self,
+        xdg_surface: XdgSurface,
+        xdg_toplevel: XdgToplevel,
+        wl_surface: WlSurface,
+    ) -> WindowId {
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+
+        log::debug!(
+            "[window] Adding window {} with surface {:?}",
+            id,
+            wl_surface.id()
+        );
+
+        let (screen_width, screen_height) = self.screen_size();
+        let num_windows = self.windows.len() + 1;
+        let geometry = calculate_tiling_geometry(
+            num_windows - 1,
+            num_windows,
+            screen_width,
+            screen_height,
+            0.5,
+        );
+        let index = self.insert_index();
+
+        self.windows.insert(
+            index,
+            Window {
+                id,
+                xdg_surface,
+                xdg_toplevel,
+                wl_surface,
+                geometry,
+                mapped: false,
+                buffer: None,
+                pending_buffer: None,
+                pending_buffer_set: false,
+                buffer_released: true,
+                needs_redraw: true,
+                pixel_cache: Vec::new(),
+                cache_width: 0,
+                cache_height: 0,
+                cache_stride: 0,
+                cache_deep_color: false,
+                cache_opaque: false,
+                pending_surface_damage: BufferDamage::default(),
+                pending_damage: BufferDamage::default(),
+                buffer_damage: BufferDamage::default(),
+                title: String::new(),
+                workspace: self.active_workspace,
+                fullscreen: false,
+                floating: false,
+                maximized: false,
+                saved_geometry: None,
+                app_id: String::new(),
+                decorations: true,
+                rules_applied: false,
+                layout_hidden: false,
+                scratchpad: false,
+                parent: None,
+                min_size: (0, 0),
+                max_size: (0, 0),
+                last_configure: None,
+                pid: None,
+                swallowed_by: None,
+                minimized: false,
+                layout_group: 0,
+                buffer_scale: 1,
+                pending_buffer_scale: None,
+                buffer_transform: OutputTransform::Normal,
+                pending_buffer_transform: None,
+                viewport: Viewport::default(),
+                pending_viewport: None,
+                hide_on_close: None,
+                blur: false,
+                opacity: 1.0,
+                last_alive: std::time::Instant::now(),
+                pending_ping: None,
+                unresponsive: false,
+                opaque_region: Vec::new(),
+                pending_opaque_region: None,
+                visible: true,
+            },
+        );
+        self.stack.push(id);
+
+        self.damage_tracker.mark_full_damage();
+
+        id
+    }
+
+    // tiled windows sit below floating ones, fullscreen and maximized windows on top;
+    // within each layer the most recently raised window wins
+    pub fn stack_key(&self, window: &Window) -> (u8, usize) {
+        let layer = if window.fullscreen || window.maximized {
+            2
+        } else if window.floating {
+            1
+        } else {
+            0
+        };
+        let position = self
+            .stack
+            .iter()
+            .position(|id| *id == window.id)
+            .unwrap_or(0);
+        (layer, position)
+    }
+
+    pub fn raise_window(&mut self, window_id: WindowId) {
+        let Some(pos) = self.stack.iter().position(|id| *id == window_id) else {
+            return;
+        };
+        if pos + 1 == self.stack.len() {
+            return;
+        }
+        self.stack.remove(pos);
+        self.stack.push(window_id);
+        self.damage_tracker.mark_full_damage();
+    }
+
+    pub fn lower_window(&mut self, window_id: WindowId) {
+        let Some(pos) = self.stack.iter().position(|id| *id == window_id) else {
+            return;
+        };
+        if pos == 0 {
+            return;
+        }
+        self.stack.remove(pos);
+        self.stack.insert(0, window_id);
+        self.damage_tracker.mark_full_damage();
+    }
+
+    fn insert_index(&mut self) -> usize {
+        let active_workspace = self.active_workspace;
+        let position_of =
+            |windows: &[Window], id: WindowId| windows.iter().position(|w| w.id == id);
+
+        if let Some(marked) = self.insert_marker.take() {
+            if let Some(idx) = position_of(&self.windows, marked) {
+                return idx + 1;
+            }
+        }
+
+        let position =
+            InsertPosition::parse(&self.config.appearance.insert_position).unwrap_or_default();
+        match position {
+            InsertPosition::End => self.windows.len(),
+            InsertPosition::AfterFocused => self
+                .focused_window
+                .and_then(|id| position_of(&self.windows, id))
+                .filter(|&idx| self.windows[idx].workspace == active_workspace)
+                .map(|idx| idx + 1)
+                .unwrap_or(self.windows.len()),
+            InsertPosition::Master => self
+                .windows
+                .iter()
+                .position(|w| w.workspace == active_workspace && !w.floating && !w.fullscreen)
+                .unwrap_or(self.windows.len()),
+        }
+    }
+
+    // strip along the bottom of the title bar of the window new windows will follow
+    pub fn insert_marker_rect(&self) -> Option<Rectangle> {
+        let window = self.windows.iter().find(|w| {
+            Some(w.id) == self.insert_marker
+                && w.workspace == self.active_workspace
+                && !w.layout_hidden
+        })?;
+        let title_bar_height = window.decoration_height(self.config.title_bar_height());
+        let height = 3.min(window.geometry.height);
+        Some(Rectangle {
+            x: window.geometry.x,
+            y: window.geometry.y + (title_bar_height - height).max(0),
+            width: window.geometry.width,
+            height,
+        })
+    }
+
+    // x of the draggable boundary between the two windows of a tile layout,
+    // placed in the usable area the way relayout_windows places the tiles
+    fn split_boundary(&self) -> Option<i32> {
+        if self.layout() != Layout::Tile {
+            return None;
+        }
+        let tiled = self
+            .windows
+            .iter()
+            .filter(|w| w.workspace == self.active_workspace && !w.floating && !w.fullscreen)
+            .count();
+        if tiled != 2 {
+            return None;
+        }
+        let split_ratio = self.workspace(self.active_workspace)?.split_ratio;
+        let usable = self.usable_area();
+        Some(usable.x + (usable.width as f32 * split_ratio.clamp(0.1, 0.9)) as i32)
+    }
+
+    pub fn split_preview_rect(&self) -> Option<Rectangle> {
+        let x = self.split_drag?;
+        let usable = self.usable_area();
+        Some(Rectangle {
+            x: x - SPLIT_PREVIEW_WIDTH / 2,
+            y: usable.y,
+            width: SPLIT_PREVIEW_WIDTH,
+            height: usable.height,
+        })
+    }
+
+    // topmost window, if it is fullscreen and nothing the compositor draws
+    // would end up on top of it
+    pub fn scanout_candidate(&self) -> Option<WindowId> {
+        if self.cursor_visible
+            || self.safe_mode.is_some()
+            || self.help_overlay.is_some()
+            || self.split_drag.is_some()
+            || self.region_select.is_some()
+            || self.pending_screenshot.is_some()
+            || self.recorder.is_some()
+            || self
+                .layer_surfaces
+                .iter()
+                .any(|ls| ls.mapped && ls.layer >= Layer::Top)
+        {
+            return None;
+        }
+        let window = self
+            .windows
+            .iter()
+            .filter(|w| w.mapped && w.workspace == self.active_workspace && !w.layout_hidden)
+            .max_by_key(|w| self.stack_key(w))?;
+        let (width, height) = self.screen_size();
+        let covers_screen = window.geometry
+            == Rectangle {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            };
+        let undecorated = window.decoration_height(self.config.title_bar_height()) == 0;
+        let opaque = self.window_opacity(window) >= 1.0;
+        // the plane shows the buffer as is, so it must already be turned the
+        // way the output is
+        let oriented = window.buffer_transform == self.buffer_transform();
+        (window.fullscreen
+            && covers_screen
+            && undecorated
+            && opaque
+            && oriented
+            && self.insert_marker_rect().is_none())
+        .then_some(window.id)
+    }
+
+    // Called after every present with the client buffer now being scanned
+    // out, if any. A replaced buffer stays on screen until that flip lands, so
+    // it is only released on the present after.
+    pub fn rotate_scanout_buffer(&mut self, buffer: Option<WlBuffer>) {
+        if let Some(retired) = self.scanout_retiring.take() {
+            retired.release();
+        }
+        if self.scanout_buffer != buffer {
+            self.scanout_retiring = std::mem::replace(&mut self.scanout_buffer, buffer);
+        }
+    }
+
+    fn begin_split_drag(&mut self, x: f64) -> bool {
+        let Some(boundary) = self.split_boundary() else {
+            return false;
+        };
+        if (x - boundary as f64).abs() > SPLIT_GRAB_MARGIN as f64 {
+            return false;
+        }
+        self.split_drag = Some(boundary);
+        if let Some(rect) = self.split_preview_rect() {
+            self.damage_tracker.add_damage(rect);
+        }
+        true
+    }
+
+    // moves only the preview; clients are reconfigured once on release
+    fn update_split_drag(&mut self, x: f64) {
+        let usable = self.usable_area();
+        let min_x = usable.x + (usable.width as f32 * 0.1) as i32;
+        let max_x = usable.x + (usable.width as f32 * 0.9) as i32;
+        if let Some(rect) = self.split_preview_rect() {
+            self.damage_tracker.add_damage(rect);
+        }
+        self.split_drag = Some((x as i32).clamp(min_x, max_x));
+        if let Some(rect) = self.split_preview_rect() {
+            self.damage_tracker.add_damage(rect);
+        }
+    }
+
+    fn end_split_drag(&mut self) {
+        if let Some(rect) = self.split_preview_rect() {
+            self.damage_tracker.add_damage(rect);
+        }
+        let Some(x) = self.split_drag.take() else {
+            return;
+        };
+        let usable = self.usable_area();
+        let ratio = (x - usable.x) as f32 / usable.width.max(1) as f32;
+        let active_workspace = self.active_workspace;
+        if let Some(workspace) = self.workspace_mut(active_workspace) {
+            workspace.split_ratio = ratio.clamp(0.1, 0.9);
+        }
+        self.needs_relayout = true;
+    }
+
+    // only a floating window's title bar, outside its buttons, picks it up
+    fn begin_move_drag(&mut self, id: WindowId, x: f64, y: f64) -> bool {
+        let title_bar_height = self.config.title_bar_height();
+        let Some(window) = self.windows.iter().find(|w| w.id == id) else {
+            return false;
+        };
+        if !window.floating || window.maximized || window.fullscreen {
+            return false;
+        }
+        let g = window.geometry;
+        let title_bar = Rectangle {
+            height: window.decoration_height(title_bar_height),
+            ..g
+        };
+        if !title_bar.contains(x, y) {
+            return false;
+        }
+        self.move_drag = Some((id, (x - g.x as f64, y - g.y as f64)));
+        true
+    }
+
+    fn update_move_drag(&mut self, x: f64, y: f64) {
+        let Some((id, (dx, dy))) = self.move_drag else {
+            return;
+        };
+        let usable = self.usable_area();
+        let Some(window) = self.windows.iter_mut().find(|w| w.id == id) else {
+            self.move_drag = None;
+            return;
+        };
+        let g = &mut window.geometry;
+        g.x = ((x - dx) as i32).clamp(usable.x, (usable.x + usable.width - g.width).max(usable.x));
+        g.y = ((y - dy) as i32).clamp(
+            usable.y,
+            (usable.y + usable.height - g.height).max(usable.y),
+        );
+        self.damage_tracker.mark_full_damage();
+    }
+
+    // The window is the floating one under the pointer; over anything else
+    // the grab only keeps the button from clients
+    pub fn begin_pointer_grab(&mut self, grab: PointerGrab, button: u32) {
+        self.pointer_grab = Some(button);
+        let (x, y) = (self.pointer_x, self.pointer_y);
+        let Some(window) = self
+            .pointer_focus
+            .and_then(|id| self.windows.iter().find(|w| w.id == id))
+            .filter(|w| w.floating && !w.maximized && !w.fullscreen)
+        else {
+            return;
+        };
+        let (id, g) = (window.id, window.geometry);
+        if self.focused_window != Some(id) {
+            self.set_focus(id);
+        }
+        self.raise_window(id);
+        match grab {
+            PointerGrab::Move => {
+                self.move_drag = Some((id, (x - g.x as f64, y - g.y as f64)));
+            }
+            PointerGrab::Resize => {
+                let edge = |at: f64, start: i32, size: i32| {
+                    if at < start as f64 + size as f64 / 2.0 {
+                        -1
+                    } else {
+                        1
+                    }
+                };
+                self.resize_drag = Some(ResizeDrag {
+                    id,
+                    start: (x, y),
+                    geometry: g,
+                    edges: (edge(x, g.x, g.width), edge(y, g.y, g.height)),
+                });
+                self.send_window_configure(id);
+            }
+        }
+    }
+
+    fn end_pointer_grab(&mut self) {
+        self.pointer_grab = None;
+        self.move_drag = None;
+        if let Some(drag) = self.resize_drag.take() {
+            self.send_window_configure(drag.id);
+        }
+        self.update_pointer_focus(self.pointer_x, self.pointer_y);
+    }
+
+    fn update_resize_drag(&mut self, x: f64, y: f64) {
+        let Some(drag) = self.resize_drag else {
+            return;
+        };
+        let Some(window) = self.windows.iter_mut().find(|w| w.id == drag.id) else {
+            self.resize_drag = None;
+            return;
+        };
+        let g = drag.geometry;
+        let (dx, dy) = ((x - drag.start.0) as i32, (y - drag.start.1) as i32);
+        let width = (g.width + dx * drag.edges.0).max(100);
+        let height = (g.height + dy * drag.edges.1).max(100);
+        window.geometry = Rectangle {
+            x: if drag.edges.0 < 0 {
+                g.x + g.width - width
+            } else {
+                g.x
+            },
+            y: if drag.edges.1 < 0 {
+                g.y + g.height - height
+            } else {
+                g.y
+            },
+            width,
+            height,
+        };
+        window.needs_redraw = true;
+        self.damage_tracker.mark_full_damage();
+        self.send_window_configure(drag.id);
+    }
+
+    pub fn request_screenshot(&mut self, rect: Rectangle) {
+        self.pending_screenshot = Some(rect);
+        self.damage_tracker.mark_full_damage();
+    }
+
+    pub fn begin_region_select(&mut self) {
+        self.region_select = Some(RegionSelect::Armed);
+        log::info!("[screenshot] Drag out a region, any other button cancels");
+    }
+
+    pub fn region_select_rect(&self) -> Option<Rectangle> {
+        let Some(RegionSelect::Dragging { x, y }) = self.region_select else {
+            return None;
+        };
+        Some(span_rect((x, y), (self.cursor_x, self.cursor_y)))
+    }
+
+    fn handle_region_select_button(&mut self, button: u32, pressed: bool) {
+        let rect = self.region_select_rect();
+        if let Some(rect) = rect {
+            self.damage_tracker.add_damage(rect);
+        }
+        match (self.region_select, pressed) {
+            (Some(RegionSelect::Armed), true) if button == BTN_LEFT => {
+                self.region_select = Some(RegionSelect::Dragging {
+                    x: self.cursor_x,
+                    y: self.cursor_y,
+                });
+            }
+            (Some(RegionSelect::Dragging { .. }), false) if button == BTN_LEFT => {
+                self.region_select = None;
+                if let Some(rect) = rect.filter(|r| r.width > 0 && r.height > 0) {
+                    self.request_screenshot(rect);
+                }
+            }
+            (_, true) => {
+                self.region_select = None;
+                log::info!("[screenshot] Region selection cancelled");
+            }
+            _ => {}
+        }
+    }
+
+    pub fn toggle_insert_marker(&mut self) {
+        let Some(focused) = self.focused_window else {
+            return;
+        };
+        let previous = self.insert_marker;
+        self.insert_marker = if previous == Some(focused) {
+            None
+        } else {
+            Some(focused)
+        };
+        for id in [previous, self.insert_marker].into_iter().flatten() {
+            if let Some(window) = self.windows.iter().find(|w| w.id == id) {
+                self.damage_tracker.add_damage(window.geometry);
+            }
+        }
+    }
+
+    // kept above 0.1 so a window can't be made to vanish
+    pub fn set_opacity(&mut self, window_id: WindowId, opacity: f64) {
+        let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) else {
+            return;
+        };
+        window.opacity = opacity.clamp(0.1, 1.0);
+        self.damage_tracker.add_damage(window.geometry);
+    }
+
+    // what the window's content is drawn at
+    pub fn window_opacity(&self, window: &Window) -> f32 {
+        let dim = if self.focused_window == Some(window.id) {
+            1.0
+        } else {
+            self.config.appearance.inactive_opacity.clamp(0.1, 1.0)
+        };
+        (window.opacity * dim) as f32
+    }
+
+    // Screen rectangles the window is sure to cover without blending: its
+    // title bar, and its contents where the buffer has no alpha or the
+    // client declared them opaque
+    fn opaque_rects(&self, window: &Window) -> Vec<Rectangle> {
+        if self.window_opacity(window) < 1.0 {
+            return Vec::new();
+        }
+        let g = window.geometry;
+        let title = window.decoration_height(self.config.title_bar_height());
+        let mut rects = vec![Rectangle {
+            x: g.x,
+            y: g.y,
+            width: g.width,
+            height: title,
+        }];
+
+        let dmabuf = window
+            .buffer
+            .as_ref()
+            .and_then(|buffer| self.dmabuf_buffers.get(&buffer.id()));
+        let (size, opaque) = match dmabuf {
+            Some(info) if window.buffer_transform.swaps_axes() => (
+                window.content_size(info.height as usize, info.width as usize),
+                fourcc_opaque(info.format),
+            ),
+            Some(info) => (
+                window.content_size(info.width as usize, info.height as usize),
+                fourcc_opaque(info.format),
+            ),
+            None => (
+                window.content_size(window.cache_width, window.cache_height),
+                window.cache_opaque,
+            ),
+        };
+        let content = Rectangle {
+            x: g.x,
+            y: g.y + title,
+            width: size.0,
+            height: size.1,
+        };
+        if opaque {
+            rects.push(content);
+        } else {
+            rects.extend(window.opaque_region.iter().filter_map(|r| {
+                let r = Rectangle {
+                    x: content.x + r.x,
+                    y: content.y + r.y,
+                    ..*r
+                };
+                r.intersection(&content)
+            }));
+        }
+        rects.retain(|r| !r.is_empty());
+        rects
+    }
+
+    // Works out which windows show anything this frame: mapped on the
+    // active workspace, not hidden by the layout, and not buried under
+    // opaque windows stacked above. The focused window always counts, it is
+    // the one pinged when it stops drawing. Windows that come back into view
+    // are damaged, their contents weren't kept up to date.
+    pub fn update_window_visibility(&mut self) {
+        let active = self.active_workspace;
+        let mut stacked: Vec<&Window> = self
+            .windows
+            .iter()
+            .filter(|w| w.mapped && w.buffer.is_some() && w.workspace == active && !w.layout_hidden)
+            .collect();
+        stacked.sort_by_key(|w| self.stack_key(w));
+
+        let mut covering: Vec<Rectangle> = Vec::new();
+        let mut visible = std::collections::HashSet::new();
+        for window in stacked.into_iter().rev() {
+            let opaque = self.opaque_rects(window);
+            let bounds = opaque.iter().fold(window.geometry, |b, r| b.union(r));
+            let mut uncovered = vec![bounds];
+            for rect in &covering {
+                uncovered = uncovered.iter().flat_map(|u| u.subtract(rect)).collect();
+            }
+            if !uncovered.is_empty() || self.focused_window == Some(window.id) {
+                visible.insert(window.id);
+            }
+            covering.extend(opaque);
+        }
+
+        let mut revealed = Vec::new();
+        for window in &mut self.windows {
+            let now = visible.contains(&window.id);
+            if now && !window.visible {
+                revealed.push(window.geometry);
+            }
+            window.visible = now;
+        }
+        for rect in revealed {
+            self.damage_tracker.add_damage(rect);
+        }
+    }
+
+    // Hidden windows' callbacks wait until they show again, and a surface
+    // whose contents are still on the upload thread waits for them to land,
+    // or the client would draw its next frame before this one is on screen.
+    fn frame_callback_due(&self, surface: &WlSurface) -> bool {
+        let uploading = |id: u64| {
+            self.gpu_renderer
+                .as_ref()
+                .is_some_and(|gpu| gpu.upload_pending(id))
+        };
+        if let Some(window) = self.windows.iter().find(|w| w.wl_surface == *surface) {
+            return window.visible && !uploading(window.id);
+        }
+        self.layer_surfaces
+            .iter()
+            .find(|ls| ls.wl_surface == *surface)
+            .is_none_or(|ls| !uploading(ls.id + crate::renderer::LAYER_TEXTURE_OFFSET))
+    }
+
+    pub fn has_due_frame_callbacks(&self) -> bool {
+        self.frame_callbacks
+            .iter()
+            .any(|(surface, _)| self.frame_callback_due(surface))
+    }
+
+    pub fn take_due_frame_callbacks(&mut self) -> Vec<WlCallback> {
+        let (due, waiting) = std::mem::take(&mut self.frame_callbacks)
+            .into_iter()
+            .partition(|(surface, _)| self.frame_callback_due(surface));
+        self.frame_callbacks = waiting;
+        due.into_iter().map(|(_, callback)| callback).collect()
+    }
+
+    // a commit or a pong shows the client still runs its event loop
+    pub fn mark_alive(&mut self, window_id: WindowId) {
+        let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) else {
+            return;
+        };
+        window.last_alive = std::time::Instant::now();
+        window.pending_ping = None;
+        if std::mem::take(&mut window.unresponsive) {
+            log::info!("[window] Window {} is responding again", window_id);
+            self.damage_tracker.add_damage(window.geometry);
+        }
+    }
+
+    pub fn handle_pong(&mut self, wm_base: &XdgWmBase, serial: u32) {
+        let answered: Vec<WindowId> = self
+            .windows
+            .iter()
+            .filter(|w| w.pending_ping.is_some_and(|(s, _)| s == serial))
+            .filter(|w| w.wl_surface.id().same_client_as(&wm_base.id()))
+            .map(|w| w.id)
+            .collect();
+        for window_id in answered {
+            self.mark_alive(window_id);
+        }
+    }
+
+    // Pings the focused window once it has gone unresponsive_timeout seconds
+    // without a commit, and marks it unresponsive when the pong takes as long;
+    // true when a ping needs flushing or the window needs a redraw
+    pub fn check_unresponsive(&mut self) -> bool {
+        let timeout = std::time::Duration::from_secs(self.config.appearance.unresponsive_timeout);
+        if timeout.is_zero() {
+            return false;
+        }
+        let Some(focused) = self.focused_window else {
+            return false;
+        };
+        let active_workspace = self.active_workspace;
+        let Some(window) = self.windows.iter_mut().find(|w| w.id == focused) else {
+            return false;
+        };
+        if !window.mapped
+            || window.unresponsive
+            || window.minimized
+            || window.workspace != active_workspace
+        {
+            return false;
+        }
+
+        match window.pending_ping {
+            None if window.last_alive.elapsed() >= timeout => {
+                self.wm_bases.retain(|wm_base| wm_base.is_alive());
+                let client = window.wl_surface.id();
+                if let Some(wm_base) = self
+                    .wm_bases
+                    .iter()
+                    .find(|wm_base| wm_base.id().same_client_as(&client))
+                {
+                    self.ping_serial = self.ping_serial.wrapping_add(1);
+                    wm_base.ping(self.ping_serial);
+                    window.pending_ping = Some((self.ping_serial, std::time::Instant::now()));
+                    return true;
+                }
+                false
+            }
+            Some((_, sent)) if sent.elapsed() >= timeout => {
+                log::warn!(
+                    "[window] Window {} ({}) did not answer a ping for {}s",
+                    window.id,
+                    window.app_id,
+                    timeout.as_secs()
+                );
+                window.unresponsive = true;
+                self.damage_tracker.add_damage(window.geometry);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // content area to tint when the window stopped answering pings
+    pub fn unresponsive_rect(&self, window_id: WindowId) -> Option<Rectangle> {
+        let window = self
+            .windows
+            .iter()
+            .find(|w| w.id == window_id && w.unresponsive)?;
+        let title_height = window.decoration_height(self.title_bar_height());
+        Some(Rectangle {
+            x: window.geometry.x,
+            y: window.geometry.y + title_height,
+            width: window.geometry.width,
+            height: window.geometry.height - title_height,
+        })
+    }
+
+    // a mark names exactly one window, so re-marking moves it
+    pub fn set_mark(&mut self, window_id: WindowId, mark: &str) {
+        if self.marks.insert(mark.to_string(), window_id) != Some(window_id) {
+            log::info!("[marks] Marked window {} as '{}'", window_id, mark);
+        }
+    }
+
+    pub fn marks_of(&self, window_id: WindowId) -> Vec<String> {
+        let mut marks: Vec<String> = self
+            .marks
+            .iter()
+            .filter(|(_, id)| **id == window_id)
+            .map(|(mark, _)| mark.clone())
+            .collect();
+        marks.sort();
+        marks
+    }
+
+    pub fn jump_to_mark(&mut self, mark: &str) -> bool {
+        let Some(&window_id) = self.marks.get(mark) else {
+            log::debug!("[marks] No window marked '{}'", mark);
+            return false;
+        };
+        let Some(window) = self.windows.iter().find(|w| w.id == window_id) else {
+            return false;
+        };
+        if window.minimized {
+            return self.restore_window(window_id);
+        }
+        if window.is_hidden() {
+            log::debug!("[marks] Window '{}' is in the scratchpad", mark);
+            return false;
+        }
+        let workspace = window.workspace;
+        if workspace != self.active_workspace {
+            self.switch_workspace(workspace);
+        }
+        self.set_focus(window_id);
+        self.raise_window(window_id);
+        true
+    }
+
+    // 1-based, in layout order on the active workspace
+    pub fn focus_index(&mut self, index: usize) -> bool {
+        let active = self.active_workspace;
+        let Some(window_id) = self
+            .windows
+            .iter()
+            .filter(|w| w.workspace == active && w.mapped)
+            .nth(index.saturating_sub(1))
+            .map(|w| w.id)
+        else {
+            return false;
+        };
+        self.set_focus(window_id);
+        self.raise_window(window_id);
+        true
+    }
+
+    pub fn apply_window_rules(&mut self, window_id: WindowId) {
+        let (app_id, title) = match self.windows.iter_mut().find(|w| w.id == window_id) {
+            Some(w) => {
+                w.rules_applied = true;
+                (w.app_id.clone(), w.title.clone())
+            }
+            None => return,
+        };
+
+        let mut rule = crate::config::WindowRule::default();
+        for r in self.config.matching_rules(&app_id, &title) {
+            rule.workspace = r.workspace.or(rule.workspace);
+            rule.floating = r.floating.or(rule.floating);
+            rule.fullscreen = r.fullscreen.or(rule.fullscreen);
+            rule.width = r.width.or(rule.width);
+            rule.height = r.height.or(rule.height);
+            rule.decorations = r.decorations.or(rule.decorations);
+            rule.swallow = r.swallow.or(rule.swallow);
+            rule.hide_on_close = r.hide_on_close.clone().or(rule.hide_on_close);
+            rule.blur = r.blur.or(rule.blur);
+            rule.opacity = r.opacity.or(rule.opacity);
+        }
+
+        log::info!(
+            "[window] Applying rules to window {} (app_id: {:?}, title: {:?})",
+            window_id,
+            app_id,
+            title
+        );
+
+        if let Some(decorations) = rule.decorations {
+            if let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) {
+                window.decorations = decorations;
+            }
+        }
+
+        if let Some(opacity) = rule.opacity {
+            self.set_opacity(window_id, opacity);
+        }
+
+        if let Some(blur) = rule.blur {
+            if let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) {
+                window.blur = blur;
+            }
+        }
+
+        if let Some(ref hide) = rule.hide_on_close {
+            let hide_on_close = HideOnClose::parse(hide);
+            if hide_on_close.is_none() {
+                log::warn!("[window] Unknown hide_on_close value '{}'", hide);
+            }
+            if let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) {
+                window.hide_on_close = hide_on_close;
+            }
+        }
+
+        let is_dialog = self
+            .windows
+            .iter()
+            .find(|w| w.id == window_id)
+            .is_some_and(|w| w.is_dialog());
+        if rule.floating.is_none() && is_dialog {
+            log::info!("[window] Floating dialog window {}", window_id);
+            rule.floating = Some(true);
+        }
+
+        if rule.floating == Some(true) {
+            let usable = self.usable_area();
+            let title_bar_height = self.config.title_bar_height();
+            let parent_geometry = self
+                .windows
+                .iter()
+                .find(|w| w.id == window_id)
+                .and_then(|w| w.parent)
+                .and_then(|parent| self.windows.iter().find(|w| w.id == parent))
+                .filter(|p| p.workspace == self.active_workspace)
+                .map(|p| p.geometry);
+            if let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) {
+                let decoration_height = window.decoration_height(title_bar_height);
+                let (min_width, min_height) = window.min_size;
+                let width = rule
+                    .width
+                    .or((min_width > 0).then_some(min_width))
+                    .unwrap_or(usable.width / 2)
+                    .clamp(100, usable.width.max(100));
+                let height = rule
+                    .height
+                    .or((min_height > 0).then_some(min_height + decoration_height))
+                    .unwrap_or(usable.height / 2)
+                    .clamp(100, usable.height.max(100));
+                let area = parent_geometry.unwrap_or(usable);
+                window.geometry = Rectangle {
+                    x: (area.x + (area.width - width) / 2)
+                        .clamp(usable.x, (usable.x + usable.width - width).max(usable.x)),
+                    y: (area.y + (area.height - height) / 2)
+                        .clamp(usable.y, (usable.y + usable.height - height).max(usable.y)),
+                    width,
+                    height,
+                };
+            }
+        }
+
+        if let Some(floating) = rule.floating {
+            self.set_floating(window_id, floating);
+        }
+
+        if let Some(workspace) = rule.workspace {
+            if workspace != self.active_workspace {
+                self.move_window_to_workspace(window_id, workspace);
+                self.refocus_away_from(window_id);
+            }
+        }
 
-    #[allow(dead_code)]
-    pub fn set_screen_size(&mut self, width: i32, height: i32) {
-        if self.outputs.is_empty() {
-            self.add_output("default".to_string(), width, height);
-        } else if let Some(output) = self.outputs.first_mut() {
-            output.width = width;
-            output.height = height;
-            let bg_color = self.config.background_dark();
-            self.canvas
-                .resize(width as usize, height as usize, bg_color);
-            let id = output.id;
-            self.send_output_configuration(id);
+        if rule.workspace.is_none() && rule.swallow.unwrap_or(self.config.appearance.swallow) {
+            self.swallow_parent(window_id);
+        }
+
+        if rule.fullscreen == Some(true) {
+            self.set_fullscreen(window_id, true);
+        } else {
+            self.send_window_configure(window_id);
         }
+
+        self.needs_relayout = true;
         self.damage_tracker.mark_full_damage();
-        self.relayout_windows();
     }
 
-    pub fn mark_surface_damage(&mut self, surface_id: ObjectId) {
-        if let Some(window) = self
+    // hides the terminal that launched this window and hands its slot to the window
+    fn swallow_parent(&mut self, window_id: WindowId) {
+        let Some(mut pid) = self
             .windows
-            .iter_mut()
-            .find(|w| w.wl_surface.id() == surface_id)
-        {
-            window.needs_redraw = true;
-            let geometry = window.geometry;
-            self.damage_tracker.add_damage(geometry);
-        }
-    }
+            .iter()
+            .find(|w| w.id == window_id && !w.floating && !w.fullscreen)
+            .and_then(|w| w.pid)
+        else {
+            return;
+        };
 
-    pub fn mark_layer_surface_damage(&mut self, surface_id: ObjectId) {
-        if let Some(ls) = self
-            .layer_surfaces
-            .iter_mut()
-            .find(|ls| ls.wl_surface.id() == surface_id)
-        {
-            ls.needs_redraw = true;
-            let geometry = ls.geometry;
-            self.damage_tracker.add_damage(geometry);
+        let mut terminal_pos = None;
+        for _ in 0..32 {
+            pid = match parent_pid(pid) {
+                Some(ppid) if ppid > 1 => ppid,
+                _ => break,
+            };
+            terminal_pos = self.windows.iter().position(|w| {
+                w.pid == Some(pid) && w.id != window_id && w.mapped && !w.is_hidden()
+            });
+            if terminal_pos.is_some() {
+                break;
+            }
         }
-    }
-
-    pub fn next_keyboard_serial(&mut self) -> u32 {
-        self.keyboard_serial = self.keyboard_serial.wrapping_add(1);
-        self.keyboard_serial
-    }
+        let (Some(terminal_pos), Some(child_pos)) = (
+            terminal_pos,
+            self.windows.iter().position(|w| w.id == window_id),
+        ) else {
+            return;
+        };
 
-    pub fn next_pointer_serial(&mut self) -> u32 {
-        self.pointer_serial = self.pointer_serial.wrapping_add(1);
-        self.pointer_serial
+        let mut child = self.windows.remove(child_pos);
+        let terminal_pos = if child_pos < terminal_pos {
+            terminal_pos - 1
+        } else {
+            terminal_pos
+        };
+        let terminal = &mut self.windows[terminal_pos];
+        let terminal_id = terminal.id;
+        child.workspace = terminal.workspace;
+        child.floating = terminal.floating;
+        if terminal.floating {
+            child.geometry = terminal.geometry;
+        }
+        terminal.workspace = SCRATCHPAD_WORKSPACE;
+        terminal.swallowed_by = Some(window_id);
+        self.windows.insert(terminal_pos, child);
+
+        log::info!(
+            "[window] Window {} swallowed terminal {}",
+            window_id,
+            terminal_id
+        );
+        if self.insert_marker == Some(terminal_id) {
+            self.insert_marker = None;
+        }
+        if matches!(self.focused_window, Some(id) if id == terminal_id || id == window_id) {
+            self.focused_window = None;
+            self.set_focus(window_id);
+        }
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
     }
 
-    #[allow(dead_code)]
-    pub fn add_window(
-        &mut self,
-        xdg_surface: XdgSurface,
-        xdg_toplevel: XdgToplevel,
-        wl_surface: WlSurface,
-    ) -> WindowId {
-        let id = self.add_window_without_relayout(xdg_surface, xdg_toplevel, wl_surface);
-        self.relayout_windows();
-        id
-    }
+    // puts a swallowed terminal back into the slot of the window that swallowed it
+    pub fn restore_swallowed(&mut self, window_id: WindowId) -> Option<WindowId> {
+        let terminal_pos = self
+            .windows
+            .iter()
+            .position(|w| w.swallowed_by == Some(window_id))?;
+        let child_pos = self.windows.iter().position(|w| w.id == window_id)?;
+        let (workspace, floating, geometry) = {
+            let child = &self.windows[child_pos];
+            (child.workspace, child.floating, child.geometry)
+        };
 
-    pub fn add_window_without_relayout(
-        &mut self,
-        xdg_surface: XdgSurface,
-        xdg_toplevel: XdgToplevel,
-        wl_surface: WlSurface,
-    ) -> WindowId {
-        let id = self.next_window_id;
-        self.next_window_id += 1;
+        let mut terminal = self.windows.remove(terminal_pos);
+        let terminal_id = terminal.id;
+        terminal.swallowed_by = None;
+        terminal.workspace = workspace;
+        terminal.floating = floating;
+        if floating {
+            terminal.geometry = geometry;
+        }
+        terminal.needs_redraw = true;
+        let child_pos = if terminal_pos < child_pos {
+            child_pos - 1
+        } else {
+            child_pos
+        };
+        self.windows.insert(child_pos, terminal);
 
-        log::debug!(
-            "[window] Adding window {} with surface {:?}",
-            id,
-            wl_surface.id()
+        log::info!(
+            "[window] Restored terminal {} swallowed by window {}",
+            terminal_id,
+            window_id
         );
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+        Some(terminal_id)
+    }
 
-        let (screen_width, screen_height) = self.screen_size();
-        let num_windows = self.windows.len() + 1;
-        let geometry =
-            calculate_tiling_geometry(num_windows - 1, num_windows, screen_width, screen_height);
+    fn min_slot_size(&self) -> (i32, i32) {
+        let appearance = &self.config.appearance;
+        (
+            appearance.min_slot_width.max(1),
+            appearance
+                .min_slot_height
+                .max(self.config.title_bar_height() + 1),
+        )
+    }
 
-        self.windows.push(Window {
-            id,
-            xdg_surface,
-            xdg_toplevel,
-            wl_surface,
-            geometry,
-            mapped: false,
-            buffer: None,
-            pending_buffer: None,
-            pending_buffer_set: false,
-            buffer_released: true,
-            needs_redraw: true,
-            pixel_cache: Vec::new(),
-            cache_width: 0,
-            cache_height: 0,
-            cache_stride: 0,
-            title: String::new(),
-            workspace: self.active_workspace,
-            fullscreen: false,
-            floating: false,
-            maximized: false,
-            saved_geometry: None,
-        });
+    // floats the newest tiled windows that don't fit at the minimum slot size
+    fn float_overflowing_windows(&mut self) {
+        let active_workspace = self.active_workspace;
+        let mut tiled: Vec<WindowId> = self
+            .windows
+            .iter()
+            .filter(|w| w.workspace == active_workspace && !w.floating && !w.fullscreen)
+            .map(|w| w.id)
+            .collect();
+        let usable = self.usable_area();
+        let (min_width, min_height) = self.min_slot_size();
+        let split_ratio = self
+            .workspace(active_workspace)
+            .map(|ws| ws.split_ratio)
+            .unwrap_or(0.5);
+
+        let mut capacity = tiled.len();
+        while capacity > 1
+            && !slots_fit(
+                capacity,
+                usable.width,
+                usable.height,
+                split_ratio,
+                min_width,
+                min_height,
+            )
+        {
+            capacity -= 1;
+        }
+        if capacity == tiled.len() {
+            return;
+        }
 
+        tiled.sort_unstable();
+        for (i, id) in tiled[capacity..].iter().enumerate() {
+            if let Some(window) = self.windows.iter_mut().find(|w| w.id == *id) {
+                let width = (usable.width / 2).max(min_width);
+                let height = (usable.height / 2).max(min_height);
+                let offset = (i as i32 % 8) * 32;
+                window.floating = true;
+                window.geometry = Rectangle {
+                    x: usable.x + (usable.width - width) / 2 + offset,
+                    y: usable.y + (usable.height - height) / 2 + offset,
+                    width,
+                    height,
+                };
+                window.needs_redraw = true;
+                window.cache_width = 0;
+                window.cache_height = 0;
+                log::info!("[layout] No room to tile window {}, floating it", id);
+            }
+        }
         self.damage_tracker.mark_full_damage();
-
-        id
     }
 
     pub fn relayout_windows(&mut self) {
         let active_workspace = self.active_workspace;
+        let mut changed = false;
+        let overflow = Overflow::parse(&self.config.appearance.overflow).unwrap_or_default();
+        if self.layout() == Layout::Tile && overflow == Overflow::Float {
+            self.float_overflowing_windows();
+        }
+
         let tiled_window_ids: Vec<WindowId> = self
             .windows
             .iter()
-            .filter(|w| {
-                w.workspace == active_workspace && !w.floating && !w.fullscreen && !w.maximized
-            })
+            .filter(|w| w.workspace == active_workspace && !w.floating && !w.fullscreen)
             .map(|w| w.id)
             .collect();
 
@@ -1177,17 +3188,134 @@ impl State {
             .map(|w| w.id)
             .collect();
 
-        let (screen_width, screen_height) = self.screen_size();
+        // tiles share what the bar and other exclusive zones leave free
+        let usable = self.usable_area();
+        let (area_width, area_height) = (usable.width, usable.height);
         let num_tiled = tiled_window_ids.len();
+        let layout = self.layout();
+        let gap = self.gap(active_workspace);
+        let split_ratio = self
+            .workspace(active_workspace)
+            .map(|ws| ws.split_ratio)
+            .unwrap_or(0.5);
+
+        let (min_width, min_height) = self.min_slot_size();
+        let scrolling = layout == Layout::Tile
+            && !slots_fit(
+                num_tiled,
+                area_width,
+                area_height,
+                split_ratio,
+                min_width,
+                min_height,
+            );
+        let scroll_row = if scrolling {
+            let cols = (area_width / min_width).clamp(1, num_tiled.max(1) as i32);
+            let visible_rows = (area_height / min_height).max(1);
+            let total_rows = (num_tiled as i32 + cols - 1) / cols;
+            let focused_row = self
+                .focused_window
+                .and_then(|id| tiled_window_ids.iter().position(|&t| t == id))
+                .map(|idx| idx as i32 / cols);
+            let mut row = self
+                .workspace(active_workspace)
+                .map(|ws| ws.scroll_row)
+                .unwrap_or(0);
+            if let Some(focused_row) = focused_row {
+                if focused_row < row {
+                    row = focused_row;
+                } else if focused_row >= row + visible_rows {
+                    row = focused_row - visible_rows + 1;
+                }
+            }
+            row = row.clamp(0, (total_rows - visible_rows).max(0));
+            if let Some(ws) = self.workspace_mut(active_workspace) {
+                ws.scroll_row = row;
+            }
+            row
+        } else {
+            0
+        };
+
+        // monocle and tabbed show a single tiled window, preferring the focused one
+        let visible_id = if layout == Layout::Tile {
+            None
+        } else {
+            self.focused_window
+                .filter(|id| tiled_window_ids.contains(id))
+                .or_else(|| {
+                    tiled_window_ids
+                        .iter()
+                        .copied()
+                        .find(|id| self.windows.iter().any(|w| w.id == *id && !w.layout_hidden))
+                })
+                .or_else(|| tiled_window_ids.first().copied())
+        };
+
+        // while scrolling, tiled windows are hidden by row below instead, so
+        // they aren't shown here only to be hidden again
+        for window in self.windows.iter_mut().filter(|w| {
+            w.workspace == active_workspace && !(scrolling && tiled_window_ids.contains(&w.id))
+        }) {
+            let hidden = visible_id
+                .is_some_and(|id| id != window.id && !window.floating && !window.fullscreen);
+            if window.layout_hidden != hidden {
+                window.layout_hidden = hidden;
+                window.needs_redraw = true;
+                changed = true;
+            }
+        }
 
         for (i, window_id) in tiled_window_ids.iter().enumerate() {
             if let Some(window) = self.windows.iter_mut().find(|w| w.id == *window_id) {
-                let new_geometry =
-                    calculate_tiling_geometry(i, num_tiled, screen_width, screen_height);
+                let slot = match layout {
+                    Layout::Tile if scrolling => {
+                        let slot = calculate_scrolled_geometry(
+                            i,
+                            num_tiled,
+                            area_width,
+                            area_height,
+                            (min_width, min_height),
+                            scroll_row,
+                        );
+                        let offscreen = slot.y + slot.height <= 0 || slot.y >= area_height;
+                        if window.layout_hidden != offscreen {
+                            window.layout_hidden = offscreen;
+                            window.needs_redraw = true;
+                            changed = true;
+                        }
+                        slot
+                    }
+                    Layout::Tile => calculate_tiling_geometry(
+                        i,
+                        num_tiled,
+                        area_width,
+                        area_height,
+                        split_ratio,
+                    ),
+                    Layout::Monocle | Layout::Tabbed => {
+                        calculate_tiling_geometry(0, 1, area_width, area_height, split_ratio)
+                    }
+                };
+                let slot = apply_gap(
+                    Rectangle {
+                        x: usable.x + slot.x,
+                        y: usable.y + slot.y,
+                        ..slot
+                    },
+                    gap,
+                );
+                let new_geometry = if window.maximized {
+                    window.saved_geometry = Some(slot);
+                    usable
+                } else {
+                    slot
+                };
                 if window.geometry != new_geometry {
                     let old_geom = window.geometry;
                     window.geometry = new_geometry;
                     window.needs_redraw = true;
+                    changed = true;
 
                     if old_geom.width != new_geometry.width
                         || old_geom.height != new_geometry.height
@@ -1199,33 +3327,26 @@ impl State {
             }
         }
 
-        self.damage_tracker.mark_full_damage();
-
-        for window_id in &all_workspace_window_ids {
-            let (geometry, xdg_surface, xdg_toplevel, is_fullscreen) = {
-                let window = match self.windows.iter().find(|w| w.id == *window_id) {
-                    Some(w) => w,
-                    None => continue,
-                };
-                (
-                    window.geometry,
-                    window.xdg_surface.clone(),
-                    window.xdg_toplevel.clone(),
-                    window.fullscreen,
-                )
-            };
+        for window in self
+            .windows
+            .iter_mut()
+            .filter(|w| w.workspace == active_workspace && w.floating && w.maximized)
+        {
+            if window.geometry != usable {
+                window.geometry = usable;
+                window.needs_redraw = true;
+                window.cache_width = 0;
+                window.cache_height = 0;
+                changed = true;
+            }
+        }
 
-            let states = self.get_toplevel_states(*window_id);
-            let serial = self.next_keyboard_serial();
+        if changed {
+            self.damage_tracker.mark_full_damage();
+        }
 
-            let title_bar_height = if is_fullscreen {
-                0
-            } else {
-                self.config.title_bar_height()
-            };
-            let client_height = (geometry.height - title_bar_height).max(1);
-            xdg_toplevel.configure(geometry.width, client_height, states);
-            xdg_surface.configure(serial);
+        for window_id in all_workspace_window_ids {
+            self.send_window_configure(window_id);
         }
     }
 
@@ -1256,6 +3377,20 @@ impl State {
         None
     }
 
+    // Size of the shm buffer a commit on this surface makes current, the
+    // newly attached one if there is one
+    pub fn committed_buffer_size(&mut self, surface: &WlSurface) -> Option<(i32, i32)> {
+        let window = self.get_window_by_surface(surface)?;
+        let buffer = if window.pending_buffer_set {
+            window.pending_buffer.as_ref()
+        } else {
+            window.buffer.as_ref()
+        };
+        let buffer_id = buffer?.id();
+        let data = self.buffers.get(&buffer_id)?;
+        Some((data.width, data.height))
+    }
+
     #[allow(dead_code)]
     pub fn get_focused_window(&mut self) -> Option<&mut Window> {
         let focused_id = self.focused_window?;
@@ -1288,6 +3423,10 @@ impl State {
             return states;
         }
 
+        if self.resize_drag.is_some_and(|drag| drag.id == window_id) {
+            states.extend_from_slice(&(ToplevelState::Resizing as u32).to_ne_bytes());
+        }
+
         if !window.floating && num_windows >= 2 {
             if num_windows == 2 {
                 if window_index == Some(0) {
@@ -1311,19 +3450,35 @@ impl State {
     }
 
     pub fn remove_window(&mut self, id: WindowId) {
+        let restored = self
+            .restore_swallowed(id)
+            .filter(|_| self.focused_window == Some(id));
         if let Some(pos) = self.windows.iter().position(|w| w.id == id) {
             let geometry = self.windows[pos].geometry;
             self.damage_tracker.add_damage(geometry);
-            self.windows.swap_remove(pos);
+            self.windows.remove(pos);
             log::debug!("[window] Removed window {}", id);
         }
+        if self.insert_marker == Some(id) {
+            self.insert_marker = None;
+        }
+        self.marks.retain(|_, window_id| *window_id != id);
+        self.stack.retain(|window_id| *window_id != id);
+        if self.minimized_windows.contains(&id) {
+            self.minimized_windows.retain(|window_id| *window_id != id);
+            self.hidden_windows_changed = true;
+        }
+        for workspace in &mut self.workspaces {
+            workspace.forget(id);
+        }
         self.keyboard_to_window
             .retain(|_, window_id| *window_id != id);
 
         if self.focused_window == Some(id) {
             self.focused_window = None;
-            if let Some(first_window) = self.windows.first() {
-                let new_focus_id = first_window.id;
+            if let Some(new_focus_id) =
+                restored.or_else(|| self.focus_candidate(self.active_workspace, None))
+            {
                 self.set_focus(new_focus_id);
             }
         }
@@ -1334,14 +3489,19 @@ impl State {
     pub fn close_window(&mut self, id: WindowId) {
         if let Some(window) = self.windows.iter().find(|w| w.id == id) {
             if window.wl_surface.client().is_some() {
-                window.xdg_toplevel.close();
+                // close-to-tray apps keep running; kill still ends them
+                match window.hide_on_close {
+                    Some(HideOnClose::Scratchpad) => self.move_to_scratchpad(id),
+                    Some(HideOnClose::Minimize) => self.minimize_window(id),
+                    None => window.xdg_toplevel.close(),
+                }
             } else {
                 log::info!(
                     "[window] Client for window {} is dead, removing directly",
                     id
                 );
                 self.remove_window(id);
-                self.relayout_windows();
+                self.needs_relayout = true;
             }
         }
     }
@@ -1361,7 +3521,7 @@ impl State {
         }
 
         if had_dead {
-            self.relayout_windows();
+            self.needs_relayout = true;
         }
 
         had_dead
@@ -1425,19 +3585,13 @@ impl State {
     }
 
     pub fn switch_workspace(&mut self, workspace: usize) {
-        if workspace < 1 || workspace > self.workspace_count {
+        if self.workspace(workspace).is_none() {
             return;
         }
 
         self.active_workspace = workspace;
 
-        let first_window = self
-            .windows
-            .iter()
-            .find(|w| w.workspace == workspace && w.mapped)
-            .map(|w| w.id);
-
-        if let Some(id) = first_window {
+        if let Some(id) = self.focus_candidate(workspace, None) {
             self.set_focus(id);
         } else {
             self.focused_window = None;
@@ -1449,7 +3603,7 @@ impl State {
     }
 
     pub fn move_window_to_workspace(&mut self, window_id: WindowId, workspace: usize) {
-        if workspace < 1 || workspace > self.workspace_count {
+        if self.workspace(workspace).is_none() {
             return;
         }
 
@@ -1458,6 +3612,157 @@ impl State {
             self.needs_relayout = true;
             self.damage_tracker.mark_full_damage();
         }
+        if workspace != self.active_workspace {
+            self.refocus_away_from(window_id);
+        }
+    }
+
+    fn refocus_away_from(&mut self, window_id: WindowId) {
+        if self.focused_window != Some(window_id) {
+            return;
+        }
+        match self.focus_candidate(self.active_workspace, Some(window_id)) {
+            Some(id) => self.set_focus(id),
+            None => self.focused_window = None,
+        }
+    }
+
+    pub fn move_to_scratchpad(&mut self, window_id: WindowId) {
+        let (fullscreen, maximized) = match self.windows.iter().find(|w| w.id == window_id) {
+            Some(w) => (w.fullscreen, w.maximized),
+            None => return,
+        };
+        if fullscreen {
+            self.set_fullscreen(window_id, false);
+        }
+        if maximized {
+            self.set_maximize(window_id, false);
+        }
+
+        let usable = self.usable_area();
+        if let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) {
+            window.scratchpad = true;
+            window.floating = true;
+            window.workspace = SCRATCHPAD_WORKSPACE;
+            window.geometry.width = usable.width / 2;
+            window.geometry.height = usable.height / 2;
+            window.cache_width = 0;
+            window.cache_height = 0;
+        }
+        log::info!("[scratchpad] Window {} moved to scratchpad", window_id);
+
+        self.refocus_away_from(window_id);
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+    }
+
+    pub fn toggle_scratchpad(&mut self) {
+        let active = self.active_workspace;
+        let is_shown = |w: &Window| w.scratchpad && w.workspace == active;
+
+        let shown = self
+            .focused_window
+            .filter(|id| self.windows.iter().any(|w| w.id == *id && is_shown(w)))
+            .or_else(|| {
+                self.windows
+                    .iter()
+                    .rev()
+                    .find(|w| is_shown(w))
+                    .map(|w| w.id)
+            });
+        if let Some(window_id) = shown {
+            if let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) {
+                window.workspace = SCRATCHPAD_WORKSPACE;
+            }
+            self.refocus_away_from(window_id);
+            self.needs_relayout = true;
+            self.damage_tracker.mark_full_damage();
+            return;
+        }
+
+        let Some(idx) = self
+            .windows
+            .iter()
+            .position(|w| w.scratchpad && w.is_hidden() && w.mapped && !w.minimized)
+        else {
+            return;
+        };
+
+        // shown windows go to the end so the next toggle cycles to another hidden window
+        let mut window = self.windows.remove(idx);
+        let usable = self.usable_area();
+        let width = window.geometry.width.clamp(100, usable.width.max(100));
+        let height = window.geometry.height.clamp(100, usable.height.max(100));
+        window.workspace = active;
+        window.floating = true;
+        window.geometry = Rectangle {
+            x: usable.x + (usable.width - width) / 2,
+            y: usable.y + (usable.height - height) / 2,
+            width,
+            height,
+        };
+        window.needs_redraw = true;
+        let window_id = window.id;
+        self.windows.push(window);
+        self.raise_window(window_id);
+
+        self.set_focus(window_id);
+        self.send_window_configure(window_id);
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+    }
+
+    pub fn minimize_window(&mut self, window_id: WindowId) {
+        let Some(window) = self
+            .windows
+            .iter_mut()
+            .find(|w| w.id == window_id && !w.is_hidden())
+        else {
+            return;
+        };
+        window.minimized = true;
+        window.workspace = SCRATCHPAD_WORKSPACE;
+        self.minimized_windows.push(window_id);
+        self.hidden_windows_changed = true;
+        log::info!("[window] Window {} minimized", window_id);
+
+        if self.insert_marker == Some(window_id) {
+            self.insert_marker = None;
+        }
+        self.refocus_away_from(window_id);
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+    }
+
+    // brings a minimized window back onto the active workspace
+    pub fn restore_window(&mut self, window_id: WindowId) -> bool {
+        let active = self.active_workspace;
+        let Some(window) = self
+            .windows
+            .iter_mut()
+            .find(|w| w.id == window_id && w.minimized)
+        else {
+            return false;
+        };
+        window.minimized = false;
+        window.workspace = active;
+        window.needs_redraw = true;
+        self.minimized_windows.retain(|id| *id != window_id);
+        self.hidden_windows_changed = true;
+        log::info!("[window] Window {} restored", window_id);
+
+        self.set_focus(window_id);
+        self.send_window_configure(window_id);
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+        true
+    }
+
+    pub fn restore_last_minimized(&mut self) -> bool {
+        match self.minimized_windows.last() {
+            Some(&window_id) => self.restore_window(window_id),
+            None => false,
+        }
     }
 
     pub fn swap_window_next(&mut self) {
@@ -1541,9 +3846,80 @@ impl State {
             (current, prev)
         };
 
-        self.windows.swap(current_pos, prev_pos);
-        self.needs_relayout = true;
-        self.damage_tracker.mark_full_damage();
+        self.windows.swap(current_pos, prev_pos);
+        self.needs_relayout = true;
+        self.damage_tracker.mark_full_damage();
+    }
+
+    // tiled windows swap with the nearest tile in that direction, floating ones just move
+    pub fn move_window_direction(&mut self, direction: crate::config::Direction) {
+        use crate::config::Direction;
+
+        let Some(focused_id) = self.focused_window else {
+            return;
+        };
+        let Some(focused) = self.windows.iter().find(|w| w.id == focused_id) else {
+            return;
+        };
+        if focused.fullscreen {
+            return;
+        }
+
+        let (dx, dy) = match direction {
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Next => return self.swap_window_next(),
+            Direction::Prev => return self.swap_window_prev(),
+        };
+
+        if focused.floating {
+            let usable = self.usable_area();
+            if let Some(window) = self.windows.iter_mut().find(|w| w.id == focused_id) {
+                let g = &mut window.geometry;
+                g.x = (g.x + dx * FLOATING_MOVE_STEP)
+                    .clamp(usable.x, (usable.x + usable.width - g.width).max(usable.x));
+                g.y = (g.y + dy * FLOATING_MOVE_STEP).clamp(
+                    usable.y,
+                    (usable.y + usable.height - g.height).max(usable.y),
+                );
+            }
+            self.damage_tracker.mark_full_damage();
+            return;
+        }
+
+        let center = |r: &Rectangle| (r.x + r.width / 2, r.y + r.height / 2);
+        let (fx, fy) = center(&focused.geometry);
+        let neighbor = self
+            .windows
+            .iter()
+            .filter(|w| {
+                w.id != focused_id
+                    && w.workspace == self.active_workspace
+                    && w.mapped
+                    && !w.floating
+                    && !w.fullscreen
+                    && !w.layout_hidden
+            })
+            .filter_map(|w| {
+                let (cx, cy) = center(&w.geometry);
+                let along = (cx - fx) * dx + (cy - fy) * dy;
+                let across = ((cx - fx) * dy).abs() + ((cy - fy) * dx).abs();
+                (along > 0).then_some((w.id, along + across * 2))
+            })
+            .min_by_key(|&(_, distance)| distance)
+            .map(|(id, _)| id);
+
+        if let Some(neighbor_id) = neighbor {
+            let a = self.windows.iter().position(|w| w.id == focused_id);
+            let b = self.windows.iter().position(|w| w.id == neighbor_id);
+            if let (Some(a), Some(b)) = (a, b) {
+                self.windows.swap(a, b);
+                self.needs_relayout = true;
+                self.damage_tracker.mark_full_damage();
+            }
+        }
     }
 
     pub fn toggle_fullscreen(&mut self, window_id: WindowId) {
@@ -1618,17 +3994,14 @@ impl State {
     }
 
     pub fn set_maximize(&mut self, window_id: WindowId, maximized: bool) {
-        let (screen_width, screen_height) = self.screen_size();
+        let usable = self.usable_area();
 
         if let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) {
             if maximized && !window.maximized {
-                window.saved_geometry = Some(window.geometry);
-                window.geometry = Rectangle {
-                    x: 0,
-                    y: 0,
-                    width: screen_width,
-                    height: screen_height,
-                };
+                if !window.fullscreen {
+                    window.saved_geometry = Some(window.geometry);
+                }
+                window.geometry = usable;
                 window.maximized = true;
                 window.fullscreen = false;
             } else if !maximized && window.maximized {
@@ -1638,6 +4011,8 @@ impl State {
                 window.maximized = false;
             }
             window.needs_redraw = true;
+            window.cache_width = 0;
+            window.cache_height = 0;
             self.damage_tracker.mark_full_damage();
         }
 
@@ -1652,6 +4027,30 @@ impl State {
     ) {
         use crate::config::ResizeDirection;
 
+        let active_workspace = self.active_workspace;
+        let tiled: Vec<WindowId> = self
+            .windows
+            .iter()
+            .filter(|w| w.workspace == active_workspace && !w.floating && !w.fullscreen)
+            .map(|w| w.id)
+            .collect();
+        let is_tiled = tiled.contains(&window_id);
+        if is_tiled && tiled.len() == 2 && self.layout() == Layout::Tile {
+            let (screen_width, _) = self.screen_size();
+            let delta = amount as f32 / screen_width.max(1) as f32;
+            let grows_first = match direction {
+                ResizeDirection::Grow | ResizeDirection::Right => tiled[0] == window_id,
+                ResizeDirection::Shrink | ResizeDirection::Left => tiled[0] != window_id,
+                ResizeDirection::Up | ResizeDirection::Down => return,
+            };
+            if let Some(workspace) = self.workspace_mut(active_workspace) {
+                let change = if grows_first { delta } else { -delta };
+                workspace.split_ratio = (workspace.split_ratio + change).clamp(0.1, 0.9);
+            }
+            self.needs_relayout = true;
+            return;
+        }
+
         if let Some(window) = self.windows.iter_mut().find(|w| w.id == window_id) {
             if window.fullscreen || window.maximized {
                 return;
@@ -1692,35 +4091,27 @@ impl State {
     }
 
     fn send_window_configure(&mut self, window_id: WindowId) {
-        let (geometry, xdg_surface, xdg_toplevel) = {
-            let window = match self.windows.iter().find(|w| w.id == window_id) {
-                Some(w) => w,
-                None => return,
-            };
-            (
-                window.geometry,
-                window.xdg_surface.clone(),
-                window.xdg_toplevel.clone(),
-            )
+        let states = self.get_toplevel_states(window_id);
+        let title_bar_height = self.config.title_bar_height();
+        let window = match self.windows.iter_mut().find(|w| w.id == window_id) {
+            Some(w) => w,
+            None => return,
         };
 
-        let states = self.get_toplevel_states(window_id);
-        let serial = self.next_keyboard_serial();
+        let width = window.geometry.width;
+        let client_height =
+            (window.geometry.height - window.decoration_height(title_bar_height)).max(1);
 
-        let title_bar_height = if self
-            .windows
-            .iter()
-            .find(|w| w.id == window_id)
-            .map(|w| w.fullscreen)
-            .unwrap_or(false)
-        {
-            0
-        } else {
-            self.config.title_bar_height()
-        };
+        // skip configures the client already has, so batched relayouts don't burn serials
+        let configure = (width, client_height, states);
+        if window.last_configure.as_ref() == Some(&configure) {
+            return;
+        }
+        let (xdg_surface, xdg_toplevel) = (window.xdg_surface.clone(), window.xdg_toplevel.clone());
+        window.last_configure = Some(configure.clone());
 
-        let client_height = (geometry.height - title_bar_height).max(1);
-        xdg_toplevel.configure(geometry.width, client_height, states);
+        let serial = self.next_keyboard_serial();
+        xdg_toplevel.configure(configure.0, configure.1, configure.2);
         xdg_surface.configure(serial);
     }
 
@@ -1766,8 +4157,21 @@ impl State {
         self.focused_window = Some(window_id);
 
         if let Some(new_win) = self.windows.iter_mut().find(|w| w.id == window_id) {
+            if let Some(workspace) = self
+                .workspaces
+                .iter_mut()
+                .find(|ws| ws.id == new_win.workspace)
+            {
+                workspace.note_focus(window_id);
+            }
             new_win.needs_redraw = true;
             self.damage_tracker.add_damage(new_win.geometry);
+            if new_win.layout_hidden {
+                self.needs_relayout = true;
+            }
+            if new_win.floating {
+                self.raise_window(window_id);
+            }
         }
 
         if let Some(old_id) = old_focused {
@@ -1792,10 +4196,12 @@ impl State {
 
         if let Some((surface, Some(new_client))) = new_window_info {
             let serial = self.next_keyboard_serial();
+            let (depressed, latched, locked, group) = self.keyboard_modifiers;
 
             for keyboard in self.keyboards.iter() {
                 if keyboard.client().as_ref() == Some(&new_client) {
                     keyboard.enter(serial, &surface, vec![]);
+                    keyboard.modifiers(serial, depressed, latched, locked, group);
                     self.keyboard_to_window.insert(keyboard.id(), window_id);
                 }
             }
@@ -1811,10 +4217,12 @@ impl State {
 
         if let Some((surface, Some(client))) = ls_info {
             let serial = self.next_keyboard_serial();
+            let (depressed, latched, locked, group) = self.keyboard_modifiers;
 
             for keyboard in self.keyboards.iter() {
                 if keyboard.client().as_ref() == Some(&client) {
                     keyboard.enter(serial, &surface, vec![]);
+                    keyboard.modifiers(serial, depressed, latched, locked, group);
                     log::debug!("[layer_shell] Sent keyboard.enter to layer surface");
                 }
             }
@@ -1921,8 +4329,19 @@ impl State {
         }
     }
 
+    // What changed in the window's buffer since its texture was last updated.
+    // A refreshed pixel cache without recorded damage uploads everything to
+    // be safe.
+    pub fn take_window_damage(&mut self, window_id: WindowId, refreshed: bool) -> BufferDamage {
+        match self.windows.iter_mut().find(|w| w.id == window_id) {
+            Some(window) if !window.buffer_damage.is_empty() => window.buffer_damage.take(),
+            _ if refreshed => BufferDamage::full(),
+            _ => BufferDamage::default(),
+        }
+    }
+
     pub fn update_window_pixel_cache(&mut self, window_id: WindowId) -> bool {
-        let (buffer_id, buf_width, buf_height, expected_width, expected_height) = {
+        let (buffer_id, buf_width, buf_height, expected_width, expected_height, transform) = {
             let window = match self.windows.iter().find(|w| w.id == window_id) {
                 Some(w) => w,
                 None => return false,
@@ -1937,7 +4356,7 @@ impl State {
                 None => return false,
             };
             let expected_w = window.geometry.width;
-            let title_bar_height = self.config.title_bar_height();
+            let title_bar_height = window.decoration_height(self.config.title_bar_height());
             let expected_h = (window.geometry.height - title_bar_height).max(1);
             (
                 buffer_id,
@@ -1945,13 +4364,19 @@ impl State {
                 buffer_data.height as usize,
                 expected_w,
                 expected_h,
+                window.buffer_transform,
             )
         };
 
         let min_width = (expected_width / 2).max(10) as usize;
         let min_height = (expected_height / 2).max(10) as usize;
+        let (upright_width, upright_height) = if transform.swaps_axes() {
+            (buf_height, buf_width)
+        } else {
+            (buf_width, buf_height)
+        };
 
-        if buf_width < min_width || buf_height < min_height {
+        if upright_width < min_width || upright_height < min_height {
             return false;
         }
 
@@ -1989,9 +4414,8 @@ impl State {
             None => return false,
         };
 
-        let stride_pixels = (buffer_data.stride / 4) as usize;
-        let pixel_count = stride_pixels * buf_height;
-        let byte_count = pixel_count * 4;
+        let stride = buffer_data.stride as usize;
+        let byte_count = stride * buf_height;
         let end_offset = buffer_data.offset as usize + byte_count;
 
         if end_offset > pool_data.size as usize {
@@ -2003,30 +4427,65 @@ impl State {
             );
             return false;
         }
+        if stride < buf_width * shm_bytes_per_pixel(buffer_data.format) {
+            log::warn!(
+                "[cache] Buffer stride {} too small for width {}",
+                stride,
+                buf_width
+            );
+            return false;
+        }
 
         let window = match self.windows.iter_mut().find(|w| w.id == window_id) {
             Some(w) => w,
             None => return false,
         };
 
-        if window.pixel_cache.len() < pixel_count {
-            window.pixel_cache.resize(pixel_count, 0);
-        }
-
-        unsafe {
-            let src = mmap_ptr.as_ptr().add(buffer_data.offset as usize) as *const u32;
-            std::ptr::copy_nonoverlapping(src, window.pixel_cache.as_mut_ptr(), pixel_count);
+        let src = unsafe {
+            std::slice::from_raw_parts(
+                mmap_ptr.as_ptr().add(buffer_data.offset as usize),
+                byte_count,
+            )
+        };
+        // the GL path samples 10-bit buffers as is, the CPU canvas is 8-bit
+        let keep_deep = self.gpu_renderer.is_some();
+        // a turned buffer is staged and copied upright into the cache
+        let staging = match transform {
+            OutputTransform::Normal => &mut window.pixel_cache,
+            _ => &mut self.transform_staging,
+        };
+        let (mut cache_stride, deep_color) = copy_shm_pixels(
+            src,
+            stride,
+            buf_width,
+            buf_height,
+            buffer_data.format,
+            keep_deep,
+            staging,
+        );
+        if transform != OutputTransform::Normal {
+            untransform_pixels(
+                &self.transform_staging,
+                cache_stride,
+                buf_width,
+                buf_height,
+                transform,
+                &mut window.pixel_cache,
+            );
+            cache_stride = upright_width;
         }
 
-        window.cache_width = buf_width;
-        window.cache_height = buf_height;
-        window.cache_stride = stride_pixels;
+        window.cache_deep_color = deep_color;
+        window.cache_opaque = shm_format_opaque(buffer_data.format);
+        window.cache_width = upright_width;
+        window.cache_height = upright_height;
+        window.cache_stride = cache_stride;
 
         true
     }
 
     pub fn update_layer_surface_pixel_cache(&mut self, layer_surface_id: LayerSurfaceId) -> bool {
-        let (buffer_id, buf_width, buf_height) = {
+        let (buffer_id, buf_width, buf_height, transform) = {
             let ls = match self
                 .layer_surfaces
                 .iter()
@@ -2048,6 +4507,7 @@ impl State {
                 buffer_id,
                 buffer_data.width as usize,
                 buffer_data.height as usize,
+                ls.buffer_transform,
             )
         };
 
@@ -2085,9 +4545,8 @@ impl State {
             None => return false,
         };
 
-        let stride_pixels = (buffer_data.stride / 4) as usize;
-        let pixel_count = stride_pixels * buf_height;
-        let byte_count = pixel_count * 4;
+        let stride = buffer_data.stride as usize;
+        let byte_count = stride * buf_height;
         let end_offset = buffer_data.offset as usize + byte_count;
 
         if end_offset > pool_data.size as usize {
@@ -2097,6 +4556,9 @@ impl State {
             );
             return false;
         }
+        if stride < buf_width * shm_bytes_per_pixel(buffer_data.format) {
+            return false;
+        }
 
         let ls = match self
             .layer_surfaces
@@ -2107,18 +4569,45 @@ impl State {
             None => return false,
         };
 
-        if ls.pixel_cache.len() < pixel_count {
-            ls.pixel_cache.resize(pixel_count, 0);
-        }
-
-        unsafe {
-            let src = mmap_ptr.as_ptr().add(buffer_data.offset as usize) as *const u32;
-            std::ptr::copy_nonoverlapping(src, ls.pixel_cache.as_mut_ptr(), pixel_count);
+        let src = unsafe {
+            std::slice::from_raw_parts(
+                mmap_ptr.as_ptr().add(buffer_data.offset as usize),
+                byte_count,
+            )
+        };
+        let staging = match transform {
+            OutputTransform::Normal => &mut ls.pixel_cache,
+            _ => &mut self.transform_staging,
+        };
+        let (mut cache_stride, _) = copy_shm_pixels(
+            src,
+            stride,
+            buf_width,
+            buf_height,
+            buffer_data.format,
+            false,
+            staging,
+        );
+        let (mut cache_width, mut cache_height) = (buf_width, buf_height);
+        if transform != OutputTransform::Normal {
+            untransform_pixels(
+                &self.transform_staging,
+                cache_stride,
+                buf_width,
+                buf_height,
+                transform,
+                &mut ls.pixel_cache,
+            );
+            if transform.swaps_axes() {
+                (cache_width, cache_height) = (buf_height, buf_width);
+            }
+            cache_stride = cache_width;
         }
 
-        ls.cache_width = buf_width;
-        ls.cache_height = buf_height;
-        ls.cache_stride = stride_pixels;
+        ls.cache_opaque = shm_format_opaque(buffer_data.format);
+        ls.cache_width = cache_width;
+        ls.cache_height = cache_height;
+        ls.cache_stride = cache_stride;
 
         true
     }
@@ -2163,38 +4652,137 @@ impl State {
     }
 
     pub fn window_at(&self, x: f64, y: f64) -> Option<WindowId> {
-        let title_bar_height = self.config.title_bar_height();
-        for window in self.windows.iter().rev() {
-            if !window.mapped {
-                continue;
+        self.windows
+            .iter()
+            .filter(|w| {
+                w.mapped
+                    && !w.layout_hidden
+                    && w.workspace == self.active_workspace
+                    && x >= w.geometry.x as f64
+                    && x < (w.geometry.x + w.geometry.width) as f64
+                    && y >= w.geometry.y as f64
+                    && y < (w.geometry.y + w.geometry.height) as f64
+            })
+            .max_by_key(|w| self.stack_key(w))
+            .map(|w| w.id)
+    }
+
+    pub fn layer_surface_at(&self, x: f64, y: f64) -> Option<LayerSurfaceId> {
+        self.layer_surfaces
+            .iter()
+            .rev()
+            .filter(|ls| ls.mapped && ls.layer >= Layer::Top)
+            .find(|ls| {
+                let g = ls.geometry;
+                x >= g.x as f64
+                    && x < (g.x + g.width) as f64
+                    && y >= g.y as f64
+                    && y < (g.y + g.height) as f64
+            })
+            .map(|ls| ls.id)
+    }
+
+    // panels on the top and overlay layers take the pointer before any window
+    fn update_layer_pointer_focus(&mut self, x: f64, y: f64) -> bool {
+        let layer_id = self.layer_surface_at(x, y);
+
+        if layer_id != self.pointer_layer_focus {
+            let serial = self.next_pointer_serial();
+            if let Some(old) = self
+                .pointer_layer_focus
+                .and_then(|id| self.layer_surfaces.iter().find(|ls| ls.id == id))
+            {
+                let client = old.wl_surface.client();
+                for pointer in self.pointers.iter().filter(|p| p.client() == client) {
+                    pointer.leave(serial, &old.wl_surface);
+                }
             }
-            let g = window.geometry;
-            let content_y = g.y + title_bar_height;
-            if x >= g.x as f64
-                && x < (g.x + g.width) as f64
-                && y >= g.y as f64
-                && y < (content_y + g.height - title_bar_height) as f64
+            if let Some(new) =
+                layer_id.and_then(|id| self.layer_surfaces.iter().find(|ls| ls.id == id))
             {
-                return Some(window.id);
+                let client = new.wl_surface.client();
+                let (local_x, local_y) = surface_local((new.geometry.x, new.geometry.y), x, y);
+                for pointer in self.pointers.iter().filter(|p| p.client() == client) {
+                    pointer.enter(serial, &new.wl_surface, local_x, local_y);
+                }
+            }
+            self.pointer_layer_focus = layer_id;
+        } else if let Some(ls) =
+            layer_id.and_then(|id| self.layer_surfaces.iter().find(|ls| ls.id == id))
+        {
+            let client = ls.wl_surface.client();
+            let (local_x, local_y) = surface_local((ls.geometry.x, ls.geometry.y), x, y);
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u32;
+            for pointer in self.pointers.iter().filter(|p| p.client() == client) {
+                pointer.motion(time, local_x, local_y);
             }
         }
-        None
+
+        layer_id.is_some()
     }
 
-    pub fn handle_pointer_motion(&mut self, x: f64, y: f64) {
+    // where the cursor is drawn, without telling any client
+    pub fn move_cursor(&mut self, x: f64, y: f64) {
         let old_x = self.cursor_x;
         let old_y = self.cursor_y;
         self.cursor_x = x as i32;
         self.cursor_y = y as i32;
         self.pointer_x = x;
         self.pointer_y = y;
+        self.last_pointer_activity = std::time::Instant::now();
+        self.set_cursor_visible(true);
 
         if self.cursor_visible && (old_x != self.cursor_x || old_y != self.cursor_y) {
             self.last_cursor_pos = (old_x, old_y);
             self.damage_tracker.add_cursor_damage();
         }
+    }
+
+    pub fn handle_pointer_motion(&mut self, x: f64, y: f64) {
+        let (old_x, old_y) = (self.cursor_x, self.cursor_y);
+        self.move_cursor(x, y);
+
+        if self.split_drag.is_some() {
+            self.update_split_drag(x);
+            return;
+        }
+        if self.move_drag.is_some() {
+            self.update_move_drag(x, y);
+            return;
+        }
+        if self.resize_drag.is_some() {
+            self.update_resize_drag(x, y);
+            return;
+        }
+        if self.pointer_grab.is_some() {
+            return;
+        }
+
+        if let Some(RegionSelect::Dragging {
+            x: start_x,
+            y: start_y,
+        }) = self.region_select
+        {
+            let corner = (start_x, start_y);
+            self.damage_tracker
+                .add_damage(span_rect(corner, (old_x, old_y)));
+            self.damage_tracker
+                .add_damage(span_rect(corner, (self.cursor_x, self.cursor_y)));
+            return;
+        }
+
+        self.update_pointer_focus(x, y);
+    }
 
-        let window_id = self.window_at(x, y);
+    fn update_pointer_focus(&mut self, x: f64, y: f64) {
+        let window_id = if self.update_layer_pointer_focus(x, y) {
+            None
+        } else {
+            self.window_at(x, y)
+        };
         let title_bar_height = self.config.title_bar_height();
 
         if window_id != self.pointer_focus {
@@ -2214,9 +4802,8 @@ impl State {
             if let Some(new_id) = window_id {
                 if let Some(new_window) = self.windows.iter().find(|w| w.id == new_id) {
                     let new_client = new_window.wl_surface.client();
-                    let g = new_window.geometry;
-                    let local_x = x - g.x as f64;
-                    let local_y = y - (g.y + title_bar_height) as f64;
+                    let (local_x, local_y) =
+                        surface_local(new_window.surface_origin(title_bar_height), x, y);
 
                     for pointer in &self.pointers {
                         if pointer.client() == new_client {
@@ -2230,9 +4817,8 @@ impl State {
         } else if let Some(win_id) = window_id {
             if let Some(window) = self.windows.iter().find(|w| w.id == win_id) {
                 let client = window.wl_surface.client();
-                let g = window.geometry;
-                let local_x = x - g.x as f64;
-                let local_y = y - (g.y + title_bar_height) as f64;
+                let (local_x, local_y) =
+                    surface_local(window.surface_origin(title_bar_height), x, y);
                 let time = std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
@@ -2245,6 +4831,49 @@ impl State {
                 }
             }
         }
+
+        self.pointer_local = self.pointer_surface_local(x, y);
+    }
+
+    fn pointer_surface_local(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        if let Some(ls) = self
+            .pointer_layer_focus
+            .and_then(|id| self.layer_surfaces.iter().find(|ls| ls.id == id))
+        {
+            return Some(surface_local((ls.geometry.x, ls.geometry.y), x, y));
+        }
+        let window = self
+            .pointer_focus
+            .and_then(|id| self.windows.iter().find(|w| w.id == id))?;
+        let title_bar_height = self.config.title_bar_height();
+        Some(surface_local(window.surface_origin(title_bar_height), x, y))
+    }
+
+    // relayout can move surfaces under a cursor that hasn't moved; resend
+    // leave/enter or motion so clients don't keep stale local coordinates
+    pub fn refresh_pointer_focus(&mut self) {
+        if self.split_drag.is_some()
+            || self.move_drag.is_some()
+            || self.resize_drag.is_some()
+            || self.region_select.is_some()
+            || self.pointer_grab.is_some()
+        {
+            return;
+        }
+        let (x, y) = (self.pointer_x, self.pointer_y);
+        let layer_id = self.layer_surface_at(x, y);
+        let window_id = if layer_id.is_some() {
+            None
+        } else {
+            self.window_at(x, y)
+        };
+        if layer_id == self.pointer_layer_focus
+            && window_id == self.pointer_focus
+            && self.pointer_surface_local(x, y) == self.pointer_local
+        {
+            return;
+        }
+        self.update_pointer_focus(x, y);
     }
 
     pub fn handle_pointer_button(&mut self, button: u32, pressed: bool) {
@@ -2259,12 +4888,80 @@ impl State {
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_millis() as u32;
+        self.last_pointer_activity = std::time::Instant::now();
+
+        if let Some(grab_button) = self.pointer_grab {
+            if !pressed && button == grab_button {
+                self.end_pointer_grab();
+            }
+            return;
+        }
+
+        if self.region_select.is_some() {
+            self.handle_region_select_button(button, pressed);
+            return;
+        }
+
+        if let Some(ls) = self
+            .pointer_layer_focus
+            .and_then(|id| self.layer_surfaces.iter().find(|ls| ls.id == id))
+        {
+            let client = ls.wl_surface.client();
+            for pointer in self.pointers.iter().filter(|p| p.client() == client) {
+                pointer.button(serial, time, button, state);
+            }
+            return;
+        }
+
+        if self.split_drag.is_some() {
+            if !pressed && button == BTN_LEFT {
+                self.end_split_drag();
+            }
+            return;
+        }
+        if self.move_drag.is_some() {
+            if !pressed && button == BTN_LEFT {
+                self.move_drag = None;
+            }
+            return;
+        }
+        if pressed && button == BTN_LEFT && self.begin_split_drag(self.pointer_x) {
+            return;
+        }
 
         if pressed {
+            let (x, y) = (self.pointer_x, self.pointer_y);
+            let tab = self
+                .tabs()
+                .into_iter()
+                .find(|(_, r)| {
+                    x >= r.x as f64
+                        && x < (r.x + r.width) as f64
+                        && y >= r.y as f64
+                        && y < (r.y + r.height) as f64
+                })
+                .map(|(id, _)| id);
+            if let Some(tab_id) = tab {
+                self.set_focus(tab_id);
+                return;
+            }
+
+            if let Some((win_id, title_button)) = self.title_button_at(x, y) {
+                match title_button {
+                    TitleButton::Close => self.close_window(win_id),
+                    TitleButton::Maximize => self.toggle_maximize(win_id),
+                }
+                return;
+            }
+
             if let Some(win_id) = self.pointer_focus {
                 if self.focused_window != Some(win_id) {
                     self.set_focus(win_id);
                 }
+                self.raise_window(win_id);
+                if button == BTN_LEFT && self.begin_move_drag(win_id, x, y) {
+                    return;
+                }
             }
         }
 
@@ -2280,8 +4977,25 @@ impl State {
         }
     }
 
-    pub fn handle_pointer_axis(&mut self, horizontal: f64, vertical: f64) {
-        use wayland_server::protocol::wl_pointer::Axis;
+    fn title_button_at(&self, x: f64, y: f64) -> Option<(WindowId, TitleButton)> {
+        let window = self
+            .pointer_focus
+            .and_then(|id| self.windows.iter().find(|w| w.id == id))?;
+        window
+            .title_buttons(self.config.title_bar_height())
+            .into_iter()
+            .find(|(_, rect)| rect.contains(x, y))
+            .map(|(button, _)| (window.id, button))
+    }
+
+    // Wheels also get their clicks, as axis_value120 or axis_discrete
+    // depending on the client's version, ahead of the axis event
+    pub fn handle_pointer_axis(&mut self, scroll: &PointerState) {
+        use wayland_server::protocol::wl_pointer::{Axis, AxisSource};
+
+        if self.pointer_grab.is_some() {
+            return;
+        }
 
         let time = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -2291,29 +5005,276 @@ impl State {
         if let Some(win_id) = self.pointer_focus {
             if let Some(window) = self.windows.iter().find(|w| w.id == win_id) {
                 let client = window.wl_surface.client();
+                let source = scroll.scroll_source.map(|source| match source {
+                    ScrollSource::Wheel => AxisSource::Wheel,
+                    ScrollSource::Finger => AxisSource::Finger,
+                    ScrollSource::Continuous => AxisSource::Continuous,
+                });
+                let axes = [
+                    (
+                        Axis::VerticalScroll,
+                        scroll.scroll_vertical,
+                        scroll.scroll_v120.1,
+                        scroll.scroll_discrete.1,
+                        scroll.scroll_stop.1,
+                    ),
+                    (
+                        Axis::HorizontalScroll,
+                        scroll.scroll_horizontal,
+                        scroll.scroll_v120.0,
+                        scroll.scroll_discrete.0,
+                        scroll.scroll_stop.0,
+                    ),
+                ];
                 for pointer in &self.pointers {
-                    if pointer.client() == client {
-                        if vertical.abs() > 0.0 {
-                            pointer.axis(time, Axis::VerticalScroll, vertical);
-                        }
-                        if horizontal.abs() > 0.0 {
-                            pointer.axis(time, Axis::HorizontalScroll, horizontal);
-                        }
-                        if pointer.version() >= 5 {
-                            pointer.frame();
+                    if pointer.client() != client {
+                        continue;
+                    }
+                    let version = pointer.version();
+                    if let Some(source) = source.filter(|_| version >= 5) {
+                        pointer.axis_source(source);
+                    }
+                    for &(axis, value, v120, discrete, stop) in &axes {
+                        if value != 0.0 {
+                            if version >= 8 && v120 != 0.0 {
+                                pointer.axis_value120(axis, v120.round() as i32);
+                            } else if (5..8).contains(&version) && discrete != 0 {
+                                pointer.axis_discrete(axis, discrete);
+                            }
+                            pointer.axis(time, axis, value);
+                        } else if stop && version >= 5 {
+                            pointer.axis_stop(time, axis);
                         }
                     }
+                    if version >= 5 {
+                        pointer.frame();
+                    }
                 }
             }
         }
     }
 }
 
+fn shm_bytes_per_pixel(format: u32) -> usize {
+    if format == wl_shm::Format::Rgb565 as u32 {
+        2
+    } else {
+        4
+    }
+}
+
+// the alpha bits of these are padding and may hold anything
+fn shm_format_opaque(format: u32) -> bool {
+    [
+        wl_shm::Format::Xrgb8888,
+        wl_shm::Format::Xbgr8888,
+        wl_shm::Format::Rgb565,
+        wl_shm::Format::Xrgb2101010,
+        wl_shm::Format::Xbgr2101010,
+    ]
+    .iter()
+    .any(|&f| f as u32 == format)
+}
+
+// Copies a shm buffer into cache as ARGB8888, or as ABGR2101010 for 10-bit
+// buffers when keep_deep. RGB565 rows are widened to 32 bits, so the cache
+// stride isn't always the buffer's. Returns the cache stride in pixels and
+// whether the result is 10-bit.
+fn copy_shm_pixels(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    format: u32,
+    keep_deep: bool,
+    cache: &mut Vec<u32>,
+) -> (usize, bool) {
+    if format == wl_shm::Format::Rgb565 as u32 {
+        cache.resize(width * height, 0);
+        for (row, out) in src.chunks(stride).zip(cache.chunks_exact_mut(width)) {
+            for (pixel, bytes) in out.iter_mut().zip(row.chunks_exact(2)) {
+                *pixel = rgb565_to_argb(u16::from_le_bytes([bytes[0], bytes[1]]));
+            }
+        }
+        return (width, false);
+    }
+
+    let stride_pixels = stride / 4;
+    let pixel_count = stride_pixels * height;
+    if cache.len() < pixel_count {
+        cache.resize(pixel_count, 0);
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(src.as_ptr(), cache.as_mut_ptr() as *mut u8, pixel_count * 4);
+    }
+    let pixels = &mut cache[..pixel_count];
+    if format == wl_shm::Format::Abgr8888 as u32 || format == wl_shm::Format::Xbgr8888 as u32 {
+        for p in pixels.iter_mut() {
+            *p = (*p & 0xFF00FF00) | ((*p >> 16) & 0xFF) | ((*p & 0xFF) << 16);
+        }
+        return (stride_pixels, false);
+    }
+    (stride_pixels, convert_deep_color(pixels, format, keep_deep))
+}
+
+// Turns a buffer the client drew with set_buffer_transform back upright,
+// packed with the upright width as its stride. The transform maps surface
+// pixels to buffer pixels the same way an output's maps logical pixels to
+// the framebuffer.
+fn untransform_pixels(
+    pixels: &[u32],
+    stride: usize,
+    width: usize,
+    height: usize,
+    transform: OutputTransform,
+    upright: &mut Vec<u32>,
+) {
+    let (w, h) = if transform.swaps_axes() {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    // refilled in place, so same-sized commits don't allocate
+    upright.clear();
+    for y in 0..h {
+        for x in 0..w {
+            let (bx, by) = transform.map_pixel(x, y, w, h);
+            upright.push(pixels[by * stride + bx]);
+        }
+    }
+}
+
+// 5/6-bit channels are widened by repeating their top bits, so white stays
+// white
+fn rgb565_to_argb(p: u16) -> u32 {
+    let (r, g, b) = (
+        (p >> 11) as u32,
+        ((p >> 5) & 0x3f) as u32,
+        (p & 0x1f) as u32,
+    );
+    let (r, g, b) = (
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    );
+    0xFF000000 | (r << 16) | (g << 8) | b
+}
+
+// Rewrites 10-bit shm pixels either as ABGR2101010, the only 10-bit layout
+// GLES uploads, or down to ARGB8888. Returns whether the result is 10-bit.
+fn convert_deep_color(pixels: &mut [u32], format: u32, keep_deep: bool) -> bool {
+    let (swap_rb, opaque) = match format {
+        f if f == wl_shm::Format::Abgr2101010 as u32 => (false, false),
+        f if f == wl_shm::Format::Xbgr2101010 as u32 => (false, true),
+        f if f == wl_shm::Format::Argb2101010 as u32 => (true, false),
+        f if f == wl_shm::Format::Xrgb2101010 as u32 => (true, true),
+        _ => return false,
+    };
+
+    for p in pixels.iter_mut() {
+        let a = if opaque { 3 } else { *p >> 30 };
+        let (hi, g, lo) = ((*p >> 20) & 0x3ff, (*p >> 10) & 0x3ff, *p & 0x3ff);
+        let (r, b) = if swap_rb { (hi, lo) } else { (lo, hi) };
+        *p = if keep_deep {
+            (a << 30) | (b << 20) | (g << 10) | r
+        } else {
+            ((a * 0x55) << 24) | ((r >> 2) << 16) | ((g >> 2) << 8) | (b >> 2)
+        };
+    }
+    keep_deep
+}
+
+fn slots_fit(
+    num_windows: usize,
+    screen_width: i32,
+    screen_height: i32,
+    split_ratio: f32,
+    min_width: i32,
+    min_height: i32,
+) -> bool {
+    (0..num_windows).all(|i| {
+        let slot =
+            calculate_tiling_geometry(i, num_windows, screen_width, screen_height, split_ratio);
+        slot.width >= min_width && slot.height >= min_height
+    })
+}
+
+// grid of minimum-size cells, scrolled vertically by whole rows
+fn calculate_scrolled_geometry(
+    index: usize,
+    num_windows: usize,
+    screen_width: i32,
+    screen_height: i32,
+    (min_width, min_height): (i32, i32),
+    scroll_row: i32,
+) -> Rectangle {
+    let cols = (screen_width / min_width).clamp(1, num_windows.max(1) as i32);
+    let visible_rows = (screen_height / min_height).max(1);
+    let cell_width = screen_width / cols;
+    let cell_height = screen_height / visible_rows;
+    let col = index as i32 % cols;
+    let row = index as i32 / cols;
+
+    Rectangle {
+        x: col * cell_width,
+        y: (row - scroll_row) * cell_height,
+        width: cell_width,
+        height: cell_height,
+    }
+}
+
+// 128 bits as hex, from getrandom or failing that /dev/urandom
+fn random_token() -> Option<String> {
+    let mut bytes = [0u8; 16];
+    let read = unsafe { libc::getrandom(bytes.as_mut_ptr() as *mut libc::c_void, bytes.len(), 0) };
+    if read != bytes.len() as isize {
+        use std::io::Read;
+        let urandom =
+            std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes));
+        if let Err(e) = urandom {
+            log::warn!("[activation] No random bytes for a token: {}", e);
+            return None;
+        }
+    }
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn parent_pid(pid: i32) -> Option<i32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name can contain spaces and parens, so skip past the last ')'
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+// rectangle between two corners, in either order
+fn span_rect(a: (i32, i32), b: (i32, i32)) -> Rectangle {
+    Rectangle {
+        x: a.0.min(b.0),
+        y: a.1.min(b.1),
+        width: (a.0 - b.0).abs(),
+        height: (a.1 - b.1).abs(),
+    }
+}
+
+fn apply_gap(rect: Rectangle, gap: i32) -> Rectangle {
+    let inset = gap / 2;
+    if inset <= 0 || rect.width <= inset * 2 || rect.height <= inset * 2 {
+        return rect;
+    }
+    Rectangle {
+        x: rect.x + inset,
+        y: rect.y + inset,
+        width: rect.width - inset * 2,
+        height: rect.height - inset * 2,
+    }
+}
+
 fn calculate_tiling_geometry(
     index: usize,
     num_windows: usize,
     screen_width: i32,
     screen_height: i32,
+    split_ratio: f32,
 ) -> Rectangle {
     if num_windows == 0 {
         return Rectangle {
@@ -2334,7 +5295,7 @@ fn calculate_tiling_geometry(
     }
 
     if num_windows == 2 {
-        let half = screen_width / 2;
+        let half = (screen_width as f32 * split_ratio.clamp(0.1, 0.9)) as i32;
         if index == 0 {
             Rectangle {
                 x: 0,
@@ -2378,3 +5339,16 @@ fn calculate_tiling_geometry(
         }
     }
 }
+
+// formats whose alpha, if any, is padding
+fn fourcc_opaque(format: u32) -> bool {
+    use drm_fourcc::DrmFourcc;
+    const OPAQUE: [DrmFourcc; 5] = [
+        DrmFourcc::Xrgb8888,
+        DrmFourcc::Xbgr8888,
+        DrmFourcc::Xrgb2101010,
+        DrmFourcc::Xbgr2101010,
+        DrmFourcc::Rgb565,
+    ];
+    OPAQUE.iter().any(|&f| f as u32 == format) || crate::renderer::is_yuv_format(format)
+}
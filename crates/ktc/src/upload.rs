@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+
+use smithay::backend::allocator::format::has_alpha;
+use smithay::backend::allocator::Fourcc;
+use smithay::backend::egl::{EGLContext, EGLDisplay};
+use smithay::backend::renderer::gles::format::fourcc_to_gl_formats;
+use smithay::backend::renderer::gles::{ffi, GlesRenderer, GlesTexture};
+use smithay::utils::{Buffer as BufferCoord, Size};
+
+struct UploadJob {
+    id: u64,
+    serial: u64,
+    width: u32,
+    height: u32,
+    format: Fourcc,
+    data: Vec<u8>,
+}
+
+// A bare GL texture name; the main renderer takes ownership of it so it is
+// deleted there, not queued for the worker's context.
+struct RawTexture {
+    name: ffi::types::GLuint,
+    internal: ffi::types::GLenum,
+    opaque: bool,
+    size: Size<i32, BufferCoord>,
+}
+
+struct UploadDone {
+    id: u64,
+    serial: u64,
+    // None when the upload failed
+    texture: Option<RawTexture>,
+}
+
+// Uploads shm contents on a worker thread with its own GL context sharing
+// textures with the renderer's, so big commits don't stall the main loop.
+pub struct TextureUploader {
+    jobs: Option<Sender<UploadJob>>,
+    done: Receiver<UploadDone>,
+    // newest serial submitted per texture; anything older is stale on arrival
+    pending: HashMap<u64, u64>,
+    next_serial: u64,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl TextureUploader {
+    pub fn new(display: &EGLDisplay, share: &EGLContext) -> Result<Self, String> {
+        let context = EGLContext::new_shared(display, share)
+            .map_err(|e| format!("shared EGL context failed: {:?}", e))?;
+
+        let (jobs, job_rx) = mpsc::channel::<UploadJob>();
+        let (done_tx, done) = mpsc::channel();
+
+        let worker = std::thread::Builder::new()
+            .name("ktc-upload".to_string())
+            .spawn(move || {
+                let mut renderer = match unsafe { GlesRenderer::new(context) } {
+                    Ok(r) => r,
+                    Err(e) => {
+                        log::error!("[upload] Worker renderer failed: {:?}", e);
+                        return;
+                    }
+                };
+
+                while let Ok(job) = job_rx.recv() {
+                    // a client drawing faster than uploads finish only needs
+                    // its newest contents, older queued jobs would be stale
+                    let mut batch = vec![job];
+                    while let Ok(job) = job_rx.try_recv() {
+                        match batch.iter_mut().find(|queued| queued.id == job.id) {
+                            Some(queued) => *queued = job,
+                            None => batch.push(job),
+                        }
+                    }
+
+                    for job in batch {
+                        let texture = match renderer.with_context(|gl| upload(gl, &job)) {
+                            Ok(Ok(texture)) => Some(texture),
+                            Ok(Err(e)) => {
+                                log::warn!("[upload] Texture {} upload failed: {}", job.id, e);
+                                None
+                            }
+                            Err(e) => {
+                                log::warn!("[upload] Texture {} upload failed: {:?}", job.id, e);
+                                None
+                            }
+                        };
+                        let done = UploadDone {
+                            id: job.id,
+                            serial: job.serial,
+                            texture,
+                        };
+                        if done_tx.send(done).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+            .map_err(|e| format!("failed to spawn upload thread: {}", e))?;
+
+        Ok(Self {
+            jobs: Some(jobs),
+            done,
+            pending: HashMap::new(),
+            next_serial: 0,
+            worker: Some(worker),
+        })
+    }
+
+    pub fn submit(
+        &mut self,
+        id: u64,
+        width: u32,
+        height: u32,
+        format: Fourcc,
+        data: &[u8],
+    ) -> bool {
+        let Some(ref jobs) = self.jobs else {
+            return false;
+        };
+        self.next_serial += 1;
+        let job = UploadJob {
+            id,
+            serial: self.next_serial,
+            width,
+            height,
+            format,
+            data: data.to_vec(),
+        };
+        if jobs.send(job).is_err() {
+            log::warn!("[upload] Worker gone, uploading on the main thread");
+            self.jobs = None;
+            return false;
+        }
+        self.pending.insert(id, self.next_serial);
+        true
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn is_pending(&self, id: u64) -> bool {
+        self.pending.contains_key(&id)
+    }
+
+    pub fn cancel(&mut self, id: u64) {
+        self.pending.remove(&id);
+    }
+
+    // Finished uploads that are still the newest for their texture. Every
+    // texture is handed to renderer, so stale ones are deleted by it too; a
+    // failed upload comes back as None and the old texture stays.
+    pub fn collect(&mut self, renderer: &GlesRenderer) -> Vec<(u64, Option<GlesTexture>)> {
+        let mut finished = Vec::new();
+        while let Ok(done) = self.done.try_recv() {
+            let texture = done.texture.map(|raw| unsafe {
+                GlesTexture::from_raw(renderer, Some(raw.internal), raw.opaque, raw.name, raw.size)
+            });
+            if self.pending.get(&done.id) == Some(&done.serial) {
+                self.pending.remove(&done.id);
+                finished.push((done.id, texture));
+            }
+        }
+        finished
+    }
+}
+
+// Creates and fills a texture the way GlesRenderer::import_memory does, but
+// without wrapping it, so the worker's renderer never owns it.
+fn upload(gl: &ffi::Gles2, job: &UploadJob) -> Result<RawTexture, String> {
+    let (mut internal, format, layout) = fourcc_to_gl_formats(job.format)
+        .ok_or_else(|| format!("unsupported format {:?}", job.format))?;
+    if job.data.len() < job.width as usize * job.height as usize * 4 {
+        return Err("short buffer".to_string());
+    }
+
+    unsafe {
+        // GLES 2 has no MAJOR_VERSION query and no sized formats
+        let mut major = 0;
+        gl.GetIntegerv(ffi::MAJOR_VERSION, &mut major);
+        while gl.GetError() != ffi::NO_ERROR {}
+        if major < 3 {
+            internal = match internal {
+                ffi::RGBA8 => ffi::RGBA,
+                ffi::BGRA_EXT => ffi::BGRA_EXT,
+                _ => return Err(format!("{:?} needs GLES 3", job.format)),
+            };
+        }
+
+        let mut name = 0;
+        gl.GenTextures(1, &mut name);
+        gl.BindTexture(ffi::TEXTURE_2D, name);
+        let clamp = ffi::CLAMP_TO_EDGE as i32;
+        gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_S, clamp);
+        gl.TexParameteri(ffi::TEXTURE_2D, ffi::TEXTURE_WRAP_T, clamp);
+        gl.TexImage2D(
+            ffi::TEXTURE_2D,
+            0,
+            internal as i32,
+            job.width as i32,
+            job.height as i32,
+            0,
+            format,
+            layout,
+            job.data.as_ptr() as *const _,
+        );
+        gl.BindTexture(ffi::TEXTURE_2D, 0);
+        // the main context samples it as soon as it arrives
+        gl.Finish();
+
+        Ok(RawTexture {
+            name,
+            internal,
+            opaque: !has_alpha(job.format),
+            size: Size::from((job.width as i32, job.height as i32)),
+        })
+    }
+}
+
+impl Drop for TextureUploader {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
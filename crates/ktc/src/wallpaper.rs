@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use calloop::channel::Sender;
+use ktc_common::{jpeg, png};
+
+use crate::config::{wallpaper_mode, WallpaperMode};
+use crate::state::State;
+
+// What the primary output should show: the file, how it is laid out and the
+// size and letterbox color it gets composed at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub path: PathBuf,
+    pub mode: WallpaperMode,
+    pub width: u32,
+    pub height: u32,
+    pub background: u32,
+}
+
+// A decoded image already composed at the output's size, so both renderers
+// copy it 1:1: the CPU one into the canvas, the GPU one as a single texture
+pub struct Wallpaper {
+    pub serial: u64,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>,
+}
+
+pub struct Loaded {
+    serial: u64,
+    path: PathBuf,
+    result: Result<Wallpaper, String>,
+}
+
+#[derive(Default)]
+pub struct WallpaperState {
+    pub current: Option<Wallpaper>,
+    // set over IPC by output name, until the next config reload
+    overrides: HashMap<String, (PathBuf, WallpaperMode)>,
+    // the last request handed to a loader; results for older ones are stale
+    requested: Option<Request>,
+    serial: u64,
+    sender: Option<Sender<Loaded>>,
+}
+
+impl WallpaperState {
+    pub fn set_sender(&mut self, sender: Sender<Loaded>) {
+        self.sender = Some(sender);
+    }
+
+    pub fn clear_overrides(&mut self) {
+        self.overrides.clear();
+    }
+}
+
+impl State {
+    fn wallpaper_request(&self) -> Option<Request> {
+        let output = self.primary_output()?;
+        let (path, mode) = match self.wallpaper.overrides.get(&output.name) {
+            Some((path, mode)) => (path.clone(), *mode),
+            None => self.config.wallpaper.for_output(&output.name)?,
+        };
+        // the GPU draws in the output's own orientation, the CPU canvas is
+        // always the size of the mode
+        let (width, height) = if self.gpu_renderer.is_some() && output.transform.swaps_axes() {
+            (output.height as u32, output.width as u32)
+        } else if self.gpu_renderer.is_some() {
+            (output.width as u32, output.height as u32)
+        } else {
+            (self.canvas.width as u32, self.canvas.height as u32)
+        };
+        if width == 0 || height == 0 {
+            return None;
+        }
+        Some(Request {
+            path,
+            mode,
+            width,
+            height,
+            background: self.config.background_dark(),
+        })
+    }
+
+    // Starts loading whatever the primary output should show when that
+    // changed since the last call; force reads the file again regardless
+    pub fn refresh_wallpaper(&mut self, force: bool) {
+        let request = self.wallpaper_request();
+        if !force && request == self.wallpaper.requested {
+            return;
+        }
+        self.wallpaper.requested = request.clone();
+        self.wallpaper.serial += 1;
+
+        let Some(request) = request else {
+            if self.wallpaper.current.take().is_some() {
+                self.damage_tracker.mark_full_damage();
+            }
+            return;
+        };
+        let Some(sender) = self.wallpaper.sender.clone() else {
+            return;
+        };
+        let serial = self.wallpaper.serial;
+        let spawned = std::thread::Builder::new()
+            .name("ktc-wallpaper".to_string())
+            .spawn(move || {
+                let path = request.path.clone();
+                let result = load(&request).map(|pixels| Wallpaper {
+                    serial,
+                    width: request.width,
+                    height: request.height,
+                    pixels,
+                });
+                let _ = sender.send(Loaded {
+                    serial,
+                    path,
+                    result,
+                });
+            });
+        if let Err(e) = spawned {
+            log::warn!("[wallpaper] Failed to spawn loader: {}", e);
+        }
+    }
+
+    pub fn wallpaper_loaded(&mut self, loaded: Loaded) {
+        if loaded.serial != self.wallpaper.serial {
+            return;
+        }
+        match loaded.result {
+            Ok(wallpaper) => {
+                log::info!(
+                    "[wallpaper] Showing {} at {}x{}",
+                    loaded.path.display(),
+                    wallpaper.width,
+                    wallpaper.height
+                );
+                self.wallpaper.current = Some(wallpaper);
+            }
+            Err(e) => {
+                log::warn!(
+                    "[wallpaper] Failed to load {}: {}",
+                    loaded.path.display(),
+                    e
+                );
+                self.wallpaper.current = None;
+            }
+        }
+        self.damage_tracker.mark_full_damage();
+    }
+
+    // An empty output means the primary one. A mode alone keeps the image
+    // shown now; with neither path nor mode the configured wallpaper comes
+    // back, read from disk again.
+    pub fn set_wallpaper(
+        &mut self,
+        output: &str,
+        path: Option<String>,
+        mode: Option<String>,
+    ) -> Result<(), String> {
+        let name = if output.is_empty() {
+            self.primary_output()
+                .map(|o| o.name.clone())
+                .ok_or("no outputs")?
+        } else if self.outputs.iter().any(|o| o.name == output) {
+            output.to_string()
+        } else {
+            return Err(format!("unknown output {:?}", output));
+        };
+        let mode = match mode {
+            Some(mode) => {
+                Some(WallpaperMode::parse(&mode).ok_or(format!("unknown mode {:?}", mode))?)
+            }
+            None => None,
+        };
+
+        match path {
+            Some(path) => {
+                let mode = mode
+                    .or_else(|| self.wallpaper.overrides.get(&name).map(|(_, m)| *m))
+                    .or_else(|| self.config.wallpaper.for_output(&name).map(|(_, m)| m))
+                    .unwrap_or_else(|| wallpaper_mode(&self.config.wallpaper.mode));
+                let path = crate::config::expand_home(&path);
+                self.wallpaper.overrides.insert(name, (path, mode));
+            }
+            None => match (self.wallpaper.overrides.get_mut(&name), mode) {
+                (Some(current), Some(mode)) => current.1 = mode,
+                (Some(_), None) => {
+                    self.wallpaper.overrides.remove(&name);
+                }
+                (None, Some(mode)) => {
+                    let (path, _) = self
+                        .config
+                        .wallpaper
+                        .for_output(&name)
+                        .ok_or("no wallpaper configured")?;
+                    self.wallpaper.overrides.insert(name, (path, mode));
+                }
+                (None, None) => {}
+            },
+        }
+        self.refresh_wallpaper(true);
+        Ok(())
+    }
+}
+
+fn load(request: &Request) -> Result<Vec<u32>, String> {
+    let data = std::fs::read(&request.path).map_err(|e| e.to_string())?;
+    let (width, height, pixels) = if png::is_png(&data) {
+        png::decode_png(&data)?
+    } else if jpeg::is_jpeg(&data) {
+        jpeg::decode_jpeg(&data)?
+    } else {
+        return Err("not a PNG or JPEG file".to_string());
+    };
+    let image = Image {
+        width: width as usize,
+        height: height as usize,
+        pixels: pixels
+            .into_iter()
+            .map(|p| flatten(p, request.background))
+            .collect(),
+    };
+    Ok(compose(
+        image,
+        request.mode,
+        request.width as usize,
+        request.height as usize,
+        request.background,
+    ))
+}
+
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+// translucent parts of the image show the background color
+fn flatten(pixel: u32, background: u32) -> u32 {
+    let alpha = pixel >> 24;
+    if alpha == 0xFF {
+        return pixel;
+    }
+    let mix = |shift: u32| {
+        let (fg, bg) = ((pixel >> shift) & 0xFF, (background >> shift) & 0xFF);
+        ((fg * alpha + bg * (255 - alpha) + 127) / 255) << shift
+    };
+    0xFF000000 | mix(16) | mix(8) | mix(0)
+}
+
+fn compose(
+    image: Image,
+    mode: WallpaperMode,
+    width: usize,
+    height: usize,
+    background: u32,
+) -> Vec<u32> {
+    let mut out = vec![background; width * height];
+
+    if mode == WallpaperMode::Tile {
+        for (y, row) in out.chunks_exact_mut(width).enumerate() {
+            let start = (y % image.height) * image.width;
+            let source = &image.pixels[start..start + image.width];
+            for chunk in row.chunks_mut(image.width) {
+                chunk.copy_from_slice(&source[..chunk.len()]);
+            }
+        }
+        return out;
+    }
+
+    let scale_x = width as f64 / image.width as f64;
+    let scale_y = height as f64 / image.height as f64;
+    let scale = match mode {
+        WallpaperMode::Fill => scale_x.max(scale_y),
+        _ => scale_x.min(scale_y),
+    };
+    let (image, scale) = shrink(image, scale);
+
+    // where the scaled image lands, hanging over the edges for fill
+    let drawn_w = image.width as f64 * scale;
+    let drawn_h = image.height as f64 * scale;
+    let left = (width as f64 - drawn_w) / 2.0;
+    let top = (height as f64 - drawn_h) / 2.0;
+    let x_range = (left.round().max(0.0) as usize)..((left + drawn_w).round() as usize).min(width);
+    let y_range = (top.round().max(0.0) as usize)..((top + drawn_h).round() as usize).min(height);
+
+    // source column pairs and weights are the same for every row
+    let columns: Vec<_> = x_range
+        .clone()
+        .map(|x| sample_position((x as f64 + 0.5 - left) / scale - 0.5, image.width))
+        .collect();
+    for y in y_range {
+        let (y0, y1, fy) = sample_position((y as f64 + 0.5 - top) / scale - 0.5, image.height);
+        let (row0, row1) = (
+            &image.pixels[y0 * image.width..],
+            &image.pixels[y1 * image.width..],
+        );
+        let row = &mut out[y * width + x_range.start..y * width + x_range.end];
+        for (pixel, &(x0, x1, fx)) in row.iter_mut().zip(&columns) {
+            let top = lerp(row0[x0], row0[x1], fx);
+            let bottom = lerp(row1[x0], row1[x1], fx);
+            *pixel = lerp(top, bottom, fy);
+        }
+    }
+    out
+}
+
+// Halves the image with a 2x2 box filter while it would still be drawn at
+// half size or less; bilinear sampling alone skips pixels and shimmers
+fn shrink(mut image: Image, mut scale: f64) -> (Image, f64) {
+    while scale <= 0.5 && image.width >= 2 && image.height >= 2 {
+        let (width, height) = (image.width / 2, image.height / 2);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row0 = &image.pixels[y * 2 * image.width..];
+            let row1 = &image.pixels[(y * 2 + 1) * image.width..];
+            for x in 0..width {
+                let quad = [row0[x * 2], row0[x * 2 + 1], row1[x * 2], row1[x * 2 + 1]];
+                let channel = |shift: u32| {
+                    let sum: u32 = quad.iter().map(|p| (p >> shift) & 0xFF).sum();
+                    ((sum + 2) / 4) << shift
+                };
+                pixels.push(0xFF000000 | channel(16) | channel(8) | channel(0));
+            }
+        }
+        // the odd last row or column is dropped, so the scale is recomputed
+        scale *= image.width as f64 / width as f64;
+        image = Image {
+            width,
+            height,
+            pixels,
+        };
+    }
+    (image, scale)
+}
+
+// the two neighbouring source pixels and the weight of the second
+fn sample_position(position: f64, size: usize) -> (usize, usize, u32) {
+    let position = position.clamp(0.0, (size - 1) as f64);
+    let first = position.floor() as usize;
+    let weight = ((position - first as f64) * 256.0).round() as u32;
+    (first, (first + 1).min(size - 1), weight)
+}
+
+fn lerp(a: u32, b: u32, weight: u32) -> u32 {
+    let mix = |shift: u32| {
+        let (a, b) = ((a >> shift) & 0xFF, (b >> shift) & 0xFF);
+        ((a * (256 - weight) + b * weight + 128) >> 8) << shift
+    };
+    0xFF000000 | mix(16) | mix(8) | mix(0)
+}
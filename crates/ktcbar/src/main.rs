@@ -1,74 +1,47 @@
 use chrono::Local;
-use ktc_common::{ipc_socket_path, AppLogger, Font, IpcCommand, IpcEvent, WorkspaceInfo};
-use std::io::{BufRead, BufReader, Write};
+use ktc_common::{
+    decode_pixels, AppLogger, Font, HiddenWindowInfo, IpcClient, IpcEvent, WorkspaceInfo,
+};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::os::unix::io::AsFd;
-use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
 use wayland_client::{
     protocol::{
-        wl_buffer, wl_callback, wl_compositor, wl_output, wl_registry, wl_shm, wl_shm_pool,
-        wl_surface,
+        wl_buffer, wl_callback, wl_compositor, wl_output, wl_pointer, wl_region, wl_registry,
+        wl_seat, wl_shm, wl_shm_pool, wl_surface,
     },
-    Connection, Dispatch, QueueHandle,
+    Connection, Dispatch, QueueHandle, WEnum,
 };
 use wayland_protocols_wlr::layer_shell::v1::client::{
     zwlr_layer_shell_v1::{self, ZwlrLayerShellV1},
     zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
 };
 
+mod status;
+
 const BAR_HEIGHT: u32 = 24;
 const BG_COLOR: u32 = 0xFF1A1A2E;
 const TEXT_COLOR: u32 = 0xFFE0E0E0;
 const ACTIVE_WS_COLOR: u32 = 0xFF4A9EFF;
 const INACTIVE_WS_COLOR: u32 = 0xFF505050;
 const WS_HAS_WINDOWS_COLOR: u32 = 0xFF808080;
+const DISCONNECTED_COLOR: u32 = 0xFFE05050;
+const HIDDEN_BG_COLOR: u32 = 0xFF2D2D40;
+const HIDDEN_MAX_LABEL: usize = 12;
+const BTN_LEFT: u32 = 0x110;
+const PREVIEW_WIDTH: u32 = 240;
 
-struct IpcClient {
-    stream: UnixStream,
-    reader: BufReader<UnixStream>,
-}
-
-impl IpcClient {
-    fn connect() -> Option<Self> {
-        let path = ipc_socket_path();
-        let stream = UnixStream::connect(&path).ok()?;
-        stream.set_nonblocking(true).ok()?;
-        let reader = BufReader::new(stream.try_clone().ok()?);
-        Some(Self { stream, reader })
-    }
-
-    fn send_command(&mut self, cmd: &IpcCommand) {
-        if let Ok(json) = serde_json::to_string(cmd) {
-            let _ = writeln!(self.stream, "{}", json);
-        }
-    }
-
-    fn poll_events(&mut self) -> Vec<IpcEvent> {
-        let mut events = Vec::new();
-        let mut line = String::new();
-
-        loop {
-            line.clear();
-            match self.reader.read_line(&mut line) {
-                Ok(0) => break,
-                Ok(_) => {
-                    if let Ok(event) = serde_json::from_str::<IpcEvent>(line.trim()) {
-                        events.push(event);
-                    }
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
-                Err(_) => break,
-            }
-        }
-
-        events
-    }
-}
+const IPC_BACKOFF_MIN: Duration = Duration::from_millis(250);
+const IPC_BACKOFF_MAX: Duration = Duration::from_secs(8);
 
 struct AppState {
     compositor: Option<wl_compositor::WlCompositor>,
     layer_shell: Option<ZwlrLayerShellV1>,
     shm: Option<wl_shm::WlShm>,
     output: Option<wl_output::WlOutput>,
+    seat: Option<wl_seat::WlSeat>,
+    pointer: Option<wl_pointer::WlPointer>,
+    pointer_x: f64,
     surface: Option<wl_surface::WlSurface>,
     layer_surface: Option<ZwlrLayerSurfaceV1>,
     configured: bool,
@@ -79,13 +52,36 @@ struct AppState {
     workspaces: Vec<WorkspaceInfo>,
     active_workspace: usize,
     focused_title: Option<String>,
+    hidden_windows: Vec<HiddenWindowInfo>,
+    keyboard_layout: Option<String>,
+    // the active [[keybinds.mode]], None in the default one
+    mode: Option<String>,
+    // keybinds are off until the passthrough binding is pressed again
+    passthrough: bool,
     needs_redraw: bool,
+    clock_text: String,
     ipc_client: Option<IpcClient>,
+    ipc_backoff: Duration,
+    ipc_retry_at: Instant,
+    // --previews: hovering a workspace shows a thumbnail of it below the bar
+    previews: bool,
+    hovered_workspace: Option<usize>,
+    preview: Option<Preview>,
+    preview_surface: Option<wl_surface::WlSurface>,
+    preview_layer: Option<ZwlrLayerSurfaceV1>,
+    preview_configured: bool,
+}
+
+struct Preview {
+    workspace: usize,
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
 }
 
 impl AppState {
-    fn new() -> Self {
-        let ipc_client = IpcClient::connect();
+    fn new(previews: bool) -> Self {
+        let ipc_client = connect_ipc();
         let workspaces = (1..=4).map(WorkspaceInfo::new).collect();
 
         Self {
@@ -93,6 +89,9 @@ impl AppState {
             layer_shell: None,
             shm: None,
             output: None,
+            seat: None,
+            pointer: None,
+            pointer_x: 0.0,
             surface: None,
             layer_surface: None,
             configured: false,
@@ -103,18 +102,80 @@ impl AppState {
             workspaces,
             active_workspace: 1,
             focused_title: None,
+            hidden_windows: Vec::new(),
+            keyboard_layout: None,
+            mode: None,
+            passthrough: false,
             needs_redraw: false,
+            clock_text: clock_text(),
             ipc_client,
+            ipc_backoff: IPC_BACKOFF_MIN,
+            ipc_retry_at: Instant::now(),
+            previews,
+            hovered_workspace: None,
+            preview: None,
+            preview_surface: None,
+            preview_layer: None,
+            preview_configured: false,
+        }
+    }
+
+    fn maintain_ipc(&mut self) {
+        if let Some(ref ipc) = self.ipc_client {
+            if ipc.is_connected() {
+                return;
+            }
+            log::warn!("[ipc] Lost connection to compositor");
+            self.ipc_client = None;
+            self.hide_preview();
+            self.ipc_backoff = IPC_BACKOFF_MIN;
+            self.ipc_retry_at = Instant::now() + self.ipc_backoff;
+            self.needs_redraw = true;
+            return;
+        }
+
+        if Instant::now() < self.ipc_retry_at {
+            return;
+        }
+
+        match connect_ipc() {
+            Some(client) => {
+                log::info!("[ipc] Connected to compositor");
+                self.ipc_client = Some(client);
+                self.ipc_backoff = IPC_BACKOFF_MIN;
+                self.request_state();
+                self.needs_redraw = true;
+            }
+            None => {
+                self.ipc_backoff = (self.ipc_backoff * 2).min(IPC_BACKOFF_MAX);
+                self.ipc_retry_at = Instant::now() + self.ipc_backoff;
+            }
+        }
+    }
+
+    fn ipc_poll_timeout(&self) -> i32 {
+        if self.ipc_client.is_some() {
+            return -1;
+        }
+        let remaining = self.ipc_retry_at.saturating_duration_since(Instant::now());
+        remaining.as_millis().min(i32::MAX as u128) as i32
+    }
+
+    fn tick_clock(&mut self) {
+        let text = clock_text();
+        if text != self.clock_text {
+            self.clock_text = text;
+            self.needs_redraw = true;
         }
     }
 
     fn request_state(&mut self) {
         if let Some(ref mut ipc) = self.ipc_client {
-            ipc.send_command(&IpcCommand::GetState);
+            let _ = ipc.request_state();
         }
     }
 
-    fn poll_ipc(&mut self) {
+    fn poll_ipc(&mut self, qh: &QueueHandle<Self>) {
         let events = if let Some(ref mut ipc) = self.ipc_client {
             ipc.poll_events()
         } else {
@@ -149,10 +210,204 @@ impl AppState {
                     self.focused_title = Some(window_title);
                     self.needs_redraw = true;
                 }
+                IpcEvent::HiddenWindows { windows } => {
+                    self.hidden_windows = windows;
+                    self.needs_redraw = true;
+                }
+                IpcEvent::KeyboardLayout { name, .. } => {
+                    self.keyboard_layout = Some(name);
+                    self.needs_redraw = true;
+                }
+                IpcEvent::Mode { name } => {
+                    self.mode = (name != "default").then_some(name);
+                    self.needs_redraw = true;
+                }
+                IpcEvent::Passthrough { enabled } => {
+                    self.passthrough = enabled;
+                    self.needs_redraw = true;
+                }
+                IpcEvent::BarVisibility { visible } => {
+                    self.set_visible(visible, qh);
+                }
+                IpcEvent::WorkspacePreview {
+                    workspace,
+                    width,
+                    height,
+                    pixels,
+                } => {
+                    if self.hovered_workspace != Some(workspace) {
+                        continue;
+                    }
+                    let Some(pixels) = decode_pixels(&pixels) else {
+                        log::warn!("[ipc] Malformed preview for workspace {}", workspace);
+                        continue;
+                    };
+                    if pixels.len() != (width * height) as usize {
+                        continue;
+                    }
+                    self.preview = Some(Preview {
+                        workspace,
+                        width,
+                        height,
+                        pixels,
+                    });
+                    self.show_preview(qh);
+                }
+                IpcEvent::Tree { .. }
+                | IpcEvent::RendererStats { .. }
+                | IpcEvent::Screenshot { .. }
+                | IpcEvent::Recording { .. }
+                | IpcEvent::Tablets { .. } => {}
             }
         }
     }
 
+    fn click(&mut self, x: usize) {
+        let Some(id) = self
+            .hidden_window_slots()
+            .into_iter()
+            .find(|(start, width, _)| x >= *start && x < start + width)
+            .map(|(_, _, id)| id)
+        else {
+            return;
+        };
+        if let Some(ref mut ipc) = self.ipc_client {
+            let _ = ipc.restore_window(id);
+        }
+    }
+
+    fn workspace_at(&self, x: usize) -> Option<(usize, usize)> {
+        let slot = self.font.char_width() + 12;
+        let index = x.checked_sub(6)? / slot;
+        let ws = self.workspaces.get(index)?;
+        Some((ws.id, 6 + index * slot))
+    }
+
+    fn hover(&mut self, x: Option<f64>) {
+        if !self.previews {
+            return;
+        }
+        let workspace = x.and_then(|x| self.workspace_at(x.max(0.0) as usize));
+        if workspace.map(|(id, _)| id) == self.hovered_workspace {
+            return;
+        }
+        self.hovered_workspace = workspace.map(|(id, _)| id);
+        match (workspace, self.ipc_client.as_mut()) {
+            (Some((id, _)), Some(ipc)) => {
+                let _ = ipc.request_workspace_preview(id, PREVIEW_WIDTH);
+            }
+            _ => self.hide_preview(),
+        }
+    }
+
+    fn show_preview(&mut self, qh: &QueueHandle<Self>) {
+        let Some(preview) = &self.preview else {
+            return;
+        };
+        let x = self
+            .workspaces
+            .iter()
+            .position(|ws| ws.id == preview.workspace)
+            .map(|index| 6 + index * (self.font.char_width() + 12))
+            .unwrap_or(0);
+        // keep it on screen, with a 1px border around the thumbnail
+        let (width, height) = (preview.width + 2, preview.height + 2);
+        let x = x.min((self.width as usize).saturating_sub(width as usize));
+
+        if self.preview_layer.is_none() {
+            let (Some(compositor), Some(layer_shell)) = (&self.compositor, &self.layer_shell)
+            else {
+                return;
+            };
+            let surface = compositor.create_surface(qh, ());
+            // the pointer goes through to whatever is below
+            let region = compositor.create_region(qh, ());
+            surface.set_input_region(Some(&region));
+            region.destroy();
+            let layer_surface = layer_shell.get_layer_surface(
+                &surface,
+                self.output.as_ref(),
+                zwlr_layer_shell_v1::Layer::Overlay,
+                "ktcbar-preview".to_string(),
+                qh,
+                (),
+            );
+            layer_surface.set_anchor(
+                zwlr_layer_surface_v1::Anchor::Top | zwlr_layer_surface_v1::Anchor::Left,
+            );
+            layer_surface.set_exclusive_zone(-1);
+            self.preview_surface = Some(surface);
+            self.preview_layer = Some(layer_surface);
+            self.preview_configured = false;
+        }
+
+        let (Some(surface), Some(layer_surface)) = (&self.preview_surface, &self.preview_layer)
+        else {
+            return;
+        };
+        layer_surface.set_size(width, height);
+        layer_surface.set_margin(self.height as i32 + 4, 0, 0, x as i32);
+        if self.preview_configured {
+            self.draw_preview(qh);
+        } else {
+            surface.commit();
+        }
+    }
+
+    fn draw_preview(&self, qh: &QueueHandle<Self>) {
+        let (Some(shm), Some(surface), Some(preview)) =
+            (&self.shm, &self.preview_surface, &self.preview)
+        else {
+            return;
+        };
+        let (width, height) = (preview.width + 2, preview.height + 2);
+        commit_pixels(shm, surface, width, height, qh, |pixels| {
+            pixels.fill(ACTIVE_WS_COLOR);
+            for (y, row) in preview.pixels.chunks(preview.width as usize).enumerate() {
+                let start = (y + 1) * width as usize + 1;
+                pixels[start..start + row.len()].copy_from_slice(row);
+            }
+        });
+    }
+
+    fn hide_preview(&mut self) {
+        self.preview = None;
+        if let Some(layer_surface) = self.preview_layer.take() {
+            layer_surface.destroy();
+        }
+        if let Some(surface) = self.preview_surface.take() {
+            surface.destroy();
+        }
+        self.preview_configured = false;
+    }
+
+    fn workspaces_width(&self) -> usize {
+        self.workspaces.len() * (self.font.char_width() + 12)
+    }
+
+    fn hidden_window_label(window: &HiddenWindowInfo) -> String {
+        let name = if window.app_id.is_empty() {
+            &window.title
+        } else {
+            &window.app_id
+        };
+        name.chars().take(HIDDEN_MAX_LABEL).collect()
+    }
+
+    // (x, width, window id) of each hidden window button, right after the workspaces
+    fn hidden_window_slots(&self) -> Vec<(usize, usize, u64)> {
+        let mut x = 8 + self.workspaces_width() + 8;
+        self.hidden_windows
+            .iter()
+            .map(|window| {
+                let width = self.font.text_width(&Self::hidden_window_label(window)) + 8;
+                let slot = (x, width, window.id);
+                x += width + 4;
+                slot
+            })
+            .collect()
+    }
+
     fn create_layer_surface(&mut self, qh: &QueueHandle<Self>) {
         let Some(compositor) = &self.compositor else {
             return;
@@ -166,7 +421,7 @@ impl AppState {
             &surface,
             self.output.as_ref(),
             zwlr_layer_shell_v1::Layer::Top,
-            "ktcbar".to_string(),
+            ktc_common::BAR_NAMESPACE.to_string(),
             qh,
             (),
         );
@@ -184,6 +439,26 @@ impl AppState {
         self.layer_surface = Some(layer_surface);
     }
 
+    // hiding destroys the layer surface; the compositor has already handed
+    // its exclusive zone back to windows
+    fn set_visible(&mut self, visible: bool, qh: &QueueHandle<Self>) {
+        if visible == self.surface.is_some() {
+            return;
+        }
+        if visible {
+            self.create_layer_surface(qh);
+            return;
+        }
+        self.hide_preview();
+        if let Some(layer_surface) = self.layer_surface.take() {
+            layer_surface.destroy();
+        }
+        if let Some(surface) = self.surface.take() {
+            surface.destroy();
+        }
+        self.configured = false;
+    }
+
     fn request_frame(&self, qh: &QueueHandle<Self>) {
         if let Some(surface) = &self.surface {
             surface.frame(qh, ());
@@ -198,43 +473,9 @@ impl AppState {
         let Some(shm) = &self.shm else { return };
         let Some(surface) = &self.surface else { return };
 
-        let stride = self.width;
-        let size = (stride * self.height) as usize;
-        let byte_size = size * 4;
-
-        let file = create_shm_file(byte_size);
-        let pool = shm.create_pool(file.as_fd(), byte_size as i32, qh, ());
-        let buffer = pool.create_buffer(
-            0,
-            self.width as i32,
-            self.height as i32,
-            (stride * 4) as i32,
-            wl_shm::Format::Argb8888,
-            qh,
-            (),
-        );
-
-        unsafe {
-            let ptr = libc::mmap(
-                std::ptr::null_mut(),
-                byte_size,
-                libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_SHARED,
-                std::os::unix::io::AsRawFd::as_raw_fd(&file),
-                0,
-            );
-            if ptr != libc::MAP_FAILED {
-                let pixels = std::slice::from_raw_parts_mut(ptr as *mut u32, size);
-                self.render(pixels, stride as usize);
-                libc::munmap(ptr, byte_size);
-            }
-        }
-
-        surface.attach(Some(&buffer), 0, 0);
-        surface.damage_buffer(0, 0, self.width as i32, self.height as i32);
-        surface.commit();
-
-        pool.destroy();
+        commit_pixels(shm, surface, self.width, self.height, qh, |pixels| {
+            self.render(pixels, self.width as usize)
+        });
         self.needs_redraw = false;
     }
 
@@ -245,8 +486,23 @@ impl AppState {
         let text_y = (self.height as usize - self.font.char_height()) / 2;
 
         self.draw_workspaces(pixels, stride, padding, text_y);
-        self.draw_title(pixels, stride, text_y);
-        self.draw_clock(pixels, stride, self.width as usize - padding, text_y);
+        self.draw_hidden_windows(pixels, stride, text_y);
+        if self.ipc_client.is_some() {
+            self.draw_title(pixels, stride, text_y);
+        } else {
+            self.draw_disconnected(pixels, stride, text_y);
+        }
+        let clock_x = self.width as usize - padding;
+        self.draw_clock(pixels, stride, clock_x, text_y);
+        let clock_width = self.font.text_width(&self.clock_text);
+        let layout_x = clock_x.saturating_sub(clock_width + 12);
+        self.draw_keyboard_layout(pixels, stride, layout_x, text_y);
+        let layout_width = self
+            .keyboard_layout
+            .as_ref()
+            .map(|layout| self.font.text_width(layout) + 12)
+            .unwrap_or(0);
+        self.draw_mode(pixels, stride, layout_x.saturating_sub(layout_width), text_y);
     }
 
     fn draw_workspaces(&self, pixels: &mut [u32], stride: usize, x: usize, y: usize) {
@@ -285,6 +541,29 @@ impl AppState {
         }
     }
 
+    fn draw_hidden_windows(&self, pixels: &mut [u32], stride: usize, y: usize) {
+        for (window, (x, width, _)) in self.hidden_windows.iter().zip(self.hidden_window_slots()) {
+            fill_rect(
+                pixels,
+                stride,
+                self.height as usize,
+                x,
+                y - 2,
+                width,
+                self.font.char_height() + 4,
+                HIDDEN_BG_COLOR,
+            );
+            self.font.draw_text(
+                pixels,
+                stride,
+                x + 4,
+                y,
+                &Self::hidden_window_label(window),
+                WS_HAS_WINDOWS_COLOR,
+            );
+        }
+    }
+
     fn draw_title(&self, pixels: &mut [u32], stride: usize, y: usize) {
         if let Some(ref title) = self.focused_title {
             let max_title_len = 40;
@@ -301,14 +580,162 @@ impl AppState {
         }
     }
 
+    fn draw_disconnected(&self, pixels: &mut [u32], stride: usize, y: usize) {
+        let text = "disconnected";
+        let text_width = self.font.text_width(text);
+        let center_x = (self.width as usize / 2).saturating_sub(text_width / 2);
+        self.font
+            .draw_text(pixels, stride, center_x, y, text, DISCONNECTED_COLOR);
+    }
+
+    fn draw_keyboard_layout(&self, pixels: &mut [u32], stride: usize, right_x: usize, y: usize) {
+        if let Some(ref layout) = self.keyboard_layout {
+            self.font
+                .draw_text_right(pixels, stride, right_x, y, layout, WS_HAS_WINDOWS_COLOR);
+        }
+    }
+
+    // passthrough wins over the mode, whose bindings are off too
+    fn draw_mode(&self, pixels: &mut [u32], stride: usize, right_x: usize, y: usize) {
+        let (mode, color) = if self.passthrough {
+            ("passthrough", DISCONNECTED_COLOR)
+        } else if let Some(ref mode) = self.mode {
+            (mode.as_str(), ACTIVE_WS_COLOR)
+        } else {
+            return;
+        };
+        let width = self.font.text_width(mode);
+        fill_rect(
+            pixels,
+            stride,
+            self.height as usize,
+            right_x.saturating_sub(width + 4),
+            y - 2,
+            width + 8,
+            self.font.char_height() + 4,
+            HIDDEN_BG_COLOR,
+        );
+        self.font
+            .draw_text_right(pixels, stride, right_x, y, mode, color);
+    }
+
     fn draw_clock(&self, pixels: &mut [u32], stride: usize, right_x: usize, y: usize) {
-        let now = Local::now();
-        let time_str = now.format("%H:%M").to_string();
         self.font
-            .draw_text_right(pixels, stride, right_x, y, &time_str, TEXT_COLOR);
+            .draw_text_right(pixels, stride, right_x, y, &self.clock_text, TEXT_COLOR);
     }
 }
 
+fn clock_text() -> String {
+    Local::now().format("%H:%M").to_string()
+}
+
+struct ClockTimer {
+    fd: OwnedFd,
+}
+
+impl ClockTimer {
+    fn new() -> std::io::Result<Self> {
+        let raw = unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        };
+        if raw < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        let mut now: libc::timespec = unsafe { std::mem::zeroed() };
+        unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut now) };
+
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 1,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: now.tv_sec + 1,
+                tv_nsec: 0,
+            },
+        };
+        let ret = unsafe {
+            libc::timerfd_settime(
+                fd.as_raw_fd(),
+                libc::TFD_TIMER_ABSTIME,
+                &spec,
+                std::ptr::null_mut(),
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Self { fd })
+    }
+
+    fn drain(&self) {
+        let mut expirations = 0u64;
+        unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                &mut expirations as *mut u64 as *mut libc::c_void,
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+}
+
+fn connect_ipc() -> Option<IpcClient> {
+    let client = IpcClient::connect().ok()?;
+    client.set_nonblocking(true).ok()?;
+    Some(client)
+}
+
+// Attaches a fresh width x height ARGB8888 buffer filled in by render
+fn commit_pixels(
+    shm: &wl_shm::WlShm,
+    surface: &wl_surface::WlSurface,
+    width: u32,
+    height: u32,
+    qh: &QueueHandle<AppState>,
+    render: impl FnOnce(&mut [u32]),
+) {
+    let size = (width * height) as usize;
+    let byte_size = size * 4;
+
+    let file = create_shm_file(byte_size);
+    let pool = shm.create_pool(file.as_fd(), byte_size as i32, qh, ());
+    let buffer = pool.create_buffer(
+        0,
+        width as i32,
+        height as i32,
+        (width * 4) as i32,
+        wl_shm::Format::Argb8888,
+        qh,
+        (),
+    );
+
+    unsafe {
+        let ptr = libc::mmap(
+            std::ptr::null_mut(),
+            byte_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            std::os::unix::io::AsRawFd::as_raw_fd(&file),
+            0,
+        );
+        if ptr != libc::MAP_FAILED {
+            let pixels = std::slice::from_raw_parts_mut(ptr as *mut u32, size);
+            render(pixels);
+            libc::munmap(ptr, byte_size);
+        }
+    }
+
+    surface.attach(Some(&buffer), 0, 0);
+    surface.damage_buffer(0, 0, width as i32, height as i32);
+    surface.commit();
+
+    pool.destroy();
+}
+
 #[allow(clippy::too_many_arguments)]
 fn fill_rect(
     pixels: &mut [u32],
@@ -386,14 +813,15 @@ impl Dispatch<wl_registry::WlRegistry, ()> for AppState {
                 "wl_shm" => {
                     state.shm = Some(registry.bind(name, version.min(1), qh, ()));
                 }
-                "wl_output" => {
-                    if state.output.is_none() {
-                        state.output = Some(registry.bind(name, version.min(4), qh, ()));
-                    }
+                "wl_output" if state.output.is_none() => {
+                    state.output = Some(registry.bind(name, version.min(4), qh, ()));
                 }
                 "zwlr_layer_shell_v1" => {
                     state.layer_shell = Some(registry.bind(name, version.min(4), qh, ()));
                 }
+                "wl_seat" if state.seat.is_none() => {
+                    state.seat = Some(registry.bind(name, version.min(5), qh, ()));
+                }
                 _ => {}
             }
         }
@@ -448,6 +876,61 @@ impl Dispatch<wl_output::WlOutput, ()> for AppState {
     }
 }
 
+impl Dispatch<wl_seat::WlSeat, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        seat: &wl_seat::WlSeat,
+        event: wl_seat::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = event
+        {
+            let has_pointer = capabilities.contains(wl_seat::Capability::Pointer);
+            if has_pointer && state.pointer.is_none() {
+                state.pointer = Some(seat.get_pointer(qh, ()));
+            } else if !has_pointer {
+                if let Some(pointer) = state.pointer.take() {
+                    pointer.release();
+                }
+            }
+        }
+    }
+}
+
+impl Dispatch<wl_pointer::WlPointer, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &wl_pointer::WlPointer,
+        event: wl_pointer::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_pointer::Event::Enter { surface_x, .. }
+            | wl_pointer::Event::Motion { surface_x, .. } => {
+                state.pointer_x = surface_x;
+                state.hover(Some(surface_x));
+            }
+            wl_pointer::Event::Leave { .. } => {
+                state.hover(None);
+            }
+            wl_pointer::Event::Button {
+                button: BTN_LEFT,
+                state: WEnum::Value(wl_pointer::ButtonState::Pressed),
+                ..
+            } => {
+                state.click(state.pointer_x.max(0.0) as usize);
+            }
+            _ => {}
+        }
+    }
+}
+
 impl Dispatch<wl_surface::WlSurface, ()> for AppState {
     fn event(
         _state: &mut Self,
@@ -460,6 +943,18 @@ impl Dispatch<wl_surface::WlSurface, ()> for AppState {
     }
 }
 
+impl Dispatch<wl_region::WlRegion, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &wl_region::WlRegion,
+        _event: wl_region::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
 impl Dispatch<wl_buffer::WlBuffer, ()> for AppState {
     fn event(
         _state: &mut Self,
@@ -515,6 +1010,13 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for AppState {
         qh: &QueueHandle<Self>,
     ) {
         match event {
+            zwlr_layer_surface_v1::Event::Configure { serial, .. }
+                if state.preview_layer.as_ref() == Some(layer_surface) =>
+            {
+                layer_surface.ack_configure(serial);
+                state.preview_configured = true;
+                state.draw_preview(qh);
+            }
             zwlr_layer_surface_v1::Event::Configure {
                 serial,
                 width,
@@ -527,6 +1029,11 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for AppState {
                 state.draw(qh);
                 state.request_frame(qh);
             }
+            zwlr_layer_surface_v1::Event::Closed
+                if state.preview_layer.as_ref() == Some(layer_surface) =>
+            {
+                state.hide_preview();
+            }
             zwlr_layer_surface_v1::Event::Closed => {
                 state.running = false;
             }
@@ -538,6 +1045,14 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for AppState {
 fn main() {
     let _ = AppLogger::init("ktcbar");
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--status") {
+        if let Err(e) = status::run() {
+            log::info!("[status] Stopped: {}", e);
+        }
+        return;
+    }
+
     let conn = Connection::connect_to_env().expect("Failed to connect to Wayland");
     let display = conn.display();
 
@@ -546,7 +1061,8 @@ fn main() {
 
     let _registry = display.get_registry(&qh, ());
 
-    let mut state = AppState::new();
+    let previews = args.iter().any(|arg| arg == "--previews");
+    let mut state = AppState::new(previews);
 
     event_queue.roundtrip(&mut state).expect("Roundtrip failed");
 
@@ -556,18 +1072,9 @@ fn main() {
 
     event_queue.roundtrip(&mut state).expect("Roundtrip failed");
 
-    use std::time::{Duration, Instant};
-    let mut last_clock_update = Instant::now();
-    let clock_interval = Duration::from_secs(1);
+    let clock = ClockTimer::new().expect("Failed to create clock timer");
 
     while state.running {
-        state.poll_ipc();
-
-        if last_clock_update.elapsed() >= clock_interval {
-            state.needs_redraw = true;
-            last_clock_update = Instant::now();
-        }
-
         if state.needs_redraw && state.configured {
             state.draw(&qh);
         }
@@ -582,6 +1089,68 @@ fn main() {
             break;
         }
 
-        std::thread::sleep(Duration::from_millis(16));
+        let Some(guard) = event_queue.prepare_read() else {
+            continue;
+        };
+
+        let ipc_fd = state
+            .ipc_client
+            .as_ref()
+            .map(|c| c.as_fd().as_raw_fd())
+            .unwrap_or(-1);
+
+        let mut fds = [
+            libc::pollfd {
+                fd: guard.connection_fd().as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: clock.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: ipc_fd,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+
+        let ret = unsafe {
+            libc::poll(
+                fds.as_mut_ptr(),
+                fds.len() as libc::nfds_t,
+                state.ipc_poll_timeout(),
+            )
+        };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                eprintln!("Poll error: {}", err);
+                break;
+            }
+            continue;
+        }
+
+        if fds[0].revents != 0 {
+            if let Err(e) = guard.read() {
+                eprintln!("Read error: {}", e);
+                break;
+            }
+        } else {
+            drop(guard);
+        }
+
+        if fds[1].revents != 0 {
+            clock.drain();
+            state.tick_clock();
+        }
+
+        if fds[2].revents != 0 {
+            state.poll_ipc(&qh);
+        }
+
+        state.maintain_ipc();
     }
 }
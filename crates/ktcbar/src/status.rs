@@ -0,0 +1,197 @@
+// `ktcbar --status`: instead of drawing a bar, stream the same workspace,
+// mode, layout and title state to stdout in the i3bar protocol, for swaybar,
+// waybar or anything else that reads i3status-style JSON
+use ktc_common::{IpcClient, IpcEvent, WorkspaceInfo};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+
+use super::{
+    ACTIVE_WS_COLOR, DISCONNECTED_COLOR, INACTIVE_WS_COLOR, IPC_BACKOFF_MAX, IPC_BACKOFF_MIN,
+    TEXT_COLOR, WS_HAS_WINDOWS_COLOR,
+};
+
+struct Status {
+    workspaces: Vec<WorkspaceInfo>,
+    active_workspace: usize,
+    focused_title: Option<String>,
+    keyboard_layout: Option<String>,
+    // None in the default mode
+    mode: Option<String>,
+    passthrough: bool,
+    connected: bool,
+}
+
+impl Status {
+    // Returns whether the status line changed
+    fn apply(&mut self, event: IpcEvent) -> bool {
+        match event {
+            IpcEvent::State {
+                workspaces,
+                active_workspace,
+                focused_window,
+            } => {
+                self.workspaces = workspaces;
+                self.active_workspace = active_workspace;
+                self.focused_title = focused_window;
+            }
+            IpcEvent::WorkspaceChanged {
+                workspaces,
+                active_workspace,
+            } => {
+                self.workspaces = workspaces;
+                self.active_workspace = active_workspace;
+            }
+            IpcEvent::FocusChanged { window_title } => {
+                self.focused_title = window_title;
+            }
+            IpcEvent::TitleChanged { window_title } => {
+                self.focused_title = Some(window_title);
+            }
+            IpcEvent::KeyboardLayout { name, .. } => {
+                self.keyboard_layout = Some(name);
+            }
+            IpcEvent::Mode { name } => {
+                self.mode = (name != "default").then_some(name);
+            }
+            IpcEvent::Passthrough { enabled } => {
+                self.passthrough = enabled;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn blocks(&self) -> Value {
+        let mut blocks: Vec<Value> = self
+            .workspaces
+            .iter()
+            .map(|ws| {
+                let color = if ws.id == self.active_workspace {
+                    ACTIVE_WS_COLOR
+                } else if ws.window_count > 0 {
+                    WS_HAS_WINDOWS_COLOR
+                } else {
+                    INACTIVE_WS_COLOR
+                };
+                json!({
+                    "name": "workspace",
+                    "instance": ws.id.to_string(),
+                    "full_text": ws.name,
+                    "color": hex(color),
+                    "urgent": ws.urgent,
+                    "separator": false,
+                })
+            })
+            .collect();
+
+        if self.passthrough {
+            blocks.push(json!({
+                "name": "passthrough",
+                "full_text": "passthrough",
+                "color": hex(DISCONNECTED_COLOR),
+                "urgent": true,
+            }));
+        } else if let Some(mode) = &self.mode {
+            blocks.push(json!({
+                "name": "mode",
+                "full_text": mode,
+                "color": hex(ACTIVE_WS_COLOR),
+            }));
+        }
+
+        let layout = self
+            .workspaces
+            .iter()
+            .find(|ws| ws.id == self.active_workspace)
+            .and_then(|ws| ws.layout.as_deref());
+        if let Some(layout) = layout {
+            blocks.push(json!({
+                "name": "layout",
+                "full_text": layout,
+                "color": hex(TEXT_COLOR),
+            }));
+        }
+
+        if let Some(layout) = &self.keyboard_layout {
+            blocks.push(json!({
+                "name": "keyboard_layout",
+                "full_text": layout,
+                "color": hex(TEXT_COLOR),
+            }));
+        }
+
+        if !self.connected {
+            blocks.push(json!({
+                "name": "title",
+                "full_text": "disconnected",
+                "color": hex(DISCONNECTED_COLOR),
+            }));
+        } else if let Some(title) = &self.focused_title {
+            blocks.push(json!({
+                "name": "title",
+                "full_text": title,
+                "color": hex(TEXT_COLOR),
+            }));
+        }
+
+        Value::Array(blocks)
+    }
+}
+
+fn hex(argb: u32) -> String {
+    format!("#{:06X}", argb & 0x00FF_FFFF)
+}
+
+// Runs until stdout is closed, reconnecting to the compositor as needed
+pub fn run() -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(out, "{{\"version\":1}}")?;
+    writeln!(out, "[")?;
+    out.flush()?;
+
+    let mut status = Status {
+        workspaces: (1..=4).map(WorkspaceInfo::new).collect(),
+        active_workspace: 1,
+        focused_title: None,
+        keyboard_layout: None,
+        mode: None,
+        passthrough: false,
+        connected: false,
+    };
+    let mut first = true;
+    let mut print = |status: &Status| -> io::Result<()> {
+        let separator = if first { "" } else { "," };
+        first = false;
+        writeln!(out, "{}{}", separator, status.blocks())?;
+        out.flush()
+    };
+
+    print(&status)?;
+    let mut backoff = IPC_BACKOFF_MIN;
+    loop {
+        let mut client = match IpcClient::connect() {
+            Ok(client) => client,
+            Err(_) => {
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(IPC_BACKOFF_MAX);
+                continue;
+            }
+        };
+        log::info!("[ipc] Connected to compositor");
+        backoff = IPC_BACKOFF_MIN;
+        status.connected = true;
+
+        if client.request_state().is_ok() {
+            while let Ok(Some(event)) = client.recv() {
+                if status.apply(event) {
+                    print(&status)?;
+                }
+            }
+        }
+
+        log::warn!("[ipc] Lost connection to compositor");
+        status.connected = false;
+        print(&status)?;
+        std::thread::sleep(backoff);
+    }
+}